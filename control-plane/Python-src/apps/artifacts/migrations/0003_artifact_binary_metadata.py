@@ -0,0 +1,18 @@
+# Generated by Django 5.2.9 on 2026-10-16 10:05
+
+from django.db import migrations, models
+
+
+class Migration(migrations.Migration):
+
+    dependencies = [
+        ('artifacts', '0002_initial'),
+    ]
+
+    operations = [
+        migrations.AddField(
+            model_name='artifact',
+            name='binary_metadata',
+            field=models.JSONField(blank=True, default=list, help_text='Architecture and version of binaries and packages in the artifact'),
+        ),
+    ]
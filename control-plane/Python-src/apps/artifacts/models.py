@@ -53,6 +53,11 @@ class Artifact(TenantAwareModel):
         default='gzip',
         blank=True
     )
+    binary_metadata = models.JSONField(
+        default=list,
+        blank=True,
+        help_text='Architecture and version of binaries and packages in the artifact'
+    )
 
     class Meta:
         ordering = ['-created_at']
@@ -19,7 +19,7 @@ class ArtifactSerializer(serializers.ModelSerializer):
             'job', 'job_name',
             'storage_path', 'size_bytes', 'size_mb',
             'checksum_sha256', 'file_count',
-            'is_compressed', 'compression_type',
+            'is_compressed', 'compression_type', 'binary_metadata',
             'retention_days', 'expires_at', 'is_expired',
             'created_at'
         ]
@@ -14,6 +14,9 @@ class LogConsumer(AsyncWebsocketConsumer):
     Clients can subscribe to:
     - /ws/logs/{execution_id}/ - All logs for an execution
     - /ws/logs/{execution_id}/{job_id}/ - Logs for a specific job
+
+    On a job's stream, clients can also send `debug_exec` and `debug_end`
+    messages to the debug session the runner keeps open after the job failed.
     """
 
     async def connect(self):
@@ -64,9 +67,27 @@ class LogConsumer(AsyncWebsocketConsumer):
 
             if message_type == 'ping':
                 await self.send(text_data=json.dumps({'type': 'pong'}))
+            elif message_type in ('debug_exec', 'debug_end') and self.job_id:
+                await self.relay_debug_request(message_type, data)
         except json.JSONDecodeError:
             pass
 
+    async def relay_debug_request(self, message_type, data):
+        """Send a debug session request to the runner of this job."""
+        runner_id = await self.get_job_runner_id()
+        if not runner_id or not data.get('session_id'):
+            return
+
+        event = {
+            'type': message_type,
+            'job_id': self.job_id,
+            'session_id': data['session_id'],
+        }
+        if message_type == 'debug_exec':
+            event['command'] = str(data.get('command', ''))
+            event['request_id'] = data.get('request_id')
+        await self.channel_layer.group_send(f'runner_{runner_id}', event)
+
     async def log_message(self, event):
         """
         Receive log message from channel layer and send to WebSocket.
@@ -92,6 +113,112 @@ class LogConsumer(AsyncWebsocketConsumer):
             'timestamp': event.get('timestamp'),
         }))
 
+    async def diagnostics_result(self, event):
+        """
+        Receive job diagnostics from channel layer and send to WebSocket.
+        """
+        await self.send(text_data=json.dumps({
+            'type': 'diagnostics',
+            'job_id': event.get('job_id'),
+            'results': event.get('results', []),
+            'error': event.get('error'),
+            'timestamp': event.get('timestamp'),
+        }))
+
+    async def artifact_progress(self, event):
+        """
+        Receive artifact upload progress from channel layer and send to WebSocket.
+        """
+        await self.send(text_data=json.dumps({
+            'type': 'artifact_progress',
+            'job_id': event.get('job_id'),
+            'artifact_name': event.get('artifact_name'),
+            'upload_id': event.get('upload_id'),
+            'uploaded_bytes': event.get('uploaded_bytes'),
+            'size_bytes': event.get('size_bytes'),
+        }))
+
+    async def timeout_warning(self, event):
+        """
+        Receive a job or step timeout warning from channel layer and send to WebSocket.
+        """
+        await self.send(text_data=json.dumps({
+            'type': 'timeout_warning',
+            'job_id': event.get('job_id'),
+            'step_id': event.get('step_id'),
+            'timeout_secs': event.get('timeout_secs'),
+            'deadline': event.get('deadline'),
+        }))
+
+    async def cancel_acknowledged(self, event):
+        """
+        Receive a runner's confirmation of a cancel request from channel layer and send to WebSocket.
+        """
+        await self.send(text_data=json.dumps({
+            'type': 'cancel_acknowledged',
+            'job_id': event.get('job_id'),
+            'state': event.get('state'),
+            'timestamp': event.get('timestamp'),
+        }))
+
+    async def annotations(self, event):
+        """
+        Receive a step's errors and warnings from channel layer and send to WebSocket.
+        """
+        await self.send(text_data=json.dumps({
+            'type': 'annotations',
+            'job_id': event.get('job_id'),
+            'step_id': event.get('step_id'),
+            'annotations': event.get('annotations', []),
+        }))
+
+    async def debug_session(self, event):
+        """
+        Receive an opened debug session from channel layer and send to WebSocket.
+        """
+        await self.send(text_data=json.dumps({
+            'type': 'debug_session',
+            'job_id': event.get('job_id'),
+            'session_id': event.get('session_id'),
+            'step_id': event.get('step_id'),
+            'expires_at': event.get('expires_at'),
+        }))
+
+    async def debug_output(self, event):
+        """
+        Receive the result of a debug command from channel layer and send to WebSocket.
+        """
+        await self.send(text_data=json.dumps({
+            'type': 'debug_output',
+            'job_id': event.get('job_id'),
+            'session_id': event.get('session_id'),
+            'request_id': event.get('request_id'),
+            'exit_code': event.get('exit_code'),
+            'output': event.get('output', ''),
+            'error': event.get('error'),
+        }))
+
+    async def debug_session_closed(self, event):
+        """
+        Receive the end of a debug session from channel layer and send to WebSocket.
+        """
+        await self.send(text_data=json.dumps({
+            'type': 'debug_session_closed',
+            'job_id': event.get('job_id'),
+            'session_id': event.get('session_id'),
+            'reason': event.get('reason'),
+        }))
+
+    @database_sync_to_async
+    def get_job_runner_id(self):
+        """Runner the job of this stream is assigned to."""
+        from apps.executions.models import Job
+
+        job = Job.objects.filter(id=self.job_id, execution_id=self.execution_id).first()
+        if job is None or job.runner_id is None:
+            return None
+        return str(job.runner_id)
+
     @database_sync_to_async
     def has_permission(self):
         """Check if user has permission to view these logs."""
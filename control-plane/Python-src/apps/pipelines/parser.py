@@ -244,6 +244,12 @@ class PipelineParser:
             'needs': self._normalize_list(config.get('needs', [])),
             'condition': config.get('if', ''),
             'container': self._parse_container(config.get('container')),
+            'executor': config.get('executor'),
+            'priority': config.get('priority', 0),
+            'debug_on_failure': config.get('debug-on-failure', False),
+            'gpus': config.get('gpus', 0),
+            'retry_on': config.get('retry-on'),
+            'concurrency': self._parse_concurrency(config['concurrency']) if config.get('concurrency') else None,
             'services': self._parse_services(config.get('services', {})),
             'env': config.get('env', {}),
             'steps': self._parse_steps(config.get('steps', [])),
@@ -275,6 +281,7 @@ class PipelineParser:
             'ports': config.get('ports', []),
             'volumes': config.get('volumes', []),
             'options': config.get('options', ''),
+            'caches': config.get('caches', []),
         }
 
     def _parse_services(self, services_config: dict) -> dict:
@@ -326,6 +333,15 @@ class PipelineParser:
             if step:
                 steps.append(step)
 
+        # Steps can only need earlier or later steps of the same job by id
+        step_ids = {step['id'] for step in steps if step['id']}
+        for i, step in enumerate(steps):
+            for needed_step in step['needs']:
+                if needed_step not in step_ids:
+                    self.errors.append(
+                        f"Step {i + 1} needs non-existent step '{needed_step}'"
+                    )
+
         return steps
 
     def _parse_step(self, index: int, config: dict) -> dict:
@@ -342,6 +358,20 @@ class PipelineParser:
             'condition': config.get('if', ''),
             'continue_on_error': config.get('continue-on-error', False),
             'timeout_minutes': config.get('timeout-minutes', 60),
+            'needs': self._normalize_list(config.get('needs', [])),
+            'outputs': {
+                'files': self._normalize_list((config.get('outputs') or {}).get('files', [])),
+            },
+            'limits': {
+                'cpus': (config.get('limits') or {}).get('cpus'),
+                'memory_mb': (config.get('limits') or {}).get('memory-mb'),
+                'pids': (config.get('limits') or {}).get('pids'),
+            },
+            'log': {
+                'max_lines': (config.get('log') or {}).get('max-lines'),
+                'sample_rate': (config.get('log') or {}).get('sample-rate'),
+                'drop_debug': bool((config.get('log') or {}).get('drop-debug', False)),
+            },
         }
 
         # Validate: must have either 'run' or 'uses'
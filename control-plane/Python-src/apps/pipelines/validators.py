@@ -144,6 +144,27 @@ PIPELINE_SCHEMA = {
                 },
                 "if": {"type": "string"},
                 "container": {"$ref": "#/definitions/container"},
+                "executor": {"type": "string"},
+                "priority": {"type": "integer"},
+                "debug-on-failure": {"type": "boolean"},
+                "gpus": {"type": "integer", "minimum": 0},
+                "retry-on": {
+                    "type": "array",
+                    "items": {"enum": ["infra_error", "timeout", "executor_failure"]},
+                },
+                "concurrency": {
+                    "oneOf": [
+                        {"type": "string"},
+                        {
+                            "type": "object",
+                            "properties": {
+                                "group": {"type": "string"},
+                                "cancel-in-progress": {"type": "boolean"},
+                            },
+                            "required": ["group"],
+                        },
+                    ]
+                },
                 "services": {
                     "type": "object",
                     "additionalProperties": {"$ref": "#/definitions/container"},
@@ -178,6 +199,17 @@ PIPELINE_SCHEMA = {
                         "ports": {"type": "array", "items": {"type": ["string", "integer"]}},
                         "volumes": {"type": "array", "items": {"type": "string"}},
                         "options": {"type": "string"},
+                        "caches": {
+                            "type": "array",
+                            "items": {
+                                "type": "object",
+                                "properties": {
+                                    "path": {"type": "string"},
+                                    "key": {"type": "string"},
+                                },
+                                "required": ["path"],
+                            },
+                        },
                     },
                     "required": ["image"],
                 },
@@ -212,6 +244,37 @@ PIPELINE_SCHEMA = {
                 "if": {"type": "string"},
                 "continue-on-error": {"type": "boolean"},
                 "timeout-minutes": {"type": "integer", "minimum": 1},
+                "needs": {
+                    "oneOf": [
+                        {"type": "string"},
+                        {"type": "array", "items": {"type": "string"}},
+                    ]
+                },
+                "outputs": {
+                    "type": "object",
+                    "properties": {
+                        "files": {"type": "array", "items": {"type": "string"}},
+                    },
+                    "additionalProperties": False,
+                },
+                "limits": {
+                    "type": "object",
+                    "properties": {
+                        "cpus": {"type": "number", "exclusiveMinimum": 0},
+                        "memory-mb": {"type": "integer", "minimum": 1},
+                        "pids": {"type": "integer", "minimum": 1},
+                    },
+                    "additionalProperties": False,
+                },
+                "log": {
+                    "type": "object",
+                    "properties": {
+                        "max-lines": {"type": "integer", "minimum": 1},
+                        "sample-rate": {"type": "number", "exclusiveMinimum": 0, "maximum": 1},
+                        "drop-debug": {"type": "boolean"},
+                    },
+                    "additionalProperties": False,
+                },
             },
         },
     },
@@ -5,6 +5,7 @@ Handles bidirectional communication between runners and control plane.
 """
 import json
 import hashlib
+import zlib
 from channels.generic.websocket import AsyncWebsocketConsumer
 from channels.db import database_sync_to_async
 from django.utils import timezone
@@ -71,18 +72,37 @@ class RunnerConsumer(AsyncWebsocketConsumer):
                 self.channel_name
             )
 
-    async def receive(self, text_data):
-        """Handle incoming messages from runner."""
+    async def receive(self, text_data=None, bytes_data=None):
+        """Handle incoming messages from runner.
+
+        Runners with `websocket.compression = "deflate"` send large messages
+        as binary frames of zlib-compressed JSON.
+        """
         try:
+            if bytes_data is not None:
+                text_data = zlib.decompress(bytes_data).decode('utf-8')
             data = json.loads(text_data)
             message_type = data.get('type')
 
             handlers = {
                 'heartbeat': self.handle_heartbeat,
+                'batch': self.handle_batch,
                 'log': self.handle_log,
                 'status_update': self.handle_status_update,
                 'job_complete': self.handle_job_complete,
+                'cancel_acknowledged': self.handle_cancel_acknowledged,
                 'artifact_ready': self.handle_artifact_ready,
+                'artifact_progress': self.handle_artifact_progress,
+                'job_diagnostics': self.handle_job_diagnostics,
+                'timeout_warning': self.handle_timeout_warning,
+                'annotations': self.handle_annotations,
+                'runner_status_report': self.handle_runner_status_report,
+                'capacity_report': self.handle_capacity_report,
+                'config_applied': self.handle_config_applied,
+                'active_jobs': self.handle_active_jobs,
+                'debug_session': self.handle_debug_session,
+                'debug_output': self.handle_debug_output,
+                'debug_session_closed': self.handle_debug_session_closed,
             }
 
             handler = handlers.get(message_type)
@@ -99,6 +119,11 @@ class RunnerConsumer(AsyncWebsocketConsumer):
                 'type': 'error',
                 'message': 'Invalid JSON'
             }))
+        except (zlib.error, UnicodeDecodeError):
+            await self.send(text_data=json.dumps({
+                'type': 'error',
+                'message': 'Invalid compressed message'
+            }))
 
     # Outgoing message handlers (from control plane to runner)
 
@@ -116,14 +141,69 @@ class RunnerConsumer(AsyncWebsocketConsumer):
             'job_id': event['job_id'],
         }))
 
+    async def job_diagnostics(self, event):
+        """Ask runner to run diagnostic commands for a job."""
+        await self.send(text_data=json.dumps({
+            'type': 'job_diagnostics',
+            'job_id': event['job_id'],
+        }))
+
+    async def query_status(self, event):
+        """Ask runner for a detailed status snapshot."""
+        await self.send(text_data=json.dumps({
+            'type': 'query_status',
+            'request_id': event.get('request_id'),
+        }))
+
+    async def config_update(self, event):
+        """Push configuration overrides to runner."""
+        await self.send(text_data=json.dumps({
+            'type': 'config_update',
+            'reset': event.get('reset', False),
+            'overrides': event.get('overrides', {}),
+        }))
+
+    async def drain(self, event):
+        """Stop runner from accepting jobs, optionally exiting once idle."""
+        await self.send(text_data=json.dumps({
+            'type': 'drain',
+            'resume': event.get('resume', False),
+            'exit': event.get('exit', False),
+        }))
+
+    async def debug_exec(self, event):
+        """Relay a user's command to the debug session of a failed job."""
+        await self.send(text_data=json.dumps({
+            'type': 'debug_exec',
+            'job_id': event['job_id'],
+            'session_id': event['session_id'],
+            'command': event['command'],
+            'request_id': event.get('request_id'),
+        }))
+
+    async def debug_end(self, event):
+        """Close the debug session of a failed job."""
+        await self.send(text_data=json.dumps({
+            'type': 'debug_end',
+            'job_id': event['job_id'],
+            'session_id': event['session_id'],
+        }))
+
     # Incoming message handlers (from runner to control plane)
 
+    async def handle_batch(self, data):
+        """Handle messages the runner sent together, in order."""
+        for message in data.get('messages', []):
+            await self.receive(text_data=json.dumps(message))
+
     async def handle_heartbeat(self, data):
         """Handle heartbeat from runner."""
         system_info = data.get('system_info', {})
         current_jobs = data.get('current_jobs', 0)
+        labels = data.get('labels')
+        draining = data.get('status') == 'draining'
 
-        await self.update_runner_heartbeat(system_info, current_jobs)
+        await self.update_runner_heartbeat(system_info, current_jobs, labels, draining)
 
         await self.send(text_data=json.dumps({
             'type': 'heartbeat_ack',
@@ -183,9 +263,21 @@ class RunnerConsumer(AsyncWebsocketConsumer):
         new_status = data.get('status')
         exit_code = data.get('exit_code')
         outputs = data.get('outputs', {})
+        # Files declared in a step's `outputs.files`, uploaded as artifacts
+        output_files = data.get('output_files', [])
+        if output_files:
+            outputs = {**outputs, 'files': output_files}
+        # Set for cancelled jobs: when the cancel arrived and the steps it stopped
+        cancellation = data.get('cancellation')
+        if cancellation:
+            outputs = {**outputs, 'cancellation': cancellation}
+        # Runner-side timestamps stay accurate under reconnect replay
+        started_at = data.get('started_at')
+        finished_at = data.get('finished_at')
 
         await self.update_entity_status(
-            entity_type, entity_id, new_status, exit_code, outputs
+            entity_type, entity_id, new_status, exit_code, outputs,
+            started_at, finished_at
         )
 
         # Broadcast status update
@@ -207,16 +299,38 @@ class RunnerConsumer(AsyncWebsocketConsumer):
                 )
 
     async def handle_job_complete(self, data):
-        """Handle job completion from runner."""
+        """Handle job completion from runner.
+
+        Current runners report a job's end, including how it was cancelled,
+        with the job's final `status_update` and do not send this.
+        """
         job_id = data.get('job_id')
         status = data.get('status')
         outputs = data.get('outputs', {})
+        finished_at = data.get('finished_at')
 
-        await self.complete_job(job_id, status, outputs)
+        await self.complete_job(job_id, status, outputs, finished_at)
 
         # Decrement current jobs count
         await self.decrement_runner_jobs()
 
+    async def handle_cancel_acknowledged(self, data):
+        """Forward the runner's confirmation of a cancel request to log subscribers."""
+        from channels.layers import get_channel_layer
+
+        job_id = data.get('job_id')
+        channel_layer = get_channel_layer()
+
+        await channel_layer.group_send(
+            f'logs_job_{job_id}',
+            {
+                'type': 'cancel_acknowledged',
+                'job_id': job_id,
+                'state': data.get('state'),
+                'timestamp': data.get('timestamp'),
+            }
+        )
+
     async def handle_artifact_ready(self, data):
         """Handle artifact upload notification from runner."""
         job_id = data.get('job_id')
@@ -224,11 +338,225 @@ class RunnerConsumer(AsyncWebsocketConsumer):
         artifact_path = data.get('artifact_path')
         size_bytes = data.get('size_bytes')
         checksum = data.get('checksum')
+        metadata = data.get('metadata', [])
 
         await self.create_artifact(
-            job_id, artifact_name, artifact_path, size_bytes, checksum
+            job_id, artifact_name, artifact_path, size_bytes, checksum, metadata
+        )
+
+    async def handle_artifact_progress(self, data):
+        """Forward artifact upload progress from runner to log subscribers."""
+        from channels.layers import get_channel_layer
+
+        job_id = data.get('job_id')
+        channel_layer = get_channel_layer()
+
+        await channel_layer.group_send(
+            f'logs_job_{job_id}',
+            {
+                'type': 'artifact_progress',
+                'job_id': job_id,
+                'artifact_name': data.get('artifact_name'),
+                'upload_id': data.get('upload_id'),
+                'uploaded_bytes': data.get('uploaded_bytes'),
+                'size_bytes': data.get('size_bytes'),
+            }
         )
 
+    async def handle_timeout_warning(self, data):
+        """Forward an about-to-time-out warning from runner to log subscribers."""
+        from channels.layers import get_channel_layer
+
+        job_id = data.get('job_id')
+        channel_layer = get_channel_layer()
+
+        await channel_layer.group_send(
+            f'logs_job_{job_id}',
+            {
+                'type': 'timeout_warning',
+                'job_id': job_id,
+                'step_id': data.get('step_id'),
+                'timeout_secs': data.get('timeout_secs'),
+                'deadline': data.get('deadline'),
+            }
+        )
+
+    async def handle_annotations(self, data):
+        """Forward a step's errors and warnings from runner to log subscribers."""
+        from channels.layers import get_channel_layer
+
+        job_id = data.get('job_id')
+        channel_layer = get_channel_layer()
+
+        await channel_layer.group_send(
+            f'logs_job_{job_id}',
+            {
+                'type': 'annotations',
+                'job_id': job_id,
+                'step_id': data.get('step_id'),
+                'annotations': data.get('annotations', []),
+            }
+        )
+
+    async def handle_job_diagnostics(self, data):
+        """Forward diagnostic results from runner to log subscribers."""
+        from channels.layers import get_channel_layer
+
+        job_id = data.get('job_id')
+        channel_layer = get_channel_layer()
+
+        await channel_layer.group_send(
+            f'logs_job_{job_id}',
+            {
+                'type': 'diagnostics_result',
+                'job_id': job_id,
+                'results': data.get('results', []),
+                'error': data.get('error'),
+                'timestamp': timezone.now().isoformat(),
+            }
+        )
+
+    async def handle_runner_status_report(self, data):
+        """Record a status snapshot and forward it to status subscribers."""
+        from channels.layers import get_channel_layer
+
+        running_jobs = data.get('running_jobs', [])
+        await self.update_runner_heartbeat(data.get('system_info', {}), len(running_jobs))
+
+        channel_layer = get_channel_layer()
+        await channel_layer.group_send(
+            f'runner_status_{self.runner_id}',
+            {
+                'type': 'runner_status_report',
+                'runner_id': self.runner_id,
+                'request_id': data.get('request_id'),
+                'status': data.get('status'),
+                'max_concurrent_jobs': data.get('max_concurrent_jobs'),
+                'running_jobs': running_jobs,
+                'pending_jobs': data.get('pending_jobs', []),
+                'system_info': data.get('system_info', {}),
+                'versions': data.get('versions', {}),
+                'image_pulls': data.get('image_pulls', {}),
+                'timestamp': data.get('timestamp'),
+            }
+        )
+
+    async def handle_capacity_report(self, data):
+        """Forward a runner's free slots to status subscribers for placement."""
+        from channels.layers import get_channel_layer
+
+        channel_layer = get_channel_layer()
+        await channel_layer.group_send(
+            f'runner_status_{self.runner_id}',
+            {
+                'type': 'capacity_report',
+                'runner_id': self.runner_id,
+                'request_id': data.get('request_id'),
+                'group': data.get('group'),
+                'capacity_weight': data.get('capacity_weight', 1.0),
+                'capacity': data.get('capacity', 0),
+                'label_slots': data.get('label_slots', {}),
+                'running_jobs': data.get('running_jobs', 0),
+                'pending_jobs': data.get('pending_jobs', 0),
+                'pending_capacity': data.get('pending_capacity', 0),
+                'free_gpus': data.get('free_gpus', 0),
+                'draining': data.get('draining', False),
+                'timestamp': data.get('timestamp'),
+            }
+        )
+
+    async def handle_config_applied(self, data):
+        """Forward the outcome of a config update to status subscribers."""
+        from channels.layers import get_channel_layer
+
+        channel_layer = get_channel_layer()
+        await channel_layer.group_send(
+            f'runner_status_{self.runner_id}',
+            {
+                'type': 'config_applied',
+                'runner_id': self.runner_id,
+                'revision': data.get('revision'),
+                'applied': data.get('applied', False),
+                'error': data.get('error'),
+                'overrides': data.get('overrides', {}),
+                'timestamp': timezone.now().isoformat(),
+            }
+        )
+
+    async def handle_debug_session(self, data):
+        """Forward an opened debug session from runner to log subscribers."""
+        from channels.layers import get_channel_layer
+
+        job_id = data.get('job_id')
+        channel_layer = get_channel_layer()
+
+        await channel_layer.group_send(
+            f'logs_job_{job_id}',
+            {
+                'type': 'debug_session',
+                'job_id': job_id,
+                'session_id': data.get('session_id'),
+                'step_id': data.get('step_id'),
+                'expires_at': data.get('expires_at'),
+            }
+        )
+
+    async def handle_debug_output(self, data):
+        """Forward the result of a debug command from runner to log subscribers."""
+        from channels.layers import get_channel_layer
+
+        job_id = data.get('job_id')
+        channel_layer = get_channel_layer()
+
+        await channel_layer.group_send(
+            f'logs_job_{job_id}',
+            {
+                'type': 'debug_output',
+                'job_id': job_id,
+                'session_id': data.get('session_id'),
+                'request_id': data.get('request_id'),
+                'exit_code': data.get('exit_code'),
+                'output': data.get('output', ''),
+                'error': data.get('error'),
+            }
+        )
+
+    async def handle_debug_session_closed(self, data):
+        """Forward the end of a debug session from runner to log subscribers."""
+        from channels.layers import get_channel_layer
+
+        job_id = data.get('job_id')
+        channel_layer = get_channel_layer()
+
+        await channel_layer.group_send(
+            f'logs_job_{job_id}',
+            {
+                'type': 'debug_session_closed',
+                'job_id': job_id,
+                'session_id': data.get('session_id'),
+                'reason': data.get('reason'),
+            }
+        )
+
+    async def handle_active_jobs(self, data):
+        """Reconcile the jobs runner holds with the ones assigned to it.
+
+        Runners report their jobs on every connection. Jobs this side does not
+        consider queued or running on the runner are cancelled there, and jobs
+        it expects but the runner did not report get their status resent.
+        """
+        reported = set(data.get('running', [])) | set(data.get('pending', []))
+        expected = await self.get_active_job_ids()
+
+        cancel = sorted(reported - expected)
+        resend = sorted(expected - reported)
+        if cancel or resend:
+            await self.send(text_data=json.dumps({
+                'type': 'reconcile',
+                'cancel': cancel,
+                'resend': resend,
+            }))
+
     # Database operations
 
     @database_sync_to_async
@@ -270,13 +598,23 @@ class RunnerConsumer(AsyncWebsocketConsumer):
         )
 
     @database_sync_to_async
-    def update_runner_heartbeat(self, system_info, current_jobs):
+    def update_runner_heartbeat(self, system_info, current_jobs, labels=None, draining=None):
         from apps.runners.models import Runner
-        Runner.objects.filter(id=self.runner_id).update(
-            last_heartbeat=timezone.now(),
-            system_info=system_info,
-            current_jobs=current_jobs
-        )
+        fields = {
+            'last_heartbeat': timezone.now(),
+            'system_info': system_info,
+            'current_jobs': current_jobs,
+        }
+        # Runners report their effective labels, including detected ones
+        if labels is not None:
+            fields['labels'] = labels
+        if draining:
+            fields['status'] = Runner.Status.DRAINING
+        Runner.objects.filter(id=self.runner_id).update(**fields)
+        if draining is False:
+            Runner.objects.filter(
+                id=self.runner_id, status=Runner.Status.DRAINING
+            ).update(status=Runner.Status.ONLINE)
 
     @database_sync_to_async
     def store_log(self, step_id, content, level, timestamp):
@@ -299,6 +637,15 @@ class RunnerConsumer(AsyncWebsocketConsumer):
         except Step.DoesNotExist:
             pass
 
+    @database_sync_to_async
+    def get_active_job_ids(self):
+        from apps.executions.models import Job
+        job_ids = Job.objects.filter(
+            runner_id=self.runner_id,
+            status__in=[Job.Status.QUEUED, Job.Status.RUNNING],
+        ).values_list('id', flat=True)
+        return {str(job_id) for job_id in job_ids}
+
     @database_sync_to_async
     def get_execution_id(self, job_id):
         from apps.executions.models import Job
@@ -309,14 +656,22 @@ class RunnerConsumer(AsyncWebsocketConsumer):
             return None
 
     @database_sync_to_async
-    def update_entity_status(self, entity_type, entity_id, status, exit_code, outputs):
+    def update_entity_status(self, entity_type, entity_id, status, exit_code, outputs,
+                             started_at=None, finished_at=None):
+        from django.utils.dateparse import parse_datetime
+
+        started = parse_datetime(started_at) if started_at else None
+        finished = parse_datetime(finished_at) if finished_at else None
+
         if entity_type == 'job':
             from apps.executions.models import Job
             update_fields = {'status': status}
             if status == 'running':
-                update_fields['started_at'] = timezone.now()
+                update_fields['started_at'] = started or timezone.now()
             elif status in ('success', 'failed', 'cancelled', 'timeout'):
-                update_fields['finished_at'] = timezone.now()
+                if started:
+                    update_fields['started_at'] = started
+                update_fields['finished_at'] = finished or timezone.now()
                 update_fields['outputs'] = outputs
             Job.objects.filter(id=entity_id).update(**update_fields)
 
@@ -326,18 +681,23 @@ class RunnerConsumer(AsyncWebsocketConsumer):
             if exit_code is not None:
                 update_fields['exit_code'] = exit_code
             if status == 'running':
-                update_fields['started_at'] = timezone.now()
+                update_fields['started_at'] = started or timezone.now()
             elif status in ('success', 'failed', 'cancelled', 'timeout', 'skipped'):
-                update_fields['finished_at'] = timezone.now()
+                if started:
+                    update_fields['started_at'] = started
+                update_fields['finished_at'] = finished or timezone.now()
                 update_fields['outputs'] = outputs
             Step.objects.filter(id=entity_id).update(**update_fields)
 
     @database_sync_to_async
-    def complete_job(self, job_id, status, outputs):
+    def complete_job(self, job_id, status, outputs, finished_at=None):
         from apps.executions.models import Job
+        from django.utils.dateparse import parse_datetime
+
+        finished = parse_datetime(finished_at) if finished_at else None
         Job.objects.filter(id=job_id).update(
             status=status,
-            finished_at=timezone.now(),
+            finished_at=finished or timezone.now(),
             outputs=outputs
         )
 
@@ -350,7 +710,7 @@ class RunnerConsumer(AsyncWebsocketConsumer):
         )
 
     @database_sync_to_async
-    def create_artifact(self, job_id, name, path, size_bytes, checksum):
+    def create_artifact(self, job_id, name, path, size_bytes, checksum, metadata=None):
         from apps.artifacts.models import Artifact
         from apps.executions.models import Job
 
@@ -364,6 +724,7 @@ class RunnerConsumer(AsyncWebsocketConsumer):
                 storage_path=path,
                 size_bytes=size_bytes,
                 checksum_sha256=checksum,
+                binary_metadata=metadata or [],
             )
         except Job.DoesNotExist:
             pass
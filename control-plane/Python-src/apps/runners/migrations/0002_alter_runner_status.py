@@ -0,0 +1,18 @@
+# Generated by Django 5.2.9 on 2026-10-16 09:12
+
+from django.db import migrations, models
+
+
+class Migration(migrations.Migration):
+
+    dependencies = [
+        ('runners', '0001_initial'),
+    ]
+
+    operations = [
+        migrations.AlterField(
+            model_name='runner',
+            name='status',
+            field=models.CharField(choices=[('online', 'Online'), ('offline', 'Offline'), ('busy', 'Busy'), ('maintenance', 'Maintenance'), ('draining', 'Draining')], default='offline', max_length=20),
+        ),
+    ]
@@ -30,6 +30,7 @@ class Runner(models.Model):
         OFFLINE = 'offline', 'Offline'
         BUSY = 'busy', 'Busy'
         MAINTENANCE = 'maintenance', 'Maintenance'
+        DRAINING = 'draining', 'Draining'
 
     id = models.UUIDField(primary_key=True, default=uuid.uuid4, editable=False)
 
@@ -0,0 +1,134 @@
+//! Log pipeline throughput
+//!
+//! Measures the path every line of step output takes: `LogStreamer::add`
+//! (including chunking of long lines), draining the buffer into WebSocket
+//! entries, and serializing a `log_batch` message.
+//!
+//! ```text
+//! cargo bench --bench log_pipeline
+//! MUELSYSE_BENCH_BUDGET=1 cargo bench --bench log_pipeline
+//! ```
+//!
+//! With `MUELSYSE_BENCH_BUDGET` set, the run fails if the whole pipeline
+//! falls below `BUDGET_LINES_PER_SEC`.
+
+use criterion::{criterion_group, BenchmarkId, Criterion, Throughput};
+use std::time::{Duration, Instant};
+
+use muelsyse_runner::client::{LogEntry, OutgoingMessage};
+use muelsyse_runner::config::{LogFormat, LoggingConfig};
+use muelsyse_runner::log::LogStreamer;
+
+/// Lines per second a release build must carry from `add` to serialized batch
+const BUDGET_LINES_PER_SEC: f64 = 250_000.0;
+
+const LINES: usize = 1_000;
+
+/// A typical line of build output
+const LINE: &str = "   Compiling muelsyse-runner v0.1.0 (/workspace/runners) [target: x86_64-unknown-linux-gnu]";
+
+fn config(chunk_size_bytes: usize) -> LoggingConfig {
+    LoggingConfig {
+        // Nothing is flushed on its own; the benchmarks drain the buffer
+        buffer_size: usize::MAX,
+        max_pending_logs: usize::MAX,
+        chunk_size_bytes,
+        enable_persistence: true,
+        ..LoggingConfig::default()
+    }
+}
+
+fn runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Builder::new_current_thread().build().unwrap()
+}
+
+/// Add `lines` copies of `line`, then drain them as WebSocket entries
+async fn stream(config: LoggingConfig, line: &str, lines: usize) -> Vec<LogEntry> {
+    let streamer = LogStreamer::new("job-1".to_string(), config);
+    for _ in 0..lines {
+        streamer.add("step-1", line, "info").await.unwrap();
+    }
+    streamer.take_buffered().await
+}
+
+fn batch(logs: Vec<LogEntry>, format: LogFormat) -> Vec<u8> {
+    let message = OutgoingMessage::LogBatch {
+        job_id: "job-1".to_string(),
+        logs: logs.into_iter().map(|entry| entry.encode(format)).collect(),
+        format,
+    };
+    serde_json::to_vec(&message).unwrap()
+}
+
+fn streamer_add(c: &mut Criterion) {
+    let runtime = runtime();
+    let mut group = c.benchmark_group("streamer_add");
+    group.throughput(Throughput::Elements(LINES as u64));
+    group.bench_function("line", |b| {
+        b.to_async(&runtime).iter(|| stream(config(65_536), LINE, LINES))
+    });
+    group.finish();
+}
+
+fn chunking(c: &mut Criterion) {
+    let runtime = runtime();
+    let long_line = LINE.repeat(64);
+    let mut group = c.benchmark_group("chunking");
+    group.throughput(Throughput::Bytes((long_line.len() * LINES) as u64));
+    for chunk_size in [65_536, 1_024, 256] {
+        group.bench_with_input(BenchmarkId::from_parameter(chunk_size), &chunk_size, |b, &chunk_size| {
+            b.to_async(&runtime).iter(|| stream(config(chunk_size), &long_line, LINES))
+        });
+    }
+    group.finish();
+}
+
+fn serialization(c: &mut Criterion) {
+    let runtime = runtime();
+    let mut group = c.benchmark_group("log_batch_serialization");
+    for lines in [1_000, 10_000] {
+        let logs = runtime.block_on(stream(config(65_536), LINE, lines));
+        group.throughput(Throughput::Elements(lines as u64));
+        for (name, format) in [("plain", LogFormat::Plain), ("json", LogFormat::Json)] {
+            group.bench_with_input(BenchmarkId::new(name, lines), &logs, |b, logs| {
+                b.iter(|| batch(logs.clone(), format))
+            });
+        }
+    }
+    group.finish();
+}
+
+/// Fail if the pipeline is slower than the budget, best of a few runs
+fn check_budget() {
+    const BUDGET_LINES: usize = 100_000;
+    let runtime = runtime();
+    let best = (0..5)
+        .map(|_| {
+            let start = Instant::now();
+            let logs = runtime.block_on(stream(config(65_536), LINE, BUDGET_LINES));
+            std::hint::black_box(batch(logs, LogFormat::Plain));
+            start.elapsed()
+        })
+        .min()
+        .unwrap_or(Duration::MAX);
+
+    let lines_per_sec = BUDGET_LINES as f64 / best.as_secs_f64();
+    println!("log pipeline: {:.0} lines/s (budget {:.0})", lines_per_sec, BUDGET_LINES_PER_SEC);
+    assert!(
+        lines_per_sec >= BUDGET_LINES_PER_SEC,
+        "log pipeline throughput {:.0} lines/s is below the budget of {:.0}",
+        lines_per_sec,
+        BUDGET_LINES_PER_SEC
+    );
+}
+
+criterion_group!(log_pipeline, streamer_add, chunking, serialization);
+
+fn main() {
+    log_pipeline();
+    Criterion::default().configure_from_args().final_summary();
+
+    if std::env::var_os("MUELSYSE_BENCH_BUDGET").is_some() {
+        check_budget();
+    }
+}
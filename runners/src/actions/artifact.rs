@@ -0,0 +1,78 @@
+//! `upload-artifact` and `download-artifact`
+
+use anyhow::Result;
+use async_trait::async_trait;
+use std::collections::HashMap;
+
+use super::traits::{Action, ActionContext, ActionOutcome};
+use crate::artifact::{ArtifactDownloader, ArtifactManager};
+use crate::client::ArtifactSpec;
+
+/// Inputs: `name` (default `artifact`), `path` (one glob per line),
+/// `if-no-files-found` (`warn`, `error` or `ignore`)
+pub struct UploadArtifactAction;
+
+#[async_trait]
+impl Action for UploadArtifactAction {
+    async fn run(&self, ctx: &ActionContext<'_>) -> Result<ActionOutcome> {
+        let spec = ArtifactSpec {
+            name: ctx.input("name").unwrap_or("artifact").to_string(),
+            paths: ctx.lines("path"),
+            when: Default::default(),
+            stream: false,
+        };
+        if spec.paths.is_empty() {
+            anyhow::bail!("Input 'path' is required");
+        }
+
+        let manager = ArtifactManager::new(ctx.settings.workspace.artifact_path.clone())
+            .with_metadata(ctx.settings.job.artifact_metadata)
+            .with_compression(ctx.settings.job.artifact_compression);
+        let Some(artifact) = manager.package(&ctx.job.job_id, ctx.workspace, &spec).await? else {
+            match ctx.input("if-no-files-found").unwrap_or("warn") {
+                "error" => anyhow::bail!("No files found for artifact '{}'", spec.name),
+                "ignore" => {}
+                _ => ctx.log(format!("No files found for artifact '{}'", spec.name)),
+            }
+            return Ok(ActionOutcome::default());
+        };
+
+        ctx.log(format!(
+            "Packaged artifact '{}': {} entries, {} bytes",
+            artifact.name, artifact.entries, artifact.size_bytes
+        ));
+        Ok(ActionOutcome {
+            artifacts: vec![artifact],
+            ..Default::default()
+        })
+    }
+}
+
+/// Inputs: `name`, `path` (default the workspace root), `checksum` (SHA256
+/// to pin instead of the one reported by the control plane)
+pub struct DownloadArtifactAction;
+
+#[async_trait]
+impl Action for DownloadArtifactAction {
+    async fn run(&self, ctx: &ActionContext<'_>) -> Result<ActionOutcome> {
+        let name = ctx.required("name")?;
+        let dest = ctx.workspace_path(ctx.input("path").unwrap_or("."))?;
+
+        let downloader = ArtifactDownloader::new(ctx.settings.workspace.artifact_path.join("downloads"));
+        let artifact = downloader
+            .download(ctx.http, &ctx.job.execution_id, name, ctx.input("checksum"), &dest)
+            .await?;
+        ctx.log(format!(
+            "Downloaded artifact '{}' ({} bytes, sha256 {})",
+            artifact.name, artifact.size_bytes, artifact.checksum
+        ));
+
+        Ok(ActionOutcome {
+            outputs: HashMap::from([
+                ("download-path".to_string(), artifact.path.display().to_string()),
+                ("checksum".to_string(), artifact.checksum),
+            ]),
+            ..Default::default()
+        })
+    }
+}
@@ -0,0 +1,54 @@
+//! `cache`: restore directories by key, saving them after the job on a miss
+
+use anyhow::Result;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use super::traits::{Action, ActionContext, ActionOutcome, PostAction};
+use crate::cache::{resolve_path, within_workspace, CacheStore};
+
+/// Inputs: `key`, `path` (one directory per line). Output: `cache-hit`
+pub struct CacheAction;
+
+#[async_trait]
+impl Action for CacheAction {
+    async fn run(&self, ctx: &ActionContext<'_>) -> Result<ActionOutcome> {
+        let key = ctx.required("key")?.to_string();
+        let paths = ctx.lines("path");
+        if paths.is_empty() {
+            anyhow::bail!("Input 'path' is required");
+        }
+
+        // Trusted cache contents may only land in an untrusted job's workspace
+        if ctx.job.untrusted && !paths.iter().all(|p| within_workspace(p)) {
+            ctx.log("Cache not restored: untrusted jobs may only cache paths in the workspace");
+            return Ok(ActionOutcome {
+                outputs: HashMap::from([("cache-hit".to_string(), "false".to_string())]),
+                ..Default::default()
+            });
+        }
+
+        let workspace = &ctx.settings.workspace;
+        let store = CacheStore::new(workspace.cache_path.clone(), workspace.cache_max_bytes);
+        let targets: Vec<PathBuf> = paths.iter().map(|p| resolve_path(p, ctx.workspace)).collect();
+        let hit = store.restore(&key, ctx.workspace, &targets).await?;
+
+        let post = if hit {
+            ctx.log(format!("Cache restored from key: {}", key));
+            None
+        } else if ctx.job.untrusted {
+            ctx.log(format!("Cache not found for key: {} (read-only for untrusted jobs)", key));
+            None
+        } else {
+            ctx.log(format!("Cache not found for key: {}, saving after the job", key));
+            Some(PostAction::SaveCache { key, paths })
+        };
+
+        Ok(ActionOutcome {
+            outputs: HashMap::from([("cache-hit".to_string(), hit.to_string())]),
+            post,
+            ..Default::default()
+        })
+    }
+}
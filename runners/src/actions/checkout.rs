@@ -0,0 +1,71 @@
+//! `checkout`: fetch the job's repository into the workspace
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::path::Path;
+use tokio::process::Command;
+
+use super::traits::{Action, ActionContext, ActionOutcome};
+
+/// Inputs: `repository` (defaults to the job's), `ref` (defaults to the
+/// job's commit, then branch), `path`, `fetch-depth` (0 = full history)
+pub struct CheckoutAction;
+
+#[async_trait]
+impl Action for CheckoutAction {
+    async fn run(&self, ctx: &ActionContext<'_>) -> Result<ActionOutcome> {
+        let spec = &ctx.job.workspace;
+        let repository = ctx.input("repository")
+            .or(spec.repository_url.as_deref())
+            .context("No repository to check out")?;
+        let reference = ctx.input("ref")
+            .or(spec.commit_sha.as_deref())
+            .or(spec.branch.as_deref())
+            .unwrap_or("HEAD");
+        let depth: u32 = ctx.input("fetch-depth").unwrap_or("1").parse().context("Invalid fetch-depth")?;
+        let dest = ctx.workspace_path(ctx.input("path").unwrap_or("."))?;
+
+        tokio::fs::create_dir_all(&dest).await.context("Failed to create checkout directory")?;
+        if !dest.join(".git").exists() {
+            git(ctx, &dest, &["init", "--quiet"]).await?;
+            git(ctx, &dest, &["remote", "add", "origin", repository]).await?;
+        }
+
+        let depth_arg = format!("--depth={}", depth);
+        let mut fetch = vec!["fetch", "--no-tags", "origin", reference];
+        if depth > 0 {
+            fetch.insert(1, &depth_arg);
+        }
+        git(ctx, &dest, &fetch).await?;
+        git(ctx, &dest, &["checkout", "--force", "--quiet", "FETCH_HEAD"]).await?;
+
+        let commit = git(ctx, &dest, &["rev-parse", "HEAD"]).await?;
+        ctx.log(format!("Checked out {} at {}", repository, commit));
+
+        Ok(ActionOutcome {
+            outputs: HashMap::from([("commit".to_string(), commit)]),
+            ..Default::default()
+        })
+    }
+}
+
+/// Run git in `dir`, returning its trimmed stdout
+async fn git(ctx: &ActionContext<'_>, dir: &Path, args: &[&str]) -> Result<String> {
+    ctx.log(format!("$ git {}", args.join(" ")));
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .output()
+        .await
+        .context("Failed to run git")?;
+
+    for line in String::from_utf8_lossy(&output.stderr).lines() {
+        ctx.log(line);
+    }
+    if !output.status.success() {
+        anyhow::bail!("git {} failed with {}", args[0], output.status);
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
@@ -0,0 +1,17 @@
+//! Built-in actions for `uses:` steps
+//!
+//! Steps written GitHub-Actions style (`uses: actions/checkout@v4`) are
+//! dispatched to a built-in action by name instead of running a command.
+//! Actions run on the runner host against the job's workspace.
+
+mod traits;
+mod registry;
+mod checkout;
+mod cache;
+mod artifact;
+
+pub use traits::{Action, ActionContext, ActionOutcome, PostAction};
+pub use registry::{action_name, ActionRegistry};
+pub use checkout::CheckoutAction;
+pub use cache::CacheAction;
+pub use artifact::{DownloadArtifactAction, UploadArtifactAction};
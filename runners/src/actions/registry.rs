@@ -0,0 +1,52 @@
+//! Lookup of built-in actions by `uses:` reference
+
+use std::collections::HashMap;
+
+use super::artifact::{DownloadArtifactAction, UploadArtifactAction};
+use super::cache::CacheAction;
+use super::checkout::CheckoutAction;
+use super::traits::Action;
+
+/// Action name of a `uses:` reference: `actions/checkout@v4` and
+/// `checkout` both name `checkout`
+pub fn action_name(uses: &str) -> &str {
+    let name = uses.split('@').next().unwrap_or_default();
+    name.rsplit('/').next().unwrap_or_default()
+}
+
+/// Built-in actions by name
+pub struct ActionRegistry {
+    actions: HashMap<&'static str, Box<dyn Action>>,
+}
+
+impl ActionRegistry {
+    pub fn builtin() -> Self {
+        let mut actions: HashMap<&'static str, Box<dyn Action>> = HashMap::new();
+        actions.insert("checkout", Box::new(CheckoutAction));
+        actions.insert("cache", Box::new(CacheAction));
+        actions.insert("upload-artifact", Box::new(UploadArtifactAction));
+        actions.insert("download-artifact", Box::new(DownloadArtifactAction));
+        Self { actions }
+    }
+
+    /// The action a `uses:` reference names
+    pub fn get(&self, uses: &str) -> Option<&dyn Action> {
+        self.actions.get(action_name(uses)).map(|a| a.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        assert_eq!(action_name("actions/checkout@v4"), "checkout");
+        assert_eq!(action_name("upload-artifact"), "upload-artifact");
+        assert_eq!(action_name("muelsyse/cache@main"), "cache");
+
+        let registry = ActionRegistry::builtin();
+        assert!(registry.get("actions/download-artifact@v4").is_some());
+        assert!(registry.get("actions/setup-node@v4").is_none());
+    }
+}
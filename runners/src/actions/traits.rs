@@ -0,0 +1,93 @@
+//! Action trait and common types
+
+use anyhow::Result;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
+use crate::artifact::PackagedArtifact;
+use crate::cache::{resolve_path, CacheStore};
+use crate::client::{HttpClient, JobSpec};
+use crate::config::Settings;
+use crate::executor::{OutputLine, OutputSink, OutputStream};
+
+/// What an action runs against
+pub struct ActionContext<'a> {
+    pub job: &'a JobSpec,
+    pub workspace: &'a Path,
+    /// `with:` inputs, with expressions already interpolated
+    pub inputs: HashMap<String, String>,
+    pub settings: &'a Settings,
+    pub http: &'a HttpClient,
+    /// Receives the action's log lines
+    pub output: &'a OutputSink,
+}
+
+impl ActionContext<'_> {
+    /// A non-empty input
+    pub fn input(&self, name: &str) -> Option<&str> {
+        self.inputs.get(name).map(|v| v.trim()).filter(|v| !v.is_empty())
+    }
+
+    pub fn required(&self, name: &str) -> Result<&str> {
+        self.input(name).ok_or_else(|| anyhow::anyhow!("Input '{}' is required", name))
+    }
+
+    /// A multi-line input as its non-empty lines
+    pub fn lines(&self, name: &str) -> Vec<String> {
+        self.input(name)
+            .map(|v| v.lines().map(str::trim).filter(|l| !l.is_empty()).map(String::from).collect())
+            .unwrap_or_default()
+    }
+
+    /// A path inside the workspace
+    pub fn workspace_path(&self, relative: &str) -> Result<PathBuf> {
+        let path = Path::new(relative);
+        if path.is_absolute() || path.components().any(|c| c == Component::ParentDir) {
+            anyhow::bail!("Path '{}' must be relative to the workspace", relative);
+        }
+        Ok(self.workspace.join(path))
+    }
+
+    /// Write a line to the step log
+    pub fn log(&self, text: impl Into<String>) {
+        let _ = self.output.send(OutputLine::new(OutputStream::Stdout, text));
+    }
+}
+
+/// Result of a successful action
+#[derive(Debug, Default)]
+pub struct ActionOutcome {
+    /// Step outputs
+    pub outputs: HashMap<String, String>,
+    /// Artifacts for the runner to publish
+    pub artifacts: Vec<PackagedArtifact>,
+    /// Work to do after the job's steps succeed
+    pub post: Option<PostAction>,
+}
+
+/// Deferred work registered by an action
+#[derive(Debug, Clone)]
+pub enum PostAction {
+    /// Save paths under a cache key that missed on restore
+    SaveCache { key: String, paths: Vec<String> },
+}
+
+impl PostAction {
+    pub async fn run(&self, settings: &Settings, workspace: &Path) -> Result<()> {
+        match self {
+            Self::SaveCache { key, paths } => {
+                let store = CacheStore::new(settings.workspace.cache_path.clone(), settings.workspace.cache_max_bytes);
+                let sources: Vec<PathBuf> = paths.iter().map(|p| resolve_path(p, workspace)).collect();
+                store.save(key, paths, workspace, &sources).await?;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A built-in action
+#[async_trait]
+pub trait Action: Send + Sync {
+    async fn run(&self, ctx: &ActionContext<'_>) -> Result<ActionOutcome>;
+}
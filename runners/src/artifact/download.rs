@@ -0,0 +1,172 @@
+//! Artifact download into the workspace
+//!
+//! Artifacts uploaded by earlier jobs of the same execution are fetched into
+//! the staging directory, checked against their SHA256 checksum and
+//! extracted into the workspace. A checksum mismatch leaves the workspace
+//! untouched.
+
+use anyhow::{Context, Result};
+use std::io::{BufRead, Read};
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+use super::manager::sanitize;
+use super::upload::ArtifactUploader;
+use crate::client::HttpClient;
+
+/// First bytes of a gzip stream
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// First bytes of a zstd frame
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// An artifact extracted into the workspace
+#[derive(Debug, Clone)]
+pub struct DownloadedArtifact {
+    pub name: String,
+    /// Directory it was extracted into
+    pub path: PathBuf,
+    pub size_bytes: u64,
+    pub checksum: String,
+}
+
+/// Downloads, verifies and extracts artifacts
+pub struct ArtifactDownloader {
+    staging_dir: PathBuf,
+}
+
+impl ArtifactDownloader {
+    pub fn new(staging_dir: PathBuf) -> Self {
+        Self { staging_dir }
+    }
+
+    /// Download artifact `name` of the execution and extract it into `dest`.
+    ///
+    /// `expected` pins the checksum; otherwise the one reported by the control
+    /// plane is used. The download fails if neither is known.
+    pub async fn download(
+        &self,
+        http: &HttpClient,
+        execution_id: &str,
+        name: &str,
+        expected: Option<&str>,
+        dest: &Path,
+    ) -> Result<DownloadedArtifact> {
+        let dir = self.staging_dir.join(sanitize(execution_id));
+        tokio::fs::create_dir_all(&dir)
+            .await
+            .context("Failed to create artifact download directory")?;
+        let archive = dir.join(format!("{}-{}.tar.gz", sanitize(name), uuid::Uuid::new_v4()));
+
+        let result = async {
+            let reported = http.download_artifact(execution_id, name, &archive).await?;
+            let expected = expected
+                .map(str::to_string)
+                .or(reported)
+                .ok_or_else(|| anyhow::anyhow!("No checksum is known for artifact '{}'", name))?;
+
+            let checksum = Self::verify(&archive, &expected)
+                .await
+                .with_context(|| format!("Artifact '{}' is corrupt", name))?;
+            let size_bytes = ArtifactUploader::get_file_size(&archive).await?;
+            Self::extract(&archive, dest)
+                .await
+                .with_context(|| format!("Failed to extract artifact '{}'", name))?;
+
+            Ok(DownloadedArtifact {
+                name: name.to_string(),
+                path: dest.to_path_buf(),
+                size_bytes,
+                checksum,
+            })
+        }
+        .await;
+
+        let _ = tokio::fs::remove_file(&archive).await;
+        result
+    }
+
+    /// Check an archive's SHA256 checksum, returning it
+    pub async fn verify(archive: &Path, expected: &str) -> Result<String> {
+        let checksum = ArtifactUploader::calculate_checksum(archive).await?;
+        if !checksum.eq_ignore_ascii_case(expected.trim()) {
+            anyhow::bail!("Checksum mismatch: expected {}, got {}", expected.trim(), checksum);
+        }
+        Ok(checksum)
+    }
+
+    /// Unpack a tarball, plain or compressed with gzip or zstd, into `dest`
+    pub async fn extract(archive: &Path, dest: &Path) -> Result<()> {
+        tokio::fs::create_dir_all(dest)
+            .await
+            .context("Failed to create download directory")?;
+
+        debug!("Extracting {:?} into {:?}", archive, dest);
+        let archive = archive.to_path_buf();
+        let dest = dest.to_path_buf();
+        tokio::task::spawn_blocking(move || -> Result<()> {
+            let mut file = std::io::BufReader::new(std::fs::File::open(&archive)?);
+            let magic = file.fill_buf()?;
+            let reader: Box<dyn Read> = if magic.starts_with(&GZIP_MAGIC) {
+                Box::new(flate2::bufread::GzDecoder::new(file))
+            } else if magic.starts_with(&ZSTD_MAGIC) {
+                Box::new(zstd::Decoder::with_buffer(file)?)
+            } else {
+                Box::new(file)
+            };
+            // Entries escaping the destination are skipped by `unpack`
+            tar::Archive::new(reader).unpack(&dest)?;
+            Ok(())
+        })
+        .await
+        .context("Artifact extraction task failed")?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::artifact::ArtifactManager;
+    use crate::client::ArtifactSpec;
+    use crate::config::ArtifactCompression;
+
+    #[tokio::test]
+    async fn test_verify_and_extract() {
+        let root = std::env::temp_dir().join(format!("muelsyse-downloads-{}", uuid::Uuid::new_v4()));
+        let source = root.join("source");
+        std::fs::create_dir_all(source.join("dist")).unwrap();
+        std::fs::write(source.join("dist/app.bin"), b"binary").unwrap();
+
+        let spec = ArtifactSpec { name: "build".into(), paths: vec!["dist".into()], when: Default::default(), stream: false };
+        let artifact = ArtifactManager::new(root.join("staging"))
+            .package("job-1", &source, &spec)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let checksum = ArtifactDownloader::verify(&artifact.path, &artifact.checksum.to_uppercase()).await.unwrap();
+        assert_eq!(checksum, artifact.checksum);
+        let mismatch = ArtifactDownloader::verify(&artifact.path, &"0".repeat(64)).await.unwrap_err();
+        assert!(mismatch.to_string().contains("Checksum mismatch"), "{}", mismatch);
+
+        let dest = root.join("workspace/inputs");
+        ArtifactDownloader::extract(&artifact.path, &dest).await.unwrap();
+        assert_eq!(std::fs::read(dest.join("dist/app.bin")).unwrap(), b"binary");
+
+        // The archive format is detected from its contents
+        for compression in [ArtifactCompression::Zstd, ArtifactCompression::None] {
+            let artifact = ArtifactManager::new(root.join("staging"))
+                .with_compression(compression)
+                .package("job-2", &source, &spec)
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(artifact.path.to_string_lossy().ends_with(compression.extension()));
+            let dest = root.join(format!("workspace/{}", compression.extension()));
+            ArtifactDownloader::extract(&artifact.path, &dest).await.unwrap();
+            assert_eq!(std::fs::read(dest.join("dist/app.bin")).unwrap(), b"binary");
+        }
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,285 @@
+//! Artifact collection and upload
+//!
+//! After a job's steps finish, files matching each declared artifact's glob
+//! patterns are packaged into a tarball under the staging directory,
+//! compressed as configured, and uploaded to the control plane in chunks
+//! (see [`super::upload::ChunkedUpload`]).
+//!
+//! Managers built `with_metadata` also describe the binaries they package;
+//! see [`super::metadata`].
+
+use anyhow::{Context, Result};
+use std::path::{Component, Path, PathBuf};
+use tracing::debug;
+
+use super::metadata::{self, BinaryMetadata};
+use super::upload::ArtifactUploader;
+use crate::client::ArtifactSpec;
+use crate::config::ArtifactCompression;
+
+/// A packaged artifact ready for upload
+#[derive(Debug, Clone)]
+pub struct PackagedArtifact {
+    pub name: String,
+    /// Archive on disk
+    pub path: PathBuf,
+    /// Number of matched workspace entries
+    pub entries: usize,
+    pub size_bytes: u64,
+    pub checksum: String,
+    /// Recognized binaries and packages among the entries
+    pub metadata: Vec<BinaryMetadata>,
+}
+
+impl PackagedArtifact {
+    /// Name to upload the archive as
+    pub fn file_name(&self) -> String {
+        self.path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| self.name.clone())
+    }
+}
+
+/// Collects and packages job artifacts
+pub struct ArtifactManager {
+    staging_dir: PathBuf,
+    extract_metadata: bool,
+    compression: ArtifactCompression,
+}
+
+impl ArtifactManager {
+    pub fn new(staging_dir: PathBuf) -> Self {
+        Self { staging_dir, extract_metadata: false, compression: ArtifactCompression::default() }
+    }
+
+    /// Inspect packaged files for binary metadata
+    pub fn with_metadata(mut self, extract: bool) -> Self {
+        self.extract_metadata = extract;
+        self
+    }
+
+    /// Compress archives with `compression`
+    pub fn with_compression(mut self, compression: ArtifactCompression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    fn job_dir(&self, job_id: &str) -> PathBuf {
+        self.staging_dir.join(job_id)
+    }
+
+    /// Workspace entries matching `patterns`, relative to the workspace.
+    ///
+    /// Patterns must be relative and may not contain `..`.
+    pub fn collect(workspace: &Path, patterns: &[String]) -> Result<Vec<PathBuf>> {
+        let base = glob::Pattern::escape(&workspace.display().to_string());
+        let mut matches = Vec::new();
+
+        for pattern in patterns {
+            let relative = Path::new(pattern);
+            if relative.is_absolute() || relative.components().any(|c| c == Component::ParentDir) {
+                anyhow::bail!("Artifact path '{}' must be relative to the workspace", pattern);
+            }
+
+            let full = format!("{}/{}", base, pattern.trim_start_matches("./"));
+            for entry in glob::glob(&full).with_context(|| format!("Invalid artifact pattern '{}'", pattern))? {
+                let entry = entry?;
+                if let Ok(relative) = entry.strip_prefix(workspace) {
+                    matches.push(relative.to_path_buf());
+                }
+            }
+        }
+
+        matches.sort();
+        matches.dedup();
+        Ok(matches)
+    }
+
+    /// Package the files matching `spec` into an archive.
+    ///
+    /// Returns `None` if nothing matched.
+    pub async fn package(&self, job_id: &str, workspace: &Path, spec: &ArtifactSpec) -> Result<Option<PackagedArtifact>> {
+        let entries = Self::collect(workspace, &spec.paths)?;
+        if entries.is_empty() {
+            return Ok(None);
+        }
+
+        let dir = self.job_dir(job_id);
+        tokio::fs::create_dir_all(&dir)
+            .await
+            .context("Failed to create artifact staging directory")?;
+        let path = dir.join(format!("{}.{}", sanitize(&spec.name), self.compression.extension()));
+
+        debug!("Packaging {} entries into {:?}", entries.len(), path);
+        let count = entries.len();
+        let workspace = workspace.to_path_buf();
+        let archive = path.clone();
+        let extract_metadata = self.extract_metadata;
+        let compression = self.compression;
+        let metadata = tokio::task::spawn_blocking(move || -> Result<Vec<BinaryMetadata>> {
+            write_archive(&archive, &workspace, &entries, compression)?;
+            Ok(if extract_metadata { metadata::extract(&workspace, &entries) } else { Vec::new() })
+        })
+        .await
+        .context("Artifact packaging task failed")??;
+
+        Ok(Some(PackagedArtifact {
+            name: spec.name.clone(),
+            size_bytes: ArtifactUploader::get_file_size(&path).await?,
+            checksum: ArtifactUploader::calculate_checksum(&path).await?,
+            path,
+            entries: count,
+            metadata,
+        }))
+    }
+
+    /// Stage generated `contents` as a single-file artifact
+    pub async fn stage(&self, job_id: &str, name: &str, file_name: &str, contents: &[u8]) -> Result<PackagedArtifact> {
+        let dir = self.job_dir(job_id);
+        tokio::fs::create_dir_all(&dir)
+            .await
+            .context("Failed to create artifact staging directory")?;
+        let path = dir.join(sanitize(file_name));
+        tokio::fs::write(&path, contents)
+            .await
+            .with_context(|| format!("Failed to write artifact {:?}", path))?;
+
+        Ok(PackagedArtifact {
+            name: name.to_string(),
+            size_bytes: contents.len() as u64,
+            checksum: ArtifactUploader::calculate_checksum(&path).await?,
+            path,
+            entries: 1,
+            metadata: Vec::new(),
+        })
+    }
+
+    /// Keep an artifact on the runner under `dir` instead of uploading it
+    pub async fn quarantine(&self, artifact: &PackagedArtifact, dir: &Path) -> Result<PathBuf> {
+        tokio::fs::create_dir_all(dir)
+            .await
+            .context("Failed to create quarantine directory")?;
+        let path = dir.join(artifact.path.file_name().unwrap_or_default());
+
+        // Staging and quarantine may be on different filesystems
+        if tokio::fs::rename(&artifact.path, &path).await.is_err() {
+            tokio::fs::copy(&artifact.path, &path)
+                .await
+                .with_context(|| format!("Failed to quarantine artifact {:?}", artifact.path))?;
+        }
+        Ok(path)
+    }
+
+    /// Remove a job's staged archives
+    pub async fn remove_staging(&self, job_id: &str) {
+        let dir = self.job_dir(job_id);
+        match tokio::fs::remove_dir_all(&dir).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => tracing::warn!("Failed to remove artifact staging {:?}: {}", dir, e),
+        }
+    }
+}
+
+/// Write `entries` (relative to `workspace`) into a tarball compressed with
+/// `compression`. Symlinks are stored as links, never followed out of the
+/// workspace.
+fn write_archive(path: &Path, workspace: &Path, entries: &[PathBuf], compression: ArtifactCompression) -> Result<()> {
+    let file = std::fs::File::create(path)
+        .with_context(|| format!("Failed to create artifact archive {:?}", path))?;
+    let file = std::io::BufWriter::new(file);
+    let file = match compression {
+        ArtifactCompression::None => append_entries(file, workspace, entries)?,
+        ArtifactCompression::Gzip => {
+            let encoder = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+            append_entries(encoder, workspace, entries)?.finish()?
+        }
+        ArtifactCompression::Zstd => {
+            let encoder = zstd::Encoder::new(file, zstd::DEFAULT_COMPRESSION_LEVEL)?;
+            append_entries(encoder, workspace, entries)?.finish()?
+        }
+    };
+    file.into_inner().map_err(|e| e.into_error())?;
+    Ok(())
+}
+
+/// Write the tar stream of `entries` to `writer`, returning it
+fn append_entries<W: std::io::Write>(writer: W, workspace: &Path, entries: &[PathBuf]) -> Result<W> {
+    let mut builder = tar::Builder::new(writer);
+    builder.follow_symlinks(false);
+
+    for entry in entries {
+        let source = workspace.join(entry);
+        if source.is_dir() && !source.is_symlink() {
+            builder.append_dir_all(entry, &source)
+        } else {
+            builder.append_path_with_name(&source, entry)
+        }
+        .with_context(|| format!("Failed to add {:?} to artifact", entry))?;
+    }
+
+    Ok(builder.into_inner()?)
+}
+
+/// Artifact name as a safe file name
+pub(super) fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') { c } else { '_' })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_collect_and_package() {
+        let root = std::env::temp_dir().join(format!("muelsyse-artifacts-{}", uuid::Uuid::new_v4()));
+        let workspace = root.join("workspace");
+        std::fs::create_dir_all(workspace.join("dist/sub")).unwrap();
+        std::fs::write(workspace.join("dist/app.bin"), b"binary").unwrap();
+        std::fs::write(workspace.join("dist/sub/notes.txt"), b"notes").unwrap();
+        std::fs::write(workspace.join("report.xml"), b"<xml/>").unwrap();
+
+        let patterns = vec!["dist/*".to_string(), "*.xml".to_string(), "missing/*".to_string()];
+        let matches = ArtifactManager::collect(&workspace, &patterns).unwrap();
+        assert_eq!(matches, vec![
+            PathBuf::from("dist/app.bin"),
+            PathBuf::from("dist/sub"),
+            PathBuf::from("report.xml"),
+        ]);
+
+        assert!(ArtifactManager::collect(&workspace, &["../etc/*".to_string()]).is_err());
+        assert!(ArtifactManager::collect(&workspace, &["/etc/passwd".to_string()]).is_err());
+
+        let manager = ArtifactManager::new(root.join("staging"));
+        let spec = ArtifactSpec {
+            name: "build output".into(),
+            paths: patterns,
+            when: Default::default(),
+            stream: false,
+        };
+        let artifact = manager.package("job-1", &workspace, &spec).await.unwrap().unwrap();
+        assert_eq!(artifact.entries, 3);
+        assert!(artifact.path.ends_with("job-1/build_output.tar.gz"));
+        assert!(artifact.size_bytes > 0);
+        assert_eq!(artifact.checksum.len(), 64);
+
+        let decoder = flate2::read::GzDecoder::new(std::fs::File::open(&artifact.path).unwrap());
+        let mut names: Vec<String> = tar::Archive::new(decoder)
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap().path().unwrap().display().to_string())
+            .collect();
+        names.sort();
+        assert!(names.contains(&"dist/sub/notes.txt".to_string()), "{:?}", names);
+
+        let empty = ArtifactSpec { name: "none".into(), paths: vec!["nope/*".into()], when: Default::default(), stream: false };
+        assert!(manager.package("job-1", &workspace, &empty).await.unwrap().is_none());
+
+        manager.remove_staging("job-1").await;
+        assert!(!root.join("staging/job-1").exists());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
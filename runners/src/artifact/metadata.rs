@@ -0,0 +1,529 @@
+//! Binary artifact metadata
+//!
+//! With `job.artifact_metadata` on, the files packaged into an artifact are
+//! inspected and what is recognized is reported with `artifact_ready`: the
+//! architecture of ELF and PE binaries, the file version of PE binaries,
+//! and the name, version and compatibility tags of Python wheels and Java
+//! archives. The control plane uses it to describe artifacts and to warn
+//! about ones that do not match where they are deployed.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Header bytes read to recognize a file
+const HEADER_BYTES: usize = 4096;
+/// Larger archives and PE files are identified from their header only
+const MAX_READ_BYTES: u64 = 64 * 1024 * 1024;
+/// Files looked at per artifact
+const MAX_FILES: usize = 1000;
+/// Metadata records reported per artifact
+const MAX_RECORDS: usize = 50;
+
+/// Kind of a recognized file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BinaryFormat {
+    Elf,
+    Pe,
+    Wheel,
+    Jar,
+}
+
+/// What was learned about one file of an artifact
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BinaryMetadata {
+    /// Path inside the artifact
+    pub path: String,
+    pub format: BinaryFormat,
+    /// Target architecture, named like the runner's `arch` label
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arch: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// Format-specific details, e.g. wheel tags or manifest attributes
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub details: BTreeMap<String, String>,
+}
+
+impl BinaryMetadata {
+    fn new(path: &Path, format: BinaryFormat) -> Self {
+        Self {
+            path: path.display().to_string(),
+            format,
+            arch: None,
+            version: None,
+            details: BTreeMap::new(),
+        }
+    }
+
+    fn detail(&mut self, key: &str, value: impl Into<String>) {
+        self.details.insert(key.to_string(), value.into());
+    }
+}
+
+/// Metadata of the recognized files among `entries` (relative to
+/// `workspace`), descending into directories but not symlinks
+pub fn extract(workspace: &Path, entries: &[PathBuf]) -> Vec<BinaryMetadata> {
+    let mut files = Vec::new();
+    for entry in entries {
+        walk(workspace, entry, &mut files);
+    }
+
+    files
+        .iter()
+        .filter_map(|file| inspect(&workspace.join(file), file))
+        .take(MAX_RECORDS)
+        .collect()
+}
+
+fn walk(workspace: &Path, relative: &Path, files: &mut Vec<PathBuf>) {
+    if files.len() >= MAX_FILES {
+        return;
+    }
+    let path = workspace.join(relative);
+    let Ok(metadata) = std::fs::symlink_metadata(&path) else {
+        return;
+    };
+    if metadata.is_file() {
+        files.push(relative.to_path_buf());
+    } else if metadata.is_dir() {
+        let Ok(dir) = std::fs::read_dir(&path) else {
+            return;
+        };
+        let mut children: Vec<PathBuf> = dir.flatten().map(|e| relative.join(e.file_name())).collect();
+        children.sort();
+        for child in children {
+            walk(workspace, &child, files);
+        }
+    }
+}
+
+/// Metadata of a single-file artifact, reported under its file name
+pub async fn inspect_file(path: &Path) -> Vec<BinaryMetadata> {
+    let path = path.to_path_buf();
+    let display = PathBuf::from(path.file_name().unwrap_or_default());
+    tokio::task::spawn_blocking(move || inspect(&path, &display).into_iter().collect())
+        .await
+        .unwrap_or_default()
+}
+
+/// Metadata of one file, reported as `display`, if its format is recognized
+pub fn inspect(path: &Path, display: &Path) -> Option<BinaryMetadata> {
+    let mut file = std::fs::File::open(path).ok()?;
+    let size = file.metadata().ok()?.len();
+    let mut data = Vec::with_capacity(HEADER_BYTES);
+    file.by_ref().take(HEADER_BYTES as u64).read_to_end(&mut data).ok()?;
+
+    if data.starts_with(b"\x7fELF") {
+        return elf(&data, display);
+    }
+
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_ascii_lowercase();
+    let archive = data.starts_with(b"PK\x03\x04") && matches!(extension.as_str(), "whl" | "jar" | "war");
+    if !data.starts_with(b"MZ") && !archive {
+        return None;
+    }
+    if size <= MAX_READ_BYTES {
+        file.read_to_end(&mut data).ok()?;
+    }
+
+    if archive {
+        if extension == "whl" {
+            wheel(&data, display)
+        } else {
+            jar(&data, display)
+        }
+    } else {
+        pe(&data, display)
+    }
+}
+
+fn elf(data: &[u8], display: &Path) -> Option<BinaryMetadata> {
+    let little = match data.get(5)? {
+        1 => true,
+        2 => false,
+        _ => return None,
+    };
+    let bits = match data.get(4)? {
+        1 => 32,
+        2 => 64,
+        _ => return None,
+    };
+    let read_u16 = |offset: usize| {
+        let bytes = [*data.get(offset)?, *data.get(offset + 1)?];
+        Some(if little { u16::from_le_bytes(bytes) } else { u16::from_be_bytes(bytes) })
+    };
+
+    let arch = match read_u16(18)? {
+        0x03 => "x86".to_string(),
+        0x3e => "x86_64".to_string(),
+        0x28 => "arm".to_string(),
+        0xb7 => "aarch64".to_string(),
+        0xf3 => format!("riscv{}", bits),
+        0x08 => "mips".to_string(),
+        0x14 => "powerpc".to_string(),
+        0x15 if little => "powerpc64le".to_string(),
+        0x15 => "powerpc64".to_string(),
+        0x16 => "s390x".to_string(),
+        0x102 => "loongarch64".to_string(),
+        machine => format!("elf-machine-{:#x}", machine),
+    };
+    let kind = match read_u16(16)? {
+        1 => "relocatable",
+        2 => "executable",
+        3 => "shared-object",
+        4 => "core",
+        _ => "unknown",
+    };
+
+    let mut metadata = BinaryMetadata::new(display, BinaryFormat::Elf);
+    metadata.arch = Some(arch);
+    metadata.detail("bits", bits.to_string());
+    metadata.detail("endian", if little { "little" } else { "big" });
+    metadata.detail("type", kind);
+    Some(metadata)
+}
+
+fn pe(data: &[u8], display: &Path) -> Option<BinaryMetadata> {
+    let header = le_u32(data, 0x3c)? as usize;
+    if data.get(header..header + 4)? != b"PE\0\0" {
+        return None;
+    }
+
+    let arch = match le_u16(data, header + 4)? {
+        0x14c => "x86".to_string(),
+        0x8664 => "x86_64".to_string(),
+        0x1c0 | 0x1c4 => "arm".to_string(),
+        0xaa64 => "aarch64".to_string(),
+        machine => format!("pe-machine-{:#x}", machine),
+    };
+    let characteristics = le_u16(data, header + 22)?;
+
+    let mut metadata = BinaryMetadata::new(display, BinaryFormat::Pe);
+    metadata.arch = Some(arch);
+    metadata.detail("type", if characteristics & 0x2000 != 0 { "dll" } else { "executable" });
+    match le_u16(data, header + 24) {
+        Some(0x10b) => metadata.detail("bits", "32"),
+        Some(0x20b) => metadata.detail("bits", "64"),
+        _ => {}
+    }
+    match le_u16(data, header + 24 + 68) {
+        Some(2) => metadata.detail("subsystem", "windows-gui"),
+        Some(3) => metadata.detail("subsystem", "windows-console"),
+        _ => {}
+    }
+    metadata.version = pe_file_version(data);
+    Some(metadata)
+}
+
+/// File version from the `VS_FIXEDFILEINFO` of the version resource
+fn pe_file_version(data: &[u8]) -> Option<String> {
+    const SIGNATURE: [u8; 4] = 0xfeef04bd_u32.to_le_bytes();
+    let mut offset = 0;
+    while let Some(found) = data[offset..].windows(4).position(|w| w == SIGNATURE) {
+        let start = offset + found;
+        if le_u32(data, start + 4) == Some(0x0001_0000) {
+            let major = le_u32(data, start + 8)?;
+            let minor = le_u32(data, start + 12)?;
+            return Some(format!("{}.{}.{}.{}", major >> 16, major & 0xffff, minor >> 16, minor & 0xffff));
+        }
+        offset = start + 4;
+    }
+    None
+}
+
+fn wheel(data: &[u8], display: &Path) -> Option<BinaryMetadata> {
+    let entries = zip_entries(data)?;
+    let mut metadata = BinaryMetadata::new(display, BinaryFormat::Wheel);
+
+    // {name}-{version}(-{build})?-{python}-{abi}-{platform}.whl
+    let stem = display.file_stem().and_then(|s| s.to_str()).unwrap_or_default();
+    let parts: Vec<&str> = stem.split('-').collect();
+    if let [.., python, abi, platform] = parts[..] {
+        if parts.len() >= 5 {
+            metadata.detail("python", python);
+            metadata.detail("abi", abi);
+            metadata.detail("platform", platform);
+            metadata.arch = wheel_arch(platform);
+        }
+    }
+
+    let entry = entries
+        .iter()
+        .find(|e| e.name.ends_with(".dist-info/METADATA") && e.name.matches('/').count() == 1);
+    if let Some(contents) = entry.and_then(|e| zip_read(data, e)) {
+        let headers = parse_headers(&String::from_utf8_lossy(&contents), false);
+        metadata.version = headers.get("Version").cloned();
+        for key in ["Name", "Requires-Python"] {
+            if let Some(value) = headers.get(key) {
+                metadata.detail(&key.to_ascii_lowercase(), value.clone());
+            }
+        }
+    }
+    Some(metadata)
+}
+
+/// Architecture of a wheel platform tag, e.g. `manylinux_2_17_x86_64`
+fn wheel_arch(platform: &str) -> Option<String> {
+    // Compressed tag sets such as `manylinux1_x86_64.manylinux2014_x86_64`
+    let platform = platform.split('.').next()?;
+    let arch = match platform {
+        "any" => return None,
+        "win32" => "x86",
+        "win_amd64" => "x86_64",
+        "win_arm64" => "aarch64",
+        _ if platform.ends_with("_universal2") => "universal2",
+        _ if platform.ends_with("_x86_64") => "x86_64",
+        _ if platform.ends_with("_aarch64") || platform.ends_with("_arm64") => "aarch64",
+        _ if platform.ends_with("_i686") => "x86",
+        _ if platform.ends_with("_armv7l") => "arm",
+        _ if platform.ends_with("_ppc64le") => "powerpc64le",
+        _ if platform.ends_with("_s390x") => "s390x",
+        _ => return None,
+    };
+    Some(arch.to_string())
+}
+
+/// Manifest attributes reported for Java archives
+const JAR_ATTRIBUTES: [&str; 6] = [
+    "Implementation-Title",
+    "Main-Class",
+    "Automatic-Module-Name",
+    "Bundle-SymbolicName",
+    "Created-By",
+    "Build-Jdk-Spec",
+];
+
+fn jar(data: &[u8], display: &Path) -> Option<BinaryMetadata> {
+    let entries = zip_entries(data)?;
+    let mut metadata = BinaryMetadata::new(display, BinaryFormat::Jar);
+
+    let manifest = entries.iter().find(|e| e.name.eq_ignore_ascii_case("META-INF/MANIFEST.MF"));
+    if let Some(contents) = manifest.and_then(|e| zip_read(data, e)) {
+        let attributes = parse_headers(&String::from_utf8_lossy(&contents), true);
+        metadata.version = attributes
+            .get("Implementation-Version")
+            .or_else(|| attributes.get("Bundle-Version"))
+            .cloned();
+        for key in JAR_ATTRIBUTES {
+            if let Some(value) = attributes.get(key) {
+                metadata.detail(&key.to_ascii_lowercase(), value.clone());
+            }
+        }
+    }
+
+    // Java release the classes were compiled for, from the class file version
+    let class = entries.iter().find(|e| e.name.ends_with(".class") && !e.name.starts_with("META-INF/"));
+    if let Some(contents) = class.and_then(|e| zip_read(data, e)) {
+        if contents.starts_with(&[0xca, 0xfe, 0xba, 0xbe]) && contents.len() >= 8 {
+            let major = u16::from_be_bytes([contents[6], contents[7]]);
+            if major > 44 {
+                metadata.detail("java", (major - 44).to_string());
+            }
+        }
+    }
+    Some(metadata)
+}
+
+/// `Key: value` lines up to the first blank line; with `continuations`,
+/// lines starting with a space continue the previous value (JAR manifests)
+fn parse_headers(text: &str, continuations: bool) -> BTreeMap<String, String> {
+    let mut headers: BTreeMap<String, String> = BTreeMap::new();
+    let mut last: Option<String> = None;
+    for line in text.lines() {
+        let line = line.trim_end_matches('\r');
+        if line.is_empty() {
+            break;
+        }
+        if let (true, Some(rest)) = (continuations, line.strip_prefix(' ')) {
+            if let Some(value) = last.as_ref().and_then(|key| headers.get_mut(key)) {
+                value.push_str(rest);
+            }
+            continue;
+        }
+        if let Some((key, value)) = line.split_once(':') {
+            // The first occurrence wins, e.g. over repeated `Classifier`s
+            headers.entry(key.trim().to_string()).or_insert_with(|| value.trim().to_string());
+            last = Some(key.trim().to_string());
+        }
+    }
+    headers
+}
+
+/// A file in a zip archive's central directory
+struct ZipEntry {
+    name: String,
+    method: u16,
+    compressed_size: usize,
+    local_offset: usize,
+}
+
+/// Entries of a zip archive; zip64 archives are not supported
+fn zip_entries(data: &[u8]) -> Option<Vec<ZipEntry>> {
+    // End of central directory record, followed by a comment of up to 64 KiB
+    let search_from = data.len().saturating_sub(22 + 0xffff);
+    let end = search_from + data[search_from..].windows(4).rposition(|w| w == b"PK\x05\x06")?;
+    let count = le_u16(data, end + 10)? as usize;
+    let mut offset = le_u32(data, end + 16)? as usize;
+
+    let mut entries = Vec::with_capacity(count);
+    for _ in 0..count {
+        if data.get(offset..offset + 4)? != b"PK\x01\x02" {
+            return None;
+        }
+        let name_len = le_u16(data, offset + 28)? as usize;
+        let extra_len = le_u16(data, offset + 30)? as usize;
+        let comment_len = le_u16(data, offset + 32)? as usize;
+        let name = data.get(offset + 46..offset + 46 + name_len)?;
+        entries.push(ZipEntry {
+            name: String::from_utf8_lossy(name).into_owned(),
+            method: le_u16(data, offset + 10)?,
+            compressed_size: le_u32(data, offset + 20)? as usize,
+            local_offset: le_u32(data, offset + 42)? as usize,
+        });
+        offset += 46 + name_len + extra_len + comment_len;
+    }
+    Some(entries)
+}
+
+/// Contents of a stored or deflated zip entry
+fn zip_read(data: &[u8], entry: &ZipEntry) -> Option<Vec<u8>> {
+    let header = entry.local_offset;
+    if data.get(header..header + 4)? != b"PK\x03\x04" {
+        return None;
+    }
+    let start = header + 30 + le_u16(data, header + 26)? as usize + le_u16(data, header + 28)? as usize;
+    let compressed = data.get(start..start.checked_add(entry.compressed_size)?)?;
+
+    match entry.method {
+        0 => Some(compressed.to_vec()),
+        8 => {
+            let mut contents = Vec::new();
+            flate2::read::DeflateDecoder::new(compressed)
+                .take(MAX_READ_BYTES)
+                .read_to_end(&mut contents)
+                .ok()?;
+            Some(contents)
+        }
+        _ => None,
+    }
+}
+
+fn le_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(offset..offset + 2)?.try_into().ok()?))
+}
+
+fn le_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// A zip archive of deflated `files`
+    fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut central = Vec::new();
+        for (name, contents) in files {
+            let mut encoder = flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(contents).unwrap();
+            let compressed = encoder.finish().unwrap();
+
+            let offset = data.len() as u32;
+            data.extend_from_slice(b"PK\x03\x04");
+            data.extend_from_slice(&[20, 0, 0, 0, 8, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+            data.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+            data.extend_from_slice(&(contents.len() as u32).to_le_bytes());
+            data.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            data.extend_from_slice(&[0, 0]);
+            data.extend_from_slice(name.as_bytes());
+            data.extend_from_slice(&compressed);
+
+            central.extend_from_slice(b"PK\x01\x02");
+            central.extend_from_slice(&[20, 0, 20, 0, 0, 0, 8, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+            central.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+            central.extend_from_slice(&(contents.len() as u32).to_le_bytes());
+            central.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            central.extend_from_slice(&[0; 12]);
+            central.extend_from_slice(&offset.to_le_bytes());
+            central.extend_from_slice(name.as_bytes());
+        }
+        let central_offset = data.len() as u32;
+        data.extend_from_slice(&central);
+        data.extend_from_slice(b"PK\x05\x06\0\0\0\0");
+        data.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        data.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        data.extend_from_slice(&(central.len() as u32).to_le_bytes());
+        data.extend_from_slice(&central_offset.to_le_bytes());
+        data.extend_from_slice(&[0, 0]);
+        data
+    }
+
+    #[test]
+    fn test_extract_metadata() {
+        let workspace = std::env::temp_dir().join(format!("muelsyse-binaries-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(workspace.join("dist")).unwrap();
+
+        // 64-bit little-endian aarch64 shared object
+        let mut elf = vec![0u8; 64];
+        elf[..6].copy_from_slice(b"\x7fELF\x02\x01");
+        elf[16..20].copy_from_slice(&[3, 0, 0xb7, 0]);
+        std::fs::write(workspace.join("dist/libapp.so"), &elf).unwrap();
+
+        // x86_64 console executable with file version 1.2.3.4
+        let mut exe = vec![0u8; 0x200];
+        exe[..2].copy_from_slice(b"MZ");
+        exe[0x3c..0x40].copy_from_slice(&0x80u32.to_le_bytes());
+        exe[0x80..0x86].copy_from_slice(b"PE\0\0\x64\x86");
+        exe[0x98..0x9a].copy_from_slice(&0x20bu16.to_le_bytes());
+        exe[0x98 + 68] = 3;
+        exe[0x100..0x110].copy_from_slice(&[0xbd, 0x04, 0xef, 0xfe, 0, 0, 1, 0, 2, 0, 1, 0, 4, 0, 3, 0]);
+        std::fs::write(workspace.join("dist/app.exe"), &exe).unwrap();
+
+        let wheel = zip(&[
+            ("app/__init__.py", b""),
+            ("app-1.0.dist-info/METADATA", b"Metadata-Version: 2.1\nName: app\nVersion: 1.0\nRequires-Python: >=3.9\n\nLong description\nVersion: 2\n"),
+        ]);
+        std::fs::write(workspace.join("dist/app-1.0-cp311-cp311-manylinux_2_17_x86_64.whl"), wheel).unwrap();
+
+        let manifest = b"Manifest-Version: 1.0\r\nMain-Class: com.example.Ma\r\n in\r\nImplementation-Version: 2.5.0\r\n\r\n";
+        let jar = zip(&[
+            ("META-INF/MANIFEST.MF", manifest),
+            ("com/example/Main.class", &[0xca, 0xfe, 0xba, 0xbe, 0, 0, 0, 61]),
+        ]);
+        std::fs::write(workspace.join("app.jar"), jar).unwrap();
+        std::fs::write(workspace.join("dist/README"), b"not a binary").unwrap();
+
+        let found = extract(&workspace, &[PathBuf::from("dist"), PathBuf::from("app.jar")]);
+        assert_eq!(found.len(), 4, "{:?}", found);
+
+        assert_eq!(found[0].path, "dist/app-1.0-cp311-cp311-manylinux_2_17_x86_64.whl");
+        assert_eq!(found[0].format, BinaryFormat::Wheel);
+        assert_eq!(found[0].arch.as_deref(), Some("x86_64"));
+        assert_eq!(found[0].version.as_deref(), Some("1.0"));
+        assert_eq!(found[0].details["abi"], "cp311");
+        assert_eq!(found[0].details["requires-python"], ">=3.9");
+
+        assert_eq!(found[1].path, "dist/app.exe");
+        assert_eq!(found[1].arch.as_deref(), Some("x86_64"));
+        assert_eq!(found[1].version.as_deref(), Some("1.2.3.4"));
+        assert_eq!(found[1].details["subsystem"], "windows-console");
+
+        assert_eq!(found[2].format, BinaryFormat::Elf);
+        assert_eq!(found[2].arch.as_deref(), Some("aarch64"));
+        assert_eq!(found[2].details["type"], "shared-object");
+
+        assert_eq!(found[3].format, BinaryFormat::Jar);
+        assert_eq!(found[3].version.as_deref(), Some("2.5.0"));
+        assert_eq!(found[3].details["main-class"], "com.example.Main");
+        assert_eq!(found[3].details["java"], "17");
+
+        assert_eq!(wheel_arch("macosx_11_0_arm64"), Some("aarch64".to_string()));
+        assert_eq!(wheel_arch("any"), None);
+        let _ = std::fs::remove_dir_all(&workspace);
+    }
+}
@@ -1,5 +1,13 @@
 //! Artifact utilities
 
+pub mod download;
+pub mod manager;
+pub mod metadata;
+pub mod stream;
 pub mod upload;
 
-pub use upload::ArtifactUploader;
+pub use download::{ArtifactDownloader, DownloadedArtifact};
+pub use manager::{ArtifactManager, PackagedArtifact};
+pub use metadata::{inspect_file, BinaryFormat, BinaryMetadata};
+pub use stream::{ArtifactStream, StreamedArtifact};
+pub use upload::{ArtifactUploader, ChunkedUpload};
@@ -0,0 +1,264 @@
+//! Artifact streaming during a job
+//!
+//! An artifact declared with `stream: true` names a single workspace file
+//! that grows while the job runs (a test video, a long trace). Its appended
+//! bytes are uploaded in chunks as the job goes, so only the tail is left
+//! when the steps finish. The `artifact_ready` message carrying the upload
+//! id finalizes it.
+
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::path::{Component, Path, PathBuf};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tracing::debug;
+
+use super::manager::PackagedArtifact;
+use super::upload::ArtifactUploader;
+use crate::client::{ArtifactSpec, HttpClient};
+
+/// Largest chunk sent in one request
+const CHUNK_BYTES: usize = 8 * 1024 * 1024;
+
+/// A streamed artifact whose bytes are all uploaded
+#[derive(Debug, Clone)]
+pub struct StreamedArtifact {
+    pub artifact: PackagedArtifact,
+    pub upload_id: String,
+    pub storage_path: String,
+}
+
+/// Upload state of one streamed file
+pub struct ArtifactStream {
+    name: String,
+    workspace: PathBuf,
+    path: PathBuf,
+    upload_id: String,
+    uploaded: u64,
+    hasher: Sha256,
+    storage_path: Option<String>,
+    chunk_bytes: usize,
+}
+
+impl ArtifactStream {
+    /// A stream for `spec`, which must name one file relative to the workspace
+    pub fn new(workspace: &Path, spec: &ArtifactSpec) -> Result<Self> {
+        let [pattern] = spec.paths.as_slice() else {
+            anyhow::bail!("Streamed artifact '{}' must name exactly one file", spec.name);
+        };
+        let relative = Path::new(pattern);
+        if relative.is_absolute()
+            || relative.components().any(|c| c == Component::ParentDir)
+            || pattern.contains(['*', '?', '['])
+        {
+            anyhow::bail!("Streamed artifact path '{}' must be a plain workspace-relative file", pattern);
+        }
+
+        Ok(Self {
+            name: spec.name.clone(),
+            workspace: workspace.to_path_buf(),
+            path: workspace.join(relative),
+            upload_id: uuid::Uuid::new_v4().to_string(),
+            uploaded: 0,
+            hasher: Sha256::new(),
+            storage_path: None,
+            chunk_bytes: CHUNK_BYTES,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Upload what was appended since the last call, returning the byte count
+    pub async fn poll(&mut self, http: &HttpClient) -> Result<u64> {
+        let start = self.uploaded;
+        let file_name = self.file_name();
+        while let Some(chunk) = self.next_chunk().await? {
+            let storage_path = http
+                .upload_artifact_chunk(&self.upload_id, &file_name, self.uploaded, chunk.clone())
+                .await
+                .with_context(|| format!("Failed to stream artifact '{}'", self.name))?;
+            self.advance(&chunk);
+            self.storage_path = Some(storage_path);
+        }
+        Ok(self.uploaded - start)
+    }
+
+    /// Upload the tail and check the file was only ever appended to
+    pub async fn finish(mut self, http: &HttpClient) -> Result<StreamedArtifact> {
+        self.poll(http).await?;
+        if self.storage_path.is_none() {
+            // Nothing was written; an empty chunk still creates the upload
+            let storage_path = http
+                .upload_artifact_chunk(&self.upload_id, &self.file_name(), 0, Vec::new())
+                .await
+                .with_context(|| format!("Failed to stream artifact '{}'", self.name))?;
+            self.storage_path = Some(storage_path);
+        }
+
+        let artifact = self.verify().await?;
+        Ok(StreamedArtifact {
+            artifact,
+            upload_id: self.upload_id,
+            storage_path: self.storage_path.unwrap_or_default(),
+        })
+    }
+
+    fn file_name(&self) -> String {
+        self.path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| self.name.clone())
+    }
+
+    /// Fail if the file, or a directory on its way from the workspace, is a
+    /// symbolic link that could lead out of the workspace
+    async fn reject_links(&self) -> Result<()> {
+        let relative = self.path.strip_prefix(&self.workspace).unwrap_or(&self.path);
+        let mut path = self.workspace.clone();
+        for component in relative.components() {
+            path.push(component);
+            match tokio::fs::symlink_metadata(&path).await {
+                Ok(metadata) if metadata.is_symlink() => {
+                    anyhow::bail!("Streamed artifact '{}' may not be a symbolic link: {:?}", self.name, path);
+                }
+                Ok(_) => {}
+                // Not created yet
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => break,
+                Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", path)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Bytes after the uploaded prefix, up to one chunk
+    async fn next_chunk(&self) -> Result<Option<Vec<u8>>> {
+        self.reject_links().await?;
+        let mut file = match tokio::fs::File::open(&self.path).await {
+            Ok(file) => file,
+            // Not created yet
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Failed to open {:?}", self.path)),
+        };
+
+        let len = file.metadata().await?.len();
+        if len < self.uploaded {
+            anyhow::bail!("Streamed artifact '{}' shrank from {} to {} bytes", self.name, self.uploaded, len);
+        }
+        if len == self.uploaded {
+            return Ok(None);
+        }
+
+        file.seek(std::io::SeekFrom::Start(self.uploaded)).await?;
+        let size = (len - self.uploaded).min(self.chunk_bytes as u64) as usize;
+        let mut chunk = vec![0u8; size];
+        file.read_exact(&mut chunk).await?;
+        Ok(Some(chunk))
+    }
+
+    fn advance(&mut self, chunk: &[u8]) {
+        self.hasher.update(chunk);
+        self.uploaded += chunk.len() as u64;
+    }
+
+    /// The uploaded bytes as an artifact, if they still match the file
+    async fn verify(&self) -> Result<PackagedArtifact> {
+        let streamed = hex::encode(self.hasher.clone().finalize());
+        self.reject_links().await?;
+        let checksum = match tokio::fs::symlink_metadata(&self.path).await {
+            Ok(_) => ArtifactUploader::calculate_checksum(&self.path).await?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => streamed.clone(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", self.path)),
+        };
+        if checksum != streamed {
+            anyhow::bail!("Streamed artifact '{}' was modified after upload", self.name);
+        }
+
+        debug!("Streamed artifact '{}': {} bytes", self.name, self.uploaded);
+        Ok(PackagedArtifact {
+            name: self.name.clone(),
+            path: self.path.clone(),
+            entries: 1,
+            size_bytes: self.uploaded,
+            checksum,
+            metadata: Vec::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn spec(paths: &[&str]) -> ArtifactSpec {
+        ArtifactSpec {
+            name: "video".into(),
+            paths: paths.iter().map(|p| p.to_string()).collect(),
+            when: Default::default(),
+            stream: true,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chunks_follow_appends() {
+        let workspace = std::env::temp_dir().join(format!("muelsyse-stream-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&workspace).unwrap();
+
+        assert!(ArtifactStream::new(&workspace, &spec(&["a.mp4", "b.mp4"])).is_err());
+        assert!(ArtifactStream::new(&workspace, &spec(&["*.mp4"])).is_err());
+        assert!(ArtifactStream::new(&workspace, &spec(&["../a.mp4"])).is_err());
+
+        let mut stream = ArtifactStream::new(&workspace, &spec(&["out/run.mp4"])).unwrap();
+        stream.chunk_bytes = 4;
+        assert!(stream.next_chunk().await.unwrap().is_none());
+
+        std::fs::create_dir_all(workspace.join("out")).unwrap();
+        let path = workspace.join("out/run.mp4");
+        std::fs::write(&path, b"abcdef").unwrap();
+        let mut chunks = Vec::new();
+        while let Some(chunk) = stream.next_chunk().await.unwrap() {
+            stream.advance(&chunk);
+            chunks.push(chunk);
+        }
+        assert_eq!(chunks, vec![b"abcd".to_vec(), b"ef".to_vec()]);
+
+        std::fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(b"gh").unwrap();
+        let chunk = stream.next_chunk().await.unwrap().unwrap();
+        assert_eq!(chunk, b"gh");
+        stream.advance(&chunk);
+
+        let artifact = stream.verify().await.unwrap();
+        assert_eq!(artifact.size_bytes, 8);
+        assert_eq!(artifact.checksum, ArtifactUploader::calculate_checksum(&path).await.unwrap());
+
+        // Rewriting uploaded bytes is detected
+        std::fs::write(&path, b"ABCDEFGH").unwrap();
+        assert!(stream.verify().await.is_err());
+        std::fs::write(&path, b"abc").unwrap();
+        assert!(stream.next_chunk().await.is_err());
+
+        let _ = std::fs::remove_dir_all(&workspace);
+    }
+
+    #[tokio::test]
+    async fn test_rejects_links() {
+        let workspace = std::env::temp_dir().join(format!("muelsyse-stream-{}", uuid::Uuid::new_v4()));
+        let outside = workspace.with_extension("outside");
+        std::fs::create_dir_all(&workspace).unwrap();
+        std::fs::create_dir_all(&outside).unwrap();
+        std::fs::write(outside.join("run.mp4"), b"secret").unwrap();
+
+        std::os::unix::fs::symlink(outside.join("run.mp4"), workspace.join("run.mp4")).unwrap();
+        let stream = ArtifactStream::new(&workspace, &spec(&["run.mp4"])).unwrap();
+        assert!(stream.next_chunk().await.is_err());
+        assert!(stream.verify().await.is_err());
+
+        std::os::unix::fs::symlink(&outside, workspace.join("out")).unwrap();
+        let stream = ArtifactStream::new(&workspace, &spec(&["out/run.mp4"])).unwrap();
+        assert!(stream.next_chunk().await.is_err());
+
+        let _ = std::fs::remove_dir_all(&workspace);
+        let _ = std::fs::remove_dir_all(&outside);
+    }
+}
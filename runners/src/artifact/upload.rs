@@ -1,10 +1,25 @@
 //! Artifact upload utilities
+//!
+//! Packaged artifacts are read from disk and sent in chunks under an upload
+//! id. A chunk that fails is retried from the offset the control plane
+//! reports it has, and an upload queued after the job resumes the same way,
+//! so a dropped connection only costs the chunk in flight.
 
 use anyhow::{Result, Context};
 use sha2::{Sha256, Digest};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use tokio::fs::File;
-use tokio::io::AsyncReadExt;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tracing::warn;
+
+use crate::client::HttpClient;
+
+/// Attempts at sending one chunk
+const CHUNK_ATTEMPTS: u32 = 3;
+
+/// Delay before the first retry of a chunk, doubled for each further one
+const CHUNK_RETRY_DELAY: Duration = Duration::from_secs(1);
 
 /// Artifact uploader
 pub struct ArtifactUploader {
@@ -42,9 +57,154 @@ impl ArtifactUploader {
         Ok(metadata.len())
     }
 
-    /// Read file contents
-    pub async fn read_file(path: &Path) -> Result<Vec<u8>> {
-        tokio::fs::read(path).await
-            .context("Failed to read file")
+}
+
+/// Chunked, resumable upload of a file
+#[derive(Debug)]
+pub struct ChunkedUpload {
+    upload_id: String,
+    path: PathBuf,
+    file_name: String,
+    size_bytes: u64,
+    chunk_bytes: usize,
+    /// Bytes the control plane has
+    uploaded: u64,
+    /// Whether `uploaded` must be fetched before the next chunk
+    resync: bool,
+    storage_path: Option<String>,
+}
+
+impl ChunkedUpload {
+    /// A new upload of the `size_bytes` bytes of `path`
+    pub fn new(path: &Path, file_name: &str, size_bytes: u64, chunk_bytes: usize) -> Self {
+        Self {
+            upload_id: uuid::Uuid::new_v4().to_string(),
+            path: path.to_path_buf(),
+            file_name: file_name.to_string(),
+            size_bytes,
+            chunk_bytes: chunk_bytes.max(1),
+            uploaded: 0,
+            resync: false,
+            storage_path: None,
+        }
+    }
+
+    /// Continue an upload started earlier, from wherever it got to
+    pub fn resume(upload_id: &str, path: &Path, file_name: &str, size_bytes: u64, chunk_bytes: usize) -> Self {
+        Self {
+            upload_id: upload_id.to_string(),
+            resync: true,
+            ..Self::new(path, file_name, size_bytes, chunk_bytes)
+        }
+    }
+
+    pub fn upload_id(&self) -> &str {
+        &self.upload_id
+    }
+
+    pub fn uploaded(&self) -> u64 {
+        self.uploaded
+    }
+
+    pub fn size_bytes(&self) -> u64 {
+        self.size_bytes
+    }
+
+    /// Whether every byte was sent; an empty file still sends one chunk
+    pub fn is_complete(&self) -> bool {
+        self.storage_path.is_some() && self.uploaded >= self.size_bytes
+    }
+
+    /// Storage path of the completed upload
+    pub fn storage_path(&self) -> Option<&str> {
+        self.storage_path.as_deref()
+    }
+
+    /// Send the next chunk, retrying it from the control plane's offset,
+    /// and return the bytes uploaded so far
+    pub async fn send_next(&mut self, http: &HttpClient) -> Result<u64> {
+        let mut attempt = 1;
+        loop {
+            match self.try_send_next(http).await {
+                Ok(()) => return Ok(self.uploaded),
+                Err(e) if attempt < CHUNK_ATTEMPTS => {
+                    let delay = CHUNK_RETRY_DELAY * 2u32.pow(attempt - 1);
+                    warn!("Uploading {} at byte {} failed, retrying in {:?}: {:#}", self.file_name, self.uploaded, delay, e);
+                    self.resync = true;
+                    attempt += 1;
+                    tokio::time::sleep(delay).await;
+                }
+                Err(e) => {
+                    self.resync = true;
+                    return Err(e).with_context(|| format!("Failed to upload {}", self.file_name));
+                }
+            }
+        }
+    }
+
+    async fn try_send_next(&mut self, http: &HttpClient) -> Result<()> {
+        if self.resync {
+            let offset = http.artifact_upload_offset(&self.upload_id).await?;
+            if offset > self.size_bytes {
+                anyhow::bail!("Upload {} has {} bytes, more than the {} of {}", self.upload_id, offset, self.size_bytes, self.file_name);
+            }
+            self.uploaded = offset;
+            self.resync = false;
+        }
+
+        let chunk = self.read_chunk().await?;
+        let len = chunk.len() as u64;
+        let storage_path = http
+            .upload_artifact_chunk(&self.upload_id, &self.file_name, self.uploaded, chunk)
+            .await?;
+        self.uploaded += len;
+        self.storage_path = Some(storage_path);
+        Ok(())
+    }
+
+    /// Bytes after the uploaded prefix, up to one chunk
+    async fn read_chunk(&self) -> Result<Vec<u8>> {
+        let mut file = File::open(&self.path)
+            .await
+            .with_context(|| format!("Failed to open {:?}", self.path))?;
+        file.seek(std::io::SeekFrom::Start(self.uploaded)).await?;
+        let size = (self.size_bytes - self.uploaded).min(self.chunk_bytes as u64) as usize;
+        let mut chunk = vec![0u8; size];
+        file.read_exact(&mut chunk)
+            .await
+            .with_context(|| format!("Failed to read {:?}", self.path))?;
+        Ok(chunk)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_read_chunks() {
+        let path = std::env::temp_dir().join(format!("muelsyse-upload-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&path, b"abcdefghij").unwrap();
+
+        let mut upload = ChunkedUpload::new(&path, "out.tar.gz", 10, 4);
+        let mut chunks = Vec::new();
+        while upload.uploaded < upload.size_bytes {
+            let chunk = upload.read_chunk().await.unwrap();
+            upload.uploaded += chunk.len() as u64;
+            chunks.push(chunk);
+        }
+        assert_eq!(chunks, vec![b"abcd".to_vec(), b"efgh".to_vec(), b"ij".to_vec()]);
+        // Not complete until the control plane acknowledged a chunk
+        assert!(!upload.is_complete());
+
+        // Resuming asks the control plane where to continue
+        let resumed = ChunkedUpload::resume(upload.upload_id(), &path, "out.tar.gz", 10, 4);
+        assert_eq!(resumed.upload_id(), upload.upload_id());
+        assert!(resumed.resync);
+
+        // A file shorter than recorded fails instead of sending garbage
+        let truncated = ChunkedUpload::new(&path, "out.tar.gz", 12, 16);
+        assert!(truncated.read_chunk().await.is_err());
+        let _ = std::fs::remove_file(&path);
     }
 }
@@ -0,0 +1,7 @@
+//! Build cache shared between jobs
+
+mod store;
+mod volumes;
+
+pub use store::{resolve_path, within_workspace, CacheEntry, CacheStore};
+pub use volumes::{volumes_to_evict, CacheVolume, VolumeIndex, CACHE_LABEL};
@@ -0,0 +1,324 @@
+//! On-disk cache store
+//!
+//! Each entry lives in `<cache_path>/<key hash>/` with one tarball per cached
+//! path and a `meta.json` recording the key, size and last use. Entries are
+//! immutable once saved; when the store outgrows its size limit the least
+//! recently used entries are evicted.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+use tracing::{debug, info, warn};
+
+const META_FILE: &str = "meta.json";
+
+/// Metadata of a saved cache entry
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheEntry {
+    pub key: String,
+    /// Paths as declared by the step that saved the entry
+    pub paths: Vec<String>,
+    pub size_bytes: u64,
+    pub last_used: DateTime<Utc>,
+}
+
+/// Resolve a declared cache path: `~` is the runner user's home, relative
+/// paths are under the workspace.
+///
+/// Container steps only see the workspace, so their cache paths should be
+/// relative.
+pub fn resolve_path(path: &str, workspace: &Path) -> PathBuf {
+    let home = || std::env::var_os("HOME").map(PathBuf::from);
+    if path == "~" {
+        if let Some(home) = home() {
+            return home;
+        }
+    }
+    if let (Some(rest), Some(home)) = (path.strip_prefix("~/"), home()) {
+        return home.join(rest);
+    }
+    workspace.join(path)
+}
+
+/// Whether a declared cache path stays in the workspace: relative, not
+/// under `~` and without `..`
+pub fn within_workspace(path: &str) -> bool {
+    let relative = Path::new(path);
+    path != "~"
+        && !path.starts_with("~/")
+        && relative.is_relative()
+        && !relative.components().any(|c| c == std::path::Component::ParentDir)
+}
+
+/// Cache entries under a directory, bounded by a total size
+pub struct CacheStore {
+    root: PathBuf,
+    max_bytes: u64,
+}
+
+impl CacheStore {
+    /// `max_bytes` of 0 disables eviction
+    pub fn new(root: PathBuf, max_bytes: u64) -> Self {
+        Self { root, max_bytes }
+    }
+
+    fn entry_dir(&self, key: &str) -> PathBuf {
+        let digest = hex::encode(Sha256::digest(key.as_bytes()));
+        self.root.join(&digest[..32])
+    }
+
+    /// Restore the entry for `key` into `targets` (one per declared path)
+    /// of the job with `workspace`.
+    ///
+    /// Returns `false` on a cache miss.
+    pub async fn restore(&self, key: &str, workspace: &Path, targets: &[PathBuf]) -> Result<bool> {
+        let dir = self.entry_dir(key);
+        let Some(mut entry) = read_meta(&dir).await else {
+            return Ok(false);
+        };
+        if entry.key != key {
+            return Ok(false);
+        }
+
+        for (index, target) in targets.iter().enumerate() {
+            let archive = dir.join(format!("{}.tar.gz", index));
+            if !archive.exists() {
+                continue;
+            }
+            reject_links(target, workspace).await?;
+            tokio::fs::create_dir_all(target)
+                .await
+                .with_context(|| format!("Failed to create cache target {:?}", target))?;
+            run_tar(Command::new("tar").arg("-xzf").arg(&archive).arg("-C").arg(target)).await?;
+        }
+
+        entry.last_used = Utc::now();
+        write_meta(&dir, &entry).await?;
+        debug!("Restored cache '{}' from {:?}", key, dir);
+        Ok(true)
+    }
+
+    /// Save `sources` of the job with `workspace` under `key`, unless an
+    /// entry already exists.
+    ///
+    /// Missing sources are skipped. Returns the saved entry, if any.
+    pub async fn save(
+        &self,
+        key: &str,
+        paths: &[String],
+        workspace: &Path,
+        sources: &[PathBuf],
+    ) -> Result<Option<CacheEntry>> {
+        let dir = self.entry_dir(key);
+        if dir.join(META_FILE).exists() {
+            debug!("Cache '{}' already saved, not overwriting", key);
+            return Ok(None);
+        }
+
+        // Build in a staging directory so readers never see a partial entry
+        let staging = self.root.join(format!(".staging-{}", uuid::Uuid::new_v4()));
+        tokio::fs::create_dir_all(&staging)
+            .await
+            .context("Failed to create cache staging directory")?;
+
+        let saved = async {
+            let mut size_bytes = 0;
+            for (index, source) in sources.iter().enumerate() {
+                reject_links(source, workspace).await?;
+                match tokio::fs::symlink_metadata(source).await {
+                    Ok(metadata) if metadata.is_dir() => {}
+                    _ => continue,
+                }
+                let archive = staging.join(format!("{}.tar.gz", index));
+                run_tar(Command::new("tar").arg("-czf").arg(&archive).arg("-C").arg(source).arg(".")).await?;
+                size_bytes += tokio::fs::metadata(&archive).await?.len();
+            }
+
+            let entry = CacheEntry {
+                key: key.to_string(),
+                paths: paths.to_vec(),
+                size_bytes,
+                last_used: Utc::now(),
+            };
+            write_meta(&staging, &entry).await?;
+            tokio::fs::rename(&staging, &dir)
+                .await
+                .context("Failed to move cache entry into place")?;
+            Ok::<_, anyhow::Error>(entry)
+        }.await;
+
+        let entry = match saved {
+            Ok(entry) => entry,
+            Err(e) => {
+                let _ = tokio::fs::remove_dir_all(&staging).await;
+                return Err(e);
+            }
+        };
+        info!("Saved cache '{}' ({} bytes)", key, entry.size_bytes);
+
+        if let Err(e) = self.evict().await {
+            warn!("Failed to evict cache entries: {}", e);
+        }
+        Ok(Some(entry))
+    }
+
+    /// Remove least recently used entries until the store fits `max_bytes`
+    pub async fn evict(&self) -> Result<usize> {
+        if self.max_bytes == 0 {
+            return Ok(0);
+        }
+
+        let mut entries = Vec::new();
+        let mut dirs = match tokio::fs::read_dir(&self.root).await {
+            Ok(dirs) => dirs,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e.into()),
+        };
+        while let Some(dir) = dirs.next_entry().await? {
+            if let Some(entry) = read_meta(&dir.path()).await {
+                entries.push((dir.path(), entry));
+            }
+        }
+
+        let to_remove = select_for_eviction(entries, self.max_bytes);
+        for path in &to_remove {
+            debug!("Evicting cache entry {:?}", path);
+            if let Err(e) = tokio::fs::remove_dir_all(path).await {
+                warn!("Failed to evict cache entry {:?}: {}", path, e);
+            }
+        }
+        Ok(to_remove.len())
+    }
+}
+
+/// Fail if `path`, or a directory between it and `workspace`, is a symbolic
+/// link.
+///
+/// `tar` runs as the runner's user, which may be root while steps run as
+/// their own users; a link a step planted would have it archive or
+/// overwrite files the step could not reach itself.
+async fn reject_links(path: &Path, workspace: &Path) -> Result<()> {
+    let mut checked = vec![path.to_path_buf()];
+    if let Ok(relative) = path.strip_prefix(workspace) {
+        let mut current = workspace.to_path_buf();
+        for component in relative.components() {
+            current.push(component);
+            checked.push(current.clone());
+        }
+    }
+    for current in checked {
+        match tokio::fs::symlink_metadata(&current).await {
+            Ok(metadata) if metadata.is_symlink() => {
+                anyhow::bail!("Cache path {:?} goes through the symbolic link {:?}", path, current);
+            }
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", current)),
+        }
+    }
+    Ok(())
+}
+
+/// Least recently used entries to drop so the rest fit in `max_bytes`
+fn select_for_eviction(mut entries: Vec<(PathBuf, CacheEntry)>, max_bytes: u64) -> Vec<PathBuf> {
+    entries.sort_by_key(|(_, entry)| entry.last_used);
+
+    let mut total: u64 = entries.iter().map(|(_, entry)| entry.size_bytes).sum();
+    let mut to_remove = Vec::new();
+    for (path, entry) in entries {
+        if total <= max_bytes {
+            break;
+        }
+        total -= entry.size_bytes;
+        to_remove.push(path);
+    }
+    to_remove
+}
+
+async fn read_meta(dir: &Path) -> Option<CacheEntry> {
+    let contents = tokio::fs::read(dir.join(META_FILE)).await.ok()?;
+    serde_json::from_slice(&contents).ok()
+}
+
+async fn write_meta(dir: &Path, entry: &CacheEntry) -> Result<()> {
+    let contents = serde_json::to_vec_pretty(entry)?;
+    tokio::fs::write(dir.join(META_FILE), contents)
+        .await
+        .context("Failed to write cache metadata")
+}
+
+async fn run_tar(command: &mut Command) -> Result<()> {
+    let output = command.output().await.context("Failed to spawn tar")?;
+    if !output.status.success() {
+        anyhow::bail!("tar failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_save_and_restore() {
+        let root = std::env::temp_dir().join(format!("muelsyse-cache-{}", uuid::Uuid::new_v4()));
+        let source = root.join("source");
+        std::fs::create_dir_all(source.join("registry")).unwrap();
+        std::fs::write(source.join("registry/index"), b"crates").unwrap();
+
+        let store = CacheStore::new(root.join("store"), 0);
+        let paths = vec!["~/.cargo".to_string(), "target".to_string()];
+        let sources = vec![source.clone(), root.join("missing")];
+
+        assert!(!store.restore("cargo-abc", &root, &[root.join("restored")]).await.unwrap());
+
+        let entry = store.save("cargo-abc", &paths, &root, &sources).await.unwrap().unwrap();
+        assert!(entry.size_bytes > 0);
+        assert!(store.save("cargo-abc", &paths, &root, &sources).await.unwrap().is_none());
+
+        let restored = root.join("restored");
+        assert!(store.restore("cargo-abc", &root, &[restored.clone()]).await.unwrap());
+        assert_eq!(std::fs::read(restored.join("registry/index")).unwrap(), b"crates");
+
+        // Links out of the workspace are neither archived nor written through
+        std::os::unix::fs::symlink(&source, root.join("link")).unwrap();
+        let linked = vec![root.join("link")];
+        assert!(store.save("cargo-link", &paths, &root, &linked).await.is_err());
+        assert!(store.restore("cargo-abc", &root, &[root.join("link/nested")]).await.is_err());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_resolve_path() {
+        let workspace = Path::new("/work");
+        assert_eq!(resolve_path("target", workspace), PathBuf::from("/work/target"));
+        assert_eq!(resolve_path("/opt/cache", workspace), PathBuf::from("/opt/cache"));
+        if let Some(home) = std::env::var_os("HOME") {
+            assert_eq!(resolve_path("~/.cargo", workspace), PathBuf::from(home).join(".cargo"));
+        }
+
+        assert!(within_workspace("target") && within_workspace("./node_modules"));
+        assert!(!within_workspace("~/.cargo") && !within_workspace("/etc") && !within_workspace("a/../../etc"));
+    }
+
+    #[test]
+    fn test_select_for_eviction() {
+        let now = Utc::now();
+        let entry = |key: &str, size_bytes, hours_ago| {
+            (PathBuf::from(key), CacheEntry {
+                key: key.to_string(),
+                paths: Vec::new(),
+                size_bytes,
+                last_used: now - chrono::Duration::hours(hours_ago),
+            })
+        };
+
+        let entries = vec![entry("recent", 100, 1), entry("stale", 100, 10), entry("older", 100, 5)];
+        let evicted = select_for_eviction(entries, 150);
+        assert_eq!(evicted, vec![PathBuf::from("stale"), PathBuf::from("older")]);
+    }
+}
@@ -0,0 +1,184 @@
+//! Docker volume caches
+//!
+//! `container.caches` keeps dependency directories such as `/root/.cargo`
+//! or `/root/.m2` in named volumes that outlive the job. A volume is named
+//! after the cache key, the path and the job's repository, so repositories
+//! never share one, and carries the `muelsyse.cache` label instead of the
+//! runner label, so garbage collection leaves it alone.
+//!
+//! Docker does not track when a volume was last mounted, so the runner
+//! records it in `volumes.json` under the cache path. Once the unused cache
+//! volumes outgrow `executor.docker.cache_volume_max_bytes`, the least
+//! recently used ones are removed.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tokio::sync::Mutex;
+
+use crate::client::VolumeCacheSpec;
+
+/// Label marking a cache volume; its value is the cache key
+pub const CACHE_LABEL: &str = "muelsyse.cache";
+
+const INDEX_FILE: &str = "volumes.json";
+
+/// Serializes updates of the index between jobs
+static INDEX_LOCK: Mutex<()> = Mutex::const_new(());
+
+/// A cache volume mounted into a job's containers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheVolume {
+    pub name: String,
+    pub key: String,
+    /// Mount point in the container
+    pub path: String,
+}
+
+impl CacheVolume {
+    /// The volume for `spec` in jobs of `repository`
+    pub fn new(spec: &VolumeCacheSpec, repository: Option<&str>) -> Self {
+        let key = spec.key.clone().unwrap_or_else(|| spec.path.clone());
+        Self { name: volume_name(&key, &spec.path, repository), key, path: spec.path.clone() }
+    }
+
+    /// `docker run -v` form of the mount
+    pub fn bind(&self) -> String {
+        format!("{}:{}", self.name, self.path)
+    }
+}
+
+/// `muelsyse-cache-<readable key>-<hash>`, a valid Docker volume name
+fn volume_name(key: &str, path: &str, repository: Option<&str>) -> String {
+    let scope = format!("{}\n{}\n{}", repository.unwrap_or_default(), key, path);
+    let digest = hex::encode(Sha256::digest(scope.as_bytes()));
+    let readable: String = key
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '_' { c.to_ascii_lowercase() } else { '-' })
+        .take(40)
+        .collect();
+    let readable = readable.trim_matches(|c| c == '-' || c == '.');
+    if readable.is_empty() {
+        format!("muelsyse-cache-{}", &digest[..16])
+    } else {
+        format!("muelsyse-cache-{}-{}", readable, &digest[..16])
+    }
+}
+
+/// When each cache volume was last mounted
+pub struct VolumeIndex {
+    path: PathBuf,
+}
+
+impl VolumeIndex {
+    /// The index under `cache_path`
+    pub fn new(cache_path: &Path) -> Self {
+        Self { path: cache_path.join(INDEX_FILE) }
+    }
+
+    /// Last use of each volume by name
+    pub async fn load(&self) -> HashMap<String, DateTime<Utc>> {
+        match tokio::fs::read(&self.path).await {
+            Ok(contents) => serde_json::from_slice(&contents).unwrap_or_default(),
+            Err(_) => HashMap::new(),
+        }
+    }
+
+    /// Record that `names` are used now
+    pub async fn touch(&self, names: &[String]) -> Result<()> {
+        let _guard = INDEX_LOCK.lock().await;
+        let mut index = self.load().await;
+        let now = Utc::now();
+        index.extend(names.iter().map(|name| (name.clone(), now)));
+        self.save(&index).await
+    }
+
+    /// Drop removed volumes from the index
+    pub async fn forget(&self, names: &[String]) -> Result<()> {
+        let _guard = INDEX_LOCK.lock().await;
+        let mut index = self.load().await;
+        index.retain(|name, _| !names.contains(name));
+        self.save(&index).await
+    }
+
+    async fn save(&self, index: &HashMap<String, DateTime<Utc>>) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(&self.path, serde_json::to_vec_pretty(index)?)
+            .await
+            .with_context(|| format!("Failed to write {:?}", self.path))
+    }
+}
+
+/// Least recently used volumes to remove so the rest fit in `max_bytes`.
+///
+/// `volumes` holds the name and size of each unused cache volume; volumes
+/// missing from `last_used` were never mounted by this runner and go first.
+pub fn volumes_to_evict(
+    mut volumes: Vec<(String, u64)>,
+    last_used: &HashMap<String, DateTime<Utc>>,
+    max_bytes: u64,
+) -> Vec<String> {
+    volumes.sort_by_key(|(name, _)| last_used.get(name).copied());
+
+    let mut total: u64 = volumes.iter().map(|(_, size)| size).sum();
+    let mut to_remove = Vec::new();
+    for (name, size) in volumes {
+        if total <= max_bytes {
+            break;
+        }
+        total -= size;
+        to_remove.push(name);
+    }
+    to_remove
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_volume_name() {
+        let spec = VolumeCacheSpec { path: "/root/.cargo".into(), key: None };
+        let volume = CacheVolume::new(&spec, Some("https://git.example.com/app.git"));
+        assert_eq!(volume.key, "/root/.cargo");
+        assert!(volume.name.starts_with("muelsyse-cache-root-.cargo-"));
+        assert_eq!(volume.bind(), format!("{}:/root/.cargo", volume.name));
+
+        // Repositories and keys get volumes of their own
+        assert_ne!(CacheVolume::new(&spec, Some("https://git.example.com/lib.git")).name, volume.name);
+        let keyed = VolumeCacheSpec { path: "/root/.cargo".into(), key: Some("Cargo v2".into()) };
+        assert!(CacheVolume::new(&keyed, None).name.starts_with("muelsyse-cache-cargo-v2-"));
+        let symbols = VolumeCacheSpec { path: "/m2".into(), key: Some("***".into()) };
+        assert_eq!(CacheVolume::new(&symbols, None).name.len(), "muelsyse-cache-".len() + 16);
+    }
+
+    #[tokio::test]
+    async fn test_index() {
+        let root = std::env::temp_dir().join(format!("muelsyse-volumes-{}", uuid::Uuid::new_v4()));
+        let index = VolumeIndex::new(&root);
+        assert!(index.load().await.is_empty());
+
+        index.touch(&["a".into(), "b".into()]).await.unwrap();
+        index.forget(&["a".into()]).await.unwrap();
+        let loaded = index.load().await;
+        assert_eq!(loaded.keys().collect::<Vec<_>>(), vec!["b"]);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_volumes_to_evict() {
+        let now = Utc::now();
+        let last_used = HashMap::from([
+            ("recent".to_string(), now - chrono::Duration::hours(1)),
+            ("stale".to_string(), now - chrono::Duration::hours(10)),
+        ]);
+        let volumes = vec![("recent".to_string(), 100), ("stale".to_string(), 100), ("unknown".to_string(), 100)];
+        assert_eq!(volumes_to_evict(volumes.clone(), &last_used, 150), vec!["unknown", "stale"]);
+        assert!(volumes_to_evict(volumes, &last_used, 300).is_empty());
+    }
+}
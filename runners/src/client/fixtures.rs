@@ -0,0 +1,49 @@
+//! Job specs for tests
+//!
+//! Specs are built from JSON as the control plane sends them, so tests go
+//! through the same defaults and aliases as real jobs.
+
+use serde_json::{json, Value};
+
+use super::JobSpec;
+
+/// A job spec with no steps, no container and a 10 minute timeout
+pub struct JobSpecBuilder {
+    spec: Value,
+}
+
+impl JobSpecBuilder {
+    pub fn new(job_id: &str) -> Self {
+        Self {
+            spec: json!({
+                "job_id": job_id,
+                "execution_id": format!("exec-{}", job_id),
+                "name": "build",
+                "steps": [],
+                "environment": {},
+                "secrets": {},
+                "container": null,
+                "timeout_minutes": 10,
+                "workspace": { "path": format!("/tmp/{}", job_id) },
+            }),
+        }
+    }
+
+    /// Append a `run` step
+    pub fn step(mut self, step_id: &str, name: &str, run: &str) -> Self {
+        if let Some(steps) = self.spec["steps"].as_array_mut() {
+            steps.push(json!({ "step_id": step_id, "name": name, "run": run }));
+        }
+        self
+    }
+
+    /// Set any other field, under its name in control plane messages
+    pub fn with(mut self, key: &str, value: Value) -> Self {
+        self.spec[key] = value;
+        self
+    }
+
+    pub fn build(self) -> JobSpec {
+        serde_json::from_value(self.spec).expect("Invalid job spec")
+    }
+}
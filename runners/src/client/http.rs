@@ -3,27 +3,46 @@
 use anyhow::{Result, Context};
 use reqwest::Client;
 use serde::{Serialize, de::DeserializeOwned};
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
 
 use crate::config::Settings;
 
+/// Response header carrying a downloaded artifact's SHA256 checksum
+const CHECKSUM_HEADER: &str = "X-Checksum-Sha256";
+
 /// HTTP client for API calls
 pub struct HttpClient {
     client: Client,
     base_url: String,
     token: String,
+    timeout: Duration,
+}
+
+/// What the runner can take on when claiming a job
+#[derive(Debug, Clone, Serialize)]
+pub struct ClaimRequest {
+    /// Job slots free for new jobs
+    pub capacity: u32,
+    /// Labels in effect, including detected and overridden ones
+    pub labels: Vec<String>,
+    /// How long the control plane may hold the request open waiting for a job
+    pub wait_secs: u64,
 }
 
 impl HttpClient {
     pub fn new(settings: Settings) -> Self {
-        let client = Client::builder()
-            .timeout(std::time::Duration::from_secs(settings.control_plane.timeout_secs))
-            .build()
+        let timeout = Duration::from_secs(settings.control_plane.timeout_secs);
+        let client = super::tls::http_client(&settings.control_plane.tls, &settings.control_plane.proxy, timeout)
             .expect("Failed to create HTTP client");
 
         Self {
             client,
             base_url: settings.control_plane.api_url,
             token: settings.runner.token,
+            timeout,
         }
     }
 
@@ -68,23 +87,121 @@ impl HttpClient {
         response.json().await.context("Failed to parse JSON response")
     }
 
-    /// Upload artifact
-    pub async fn upload_artifact(&self, path: &str, data: Vec<u8>) -> Result<String> {
-        let url = format!("{}/api/v1/artifacts/upload", self.base_url);
+    /// Claim a job for `runner_id`, long-polling up to `request.wait_secs`.
+    ///
+    /// Returns the job spec as sent, so a malformed one can still be
+    /// released with field errors; `None` if no job came up.
+    pub async fn claim_job(&self, runner_id: &str, request: &ClaimRequest) -> Result<Option<serde_json::Value>> {
+        let url = format!("{}/api/v1/runners/{}/claim", self.base_url, runner_id);
+
+        let response = self.client
+            .post(&url)
+            .header("X-Runner-Token", &self.token)
+            .timeout(self.timeout + Duration::from_secs(request.wait_secs))
+            .json(request)
+            .send()
+            .await
+            .context("Job claim request failed")?;
+
+        let status = response.status();
+        if status == reqwest::StatusCode::NO_CONTENT {
+            return Ok(None);
+        }
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            anyhow::bail!("Claim error ({}): {}", status, body);
+        }
+
+        #[derive(serde::Deserialize)]
+        struct ClaimResponse {
+            #[serde(default)]
+            job: Option<serde_json::Value>,
+        }
+
+        let result: ClaimResponse = response.json().await.context("Failed to parse claim response")?;
+        Ok(result.job.filter(|job| !job.is_null()))
+    }
+
+    /// Hand a claimed job back to the control plane with the outputs of a
+    /// `rejected` status update, so it can be assigned elsewhere
+    pub async fn release_job(&self, runner_id: &str, job_id: &str, outputs: &HashMap<String, String>) -> Result<()> {
+        let url = format!("{}/api/v1/runners/{}/claim/{}/release", self.base_url, runner_id, job_id);
+
+        let response = self.client
+            .post(&url)
+            .header("X-Runner-Token", &self.token)
+            .json(&serde_json::json!({ "outputs": outputs }))
+            .send()
+            .await
+            .context("Job release request failed")?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            anyhow::bail!("Release error ({}): {}", status, body);
+        }
+        Ok(())
+    }
+
+    /// Fetch the values of a job's just-in-time secrets for one step
+    pub async fn fetch_step_secrets(&self, job_id: &str, step_id: &str, names: &[&str]) -> Result<HashMap<String, String>> {
+        #[derive(serde::Deserialize)]
+        struct SecretsResponse {
+            secrets: HashMap<String, String>,
+        }
+
+        let path = format!("/api/v1/jobs/{}/secrets", job_id);
+        let body = serde_json::json!({ "step_id": step_id, "names": names });
+        let response: SecretsResponse = self.post(&path, &body).await.context("Secret fetch failed")?;
+        Ok(response.secrets)
+    }
+
+    /// Bytes of an artifact upload the control plane has received; 0 for
+    /// an upload it does not know
+    pub async fn artifact_upload_offset(&self, upload_id: &str) -> Result<u64> {
+        let url = format!("{}/api/v1/artifacts/chunks", self.base_url);
 
-        let part = reqwest::multipart::Part::bytes(data)
-            .file_name(path.to_string());
+        let response = self.client
+            .get(&url)
+            .header("X-Runner-Token", &self.token)
+            .query(&[("upload_id", upload_id)])
+            .send()
+            .await
+            .context("Artifact upload status request failed")?;
 
-        let form = reqwest::multipart::Form::new()
-            .part("file", part);
+        let status = response.status();
+        if status == reqwest::StatusCode::NOT_FOUND {
+            return Ok(0);
+        }
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            anyhow::bail!("Upload error ({}): {}", status, body);
+        }
+
+        #[derive(serde::Deserialize)]
+        struct OffsetResponse {
+            offset: u64,
+        }
+
+        let result: OffsetResponse = response.json().await?;
+        Ok(result.offset)
+    }
+
+    /// Upload one chunk of an artifact, starting at byte `offset`.
+    ///
+    /// Returns the storage path the upload will be finalized to.
+    pub async fn upload_artifact_chunk(&self, upload_id: &str, file_name: &str, offset: u64, data: Vec<u8>) -> Result<String> {
+        let url = format!("{}/api/v1/artifacts/chunks", self.base_url);
 
         let response = self.client
             .post(&url)
             .header("X-Runner-Token", &self.token)
-            .multipart(form)
+            .header(reqwest::header::CONTENT_TYPE, "application/octet-stream")
+            .query(&[("upload_id", upload_id), ("file_name", file_name), ("offset", &offset.to_string())])
+            .body(data)
             .send()
             .await
-            .context("Artifact upload failed")?;
+            .context("Artifact chunk upload failed")?;
 
         let status = response.status();
         if !status.is_success() {
@@ -93,11 +210,58 @@ impl HttpClient {
         }
 
         #[derive(serde::Deserialize)]
-        struct UploadResponse {
+        struct ChunkResponse {
             storage_path: String,
         }
 
-        let result: UploadResponse = response.json().await?;
+        let result: ChunkResponse = response.json().await?;
         Ok(result.storage_path)
     }
+
+    /// URL jobs of `execution_id` download artifact `name` from
+    pub fn artifact_download_url(&self, execution_id: &str, name: &str) -> String {
+        let url = format!("{}/api/v1/artifacts/download", self.base_url);
+        match reqwest::Url::parse_with_params(&url, &[("execution_id", execution_id), ("name", name)]) {
+            Ok(url) => url.to_string(),
+            Err(_) => url,
+        }
+    }
+
+    /// Download an artifact uploaded earlier in an execution to `path`.
+    ///
+    /// Returns the SHA256 checksum the control plane recorded for it, if it
+    /// sent one.
+    pub async fn download_artifact(&self, execution_id: &str, name: &str, path: &Path) -> Result<Option<String>> {
+        let url = format!("{}/api/v1/artifacts/download", self.base_url);
+
+        let mut response = self.client
+            .get(&url)
+            .header("X-Runner-Token", &self.token)
+            .query(&[("execution_id", execution_id), ("name", name)])
+            .send()
+            .await
+            .context("Artifact download failed")?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            anyhow::bail!("Download error ({}): {}", status, body);
+        }
+
+        let checksum = response
+            .headers()
+            .get(CHECKSUM_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.trim().to_ascii_lowercase());
+
+        let mut file = tokio::fs::File::create(path)
+            .await
+            .with_context(|| format!("Failed to create {:?}", path))?;
+        while let Some(chunk) = response.chunk().await.context("Artifact download failed")? {
+            file.write_all(&chunk).await?;
+        }
+        file.flush().await?;
+
+        Ok(checksum)
+    }
 }
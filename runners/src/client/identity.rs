@@ -0,0 +1,144 @@
+//! Generated runner identity
+//!
+//! `runner.id` can be left out of the configuration, so one config file
+//! serves a whole fleet: on first start the runner generates an ID and keeps
+//! it in `runner.identity_path`. With `runner.fingerprint` the file also
+//! records a hash of the machine's hostname, MAC addresses and CPU model, so
+//! an identity copied to another machine (e.g. baked into a disk image) is
+//! detected and replaced by a fresh ID.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use sysinfo::{Networks, System};
+use tracing::{info, warn};
+
+/// Runner ID generated on this machine
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunnerIdentity {
+    pub runner_id: String,
+    /// Machine fingerprint when the ID was generated
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl RunnerIdentity {
+    fn generate(fingerprint: Option<String>) -> Self {
+        Self { runner_id: uuid::Uuid::new_v4().to_string(), fingerprint, created_at: Utc::now() }
+    }
+
+    /// The identity generated on an earlier start, if any
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        let contents = match std::fs::read(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read runner identity {:?}", path)),
+        };
+        let identity = serde_json::from_slice(&contents)
+            .with_context(|| format!("Failed to parse runner identity {:?}", path))?;
+        Ok(Some(identity))
+    }
+
+    async fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .context("Failed to create runner identity directory")?;
+        }
+        let tmp_path = path.with_extension("tmp");
+        tokio::fs::write(&tmp_path, serde_json::to_vec_pretty(self)?)
+            .await
+            .context("Failed to write runner identity")?;
+        tokio::fs::rename(&tmp_path, path)
+            .await
+            .context("Failed to write runner identity")
+    }
+
+    /// The identity in `path`, generating one if there is none or it was
+    /// generated on another machine
+    pub async fn load_or_create(path: &Path, fingerprint: bool) -> Result<Self> {
+        Self::load_or_create_with(path, fingerprint.then(machine_fingerprint)).await
+    }
+
+    async fn load_or_create_with(path: &Path, fingerprint: Option<String>) -> Result<Self> {
+        match Self::load(path)? {
+            Some(identity) if fingerprint.is_none() || identity.fingerprint == fingerprint => return Ok(identity),
+            Some(identity) => warn!(
+                "Runner identity {:?} was generated on another machine, replacing runner ID {}",
+                path, identity.runner_id
+            ),
+            None => {}
+        }
+
+        let identity = Self::generate(fingerprint);
+        identity.save(path).await?;
+        info!("Generated runner ID {}", identity.runner_id);
+        Ok(identity)
+    }
+}
+
+/// Hash of this machine's hostname, MAC addresses and CPU model
+pub fn machine_fingerprint() -> String {
+    let hostname = System::host_name().unwrap_or_default();
+    let macs: Vec<String> = Networks::new_with_refreshed_list()
+        .values()
+        .map(|network| network.mac_address())
+        .filter(|mac| !mac.is_unspecified())
+        .map(|mac| mac.to_string())
+        .collect();
+    let mut system = System::new();
+    system.refresh_cpu();
+    let cpu = system.cpus().first().map(|cpu| cpu.brand().to_string()).unwrap_or_default();
+    fingerprint_of(&hostname, macs, &cpu)
+}
+
+fn fingerprint_of(hostname: &str, mut macs: Vec<String>, cpu: &str) -> String {
+    // Interfaces are listed in no particular order
+    macs.sort();
+    macs.dedup();
+    let mut hasher = Sha256::new();
+    for part in [hostname, &macs.join(","), cpu] {
+        hasher.update(part.as_bytes());
+        hasher.update(b"\n");
+    }
+    hex::encode(hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_load_or_create() {
+        let dir = std::env::temp_dir().join(format!("muelsyse-identity-{}", uuid::Uuid::new_v4()));
+        let path = dir.join("identity.json");
+
+        let identity = RunnerIdentity::load_or_create_with(&path, None).await.unwrap();
+        assert!(uuid::Uuid::parse_str(&identity.runner_id).is_ok());
+        assert_eq!(RunnerIdentity::load_or_create_with(&path, None).await.unwrap(), identity);
+
+        // Fingerprinting an existing identity replaces it once, then keeps it
+        let fingerprint = Some(fingerprint_of("build-01", vec!["02:42:ac:11:00:02".into()], "EPYC"));
+        let pinned = RunnerIdentity::load_or_create_with(&path, fingerprint.clone()).await.unwrap();
+        assert_ne!(pinned.runner_id, identity.runner_id);
+        assert_eq!(RunnerIdentity::load_or_create_with(&path, fingerprint).await.unwrap(), pinned);
+
+        // A copy on another machine gets an ID of its own
+        let other = Some(fingerprint_of("build-02", vec!["02:42:ac:11:00:03".into()], "EPYC"));
+        assert_ne!(RunnerIdentity::load_or_create_with(&path, other).await.unwrap().runner_id, pinned.runner_id);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_fingerprint_of() {
+        let macs = vec!["02:42:ac:11:00:02".to_string(), "00:16:3e:00:00:01".to_string()];
+        let reordered = vec![macs[1].clone(), macs[0].clone(), macs[0].clone()];
+        assert_eq!(fingerprint_of("build-01", macs.clone(), "EPYC"), fingerprint_of("build-01", reordered, "EPYC"));
+        assert_ne!(fingerprint_of("build-01", macs.clone(), "EPYC"), fingerprint_of("build-02", macs, "EPYC"));
+        assert_eq!(machine_fingerprint().len(), 64);
+    }
+}
@@ -2,6 +2,13 @@
 
 mod websocket;
 mod http;
+mod register;
+mod identity;
+mod tls;
+mod proxy;
+mod outbox;
+#[cfg(test)]
+mod fixtures;
 
 pub use websocket::{
     WebSocketClient,
@@ -11,13 +18,35 @@ pub use websocket::{
     OutgoingMessage,
     IncomingMessage,
     LogEntry,
+    LogChunk,
     SystemInfo,
     JobSpec,
     StepSpec,
     ContainerSpec,
+    ConcurrencySpec,
+    VolumeCacheSpec,
+    ArtifactSpec,
+    ArtifactWhen,
+    CacheSpec,
+    StepOutputs,
     WorkspaceSpec,
+    CleanupPolicy,
+    StatusMeta,
+    OutputFileRef,
+    DiagnosticResult,
+    JobSnapshot,
+    PendingJobSnapshot,
+    RunnerVersions,
+    Capacity,
+    FleetInfo,
+    Cancellation,
 };
-pub use http::HttpClient;
+pub use http::{ClaimRequest, HttpClient};
+pub use register::{ensure_registered, RunnerCredentials};
+pub use identity::{machine_fingerprint, RunnerIdentity};
+pub use tls::check_tls;
+#[cfg(test)]
+pub use fixtures::JobSpecBuilder;
 
 use crate::config::Settings;
 
@@ -38,6 +67,22 @@ impl ControlPlaneClient {
         WebSocketClient::connect(self.settings.clone()).await
     }
 
+    /// Claim a job over HTTP, for when the WebSocket is down; see
+    /// [`HttpClient::claim_job`]
+    pub async fn claim_job(&self, capacity: u32, labels: &[String]) -> anyhow::Result<Option<serde_json::Value>> {
+        let request = ClaimRequest {
+            capacity,
+            labels: labels.to_vec(),
+            wait_secs: self.settings.control_plane.claim_wait_secs,
+        };
+        self.http.claim_job(&self.settings.runner.id, &request).await
+    }
+
+    /// Hand back a job claimed over HTTP that the runner will not run
+    pub async fn release_job(&self, job_id: &str, outputs: &std::collections::HashMap<String, String>) -> anyhow::Result<()> {
+        self.http.release_job(&self.settings.runner.id, job_id, outputs).await
+    }
+
     /// Get the HTTP client
     pub fn http(&self) -> &HttpClient {
         &self.http
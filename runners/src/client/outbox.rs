@@ -0,0 +1,178 @@
+//! Coalescing of messages to the control plane
+//!
+//! A job with hundreds of fast steps would otherwise send a frame for every
+//! status update and log line. In-progress statuses and logs wait up to
+//! `websocket.batch_window_ms` and go out together as one `batch` message,
+//! at most one per window. Any other message, including terminal statuses,
+//! first flushes what is waiting and then goes out at once, so the order is
+//! kept and nothing final is delayed.
+
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+use super::websocket::OutgoingMessage;
+
+/// Statuses that are followed by another one for the same job or step
+const IN_PROGRESS_STATUSES: &[&str] = &["pending", "queued", "running"];
+
+/// How soon a message has to reach the control plane
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    /// Sent at once, after the messages waiting before it
+    Immediate,
+    /// May wait for the rest of the batch window
+    Batched,
+}
+
+impl Priority {
+    pub fn of(message: &OutgoingMessage) -> Self {
+        match message {
+            OutgoingMessage::Log { .. } | OutgoingMessage::LogBatch { .. } => Self::Batched,
+            OutgoingMessage::StatusUpdate { status, .. } if IN_PROGRESS_STATUSES.contains(&status.as_str()) => {
+                Self::Batched
+            }
+            _ => Self::Immediate,
+        }
+    }
+}
+
+/// Messages waiting for the end of the batch window
+#[derive(Debug)]
+pub struct Outbox {
+    pending: Vec<OutgoingMessage>,
+    max_messages: usize,
+}
+
+impl Outbox {
+    pub fn new(max_messages: usize) -> Self {
+        Self { pending: Vec::new(), max_messages: max_messages.max(1) }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Queue a message, returning those to send now in order
+    pub fn push(&mut self, message: OutgoingMessage) -> Vec<OutgoingMessage> {
+        let priority = Priority::of(&message);
+        self.pending.push(message);
+        if priority == Priority::Immediate {
+            // Terminal messages go alone so the control plane sees them as before
+            let message = self.pending.pop();
+            self.flush().into_iter().chain(message).collect()
+        } else if self.pending.len() >= self.max_messages {
+            self.flush().into_iter().collect()
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// The waiting messages as one message, if any are waiting
+    pub fn flush(&mut self) -> Option<OutgoingMessage> {
+        match self.pending.len() {
+            0 => None,
+            1 => self.pending.pop(),
+            _ => Some(OutgoingMessage::Batch { messages: std::mem::take(&mut self.pending) }),
+        }
+    }
+}
+
+/// Pass messages from `rx` on to `tx`, coalescing them within `window`
+pub(super) async fn run(
+    window: Duration,
+    max_messages: usize,
+    mut rx: mpsc::Receiver<OutgoingMessage>,
+    tx: mpsc::Sender<OutgoingMessage>,
+) {
+    let mut outbox = Outbox::new(max_messages);
+    let mut deadline: Option<Instant> = None;
+
+    loop {
+        let ready = tokio::select! {
+            message = rx.recv() => match message {
+                Some(message) => {
+                    let was_empty = outbox.is_empty();
+                    let ready = outbox.push(message);
+                    if outbox.is_empty() {
+                        deadline = None;
+                    } else if was_empty {
+                        deadline = Some(Instant::now() + window);
+                    }
+                    ready
+                }
+                None => break,
+            },
+            _ = tokio::time::sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                deadline = None;
+                outbox.flush().into_iter().collect()
+            }
+        };
+        for message in ready {
+            if tx.send(message).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    if let Some(message) = outbox.flush() {
+        let _ = tx.send(message).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::StatusMeta;
+    use std::collections::HashMap;
+
+    fn status(step_id: &str, status: &str) -> OutgoingMessage {
+        OutgoingMessage::StatusUpdate {
+            entity_type: "step".into(),
+            entity_id: step_id.into(),
+            status: status.into(),
+            exit_code: None,
+            outputs: HashMap::new(),
+            runner_id: "runner-1".into(),
+            meta: StatusMeta::default(),
+        }
+    }
+
+    fn ids(messages: &[OutgoingMessage]) -> Vec<String> {
+        messages
+            .iter()
+            .map(|message| match message {
+                OutgoingMessage::StatusUpdate { entity_id, status, .. } => format!("{}:{}", entity_id, status),
+                OutgoingMessage::Batch { messages } => format!("batch[{}]", ids(messages).join(",")),
+                other => format!("{:?}", other),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_outbox() {
+        assert_eq!(Priority::of(&status("s1", "running")), Priority::Batched);
+        assert_eq!(Priority::of(&status("s1", "failed")), Priority::Immediate);
+
+        let mut outbox = Outbox::new(3);
+        assert!(outbox.push(status("s1", "running")).is_empty());
+        assert!(outbox.push(status("s2", "running")).is_empty());
+        let sent = outbox.push(status("s1", "success"));
+        assert_eq!(ids(&sent), vec!["batch[s1:running,s2:running]", "s1:success"]);
+        assert!(outbox.is_empty());
+
+        assert!(outbox.push(status("s3", "running")).is_empty());
+        assert_eq!(ids(&outbox.flush().into_iter().collect::<Vec<_>>()), vec!["s3:running"]);
+        assert!(outbox.flush().is_none());
+
+        for step in ["s4", "s5"] {
+            assert!(outbox.push(status(step, "running")).is_empty());
+        }
+        let full = outbox.push(status("s6", "running"));
+        assert_eq!(ids(&full), vec!["batch[s4:running,s5:running,s6:running]"]);
+
+        let batch = serde_json::to_value(&full[0]).unwrap();
+        assert_eq!(batch["type"], "batch");
+        assert_eq!(batch["messages"][0]["type"], "status_update");
+    }
+}
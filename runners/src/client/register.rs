@@ -0,0 +1,188 @@
+//! Runner registration
+//!
+//! A new machine only needs `runner.registration_token`. On first start the
+//! runner exchanges it with the control plane for its runner ID and
+//! long-lived token, and keeps those in `runner.credentials_path` for later
+//! starts. Credentials set in the configuration take precedence.
+//!
+//! A runner without a configured `runner.id` uses the one it generated (see
+//! [`RunnerIdentity`]), proposing it to the control plane when registering.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use tracing::info;
+
+use super::http::HttpClient;
+use super::identity::RunnerIdentity;
+use super::websocket::FleetInfo;
+use crate::config::Settings;
+use crate::utils::{available_shells, capabilities};
+
+/// Runner ID and token issued by the control plane
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunnerCredentials {
+    pub runner_id: String,
+    pub token: String,
+}
+
+impl RunnerCredentials {
+    /// Credentials saved by an earlier registration, if any
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        let contents = match std::fs::read(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read runner credentials {:?}", path)),
+        };
+        let credentials = serde_json::from_slice(&contents)
+            .with_context(|| format!("Failed to parse runner credentials {:?}", path))?;
+        Ok(Some(credentials))
+    }
+
+    /// Persist the credentials, readable only by the runner's user
+    pub async fn save(&self, path: &Path) -> Result<()> {
+        use tokio::io::AsyncWriteExt;
+
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .context("Failed to create runner credentials directory")?;
+        }
+
+        let tmp_path = path.with_extension("tmp");
+        let mut options = tokio::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+
+        let mut file = options
+            .open(&tmp_path)
+            .await
+            .context("Failed to write runner credentials")?;
+        file.write_all(&serde_json::to_vec_pretty(self)?).await?;
+        file.flush().await?;
+        drop(file);
+
+        tokio::fs::rename(&tmp_path, path)
+            .await
+            .context("Failed to write runner credentials")
+    }
+
+    fn apply(self, settings: &mut Settings) {
+        settings.runner.id = self.runner_id;
+        settings.runner.token = self.token;
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct RegisterRequest<'a> {
+    registration_token: &'a str,
+    /// Generated or configured ID the runner would like to keep
+    runner_id: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fingerprint: Option<&'a str>,
+    name: &'a str,
+    labels: &'a [String],
+    capabilities: Vec<String>,
+    /// Shells installed on the host, which steps can use without falling
+    /// back to `executor.shell.fallback`
+    shells: &'static [String],
+    version: &'static str,
+    #[serde(flatten)]
+    fleet: FleetInfo,
+    max_concurrent_jobs: usize,
+    /// Jobs per limited label, from `runner.concurrency`
+    concurrency: &'a HashMap<String, usize>,
+}
+
+/// Exchange the registration token for runner credentials
+pub async fn register(
+    settings: &Settings,
+    registration_token: &str,
+    fingerprint: Option<&str>,
+) -> Result<RunnerCredentials> {
+    let request = RegisterRequest {
+        registration_token,
+        runner_id: &settings.runner.id,
+        fingerprint,
+        name: &settings.runner.name,
+        labels: &settings.runner.labels,
+        capabilities: capabilities(),
+        shells: available_shells(),
+        version: env!("CARGO_PKG_VERSION"),
+        fleet: FleetInfo::new(settings),
+        max_concurrent_jobs: settings.runner.max_concurrent_jobs,
+        concurrency: &settings.runner.concurrency,
+    };
+
+    HttpClient::new(settings.clone())
+        .post("/api/v1/runners/register/", &request)
+        .await
+        .context("Runner registration failed")
+}
+
+/// Fill in `runner.id` and `runner.token`, generating the ID and registering
+/// the runner if no credentials are configured or saved
+pub async fn ensure_registered(settings: &mut Settings) -> Result<()> {
+    if !settings.runner.id.is_empty() && !settings.runner.token.is_empty() {
+        return Ok(());
+    }
+
+    let path = settings.runner.credentials_path.clone();
+    if let Some(credentials) = RunnerCredentials::load(&path)? {
+        info!("Using runner credentials from {:?}", path);
+        credentials.apply(settings);
+        return Ok(());
+    }
+
+    let mut fingerprint = None;
+    if settings.runner.id.is_empty() {
+        let identity = RunnerIdentity::load_or_create(&settings.runner.identity_path, settings.runner.fingerprint).await?;
+        settings.runner.id = identity.runner_id;
+        fingerprint = identity.fingerprint;
+    }
+    // The token was provisioned for the configured or generated ID
+    if !settings.runner.token.is_empty() {
+        return Ok(());
+    }
+
+    let Some(registration_token) = settings.runner.registration_token.clone() else {
+        anyhow::bail!("runner.token is not configured and no runner.registration_token is set");
+    };
+
+    let credentials = register(settings, &registration_token, fingerprint.as_deref()).await?;
+    info!("Registered as runner {}", credentials.runner_id);
+    credentials.save(&path).await?;
+    credentials.apply(settings);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_credentials_round_trip() {
+        let path = std::env::temp_dir()
+            .join(format!("muelsyse-credentials-{}", uuid::Uuid::new_v4()))
+            .join("credentials.json");
+        assert!(RunnerCredentials::load(&path).unwrap().is_none());
+
+        let credentials = RunnerCredentials {
+            runner_id: "00000000-0000-0000-0000-000000000002".into(),
+            token: "mci_runner_secret".into(),
+        };
+        credentials.save(&path).await.unwrap();
+        assert_eq!(RunnerCredentials::load(&path).unwrap(), Some(credentials));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+}
@@ -0,0 +1,175 @@
+//! TLS for control plane connections
+//!
+//! `control_plane.tls` adds trusted CAs and a client certificate to both the
+//! HTTP client and the WebSocket connector, so the runner can reach a control
+//! plane behind a private CA or a TLS-terminating proxy requiring mutual TLS.
+
+use anyhow::{Context, Result};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{DigitallySignedStruct, SignatureScheme};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_tungstenite::Connector;
+
+use crate::config::{ProxyConfig, TlsConfig};
+
+/// HTTP client for the control plane API
+pub fn http_client(tls: &TlsConfig, proxy: &ProxyConfig, timeout: Duration) -> Result<reqwest::Client> {
+    let mut builder = super::proxy::http_builder(reqwest::Client::builder().timeout(timeout), proxy)?;
+
+    if let Some(ref path) = tls.ca_cert_path {
+        let certs = reqwest::Certificate::from_pem_bundle(&read(path)?)
+            .with_context(|| format!("Invalid CA certificates in {:?}", path))?;
+        for cert in certs {
+            builder = builder.add_root_certificate(cert);
+        }
+    }
+    if let Some((cert_path, key_path)) = client_identity_paths(tls)? {
+        // reqwest takes the chain and key as one PEM document
+        let mut pem = read(cert_path)?;
+        pem.push(b'\n');
+        pem.extend(read(key_path)?);
+        let identity = reqwest::Identity::from_pem(&pem)
+            .with_context(|| format!("Invalid client certificate {:?} or key {:?}", cert_path, key_path))?;
+        builder = builder.identity(identity);
+    }
+    if tls.insecure_skip_verify {
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+
+    builder.build().context("Failed to create HTTP client")
+}
+
+/// Connector for the WebSocket connection, or `None` for the defaults
+pub fn websocket_connector(tls: &TlsConfig) -> Result<Option<Connector>> {
+    if tls.is_default() {
+        return Ok(None);
+    }
+
+    let mut roots = rustls::RootCertStore::empty();
+    roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    if let Some(ref path) = tls.ca_cert_path {
+        for cert in certificates(path)? {
+            roots.add(cert).with_context(|| format!("Invalid CA certificate in {:?}", path))?;
+        }
+    }
+
+    let builder = rustls::ClientConfig::builder().with_root_certificates(roots);
+    let mut config = match client_identity_paths(tls)? {
+        Some((cert_path, key_path)) => builder
+            .with_client_auth_cert(certificates(cert_path)?, private_key(key_path)?)
+            .with_context(|| format!("Invalid client certificate {:?} or key {:?}", cert_path, key_path))?,
+        None => builder.with_no_client_auth(),
+    };
+    if tls.insecure_skip_verify {
+        config.dangerous().set_certificate_verifier(Arc::new(SkipServerVerification::new()));
+    }
+
+    Ok(Some(Connector::Rustls(Arc::new(config))))
+}
+
+/// Fail early on missing or invalid TLS files or proxy settings rather than
+/// on first use
+pub fn check_tls(tls: &TlsConfig, proxy: &ProxyConfig) -> Result<()> {
+    http_client(tls, proxy, Duration::from_secs(1))?;
+    websocket_connector(tls)?;
+    Ok(())
+}
+
+fn client_identity_paths(tls: &TlsConfig) -> Result<Option<(&Path, &Path)>> {
+    match (&tls.client_cert_path, &tls.client_key_path) {
+        (Some(cert), Some(key)) => Ok(Some((cert, key))),
+        (None, None) => Ok(None),
+        _ => anyhow::bail!("control_plane.tls needs both client_cert_path and client_key_path"),
+    }
+}
+
+fn read(path: &Path) -> Result<Vec<u8>> {
+    std::fs::read(path).with_context(|| format!("Failed to read {:?}", path))
+}
+
+fn certificates(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let certs = rustls_pemfile::certs(&mut read(path)?.as_slice())
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Invalid PEM in {:?}", path))?;
+    if certs.is_empty() {
+        anyhow::bail!("No certificates in {:?}", path);
+    }
+    Ok(certs)
+}
+
+fn private_key(path: &Path) -> Result<PrivateKeyDer<'static>> {
+    rustls_pemfile::private_key(&mut read(path)?.as_slice())
+        .with_context(|| format!("Invalid PEM in {:?}", path))?
+        .with_context(|| format!("No private key in {:?}", path))
+}
+
+/// Accepts any server certificate but still checks handshake signatures
+#[derive(Debug)]
+struct SkipServerVerification {
+    provider: rustls::crypto::CryptoProvider,
+}
+
+impl SkipServerVerification {
+    fn new() -> Self {
+        Self { provider: rustls::crypto::ring::default_provider() }
+    }
+}
+
+impl ServerCertVerifier for SkipServerVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tls_settings() {
+        let default = TlsConfig::default();
+        assert!(websocket_connector(&default).unwrap().is_none());
+        assert!(check_tls(&default, &ProxyConfig::default()).is_ok());
+
+        let insecure = TlsConfig { insecure_skip_verify: true, ..Default::default() };
+        assert!(matches!(websocket_connector(&insecure).unwrap(), Some(Connector::Rustls(_))));
+
+        let half = TlsConfig { client_cert_path: Some("/etc/runner.pem".into()), ..Default::default() };
+        assert!(check_tls(&half, &ProxyConfig::default()).unwrap_err().to_string().contains("client_key_path"));
+
+        let missing = TlsConfig { ca_cert_path: Some("/nonexistent/ca.pem".into()), ..Default::default() };
+        assert!(format!("{:#}", check_tls(&missing, &ProxyConfig::default()).unwrap_err()).contains("/nonexistent/ca.pem"));
+    }
+}
@@ -5,10 +5,12 @@
 //! - Ping/pong heartbeat
 //! - Connection state callbacks
 //! - Automatic reconnection on disconnect
+//! - Optional deflate compression of large messages (`websocket.compression`)
+//! - Batching of in-progress statuses and logs (`websocket.batch_window_ms`)
 
 use anyhow::Result;
 use futures_util::{SinkExt, StreamExt};
-use tokio_tungstenite::{connect_async, tungstenite::Message as WsMessage};
+use tokio_tungstenite::{client_async_tls_with_config, connect_async_tls_with_config, tungstenite::Message as WsMessage, Connector};
 use serde::{Serialize, Deserialize};
 use tracing::{info, warn, debug, error};
 use chrono::{DateTime, Utc};
@@ -18,7 +20,11 @@ use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::{Duration, Instant};
 use tokio::sync::{mpsc, Mutex, RwLock};
 
-use crate::config::{Settings, WebSocketConfig};
+use crate::artifact::BinaryMetadata;
+use crate::log::{Annotation, LogControls};
+use crate::error::{RetryClass, RunnerError};
+use crate::config::{ConfigOverrides, LogFormat, Settings, WebSocketConfig, WsCompression};
+use crate::executor::{ContainerMode, ImagePullStats, ImagePulls, OutputEncoding, OutputStream, ResourceLimits};
 
 // ============================================================================
 // Connection State
@@ -125,7 +131,16 @@ pub enum OutgoingMessage {
         runner_id: String,
         status: String,
         current_jobs: u32,
+        #[serde(flatten)]
+        capacity: Capacity,
+        #[serde(flatten)]
+        fleet: FleetInfo,
         system_info: SystemInfo,
+        /// Labels in effect, including detected and overridden ones
+        labels: Vec<String>,
+        /// Revision of the control plane overrides in effect
+        #[serde(skip_serializing_if = "Option::is_none")]
+        config_revision: Option<u64>,
     },
 
     #[serde(rename = "log")]
@@ -137,14 +152,25 @@ pub enum OutgoingMessage {
         level: String,
         #[serde(skip_serializing_if = "Option::is_none")]
         sequence: Option<u64>,
+        /// Set when `content` is one chunk of a longer line
+        #[serde(flatten)]
+        chunk: Option<LogChunk>,
+        /// Set when `content` is encoded as JSON rather than plain text
+        #[serde(skip_serializing_if = "LogFormat::is_plain")]
+        format: LogFormat,
     },
 
     #[serde(rename = "log_batch")]
     LogBatch {
         job_id: String,
         logs: Vec<LogEntry>,
+        #[serde(skip_serializing_if = "LogFormat::is_plain")]
+        format: LogFormat,
     },
 
+    /// A job's or step's status. A job's last update, with its outputs and
+    /// how it was cancelled if it was, also reports its completion; there is
+    /// no separate `job_complete` message.
     #[serde(rename = "status_update")]
     StatusUpdate {
         entity_type: String,
@@ -152,13 +178,20 @@ pub enum OutgoingMessage {
         status: String,
         exit_code: Option<i32>,
         outputs: HashMap<String, String>,
+        runner_id: String,
+        #[serde(flatten)]
+        meta: StatusMeta,
     },
 
-    #[serde(rename = "job_complete")]
-    JobComplete {
+    /// A cancel request was received: `dequeued` for a pending job,
+    /// `stopping` for a running one, `unknown` for a job the runner does
+    /// not hold
+    #[serde(rename = "cancel_acknowledged")]
+    CancelAcknowledged {
         job_id: String,
-        status: String,
-        outputs: HashMap<String, String>,
+        runner_id: String,
+        state: String,
+        timestamp: DateTime<Utc>,
     },
 
     #[serde(rename = "artifact_ready")]
@@ -168,6 +201,102 @@ pub enum OutgoingMessage {
         artifact_path: String,
         size_bytes: u64,
         checksum: String,
+        /// The control plane joins the chunks uploaded under this id
+        upload_id: String,
+        /// Recognized binaries and packages, with `job.artifact_metadata`
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        metadata: Vec<BinaryMetadata>,
+    },
+
+    /// Bytes of an artifact uploaded so far, sent after each chunk
+    #[serde(rename = "artifact_progress")]
+    ArtifactProgress {
+        job_id: String,
+        artifact_name: String,
+        upload_id: String,
+        uploaded_bytes: u64,
+        size_bytes: u64,
+    },
+
+    /// A job or step has used `job.timeout_warning_percent` of its timeout
+    #[serde(rename = "timeout_warning")]
+    TimeoutWarning {
+        job_id: String,
+        /// Unset for the job's own timeout
+        #[serde(skip_serializing_if = "Option::is_none")]
+        step_id: Option<String>,
+        runner_id: String,
+        timeout_secs: u64,
+        /// When the job or step will be stopped
+        deadline: DateTime<Utc>,
+    },
+
+    /// Errors and warnings a step reported with `::error` and `::warning`
+    #[serde(rename = "annotations")]
+    Annotations {
+        job_id: String,
+        step_id: String,
+        runner_id: String,
+        annotations: Vec<Annotation>,
+    },
+
+    #[serde(rename = "job_diagnostics")]
+    JobDiagnostics {
+        job_id: String,
+        results: Vec<DiagnosticResult>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+
+    #[serde(rename = "runner_status_report")]
+    RunnerStatusReport {
+        runner_id: String,
+        /// Echoes the `request_id` of the query, if any
+        #[serde(skip_serializing_if = "Option::is_none")]
+        request_id: Option<String>,
+        status: String,
+        max_concurrent_jobs: u32,
+        running_jobs: Vec<JobSnapshot>,
+        pending_jobs: Vec<PendingJobSnapshot>,
+        system_info: SystemInfo,
+        versions: RunnerVersions,
+        /// Image pulls since the runner started
+        image_pulls: Box<ImagePullStats>,
+        timestamp: DateTime<Utc>,
+    },
+
+    /// Reply to a `capacity_query`
+    #[serde(rename = "capacity_report")]
+    CapacityReport {
+        runner_id: String,
+        /// Echoes the `request_id` of the query, if any
+        #[serde(skip_serializing_if = "Option::is_none")]
+        request_id: Option<String>,
+        #[serde(flatten)]
+        fleet: FleetInfo,
+        running_jobs: u32,
+        pending_jobs: usize,
+        /// Queue slots left for jobs accepted while at capacity
+        pending_capacity: usize,
+        #[serde(flatten)]
+        capacity: Capacity,
+        /// GPUs not held by running jobs
+        free_gpus: u32,
+        draining: bool,
+        timestamp: DateTime<Utc>,
+    },
+
+    #[serde(rename = "config_applied")]
+    ConfigApplied {
+        runner_id: String,
+        /// Revision of the rejected update, or of the overrides now in effect
+        #[serde(skip_serializing_if = "Option::is_none")]
+        revision: Option<u64>,
+        applied: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+        /// Overrides in effect after the update
+        overrides: ConfigOverrides,
     },
 
     #[serde(rename = "runner_offline")]
@@ -175,16 +304,265 @@ pub enum OutgoingMessage {
         runner_id: String,
         reason: String,
     },
+
+    /// A failed job is kept for debugging until `expires_at`
+    #[serde(rename = "debug_session")]
+    DebugSession {
+        job_id: String,
+        session_id: String,
+        /// The failed step, whose environment commands run in
+        step_id: String,
+        runner_id: String,
+        expires_at: DateTime<Utc>,
+    },
+
+    /// Result of a `debug_exec`
+    #[serde(rename = "debug_output")]
+    DebugOutput {
+        job_id: String,
+        session_id: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        request_id: Option<String>,
+        exit_code: Option<i32>,
+        output: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+
+    /// The debug session ended: `ended`, `expired` or `cancelled`
+    #[serde(rename = "debug_session_closed")]
+    DebugSessionClosed {
+        job_id: String,
+        session_id: String,
+        reason: String,
+    },
+
+    /// Jobs the runner holds, sent on every connection so the control plane
+    /// can reconcile them with the jobs it assigned
+    #[serde(rename = "active_jobs")]
+    ActiveJobs {
+        runner_id: String,
+        running: Vec<String>,
+        pending: Vec<String>,
+    },
+
+    /// Messages coalesced within `websocket.batch_window_ms`, in order
+    #[serde(rename = "batch")]
+    Batch {
+        messages: Vec<OutgoingMessage>,
+    },
+}
+
+/// How a cancelled job ended
+#[derive(Debug, Clone, Serialize)]
+pub struct Cancellation {
+    /// When the runner received the cancel request
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub requested_at: Option<DateTime<Utc>>,
+    pub reason: String,
+    /// Steps that were executing and were stopped
+    pub steps: Vec<String>,
+}
+
+/// Free job slots, for the control plane to place jobs by
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Capacity {
+    /// Job slots free for new assignments; 0 while draining
+    pub capacity: u32,
+    /// Free slots of each label limited by `runner.concurrency`, at most
+    /// `capacity`; jobs without a limited label only need `capacity`
+    pub label_slots: HashMap<String, u32>,
+}
+
+/// Where a runner stands in its fleet
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FleetInfo {
+    /// `runner.group`: runners the control plane balances jobs across
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// `runner.capacity_weight`: the runner's share of the group's jobs
+    pub capacity_weight: f64,
+}
+
+impl FleetInfo {
+    pub fn new(settings: &Settings) -> Self {
+        Self { group: settings.runner.group.clone(), capacity_weight: settings.runner.capacity_weight }
+    }
+}
+
+/// Attempt and timing metadata attached to status updates
+///
+/// Timestamps are taken on the runner so durations stay accurate even when
+/// messages are delayed or replayed after a reconnect.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct StatusMeta {
+    /// Runner-side attempt number (1-based, 0 if no attempt was made)
+    pub attempt: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub started_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<DateTime<Utc>>,
+    /// Uploaded `outputs.files` of a step
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub output_files: Vec<OutputFileRef>,
+    /// How a cancelled job ended
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cancellation: Option<Cancellation>,
+}
+
+impl StatusMeta {
+    /// Metadata for an entity that has started but not finished
+    pub fn started(attempt: u32, started_at: DateTime<Utc>) -> Self {
+        Self {
+            attempt,
+            started_at: Some(started_at),
+            finished_at: None,
+            output_files: Vec::new(),
+            cancellation: None,
+        }
+    }
+
+    /// Metadata for an entity finishing now
+    pub fn finished(attempt: u32, started_at: Option<DateTime<Utc>>) -> Self {
+        Self {
+            attempt,
+            started_at,
+            finished_at: Some(Utc::now()),
+            output_files: Vec::new(),
+            cancellation: None,
+        }
+    }
+
+    /// With references to a step's uploaded output files
+    pub fn with_output_files(mut self, output_files: Vec<OutputFileRef>) -> Self {
+        self.output_files = output_files;
+        self
+    }
+
+    /// With how the cancelled job ended
+    pub fn with_cancellation(mut self, cancellation: Cancellation) -> Self {
+        self.cancellation = Some(cancellation);
+        self
+    }
+}
+
+/// A step output file uploaded as an artifact
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct OutputFileRef {
+    /// Path relative to the workspace
+    pub path: String,
+    pub artifact_name: String,
+    /// Where jobs of the execution download it from
+    pub url: String,
+    pub size_bytes: u64,
+    pub checksum: String,
+}
+
+/// Output of one diagnostic command
+#[derive(Debug, Clone, Serialize)]
+pub struct DiagnosticResult {
+    pub name: String,
+    pub command: String,
+    pub exit_code: Option<i64>,
+    pub output: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Progress of a running job
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct JobSnapshot {
+    pub job_id: String,
+    pub name: String,
+    pub attempt: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub started_at: Option<DateTime<Utc>>,
+    /// Step currently executing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current_step: Option<String>,
+    /// Steps finished in this attempt, including skipped ones
+    pub steps_completed: usize,
+    pub steps_total: usize,
+}
+
+/// A job waiting in the runner's pending queue
+#[derive(Debug, Clone, Serialize)]
+pub struct PendingJobSnapshot {
+    pub job_id: String,
+    pub name: String,
+    /// 1-based position in the queue
+    pub queue_position: usize,
+}
+
+/// Versions of the runner and its host
+#[derive(Debug, Clone, Serialize)]
+pub struct RunnerVersions {
+    pub runner: String,
+    pub os: Option<String>,
+    pub kernel: Option<String>,
 }
 
 /// Log entry for batch sending
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LogEntry {
     pub step_id: String,
+    /// When the line was captured
     pub timestamp: DateTime<Utc>,
     pub content: String,
     pub level: String,
     pub sequence: u64,
+    /// Output stream of a step output line
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream: Option<OutputStream>,
+    /// Set when `content` is one chunk of a longer line
+    #[serde(flatten)]
+    pub chunk: Option<LogChunk>,
+}
+
+/// Position of a chunk in a line too large for one log entry.
+///
+/// The chunks of a line have consecutive sequence numbers; joining their
+/// contents in order gives the line back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogChunk {
+    /// Chunk index (0-based)
+    pub chunk_index: u32,
+    /// Total chunks of the line
+    pub total_chunks: u32,
+}
+
+impl LogEntry {
+    /// The entry with its content in the wire `format`
+    pub fn encode(mut self, format: LogFormat) -> Self {
+        self.content = encode_log_content(format, self.timestamp, self.stream, &self.level, self.content);
+        self
+    }
+}
+
+/// A log line in `json` format
+#[derive(Serialize)]
+struct JsonLogLine<'a> {
+    timestamp: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream: Option<OutputStream>,
+    level: &'a str,
+    message: &'a str,
+}
+
+fn encode_log_content(
+    format: LogFormat,
+    timestamp: DateTime<Utc>,
+    stream: Option<OutputStream>,
+    level: &str,
+    content: String,
+) -> String {
+    match format {
+        LogFormat::Plain => content,
+        LogFormat::Json => {
+            let line = JsonLogLine { timestamp, stream, level, message: &content };
+            serde_json::to_string(&line).unwrap_or(content)
+        }
+    }
 }
 
 /// Messages received from control plane
@@ -200,15 +578,82 @@ pub enum IncomingMessage {
     #[serde(rename = "job_assignment")]
     JobAssignment { job: JobSpec },
 
+    /// A job assignment whose job failed to parse, kept so the runner can
+    /// reject it with the fields at fault
+    #[serde(skip_deserializing)]
+    MalformedJobAssignment { job: serde_json::Value, error: String },
+
     #[serde(rename = "job_cancel")]
     JobCancel { job_id: String },
 
+    #[serde(rename = "job_diagnostics")]
+    JobDiagnostics { job_id: String },
+
+    #[serde(rename = "query_status")]
+    QueryStatus {
+        #[serde(default)]
+        request_id: Option<String>,
+    },
+
+    /// Ask for the runner's free slots, answered with a `capacity_report`
+    #[serde(rename = "capacity_query")]
+    CapacityQuery {
+        #[serde(default)]
+        request_id: Option<String>,
+    },
+
+    /// Runtime overrides; with `reset`, earlier overrides are dropped first
+    #[serde(rename = "config_update")]
+    ConfigUpdate {
+        #[serde(default)]
+        reset: bool,
+        #[serde(default)]
+        overrides: ConfigOverrides,
+    },
+
+    /// Stop accepting jobs while running and queued ones finish; with
+    /// `exit` the runner shuts down once they have, and `resume` accepts
+    /// jobs again. Unlike the `drain` override this is not kept across
+    /// restarts.
+    #[serde(rename = "drain")]
+    Drain {
+        #[serde(default)]
+        resume: bool,
+        #[serde(default)]
+        exit: bool,
+    },
+
     #[serde(rename = "log_ack")]
     LogAck {
         job_id: String,
         last_sequence: u64,
     },
 
+    /// Run `command` in the debug session of a failed job
+    #[serde(rename = "debug_exec")]
+    DebugExec {
+        job_id: String,
+        session_id: String,
+        command: String,
+        /// Echoed in the `debug_output`
+        #[serde(default)]
+        request_id: Option<String>,
+    },
+
+    /// Close the debug session of a failed job before it expires
+    #[serde(rename = "debug_end")]
+    DebugEnd { job_id: String, session_id: String },
+
+    /// Answer to `active_jobs`: `cancel` lists jobs the control plane does
+    /// not consider running here, `resend` the jobs whose status it lost
+    #[serde(rename = "reconcile")]
+    Reconcile {
+        #[serde(default)]
+        cancel: Vec<String>,
+        #[serde(default)]
+        resend: Vec<String>,
+    },
+
     #[serde(rename = "error")]
     Error { message: String },
 
@@ -226,10 +671,16 @@ pub struct SystemInfo {
     pub memory_total_mb: u64,
     pub memory_used_mb: u64,
     pub memory_usage_percent: f32,
+    /// Shells installed on the host
+    pub shells: Vec<String>,
+    /// Optional host capabilities (e.g. `kvm`)
+    pub capabilities: Vec<String>,
+    /// NVIDIA GPUs jobs can request with `gpus`
+    pub gpu_count: usize,
 }
 
 /// Job specification received from control plane
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct JobSpec {
     pub job_id: String,
     pub execution_id: String,
@@ -238,14 +689,124 @@ pub struct JobSpec {
     pub environment: HashMap<String, String>,
     pub secrets: HashMap<String, String>,
     pub container: Option<ContainerSpec>,
+    /// Executor to run the steps with, by name; chosen from `container`
+    /// if absent
+    #[serde(default)]
+    pub executor: Option<String>,
     pub timeout_minutes: u32,
     pub workspace: WorkspaceSpec,
+    #[serde(default)]
+    pub labels: Vec<String>,
+    #[serde(default)]
+    pub cleanup: CleanupPolicy,
+    /// Runner-local resources held for the whole job
+    #[serde(default)]
+    pub resources: Vec<String>,
+    /// Artifacts to collect from the workspace after the steps
+    #[serde(default)]
+    pub artifacts: Vec<ArtifactSpec>,
+    /// Untrusted code (e.g. a pull request from a fork); the runner's
+    /// `[untrusted]` restrictions apply
+    #[serde(default)]
+    pub untrusted: bool,
+    /// Pending jobs with a higher priority start first
+    #[serde(default)]
+    pub priority: i32,
+    /// Keep the job for a debug session if a step fails, where the runner
+    /// allows it
+    #[serde(default)]
+    pub debug_on_failure: bool,
+    /// Only one job of the group runs on this runner at a time; a newer
+    /// job supersedes the pending ones
+    #[serde(default)]
+    pub concurrency: Option<ConcurrencySpec>,
+    /// NVIDIA GPUs the job gets to itself while it runs
+    #[serde(default)]
+    pub gpus: u32,
+    /// Errors the job is retried on, instead of the runner's `job.retry_on`
+    #[serde(default, alias = "retry-on")]
+    pub retry_on: Option<Vec<RetryClass>>,
+    /// Secrets left out of `secrets` and fetched from the control plane
+    /// right before each step that may see them, by name
+    #[serde(default, alias = "jit-secrets")]
+    pub jit_secrets: Vec<String>,
+}
+
+/// Concurrency group of a job
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConcurrencySpec {
+    pub group: String,
+    /// Also cancel the group's running job instead of waiting for it
+    #[serde(default, alias = "cancel-in-progress")]
+    pub cancel_in_progress: bool,
+}
+
+/// Artifact declaration: workspace files matching `paths`, packaged as one archive
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArtifactSpec {
+    pub name: String,
+    /// Glob patterns relative to the workspace
+    pub paths: Vec<String>,
+    #[serde(default)]
+    pub when: ArtifactWhen,
+    /// Upload the single file in `paths` in chunks while the job runs
+    #[serde(default)]
+    pub stream: bool,
+}
+
+/// Which job outcomes an artifact is uploaded for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ArtifactWhen {
+    #[default]
+    OnSuccess,
+    OnFailure,
+    Always,
+}
+
+impl ArtifactWhen {
+    /// Whether the artifact should be uploaded given the job outcome
+    pub fn matches(&self, succeeded: bool) -> bool {
+        match self {
+            Self::OnSuccess => succeeded,
+            Self::OnFailure => !succeeded,
+            Self::Always => true,
+        }
+    }
+}
+
+/// Workspace cleanup policy for a job
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CleanupPolicy {
+    /// Always remove the workspace
+    #[default]
+    Always,
+    /// Remove only if the job succeeded, retain failed workspaces
+    OnSuccess,
+    /// Always retain the workspace
+    Never,
+}
+
+impl CleanupPolicy {
+    /// Whether the workspace should be removed given the job outcome
+    pub fn should_remove(&self, succeeded: bool) -> bool {
+        match self {
+            Self::Always => true,
+            Self::OnSuccess => succeeded,
+            Self::Never => false,
+        }
+    }
 }
 
 /// Step specification
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StepSpec {
+    /// Control plane step UUID
     pub step_id: String,
+    /// User-facing id for `steps.<id>` references
+    #[serde(default)]
+    pub id: Option<String>,
     pub name: String,
     pub run: Option<String>,
     pub uses: Option<String>,
@@ -260,21 +821,107 @@ pub struct StepSpec {
     pub continue_on_error: bool,
     #[serde(default = "default_timeout")]
     pub timeout_minutes: u32,
+    /// Condition deciding whether the step runs (defaults to `success()`)
+    #[serde(default, rename = "if", alias = "condition")]
+    pub condition: Option<String>,
+    /// Runner-local resources held while the step runs
+    #[serde(default)]
+    pub resources: Vec<String>,
+    /// Output encoding, overriding the runner default
+    #[serde(default)]
+    pub output_encoding: Option<OutputEncoding>,
+    /// Directories restored before and saved after the step
+    #[serde(default)]
+    pub cache: Option<CacheSpec>,
+    /// Names of the job secrets the step may see; the runner's
+    /// `step_secrets` default applies if absent
+    #[serde(default)]
+    pub secrets: Option<Vec<String>>,
+    /// Script run if the job is cancelled while this step executes, before
+    /// the step is terminated
+    #[serde(default)]
+    pub on_cancel: Option<String>,
+    /// Ids of steps that must finish first; if any step declares `needs`,
+    /// steps run as a graph instead of in order
+    #[serde(default)]
+    pub needs: Vec<String>,
+    /// Files uploaded as artifacts once the step succeeds
+    #[serde(default)]
+    pub outputs: StepOutputs,
+    /// CPU, memory and process limits of a host step, within the runner's
+    /// `executor.shell.limits`
+    #[serde(default)]
+    pub limits: ResourceLimits,
+    /// Caps, sampling and filtering of the step's logged output
+    #[serde(default)]
+    pub log: LogControls,
+}
+
+/// Step output declaration
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StepOutputs {
+    /// Glob patterns relative to the workspace; each matching file becomes
+    /// an artifact of its own, referenced in the step's status update
+    #[serde(default)]
+    pub files: Vec<String>,
+}
+
+/// Step cache declaration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheSpec {
+    /// Cache key; may reference step outputs like the step's command
+    pub key: String,
+    /// Directories to cache (`~/...`, absolute, or workspace-relative)
+    pub paths: Vec<String>,
+}
+
+impl StepSpec {
+    /// Id used to reference this step from expressions
+    pub fn reference_id(&self) -> &str {
+        self.id
+            .as_deref()
+            .filter(|id| !id.is_empty())
+            .unwrap_or(&self.step_id)
+    }
 }
 
 /// Container specification
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ContainerSpec {
     pub image: String,
     #[serde(default)]
     pub env: HashMap<String, String>,
     #[serde(default)]
     pub volumes: Vec<String>,
+    /// Host devices to pass through, e.g. `/dev/ttyUSB0` or `/dev/kvm`
+    #[serde(default)]
+    pub devices: Vec<String>,
+    /// Named container profile, e.g. `kvm` or `android-emulator`
+    #[serde(default)]
+    pub profile: Option<String>,
+    /// Per-step or per-job container, overriding the runner default
+    #[serde(default)]
+    pub mode: Option<ContainerMode>,
     pub options: Option<String>,
+    /// Directories kept in runner-managed volumes between jobs, e.g.
+    /// `/root/.cargo`; ignored for untrusted jobs
+    #[serde(default)]
+    pub caches: Vec<VolumeCacheSpec>,
+}
+
+/// A container directory cached in a named Docker volume
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VolumeCacheSpec {
+    /// Absolute path in the container
+    pub path: String,
+    /// Jobs with the same key (and repository) share the volume; defaults
+    /// to the path
+    #[serde(default)]
+    pub key: Option<String>,
 }
 
 /// Workspace specification
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WorkspaceSpec {
     pub path: String,
     pub repository_url: Option<String>,
@@ -309,7 +956,13 @@ pub struct WebSocketClient {
 impl WebSocketClient {
     /// Create a new WebSocket client and start connection
     pub async fn new(settings: Settings) -> Result<Self> {
-        let (outgoing_tx, outgoing_rx) = mpsc::channel::<OutgoingMessage>(1000);
+        let (mut outgoing_tx, outgoing_rx) = mpsc::channel::<OutgoingMessage>(1000);
+        if settings.websocket.batch_window_ms > 0 {
+            let (queue_tx, queue_rx) = mpsc::channel::<OutgoingMessage>(1000);
+            let window = Duration::from_millis(settings.websocket.batch_window_ms);
+            tokio::spawn(super::outbox::run(window, settings.websocket.batch_max_messages, queue_rx, outgoing_tx));
+            outgoing_tx = queue_tx;
+        }
         let (incoming_tx, incoming_rx) = mpsc::channel::<IncomingMessage>(1000);
 
         let state = Arc::new(RwLock::new(ConnectionState::Disconnected));
@@ -317,6 +970,7 @@ impl WebSocketClient {
         let last_pong = Arc::new(RwLock::new(Instant::now()));
         let state_callbacks: Arc<RwLock<Vec<StateCallback>>> = Arc::new(RwLock::new(Vec::new()));
         let reconnect_strategy = Arc::new(Mutex::new(ReconnectStrategy::new(&settings.websocket)));
+        let connector = super::tls::websocket_connector(&settings.control_plane.tls)?;
 
         let client = Self {
             settings: settings.clone(),
@@ -336,6 +990,7 @@ impl WebSocketClient {
         tokio::spawn(async move {
             Self::connection_loop(
                 settings_clone,
+                connector,
                 state,
                 is_running,
                 last_pong,
@@ -357,6 +1012,7 @@ impl WebSocketClient {
     /// Main connection loop with reconnection logic
     async fn connection_loop(
         settings: Settings,
+        connector: Option<Connector>,
         state: Arc<RwLock<ConnectionState>>,
         is_running: Arc<AtomicBool>,
         last_pong: Arc<RwLock<Instant>>,
@@ -378,8 +1034,8 @@ impl WebSocketClient {
 
             info!("Connecting to control plane: {}", settings.control_plane.ws_url);
 
-            match connect_async(&url).await {
-                Ok((ws_stream, _)) => {
+            match Self::open(&url, &settings, connector.clone()).await {
+                Ok(ws_stream) => {
                     info!("WebSocket connected successfully");
                     Self::set_state(&state, &state_callbacks, ConnectionState::Connected).await;
 
@@ -402,7 +1058,7 @@ impl WebSocketClient {
                     }
                 }
                 Err(e) => {
-                    error!("Failed to connect: {}", e);
+                    error!("Failed to connect: {:#}", e);
                 }
             }
 
@@ -439,6 +1095,23 @@ impl WebSocketClient {
         info!("WebSocket connection loop ended");
     }
 
+    /// Open the WebSocket connection, through the proxy if there is one
+    async fn open(url: &str, settings: &Settings, connector: Option<Connector>) -> Result<WsStream> {
+        let target = reqwest::Url::parse(url)?;
+        match super::proxy::proxy_for(&settings.control_plane.proxy, &target)? {
+            Some(proxy) => {
+                let timeout = Duration::from_secs(settings.control_plane.timeout_secs);
+                let stream = proxy.connect(&target, timeout).await?;
+                let (ws_stream, _) = client_async_tls_with_config(url, stream, None, connector).await?;
+                Ok(ws_stream)
+            }
+            None => {
+                let (ws_stream, _) = connect_async_tls_with_config(url, None, false, connector).await?;
+                Ok(ws_stream)
+            }
+        }
+    }
+
     /// Handle an active WebSocket connection
     async fn handle_connection(
         ws_stream: WsStream,
@@ -463,21 +1136,12 @@ impl WebSocketClient {
                 msg = receiver.next() => {
                     match msg {
                         Some(Ok(WsMessage::Text(text))) => {
-                            debug!("Received: {}", text);
-                            match serde_json::from_str::<IncomingMessage>(&text) {
-                                Ok(message) => {
-                                    // Update pong time for any message
-                                    *last_pong.write().await = Instant::now();
-
-                                    if incoming_tx.send(message).await.is_err() {
-                                        warn!("Failed to forward incoming message");
-                                    }
-                                }
-                                Err(e) => {
-                                    warn!("Failed to parse message: {} - {}", e, text);
-                                }
-                            }
+                            Self::forward_incoming(&text, last_pong, incoming_tx).await;
                         }
+                        Some(Ok(WsMessage::Binary(data))) => match decode_frame(&data) {
+                            Ok(text) => Self::forward_incoming(&text, last_pong, incoming_tx).await,
+                            Err(e) => warn!("Failed to decompress message: {}", e),
+                        },
                         Some(Ok(WsMessage::Ping(data))) => {
                             debug!("Received ping, sending pong");
                             sender.send(WsMessage::Pong(data)).await?;
@@ -510,7 +1174,7 @@ impl WebSocketClient {
                     if let Some(message) = msg {
                         let json = serde_json::to_string(&message)?;
                         debug!("Sending: {}", json);
-                        sender.send(WsMessage::Text(json)).await?;
+                        sender.send(encode_frame(json, &settings.websocket)?).await?;
                     }
                 }
 
@@ -541,6 +1205,33 @@ impl WebSocketClient {
         }
     }
 
+    /// Parse a received message and pass it on
+    async fn forward_incoming(
+        text: &str,
+        last_pong: &Arc<RwLock<Instant>>,
+        incoming_tx: &mpsc::Sender<IncomingMessage>,
+    ) {
+        debug!("Received: {}", text);
+        match serde_json::from_str::<IncomingMessage>(text) {
+            Ok(message) => {
+                // Update pong time for any message
+                *last_pong.write().await = Instant::now();
+
+                if incoming_tx.send(message).await.is_err() {
+                    warn!("Failed to forward incoming message");
+                }
+            }
+            Err(e) => {
+                warn!("Failed to parse message: {} - {}", e, text);
+                if let Some(message) = malformed_assignment(text, &e) {
+                    if incoming_tx.send(message).await.is_err() {
+                        warn!("Failed to forward incoming message");
+                    }
+                }
+            }
+        }
+    }
+
     /// Set connection state and notify callbacks
     async fn set_state(
         state: &Arc<RwLock<ConnectionState>>,
@@ -583,7 +1274,7 @@ impl WebSocketClient {
     pub async fn send(&self, message: &OutgoingMessage) -> Result<()> {
         self.message_tx.send(message.clone())
             .await
-            .map_err(|_| anyhow::anyhow!("Failed to queue message for sending"))
+            .map_err(|_| RunnerError::InfraError("Failed to queue message for sending".into()).into())
     }
 
     /// Receive a message (blocking)
@@ -606,14 +1297,53 @@ impl WebSocketClient {
         &self,
         runner_id: &str,
         current_jobs: u32,
+        capacity: Capacity,
+        draining: bool,
+        labels: &[String],
+        config_revision: Option<u64>,
     ) -> Result<()> {
         let system_info = get_system_info();
+        let status = if draining {
+            "draining"
+        } else if current_jobs > 0 {
+            "busy"
+        } else {
+            "online"
+        };
 
         self.send(&OutgoingMessage::Heartbeat {
             runner_id: runner_id.to_string(),
-            status: if current_jobs > 0 { "busy" } else { "online" }.to_string(),
+            status: status.to_string(),
             current_jobs,
+            capacity,
+            fleet: FleetInfo::new(&self.settings),
             system_info,
+            labels: labels.to_vec(),
+            config_revision,
+        }).await
+    }
+
+    /// Send a detailed status snapshot in reply to a status query
+    pub async fn send_status_report(
+        &self,
+        runner_id: &str,
+        request_id: Option<String>,
+        max_concurrent_jobs: u32,
+        running_jobs: Vec<JobSnapshot>,
+        pending_jobs: Vec<PendingJobSnapshot>,
+    ) -> Result<()> {
+        let busy = !running_jobs.is_empty();
+        self.send(&OutgoingMessage::RunnerStatusReport {
+            runner_id: runner_id.to_string(),
+            request_id,
+            status: if busy { "busy" } else { "online" }.to_string(),
+            max_concurrent_jobs,
+            running_jobs,
+            pending_jobs,
+            system_info: get_system_info(),
+            versions: get_versions(),
+            image_pulls: Box::new(ImagePulls::global().stats()),
+            timestamp: Utc::now(),
         }).await
     }
 
@@ -625,13 +1355,17 @@ impl WebSocketClient {
         content: &str,
         level: &str,
     ) -> Result<()> {
+        let format = self.settings.logging.format;
+        let timestamp = Utc::now();
         self.send(&OutgoingMessage::Log {
             job_id: job_id.to_string(),
             step_id: step_id.to_string(),
-            timestamp: Utc::now(),
-            content: content.to_string(),
+            timestamp,
+            content: encode_log_content(format, timestamp, None, level, content.to_string()),
             level: level.to_string(),
             sequence: None,
+            chunk: None,
+            format,
         }).await
     }
 
@@ -644,13 +1378,17 @@ impl WebSocketClient {
         level: &str,
         sequence: u64,
     ) -> Result<()> {
+        let format = self.settings.logging.format;
+        let timestamp = Utc::now();
         self.send(&OutgoingMessage::Log {
             job_id: job_id.to_string(),
             step_id: step_id.to_string(),
-            timestamp: Utc::now(),
-            content: content.to_string(),
+            timestamp,
+            content: encode_log_content(format, timestamp, None, level, content.to_string()),
             level: level.to_string(),
             sequence: Some(sequence),
+            chunk: None,
+            format,
         }).await
     }
 
@@ -660,9 +1398,11 @@ impl WebSocketClient {
         job_id: &str,
         logs: Vec<LogEntry>,
     ) -> Result<()> {
+        let format = self.settings.logging.format;
         self.send(&OutgoingMessage::LogBatch {
             job_id: job_id.to_string(),
-            logs,
+            logs: logs.into_iter().map(|entry| entry.encode(format)).collect(),
+            format,
         }).await
     }
 
@@ -674,6 +1414,7 @@ impl WebSocketClient {
         status: &str,
         exit_code: Option<i32>,
         outputs: HashMap<String, String>,
+        meta: StatusMeta,
     ) -> Result<()> {
         self.send(&OutgoingMessage::StatusUpdate {
             entity_type: entity_type.to_string(),
@@ -681,6 +1422,28 @@ impl WebSocketClient {
             status: status.to_string(),
             exit_code,
             outputs,
+            runner_id: self.settings.runner.id.clone(),
+            meta,
+        }).await
+    }
+
+    /// Confirm a cancel request; see [`OutgoingMessage::CancelAcknowledged`]
+    pub async fn send_cancel_acknowledged(&self, job_id: &str, state: &str) -> Result<()> {
+        self.send(&OutgoingMessage::CancelAcknowledged {
+            job_id: job_id.to_string(),
+            runner_id: self.settings.runner.id.clone(),
+            state: state.to_string(),
+            timestamp: Utc::now(),
+        }).await
+    }
+
+    /// Send the annotations a step reported
+    pub async fn send_annotations(&self, job_id: &str, step_id: &str, annotations: Vec<Annotation>) -> Result<()> {
+        self.send(&OutgoingMessage::Annotations {
+            job_id: job_id.to_string(),
+            step_id: step_id.to_string(),
+            runner_id: self.settings.runner.id.clone(),
+            annotations,
         }).await
     }
 
@@ -707,7 +1470,7 @@ impl WebSocketClient {
             }
             tokio::time::sleep(Duration::from_millis(100)).await;
         }
-        Err(anyhow::anyhow!("Connection timeout"))
+        anyhow::bail!(RunnerError::InfraError("Connection timeout".into()))
     }
 }
 
@@ -715,27 +1478,65 @@ impl WebSocketClient {
 fn get_system_info() -> SystemInfo {
     use sysinfo::System;
 
-    let mut sys = System::new_all();
-    sys.refresh_all();
-
-    // sysinfo 0.30 uses global_cpu_info() instead of global_cpu_usage()
-    let cpu_usage = sys.global_cpu_info().cpu_usage();
-    let total_memory = sys.total_memory() / 1024 / 1024;
-    let used_memory = sys.used_memory() / 1024 / 1024;
+    // Sampled in the background; see `runner.stats_refresh_secs`
+    let stats = crate::utils::StatsCache::global().latest();
 
     SystemInfo {
         os: System::name().unwrap_or_else(|| "unknown".into()),
         arch: std::env::consts::ARCH.to_string(),
-        cpu_count: sys.cpus().len(),
-        cpu_usage_percent: cpu_usage,
-        memory_total_mb: total_memory,
-        memory_used_mb: used_memory,
-        memory_usage_percent: if total_memory > 0 {
-            (used_memory as f32 / total_memory as f32) * 100.0
-        } else {
-            0.0
-        },
+        cpu_count: stats.cpu_count,
+        cpu_usage_percent: stats.cpu_usage_percent,
+        memory_total_mb: stats.memory_total_mb,
+        memory_used_mb: stats.memory_used_mb,
+        memory_usage_percent: stats.memory_usage_percent(),
+        shells: crate::utils::available_shells().to_vec(),
+        capabilities: crate::utils::capabilities(),
+        gpu_count: crate::utils::nvidia_gpus().len(),
+    }
+}
+
+/// Get runner and host versions
+fn get_versions() -> RunnerVersions {
+    use sysinfo::System;
+
+    RunnerVersions {
+        runner: env!("CARGO_PKG_VERSION").to_string(),
+        os: System::os_version(),
+        kernel: System::kernel_version(),
+    }
+}
+
+/// Frame carrying `json`, compressed if it is long enough
+fn encode_frame(json: String, config: &WebSocketConfig) -> Result<WsMessage> {
+    use std::io::Write;
+
+    if config.compression == WsCompression::None || json.len() < config.compression_min_bytes {
+        return Ok(WsMessage::Text(json));
     }
+    let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::fast());
+    encoder.write_all(json.as_bytes())?;
+    Ok(WsMessage::Binary(encoder.finish()?))
+}
+
+/// JSON text of a compressed binary frame
+fn decode_frame(data: &[u8]) -> Result<String> {
+    use std::io::Read;
+
+    let mut text = String::new();
+    flate2::read::ZlibDecoder::new(data).read_to_string(&mut text)?;
+    Ok(text)
+}
+
+/// A job assignment for `text`, if it is one that failed to parse with `error`
+fn malformed_assignment(text: &str, error: &serde_json::Error) -> Option<IncomingMessage> {
+    let mut value: serde_json::Value = serde_json::from_str(text).ok()?;
+    if value.get("type")?.as_str()? != "job_assignment" {
+        return None;
+    }
+    Some(IncomingMessage::MalformedJobAssignment {
+        job: value.get_mut("job").map(serde_json::Value::take).unwrap_or_default(),
+        error: error.to_string(),
+    })
 }
 
 // ============================================================================
@@ -745,6 +1546,207 @@ fn get_system_info() -> SystemInfo {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_status_update_serialization() {
+        let started_at = Utc::now();
+        let message = OutgoingMessage::StatusUpdate {
+            entity_type: "step".to_string(),
+            entity_id: "step-1".to_string(),
+            status: "running".to_string(),
+            exit_code: None,
+            outputs: HashMap::new(),
+            runner_id: "runner-1".to_string(),
+            meta: StatusMeta::started(2, started_at),
+        };
+
+        let value = serde_json::to_value(&message).unwrap();
+        assert_eq!(value["type"], "status_update");
+        assert_eq!(value["runner_id"], "runner-1");
+        assert_eq!(value["attempt"], 2);
+        assert!(value["started_at"].is_string());
+        assert!(value.get("finished_at").is_none());
+    }
+
+    #[test]
+    fn test_status_update_cancellation() {
+        let requested_at = Utc::now();
+        let cancellation = Cancellation {
+            requested_at: Some(requested_at),
+            reason: "Job cancelled".to_string(),
+            steps: vec!["step-2".to_string()],
+        };
+        let message = OutgoingMessage::StatusUpdate {
+            entity_type: "job".to_string(),
+            entity_id: "job-1".to_string(),
+            status: "cancelled".to_string(),
+            exit_code: None,
+            outputs: HashMap::new(),
+            runner_id: "runner-1".to_string(),
+            meta: StatusMeta::finished(1, None).with_cancellation(cancellation),
+        };
+        let value = serde_json::to_value(&message).unwrap();
+        assert_eq!(value["type"], "status_update");
+        assert_eq!(value["cancellation"]["reason"], "Job cancelled");
+        assert_eq!(value["cancellation"]["steps"][0], "step-2");
+        assert!(value["cancellation"]["requested_at"].is_string());
+
+        let message = OutgoingMessage::StatusUpdate {
+            entity_type: "job".to_string(),
+            entity_id: "job-2".to_string(),
+            status: "success".to_string(),
+            exit_code: None,
+            outputs: HashMap::new(),
+            runner_id: "runner-1".to_string(),
+            meta: StatusMeta::default(),
+        };
+        assert!(serde_json::to_value(&message).unwrap().get("cancellation").is_none());
+    }
+
+    #[test]
+    fn test_query_status_deserialization() {
+        let message: IncomingMessage = serde_json::from_str(r#"{"type":"query_status","request_id":"q-1"}"#).unwrap();
+        assert!(matches!(message, IncomingMessage::QueryStatus { request_id: Some(ref id) } if id == "q-1"));
+
+        let message: IncomingMessage = serde_json::from_str(r#"{"type":"query_status"}"#).unwrap();
+        assert!(matches!(message, IncomingMessage::QueryStatus { request_id: None }));
+    }
+
+    #[test]
+    fn test_capacity_report() {
+        let message: IncomingMessage = serde_json::from_str(r#"{"type":"capacity_query","request_id":"c-1"}"#).unwrap();
+        assert!(matches!(message, IncomingMessage::CapacityQuery { request_id: Some(ref id) } if id == "c-1"));
+
+        let report = OutgoingMessage::CapacityReport {
+            runner_id: "runner-1".to_string(),
+            request_id: Some("c-1".to_string()),
+            fleet: FleetInfo { group: Some("linux-builders".to_string()), capacity_weight: 2.0 },
+            running_jobs: 1,
+            pending_jobs: 0,
+            pending_capacity: 2,
+            capacity: Capacity { capacity: 3, label_slots: HashMap::from([("gpu".to_string(), 0)]) },
+            free_gpus: 0,
+            draining: false,
+            timestamp: Utc::now(),
+        };
+        let value = serde_json::to_value(&report).unwrap();
+        assert_eq!(value["type"], "capacity_report");
+        assert_eq!(value["group"], "linux-builders");
+        assert_eq!(value["capacity_weight"], 2.0);
+        assert_eq!(value["capacity"], 3);
+        assert_eq!(value["label_slots"]["gpu"], 0);
+    }
+
+    #[test]
+    fn test_config_update_deserialization() {
+        let message: IncomingMessage = serde_json::from_str(
+            r#"{"type":"config_update","overrides":{"revision":3,"max_concurrent_jobs":2,"drain":true}}"#,
+        ).unwrap();
+        match message {
+            IncomingMessage::ConfigUpdate { reset, overrides } => {
+                assert!(!reset);
+                assert_eq!(overrides.revision, Some(3));
+                assert_eq!(overrides.max_concurrent_jobs, Some(2));
+                assert_eq!(overrides.drain, Some(true));
+                assert!(overrides.labels.is_none());
+            }
+            other => panic!("unexpected message: {:?}", other),
+        }
+
+        let message: IncomingMessage = serde_json::from_str(r#"{"type":"config_update","reset":true}"#).unwrap();
+        assert!(matches!(message, IncomingMessage::ConfigUpdate { reset: true, ref overrides } if *overrides == ConfigOverrides::default()));
+
+        let message: IncomingMessage = serde_json::from_str(r#"{"type":"drain","exit":true}"#).unwrap();
+        assert!(matches!(message, IncomingMessage::Drain { resume: false, exit: true }));
+
+        let message: IncomingMessage = serde_json::from_str(r#"{"type":"reconcile","cancel":["job-1"]}"#).unwrap();
+        assert!(matches!(message, IncomingMessage::Reconcile { ref cancel, ref resend } if cancel == &["job-1"] && resend.is_empty()));
+    }
+
+    #[test]
+    fn test_malformed_assignment() {
+        let text = r#"{"type":"job_assignment","job":{"job_id":"job-1","steps":"make"}}"#;
+        let error = serde_json::from_str::<IncomingMessage>(text).unwrap_err();
+        match malformed_assignment(text, &error) {
+            Some(IncomingMessage::MalformedJobAssignment { job, error }) => {
+                assert_eq!(job["job_id"], "job-1");
+                assert!(!error.is_empty());
+            }
+            other => panic!("unexpected message: {:?}", other),
+        }
+
+        let text = r#"{"type":"job_cancel"}"#;
+        let error = serde_json::from_str::<IncomingMessage>(text).unwrap_err();
+        assert!(malformed_assignment(text, &error).is_none());
+    }
+
+    /// Tags of `IncomingMessage`
+    const INCOMING_TYPES: [&str; 14] = [
+        "connected", "heartbeat_ack", "job_assignment", "job_cancel", "job_diagnostics",
+        "query_status", "config_update", "drain", "log_ack", "reconcile", "debug_exec", "debug_end",
+        "error", "pong",
+    ];
+
+    /// Field names used by incoming messages, mixed with random ones
+    fn field_name() -> impl Strategy<Value = String> {
+        prop_oneof![
+            prop::sample::select(vec![
+                "job", "job_id", "step_id", "steps", "name", "run", "needs", "env", "container",
+                "artifacts", "overrides", "revision", "reset", "resume", "exit", "request_id",
+                "sequence", "last_sequence", "message", "runner_id", "timestamp", "timeout_minutes",
+            ]).prop_map(String::from),
+            "[a-z_]{1,12}",
+        ]
+    }
+
+    fn json_value() -> impl Strategy<Value = serde_json::Value> {
+        use serde_json::Value;
+        let leaf = prop_oneof![
+            Just(Value::Null),
+            any::<bool>().prop_map(Value::from),
+            any::<i64>().prop_map(Value::from),
+            any::<u64>().prop_map(Value::from),
+            (-1e9f64..1e9).prop_map(Value::from),
+            "\\PC{0,20}".prop_map(Value::from),
+        ];
+        leaf.prop_recursive(4, 64, 8, |inner| {
+            prop_oneof![
+                prop::collection::vec(inner.clone(), 0..6).prop_map(Value::Array),
+                prop::collection::vec((field_name(), inner), 0..8)
+                    .prop_map(|fields| Value::Object(fields.into_iter().collect())),
+            ]
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn prop_incoming_message_any_text(text in "\\PC{0,200}") {
+            let _ = serde_json::from_str::<IncomingMessage>(&text);
+        }
+
+        #[test]
+        fn prop_incoming_message_any_fields(
+            message_type in prop::sample::select(INCOMING_TYPES.to_vec()),
+            fields in prop::collection::vec((field_name(), json_value()), 0..8),
+        ) {
+            let mut object: serde_json::Map<String, serde_json::Value> = fields.into_iter().collect();
+            object.insert("type".to_string(), message_type.into());
+            let _ = serde_json::from_value::<IncomingMessage>(serde_json::Value::Object(object.clone()));
+            let _ = serde_json::from_slice::<IncomingMessage>(&serde_json::to_vec(&object).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_cleanup_policy() {
+        let policy: CleanupPolicy = serde_json::from_str("\"on-success\"").unwrap();
+        assert_eq!(policy, CleanupPolicy::OnSuccess);
+        assert!(policy.should_remove(true));
+        assert!(!policy.should_remove(false));
+
+        assert!(CleanupPolicy::default().should_remove(false));
+        assert!(!CleanupPolicy::Never.should_remove(true));
+    }
 
     #[test]
     fn test_reconnect_strategy() {
@@ -756,6 +1758,7 @@ mod tests {
             heartbeat_interval_secs: 30,
             heartbeat_timeout_secs: 10,
             enable_heartbeat: true,
+            ..WebSocketConfig::default()
         };
 
         let mut strategy = ReconnectStrategy::new(&config);
@@ -796,6 +1799,7 @@ mod tests {
             heartbeat_interval_secs: 30,
             heartbeat_timeout_secs: 10,
             enable_heartbeat: true,
+            ..WebSocketConfig::default()
         };
 
         let mut strategy = ReconnectStrategy::new(&config);
@@ -809,4 +1813,85 @@ mod tests {
         let delay = strategy.next_delay().unwrap();
         assert_eq!(delay, Duration::from_millis(4000));
     }
+
+    #[test]
+    fn test_frame_compression() {
+        let mut config = WebSocketConfig { compression_min_bytes: 64, ..WebSocketConfig::default() };
+        let batch = serde_json::to_string(&OutgoingMessage::LogBatch {
+            job_id: "job-1".into(),
+            logs: (0..50)
+                .map(|i| LogEntry {
+                    step_id: "step-1".into(),
+                    timestamp: Utc::now(),
+                    content: format!("Compiling crate-{} v1.0.0", i),
+                    level: "info".into(),
+                    sequence: i,
+                    stream: None,
+                    chunk: None,
+                })
+                .collect(),
+            format: LogFormat::Plain,
+        }).unwrap();
+
+        assert!(matches!(encode_frame(batch.clone(), &config).unwrap(), WsMessage::Text(ref text) if *text == batch));
+
+        config.compression = WsCompression::Deflate;
+        let WsMessage::Binary(data) = encode_frame(batch.clone(), &config).unwrap() else {
+            panic!("Expected a binary frame");
+        };
+        assert!(data.len() * 5 < batch.len());
+        assert_eq!(decode_frame(&data).unwrap(), batch);
+
+        // Short messages stay readable
+        assert!(matches!(encode_frame("{}".into(), &config).unwrap(), WsMessage::Text(_)));
+        assert!(decode_frame(b"not zlib").is_err());
+    }
+
+    #[test]
+    fn test_log_entry_encoding() {
+        let timestamp: DateTime<Utc> = "2026-01-02T03:04:05.678Z".parse().unwrap();
+        let entry = LogEntry {
+            step_id: "step-1".into(),
+            timestamp,
+            content: "warning: \"unused\"".into(),
+            level: "error".into(),
+            sequence: 7,
+            stream: Some(OutputStream::Stderr),
+            chunk: None,
+        };
+
+        assert_eq!(entry.clone().encode(LogFormat::Plain).content, "warning: \"unused\"");
+
+        let encoded = entry.clone().encode(LogFormat::Json);
+        let line: serde_json::Value = serde_json::from_str(&encoded.content).unwrap();
+        assert_eq!(line, serde_json::json!({
+            "timestamp": "2026-01-02T03:04:05.678Z",
+            "stream": "stderr",
+            "level": "error",
+            "message": "warning: \"unused\"",
+        }));
+
+        let batch = serde_json::to_value(OutgoingMessage::LogBatch {
+            job_id: "job-1".into(),
+            logs: vec![entry.clone()],
+            format: LogFormat::Plain,
+        }).unwrap();
+        assert!(batch.get("format").is_none());
+        assert_eq!(batch["logs"][0]["stream"], "stderr");
+        assert!(batch["logs"][0].get("chunk_index").is_none());
+
+        // Chunks carry their position next to the other fields
+        let chunk = LogEntry { chunk: Some(LogChunk { chunk_index: 1, total_chunks: 3 }), ..entry.clone() };
+        let value = serde_json::to_value(&chunk).unwrap();
+        assert_eq!(value["chunk_index"], 1);
+        assert_eq!(value["total_chunks"], 3);
+        assert_eq!(serde_json::from_value::<LogEntry>(value).unwrap().chunk, chunk.chunk);
+
+        let batch = serde_json::to_value(OutgoingMessage::LogBatch {
+            job_id: "job-1".into(),
+            logs: vec![encoded],
+            format: LogFormat::Json,
+        }).unwrap();
+        assert_eq!(batch["format"], "json");
+    }
 }
@@ -1,16 +1,38 @@
 //! Configuration management for Muelsyse Runner
 
+mod overrides;
 mod settings;
 
+pub use overrides::{ConfigOverrides, LogLevelControl};
+
 pub use settings::{
     Settings,
     RunnerConfig,
+    ResourceConfig,
     ControlPlaneConfig,
+    TlsConfig,
+    ProxyConfig,
     ExecutorConfig,
     DockerConfig,
+    KvmProfileConfig,
     ShellConfig,
+    KubernetesConfig,
     WorkspaceConfig,
+    SnapshotConfig,
     WebSocketConfig,
+    WsCompression,
     LoggingConfig,
+    LogFormat,
+    AnsiMode,
     JobConfig,
+    ArtifactCompression,
+    StepSecrets,
+    UntrustedConfig,
+    HooksConfig,
+    DiagnosticsConfig,
+    DiagnosticCommand,
+    EventsConfig,
+    AdminConfig,
+    TracingConfig,
+    TracingProtocol,
 };
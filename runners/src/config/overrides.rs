@@ -0,0 +1,165 @@
+//! Remote configuration overrides
+//!
+//! The control plane can change a few runner settings at runtime with a
+//! `config_update` message. Accepted overrides are kept in a local file and
+//! applied again on start, on top of the configuration file.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Highest `max_concurrent_jobs` accepted from the control plane
+pub const MAX_CONCURRENT_JOBS: usize = 256;
+
+const MAX_LABELS: usize = 64;
+const MAX_LABEL_LEN: usize = 63;
+const LOG_LEVELS: [&str; 5] = ["trace", "debug", "info", "warn", "error"];
+
+/// Changes the runner's log verbosity; `None` restores the startup filter
+pub type LogLevelControl = Arc<dyn Fn(Option<&str>) -> Result<()> + Send + Sync>;
+
+/// Settings overridden by the control plane; unset fields keep the
+/// configuration file's value
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConfigOverrides {
+    /// Control plane revision of these overrides, echoed in heartbeats
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revision: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_concurrent_jobs: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub labels: Option<Vec<String>>,
+    /// trace, debug, info, warn or error
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_level: Option<String>,
+    /// Stop accepting jobs; running and queued jobs still finish
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub drain: Option<bool>,
+}
+
+impl ConfigOverrides {
+    /// Overlay the fields set in `update`
+    pub fn merge(&mut self, update: ConfigOverrides) {
+        if update.revision.is_some() {
+            self.revision = update.revision;
+        }
+        if update.max_concurrent_jobs.is_some() {
+            self.max_concurrent_jobs = update.max_concurrent_jobs;
+        }
+        if update.labels.is_some() {
+            self.labels = update.labels;
+        }
+        if update.log_level.is_some() {
+            self.log_level = update.log_level;
+        }
+        if update.drain.is_some() {
+            self.drain = update.drain;
+        }
+    }
+
+    /// Reject values the runner cannot apply
+    pub fn validate(&self) -> Result<()> {
+        if let Some(max) = self.max_concurrent_jobs {
+            if max == 0 || max > MAX_CONCURRENT_JOBS {
+                anyhow::bail!("max_concurrent_jobs must be between 1 and {}, got {}", MAX_CONCURRENT_JOBS, max);
+            }
+        }
+
+        if let Some(ref labels) = self.labels {
+            if labels.len() > MAX_LABELS {
+                anyhow::bail!("At most {} labels are allowed, got {}", MAX_LABELS, labels.len());
+            }
+            let invalid: Vec<&str> = labels
+                .iter()
+                .filter(|label| {
+                    label.is_empty()
+                        || label.len() > MAX_LABEL_LEN
+                        || !label.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'))
+                })
+                .map(String::as_str)
+                .collect();
+            if !invalid.is_empty() {
+                anyhow::bail!("Invalid labels: {:?}", invalid);
+            }
+        }
+
+        if let Some(ref level) = self.log_level {
+            if !LOG_LEVELS.contains(&level.as_str()) {
+                anyhow::bail!("log_level must be one of {}, got {:?}", LOG_LEVELS.join(", "), level);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Overrides saved by an earlier run; a missing file means none
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = match std::fs::read(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read config overrides {:?}", path)),
+        };
+        let overrides: Self = serde_json::from_slice(&contents)
+            .with_context(|| format!("Failed to parse config overrides {:?}", path))?;
+        overrides.validate()?;
+        Ok(overrides)
+    }
+
+    /// Persist the overrides, replacing the file atomically
+    pub async fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .context("Failed to create config overrides directory")?;
+        }
+
+        let tmp_path = path.with_extension("tmp");
+        tokio::fs::write(&tmp_path, serde_json::to_vec_pretty(self)?)
+            .await
+            .context("Failed to write config overrides")?;
+        tokio::fs::rename(&tmp_path, path)
+            .await
+            .context("Failed to write config overrides")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_merge_validate_and_persist() {
+        let mut overrides = ConfigOverrides {
+            revision: Some(1),
+            max_concurrent_jobs: Some(4),
+            ..Default::default()
+        };
+        overrides.merge(serde_json::from_value(serde_json::json!({
+            "revision": 2,
+            "labels": ["linux", "gpu:a100"],
+            "drain": true,
+        })).unwrap());
+        assert_eq!(overrides.revision, Some(2));
+        assert_eq!(overrides.max_concurrent_jobs, Some(4));
+        assert_eq!(overrides.drain, Some(true));
+        assert!(overrides.validate().is_ok());
+
+        let invalid = [
+            serde_json::json!({ "max_concurrent_jobs": 0 }),
+            serde_json::json!({ "max_concurrent_jobs": MAX_CONCURRENT_JOBS + 1 }),
+            serde_json::json!({ "labels": ["linux", "has space"] }),
+            serde_json::json!({ "log_level": "verbose" }),
+        ];
+        for update in invalid {
+            let update: ConfigOverrides = serde_json::from_value(update.clone()).unwrap();
+            assert!(update.validate().is_err(), "{:?}", update);
+        }
+
+        let path = std::env::temp_dir().join(format!("muelsyse-overrides-{}.json", uuid::Uuid::new_v4()));
+        assert_eq!(ConfigOverrides::load(&path).unwrap(), ConfigOverrides::default());
+        overrides.save(&path).await.unwrap();
+        assert_eq!(ConfigOverrides::load(&path).unwrap(), overrides);
+        let _ = std::fs::remove_file(&path);
+    }
+}
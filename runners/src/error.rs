@@ -0,0 +1,149 @@
+//! Runner error taxonomy
+//!
+//! Errors that decide a job's outcome are raised as [`RunnerError`] and
+//! carried inside `anyhow::Error`; [`RunnerError::classify`] finds them
+//! anywhere in the context chain. Any other error is an infrastructure
+//! failure.
+//!
+//! Failed jobs are only retried for the [`RetryClass`]es they list in
+//! `retry_on`, by default infrastructure errors: network and image pull
+//! failures or a full disk may pass, a failing build will fail again.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use thiserror::Error;
+
+use crate::job::{JobStatus, StepStatus};
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum RunnerError {
+    /// A step or the whole job ran out of time
+    #[error("{0}")]
+    Timeout(String),
+    /// The job was cancelled
+    #[error("{0}")]
+    Cancelled(String),
+    /// A step's command exited unsuccessfully
+    #[error("Step failed with exit code {exit_code}")]
+    ExecutorFailure { exit_code: i32 },
+    /// The runner host, an executor backend or the control plane failed
+    #[error("{0}")]
+    InfraError(String),
+    /// The job cannot run as configured
+    #[error("{0}")]
+    ConfigError(String),
+}
+
+impl RunnerError {
+    /// The `RunnerError` in `error`'s chain, or an infrastructure error
+    pub fn classify(error: &anyhow::Error) -> Self {
+        error
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<RunnerError>())
+            .cloned()
+            .unwrap_or_else(|| Self::InfraError(format!("{:#}", error)))
+    }
+
+    /// Stable name reported as `error_kind`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Timeout(_) => "timeout",
+            Self::Cancelled(_) => "cancelled",
+            Self::ExecutorFailure { .. } => "executor_failure",
+            Self::InfraError(_) => "infra_error",
+            Self::ConfigError(_) => "config_error",
+        }
+    }
+
+    pub fn job_status(&self) -> JobStatus {
+        match self {
+            Self::Timeout(_) => JobStatus::Timeout,
+            Self::Cancelled(_) => JobStatus::Cancelled,
+            Self::ExecutorFailure { .. } | Self::InfraError(_) | Self::ConfigError(_) => JobStatus::Failed,
+        }
+    }
+
+    pub fn step_status(&self) -> StepStatus {
+        match self {
+            Self::Timeout(_) => StepStatus::Timeout,
+            Self::Cancelled(_) => StepStatus::Cancelled,
+            Self::ExecutorFailure { .. } | Self::InfraError(_) | Self::ConfigError(_) => StepStatus::Failed,
+        }
+    }
+
+    /// Whether another attempt of the job could succeed
+    pub fn is_retryable(&self) -> bool {
+        self.retry_class().is_some()
+    }
+
+    /// What `retry_on` must list for the job to be retried after this
+    /// error; none for errors no attempt can get past
+    pub fn retry_class(&self) -> Option<RetryClass> {
+        match self {
+            Self::Timeout(_) => Some(RetryClass::Timeout),
+            Self::ExecutorFailure { .. } => Some(RetryClass::ExecutorFailure),
+            Self::InfraError(_) => Some(RetryClass::InfraError),
+            Self::Cancelled(_) | Self::ConfigError(_) => None,
+        }
+    }
+
+    /// Status outputs describing the error
+    pub fn to_outputs(&self) -> HashMap<String, String> {
+        let mut outputs = HashMap::from([
+            ("error".to_string(), self.to_string()),
+            ("error_kind".to_string(), self.kind().to_string()),
+        ]);
+        if let Self::ExecutorFailure { exit_code } = self {
+            outputs.insert("exit_code".to_string(), exit_code.to_string());
+        }
+        outputs
+    }
+}
+
+/// Errors a failed job can be retried on, named as their `error_kind`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RetryClass {
+    /// Network, image pull, disk and other host or backend failures
+    InfraError,
+    /// The job or a step ran out of time
+    Timeout,
+    /// A step's command exited unsuccessfully
+    ExecutorFailure,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_classify() {
+        let failed: anyhow::Result<()> = Err(RunnerError::ExecutorFailure { exit_code: 2 }.into());
+        let error = failed.context("Step build").unwrap_err();
+        let classified = RunnerError::classify(&error);
+        assert_eq!(classified, RunnerError::ExecutorFailure { exit_code: 2 });
+        assert_eq!(classified.job_status(), JobStatus::Failed);
+        let outputs = classified.to_outputs();
+        assert_eq!(outputs["error_kind"], "executor_failure");
+        assert_eq!(outputs["exit_code"], "2");
+
+        // Messages no longer decide the outcome
+        let connection = anyhow::anyhow!("Connection timeout");
+        assert_eq!(RunnerError::classify(&connection), RunnerError::InfraError("Connection timeout".into()));
+        assert!(RunnerError::classify(&connection).is_retryable());
+
+        let timeout = anyhow::Error::from(RunnerError::Timeout("Job timeout exceeded".into()));
+        assert_eq!(RunnerError::classify(&timeout).job_status(), JobStatus::Timeout);
+        assert_eq!(RunnerError::classify(&timeout).step_status(), StepStatus::Timeout);
+        assert_eq!(RunnerError::classify(&connection).step_status(), StepStatus::Failed);
+        assert_eq!(RunnerError::Cancelled("Job cancelled".into()).job_status(), JobStatus::Cancelled);
+        assert!(!RunnerError::Cancelled("Job cancelled".into()).is_retryable());
+        assert!(!RunnerError::ConfigError("Unknown executor 'x'".into()).is_retryable());
+
+        let failure = RunnerError::ExecutorFailure { exit_code: 1 };
+        assert_eq!(failure.retry_class(), Some(RetryClass::ExecutorFailure));
+        assert_eq!(serde_json::to_value(failure.retry_class()).unwrap(), failure.kind());
+        assert_eq!(RunnerError::Cancelled("Job cancelled".into()).retry_class(), None);
+    }
+}
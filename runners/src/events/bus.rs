@@ -0,0 +1,114 @@
+//! Runner event types and broadcast bus
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+/// Something that happened on the runner
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum RunnerEvent {
+    Connected,
+    Disconnected { reason: String },
+    Reconnecting { delay_ms: u64 },
+    JobAccepted { job_id: String, name: String },
+    JobRejected { job_id: String, reason: String },
+    JobStarted { job_id: String, attempt: u32 },
+    JobFinished { job_id: String, status: String, attempt: u32, duration_ms: u64 },
+    StepStarted { job_id: String, step_id: String },
+    StepFinished {
+        job_id: String,
+        step_id: String,
+        status: String,
+        exit_code: Option<i32>,
+        duration_ms: u64,
+    },
+    CacheHit { job_id: String, key: String },
+    ArtifactUploaded { job_id: String, name: String, size_bytes: u64 },
+}
+
+impl RunnerEvent {
+    /// Stable snake_case name, matching the serialized `event` tag
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Connected => "connected",
+            Self::Disconnected { .. } => "disconnected",
+            Self::Reconnecting { .. } => "reconnecting",
+            Self::JobAccepted { .. } => "job_accepted",
+            Self::JobRejected { .. } => "job_rejected",
+            Self::JobStarted { .. } => "job_started",
+            Self::JobFinished { .. } => "job_finished",
+            Self::StepStarted { .. } => "step_started",
+            Self::StepFinished { .. } => "step_finished",
+            Self::CacheHit { .. } => "cache_hit",
+            Self::ArtifactUploaded { .. } => "artifact_uploaded",
+        }
+    }
+}
+
+/// An event with its origin and time
+#[derive(Debug, Clone, Serialize)]
+pub struct EventEnvelope {
+    pub runner_id: String,
+    pub timestamp: DateTime<Utc>,
+    #[serde(flatten)]
+    pub event: RunnerEvent,
+}
+
+/// Fan-out bus for runner events
+///
+/// Emitting never blocks; subscribers that fall behind by more than the
+/// channel capacity miss the oldest events.
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    runner_id: String,
+    tx: broadcast::Sender<EventEnvelope>,
+}
+
+impl EventBus {
+    pub fn new(runner_id: &str, capacity: usize) -> Self {
+        let (tx, _) = broadcast::channel(capacity.max(1));
+        Self {
+            runner_id: runner_id.to_string(),
+            tx,
+        }
+    }
+
+    /// Publish an event to all current subscribers
+    pub fn emit(&self, event: RunnerEvent) {
+        // No subscribers is not an error
+        let _ = self.tx.send(EventEnvelope {
+            runner_id: self.runner_id.clone(),
+            timestamp: Utc::now(),
+            event,
+        });
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<EventEnvelope> {
+        self.tx.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_emit_and_serialize() {
+        let bus = EventBus::new("runner-1", 16);
+        let mut rx = bus.subscribe();
+
+        bus.emit(RunnerEvent::StepStarted {
+            job_id: "job-1".into(),
+            step_id: "step-1".into(),
+        });
+
+        let envelope = rx.recv().await.unwrap();
+        assert_eq!(envelope.event.kind(), "step_started");
+
+        let json = serde_json::to_value(&envelope).unwrap();
+        assert_eq!(json["event"], "step_started");
+        assert_eq!(json["runner_id"], "runner-1");
+        assert_eq!(json["step_id"], "step-1");
+    }
+}
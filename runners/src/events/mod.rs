@@ -0,0 +1,7 @@
+//! Structured runner event stream
+
+mod bus;
+mod subscribers;
+
+pub use bus::{EventBus, EventEnvelope, RunnerEvent};
+pub use subscribers::{spawn_audit_log, spawn_webhook, EventCounters};
@@ -0,0 +1,135 @@
+//! Built-in event subscribers
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{broadcast, RwLock};
+use tokio::task::JoinHandle;
+use tracing::warn;
+
+use super::bus::{EventBus, EventEnvelope};
+
+/// Receive the next event, skipping over lag; `None` once the bus is gone
+async fn next_event(rx: &mut broadcast::Receiver<EventEnvelope>, subscriber: &str) -> Option<EventEnvelope> {
+    loop {
+        match rx.recv().await {
+            Ok(envelope) => return Some(envelope),
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                warn!("Event subscriber '{}' missed {} events", subscriber, missed);
+            }
+            Err(broadcast::error::RecvError::Closed) => return None,
+        }
+    }
+}
+
+/// Append every event as a JSON line to `path`
+pub fn spawn_audit_log(bus: &EventBus, path: PathBuf) -> JoinHandle<()> {
+    let mut rx = bus.subscribe();
+
+    tokio::spawn(async move {
+        while let Some(envelope) = next_event(&mut rx, "audit_log").await {
+            let Ok(mut line) = serde_json::to_string(&envelope) else {
+                continue;
+            };
+            line.push('\n');
+
+            let written = async {
+                let mut file = tokio::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .await?;
+                file.write_all(line.as_bytes()).await?;
+                file.flush().await
+            }.await;
+
+            if let Err(e) = written {
+                warn!("Failed to write audit log {:?}: {}", path, e);
+            }
+        }
+    })
+}
+
+/// POST events as JSON to `url`; `kinds` limits which events are sent (empty = all)
+pub fn spawn_webhook(bus: &EventBus, url: String, kinds: Vec<String>) -> JoinHandle<()> {
+    let mut rx = bus.subscribe();
+    let client = reqwest::Client::new();
+
+    tokio::spawn(async move {
+        while let Some(envelope) = next_event(&mut rx, "webhook").await {
+            if !kinds.is_empty() && !kinds.iter().any(|k| k == envelope.event.kind()) {
+                continue;
+            }
+
+            let sent = client
+                .post(&url)
+                .json(&envelope)
+                .timeout(std::time::Duration::from_secs(10))
+                .send()
+                .await
+                .and_then(|r| r.error_for_status());
+
+            if let Err(e) = sent {
+                warn!("Failed to deliver '{}' event to webhook: {}", envelope.event.kind(), e);
+            }
+        }
+    })
+}
+
+/// Per-kind event counts, for metrics
+#[derive(Debug, Clone, Default)]
+pub struct EventCounters {
+    counts: Arc<RwLock<HashMap<&'static str, u64>>>,
+}
+
+impl EventCounters {
+    /// Count events from `bus` until it closes
+    pub fn spawn(&self, bus: &EventBus) -> JoinHandle<()> {
+        let mut rx = bus.subscribe();
+        let counts = self.counts.clone();
+
+        tokio::spawn(async move {
+            while let Some(envelope) = next_event(&mut rx, "counters").await {
+                *counts.write().await.entry(envelope.event.kind()).or_insert(0) += 1;
+            }
+        })
+    }
+
+    /// Snapshot of the counts so far
+    pub async fn snapshot(&self) -> HashMap<&'static str, u64> {
+        self.counts.read().await.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::RunnerEvent;
+
+    #[tokio::test]
+    async fn test_audit_log_and_counters() {
+        let path = std::env::temp_dir().join(format!("muelsyse-audit-{}.jsonl", uuid::Uuid::new_v4()));
+        let bus = EventBus::new("runner-1", 16);
+        let counters = EventCounters::default();
+        let counting = counters.spawn(&bus);
+        let audit = spawn_audit_log(&bus, path.clone());
+
+        bus.emit(RunnerEvent::Connected);
+        bus.emit(RunnerEvent::Reconnecting { delay_ms: 500 });
+        bus.emit(RunnerEvent::Connected);
+        drop(bus);
+
+        counting.await.unwrap();
+        audit.await.unwrap();
+
+        let counts = counters.snapshot().await;
+        assert_eq!(counts.get("connected"), Some(&2));
+        assert_eq!(counts.get("reconnecting"), Some(&1));
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(log.lines().count(), 3);
+        assert!(log.lines().nth(1).unwrap().contains("\"delay_ms\":500"));
+        let _ = std::fs::remove_file(&path);
+    }
+}
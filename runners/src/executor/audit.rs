@@ -0,0 +1,352 @@
+//! Filesystem write auditing for shell steps
+//!
+//! With `executor.shell.audit_writes`, host steps run under `strace`, which
+//! records the file system calls of the step and every process it starts.
+//! Calls that create, modify or remove paths outside the workspace and the
+//! allowed paths are reported, whether or not they succeeded, so jobs that
+//! rely on the host can be found before they are moved into containers.
+//!
+//! Relative paths are resolved against each process's working directory as
+//! traced through `chdir`; a directory entered with `fchdir` is not tracked.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+use std::sync::OnceLock;
+
+/// Most violations reported for one step
+const MAX_VIOLATIONS: usize = 100;
+
+static STRACE: OnceLock<bool> = OnceLock::new();
+
+/// Whether `strace` can be run, detected once
+pub fn strace_available() -> bool {
+    *STRACE.get_or_init(|| {
+        std::process::Command::new("strace")
+            .arg("-V")
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success())
+    })
+}
+
+/// `argv` run under `strace`, writing the trace to `trace_path`.
+///
+/// The tracer runs detached (`-D`), so the step stays the spawned process
+/// and receives signals and exit codes as without auditing.
+pub fn traced_command(argv: &[String], trace_path: &Path) -> Vec<String> {
+    let mut command: Vec<String> = [
+        "strace", "-D", "-f", "-qq", "-y", "-s", "4096",
+        "-e", "trace=%file,%process", "-e", "signal=none", "-o",
+    ]
+    .iter()
+    .map(|arg| arg.to_string())
+    .collect();
+    command.push(trace_path.display().to_string());
+    command.push("--".to_string());
+    command.extend(argv.iter().cloned());
+    command
+}
+
+/// A write to a path outside the workspace
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WriteAttempt {
+    pub path: PathBuf,
+    pub syscall: String,
+    /// The call failed, e.g. for lack of permission
+    pub denied: bool,
+}
+
+impl std::fmt::Display for WriteAttempt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({}", self.path.display(), self.syscall)?;
+        if self.denied {
+            write!(f, ", failed")?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// Which paths a step may write to
+pub struct WriteAudit {
+    allowed: Vec<PathBuf>,
+}
+
+impl WriteAudit {
+    /// Writes below `workspace` or any of `allowed` are not reported
+    pub fn new(workspace: &Path, allowed: &[PathBuf]) -> Self {
+        let mut all = vec![normalize(workspace)];
+        all.extend(allowed.iter().map(|path| normalize(path)));
+        Self { allowed: all }
+    }
+
+    /// Writes in an `strace` log of a step started in `cwd`, first attempt
+    /// per path
+    pub fn violations(&self, trace: &str, cwd: &Path) -> Vec<WriteAttempt> {
+        let mut cwds: HashMap<u32, PathBuf> = HashMap::new();
+        let mut seen = HashSet::new();
+        let mut violations = Vec::new();
+
+        for line in join_interrupted(trace) {
+            let Some(call) = Call::parse(&line) else {
+                continue;
+            };
+            let process_cwd = cwds.get(&call.pid).cloned().unwrap_or_else(|| cwd.to_path_buf());
+
+            match call.name {
+                "chdir" if call.succeeded() => {
+                    if let Some(path) = call.string(0) {
+                        cwds.insert(call.pid, process_cwd.join(path));
+                    }
+                    continue;
+                }
+                "clone" | "clone3" | "fork" | "vfork" => {
+                    if let Some(child) = call.result.parse::<u32>().ok().filter(|&pid| pid > 0) {
+                        cwds.insert(child, process_cwd);
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+
+            for path in call.written_paths(&process_cwd) {
+                let path = normalize(&path);
+                if self.allowed.iter().any(|allowed| path.starts_with(allowed)) || !seen.insert(path.clone()) {
+                    continue;
+                }
+                violations.push(WriteAttempt {
+                    path,
+                    syscall: call.name.to_string(),
+                    denied: !call.succeeded(),
+                });
+                if violations.len() == MAX_VIOLATIONS {
+                    return violations;
+                }
+            }
+        }
+        violations
+    }
+}
+
+/// One completed system call in the trace
+struct Call<'a> {
+    pid: u32,
+    name: &'a str,
+    args: Vec<&'a str>,
+    result: &'a str,
+}
+
+impl<'a> Call<'a> {
+    fn parse(line: &'a str) -> Option<Self> {
+        let line = line.trim_start();
+        let (pid, rest) = match line.split_once(char::is_whitespace) {
+            Some((pid, rest)) if pid.bytes().all(|b| b.is_ascii_digit()) => (pid.parse().ok()?, rest.trim_start()),
+            _ => (0, line),
+        };
+
+        let (head, result) = rest.rsplit_once(") = ")?;
+        let result = result.split_whitespace().next().unwrap_or_default();
+        let (name, args) = head.split_once('(')?;
+        Some(Self { pid, name, args: split_args(args), result })
+    }
+
+    fn succeeded(&self) -> bool {
+        !self.result.starts_with('-')
+    }
+
+    /// A string argument, unquoted
+    fn string(&self, index: usize) -> Option<String> {
+        let arg = self.args.get(index)?;
+        let quoted = arg.strip_prefix('"')?;
+        let end = quoted.rfind('"')?;
+        Some(unescape(&quoted[..end]))
+    }
+
+    /// The directory a `dirfd` argument refers to
+    fn dir(&self, index: usize, cwd: &Path) -> Option<PathBuf> {
+        let arg = self.args.get(index)?;
+        if *arg == "AT_FDCWD" {
+            return Some(cwd.to_path_buf());
+        }
+        // `-y` prints descriptors as `3</path>`
+        let (_, path) = arg.split_once('<')?;
+        Some(PathBuf::from(path.strip_suffix('>')?))
+    }
+
+    /// Paths this call creates, changes or removes
+    fn written_paths(&self, cwd: &Path) -> Vec<PathBuf> {
+        let opens = |flags: usize| self.args.get(flags).is_some_and(|flags| opens_for_writing(flags));
+        // (dirfd argument, path argument) pairs
+        let targets: &[(Option<usize>, usize)] = match self.name {
+            "open" if opens(1) => &[(None, 0)],
+            "openat" | "openat2" if opens(2) => &[(Some(0), 1)],
+            "creat" | "mkdir" | "rmdir" | "unlink" | "truncate" | "chmod" | "chown" | "lchown" | "mknod"
+            | "utime" | "utimes" => &[(None, 0)],
+            "mkdirat" | "unlinkat" | "fchmodat" | "fchownat" | "mknodat" | "utimensat" => &[(Some(0), 1)],
+            "rename" => &[(None, 0), (None, 1)],
+            "renameat" | "renameat2" => &[(Some(0), 1), (Some(2), 3)],
+            "link" | "symlink" => &[(None, 1)],
+            "linkat" => &[(Some(2), 3)],
+            "symlinkat" => &[(Some(1), 2)],
+            _ => &[],
+        };
+
+        targets
+            .iter()
+            .filter_map(|&(dir, path)| {
+                let path = PathBuf::from(self.string(path)?);
+                if path.is_absolute() {
+                    return Some(path);
+                }
+                let dir = match dir {
+                    Some(dir) => self.dir(dir, cwd)?,
+                    None => cwd.to_path_buf(),
+                };
+                Some(dir.join(path))
+            })
+            .collect()
+    }
+}
+
+/// The trace's lines, with calls that another process interrupted
+/// (`<unfinished ...>`, later `<... name resumed>`) joined back together
+fn join_interrupted(trace: &str) -> Vec<String> {
+    const UNFINISHED: &str = " <unfinished ...>";
+
+    let mut unfinished: HashMap<&str, &str> = HashMap::new();
+    let mut lines = Vec::new();
+    for line in trace.lines() {
+        let pid = line.split_whitespace().next().unwrap_or_default();
+        if let Some(start) = line.strip_suffix(UNFINISHED) {
+            unfinished.insert(pid, start);
+        } else if let Some((_, rest)) = line.split_once(" resumed>") {
+            if let Some(start) = unfinished.remove(pid) {
+                lines.push(format!("{}{}", start, rest));
+            }
+        } else {
+            lines.push(line.to_string());
+        }
+    }
+    lines
+}
+
+fn opens_for_writing(flags: &str) -> bool {
+    flags
+        .split(|c: char| c == '|' || c == ',' || c == '=' || c.is_whitespace() || c == '{' || c == '}')
+        .any(|flag| matches!(flag, "O_WRONLY" | "O_RDWR" | "O_CREAT" | "O_TRUNC"))
+}
+
+/// Split an argument list at top-level commas
+fn split_args(args: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut depth, mut quoted, mut escaped, mut start) = (0usize, false, false, 0);
+    for (i, c) in args.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '{' | '[' | '(' | '<' if !quoted => depth += 1,
+            '}' | ']' | ')' | '>' if !quoted => depth = depth.saturating_sub(1),
+            ',' if !quoted && depth == 0 => {
+                parts.push(args[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(args[start..].trim());
+    parts
+}
+
+/// Undo strace's C-style escaping
+fn unescape(text: &str) -> String {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut chars = text.bytes().peekable();
+    while let Some(b) = chars.next() {
+        if b != b'\\' {
+            bytes.push(b);
+            continue;
+        }
+        match chars.next() {
+            Some(b'n') => bytes.push(b'\n'),
+            Some(b't') => bytes.push(b'\t'),
+            Some(b'r') => bytes.push(b'\r'),
+            Some(b'v') => bytes.push(0x0b),
+            Some(b'f') => bytes.push(0x0c),
+            Some(digit @ b'0'..=b'7') => {
+                let mut value = u32::from(digit - b'0');
+                for _ in 0..2 {
+                    match chars.peek() {
+                        Some(&next @ b'0'..=b'7') => {
+                            value = value * 8 + u32::from(next - b'0');
+                            chars.next();
+                        }
+                        _ => break,
+                    }
+                }
+                bytes.push(value as u8);
+            }
+            Some(other) => bytes.push(other),
+            None => bytes.push(b'\\'),
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Resolve `.` and `..` without touching the file system
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_violations() {
+        let trace = r#"4100  execve("/bin/bash", ["bash", "-e", "/tmp/muelsyse-scripts/job-1.sh"], 0x7ffd /* 20 vars */) = 0
+4100  openat(AT_FDCWD, "/etc/ld.so.cache", O_RDONLY|O_CLOEXEC) = 3</etc/ld.so.cache>
+4100  openat(AT_FDCWD, "build/out.txt", O_WRONLY|O_CREAT|O_TRUNC, 0666) = 3</work/job-1/build/out.txt>
+4100  openat(AT_FDCWD, "/dev/null", O_WRONLY|O_CREAT|O_TRUNC, 0666) = 3</dev/null>
+4100  clone(child_stack=NULL, flags=CLONE_CHILD_CLEARTID|CLONE_CHILD_SETTID|SIGCHLD, child_tidptr=0x7f) = 4101
+4101  chdir("/etc") = 0
+4101  openat(AT_FDCWD, "hosts", O_WRONLY|O_APPEND) = -1 EACCES (Permission denied)
+4101  mkdir("../opt/tool", 0777) = 0
+4101  +++ exited with 1 +++
+4100  openat(AT_FDCWD, "../shared/cache.db", O_RDWR|O_CREAT, 0644 <unfinished ...>
+4100  <... openat resumed>) = 4</work/shared/cache.db>
+4100  renameat2(3</home/ci>, ".m2/tmp", AT_FDCWD, "/home/ci/.m2/settings.xml", RENAME_NOREPLACE) = 0
+4100  unlinkat(AT_FDCWD, "/tmp/scratch", 0) = 0
+4100  openat(AT_FDCWD, "/var/log/a\"b\303\251", O_WRONLY|O_CREAT, 0644) = 3
+4100  openat(AT_FDCWD, "/etc/hosts", O_WRONLY|O_APPEND) = -1 EACCES (Permission denied)
+"#;
+        let audit = WriteAudit::new(Path::new("/work/job-1"), &[PathBuf::from("/tmp"), PathBuf::from("/dev")]);
+        let violations: Vec<String> = audit
+            .violations(trace, Path::new("/work/job-1"))
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+
+        assert_eq!(violations, vec![
+            "/etc/hosts (openat, failed)",
+            "/opt/tool (mkdir)",
+            "/work/shared/cache.db (openat)",
+            "/home/ci/.m2/tmp (renameat2)",
+            "/home/ci/.m2/settings.xml (renameat2)",
+            "/var/log/a\"bé (openat)",
+        ]);
+
+        assert!(opens_for_writing("{flags=O_RDWR|O_CLOEXEC, mode=0}"));
+        assert!(!opens_for_writing("O_RDONLY|O_CLOEXEC|O_DIRECTORY"));
+    }
+}
@@ -0,0 +1,247 @@
+//! cgroup v2 limits for host steps
+//!
+//! With `executor.shell.limits` or a step's own `limits`, the shell executor
+//! creates a transient cgroup `<cgroup_parent>/<job_id>/<step_id>`, writes its
+//! `cpu.max`, `memory.max` and `pids.max`, and moves the step's shell into it
+//! right after spawning it, so every process the step starts is limited with
+//! it. Once the step exits, `memory.events` and `pids.events` tell whether the
+//! kernel killed one of its processes for running out of memory or refused it
+//! a fork, which is reported as the step's `failure_reason`.
+//!
+//! The runner needs write access to `cgroup_parent`: as root on a cgroup v2
+//! host, or under a systemd unit with `Delegate=yes` and `cgroup_parent` set
+//! to an empty cgroup below the unit's own. Elsewhere steps run unlimited.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+
+/// Period of `cpu.max` quotas, in microseconds
+const CPU_PERIOD_US: u64 = 100_000;
+/// Controllers enabled for step cgroups
+const CONTROLLERS: &str = "+cpu +memory +pids";
+
+/// CPU, memory and process limits of a step
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ResourceLimits {
+    /// CPUs the step may use, e.g. `1.5`
+    #[serde(default)]
+    pub cpus: Option<f64>,
+    /// Memory of all the step's processes, swap excluded
+    #[serde(default, alias = "memory-mb")]
+    pub memory_mb: Option<u64>,
+    /// Processes and threads the step may have at once
+    #[serde(default)]
+    pub pids: Option<u64>,
+}
+
+impl ResourceLimits {
+    pub fn is_empty(&self) -> bool {
+        self.cpus.is_none() && self.memory_mb.is_none() && self.pids.is_none()
+    }
+
+    /// These limits, capped by and defaulting to `ceiling`
+    pub fn within(&self, ceiling: &ResourceLimits) -> ResourceLimits {
+        fn lower<T: PartialOrd>(a: Option<T>, b: Option<T>) -> Option<T> {
+            match (a, b) {
+                (Some(a), Some(b)) => Some(if b < a { b } else { a }),
+                (a, b) => a.or(b),
+            }
+        }
+        ResourceLimits {
+            cpus: lower(self.cpus, ceiling.cpus),
+            memory_mb: lower(self.memory_mb, ceiling.memory_mb),
+            pids: lower(self.pids, ceiling.pids),
+        }
+    }
+
+    /// Interface files of a cgroup and what to write to them
+    fn files(&self) -> Vec<(&'static str, String)> {
+        let mut files = Vec::new();
+        if let Some(cpus) = self.cpus {
+            let quota = ((cpus * CPU_PERIOD_US as f64).round() as u64).max(1000);
+            files.push(("cpu.max", format!("{} {}", quota, CPU_PERIOD_US)));
+        }
+        if let Some(memory_mb) = self.memory_mb {
+            files.push(("memory.max", (memory_mb * 1024 * 1024).to_string()));
+            files.push(("memory.swap.max", "0".to_string()));
+        }
+        if let Some(pids) = self.pids {
+            files.push(("pids.max", pids.to_string()));
+        }
+        files
+    }
+}
+
+/// A limit that made a step fail
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitExceeded {
+    /// A process was killed for running out of memory
+    Memory,
+    /// A fork was refused at the process limit
+    Pids,
+}
+
+impl LimitExceeded {
+    /// Stable name reported as the step's `failure_reason`
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Self::Memory => "oom_killed",
+            Self::Pids => "pids_limit",
+        }
+    }
+}
+
+impl std::fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Memory => write!(f, "memory limit"),
+            Self::Pids => write!(f, "process limit"),
+        }
+    }
+}
+
+/// Whether this host has a cgroup v2 hierarchy
+pub fn cgroup_v2_available() -> bool {
+    Path::new("/sys/fs/cgroup/cgroup.controllers").exists()
+}
+
+/// The cgroup of a running step
+pub(super) struct StepCgroup {
+    path: PathBuf,
+}
+
+impl StepCgroup {
+    /// Create the cgroup of `step_id` with `limits` below `parent`
+    pub async fn create(parent: &Path, job_id: &str, step_id: &str, limits: &ResourceLimits) -> Result<Self> {
+        let job_dir = parent.join(job_id);
+        tokio::fs::create_dir_all(&job_dir)
+            .await
+            .with_context(|| format!("Failed to create cgroup {:?}", job_dir))?;
+        for dir in [parent, job_dir.as_path()] {
+            tokio::fs::write(dir.join("cgroup.subtree_control"), CONTROLLERS)
+                .await
+                .with_context(|| format!("Failed to enable controllers in cgroup {:?}", dir))?;
+        }
+
+        let path = job_dir.join(step_id);
+        match tokio::fs::create_dir(&path).await {
+            Err(e) if e.kind() != std::io::ErrorKind::AlreadyExists => {
+                return Err(e).with_context(|| format!("Failed to create cgroup {:?}", path));
+            }
+            _ => {}
+        }
+        for (file, value) in limits.files() {
+            if let Err(e) = tokio::fs::write(path.join(file), &value).await {
+                // Hosts without swap accounting have no memory.swap.max
+                if file == "memory.swap.max" {
+                    debug!("Not limiting swap of cgroup {:?}: {}", path, e);
+                    continue;
+                }
+                return Err(e).with_context(|| format!("Failed to set {} of cgroup {:?}", file, path));
+            }
+        }
+        Ok(Self { path })
+    }
+
+    /// The cgroup's `cgroup.procs`, opened for writing.
+    ///
+    /// A process writing `0` to it joins the cgroup, as do the processes it
+    /// starts from then on. The kernel checks the permissions of whoever
+    /// opened the file, so a step process can still join after switching to
+    /// its job user.
+    pub fn procs_file(&self) -> Result<std::fs::File> {
+        let path = self.path.join("cgroup.procs");
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open {:?}", path))
+    }
+
+    /// The limit the step's processes ran into, if any
+    pub async fn exceeded(&self) -> Option<LimitExceeded> {
+        let read = |file: &str| tokio::fs::read_to_string(self.path.join(file));
+        if event_count(&read("memory.events").await.unwrap_or_default(), "oom_kill") > 0 {
+            return Some(LimitExceeded::Memory);
+        }
+        if event_count(&read("pids.events").await.unwrap_or_default(), "max") > 0 {
+            return Some(LimitExceeded::Pids);
+        }
+        None
+    }
+
+    /// Remove the cgroup, unless processes the step left in the background
+    /// still run in it; those go with the job's
+    pub async fn remove(self) {
+        if let Err(e) = tokio::fs::remove_dir(&self.path).await {
+            debug!("Keeping cgroup {:?} until the job finishes: {}", self.path, e);
+        }
+    }
+}
+
+/// Stop what is left of a job's processes in its step cgroups and remove them
+pub(super) async fn remove_job_cgroups(parent: &Path, job_id: &str) {
+    let job_dir = parent.join(job_id);
+    let Ok(mut steps) = tokio::fs::read_dir(&job_dir).await else {
+        return;
+    };
+    while let Ok(Some(entry)) = steps.next_entry().await {
+        let path = entry.path();
+        if !path.is_dir() {
+            continue;
+        }
+        // cgroup.kill needs Linux 5.14; the job's process groups are
+        // stopped anyway
+        let _ = tokio::fs::write(path.join("cgroup.kill"), "1").await;
+        for _ in 0..50 {
+            match tokio::fs::remove_dir(&path).await {
+                Ok(()) => break,
+                Err(_) => tokio::time::sleep(std::time::Duration::from_millis(100)).await,
+            }
+        }
+    }
+    if let Err(e) = tokio::fs::remove_dir(&job_dir).await {
+        warn!("Failed to remove cgroup {:?}: {}", job_dir, e);
+    }
+}
+
+/// Value of `key` in a flat-keyed cgroup file like `memory.events`
+fn event_count(contents: &str, key: &str) -> u64 {
+    contents
+        .lines()
+        .find_map(|line| line.strip_prefix(key)?.strip_prefix(' ')?.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limits() {
+        let runner = ResourceLimits { cpus: Some(4.0), memory_mb: Some(8192), pids: None };
+        let step: ResourceLimits = serde_json::from_str(r#"{"cpus": 8, "memory-mb": 1024, "pids": 256}"#).unwrap();
+        let effective = step.within(&runner);
+        assert_eq!(effective, ResourceLimits { cpus: Some(4.0), memory_mb: Some(1024), pids: Some(256) });
+        assert_eq!(ResourceLimits::default().within(&runner), runner);
+        assert!(ResourceLimits::default().is_empty());
+
+        let files = ResourceLimits { cpus: Some(1.5), memory_mb: Some(512), pids: Some(64) }.files();
+        assert_eq!(files, vec![
+            ("cpu.max", "150000 100000".to_string()),
+            ("memory.max", "536870912".to_string()),
+            ("memory.swap.max", "0".to_string()),
+            ("pids.max", "64".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn test_event_count() {
+        let memory_events = "low 0\nhigh 0\nmax 12\noom 1\noom_kill 1\noom_group_kill 0\n";
+        assert_eq!(event_count(memory_events, "oom_kill"), 1);
+        assert_eq!(event_count(memory_events, "max"), 12);
+        assert_eq!(event_count("max 0\n", "max"), 0);
+        assert_eq!(event_count("", "oom_kill"), 0);
+    }
+}
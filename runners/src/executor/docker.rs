@@ -1,40 +1,424 @@
 //! Docker executor - runs commands in Docker containers
+//!
+//! By default every step gets a fresh container with the step's working
+//! directory mounted at `/workspace`. Jobs in per-job container mode share
+//! one long-lived container instead: it mounts the job workspace, each step
+//! is run in it with `docker exec`, and it is removed when the job finishes.
+//!
+//! Containers are labelled with the runner and job that created them, so
+//! [`DockerExecutor::collect_garbage`] can remove the ones a crashed runner
+//! left behind. Cache volumes (`container.caches`) are labelled differently
+//! and survive it; [`DockerExecutor::evict_cache_volumes`] bounds their size.
 
 use async_trait::async_trait;
 use anyhow::{Result, Context};
 use bollard::Docker;
 use bollard::container::{
     Config, CreateContainerOptions, StartContainerOptions, WaitContainerOptions,
-    LogsOptions, RemoveContainerOptions,
+    LogsOptions, RemoveContainerOptions, KillContainerOptions, ListContainersOptions, StopContainerOptions,
 };
 use bollard::image::CreateImageOptions;
+use bollard::service::{DeviceMapping, DeviceRequest};
 use bollard::exec::{CreateExecOptions, StartExecResults};
+use bollard::network::PruneNetworksOptions;
+use bollard::volume::{CreateVolumeOptions, PruneVolumesOptions, RemoveVolumeOptions};
+use futures_util::future::{FusedFuture, FutureExt};
 use futures_util::StreamExt;
-use std::time::Instant;
-use std::collections::HashMap;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
 use tracing::{info, debug, warn};
 
-use super::traits::{Executor, ExecutorType, ExecutionContext, ExecutionResult};
-use crate::config::DockerConfig;
+use super::output::{LineForwarder, MergedOutput, OutputSink, OutputStream};
+use super::pulls::{ImagePulls, LayerCounts};
+use super::script::{
+    create_private_dir, job_output_dir, job_script_dir, write_script, ShellInvocation, CONTAINER_OUTPUT_DIR,
+    CONTAINER_SCRIPT_DIR, CONTAINER_TEMP_DIR, TEMP_DIR_VARS,
+};
+use super::traits::{ContainerMode, Executor, ExecutorType, ExecutionContext, ExecutionResult, Termination};
+use super::usage::UsageSampler;
+use crate::cache::{volumes_to_evict, CacheVolume, VolumeIndex, CACHE_LABEL};
+use crate::config::{DockerConfig, ShellConfig};
+use crate::error::RunnerError;
+
+/// Keeps a per-job container alive between steps
+const KEEP_ALIVE: [&str; 3] = ["sh", "-c", "trap 'exit 0' TERM INT; while :; do sleep 3600 & wait $!; done"];
+
+/// How long a stopped container's last output is waited for
+const OUTPUT_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Label holding the id of the runner that created a container, network or volume
+pub const RUNNER_LABEL: &str = "muelsyse.runner";
+/// Label holding the id of the job a container was created for
+pub const JOB_LABEL: &str = "muelsyse.job";
 
 /// Docker executor that runs commands in containers
 pub struct DockerExecutor {
     docker: Docker,
     config: DockerConfig,
+    shell: ShellConfig,
+    runner_id: String,
+    /// Last use of cache volumes
+    cache_index: Option<VolumeIndex>,
+}
+
+/// Docker resources removed by a garbage collection
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GarbageReport {
+    pub containers: Vec<String>,
+    pub networks: Vec<String>,
+    pub volumes: Vec<String>,
+}
+
+impl GarbageReport {
+    pub fn is_empty(&self) -> bool {
+        self.containers.is_empty() && self.networks.is_empty() && self.volumes.is_empty()
+    }
 }
 
 impl DockerExecutor {
-    pub fn new(config: DockerConfig) -> Result<Self> {
+    pub fn new(config: DockerConfig, shell: ShellConfig) -> Result<Self> {
         let docker = if config.socket.starts_with("unix://") || config.socket.starts_with('/') {
             Docker::connect_with_socket(&config.socket, 120, bollard::API_DEFAULT_VERSION)?
         } else {
             Docker::connect_with_socket_defaults()?
         };
 
-        Ok(Self { docker, config })
+        Ok(Self { docker, config, shell, runner_id: String::new(), cache_index: None })
+    }
+
+    /// Label created containers as belonging to `runner_id`
+    pub fn with_runner_id(mut self, runner_id: &str) -> Self {
+        self.runner_id = runner_id.to_string();
+        self
+    }
+
+    /// Record the use of cache volumes in `index`
+    pub fn with_cache_index(mut self, index: VolumeIndex) -> Self {
+        self.cache_index = Some(index);
+        self
+    }
+
+    /// Name of the container running a step
+    pub fn container_name(job_id: &str, step_id: &str) -> String {
+        format!("muelsyse-{}-{}", job_id, step_id)
+    }
+
+    /// Name of the container shared by a job's steps in per-job mode
+    pub fn job_container_name(job_id: &str) -> String {
+        format!("muelsyse-{}-job", job_id)
+    }
+
+    /// Remove this runner's containers whose job is not running, with their
+    /// anonymous volumes, then prune its unused networks and volumes.
+    ///
+    /// `active_jobs` is asked for the running jobs after the containers are
+    /// listed, so a job that starts meanwhile never loses its container.
+    pub async fn collect_garbage<F, Fut>(&self, active_jobs: F) -> Result<GarbageReport>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = HashSet<String>>,
+    {
+        let filters = HashMap::from([("label".to_string(), vec![format!("{}={}", RUNNER_LABEL, self.runner_id)])]);
+        let containers = self.docker.list_containers(Some(ListContainersOptions {
+            all: true,
+            filters: filters.clone(),
+            ..Default::default()
+        })).await.context("Failed to list containers")?;
+        let active = active_jobs().await;
+
+        let mut report = GarbageReport::default();
+        for container in containers {
+            let name = container.names
+                .as_ref()
+                .and_then(|names| names.first())
+                .map(|name| name.trim_start_matches('/'))
+                .unwrap_or_default();
+            let job = container.labels.as_ref().and_then(|labels| labels.get(JOB_LABEL));
+            let Some(ref id) = container.id else { continue };
+            if !is_orphan(name, job.map(String::as_str), &active) {
+                continue;
+            }
+
+            let removed = self.docker.remove_container(
+                id,
+                Some(RemoveContainerOptions {
+                    force: true,
+                    v: true,
+                    ..Default::default()
+                }),
+            ).await;
+            match removed {
+                Ok(()) => report.containers.push(name.to_string()),
+                // Removed by its job in the meantime
+                Err(bollard::errors::Error::DockerResponseServerError { status_code: 404, .. }) => {}
+                Err(e) => warn!("Failed to remove orphaned container {}: {}", name, e),
+            }
+        }
+
+        let networks = self.docker.prune_networks(Some(PruneNetworksOptions { filters: filters.clone() }))
+            .await
+            .context("Failed to prune networks")?;
+        report.networks = networks.networks_deleted.unwrap_or_default();
+        let volumes = self.docker.prune_volumes(Some(PruneVolumesOptions { filters }))
+            .await
+            .context("Failed to prune volumes")?;
+        report.volumes = volumes.volumes_deleted.unwrap_or_default();
+
+        Ok(report)
+    }
+
+    /// Remove the least recently used cache volumes not mounted by any
+    /// container until the rest fit in `max_bytes`, returning their names
+    pub async fn evict_cache_volumes(&self, max_bytes: u64) -> Result<Vec<String>> {
+        let usage = self.docker.df().await.context("Failed to get Docker disk usage")?;
+        let unused: Vec<(String, u64)> = usage.volumes
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|volume| volume.labels.contains_key(CACHE_LABEL))
+            .filter_map(|volume| {
+                let usage = volume.usage_data?;
+                (usage.ref_count == 0).then(|| (volume.name, usage.size.max(0) as u64))
+            })
+            .collect();
+        let last_used = match self.cache_index {
+            Some(ref index) => index.load().await,
+            None => HashMap::new(),
+        };
+
+        let mut removed = Vec::new();
+        for name in volumes_to_evict(unused, &last_used, max_bytes) {
+            match self.docker.remove_volume(&name, Some(RemoveVolumeOptions { force: false })).await {
+                Ok(()) => removed.push(name),
+                // Mounted by a job that started meanwhile, or already gone
+                Err(bollard::errors::Error::DockerResponseServerError { status_code: 404 | 409, .. }) => {}
+                Err(e) => warn!("Failed to remove cache volume {}: {}", name, e),
+            }
+        }
+        if let (Some(ref index), false) = (&self.cache_index, removed.is_empty()) {
+            index.forget(&removed).await?;
+        }
+        Ok(removed)
+    }
+
+    /// Create the cache volumes a container mounts and record their use
+    async fn ensure_cache_volumes(&self, volumes: &[CacheVolume]) -> Result<()> {
+        if volumes.is_empty() {
+            return Ok(());
+        }
+        for volume in volumes {
+            // Creating an existing volume returns it unchanged
+            self.docker.create_volume(CreateVolumeOptions {
+                name: volume.name.clone(),
+                labels: HashMap::from([(CACHE_LABEL.to_string(), volume.key.clone())]),
+                ..Default::default()
+            }).await.with_context(|| format!("Failed to create cache volume {}", volume.name))?;
+        }
+        if let Some(ref index) = self.cache_index {
+            let names: Vec<String> = volumes.iter().map(|volume| volume.name.clone()).collect();
+            if let Err(e) = index.touch(&names).await {
+                warn!("Failed to record use of cache volumes: {:#}", e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Create and start a job's shared container unless it is running
+    async fn ensure_job_container(&self, ctx: &ExecutionContext) -> Result<()> {
+        let name = Self::job_container_name(&ctx.job_id);
+        let running = self.docker.inspect_container(&name, None).await
+            .ok()
+            .and_then(|c| c.state)
+            .and_then(|s| s.running)
+            .unwrap_or(false);
+        if running {
+            return Ok(());
+        }
+
+        // A stopped container from an earlier attempt would hold the name
+        let _ = self.docker.remove_container(
+            &name,
+            Some(RemoveContainerOptions {
+                force: true,
+                ..Default::default()
+            }),
+        ).await;
+
+        // Docker would create a missing bind source owned by root
+        create_job_dirs(&ctx.job_id).await?;
+
+        // Mount the whole workspace; step environments are passed per exec
+        let shared = ExecutionContext {
+            working_directory: ctx.workspace.clone(),
+            environment: HashMap::new(),
+            ..ctx.clone()
+        };
+        let config = self.build_container_config(&shared, KEEP_ALIVE.iter().map(|s| s.to_string()).collect())?;
+
+        info!("Creating job container {}", name);
+        self.docker.create_container(
+            Some(CreateContainerOptions {
+                name: &name,
+                platform: None,
+            }),
+            config,
+        ).await.context("Failed to create job container")?;
+        self.docker.start_container(
+            &name,
+            None::<StartContainerOptions<String>>,
+        ).await.context("Failed to start job container")?;
+        Ok(())
+    }
+
+    /// Run a step in its job's shared container with `docker exec`
+    async fn execute_in_job_container(&self, ctx: &ExecutionContext, output: &OutputSink) -> Result<ExecutionResult> {
+        let start = Instant::now();
+        let name = Self::job_container_name(&ctx.job_id);
+        let invocation = ShellInvocation::resolve(&ctx.shell, &self.shell)?;
+        let script_name = format!("{}-{}", ctx.job_id, ctx.step_id);
+        let cmd = invocation.command_line(&format!(
+            "{}/{}.{}", CONTAINER_SCRIPT_DIR, script_name, invocation.extension
+        ));
+        let script_path = write_script(
+            &job_script_dir(&ctx.job_id),
+            &script_name,
+            invocation.extension,
+            &invocation.script(&ctx.command, &self.shell),
+        ).await?;
+
+        let relative = ctx.working_directory.strip_prefix(&ctx.workspace).unwrap_or(Path::new(""));
+        let exec = self.docker.create_exec(
+            &name,
+            CreateExecOptions {
+                cmd: Some(cmd),
+                env: Some(ctx.environment.iter().map(|(k, v)| format!("{}={}", k, v)).collect()),
+                working_dir: Some(Path::new("/workspace").join(relative).display().to_string()),
+                attach_stdout: Some(true),
+                attach_stderr: Some(true),
+                ..Default::default()
+            },
+        ).await.context("Failed to create exec")?;
+
+        debug!("Executing step {} in job container {}", ctx.step_id, name);
+
+        let merged = MergedOutput::new(output);
+        let mut stdout = LineForwarder::new(OutputStream::Stdout, ctx.output_encoding, merged.sink());
+        let mut stderr = LineForwarder::new(OutputStream::Stderr, ctx.output_encoding, merged.sink());
+        let sampler = UsageSampler::container(self.docker.clone(), name.clone(), false);
+        let exec_output = async {
+            let started = self.docker.start_exec(&exec.id, None).await.context("Failed to start exec")?;
+            if let StartExecResults::Attached { output: mut stream, .. } = started {
+                while let Some(chunk) = stream.next().await {
+                    match chunk {
+                        Ok(bollard::container::LogOutput::StdOut { message }) => stdout.push(&message),
+                        Ok(bollard::container::LogOutput::StdErr { message }) => stderr.push(&message),
+                        Ok(_) => {}
+                        Err(e) => {
+                            warn!("Exec output error: {}", e);
+                            break;
+                        }
+                    }
+                }
+            }
+            Ok::<_, anyhow::Error>(())
+        };
+        let attached = tokio::select! {
+            attached = tokio::time::timeout(ctx.timeout, exec_output) => Some(attached),
+            _ = ctx.cancel.cancelled() => None,
+        };
+        let usage = sampler.stop();
+
+        let stdout = ctx.output_encoding.decode(&stdout.finish());
+        let stderr = ctx.output_encoding.decode(&stderr.finish());
+        let merged = merged.finish().await;
+
+        if let Err(e) = tokio::fs::remove_file(&script_path).await {
+            warn!("Failed to remove script {:?}: {}", script_path, e);
+        }
+
+        let Some(attached) = attached else {
+            info!("Step {} cancelled, killing job container {}", ctx.step_id, name);
+            if let Err(e) = self.docker.kill_container(&name, None::<KillContainerOptions<String>>).await {
+                warn!("Failed to kill job container {}: {}", name, e);
+            }
+            anyhow::bail!(RunnerError::Cancelled("Step cancelled".into()));
+        };
+        match attached {
+            Ok(Ok(())) => {
+                let inspect = self.docker.inspect_exec(&exec.id).await.context("Failed to inspect exec")?;
+                Ok(ExecutionResult {
+                    exit_code: inspect.exit_code.unwrap_or(-1) as i32,
+                    stdout,
+                    stderr,
+                    output: merged,
+                    duration: start.elapsed(),
+                    timed_out: false,
+                    write_audit: None,
+                    usage: Some(usage),
+                    limit_exceeded: None,
+                    termination: None,
+                })
+            }
+            Ok(Err(e)) => Err(e),
+            Err(_) => {
+                warn!("Step {} timed out in job container {}", ctx.step_id, name);
+
+                // Exec'd processes cannot be killed on their own; restarting
+                // stops them and keeps the container's filesystem
+                if let Err(e) = self.docker.restart_container(&name, None).await {
+                    warn!("Failed to restart job container {}: {}", name, e);
+                }
+
+                Ok(ExecutionResult {
+                    exit_code: -1,
+                    stdout,
+                    stderr: "Container execution timed out".to_string(),
+                    output: merged,
+                    duration: start.elapsed(),
+                    timed_out: true,
+                    write_audit: None,
+                    usage: Some(usage),
+                    limit_exceeded: None,
+                    termination: None,
+                })
+            }
+        }
+    }
+
+    /// Run a shell command in a running container, returning exit code and output
+    pub async fn exec(&self, container: &str, command: &str) -> Result<(Option<i64>, String)> {
+        let exec = self.docker.create_exec(
+            container,
+            CreateExecOptions {
+                cmd: Some(vec!["sh", "-c", command]),
+                attach_stdout: Some(true),
+                attach_stderr: Some(true),
+                ..Default::default()
+            },
+        ).await.context("Failed to create exec")?;
+
+        let mut output = String::new();
+        if let StartExecResults::Attached { output: mut stream, .. } =
+            self.docker.start_exec(&exec.id, None).await.context("Failed to start exec")?
+        {
+            while let Some(chunk) = stream.next().await {
+                match chunk {
+                    Ok(chunk) => output.push_str(&chunk.to_string()),
+                    Err(e) => {
+                        warn!("Exec output error: {}", e);
+                        break;
+                    }
+                }
+            }
+        }
+
+        let inspect = self.docker.inspect_exec(&exec.id).await?;
+        Ok((inspect.exit_code, output))
     }
 
     async fn pull_image(&self, image: &str) -> Result<()> {
+        let pulls = ImagePulls::global();
         match self.config.pull_policy.as_str() {
             "never" => {
                 debug!("Pull policy is 'never', skipping image pull");
@@ -44,45 +428,97 @@ impl DockerExecutor {
                 // Check if image exists
                 if self.docker.inspect_image(image).await.is_ok() {
                     debug!("Image {} already exists, skipping pull", image);
+                    pulls.record_cache_hit();
                     return Ok(());
                 }
             }
             _ => {} // "always" - always pull
         }
 
-        info!("Pulling image: {}", image);
+        // Concurrent jobs needing the same image share one pull
+        let docker = self.docker.clone();
+        let reference = image.to_string();
+        pulls.pull(image, move || stream_pull(docker, reference)).await
+    }
+
+    /// Stop a timed out step's container, killing it if it is still running
+    /// `stop_grace_secs` after SIGTERM
+    async fn stop_gracefully(&self, container_id: &str) -> Termination {
+        let options = StopContainerOptions { t: self.config.stop_grace_secs as i64 };
+        if let Err(e) = self.docker.stop_container(container_id, Some(options)).await {
+            // The forced removal kills it
+            warn!("Failed to stop container {}: {}", container_id, e);
+            return Termination::Forced;
+        }
+        let exit_code = match self.docker.inspect_container(container_id, None).await {
+            Ok(container) => container.state.and_then(|state| state.exit_code),
+            Err(e) => {
+                warn!("Failed to inspect container {}: {}", container_id, e);
+                None
+            }
+        };
+        let termination = stop_termination(exit_code);
+        if termination == Termination::Forced {
+            warn!("Container {} ignored SIGTERM for {}s, killed it", container_id, self.config.stop_grace_secs);
+        }
+        termination
+    }
 
-        let mut stream = self.docker.create_image(
-            Some(CreateImageOptions {
-                from_image: image,
+    /// Forward container logs until the container exits
+    async fn follow_logs(&self, container_id: &str, stdout: &mut LineForwarder<'_>, stderr: &mut LineForwarder<'_>) {
+        let mut log_stream = self.docker.logs(
+            container_id,
+            Some(LogsOptions::<String> {
+                follow: true,
+                stdout: true,
+                stderr: true,
                 ..Default::default()
             }),
-            None,
-            None,
         );
 
-        while let Some(result) = stream.next().await {
+        while let Some(result) = log_stream.next().await {
             match result {
-                Ok(info) => {
-                    if let Some(status) = info.status {
-                        debug!("Pull status: {}", status);
-                    }
-                }
+                Ok(bollard::container::LogOutput::StdOut { message }) => stdout.push(&message),
+                Ok(bollard::container::LogOutput::StdErr { message }) => stderr.push(&message),
+                Ok(_) => {}
                 Err(e) => {
-                    warn!("Pull warning: {}", e);
+                    warn!("Log stream error: {}", e);
+                    break;
                 }
             }
         }
-
-        info!("Successfully pulled image: {}", image);
-        Ok(())
     }
 
-    fn build_container_config(&self, ctx: &ExecutionContext) -> Config<String> {
-        let mut env: Vec<String> = ctx.environment
+    /// Map requested devices to Docker device mappings, rejecting any not in
+    /// the allowlist
+    fn device_mappings(&self, devices: &[String]) -> Result<Vec<DeviceMapping>> {
+        let mappings = devices
             .iter()
-            .map(|(k, v)| format!("{}={}", k, v))
+            .map(|d| parse_device(d))
+            .collect::<Result<Vec<_>>>()?;
+
+        let denied: Vec<&str> = mappings
+            .iter()
+            .filter_map(|m| m.path_on_host.as_deref())
+            .filter(|host| !device_allowed(&self.config.allowed_devices, host))
             .collect();
+        if !denied.is_empty() {
+            anyhow::bail!("Devices not allowed on this runner: {}", denied.join(", "));
+        }
+
+        Ok(mappings)
+    }
+
+    fn build_container_config(&self, ctx: &ExecutionContext, cmd: Vec<String>) -> Result<Config<String>> {
+        // Job containers pass these on to every exec
+        let mut env: Vec<String> = match ctx.temp_dir {
+            Some(_) => TEMP_DIR_VARS.iter().map(|name| format!("{}={}", name, CONTAINER_TEMP_DIR)).collect(),
+            None => Vec::new(),
+        };
+        // The init process sends the SIGTERM of a stop to the step's whole
+        // process group, which would be ignored by a shell running as PID 1
+        env.push("TINI_KILL_PROCESS_GROUP=1".to_string());
+        env.extend(ctx.environment.iter().map(|(k, v)| format!("{}={}", k, v)));
 
         // Add container-specific env if provided
         if let Some(ref opts) = ctx.container_options {
@@ -95,16 +531,32 @@ impl DockerExecutor {
 
         let mut host_config = bollard::service::HostConfig {
             network_mode: Some(self.config.network_mode.clone()),
+            init: Some(true),
             ..Default::default()
         };
 
-        // Add volume mounts
+        // Add volume mounts; of the runner's script and output directories
+        // the container sees only its own job's
         let mut binds = vec![
             format!("{}:/workspace", ctx.working_directory.display()),
+            format!("{}:{}:ro", job_script_dir(&ctx.job_id).display(), CONTAINER_SCRIPT_DIR),
+            format!("{}:{}", job_output_dir(&ctx.job_id).display(), CONTAINER_OUTPUT_DIR),
         ];
+        if let Some(ref temp_dir) = ctx.temp_dir {
+            binds.push(format!("{}:{}", temp_dir.display(), CONTAINER_TEMP_DIR));
+        }
+        if !ctx.gpus.is_empty() {
+            host_config.device_requests = Some(vec![DeviceRequest {
+                driver: Some("nvidia".to_string()),
+                device_ids: Some(ctx.gpus.iter().map(u32::to_string).collect()),
+                capabilities: Some(vec![vec!["gpu".to_string()]]),
+                ..Default::default()
+            }]);
+        }
 
         if let Some(ref opts) = ctx.container_options {
             binds.extend(opts.volumes.clone());
+            binds.extend(opts.cache_volumes.iter().map(CacheVolume::bind));
 
             if let Some(mem) = opts.memory_limit {
                 host_config.memory = Some(mem as i64);
@@ -116,14 +568,26 @@ impl DockerExecutor {
             if let Some(ref network) = opts.network_mode {
                 host_config.network_mode = Some(network.clone());
             }
+            if !opts.devices.is_empty() {
+                host_config.devices = Some(self.device_mappings(&opts.devices)?);
+            }
+            if let Some(shm) = opts.shm_size {
+                host_config.shm_size = Some(shm as i64);
+            }
+            if !opts.sysctls.is_empty() {
+                host_config.sysctls = Some(opts.sysctls.clone());
+            }
         }
 
+        // Runner limits cap whatever the job asked for
         if self.config.memory_limit > 0 {
-            host_config.memory = Some(self.config.memory_limit as i64);
+            let limit = self.config.memory_limit as i64;
+            host_config.memory = Some(host_config.memory.map_or(limit, |m| m.min(limit)));
         }
         if self.config.cpu_limit > 0.0 {
+            let quota = (self.config.cpu_limit * 100000.0) as i64;
             host_config.cpu_period = Some(100000);
-            host_config.cpu_quota = Some((self.config.cpu_limit * 100000.0) as i64);
+            host_config.cpu_quota = Some(host_config.cpu_quota.map_or(quota, |q| q.min(quota)));
         }
 
         host_config.binds = Some(binds);
@@ -131,34 +595,148 @@ impl DockerExecutor {
         // Security options
         host_config.security_opt = Some(vec!["no-new-privileges:true".to_string()]);
 
-        Config {
+        let labels = HashMap::from([
+            (RUNNER_LABEL.to_string(), self.runner_id.clone()),
+            (JOB_LABEL.to_string(), ctx.job_id.clone()),
+        ]);
+
+        Ok(Config {
             image: Some(image),
             env: Some(env),
             working_dir: Some("/workspace".to_string()),
-            cmd: Some(vec![
-                ctx.shell.clone(),
-                "-c".to_string(),
-                ctx.command.clone(),
-            ]),
+            cmd: Some(cmd),
             host_config: Some(host_config),
+            labels: Some(labels),
             ..Default::default()
+        })
+    }
+}
+
+/// How a stopped container ended, from its exit code: Docker's SIGKILL after
+/// the grace period leaves 137
+fn stop_termination(exit_code: Option<i64>) -> Termination {
+    match exit_code {
+        Some(137) | None => Termination::Forced,
+        Some(_) => Termination::Graceful,
+    }
+}
+
+/// Whether the container `name` was left behind by a job that is not running.
+///
+/// Containers without a job label are matched to jobs by name.
+fn is_orphan(name: &str, job: Option<&str>, active: &HashSet<String>) -> bool {
+    if !name.starts_with("muelsyse-") {
+        return false;
+    }
+    match job {
+        Some(job) => !active.contains(job),
+        None => !active.iter().any(|job| name.starts_with(&format!("muelsyse-{}-", job))),
+    }
+}
+
+/// Whether the step runs in its job's shared container
+fn per_job(ctx: &ExecutionContext) -> bool {
+    ctx.container_options.as_ref().is_some_and(|o| o.mode == ContainerMode::PerJob)
+}
+
+/// Create the job's script and output directories, the sources of its
+/// containers' binds
+async fn create_job_dirs(job_id: &str) -> Result<()> {
+    create_private_dir(&job_script_dir(job_id)).await?;
+    tokio::fs::create_dir_all(job_output_dir(job_id))
+        .await
+        .context("Failed to create step output directory")
+}
+
+/// Pull an image, counting cached and downloaded layers
+async fn stream_pull(docker: Docker, image: String) -> Result<LayerCounts> {
+    info!("Pulling image: {}", image);
+
+    let mut stream = docker.create_image(
+        Some(CreateImageOptions {
+            from_image: image.as_str(),
+            ..Default::default()
+        }),
+        None,
+        None,
+    );
+
+    let mut layers = LayerCounts::default();
+    while let Some(result) = stream.next().await {
+        match result {
+            Ok(info) => {
+                if let Some(status) = info.status {
+                    debug!("Pull status: {}", status);
+                    layers.observe(&status);
+                }
+            }
+            Err(e) => {
+                warn!("Pull warning: {}", e);
+            }
         }
     }
+
+    info!(
+        "Successfully pulled image: {} ({} layers cached, {} downloaded)",
+        image, layers.cached, layers.downloaded
+    );
+    Ok(layers)
+}
+
+/// Parse `host[:container[:permissions]]` into a device mapping
+fn parse_device(spec: &str) -> Result<DeviceMapping> {
+    let mut parts = spec.splitn(3, ':');
+    let host = parts.next().unwrap_or_default();
+    if !host.starts_with("/dev/") {
+        anyhow::bail!("Invalid device '{}': host path must be under /dev", spec);
+    }
+    let container = parts.next().filter(|p| !p.is_empty()).unwrap_or(host);
+    let permissions = parts.next().filter(|p| !p.is_empty()).unwrap_or("rwm");
+    if !permissions.chars().all(|c| matches!(c, 'r' | 'w' | 'm')) {
+        anyhow::bail!("Invalid device '{}': permissions must be a combination of r, w and m", spec);
+    }
+
+    Ok(DeviceMapping {
+        path_on_host: Some(host.to_string()),
+        path_in_container: Some(container.to_string()),
+        cgroup_permissions: Some(permissions.to_string()),
+    })
+}
+
+/// Whether `host` matches an allowlist entry (exact, or prefix with trailing `*`)
+fn device_allowed(allowlist: &[String], host: &str) -> bool {
+    allowlist.iter().any(|pattern| match pattern.strip_suffix('*') {
+        Some(prefix) => host.starts_with(prefix),
+        None => host == pattern,
+    })
 }
 
 #[async_trait]
 impl Executor for DockerExecutor {
-    async fn execute(&self, ctx: &ExecutionContext) -> Result<ExecutionResult> {
+    async fn execute(&self, ctx: &ExecutionContext, output: &OutputSink) -> Result<ExecutionResult> {
         let start = Instant::now();
-        let image = ctx.container_image.clone()
-            .ok_or_else(|| anyhow::anyhow!("Container image required for Docker executor"))?;
-
-        // Pull image
-        self.pull_image(&image).await?;
+        if ctx.container_image.is_none() {
+            anyhow::bail!("Container image required for Docker executor");
+        }
+        if per_job(ctx) {
+            return self.execute_in_job_container(ctx, output).await;
+        }
 
-        // Create container
-        let container_name = format!("muelsyse-{}-{}", ctx.job_id, ctx.step_id);
-        let config = self.build_container_config(ctx);
+        // Create container (image is pulled during prepare)
+        let container_name = Self::container_name(&ctx.job_id, &ctx.step_id);
+        let invocation = ShellInvocation::resolve(&ctx.shell, &self.shell)?;
+        let script_name = format!("{}-{}", ctx.job_id, ctx.step_id);
+        let cmd = invocation.command_line(&format!(
+            "{}/{}.{}", CONTAINER_SCRIPT_DIR, script_name, invocation.extension
+        ));
+        let config = self.build_container_config(ctx, cmd)?;
+        create_job_dirs(&ctx.job_id).await?;
+        let script_path = write_script(
+            &job_script_dir(&ctx.job_id),
+            &script_name,
+            invocation.extension,
+            &invocation.script(&ctx.command, &self.shell),
+        ).await?;
 
         debug!("Creating container: {}", container_name);
 
@@ -179,65 +757,87 @@ impl Executor for DockerExecutor {
         ).await.context("Failed to start container")?;
 
         debug!("Container started: {}", container_id);
-
-        // Wait for container with timeout
-        let wait_result = tokio::time::timeout(
-            ctx.timeout,
-            async {
-                let mut stream = self.docker.wait_container(
-                    &container_id,
-                    None::<WaitContainerOptions<String>>,
-                );
-
-                while let Some(result) = stream.next().await {
-                    match result {
-                        Ok(response) => {
-                            return Ok(response.status_code);
-                        }
-                        Err(e) => {
-                            return Err(anyhow::anyhow!("Wait error: {}", e));
-                        }
-                    }
+        let sampler = UsageSampler::container(self.docker.clone(), container_id.clone(), true);
+
+        // Early warning so the step can checkpoint before being killed
+        let warning = ctx.warning_signal_after.map(|after| {
+            let docker = self.docker.clone();
+            let container_id = container_id.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(after).await;
+                debug!("Sending SIGUSR2 to container {}", container_id);
+                let options = KillContainerOptions { signal: "SIGUSR2" };
+                if let Err(e) = docker.kill_container(&container_id, Some(options)).await {
+                    warn!("Failed to signal container {}: {}", container_id, e);
                 }
+            })
+        });
+
+        // Follow logs while waiting, so output is forwarded as it is written
+        let merged = MergedOutput::new(output);
+        let mut stdout = LineForwarder::new(OutputStream::Stdout, ctx.output_encoding, merged.sink());
+        let mut stderr = LineForwarder::new(OutputStream::Stderr, ctx.output_encoding, merged.sink());
+        let (wait_result, termination) = {
+            let mut logs = std::pin::pin!(self.follow_logs(&container_id, &mut stdout, &mut stderr).fuse());
+            let waited = tokio::time::timeout(
+                ctx.timeout,
+                async {
+                    let wait = async {
+                        let mut stream = self.docker.wait_container(
+                            &container_id,
+                            None::<WaitContainerOptions<String>>,
+                        );
+
+                        while let Some(result) = stream.next().await {
+                            match result {
+                                Ok(response) => {
+                                    return Ok(response.status_code);
+                                }
+                                Err(e) => {
+                                    return Err(anyhow::anyhow!("Wait error: {}", e));
+                                }
+                            }
+                        }
 
-                Err(anyhow::anyhow!("Container wait stream ended unexpectedly"))
-            }
-        ).await;
-
-        // Get logs
-        let mut stdout = String::new();
-        let mut stderr = String::new();
+                        Err(anyhow::anyhow!("Container wait stream ended unexpectedly"))
+                    };
 
-        let mut log_stream = self.docker.logs(
-            &container_id,
-            Some(LogsOptions::<String> {
-                stdout: true,
-                stderr: true,
-                ..Default::default()
-            }),
-        );
-
-        while let Some(result) = log_stream.next().await {
-            match result {
-                Ok(output) => {
-                    match output {
-                        bollard::container::LogOutput::StdOut { message } => {
-                            stdout.push_str(&String::from_utf8_lossy(&message));
-                        }
-                        bollard::container::LogOutput::StdErr { message } => {
-                            stderr.push_str(&String::from_utf8_lossy(&message));
-                        }
-                        _ => {}
-                    }
+                    let ((), status) = tokio::join!(&mut logs, wait);
+                    status
                 }
-                Err(e) => {
-                    warn!("Log stream error: {}", e);
-                    break;
+            );
+            let wait_result = tokio::select! {
+                waited = waited => Some(waited),
+                _ = ctx.cancel.cancelled() => None,
+            };
+
+            // A timed out container gets SIGTERM first; what it writes until it
+            // stops is still forwarded
+            let mut termination = None;
+            if let Some(Err(_)) = wait_result {
+                warn!("Container execution timed out, stopping container {}", container_id);
+                termination = Some(self.stop_gracefully(&container_id).await);
+                if !logs.is_terminated() {
+                    let _ = tokio::time::timeout(OUTPUT_DRAIN_TIMEOUT, &mut logs).await;
                 }
             }
+            (wait_result, termination)
+        };
+
+        if let Some(warning) = warning {
+            warning.abort();
         }
+        let usage = sampler.stop();
+
+        let stdout = ctx.output_encoding.decode(&stdout.finish());
+        let stderr = ctx.output_encoding.decode(&stderr.finish());
+        let merged = merged.finish().await;
 
-        // Remove container
+        // Remove container and its script; a cancelled step's container is
+        // killed by the forced removal
+        if let Err(e) = tokio::fs::remove_file(&script_path).await {
+            warn!("Failed to remove script {:?}: {}", script_path, e);
+        }
         let _ = self.docker.remove_container(
             &container_id,
             Some(RemoveContainerOptions {
@@ -246,30 +846,38 @@ impl Executor for DockerExecutor {
             }),
         ).await;
 
+        let Some(wait_result) = wait_result else {
+            info!("Step {} cancelled, removed container {}", ctx.step_id, container_id);
+            anyhow::bail!(RunnerError::Cancelled("Step cancelled".into()));
+        };
         match wait_result {
             Ok(Ok(exit_code)) => {
                 Ok(ExecutionResult {
                     exit_code: exit_code as i32,
                     stdout,
                     stderr,
+                    output: merged,
                     duration: start.elapsed(),
                     timed_out: false,
+                    write_audit: None,
+                    usage: Some(usage),
+                    limit_exceeded: None,
+                    termination: None,
                 })
             }
             Ok(Err(e)) => Err(e),
             Err(_) => {
-                // Timeout
-                warn!("Container execution timed out");
-
-                // Force stop container
-                let _ = self.docker.stop_container(&container_id, None).await;
-
                 Ok(ExecutionResult {
                     exit_code: -1,
                     stdout,
                     stderr: "Container execution timed out".to_string(),
+                    output: merged,
                     duration: start.elapsed(),
                     timed_out: true,
+                    write_audit: None,
+                    usage: Some(usage),
+                    limit_exceeded: None,
+                    termination,
                 })
             }
         }
@@ -281,11 +889,45 @@ impl Executor for DockerExecutor {
             .await
             .context("Failed to create working directory")?;
 
+        // Pull image up front so it is billed to the prepare phase budget
+        if let Some(ref image) = ctx.container_image {
+            self.pull_image(image).await?;
+        }
+        if let Some(ref opts) = ctx.container_options {
+            self.ensure_cache_volumes(&opts.cache_volumes).await?;
+        }
+
+        if per_job(ctx) {
+            self.ensure_job_container(ctx).await?;
+        }
+
         Ok(())
     }
 
     async fn cleanup(&self, _ctx: &ExecutionContext) -> Result<()> {
-        // Container is already removed after execution
+        // Step containers are already removed after execution
+        Ok(())
+    }
+
+    fn stop_allowance(&self) -> Duration {
+        Duration::from_secs(self.config.stop_grace_secs) + OUTPUT_DRAIN_TIMEOUT
+    }
+
+    async fn finish_job(&self, job_id: &str) -> Result<()> {
+        let name = Self::job_container_name(job_id);
+        let removed = self.docker.remove_container(
+            &name,
+            Some(RemoveContainerOptions {
+                force: true,
+                ..Default::default()
+            }),
+        ).await;
+
+        match removed {
+            Ok(()) => info!("Removed job container {}", name),
+            Err(bollard::errors::Error::DockerResponseServerError { status_code: 404, .. }) => {}
+            Err(e) => return Err(e).context("Failed to remove job container"),
+        }
         Ok(())
     }
 
@@ -298,3 +940,51 @@ impl Executor for DockerExecutor {
         ExecutorType::Docker
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_device() {
+        let mapping = parse_device("/dev/ttyUSB0").unwrap();
+        assert_eq!(mapping.path_in_container.as_deref(), Some("/dev/ttyUSB0"));
+        assert_eq!(mapping.cgroup_permissions.as_deref(), Some("rwm"));
+
+        let mapping = parse_device("/dev/ttyUSB1:/dev/ttyACM0:rw").unwrap();
+        assert_eq!(mapping.path_on_host.as_deref(), Some("/dev/ttyUSB1"));
+        assert_eq!(mapping.path_in_container.as_deref(), Some("/dev/ttyACM0"));
+        assert_eq!(mapping.cgroup_permissions.as_deref(), Some("rw"));
+
+        assert!(parse_device("/etc/shadow").is_err());
+        assert!(parse_device("/dev/kvm:/dev/kvm:rwx").is_err());
+    }
+
+    #[test]
+    fn test_device_allowed() {
+        let allowlist = vec!["/dev/kvm".to_string(), "/dev/ttyUSB*".to_string()];
+        assert!(device_allowed(&allowlist, "/dev/kvm"));
+        assert!(device_allowed(&allowlist, "/dev/ttyUSB3"));
+        assert!(!device_allowed(&allowlist, "/dev/kvm2"));
+        assert!(!device_allowed(&allowlist, "/dev/sda"));
+        assert!(!device_allowed(&[], "/dev/kvm"));
+    }
+
+    #[test]
+    fn test_stop_termination() {
+        assert_eq!(stop_termination(Some(143)), Termination::Graceful);
+        assert_eq!(stop_termination(Some(0)), Termination::Graceful);
+        assert_eq!(stop_termination(Some(137)), Termination::Forced);
+        assert_eq!(stop_termination(None), Termination::Forced);
+    }
+
+    #[test]
+    fn test_is_orphan() {
+        let active = HashSet::from(["job-1".to_string()]);
+        assert!(!is_orphan("muelsyse-job-1-build", Some("job-1"), &active));
+        assert!(is_orphan("muelsyse-job-2-job", Some("job-2"), &active));
+        assert!(!is_orphan("muelsyse-job-1-job", None, &active));
+        assert!(is_orphan("muelsyse-job-10-job", None, &active));
+        assert!(!is_orphan("postgres", Some("job-2"), &active));
+    }
+}
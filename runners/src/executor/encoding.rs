@@ -0,0 +1,166 @@
+//! Step output decoding
+//!
+//! Some toolchains (notably on Windows) write output in a legacy code page
+//! or UTF-16. Output is captured as bytes and decoded with the configured
+//! encoding before it reaches the log stream.
+
+use encoding_rs::{Decoder, Encoding, GBK, UTF_16LE, UTF_8};
+use serde::{Deserialize, Serialize};
+
+/// Encoding of a step's stdout/stderr
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum OutputEncoding {
+    #[default]
+    #[serde(rename = "utf-8", alias = "utf8")]
+    Utf8,
+    #[serde(rename = "utf-16le", alias = "utf16le")]
+    Utf16Le,
+    #[serde(rename = "gbk")]
+    Gbk,
+    /// Detect from BOM and content, falling back to lossy UTF-8
+    #[serde(rename = "auto")]
+    Auto,
+}
+
+impl OutputEncoding {
+    /// Decode captured output, replacing invalid sequences
+    pub fn decode(&self, bytes: &[u8]) -> String {
+        let encoding = match self {
+            Self::Utf8 => UTF_8,
+            Self::Utf16Le => UTF_16LE,
+            Self::Gbk => GBK,
+            Self::Auto => detect(bytes),
+        };
+        // A BOM, if present, overrides the configured encoding
+        let (text, _, _) = encoding.decode(bytes);
+        text.into_owned()
+    }
+}
+
+/// Incrementally decodes output chunks into complete lines.
+///
+/// Multi-byte sequences split across chunks are carried over. `Auto`
+/// detects the encoding from the first chunk.
+pub struct LineDecoder {
+    encoding: OutputEncoding,
+    decoder: Option<Decoder>,
+    pending: String,
+}
+
+impl LineDecoder {
+    pub fn new(encoding: OutputEncoding) -> Self {
+        Self {
+            encoding,
+            decoder: None,
+            pending: String::new(),
+        }
+    }
+
+    /// Decode a chunk, returning the lines it completes
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<String> {
+        let encoding = self.encoding;
+        let decoder = self.decoder.get_or_insert_with(|| match encoding {
+            OutputEncoding::Utf8 => UTF_8,
+            OutputEncoding::Utf16Le => UTF_16LE,
+            OutputEncoding::Gbk => GBK,
+            OutputEncoding::Auto => detect(bytes),
+        }.new_decoder());
+        decode_into(decoder, bytes, false, &mut self.pending);
+
+        let mut lines = Vec::new();
+        while let Some(end) = self.pending.find('\n') {
+            let mut line: String = self.pending.drain(..=end).collect();
+            line.pop();
+            if line.ends_with('\r') {
+                line.pop();
+            }
+            lines.push(line);
+        }
+        lines
+    }
+
+    /// Decode whatever is left, returning a final unterminated line
+    pub fn finish(&mut self) -> Option<String> {
+        if let Some(mut decoder) = self.decoder.take() {
+            decode_into(&mut decoder, &[], true, &mut self.pending);
+        }
+        let line = std::mem::take(&mut self.pending);
+        (!line.is_empty()).then_some(line)
+    }
+}
+
+fn decode_into(decoder: &mut Decoder, bytes: &[u8], last: bool, out: &mut String) {
+    if let Some(needed) = decoder.max_utf8_buffer_length(bytes.len()) {
+        out.reserve(needed);
+    }
+    let _ = decoder.decode_to_string(bytes, out, last);
+}
+
+fn detect(bytes: &[u8]) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+        return encoding;
+    }
+    // Checked first: ASCII-range UTF-16LE is also valid UTF-8
+    if looks_like_utf16le(bytes) {
+        return UTF_16LE;
+    }
+    if std::str::from_utf8(bytes).is_ok() {
+        return UTF_8;
+    }
+    if GBK.decode_without_bom_handling_and_without_replacement(bytes).is_some() {
+        return GBK;
+    }
+    UTF_8
+}
+
+/// Mostly-ASCII UTF-16LE text has a zero in most odd byte positions
+fn looks_like_utf16le(bytes: &[u8]) -> bool {
+    let units = bytes.chunks_exact(2);
+    if bytes.is_empty() || !units.remainder().is_empty() {
+        return false;
+    }
+    let total = units.len();
+    let zeros = units.filter(|unit| unit[1] == 0).count();
+    zeros * 2 >= total
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let utf16: Vec<u8> = "build ok\n".encode_utf16().flat_map(|u| u.to_le_bytes()).collect();
+        assert_eq!(OutputEncoding::Utf16Le.decode(&utf16), "build ok\n");
+        assert_eq!(OutputEncoding::Auto.decode(&utf16), "build ok\n");
+
+        // "编译成功" in GBK
+        let gbk = [0xb1, 0xe0, 0xd2, 0xeb, 0xb3, 0xc9, 0xb9, 0xa6];
+        assert_eq!(OutputEncoding::Gbk.decode(&gbk), "编译成功");
+        assert_eq!(OutputEncoding::Auto.decode(&gbk), "编译成功");
+        assert!(OutputEncoding::Utf8.decode(&gbk).contains('\u{fffd}'));
+
+        assert_eq!(OutputEncoding::Auto.decode("héllo".as_bytes()), "héllo");
+
+        let encoding: OutputEncoding = serde_json::from_str("\"utf-16le\"").unwrap();
+        assert_eq!(encoding, OutputEncoding::Utf16Le);
+    }
+
+    #[test]
+    fn test_line_decoder() {
+        let mut decoder = LineDecoder::new(OutputEncoding::Utf8);
+        // "é" split across chunks
+        assert!(decoder.push(b"caf\xc3").is_empty());
+        assert_eq!(decoder.push(b"\xa9\r\nstep 2\nstep"), vec!["café", "step 2"]);
+        assert_eq!(decoder.finish().as_deref(), Some("step"));
+        assert_eq!(decoder.finish(), None);
+
+        let utf16: Vec<u8> = "one\ntwo\n".encode_utf16().flat_map(|u| u.to_le_bytes()).collect();
+        let mut decoder = LineDecoder::new(OutputEncoding::Utf16Le);
+        let (head, tail) = utf16.split_at(5);
+        let mut lines = decoder.push(head);
+        lines.extend(decoder.push(tail));
+        assert_eq!(lines, vec!["one", "two"]);
+        assert_eq!(decoder.finish(), None);
+    }
+}
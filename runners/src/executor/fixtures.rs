@@ -0,0 +1,29 @@
+//! Execution contexts for tests
+
+use std::path::Path;
+use std::time::Duration;
+
+use super::{ExecutionContext, OutputEncoding};
+
+/// A `sh` step of `job-1` running `command` in `workspace`, with a fresh
+/// step id, no container and no limits
+pub fn step_context(workspace: &Path, command: &str, timeout: Duration) -> ExecutionContext {
+    ExecutionContext {
+        job_id: "job-1".into(),
+        step_id: uuid::Uuid::new_v4().to_string(),
+        command: command.into(),
+        shell: "sh".into(),
+        working_directory: workspace.to_path_buf(),
+        workspace: workspace.to_path_buf(),
+        environment: Default::default(),
+        timeout,
+        container_image: None,
+        container_options: None,
+        output_encoding: OutputEncoding::Utf8,
+        warning_signal_after: None,
+        cancel: Default::default(),
+        limits: Default::default(),
+        temp_dir: None,
+        gpus: Vec::new(),
+    }
+}
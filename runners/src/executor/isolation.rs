@@ -0,0 +1,140 @@
+//! Per-job user accounts for host steps
+//!
+//! With `executor.shell.job_uid_range`, every job runs as a UID of its own
+//! from that range, with the same number as its GID. The job's workspace and
+//! its script and output directories are handed to that user and closed to
+//! everyone else, and its steps start with a minimal environment,
+//! so jobs can read neither each other's files nor the runner's credentials.
+//! UIDs are returned, and their leftover processes killed, when the job
+//! finishes.
+
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::{debug, warn};
+
+use super::script::{job_output_dir, job_script_dir};
+use crate::error::RunnerError;
+
+/// Variables of the runner's environment isolated steps keep
+pub const INHERITED_ENV: [&str; 4] = ["PATH", "LANG", "LC_ALL", "TZ"];
+
+/// UIDs of running jobs, by job id
+static JOB_USERS: Mutex<BTreeMap<String, u32>> = Mutex::new(BTreeMap::new());
+
+/// The UID of `job_id`, taking the lowest free one in `range` on first use
+pub fn allocate(job_id: &str, range: [u32; 2]) -> Result<u32> {
+    let [first, last] = range;
+    if first == 0 || first > last {
+        anyhow::bail!(RunnerError::ConfigError(format!(
+            "Invalid job_uid_range [{}, {}]: must be ascending and exclude root", first, last
+        )));
+    }
+
+    let mut users = JOB_USERS.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(&uid) = users.get(job_id) {
+        return Ok(uid);
+    }
+    let Some(uid) = (first..=last).find(|uid| !users.values().any(|used| used == uid)) else {
+        anyhow::bail!(RunnerError::InfraError(format!(
+            "All {} job users in job_uid_range are in use", last - first + 1
+        )));
+    };
+    users.insert(job_id.to_string(), uid);
+    debug!("Job {} runs as uid {}", job_id, uid);
+    Ok(uid)
+}
+
+/// Give back the UID of `job_id`, if it had one
+pub fn release(job_id: &str) -> Option<u32> {
+    JOB_USERS.lock().unwrap_or_else(|e| e.into_inner()).remove(job_id)
+}
+
+/// Hand `path` and everything under it to `uid`, closed to other users
+#[cfg(unix)]
+pub async fn give_to(path: PathBuf, uid: u32) -> Result<()> {
+    tokio::task::spawn_blocking(move || {
+        give_tree(&path, uid).with_context(|| {
+            format!("Failed to give {:?} to job user {} (job users need the runner to run as root)", path, uid)
+        })
+    })
+    .await?
+}
+
+/// Hand the job's scripts, outputs and environment files to `uid`
+#[cfg(unix)]
+pub async fn give_job_files(job_id: &str, uid: u32) -> Result<()> {
+    for path in [job_script_dir(job_id), job_output_dir(job_id)] {
+        if path.exists() {
+            give_to(path, uid).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Kill whatever processes `uid` left running
+#[cfg(unix)]
+pub async fn kill_processes(uid: u32) {
+    let killed = tokio::process::Command::new("pkill")
+        .arg("-KILL")
+        .arg("-U")
+        .arg(uid.to_string())
+        .status()
+        .await;
+    match killed {
+        // 1: no process matched
+        Ok(status) if status.success() || status.code() == Some(1) => {}
+        Ok(status) => warn!("pkill for job user {} exited with {}", uid, status),
+        Err(e) => warn!("Failed to kill processes of job user {}: {}", uid, e),
+    }
+}
+
+#[cfg(unix)]
+fn give_tree(path: &Path, uid: u32) -> std::io::Result<()> {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    let metadata = std::fs::symlink_metadata(path)?;
+    if metadata.uid() != uid || metadata.gid() != uid {
+        std::os::unix::fs::lchown(path, Some(uid), Some(uid))?;
+    }
+    if metadata.is_symlink() {
+        return Ok(());
+    }
+
+    let mode = metadata.permissions().mode();
+    let private = mode & 0o7700;
+    if mode & 0o7777 != private {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(private))?;
+    }
+    if metadata.is_dir() {
+        for entry in std::fs::read_dir(path)? {
+            give_tree(&entry?.path(), uid)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allocate() {
+        let range = [60000, 60001];
+        assert_eq!(allocate("uid-job-1", range).unwrap(), 60000);
+        assert_eq!(allocate("uid-job-1", range).unwrap(), 60000);
+        assert_eq!(allocate("uid-job-2", range).unwrap(), 60001);
+
+        let exhausted = allocate("uid-job-3", range).unwrap_err();
+        assert!(matches!(RunnerError::classify(&exhausted), RunnerError::InfraError(_)));
+
+        assert_eq!(release("uid-job-1"), Some(60000));
+        assert_eq!(allocate("uid-job-3", range).unwrap(), 60000);
+        release("uid-job-2");
+        release("uid-job-3");
+
+        let invalid = allocate("uid-job-4", [0, 10]).unwrap_err();
+        assert!(matches!(RunnerError::classify(&invalid), RunnerError::ConfigError(_)));
+    }
+}
@@ -0,0 +1,518 @@
+//! Kubernetes executor - runs job steps inside a pod
+//!
+//! One pod is created per job during the first step's prepare phase. Each
+//! step is exec'd into the pod's `job` container, so the `/workspace`
+//! emptyDir volume is shared between steps like a host workspace. The pod
+//! is deleted when the job finishes.
+//!
+//! Exec commands travel in the request URL, where API server audit logs
+//! record them, so scripts and environments are sent over stdin instead.
+
+use async_trait::async_trait;
+use anyhow::{Context, Result};
+use k8s_openapi::api::core::v1::Pod;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::Status;
+use kube::api::{AttachParams, DeleteParams, PostParams};
+use kube::config::{KubeConfigOptions, Kubeconfig};
+use kube::{Api, Client};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::sync::OnceCell;
+use tracing::{debug, info, warn};
+
+use super::encoding::OutputEncoding;
+use super::output::{forward_output, LineForwarder, MergedOutput, OutputSink, OutputStream};
+use super::script::{job_script_dir, ShellInvocation, CONTAINER_SCRIPT_DIR};
+use super::traits::{Executor, ExecutorType, ExecutionContext, ExecutionResult, Termination};
+use crate::config::{KubernetesConfig, ShellConfig};
+use crate::error::RunnerError;
+
+/// Name of the container steps run in
+const JOB_CONTAINER: &str = "job";
+
+/// Time to reach the pod and signal a timed out or cancelled step, on top
+/// of the kill grace period
+const STOP_EXEC_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Writes stdin to `$1`: exactly `$2` bytes, as exec stdin is never closed
+const WRITE_FILE_SCRIPT: &str = "umask 077 && mkdir -p \"$(dirname \"$1\")\" && head -c \"$2\" > \"$1\"";
+
+/// Records the step's PID in `$1`, exports and removes the environment file
+/// `$2`, then runs the step command
+const STEP_SCRIPT: &str = "echo $$ > \"$1\" && set -a && . \"$2\" && set +a && rm -f \"$2\" && shift 2 && exec \"$@\"";
+
+/// Sends SIGTERM to the step whose PID is in `$1` (its process group if it
+/// leads one), and SIGKILL if it is still running after `$2` seconds
+const STOP_SCRIPT: &str = r#"pid=$(cat "$1" 2>/dev/null) || exit 0
+kill -TERM -"$pid" 2>/dev/null || kill -TERM "$pid" 2>/dev/null
+i=0
+while [ "$i" -lt "$2" ] && kill -0 "$pid" 2>/dev/null; do sleep 1; i=$((i + 1)); done
+if kill -0 "$pid" 2>/dev/null; then
+  kill -KILL -"$pid" 2>/dev/null || kill -KILL "$pid" 2>/dev/null
+  echo forced
+fi
+rm -f "$1""#;
+
+/// Kubernetes executor that runs each job in its own pod
+pub struct KubernetesExecutor {
+    config: KubernetesConfig,
+    shell: ShellConfig,
+    pods: OnceCell<Api<Pod>>,
+}
+
+impl KubernetesExecutor {
+    pub fn new(config: KubernetesConfig, shell: ShellConfig) -> Self {
+        Self {
+            config,
+            shell,
+            pods: OnceCell::new(),
+        }
+    }
+
+    /// Pod API for the configured namespace, connecting on first use
+    async fn pods(&self) -> Result<&Api<Pod>> {
+        self.pods.get_or_try_init(|| async {
+            let config = match self.config.kubeconfig {
+                Some(ref path) => {
+                    let kubeconfig = Kubeconfig::read_from(path)
+                        .with_context(|| format!("Failed to read kubeconfig {:?}", path))?;
+                    kube::Config::from_custom_kubeconfig(kubeconfig, &KubeConfigOptions::default()).await?
+                }
+                None => kube::Config::infer().await?,
+            };
+            let client = Client::try_from(config).context("Failed to create Kubernetes client")?;
+            Ok(Api::namespaced(client, &self.config.namespace))
+        }).await
+    }
+
+    /// Name of the pod running a job
+    pub fn pod_name(job_id: &str) -> String {
+        let name: String = format!("muelsyse-{}", job_id)
+            .to_lowercase()
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '-' })
+            .take(63)
+            .collect();
+        name.trim_end_matches('-').to_string()
+    }
+
+    fn build_pod(&self, ctx: &ExecutionContext) -> Result<Pod> {
+        let image = ctx.container_image.clone()
+            .unwrap_or_else(|| self.config.default_image.clone());
+
+        let mut limits = serde_json::Map::new();
+        let mut requests = serde_json::Map::new();
+        if let Some(ref cpu) = self.config.cpu_limit {
+            limits.insert("cpu".into(), cpu.clone().into());
+        }
+        if let Some(ref memory) = self.config.memory_limit {
+            limits.insert("memory".into(), memory.clone().into());
+        }
+        if let Some(ref cpu) = self.config.cpu_request {
+            requests.insert("cpu".into(), cpu.clone().into());
+        }
+        if let Some(ref memory) = self.config.memory_request {
+            requests.insert("memory".into(), memory.clone().into());
+        }
+
+        let pod = serde_json::json!({
+            "apiVersion": "v1",
+            "kind": "Pod",
+            "metadata": {
+                "name": Self::pod_name(&ctx.job_id),
+                "labels": {
+                    "app.kubernetes.io/managed-by": "muelsyse-runner",
+                    "muelsyse.io/job-id": Self::pod_name(&ctx.job_id),
+                },
+            },
+            "spec": {
+                "restartPolicy": "Never",
+                "serviceAccountName": self.config.service_account,
+                "containers": [{
+                    "name": JOB_CONTAINER,
+                    "image": image,
+                    // Keep the pod alive; steps are exec'd into it
+                    "command": ["sh", "-c", "trap 'exit 0' TERM INT; while :; do sleep 3600 & wait $!; done"],
+                    "workingDir": "/workspace",
+                    "resources": { "limits": limits, "requests": requests },
+                    "volumeMounts": [
+                        { "name": "workspace", "mountPath": "/workspace" },
+                        { "name": "scripts", "mountPath": CONTAINER_SCRIPT_DIR },
+                    ],
+                }],
+                "volumes": [
+                    { "name": "workspace", "emptyDir": {} },
+                    { "name": "scripts", "emptyDir": {} },
+                ],
+            },
+        });
+
+        serde_json::from_value(pod).context("Failed to build pod spec")
+    }
+
+    /// Wait until the job pod is running
+    async fn wait_running(&self, pods: &Api<Pod>, name: &str) -> Result<()> {
+        loop {
+            let pod = pods.get(name).await.context("Failed to get job pod")?;
+            let phase = pod.status.and_then(|s| s.phase).unwrap_or_default();
+
+            match phase.as_str() {
+                "Running" => return Ok(()),
+                "Failed" | "Succeeded" => anyhow::bail!("Job pod {} ended in phase {}", name, phase),
+                _ => {
+                    debug!("Waiting for pod {} (phase: {})", name, phase);
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        }
+    }
+
+    /// Run `argv` in the job container, returning exit code, stdout and stderr
+    async fn exec(
+        &self,
+        pods: &Api<Pod>,
+        pod: &str,
+        argv: Vec<String>,
+        encoding: OutputEncoding,
+        output: Option<&OutputSink>,
+    ) -> Result<(i32, String, String)> {
+        self.exec_with_input(pods, pod, argv, &[], encoding, output).await
+    }
+
+    /// Like [`Self::exec`], writing `input` to the command's stdin first
+    async fn exec_with_input(
+        &self,
+        pods: &Api<Pod>,
+        pod: &str,
+        argv: Vec<String>,
+        input: &[u8],
+        encoding: OutputEncoding,
+        output: Option<&OutputSink>,
+    ) -> Result<(i32, String, String)> {
+        let params = AttachParams::default()
+            .container(JOB_CONTAINER)
+            .stdin(!input.is_empty())
+            .stdout(true)
+            .stderr(true);
+
+        let mut attached = pods.exec(pod, argv, &params).await.context("Failed to exec in job pod")?;
+        if let Some(mut stdin) = attached.stdin() {
+            stdin.write_all(input).await.context("Failed to write to job pod")?;
+            stdin.flush().await?;
+        }
+        let stdout = attached.stdout();
+        let stderr = attached.stderr();
+        let status = attached.take_status();
+
+        let (stdout, stderr) = tokio::join!(
+            read_all(stdout, OutputStream::Stdout, encoding, output),
+            read_all(stderr, OutputStream::Stderr, encoding, output),
+        );
+        let status = match status {
+            Some(status) => status.await,
+            None => None,
+        };
+
+        Ok((exit_code(status.as_ref()), encoding.decode(&stdout), encoding.decode(&stderr)))
+    }
+
+    /// Copy indirected environment files the step refers to into the pod
+    async fn upload_env_files(&self, pods: &Api<Pod>, pod: &str, ctx: &ExecutionContext) -> Result<()> {
+        for (key, value) in &ctx.environment {
+            let Some(relative) = key.ends_with("_FILE")
+                .then(|| value.strip_prefix(CONTAINER_SCRIPT_DIR))
+                .flatten()
+            else {
+                continue;
+            };
+
+            let host_path = job_script_dir(&ctx.job_id).join(relative.trim_start_matches('/'));
+            let Ok(contents) = tokio::fs::read_to_string(&host_path).await else {
+                continue;
+            };
+            self.write_file(pods, pod, value, &contents).await?;
+        }
+        Ok(())
+    }
+
+    /// Write `contents` to `path` in the job container, readable only by
+    /// its user
+    async fn write_file(&self, pods: &Api<Pod>, pod: &str, path: &str, contents: &str) -> Result<()> {
+        let argv = vec![
+            "sh".to_string(),
+            "-c".to_string(),
+            WRITE_FILE_SCRIPT.to_string(),
+            "sh".to_string(),
+            path.to_string(),
+            contents.len().to_string(),
+        ];
+        let (code, _, stderr) = self
+            .exec_with_input(pods, pod, argv, contents.as_bytes(), OutputEncoding::Utf8, None)
+            .await?;
+        if code != 0 {
+            anyhow::bail!("Failed to write {} in job pod: {}", path, stderr.trim());
+        }
+        Ok(())
+    }
+
+    /// Stop the step whose PID is in `pid_file`, which keeps running in the
+    /// pod after its exec connection is dropped
+    async fn stop_step(&self, pods: &Api<Pod>, pod: &str, pid_file: &str) -> Termination {
+        let argv = vec![
+            "sh".to_string(),
+            "-c".to_string(),
+            STOP_SCRIPT.to_string(),
+            "sh".to_string(),
+            pid_file.to_string(),
+            self.shell.kill_grace_secs.to_string(),
+        ];
+        let limit = Duration::from_secs(self.shell.kill_grace_secs) + STOP_EXEC_TIMEOUT;
+        match tokio::time::timeout(limit, self.exec(pods, pod, argv, OutputEncoding::Utf8, None)).await {
+            Ok(Ok((_, stdout, _))) if stdout.trim().is_empty() => Termination::Graceful,
+            Ok(Ok(_)) => Termination::Forced,
+            Ok(Err(e)) => {
+                warn!("Failed to stop step in pod {}: {}", pod, e);
+                Termination::Forced
+            }
+            Err(_) => {
+                warn!("Timed out stopping step in pod {}", pod);
+                Termination::Forced
+            }
+        }
+    }
+}
+
+/// `environment` as a file for `set -a; .`, skipping names the shell cannot
+/// export
+fn env_file(environment: &std::collections::HashMap<String, String>) -> String {
+    let mut lines: Vec<String> = environment
+        .iter()
+        .filter(|(key, _)| is_shell_name(key))
+        .map(|(key, value)| format!("{}='{}'\n", key, value.replace('\'', "'\\''")))
+        .collect();
+    lines.sort();
+    lines.concat()
+}
+
+fn is_shell_name(key: &str) -> bool {
+    let mut chars = key.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Read an exec stream, forwarding its lines to `output` if given
+async fn read_all(
+    reader: Option<impl AsyncRead + Unpin>,
+    stream: OutputStream,
+    encoding: OutputEncoding,
+    output: Option<&OutputSink>,
+) -> Vec<u8> {
+    let Some(mut reader) = reader else {
+        return Vec::new();
+    };
+    if let Some(output) = output {
+        return forward_output(reader, LineForwarder::new(stream, encoding, output)).await;
+    }
+
+    let mut buf = Vec::new();
+    if let Err(e) = reader.read_to_end(&mut buf).await {
+        warn!("Error reading exec output: {}", e);
+    }
+    buf
+}
+
+/// Exit code from an exec status (`Success`, or a `NonZeroExitCode` cause)
+fn exit_code(status: Option<&Status>) -> i32 {
+    let Some(status) = status else {
+        return -1;
+    };
+    if status.status.as_deref() == Some("Success") {
+        return 0;
+    }
+
+    status.details
+        .as_ref()
+        .and_then(|d| d.causes.as_ref())
+        .and_then(|causes| causes.iter().find(|c| c.reason.as_deref() == Some("ExitCode")))
+        .and_then(|c| c.message.as_deref())
+        .and_then(|m| m.parse().ok())
+        .unwrap_or(-1)
+}
+
+#[async_trait]
+impl Executor for KubernetesExecutor {
+    async fn execute(&self, ctx: &ExecutionContext, output: &OutputSink) -> Result<ExecutionResult> {
+        let start = Instant::now();
+        let pods = self.pods().await?;
+        let pod = Self::pod_name(&ctx.job_id);
+
+        self.upload_env_files(pods, &pod, ctx).await?;
+
+        let invocation = ShellInvocation::resolve(&ctx.shell, &self.shell)?;
+        let script_path = format!(
+            "{}/{}-{}.{}", CONTAINER_SCRIPT_DIR, ctx.job_id, ctx.step_id, invocation.extension
+        );
+        self.write_file(pods, &pod, &script_path, &invocation.script(&ctx.command, &self.shell)).await?;
+
+        // exec has no environment of its own; it is written to a file the
+        // step's shell exports and removes before running the script
+        let step_file = format!("{}/{}-{}", CONTAINER_SCRIPT_DIR, ctx.job_id, ctx.step_id);
+        let (env_path, pid_file) = (format!("{}.env", step_file), format!("{}.pid", step_file));
+        let skipped: Vec<&str> = ctx.environment.keys().map(String::as_str).filter(|k| !is_shell_name(k)).collect();
+        if !skipped.is_empty() {
+            warn!("Step {}: variables not exported in pod: {}", ctx.step_id, skipped.join(", "));
+        }
+        self.write_file(pods, &pod, &env_path, &env_file(&ctx.environment)).await?;
+        let mut argv = vec![
+            "sh".to_string(),
+            "-c".to_string(),
+            STEP_SCRIPT.to_string(),
+            "sh".to_string(),
+            pid_file.clone(),
+            env_path,
+        ];
+        argv.extend(invocation.command_line(&script_path));
+
+        debug!("Executing step {} in pod {}", ctx.step_id, pod);
+
+        let merged = MergedOutput::new(output);
+        let executed = tokio::select! {
+            executed = tokio::time::timeout(
+                ctx.timeout,
+                self.exec(pods, &pod, argv, ctx.output_encoding, Some(merged.sink())),
+            ) => executed,
+            // Dropping the exec connection leaves the step running in the pod
+            _ = ctx.cancel.cancelled() => {
+                info!("Step {} cancelled in pod {}", ctx.step_id, pod);
+                self.stop_step(pods, &pod, &pid_file).await;
+                anyhow::bail!(RunnerError::Cancelled("Step cancelled".into()));
+            }
+        };
+        match executed {
+            Ok(Ok((exit_code, stdout, stderr))) => Ok(ExecutionResult {
+                exit_code,
+                stdout,
+                stderr,
+                output: merged.finish().await,
+                duration: start.elapsed(),
+                timed_out: false,
+                write_audit: None,
+                usage: None,
+                limit_exceeded: None,
+                termination: None,
+            }),
+            Ok(Err(e)) => Err(e),
+            Err(_) => {
+                warn!("Step {} timed out in pod {}", ctx.step_id, pod);
+                let termination = self.stop_step(pods, &pod, &pid_file).await;
+                Ok(ExecutionResult {
+                    exit_code: -1,
+                    stdout: String::new(),
+                    stderr: "Command timed out".to_string(),
+                    output: merged.finish().await,
+                    duration: start.elapsed(),
+                    timed_out: true,
+                    write_audit: None,
+                    usage: None,
+                    limit_exceeded: None,
+                    termination: Some(termination),
+                })
+            }
+        }
+    }
+
+    async fn prepare(&self, ctx: &ExecutionContext) -> Result<()> {
+        if ctx.container_options.as_ref().is_some_and(|o| !o.devices.is_empty()) {
+            anyhow::bail!("Device passthrough is not supported by the Kubernetes executor");
+        }
+
+        let pods = self.pods().await?;
+        let name = Self::pod_name(&ctx.job_id);
+
+        if pods.get_opt(&name).await?.is_none() {
+            info!("Creating pod {} in namespace {}", name, self.config.namespace);
+            let pod = self.build_pod(ctx)?;
+            pods.create(&PostParams::default(), &pod)
+                .await
+                .context("Failed to create job pod")?;
+        }
+
+        self.wait_running(pods, &name).await
+    }
+
+    async fn cleanup(&self, _ctx: &ExecutionContext) -> Result<()> {
+        // The pod outlives steps; it is removed in finish_job
+        Ok(())
+    }
+
+    fn stop_allowance(&self) -> Duration {
+        Duration::from_secs(self.shell.kill_grace_secs) + STOP_EXEC_TIMEOUT
+    }
+
+    async fn finish_job(&self, job_id: &str) -> Result<()> {
+        let pods = self.pods().await?;
+        let name = Self::pod_name(job_id);
+
+        match pods.delete(&name, &DeleteParams::default()).await {
+            Ok(_) => info!("Deleted pod {}", name),
+            Err(kube::Error::Api(e)) if e.code == 404 => {}
+            Err(e) => return Err(e).context("Failed to delete job pod"),
+        }
+        Ok(())
+    }
+
+    async fn health_check(&self) -> Result<bool> {
+        let pods = self.pods().await?;
+        Ok(pods.list(&Default::default()).await.is_ok())
+    }
+
+    fn executor_type(&self) -> ExecutorType {
+        ExecutorType::Kubernetes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::{StatusCause, StatusDetails};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_pod_name() {
+        assert_eq!(KubernetesExecutor::pod_name("ABC_123"), "muelsyse-abc-123");
+        assert!(KubernetesExecutor::pod_name(&"x".repeat(100)).len() <= 63);
+    }
+
+    #[test]
+    fn test_exit_code() {
+        assert_eq!(exit_code(None), -1);
+
+        let success = Status { status: Some("Success".into()), ..Default::default() };
+        assert_eq!(exit_code(Some(&success)), 0);
+
+        let failure = Status {
+            status: Some("Failure".into()),
+            details: Some(StatusDetails {
+                causes: Some(vec![StatusCause {
+                    reason: Some("ExitCode".into()),
+                    message: Some("3".into()),
+                    ..Default::default()
+                }]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert_eq!(exit_code(Some(&failure)), 3);
+    }
+
+    #[test]
+    fn test_env_file() {
+        let environment = HashMap::from([
+            ("TOKEN".to_string(), "it's s3cret".to_string()),
+            ("_A1".to_string(), "x".to_string()),
+            ("1BAD".to_string(), "y".to_string()),
+            ("has-dash".to_string(), "z".to_string()),
+        ]);
+        assert_eq!(env_file(&environment), "TOKEN='it'\\''s s3cret'\n_A1='x'\n");
+    }
+}
@@ -1,23 +1,76 @@
 //! Executor module for running jobs
 
 mod traits;
+mod audit;
+mod cgroup;
+mod script;
+mod encoding;
+mod isolation;
+mod output;
+mod profile;
 mod shell;
+mod pulls;
 mod docker;
+mod registry;
+mod usage;
+#[cfg(feature = "kubernetes")]
+mod kubernetes;
+#[cfg(test)]
+mod fixtures;
 
-pub use traits::{Executor, ExecutorType, ExecutionContext, ExecutionResult};
+pub use traits::{
+    Executor, ExecutorType, ExecutionContext, ExecutionResult, ExecutionPhase, ContainerMode,
+    ContainerOptions, Termination,
+};
+pub use audit::{strace_available, traced_command, WriteAttempt, WriteAudit};
+pub use cgroup::{cgroup_v2_available, LimitExceeded, ResourceLimits};
+pub use profile::{apply_profile, KVM_PROFILES};
+pub use encoding::OutputEncoding;
+pub use output::{OutputLine, OutputSink, OutputStream, RepeatCollapser};
+pub use script::{
+    create_private_dir, job_output_dir, job_script_dir, remove_job_dirs, ShellInvocation,
+    CONTAINER_OUTPUT_DIR, CONTAINER_SCRIPT_DIR,
+};
 pub use shell::ShellExecutor;
-pub use docker::DockerExecutor;
+pub use docker::{DockerExecutor, GarbageReport};
+pub use pulls::{ImagePullStats, ImagePulls, LayerCounts};
+pub use registry::{register_executor, registered_executor, registered_executors, ExecutorFactory};
+pub use usage::ResourceUsage;
+#[cfg(feature = "kubernetes")]
+pub use kubernetes::KubernetesExecutor;
+#[cfg(test)]
+pub use fixtures::step_context;
 
 use anyhow::Result;
+use crate::cache::VolumeIndex;
 use crate::config::Settings;
+use crate::error::RunnerError;
 
-/// Create an executor based on type
-pub fn create_executor(
-    executor_type: ExecutorType,
-    settings: &Settings,
-) -> Result<Box<dyn Executor>> {
-    match executor_type {
-        ExecutorType::Shell => Ok(Box::new(ShellExecutor::new(settings.executor.shell.clone()))),
-        ExecutorType::Docker => Ok(Box::new(DockerExecutor::new(settings.executor.docker.clone())?)),
+/// Create the executor registered or built in under `name`
+pub fn create_executor(name: &str, settings: &Settings) -> Result<Box<dyn Executor>> {
+    if let Some(factory) = registered_executor(name) {
+        return factory(settings);
+    }
+
+    match ExecutorType::from_str(name) {
+        Some(ExecutorType::Shell) => Ok(Box::new(ShellExecutor::new(settings.executor.shell.clone()))),
+        Some(ExecutorType::Docker) => Ok(Box::new(DockerExecutor::new(
+            settings.executor.docker.clone(),
+            settings.executor.shell.clone(),
+        )?
+        .with_runner_id(&settings.runner.id)
+        .with_cache_index(VolumeIndex::new(&settings.workspace.cache_path)))),
+        #[cfg(feature = "kubernetes")]
+        Some(ExecutorType::Kubernetes) => Ok(Box::new(KubernetesExecutor::new(
+            settings.executor.kubernetes.clone(),
+            settings.executor.shell.clone(),
+        ))),
+        #[cfg(not(feature = "kubernetes"))]
+        Some(ExecutorType::Kubernetes) => {
+            anyhow::bail!(RunnerError::ConfigError(
+                "Runner was built without Kubernetes support (enable the `kubernetes` feature)".into()
+            ))
+        }
+        Some(ExecutorType::Custom) | None => anyhow::bail!(RunnerError::ConfigError(format!("Unknown executor '{}'", name))),
     }
 }
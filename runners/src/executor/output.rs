@@ -0,0 +1,248 @@
+//! Live step output
+//!
+//! Executors forward each line of a step's stdout/stderr to an output sink
+//! as it is produced, so long-running steps show progress before they
+//! finish. The complete output is still returned in the `ExecutionResult`,
+//! per stream and merged in the order the lines arrived.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::warn;
+
+use super::encoding::{LineDecoder, OutputEncoding};
+
+/// Stream a line of output was written to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+/// A decoded line of step output, without its line ending
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputLine {
+    pub stream: OutputStream,
+    pub text: String,
+    /// When the line was read from the step
+    pub timestamp: DateTime<Utc>,
+}
+
+impl OutputLine {
+    /// A line read just now
+    pub fn new(stream: OutputStream, text: impl Into<String>) -> Self {
+        Self { stream, text: text.into(), timestamp: Utc::now() }
+    }
+}
+
+/// Receives output lines as a step produces them
+pub type OutputSink = mpsc::UnboundedSender<OutputLine>;
+
+/// Splits one output stream into lines for a sink, keeping the raw bytes
+pub struct LineForwarder<'a> {
+    stream: OutputStream,
+    decoder: LineDecoder,
+    sink: &'a OutputSink,
+    captured: Vec<u8>,
+}
+
+impl<'a> LineForwarder<'a> {
+    pub fn new(stream: OutputStream, encoding: OutputEncoding, sink: &'a OutputSink) -> Self {
+        Self {
+            stream,
+            decoder: LineDecoder::new(encoding),
+            sink,
+            captured: Vec::new(),
+        }
+    }
+
+    /// Forward the lines completed by `bytes`
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.captured.extend_from_slice(bytes);
+        for text in self.decoder.push(bytes) {
+            self.send(text);
+        }
+    }
+
+    /// Forward a final unterminated line, returning everything captured
+    pub fn finish(mut self) -> Vec<u8> {
+        if let Some(text) = self.decoder.finish() {
+            self.send(text);
+        }
+        self.captured
+    }
+
+    fn send(&self, text: String) {
+        // The receiver only goes away once the step is abandoned
+        let _ = self.sink.send(OutputLine::new(self.stream, text));
+    }
+}
+
+/// Both output streams of a step merged into one, in arrival order.
+///
+/// Lines sent to [`MergedOutput::sink`] are passed on to the step's sink and
+/// kept, so the result can show stdout and stderr interleaved as they were
+/// written.
+pub struct MergedOutput {
+    sink: OutputSink,
+    collector: JoinHandle<Vec<String>>,
+}
+
+impl MergedOutput {
+    /// Merge into `output`
+    pub fn new(output: &OutputSink) -> Self {
+        let (sink, mut rx) = mpsc::unbounded_channel::<OutputLine>();
+        let output = output.clone();
+        let collector = tokio::spawn(async move {
+            let mut lines = Vec::new();
+            while let Some(line) = rx.recv().await {
+                lines.push(line.text.clone());
+                let _ = output.send(line);
+            }
+            lines
+        });
+        Self { sink, collector }
+    }
+
+    /// Sink for one of the streams
+    pub fn sink(&self) -> &OutputSink {
+        &self.sink
+    }
+
+    /// The merged output, once every clone of the sink is gone
+    pub async fn finish(self) -> String {
+        drop(self.sink);
+        self.collector.await.unwrap_or_default().join("\n")
+    }
+}
+
+/// Collapses runs of identical consecutive lines.
+///
+/// The first line of a run passes through; the repeats are only counted and
+/// reported as one "last line repeated N times" line when the run ends or
+/// is flushed, so no occurrence goes uncounted.
+#[derive(Debug, Default)]
+pub struct RepeatCollapser {
+    last: Option<OutputLine>,
+    repeats: u64,
+}
+
+impl RepeatCollapser {
+    /// Lines to log for `line`
+    pub fn push(&mut self, line: OutputLine) -> Vec<OutputLine> {
+        if self.last.as_ref().is_some_and(|last| last.stream == line.stream && last.text == line.text) {
+            self.repeats += 1;
+            return Vec::new();
+        }
+
+        let mut lines: Vec<OutputLine> = self.flush().into_iter().collect();
+        self.last = Some(line.clone());
+        lines.push(line);
+        lines
+    }
+
+    /// Report the repeats counted so far; later repeats of the same line are
+    /// counted afresh
+    pub fn flush(&mut self) -> Option<OutputLine> {
+        let last = self.last.as_ref()?;
+        if self.repeats == 0 {
+            return None;
+        }
+        let summary = OutputLine::new(last.stream, format!("last line repeated {} times", self.repeats));
+        self.repeats = 0;
+        Some(summary)
+    }
+}
+
+/// Read `reader` to the end, forwarding lines as they arrive
+pub async fn forward_output(mut reader: impl AsyncRead + Unpin, mut forwarder: LineForwarder<'_>) -> Vec<u8> {
+    let mut buf = [0u8; 8192];
+    loop {
+        match reader.read(&mut buf).await {
+            Ok(0) => break,
+            Ok(n) => forwarder.push(&buf[..n]),
+            Err(e) => {
+                warn!("Error reading {:?}: {}", forwarder.stream, e);
+                break;
+            }
+        }
+    }
+    forwarder.finish()
+}
+
+/// Read `reader` to the end on its own task, forwarding lines to `sink`
+pub fn spawn_forwarder(
+    reader: impl AsyncRead + Unpin + Send + 'static,
+    stream: OutputStream,
+    encoding: OutputEncoding,
+    sink: OutputSink,
+) -> JoinHandle<Vec<u8>> {
+    tokio::spawn(async move {
+        forward_output(reader, LineForwarder::new(stream, encoding, &sink)).await
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_forward_output() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let forwarder = LineForwarder::new(OutputStream::Stderr, OutputEncoding::Utf8, &tx);
+
+        let captured = forward_output(&b"compiling\nwarning: unused\ndone"[..], forwarder).await;
+        assert_eq!(captured, b"compiling\nwarning: unused\ndone");
+
+        drop(tx);
+        let mut lines = Vec::new();
+        while let Some(line) = rx.recv().await {
+            assert_eq!(line.stream, OutputStream::Stderr);
+            lines.push(line.text);
+        }
+        assert_eq!(lines, vec!["compiling", "warning: unused", "done"]);
+    }
+
+    #[tokio::test]
+    async fn test_merged_output() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let merged = MergedOutput::new(&tx);
+
+        let stdout = merged.sink().clone();
+        stdout.send(OutputLine::new(OutputStream::Stdout, "building")).unwrap();
+        merged.sink().send(OutputLine::new(OutputStream::Stderr, "warning: slow")).unwrap();
+        // Stdout closing first does not end the merged output
+        drop(stdout);
+        merged.sink().send(OutputLine::new(OutputStream::Stderr, "error: failed")).unwrap();
+
+        assert_eq!(merged.finish().await, "building\nwarning: slow\nerror: failed");
+        let streams: Vec<OutputStream> = std::iter::from_fn(|| rx.try_recv().ok()).map(|l| l.stream).collect();
+        assert_eq!(streams, vec![OutputStream::Stdout, OutputStream::Stderr, OutputStream::Stderr]);
+    }
+
+    #[test]
+    fn test_collapse_repeats() {
+        let line = OutputLine::new;
+        let texts = |lines: Vec<OutputLine>| lines.into_iter().map(|l| l.text).collect::<Vec<_>>();
+        let mut collapser = RepeatCollapser::default();
+
+        assert_eq!(texts(collapser.push(line(OutputStream::Stderr, "warning: retry"))), vec!["warning: retry"]);
+        for _ in 0..4 {
+            assert!(collapser.push(line(OutputStream::Stderr, "warning: retry")).is_empty());
+        }
+
+        // A periodic flush reports the count without repeating the line
+        assert_eq!(collapser.flush().unwrap().text, "last line repeated 4 times");
+        assert!(collapser.flush().is_none());
+        assert!(collapser.push(line(OutputStream::Stderr, "warning: retry")).is_empty());
+
+        // Same text on another stream is a different line
+        let lines = collapser.push(line(OutputStream::Stdout, "warning: retry"));
+        assert_eq!((lines[0].stream, lines[0].text.as_str()), (OutputStream::Stderr, "last line repeated 1 times"));
+        assert_eq!((lines[1].stream, lines[1].text.as_str()), (OutputStream::Stdout, "warning: retry"));
+        assert!(collapser.flush().is_none());
+    }
+}
@@ -0,0 +1,71 @@
+//! Container job profiles
+//!
+//! A profile bundles the container settings a class of workload needs, so
+//! jobs can ask for e.g. `kvm` instead of spelling out devices and shm size.
+
+use anyhow::Result;
+
+use super::traits::ContainerOptions;
+use crate::config::KvmProfileConfig;
+use crate::error::RunnerError;
+
+/// Profiles that need hardware virtualization
+pub const KVM_PROFILES: &[&str] = &["kvm", "android-emulator"];
+
+/// Apply `profile` to `options`; `kvm` is whether the host has usable KVM
+pub fn apply_profile(
+    profile: &str,
+    config: &KvmProfileConfig,
+    kvm: bool,
+    options: &mut ContainerOptions,
+) -> Result<()> {
+    if !KVM_PROFILES.contains(&profile) {
+        anyhow::bail!(RunnerError::ConfigError(format!(
+            "Unknown job profile '{}' (known: {})",
+            profile,
+            KVM_PROFILES.join(", ")
+        )));
+    }
+    if !kvm {
+        anyhow::bail!(RunnerError::ConfigError(format!(
+            "Profile '{}' requires KVM, which is not available on this runner",
+            profile
+        )));
+    }
+
+    if !options.devices.iter().any(|d| d.split(':').next() == Some("/dev/kvm")) {
+        options.devices.push("/dev/kvm".to_string());
+    }
+    options.shm_size = Some(config.shm_size_mb * 1024 * 1024);
+    for (key, value) in &config.sysctls {
+        options.sysctls.entry(key.clone()).or_insert_with(|| value.clone());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_kvm_profile() {
+        let config = KvmProfileConfig::default();
+        let mut options = ContainerOptions {
+            devices: vec!["/dev/ttyUSB0".to_string()],
+            ..Default::default()
+        };
+
+        apply_profile("android-emulator", &config, true, &mut options).unwrap();
+        assert_eq!(options.devices, vec!["/dev/ttyUSB0".to_string(), "/dev/kvm".to_string()]);
+        assert_eq!(options.shm_size, Some(2048 * 1024 * 1024));
+        assert_eq!(options.sysctls.get("net.ipv4.ip_forward").map(String::as_str), Some("1"));
+
+        // Applying twice does not duplicate the device
+        apply_profile("kvm", &config, true, &mut options).unwrap();
+        assert_eq!(options.devices.len(), 2);
+
+        assert!(apply_profile("kvm", &config, false, &mut options).is_err());
+        assert!(apply_profile("gpu", &config, true, &mut options).is_err());
+    }
+}
@@ -0,0 +1,203 @@
+//! Image pull deduplication and metrics
+//!
+//! Executors are created per step, so concurrent jobs needing the same image
+//! would each start their own pull. Pulls go through a process-wide registry
+//! instead: the first caller starts the pull and later callers for the same
+//! reference await the same shared future.
+
+use anyhow::Result;
+use futures_util::future::{BoxFuture, FutureExt, Shared};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Instant;
+
+/// Outcome of a shared pull; errors are flattened so they can be cloned
+type PullResult = std::result::Result<(), Arc<String>>;
+
+/// Counters for image pulls since the runner started
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ImagePullStats {
+    /// Images requested by executors
+    pub requests: u64,
+    /// Images already present locally, so no pull was needed
+    pub image_cache_hits: u64,
+    /// Pulls started against the registry
+    pub pulls: u64,
+    /// Requests that joined a pull already in progress
+    pub deduplicated: u64,
+    pub failed_pulls: u64,
+    /// Layers the daemon already had
+    pub layers_cached: u64,
+    /// Layers downloaded
+    pub layers_downloaded: u64,
+    pub pull_time_ms_total: u64,
+    pub pull_time_ms_max: u64,
+    /// Share of requests served without starting a pull
+    pub image_hit_rate: f64,
+    /// Share of pulled layers that were already cached
+    pub layer_hit_rate: f64,
+}
+
+fn ratio(part: u64, whole: u64) -> f64 {
+    if whole == 0 { 0.0 } else { part as f64 / whole as f64 }
+}
+
+/// Layer progress seen while streaming one pull
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LayerCounts {
+    pub cached: u64,
+    pub downloaded: u64,
+}
+
+impl LayerCounts {
+    /// Count a status line from the daemon's pull progress
+    pub fn observe(&mut self, status: &str) {
+        match status {
+            "Already exists" => self.cached += 1,
+            "Pull complete" => self.downloaded += 1,
+            _ => {}
+        }
+    }
+}
+
+/// Registry of in-flight pulls
+#[derive(Default)]
+pub struct ImagePulls {
+    in_flight: Mutex<HashMap<String, Shared<BoxFuture<'static, PullResult>>>>,
+    stats: Arc<Mutex<ImagePullStats>>,
+}
+
+static IMAGE_PULLS: OnceLock<ImagePulls> = OnceLock::new();
+
+impl ImagePulls {
+    /// The runner-wide registry
+    pub fn global() -> &'static Self {
+        IMAGE_PULLS.get_or_init(Self::default)
+    }
+
+    /// Record a request served by an image already present
+    pub fn record_cache_hit(&self) {
+        let mut stats = self.stats.lock().unwrap_or_else(|e| e.into_inner());
+        stats.requests += 1;
+        stats.image_cache_hits += 1;
+    }
+
+    /// Pull `image` with `pull`, or wait for a pull of it already in progress
+    pub async fn pull<F, Fut>(&self, image: &str, pull: F) -> Result<()>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<LayerCounts>> + Send + 'static,
+    {
+        let shared = {
+            let mut in_flight = self.in_flight.lock().unwrap_or_else(|e| e.into_inner());
+            let mut stats = self.stats.lock().unwrap_or_else(|e| e.into_inner());
+            stats.requests += 1;
+
+            // A finished entry is left behind if its starter was cancelled
+            match in_flight.get(image).filter(|pull| pull.peek().is_none()) {
+                Some(pull) => {
+                    stats.deduplicated += 1;
+                    pull.clone()
+                }
+                None => {
+                    stats.pulls += 1;
+                    let shared = timed(pull(), self.stats.clone()).boxed().shared();
+                    in_flight.insert(image.to_string(), shared.clone());
+                    shared
+                }
+            }
+        };
+
+        let result = shared.clone().await;
+
+        let mut in_flight = self.in_flight.lock().unwrap_or_else(|e| e.into_inner());
+        if in_flight.get(image).is_some_and(|pull| pull.ptr_eq(&shared)) {
+            in_flight.remove(image);
+        }
+        result.map_err(|e| anyhow::anyhow!("{}", e))
+    }
+
+    /// Snapshot of the counters
+    pub fn stats(&self) -> ImagePullStats {
+        let mut stats = self.stats.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        stats.image_hit_rate = ratio(stats.image_cache_hits + stats.deduplicated, stats.requests);
+        stats.layer_hit_rate = ratio(stats.layers_cached, stats.layers_cached + stats.layers_downloaded);
+        stats
+    }
+}
+
+/// Run a pull, recording its duration and layers
+async fn timed<Fut>(pull: Fut, stats: Arc<Mutex<ImagePullStats>>) -> PullResult
+where
+    Fut: Future<Output = Result<LayerCounts>>,
+{
+    let start = Instant::now();
+    let result = pull.await;
+    let elapsed = start.elapsed().as_millis() as u64;
+
+    let mut stats = stats.lock().unwrap_or_else(|e| e.into_inner());
+    stats.pull_time_ms_total += elapsed;
+    stats.pull_time_ms_max = stats.pull_time_ms_max.max(elapsed);
+    match result {
+        Ok(layers) => {
+            stats.layers_cached += layers.cached;
+            stats.layers_downloaded += layers.downloaded;
+            Ok(())
+        }
+        Err(e) => {
+            stats.failed_pulls += 1;
+            Err(Arc::new(format!("{:#}", e)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::time::Duration;
+
+    const PULL_TIME: Duration = Duration::from_millis(50);
+
+    #[tokio::test]
+    async fn test_concurrent_pulls_are_shared() {
+        let pulls = ImagePulls::default();
+        let started = Arc::new(AtomicU32::new(0));
+
+        let pull = |started: Arc<AtomicU32>| move || async move {
+            started.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(PULL_TIME).await;
+            let mut layers = LayerCounts::default();
+            for status in ["Pulling fs layer", "Already exists", "Pull complete", "Pull complete"] {
+                layers.observe(status);
+            }
+            Ok(layers)
+        };
+
+        let (a, b, c) = tokio::join!(
+            pulls.pull("alpine:3", pull(started.clone())),
+            pulls.pull("alpine:3", pull(started.clone())),
+            pulls.pull("alpine:3", pull(started.clone())),
+        );
+        assert!(a.is_ok() && b.is_ok() && c.is_ok());
+        assert_eq!(started.load(Ordering::SeqCst), 1);
+
+        // Once finished, the next request pulls again
+        pulls.pull("alpine:3", pull(started.clone())).await.unwrap();
+        assert_eq!(started.load(Ordering::SeqCst), 2);
+
+        let failed = pulls.pull("missing:latest", || async { anyhow::bail!("manifest unknown") }).await;
+        assert!(failed.unwrap_err().to_string().contains("manifest unknown"));
+        pulls.record_cache_hit();
+
+        let stats = pulls.stats();
+        assert_eq!(stats.requests, 6);
+        assert_eq!((stats.pulls, stats.deduplicated, stats.failed_pulls, stats.image_cache_hits), (3, 2, 1, 1));
+        assert_eq!((stats.layers_cached, stats.layers_downloaded), (2, 4));
+        assert_eq!(stats.image_hit_rate, 0.5);
+        assert_eq!(stats.layer_hit_rate, 2.0 / 6.0);
+        assert!(stats.pull_time_ms_max >= PULL_TIME.as_millis() as u64);
+    }
+}
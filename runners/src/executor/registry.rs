@@ -0,0 +1,92 @@
+//! Custom executors
+//!
+//! Applications embedding the runner register their own [`Executor`]
+//! implementations (a Firecracker VM, a WASM sandbox, SSH to another
+//! machine) under a name. A job selects one with `executor: <name>`, which
+//! must also be listed in the runner's `executor.enabled`.
+
+use anyhow::Result;
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock, RwLock};
+
+use super::traits::Executor;
+use crate::config::Settings;
+
+/// Builds a custom executor for a job
+pub type ExecutorFactory = Arc<dyn Fn(&Settings) -> Result<Box<dyn Executor>> + Send + Sync>;
+
+static REGISTRY: OnceLock<RwLock<HashMap<String, ExecutorFactory>>> = OnceLock::new();
+
+fn registry() -> &'static RwLock<HashMap<String, ExecutorFactory>> {
+    REGISTRY.get_or_init(Default::default)
+}
+
+/// Register `factory` under `name`, replacing an earlier registration.
+///
+/// Registered names take precedence over the built-in executors.
+pub fn register_executor<F>(name: impl Into<String>, factory: F)
+where
+    F: Fn(&Settings) -> Result<Box<dyn Executor>> + Send + Sync + 'static,
+{
+    let name = name.into();
+    tracing::info!("Registered executor '{}'", name);
+    registry().write().unwrap_or_else(|e| e.into_inner()).insert(name, Arc::new(factory));
+}
+
+/// Factory registered under `name`
+pub fn registered_executor(name: &str) -> Option<ExecutorFactory> {
+    registry().read().unwrap_or_else(|e| e.into_inner()).get(name).cloned()
+}
+
+/// Names of the registered executors, sorted
+pub fn registered_executors() -> Vec<String> {
+    let mut names: Vec<String> = registry().read().unwrap_or_else(|e| e.into_inner()).keys().cloned().collect();
+    names.sort();
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::{create_executor, ExecutionContext, ExecutionResult, ExecutorType, OutputSink};
+    use async_trait::async_trait;
+
+    struct Remote;
+
+    #[async_trait]
+    impl Executor for Remote {
+        async fn execute(&self, _ctx: &ExecutionContext, _output: &OutputSink) -> Result<ExecutionResult> {
+            anyhow::bail!("not connected")
+        }
+
+        async fn prepare(&self, _ctx: &ExecutionContext) -> Result<()> {
+            Ok(())
+        }
+
+        async fn cleanup(&self, _ctx: &ExecutionContext) -> Result<()> {
+            Ok(())
+        }
+
+        async fn health_check(&self) -> Result<bool> {
+            Ok(true)
+        }
+
+        fn executor_type(&self) -> ExecutorType {
+            ExecutorType::Custom
+        }
+    }
+
+    #[test]
+    fn test_register_executor() {
+        assert!(registered_executor("test-remote").is_none());
+        register_executor("test-remote", |_: &Settings| Ok(Box::new(Remote) as Box<dyn Executor>));
+        assert!(registered_executor("test-remote").is_some());
+        assert!(registered_executors().contains(&"test-remote".to_string()));
+
+        let settings = Settings::load_offline().unwrap();
+        let executor = create_executor("test-remote", &settings).unwrap();
+        assert_eq!(executor.executor_type(), ExecutorType::Custom);
+        assert_eq!(create_executor("shell", &settings).unwrap().executor_type(), ExecutorType::Shell);
+        assert!(create_executor("firecracker", &settings).is_err());
+    }
+}
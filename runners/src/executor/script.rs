@@ -0,0 +1,268 @@
+//! Step script files and shell invocation
+
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncWriteExt;
+
+use crate::config::ShellConfig;
+use crate::error::RunnerError;
+
+/// Placeholder replaced by the script path in custom shell strings
+pub const SCRIPT_PLACEHOLDER: &str = "{0}";
+
+/// Where the host script directory is mounted inside step containers
+pub const CONTAINER_SCRIPT_DIR: &str = "/__muelsyse/scripts";
+
+/// Where the host output directory is mounted inside step containers
+pub const CONTAINER_OUTPUT_DIR: &str = "/__muelsyse/outputs";
+
+/// Where the job's temporary directory is mounted inside step containers
+pub const CONTAINER_TEMP_DIR: &str = "/tmp";
+
+/// Variables pointing steps at the job's temporary directory
+pub const TEMP_DIR_VARS: &[&str] = &["TMPDIR", "TEMP", "TMP"];
+
+/// How to invoke a shell on a script file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShellInvocation {
+    /// Shell program
+    pub program: String,
+
+    /// Arguments; the script path replaces `{0}` or is appended
+    pub args: Vec<String>,
+
+    /// Script file extension
+    pub extension: &'static str,
+}
+
+impl ShellInvocation {
+    /// Resolve a step's `shell` value.
+    ///
+    /// A bare name (`bash`, `pwsh`, ...) gets the default options from
+    /// `config`; a full string such as `bash --noprofile --norc -e {0}` is
+    /// used verbatim. Other bare names are rejected rather than run under
+    /// another shell; they need the full string, such as `python {0}`.
+    pub fn resolve(shell: &str, config: &ShellConfig) -> Result<Self> {
+        let mut parts = shell.split_whitespace();
+        let program = parts.next().unwrap_or("bash");
+        let custom: Vec<String> = parts.map(String::from).collect();
+
+        if !custom.is_empty() {
+            return Ok(Self {
+                program: program.to_string(),
+                args: custom,
+                extension: extension_for(program),
+            });
+        }
+
+        let mut args: Vec<String> = Vec::new();
+        let program = match program {
+            "bash" | "sh" | "zsh" => {
+                if program == "bash" {
+                    args.extend(["--noprofile", "--norc"].map(String::from));
+                }
+                if config.errexit {
+                    args.push("-e".into());
+                }
+                // POSIX sh has no portable pipefail
+                if config.pipefail && program != "sh" {
+                    args.extend(["-o", "pipefail"].map(String::from));
+                }
+                program
+            }
+            "fish" => "fish",
+            "pwsh" | "powershell" => {
+                args.extend(["-NoProfile", "-NonInteractive", "-File"].map(String::from));
+                "pwsh"
+            }
+            "cmd" => {
+                args.extend(["/D", "/E:ON", "/V:OFF", "/S", "/C", "CALL"].map(String::from));
+                "cmd"
+            }
+            _ => anyhow::bail!(RunnerError::ConfigError(format!(
+                "Unknown shell '{}'; give the full command, such as '{} {}'",
+                program, program, SCRIPT_PLACEHOLDER
+            ))),
+        };
+
+        Ok(Self {
+            program: program.to_string(),
+            args,
+            extension: extension_for(program),
+        })
+    }
+
+    /// Full argument vector for running `script_path`
+    pub fn command_line(&self, script_path: &str) -> Vec<String> {
+        let mut argv = vec![self.program.clone()];
+        let mut substituted = false;
+
+        for arg in &self.args {
+            if arg.contains(SCRIPT_PLACEHOLDER) {
+                argv.push(arg.replace(SCRIPT_PLACEHOLDER, script_path));
+                substituted = true;
+            } else {
+                argv.push(arg.clone());
+            }
+        }
+
+        if !substituted {
+            argv.push(script_path.to_string());
+        }
+        argv
+    }
+
+    /// Script file contents for `command`
+    pub fn script(&self, command: &str, config: &ShellConfig) -> String {
+        // PowerShell has no errexit flag; stop on the first error instead
+        if self.extension == "ps1" && config.errexit {
+            format!("$ErrorActionPreference = 'Stop'\n{}\n", command)
+        } else {
+            format!("{}\n", command)
+        }
+    }
+}
+
+fn extension_for(program: &str) -> &'static str {
+    match program {
+        "pwsh" | "powershell" => "ps1",
+        "cmd" => "cmd",
+        "fish" => "fish",
+        _ => "sh",
+    }
+}
+
+/// Host directory where step scripts are written
+pub fn script_dir() -> PathBuf {
+    std::env::temp_dir().join("muelsyse-scripts")
+}
+
+/// Host directory where steps write their outputs
+pub fn output_dir() -> PathBuf {
+    std::env::temp_dir().join("muelsyse-outputs")
+}
+
+/// Host directory of a job's scripts and environment files; step
+/// containers see only this one under [`CONTAINER_SCRIPT_DIR`]
+pub fn job_script_dir(job_id: &str) -> PathBuf {
+    script_dir().join(job_id)
+}
+
+/// Host directory of a job's output files; step containers see only this
+/// one under [`CONTAINER_OUTPUT_DIR`]
+pub fn job_output_dir(job_id: &str) -> PathBuf {
+    output_dir().join(job_id)
+}
+
+/// Create `dir`, open only to the runner's user, if it does not exist
+pub async fn create_private_dir(dir: &Path) -> Result<()> {
+    if let Some(parent) = dir.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .with_context(|| format!("Failed to create directory {:?}", parent))?;
+    }
+    let mut builder = tokio::fs::DirBuilder::new();
+    #[cfg(unix)]
+    builder.mode(0o700);
+    match builder.create(dir).await {
+        Err(e) if e.kind() != std::io::ErrorKind::AlreadyExists => {
+            Err(e).with_context(|| format!("Failed to create directory {:?}", dir))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Remove a job's script and output directories
+pub async fn remove_job_dirs(job_id: &str) {
+    for dir in [job_script_dir(job_id), job_output_dir(job_id)] {
+        match tokio::fs::remove_dir_all(&dir).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => tracing::warn!("Failed to remove {:?}: {}", dir, e),
+        }
+    }
+}
+
+/// Write a step script into `dir`, readable only by the runner's user, and
+/// return its path
+pub async fn write_script(dir: &Path, name: &str, extension: &str, contents: &str) -> Result<PathBuf> {
+    create_private_dir(dir)
+        .await
+        .context("Failed to create script directory")?;
+
+    let path = dir.join(format!("{}.{}", name, extension));
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options
+        .open(&path)
+        .await
+        .with_context(|| format!("Failed to write script {:?}", path))?;
+    file.write_all(contents.as_bytes()).await?;
+    file.flush().await?;
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_default_options() {
+        let config = ShellConfig::default();
+
+        let bash = ShellInvocation::resolve("bash", &config).unwrap();
+        assert_eq!(
+            bash.command_line("/tmp/s.sh"),
+            vec!["bash", "--noprofile", "--norc", "-e", "-o", "pipefail", "/tmp/s.sh"]
+        );
+
+        let sh = ShellInvocation::resolve("sh", &config).unwrap();
+        assert_eq!(sh.command_line("/tmp/s.sh"), vec!["sh", "-e", "/tmp/s.sh"]);
+
+        let relaxed = ShellConfig { errexit: false, pipefail: false, ..ShellConfig::default() };
+        let bash = ShellInvocation::resolve("bash", &relaxed).unwrap();
+        assert_eq!(bash.command_line("s.sh"), vec!["bash", "--noprofile", "--norc", "s.sh"]);
+    }
+
+    #[test]
+    fn test_resolve_custom_shell_string() {
+        let config = ShellConfig::default();
+
+        let custom = ShellInvocation::resolve("bash --noprofile -x {0}", &config).unwrap();
+        assert_eq!(custom.command_line("s.sh"), vec!["bash", "--noprofile", "-x", "s.sh"]);
+
+        let appended = ShellInvocation::resolve("python3 -u", &config).unwrap();
+        assert_eq!(appended.command_line("s.sh"), vec!["python3", "-u", "s.sh"]);
+
+        let python = ShellInvocation::resolve("python {0}", &config).unwrap();
+        assert_eq!(python.command_line("s.sh"), vec!["python", "s.sh"]);
+        assert!(ShellInvocation::resolve("python", &config).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_write_script_private() {
+        let dir = job_script_dir(&format!("script-test-{}", uuid::Uuid::new_v4()));
+        let path = write_script(&dir, "step-1", "sh", "echo hi").await.unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "echo hi");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(std::fs::metadata(&dir).unwrap().permissions().mode() & 0o777, 0o700);
+            assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_powershell_script_preamble() {
+        let config = ShellConfig::default();
+        let pwsh = ShellInvocation::resolve("powershell", &config).unwrap();
+
+        assert_eq!(pwsh.extension, "ps1");
+        assert!(pwsh.script("Get-Date", &config).starts_with("$ErrorActionPreference"));
+    }
+}
@@ -1,16 +1,31 @@
 //! Shell executor - runs commands directly on the host
+//!
+//! With `job_uid_range` set, each job runs as its own unprivileged user; see
+//! the `isolation` module. With `limits` or a step's own, each step runs in a
+//! cgroup of its own; see the `cgroup` module.
 
 use async_trait::async_trait;
 use anyhow::{Result, Context};
-use tokio::process::Command;
-use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::{Child, Command};
 use tokio::time::timeout;
+use std::ffi::OsString;
+use std::path::Path;
 use std::process::Stdio;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use tracing::{debug, warn};
 
-use super::traits::{Executor, ExecutorType, ExecutionContext, ExecutionResult};
+use super::audit::{strace_available, traced_command, WriteAttempt, WriteAudit};
+use super::cgroup::{cgroup_v2_available, remove_job_cgroups, StepCgroup};
+use super::isolation;
+use super::output::{spawn_forwarder, MergedOutput, OutputSink, OutputStream};
+use super::script::{job_script_dir, write_script, ShellInvocation, TEMP_DIR_VARS};
+use super::traits::{Executor, ExecutorType, ExecutionContext, ExecutionResult, Termination};
+use super::usage::UsageSampler;
 use crate::config::ShellConfig;
+use crate::error::RunnerError;
+
+/// How long a killed step's output may take to close
+const OUTPUT_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
 
 /// Shell executor that runs commands directly on the host
 pub struct ShellExecutor {
@@ -22,109 +37,350 @@ impl ShellExecutor {
         Self { config }
     }
 
-    fn get_shell_command(&self, shell: &str) -> (&str, &str) {
-        match shell {
-            "bash" => ("bash", "-c"),
-            "sh" => ("sh", "-c"),
-            "zsh" => ("zsh", "-c"),
-            "fish" => ("fish", "-c"),
-            "pwsh" | "powershell" => ("pwsh", "-Command"),
-            "cmd" => ("cmd", "/C"),
-            _ => ("bash", "-c"),
+    /// The user `job_id` runs as, if jobs get users of their own
+    fn job_user(&self, job_id: &str) -> Result<Option<u32>> {
+        self.config.job_uid_range
+            .map(|range| {
+                if cfg!(not(unix)) {
+                    anyhow::bail!(RunnerError::ConfigError("job_uid_range is only supported on Unix".into()));
+                }
+                isolation::allocate(job_id, range)
+            })
+            .transpose()
+    }
+
+    /// A cgroup limiting the step, if it or the runner sets limits
+    async fn step_cgroup(&self, ctx: &ExecutionContext) -> Option<StepCgroup> {
+        let limits = ctx.limits.within(&self.config.limits);
+        if limits.is_empty() {
+            return None;
+        }
+        if !cgroup_v2_available() {
+            warn!("Step {} runs without limits: no cgroup v2 hierarchy on this host", ctx.step_id);
+            return None;
+        }
+        match StepCgroup::create(&self.config.cgroup_parent, &ctx.job_id, &ctx.step_id, &limits).await {
+            Ok(cgroup) => Some(cgroup),
+            Err(e) => {
+                warn!("Step {} runs without limits: {:#}", ctx.step_id, e);
+                None
+            }
         }
     }
+
+    /// Collect output from a spawned shell, stopping its process group on
+    /// timeout or cancellation
+    async fn wait_for_output(
+        &self,
+        mut child: Child,
+        ctx: &ExecutionContext,
+        output: &OutputSink,
+        start: Instant,
+    ) -> Result<ExecutionResult> {
+        // Early warning so the step can checkpoint before being killed
+        let warning = match (ctx.warning_signal_after, child.id()) {
+            (Some(after), Some(pid)) => Some(tokio::spawn(async move {
+                tokio::time::sleep(after).await;
+                debug!("Sending SIGUSR2 to step process {}", pid);
+                if let Err(e) = Command::new("kill").arg("-USR2").arg(pid.to_string()).status().await {
+                    warn!("Failed to signal step process {}: {}", pid, e);
+                }
+            })),
+            _ => None,
+        };
+
+        let mut group = ProcessGroup::new(&child, Duration::from_secs(self.config.kill_grace_secs));
+        let sampler = child.id().filter(|_| cfg!(unix)).and_then(UsageSampler::process_group);
+
+        // Each stream is read to its end on its own task, so neither is cut
+        // short when the other closes
+        let merged = MergedOutput::new(output);
+        let stdout = child.stdout.take().expect("stdout not captured");
+        let stderr = child.stderr.take().expect("stderr not captured");
+        let mut readers = [
+            spawn_forwarder(stdout, OutputStream::Stdout, ctx.output_encoding, merged.sink().clone()),
+            spawn_forwarder(stderr, OutputStream::Stderr, ctx.output_encoding, merged.sink().clone()),
+        ];
+
+        let exited = tokio::select! {
+            exited = timeout(ctx.timeout, child.wait()) => Some(exited),
+            _ = ctx.cancel.cancelled() => None,
+        };
+        if let Some(warning) = warning {
+            warning.abort();
+        }
+        let Some(exited) = exited else {
+            debug!("Step {} cancelled, terminating its process group", ctx.step_id);
+            let _ = tokio::join!(group.terminate(), child.wait());
+            readers.iter().for_each(|reader| reader.abort());
+            anyhow::bail!(RunnerError::Cancelled("Step cancelled".into()));
+        };
+        let mut termination = None;
+        if exited.is_err() {
+            warn!("Command timed out, terminating its process group");
+            (termination, _) = tokio::join!(group.terminate(), child.wait());
+        }
+        let usage = sampler.map(UsageSampler::stop);
+
+        // Background processes may keep the pipes open after the shell exits
+        let drain = match exited {
+            Ok(_) => ctx.timeout.saturating_sub(start.elapsed()),
+            Err(_) => OUTPUT_DRAIN_TIMEOUT,
+        };
+        let captured = timeout(drain, async {
+            let [stdout, stderr] = &mut readers;
+            Ok::<_, anyhow::Error>((stdout.await?, stderr.await?))
+        }).await;
+        if captured.is_err() {
+            debug!("Step output is still open, dropping the rest");
+            group.terminate().await;
+            readers.iter().for_each(|reader| reader.abort());
+        }
+        // Processes a finished step left in the background keep running
+        group.disarm();
+
+        match (exited, captured) {
+            (Ok(Ok(status)), Ok(Ok((stdout, stderr)))) => {
+                // Output may not be UTF-8; it is decoded once complete
+                Ok(ExecutionResult {
+                    exit_code: status.code().unwrap_or(-1),
+                    stdout: trim_trailing_newline(ctx.output_encoding.decode(&stdout)),
+                    stderr: trim_trailing_newline(ctx.output_encoding.decode(&stderr)),
+                    output: merged.finish().await,
+                    duration: start.elapsed(),
+                    timed_out: false,
+                    write_audit: None,
+                    usage,
+                    limit_exceeded: None,
+                    termination: None,
+                })
+            }
+            (Ok(Err(e)), _) => Err(e.into()),
+            (_, Ok(Err(e))) => Err(e),
+            _ => Ok(ExecutionResult {
+                exit_code: -1,
+                stdout: String::new(),
+                stderr: "Command timed out".to_string(),
+                output: merged.finish().await,
+                duration: start.elapsed(),
+                timed_out: true,
+                write_audit: None,
+                usage,
+                limit_exceeded: None,
+                termination,
+            }),
+        }
+    }
+
+    /// Writes outside the workspace recorded in a step's trace
+    async fn audit_writes(&self, ctx: &ExecutionContext, trace_path: &Path) -> Vec<WriteAttempt> {
+        let trace = match tokio::fs::read(trace_path).await {
+            Ok(trace) => String::from_utf8_lossy(&trace).into_owned(),
+            Err(e) => {
+                warn!("Failed to read write audit trace {:?}: {}", trace_path, e);
+                return Vec::new();
+            }
+        };
+        WriteAudit::new(&ctx.workspace, &self.config.audit_allowed_paths).violations(&trace, &ctx.working_directory)
+    }
+}
+
+/// The process group of a running step.
+///
+/// Steps that time out are stopped with SIGTERM to the whole group, and
+/// SIGKILL for whatever is left after the grace period, as are cancelled
+/// ones. Dropping an armed group does the same in the background, so steps
+/// abandoned without being cancelled are stopped too.
+struct ProcessGroup {
+    pgid: Option<u32>,
+    grace: Duration,
+}
+
+impl ProcessGroup {
+    /// The group led by `child`
+    fn new(child: &Child, grace: Duration) -> Self {
+        Self { pgid: child.id().filter(|_| cfg!(unix)), grace }
+    }
+
+    /// Stop every process in the group, telling how if any was left
+    async fn terminate(&mut self) -> Option<Termination> {
+        let pgid = self.pgid.take()?;
+        if !signal_group(pgid, "TERM") {
+            return None;
+        }
+        Some(kill_after_grace(pgid, self.grace).await)
+    }
+
+    /// Leave the group's processes running
+    fn disarm(&mut self) {
+        self.pgid = None;
+    }
+}
+
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        let Some(pgid) = self.pgid.take() else {
+            return;
+        };
+        debug!("Step abandoned, terminating process group {}", pgid);
+        if signal_group(pgid, "TERM") {
+            if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+                runtime.spawn(kill_after_grace(pgid, self.grace));
+            }
+        }
+    }
+}
+
+/// Wait up to `grace` for the group to exit, then SIGKILL what is left
+async fn kill_after_grace(pgid: u32, grace: Duration) -> Termination {
+    let deadline = Instant::now() + grace;
+    while Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        if !signal_group(pgid, "0") {
+            return Termination::Graceful;
+        }
+    }
+    warn!("Process group {} ignored SIGTERM for {:?}, killing it", pgid, grace);
+    signal_group(pgid, "KILL");
+    Termination::Forced
+}
+
+/// Send `signal` to every process in the group; false if none is left
+fn signal_group(pgid: u32, signal: &str) -> bool {
+    std::process::Command::new("kill")
+        .arg(format!("-{}", signal))
+        .arg("--")
+        .arg(format!("-{}", pgid))
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// Drop the final line ending, matching line-by-line collection
+fn trim_trailing_newline(mut text: String) -> String {
+    if text.ends_with('\n') {
+        text.pop();
+        if text.ends_with('\r') {
+            text.pop();
+        }
+    }
+    text
 }
 
 #[async_trait]
 impl Executor for ShellExecutor {
-    async fn execute(&self, ctx: &ExecutionContext) -> Result<ExecutionResult> {
-        let (shell, flag) = self.get_shell_command(&ctx.shell);
+    async fn execute(&self, ctx: &ExecutionContext, output: &OutputSink) -> Result<ExecutionResult> {
+        let invocation = ShellInvocation::resolve(&ctx.shell, &self.config)?;
+        let script_path = write_script(
+            &job_script_dir(&ctx.job_id),
+            &format!("{}-{}", ctx.job_id, ctx.step_id),
+            invocation.extension,
+            &invocation.script(&ctx.command, &self.config),
+        ).await?;
+        let mut argv = invocation.command_line(&script_path.to_string_lossy());
+
+        let trace_path = if !self.config.audit_writes {
+            None
+        } else if strace_available() {
+            Some(script_path.with_extension("trace"))
+        } else {
+            warn!("Write auditing is enabled but strace is not installed");
+            None
+        };
+        if let Some(ref trace_path) = trace_path {
+            argv = traced_command(&argv, trace_path);
+        }
+        let user = self.job_user(&ctx.job_id)?;
         let start = Instant::now();
 
-        debug!("Executing command in shell '{}': {}", shell, ctx.command);
+        debug!("Executing script {:?} with {:?}", script_path, argv);
 
-        let mut cmd = Command::new(shell);
-        cmd.arg(flag)
-           .arg(&ctx.command)
+        let mut cmd = Command::new(&argv[0]);
+        cmd.args(&argv[1..])
            .current_dir(&ctx.working_directory)
-           .envs(&ctx.environment)
            .stdout(Stdio::piped())
            .stderr(Stdio::piped());
+        let isolated = user.is_some();
+        if isolated || !self.config.env_passthrough.is_empty() || !self.config.env_blocklist.is_empty() {
+            cmd.env_clear().envs(inherited_env(&self.config, isolated, std::env::vars_os()));
+        }
+        // Its own group, so background processes it starts can be stopped with it
+        #[cfg(unix)]
+        cmd.process_group(0);
+        #[cfg(unix)]
+        if let Some(uid) = user {
+            // Including what the runner put there since the last step, such
+            // as restored caches
+            isolation::give_to(ctx.workspace.clone(), uid).await?;
+            if let Some(ref temp_dir) = ctx.temp_dir {
+                isolation::give_to(temp_dir.clone(), uid).await?;
+            }
+            // strace writes the trace as the job user
+            if let Some(ref trace_path) = trace_path {
+                tokio::fs::write(trace_path, b"").await.context("Failed to create trace file")?;
+            }
+            isolation::give_job_files(&ctx.job_id, uid).await?;
+            cmd.env("HOME", &ctx.workspace)
+               .uid(uid)
+               .gid(uid);
+        }
+        if let Some(ref temp_dir) = ctx.temp_dir {
+            for name in TEMP_DIR_VARS {
+                cmd.env(name, temp_dir);
+            }
+        }
+        if !ctx.gpus.is_empty() {
+            let devices: Vec<String> = ctx.gpus.iter().map(u32::to_string).collect();
+            cmd.env("CUDA_VISIBLE_DEVICES", devices.join(","));
+        }
+        cmd.envs(&ctx.environment);
 
-        // Spawn the process
-        let mut child = cmd.spawn()
-            .context("Failed to spawn shell process")?;
-
-        // Read output with timeout
-        let result = timeout(ctx.timeout, async {
-            let stdout = child.stdout.take().expect("stdout not captured");
-            let stderr = child.stderr.take().expect("stderr not captured");
-
-            let mut stdout_reader = BufReader::new(stdout).lines();
-            let mut stderr_reader = BufReader::new(stderr).lines();
-
-            let mut stdout_lines = Vec::new();
-            let mut stderr_lines = Vec::new();
-
-            // Read stdout and stderr concurrently
-            loop {
-                tokio::select! {
-                    line = stdout_reader.next_line() => {
-                        match line {
-                            Ok(Some(l)) => stdout_lines.push(l),
-                            Ok(None) => break,
-                            Err(e) => {
-                                warn!("Error reading stdout: {}", e);
-                                break;
-                            }
-                        }
-                    }
-                    line = stderr_reader.next_line() => {
-                        match line {
-                            Ok(Some(l)) => stderr_lines.push(l),
-                            Ok(None) => {}
-                            Err(e) => {
-                                warn!("Error reading stderr: {}", e);
-                            }
-                        }
+        let cgroup = self.step_cgroup(ctx).await;
+        #[cfg(unix)]
+        if let Some(ref cgroup) = cgroup {
+            match cgroup.procs_file() {
+                Ok(procs) => {
+                    // Joined before exec, so nothing the step forks escapes the
+                    // limits. The hook only makes a write(2), which is safe
+                    // between fork and exec.
+                    unsafe {
+                        cmd.pre_exec(move || {
+                            use std::io::Write;
+                            (&procs).write_all(b"0")
+                        });
                     }
                 }
+                Err(e) => warn!("Step {} runs without limits: {:#}", ctx.step_id, e),
             }
+        }
 
-            let status = child.wait().await?;
-
-            Ok::<_, anyhow::Error>((
-                status.code().unwrap_or(-1),
-                stdout_lines.join("\n"),
-                stderr_lines.join("\n"),
-            ))
-        }).await;
+        // Spawn the process
+        let mut result = match cmd.spawn().context("Failed to spawn shell process") {
+            Ok(child) => self.wait_for_output(child, ctx, output, start).await,
+            Err(e) => Err(e),
+        };
 
-        match result {
-            Ok(Ok((exit_code, stdout, stderr))) => {
-                Ok(ExecutionResult {
-                    exit_code,
-                    stdout,
-                    stderr,
-                    duration: start.elapsed(),
-                    timed_out: false,
-                })
+        if let Some(cgroup) = cgroup {
+            if let Ok(ref mut result) = result {
+                if !result.success() {
+                    result.limit_exceeded = cgroup.exceeded().await;
+                }
             }
-            Ok(Err(e)) => Err(e),
-            Err(_) => {
-                // Timeout - kill the process
-                warn!("Command timed out, killing process");
-                let _ = child.kill().await;
+            cgroup.remove().await;
+        }
 
-                Ok(ExecutionResult {
-                    exit_code: -1,
-                    stdout: String::new(),
-                    stderr: "Command timed out".to_string(),
-                    duration: start.elapsed(),
-                    timed_out: true,
-                })
+        if let Some(trace_path) = trace_path {
+            if let Ok(ref mut result) = result {
+                result.write_audit = Some(self.audit_writes(ctx, &trace_path).await);
             }
+            let _ = tokio::fs::remove_file(&trace_path).await;
         }
+
+        if let Err(e) = tokio::fs::remove_file(&script_path).await {
+            warn!("Failed to remove script {:?}: {}", script_path, e);
+        }
+
+        result
     }
 
     async fn prepare(&self, ctx: &ExecutionContext) -> Result<()> {
@@ -137,11 +393,23 @@ impl Executor for ShellExecutor {
         Ok(())
     }
 
-    async fn cleanup(&self, ctx: &ExecutionContext) -> Result<()> {
-        if self.config.cleanup_workspace {
-            if let Err(e) = tokio::fs::remove_dir_all(&ctx.working_directory).await {
-                warn!("Failed to cleanup workspace: {}", e);
-            }
+    async fn cleanup(&self, _ctx: &ExecutionContext) -> Result<()> {
+        // The workspace outlives steps; the job's cleanup policy removes it
+        Ok(())
+    }
+
+    fn stop_allowance(&self) -> Duration {
+        Duration::from_secs(self.config.kill_grace_secs) + OUTPUT_DRAIN_TIMEOUT
+    }
+
+    async fn finish_job(&self, job_id: &str) -> Result<()> {
+        if cgroup_v2_available() {
+            remove_job_cgroups(&self.config.cgroup_parent, job_id).await;
+        }
+        if let Some(uid) = isolation::release(job_id) {
+            #[cfg(unix)]
+            isolation::kill_processes(uid).await;
+            debug!("Released job user {} of job {}", uid, job_id);
         }
         Ok(())
     }
@@ -160,3 +428,184 @@ impl Executor for ShellExecutor {
         ExecutorType::Shell
     }
 }
+
+/// Variables of the runner's environment `vars` a step inherits, by
+/// `env_passthrough` and `env_blocklist`
+fn inherited_env(
+    config: &ShellConfig,
+    isolated: bool,
+    vars: impl Iterator<Item = (OsString, OsString)>,
+) -> Vec<(OsString, OsString)> {
+    let matches = |patterns: &[String], key: &str| {
+        patterns.iter().any(|pattern| match pattern.strip_suffix('*') {
+            Some(prefix) => key.starts_with(prefix),
+            None => key == pattern,
+        })
+    };
+    let inherit_all = !isolated && config.env_passthrough.is_empty();
+
+    vars.filter(|(key, _)| {
+        let Some(key) = key.to_str() else {
+            return inherit_all;
+        };
+        let passed = inherit_all || isolation::INHERITED_ENV.contains(&key) || matches(&config.env_passthrough, key);
+        passed && !matches(&config.env_blocklist, key)
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::step_context;
+    use tokio::sync::mpsc;
+
+    fn temp_workspace() -> std::path::PathBuf {
+        let workspace = std::env::temp_dir().join(format!("muelsyse-shell-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&workspace).unwrap();
+        workspace
+    }
+
+    /// Whether the process whose pid the step wrote to `pid_file` is alive
+    async fn background_alive(pid_file: &Path) -> bool {
+        let pid = tokio::fs::read_to_string(pid_file).await.unwrap();
+        for _ in 0..50 {
+            if !Path::new(&format!("/proc/{}", pid.trim())).exists() {
+                return false;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        true
+    }
+
+    #[test]
+    fn test_inherited_env() {
+        let vars = || {
+            ["PATH", "HOME", "AWS_SECRET_ACCESS_KEY", "AWS_REGION", "JAVA_HOME"]
+                .into_iter()
+                .map(|key| (OsString::from(key), OsString::from("x")))
+        };
+        let keys = |config: &ShellConfig, isolated| -> Vec<String> {
+            inherited_env(config, isolated, vars()).into_iter().map(|(key, _)| key.into_string().unwrap()).collect()
+        };
+
+        assert_eq!(keys(&ShellConfig::default(), false).len(), 5);
+        assert_eq!(keys(&ShellConfig::default(), true), vec!["PATH"]);
+
+        let config = ShellConfig {
+            env_passthrough: vec!["HOME".into(), "AWS_*".into()],
+            env_blocklist: vec!["AWS_SECRET_*".into()],
+            ..ShellConfig::default()
+        };
+        assert_eq!(keys(&config, false), vec!["PATH", "HOME", "AWS_REGION"]);
+
+        let blocked = ShellConfig { env_blocklist: vec!["PATH".into(), "JAVA_*".into()], ..ShellConfig::default() };
+        assert_eq!(keys(&blocked, false), vec!["HOME", "AWS_SECRET_ACCESS_KEY", "AWS_REGION"]);
+    }
+
+    #[tokio::test]
+    async fn test_output_order_after_stdout_closes() {
+        let workspace = temp_workspace();
+        let command = "echo one\nsleep 0.1\necho two >&2\nsleep 0.1\necho three\nexec 1>&-\nsleep 0.1\necho four >&2";
+        let ctx = step_context(&workspace, command, Duration::from_secs(30));
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let result = ShellExecutor::new(ShellConfig::default()).execute(&ctx, &tx).await.unwrap();
+        assert!(result.success());
+        assert_eq!(result.stdout, "one\nthree");
+        assert_eq!(result.stderr, "two\nfour");
+        assert_eq!(result.output, "one\ntwo\nthree\nfour");
+
+        drop(tx);
+        let mut streamed = Vec::new();
+        while let Some(line) = rx.recv().await {
+            streamed.push((line.stream, line.text));
+        }
+        assert_eq!(streamed, vec![
+            (OutputStream::Stdout, "one".to_string()),
+            (OutputStream::Stderr, "two".to_string()),
+            (OutputStream::Stdout, "three".to_string()),
+            (OutputStream::Stderr, "four".to_string()),
+        ]);
+        let _ = std::fs::remove_dir_all(&workspace);
+    }
+
+    #[tokio::test]
+    async fn test_temp_dir() {
+        let workspace = temp_workspace();
+        let temp_dir = workspace.join(".tmp");
+        std::fs::create_dir_all(&temp_dir).unwrap();
+        let mut ctx = step_context(&workspace, "echo \"$TMPDIR $TEMP $TMP\"", Duration::from_secs(30));
+        ctx.temp_dir = Some(temp_dir.clone());
+
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let result = ShellExecutor::new(ShellConfig::default()).execute(&ctx, &tx).await.unwrap();
+        let expected = temp_dir.display().to_string();
+        assert_eq!(result.stdout, [expected.as_str(); 3].join(" "));
+        let _ = std::fs::remove_dir_all(&workspace);
+    }
+
+    #[tokio::test]
+    async fn test_gpus() {
+        let workspace = temp_workspace();
+        let mut ctx = step_context(&workspace, "echo \"${CUDA_VISIBLE_DEVICES-unset}\"", Duration::from_secs(30));
+        let executor = ShellExecutor::new(ShellConfig::default());
+        let (tx, _rx) = mpsc::unbounded_channel();
+        assert_eq!(executor.execute(&ctx, &tx).await.unwrap().stdout, "unset");
+
+        ctx.gpus = vec![1, 3];
+        assert_eq!(executor.execute(&ctx, &tx).await.unwrap().stdout, "1,3");
+        let _ = std::fs::remove_dir_all(&workspace);
+    }
+
+    #[tokio::test]
+    async fn test_process_group_stopped() {
+        let workspace = temp_workspace();
+        let config = ShellConfig { kill_grace_secs: 1, ..ShellConfig::default() };
+        let executor = ShellExecutor::new(config);
+        let (tx, _rx) = mpsc::unbounded_channel();
+
+        // Timed out: the background process goes with the shell
+        let ctx = step_context(&workspace, "sleep 60 &\necho $! > timeout.pid\nwait", Duration::from_secs(1));
+        let result = executor.execute(&ctx, &tx).await.unwrap();
+        assert!(result.timed_out);
+        assert!(!background_alive(&workspace.join("timeout.pid")).await);
+
+        // Timed out steps that exit on SIGTERM are stopped gracefully, the
+        // ones ignoring it are killed after the grace period
+        let ctx = step_context(&workspace, "exec sleep 60", Duration::from_secs(1));
+        let result = executor.execute(&ctx, &tx).await.unwrap();
+        assert_eq!(result.termination, Some(Termination::Graceful));
+        let ctx = step_context(&workspace, "trap '' TERM\nexec sleep 60", Duration::from_secs(1));
+        let result = executor.execute(&ctx, &tx).await.unwrap();
+        assert_eq!(result.termination, Some(Termination::Forced));
+
+        // Cancelled: stopped right away
+        let ctx = step_context(&workspace, "sleep 60 &\necho $! > cancel.pid\nwait", Duration::from_secs(60));
+        let cancel = ctx.cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            cancel.cancel();
+        });
+        let started = Instant::now();
+        let cancelled = executor.execute(&ctx, &tx).await;
+        assert_eq!(cancelled.unwrap_err().to_string(), "Step cancelled");
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(!background_alive(&workspace.join("cancel.pid")).await);
+
+        // Abandoned without being cancelled
+        let ctx = step_context(&workspace, "sleep 60 &\necho $! > abandoned.pid\nwait", Duration::from_secs(60));
+        let abandoned = tokio::time::timeout(Duration::from_millis(500), executor.execute(&ctx, &tx)).await;
+        assert!(abandoned.is_err());
+        assert!(!background_alive(&workspace.join("abandoned.pid")).await);
+
+        // A finished step's background processes are left alone
+        let ctx = step_context(&workspace, "sleep 60 >/dev/null 2>&1 &\necho $! > done.pid", Duration::from_secs(60));
+        assert!(executor.execute(&ctx, &tx).await.unwrap().success());
+        let pid = std::fs::read_to_string(workspace.join("done.pid")).unwrap();
+        assert!(Path::new(&format!("/proc/{}", pid.trim())).exists());
+        let _ = std::process::Command::new("kill").arg(pid.trim()).status();
+
+        let _ = std::fs::remove_dir_all(&workspace);
+    }
+}
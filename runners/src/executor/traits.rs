@@ -2,15 +2,27 @@
 
 use async_trait::async_trait;
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::path::PathBuf;
 use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+use super::audit::WriteAttempt;
+use super::cgroup::{LimitExceeded, ResourceLimits};
+use super::encoding::OutputEncoding;
+use super::usage::ResourceUsage;
+use super::output::OutputSink;
+use crate::cache::CacheVolume;
 
 /// Type of executor
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ExecutorType {
     Shell,
     Docker,
+    Kubernetes,
+    /// Registered with [`super::register_executor`]
+    Custom,
 }
 
 impl ExecutorType {
@@ -18,11 +30,33 @@ impl ExecutorType {
         match s.to_lowercase().as_str() {
             "shell" => Some(Self::Shell),
             "docker" => Some(Self::Docker),
+            "kubernetes" => Some(Self::Kubernetes),
             _ => None,
         }
     }
 }
 
+/// Phase of a step's lifecycle, each with its own time budget
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ExecutionPhase {
+    /// Workspace setup and image pull
+    Prepare,
+    /// Running the step command
+    Execute,
+    /// Log upload and output collection
+    Collect,
+}
+
+impl std::fmt::Display for ExecutionPhase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Prepare => write!(f, "prepare"),
+            Self::Execute => write!(f, "execute"),
+            Self::Collect => write!(f, "collect"),
+        }
+    }
+}
+
 /// Context for command execution
 #[derive(Debug, Clone)]
 pub struct ExecutionContext {
@@ -41,6 +75,9 @@ pub struct ExecutionContext {
     /// Working directory
     pub working_directory: PathBuf,
 
+    /// Job workspace root, containing the working directory
+    pub workspace: PathBuf,
+
     /// Environment variables
     pub environment: HashMap<String, String>,
 
@@ -52,16 +89,73 @@ pub struct ExecutionContext {
 
     /// Container options
     pub container_options: Option<ContainerOptions>,
+
+    /// Encoding of the step's output
+    pub output_encoding: OutputEncoding,
+
+    /// Send SIGUSR2 to the step this long after it starts, as an early
+    /// warning before the timeout kills it
+    pub warning_signal_after: Option<Duration>,
+
+    /// Cancelled when the job is; executors then stop the step right away
+    pub cancel: CancellationToken,
+
+    /// The step's own CPU, memory and process limits (shell executor)
+    pub limits: ResourceLimits,
+
+    /// The job's temporary directory, exported as `TMPDIR`, `TEMP` and `TMP`
+    /// (shell and Docker executors)
+    pub temp_dir: Option<PathBuf>,
+
+    /// Indices of the GPUs leased to the job, exposed as device requests or
+    /// `CUDA_VISIBLE_DEVICES` (shell and Docker executors)
+    pub gpus: Vec<u32>,
+}
+
+impl ExecutionContext {
+    /// Context running `command` within `timeout` in the environment of this
+    /// step, with its own step id (`<step>-<suffix>`) and so its own
+    /// container and script
+    pub fn companion(&self, suffix: &str, command: &str, timeout: Duration) -> Self {
+        Self {
+            step_id: format!("{}-{}", self.step_id, suffix),
+            command: command.to_string(),
+            timeout,
+            warning_signal_after: None,
+            ..self.clone()
+        }
+    }
+}
+
+/// How a job's steps use Docker containers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ContainerMode {
+    /// A fresh container for every step
+    #[default]
+    PerStep,
+    /// One container for the whole job, with each step run by `docker exec`,
+    /// so files outside the workspace persist between steps
+    PerJob,
 }
 
 /// Container execution options
 #[derive(Debug, Clone, Default)]
 pub struct ContainerOptions {
+    pub mode: ContainerMode,
     pub env: HashMap<String, String>,
     pub volumes: Vec<String>,
     pub network_mode: Option<String>,
     pub memory_limit: Option<u64>,
     pub cpu_limit: Option<f64>,
+    /// Host devices to pass through (`host[:container[:permissions]]`)
+    pub devices: Vec<String>,
+    /// Size of /dev/shm in bytes
+    pub shm_size: Option<u64>,
+    /// Namespaced kernel parameters to set in the container
+    pub sysctls: HashMap<String, String>,
+    /// Named volumes kept between jobs
+    pub cache_volumes: Vec<CacheVolume>,
 }
 
 /// Result of command execution
@@ -76,11 +170,26 @@ pub struct ExecutionResult {
     /// Standard error
     pub stderr: String,
 
+    /// Lines of both streams in the order they arrived
+    pub output: String,
+
     /// Execution duration
     pub duration: Duration,
 
     /// Whether the command was killed due to timeout
     pub timed_out: bool,
+
+    /// Writes outside the workspace, if the step was audited
+    pub write_audit: Option<Vec<WriteAttempt>>,
+
+    /// CPU and memory used by the step, if the executor measures them
+    pub usage: Option<ResourceUsage>,
+
+    /// The limit that made the step fail, if the executor enforces limits
+    pub limit_exceeded: Option<LimitExceeded>,
+
+    /// How a timed out step was stopped, if the executor knows
+    pub termination: Option<Termination>,
 }
 
 impl ExecutionResult {
@@ -89,11 +198,30 @@ impl ExecutionResult {
     }
 }
 
+/// How a timed out step was stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Termination {
+    /// It exited within the grace period after SIGTERM
+    Graceful,
+    /// It was killed once the grace period was over
+    Forced,
+}
+
+impl Termination {
+    /// Stable name reported as the step's `termination`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Graceful => "graceful",
+            Self::Forced => "forced",
+        }
+    }
+}
+
 /// Trait for job executors
 #[async_trait]
 pub trait Executor: Send + Sync {
-    /// Execute a command
-    async fn execute(&self, ctx: &ExecutionContext) -> Result<ExecutionResult>;
+    /// Execute a command, forwarding output lines to `output` as they arrive
+    async fn execute(&self, ctx: &ExecutionContext, output: &OutputSink) -> Result<ExecutionResult>;
 
     /// Prepare execution environment
     async fn prepare(&self, ctx: &ExecutionContext) -> Result<()>;
@@ -101,6 +229,17 @@ pub trait Executor: Send + Sync {
     /// Cleanup after execution
     async fn cleanup(&self, ctx: &ExecutionContext) -> Result<()>;
 
+    /// Release job-wide resources once all steps are done
+    async fn finish_job(&self, _job_id: &str) -> Result<()> {
+        Ok(())
+    }
+
+    /// How long a step that reached `ctx.timeout` may take to be stopped
+    /// and have its remaining output forwarded
+    fn stop_allowance(&self) -> Duration {
+        Duration::ZERO
+    }
+
     /// Check if executor is healthy
     async fn health_check(&self) -> Result<bool>;
 
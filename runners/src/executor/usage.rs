@@ -0,0 +1,241 @@
+//! Resource usage of steps
+//!
+//! The shell executor samples the step's process group through `/proc`; the
+//! Docker executor follows the container's stats stream. Both see the step
+//! about once a second, so processes living shorter than that may be missed.
+
+use bollard::Docker;
+use bollard::container::StatsOptions;
+use futures_util::StreamExt;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How often a process group is sampled
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(500);
+/// Clock ticks per second of CPU times in `/proc/<pid>/stat` (`USER_HZ`)
+const CLOCK_TICKS: u64 = 100;
+
+/// CPU time, peak memory and disk used by a step
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResourceUsage {
+    /// User and system CPU time of the step's processes
+    pub cpu_time: Duration,
+    /// Largest resident memory of the step's processes at any sample
+    pub peak_memory_bytes: u64,
+    /// Size of the workspace after the step, if measured
+    pub disk_bytes: Option<u64>,
+    /// Size of the job's temporary directory after the step, if measured
+    pub temp_bytes: Option<u64>,
+}
+
+impl ResourceUsage {
+    /// Add a later step's usage: CPU times add up, the rest keep their peak
+    pub fn merge(&mut self, other: &ResourceUsage) {
+        self.cpu_time += other.cpu_time;
+        self.peak_memory_bytes = self.peak_memory_bytes.max(other.peak_memory_bytes);
+        self.disk_bytes = peak(self.disk_bytes, other.disk_bytes);
+        self.temp_bytes = peak(self.temp_bytes, other.temp_bytes);
+    }
+
+    /// Render as status update outputs
+    pub fn to_outputs(&self) -> HashMap<String, String> {
+        let mut outputs = HashMap::from([
+            ("cpu_time_ms".to_string(), self.cpu_time.as_millis().to_string()),
+            ("peak_memory_bytes".to_string(), self.peak_memory_bytes.to_string()),
+        ]);
+        if let Some(disk) = self.disk_bytes {
+            outputs.insert("disk_bytes".to_string(), disk.to_string());
+        }
+        if let Some(temp) = self.temp_bytes {
+            outputs.insert("temp_bytes".to_string(), temp.to_string());
+        }
+        outputs
+    }
+}
+
+fn peak(a: Option<u64>, b: Option<u64>) -> Option<u64> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.max(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// Collects a step's usage in the background until stopped
+pub(super) struct UsageSampler {
+    usage: Arc<Mutex<ResourceUsage>>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl UsageSampler {
+    /// Sample the processes of group `pgid`, if `/proc` is available
+    pub fn process_group(pgid: u32) -> Option<Self> {
+        if !Path::new("/proc/self/stat").exists() {
+            return None;
+        }
+        let usage = Arc::new(Mutex::new(ResourceUsage::default()));
+        let shared = usage.clone();
+        let task = tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(SAMPLE_INTERVAL);
+            loop {
+                ticker.tick().await;
+                let sample = tokio::task::spawn_blocking(move || sample_group(Path::new("/proc"), pgid))
+                    .await
+                    .unwrap_or_default();
+                record(&shared, sample);
+            }
+        });
+        Some(Self { usage, task })
+    }
+
+    /// Follow the stats of `container`. Only CPU time used from now on counts
+    /// unless `fresh`, for a container started for the step.
+    pub fn container(docker: Docker, container: String, fresh: bool) -> Self {
+        let usage = Arc::new(Mutex::new(ResourceUsage::default()));
+        let shared = usage.clone();
+        let task = tokio::spawn(async move {
+            let options = StatsOptions { stream: true, one_shot: false };
+            let mut stats = docker.stats(&container, Some(options));
+            let mut baseline = None;
+            while let Some(Ok(stats)) = stats.next().await {
+                let total = stats.cpu_stats.cpu_usage.total_usage;
+                // A stopped container reports zeros
+                if total == 0 {
+                    continue;
+                }
+                let start = *baseline.get_or_insert(if fresh { 0 } else { total });
+                let memory = &stats.memory_stats;
+                let sample = ResourceUsage {
+                    cpu_time: Duration::from_nanos(total.saturating_sub(start)),
+                    peak_memory_bytes: memory.max_usage.unwrap_or(0).max(memory.usage.unwrap_or(0)),
+                    disk_bytes: None,
+                    temp_bytes: None,
+                };
+                record(&shared, sample);
+            }
+        });
+        Self { usage, task }
+    }
+
+    /// Stop sampling, returning the usage seen so far
+    pub fn stop(self) -> ResourceUsage {
+        *self.usage.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Drop for UsageSampler {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Keep the highest CPU time and memory seen
+fn record(usage: &Mutex<ResourceUsage>, sample: ResourceUsage) {
+    let mut usage = usage.lock().unwrap_or_else(|e| e.into_inner());
+    usage.cpu_time = usage.cpu_time.max(sample.cpu_time);
+    usage.peak_memory_bytes = usage.peak_memory_bytes.max(sample.peak_memory_bytes);
+}
+
+/// CPU time and memory of the live processes in group `pgid`.
+///
+/// CPU time includes children the processes have already waited for.
+fn sample_group(proc: &Path, pgid: u32) -> ResourceUsage {
+    let Ok(entries) = std::fs::read_dir(proc) else {
+        return ResourceUsage::default();
+    };
+
+    let mut ticks = 0;
+    let mut rss_kb = 0;
+    let mut peak_kb = 0;
+    for entry in entries.filter_map(|e| e.ok()) {
+        let path = entry.path();
+        let is_pid = entry.file_name().to_str().is_some_and(|name| name.bytes().all(|b| b.is_ascii_digit()));
+        if !is_pid {
+            continue;
+        }
+        let Some((group, cpu)) = std::fs::read_to_string(path.join("stat")).ok().as_deref().and_then(parse_stat) else {
+            continue;
+        };
+        if group != pgid {
+            continue;
+        }
+        ticks += cpu;
+        if let Ok(status) = std::fs::read_to_string(path.join("status")) {
+            rss_kb += status_kb(&status, "VmRSS:").unwrap_or(0);
+            peak_kb = peak_kb.max(status_kb(&status, "VmHWM:").unwrap_or(0));
+        }
+    }
+
+    ResourceUsage {
+        cpu_time: Duration::from_millis(ticks * 1000 / CLOCK_TICKS),
+        peak_memory_bytes: rss_kb.max(peak_kb) * 1024,
+        disk_bytes: None,
+        temp_bytes: None,
+    }
+}
+
+/// Process group and CPU ticks (own and waited-for children's) from
+/// `/proc/<pid>/stat`
+fn parse_stat(stat: &str) -> Option<(u32, u64)> {
+    // The command name may contain spaces and parentheses
+    let fields: Vec<&str> = stat.get(stat.rfind(')')? + 1..)?.split_whitespace().collect();
+    let group = fields.get(2)?.parse().ok()?;
+    let ticks = fields.get(11..15)?
+        .iter()
+        .map(|field| field.parse::<u64>().ok())
+        .sum::<Option<u64>>()?;
+    Some((group, ticks))
+}
+
+/// A `kB` value from `/proc/<pid>/status`
+fn status_kb(status: &str, key: &str) -> Option<u64> {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix(key))
+        .and_then(|value| value.split_whitespace().next())
+        .and_then(|value| value.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_stat() {
+        let stat = "4242 (my (odd) cmd) S 1 4240 4240 0 -1 4194560 120 0 0 0 250 30 12 8 20 0 1 0 100 1000 300 0";
+        assert_eq!(parse_stat(stat), Some((4240, 300)));
+        assert_eq!(parse_stat("4242 (truncated) S 1"), None);
+
+        let status = "Name:\tcargo\nVmHWM:\t  20480 kB\nVmRSS:\t  10240 kB\n";
+        assert_eq!(status_kb(status, "VmRSS:"), Some(10240));
+        assert_eq!(status_kb(status, "VmSwap:"), None);
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut job = ResourceUsage::default();
+        job.merge(&ResourceUsage { cpu_time: Duration::from_secs(2), peak_memory_bytes: 100, disk_bytes: Some(10), temp_bytes: Some(4) });
+        job.merge(&ResourceUsage { cpu_time: Duration::from_secs(3), peak_memory_bytes: 50, disk_bytes: None, temp_bytes: Some(8) });
+        assert_eq!(job, ResourceUsage { cpu_time: Duration::from_secs(5), peak_memory_bytes: 100, disk_bytes: Some(10), temp_bytes: Some(8) });
+        assert_eq!(job.to_outputs()["cpu_time_ms"], "5000");
+        assert_eq!(job.to_outputs()["temp_bytes"], "8");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_sample_group() {
+        let mut child = tokio::process::Command::new("sleep")
+            .arg("5")
+            .process_group(0)
+            .kill_on_drop(true)
+            .spawn()
+            .unwrap();
+        // The child joins its group between fork and exec
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let usage = sample_group(Path::new("/proc"), child.id().unwrap());
+        assert!(usage.peak_memory_bytes > 0);
+        assert_eq!(sample_group(Path::new("/proc"), u32::MAX), ResourceUsage::default());
+        child.kill().await.unwrap();
+    }
+}
@@ -0,0 +1,278 @@
+//! Job admission
+//!
+//! Decides whether an assigned job starts now, waits in the runner's local
+//! pending queue, or is rejected. Rejections carry a structured reason that
+//! is reported back to the control plane.
+
+use std::collections::HashMap;
+
+use crate::client::JobSpec;
+use crate::config::{ConfigOverrides, Settings};
+use super::scheduler::Scheduler;
+use super::validate::FieldError;
+
+/// Why a job was rejected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rejection {
+    /// The job requires labels this runner does not have
+    LabelMismatch { missing: Vec<String> },
+    /// Untrusted jobs may not run on the host
+    UntrustedWithoutContainer,
+    /// The job names an executor this runner has not enabled
+    ExecutorUnavailable { executor: String },
+    /// The job asks for more GPUs than the host has
+    GpusUnavailable { requested: u32, available: u32 },
+    /// Every slot and the pending queue are full
+    AtCapacity,
+    /// The runner is draining and takes no new jobs
+    Draining,
+    /// The job spec is malformed
+    InvalidSpec { errors: Vec<FieldError> },
+}
+
+impl Rejection {
+    /// Stable reason code
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Self::LabelMismatch { .. } => "label_mismatch",
+            Self::UntrustedWithoutContainer => "untrusted_requires_container",
+            Self::ExecutorUnavailable { .. } => "executor_unavailable",
+            Self::GpusUnavailable { .. } => "gpus_unavailable",
+            Self::AtCapacity => "runner_at_capacity",
+            Self::Draining => "runner_draining",
+            Self::InvalidSpec { .. } => "invalid_spec",
+        }
+    }
+
+    /// Outputs of the `rejected` status update
+    pub fn to_outputs(&self) -> HashMap<String, String> {
+        let mut outputs = HashMap::from([("reason".to_string(), self.reason().to_string())]);
+        match self {
+            Self::LabelMismatch { missing } => {
+                outputs.insert("missing_labels".to_string(), missing.join(","));
+            }
+            Self::ExecutorUnavailable { executor } => {
+                outputs.insert("executor".to_string(), executor.clone());
+            }
+            Self::GpusUnavailable { requested, available } => {
+                outputs.insert("requested_gpus".to_string(), requested.to_string());
+                outputs.insert("available_gpus".to_string(), available.to_string());
+            }
+            Self::InvalidSpec { errors } => {
+                let fields: Vec<&str> = errors.iter().map(|e| e.field.as_str()).collect();
+                let lines: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                outputs.insert("invalid_fields".to_string(), fields.join(","));
+                outputs.insert("errors".to_string(), lines.join("\n"));
+            }
+            _ => {}
+        }
+        outputs
+    }
+}
+
+/// Outcome of admitting a job
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Admission {
+    Start,
+    /// Accepted into the pending queue
+    Queue,
+    Reject(Rejection),
+}
+
+/// Label and capacity limits of this runner
+#[derive(Debug, Clone)]
+pub struct AdmissionPolicy {
+    labels: Vec<String>,
+    /// Enabled executors, by name
+    executors: Vec<String>,
+    max_running: u32,
+    max_pending: usize,
+    /// Drained by the control plane's overrides
+    draining: bool,
+    /// Drained by a `drain` command; not kept across restarts
+    drain_requested: bool,
+}
+
+impl From<&Settings> for AdmissionPolicy {
+    fn from(settings: &Settings) -> Self {
+        let config = &settings.runner;
+        Self {
+            labels: config.labels.clone(),
+            executors: settings.executor.enabled.clone(),
+            max_running: config.max_concurrent_jobs as u32,
+            max_pending: config.max_pending_jobs,
+            draining: false,
+            drain_requested: false,
+        }
+    }
+}
+
+impl AdmissionPolicy {
+    /// Apply the control plane's overrides on top of the configured limits
+    pub fn with_overrides(mut self, overrides: &ConfigOverrides) -> Self {
+        if let Some(max) = overrides.max_concurrent_jobs {
+            self.max_running = max as u32;
+        }
+        if let Some(ref labels) = overrides.labels {
+            self.labels = labels.clone();
+        }
+        self.draining = overrides.drain.unwrap_or(false);
+        self
+    }
+
+    pub fn labels(&self) -> &[String] {
+        &self.labels
+    }
+
+    pub fn max_running(&self) -> u32 {
+        self.max_running
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining || self.drain_requested
+    }
+
+    /// Job slots free for new jobs with `running` jobs running; none while
+    /// draining
+    pub fn capacity(&self, running: u32) -> u32 {
+        if self.is_draining() { 0 } else { self.max_running.saturating_sub(running) }
+    }
+
+    /// Queue slots free for jobs accepted while at capacity; none while
+    /// draining
+    pub fn pending_capacity(&self, pending: usize) -> usize {
+        if self.is_draining() { 0 } else { self.max_pending.saturating_sub(pending) }
+    }
+
+    pub fn drain_requested(&self) -> bool {
+        self.drain_requested
+    }
+
+    /// Start or stop a drain independent of the overrides
+    pub fn request_drain(&mut self, drain: bool) {
+        self.drain_requested = drain;
+    }
+
+    /// Decide for `job`, given the running and pending jobs
+    pub fn admit(&self, job: &JobSpec, scheduler: &Scheduler) -> Admission {
+        let missing: Vec<String> = job.labels
+            .iter()
+            .filter(|label| !self.labels.contains(label))
+            .cloned()
+            .collect();
+
+        if self.is_draining() {
+            Admission::Reject(Rejection::Draining)
+        } else if !missing.is_empty() {
+            Admission::Reject(Rejection::LabelMismatch { missing })
+        } else if job.untrusted && job.container.is_none() {
+            Admission::Reject(Rejection::UntrustedWithoutContainer)
+        } else if let Some(executor) = job.executor.as_ref().filter(|e| !self.executors.contains(e)) {
+            Admission::Reject(Rejection::ExecutorUnavailable { executor: executor.clone() })
+        } else if job.gpus > scheduler.gpus() {
+            Admission::Reject(Rejection::GpusUnavailable { requested: job.gpus, available: scheduler.gpus() })
+        } else if scheduler.can_start(job, self.max_running) {
+            Admission::Start
+        } else if scheduler.pending_len() < self.max_pending {
+            Admission::Queue
+        } else {
+            Admission::Reject(Rejection::AtCapacity)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::JobSpecBuilder;
+
+    fn job(labels: &[&str], untrusted: bool) -> JobSpec {
+        JobSpecBuilder::new("job-1")
+            .with("labels", serde_json::json!(labels))
+            .with("untrusted", serde_json::json!(untrusted))
+            .build()
+    }
+
+    /// A scheduler with `running` jobs and `pending` queued ones
+    fn load(running: u32, pending: usize) -> Scheduler {
+        let mut scheduler = Scheduler::default();
+        for i in 0..running {
+            scheduler.start(&JobSpec { job_id: format!("running-{}", i), ..job(&[], false) });
+        }
+        for i in 0..pending {
+            scheduler.enqueue(JobSpec { job_id: format!("pending-{}", i), ..job(&[], false) });
+        }
+        scheduler
+    }
+
+    #[test]
+    fn test_admit() {
+        let policy = AdmissionPolicy {
+            labels: vec!["linux".into(), "docker".into()],
+            executors: vec!["shell".into(), "docker".into()],
+            max_running: 2,
+            max_pending: 1,
+            draining: false,
+            drain_requested: false,
+        };
+        let linux = job(&["linux"], false);
+
+        assert_eq!(policy.admit(&linux, &load(1, 0)), Admission::Start);
+        assert_eq!(policy.capacity(1), 1);
+        assert_eq!(policy.capacity(3), 0);
+        assert_eq!(policy.admit(&job(&[], false), &load(2, 0)), Admission::Queue);
+        assert_eq!(policy.admit(&linux, &load(2, 1)), Admission::Reject(Rejection::AtCapacity));
+        assert_eq!(policy.pending_capacity(0), 1);
+        assert_eq!(policy.pending_capacity(1), 0);
+        assert_eq!(
+            policy.admit(&job(&["linux"], true), &load(0, 0)),
+            Admission::Reject(Rejection::UntrustedWithoutContainer)
+        );
+
+        let mut remote = job(&["linux"], false);
+        remote.executor = Some("firecracker".into());
+        let rejection = Rejection::ExecutorUnavailable { executor: "firecracker".into() };
+        assert_eq!(policy.admit(&remote, &load(0, 0)), Admission::Reject(rejection.clone()));
+        assert_eq!(rejection.to_outputs()["executor"], "firecracker");
+        remote.executor = Some("docker".into());
+        assert_eq!(policy.admit(&remote, &load(0, 0)), Admission::Start);
+
+        let mut training = job(&["linux"], false);
+        training.gpus = 2;
+        let rejection = Rejection::GpusUnavailable { requested: 2, available: 0 };
+        assert_eq!(policy.admit(&training, &load(0, 0)), Admission::Reject(rejection.clone()));
+        assert_eq!(rejection.to_outputs()["available_gpus"], "0");
+        assert_eq!(policy.admit(&training, &load(0, 0).with_gpus(2)), Admission::Start);
+
+        let gpu = job(&["linux", "gpu", "arm64"], false);
+        let rejection = Rejection::LabelMismatch { missing: vec!["gpu".into(), "arm64".into()] };
+        assert_eq!(policy.admit(&gpu, &load(0, 0)), Admission::Reject(rejection.clone()));
+
+        let outputs = rejection.to_outputs();
+        assert_eq!(outputs["reason"], "label_mismatch");
+        assert_eq!(outputs["missing_labels"], "gpu,arm64");
+
+        // Overrides from the control plane
+        let overrides = ConfigOverrides {
+            max_concurrent_jobs: Some(3),
+            labels: Some(vec!["linux".into(), "gpu".into(), "arm64".into()]),
+            ..Default::default()
+        };
+        let policy = policy.with_overrides(&overrides);
+        assert_eq!(policy.admit(&gpu, &load(2, 0)), Admission::Start);
+
+        // A drain command applies whatever the overrides say
+        let mut policy = policy.with_overrides(&overrides);
+        policy.request_drain(true);
+        assert_eq!(policy.admit(&linux, &load(0, 0)), Admission::Reject(Rejection::Draining));
+        assert_eq!(policy.capacity(0), 0);
+        assert_eq!(policy.pending_capacity(0), 0);
+        policy.request_drain(false);
+        assert_eq!(policy.admit(&linux, &load(0, 0)), Admission::Start);
+
+        let draining = ConfigOverrides { drain: Some(true), ..overrides };
+        let policy = policy.with_overrides(&draining);
+        assert!(policy.is_draining());
+        assert_eq!(policy.admit(&linux, &load(0, 0)), Admission::Reject(Rejection::Draining));
+    }
+}
@@ -0,0 +1,379 @@
+//! Step expression contexts
+//!
+//! Resolves `${{ ... }}` templates and `if:` conditions against the job's
+//! environment and the results of earlier steps, addressed by their
+//! user-facing `id`:
+//!
+//! - `env.<name>` - a variable of the current step's environment, one written
+//!   to `MUELSYSE_ENV` by an earlier step, or one of the job's environment
+//! - `secrets.<name>` - a secret the current step may see
+//! - `steps.<id>.outputs.<name>` - an output of a previous step
+//! - `steps.<id>.outcome` - `success`, `failed`, `timeout` or `skipped`
+//! - `success()`, `failure()`, `always()` - status check functions
+//!
+//! For a step with `needs`, the status functions only look at the steps it
+//! needs: `success()` requires each of them to have succeeded (or failed
+//! with `continue_on_error`), `failure()` that one of them failed. A skipped
+//! need counts as neither, so its dependents are skipped too unless they
+//! use `always()`. The outputs of the steps it needs are also passed to
+//! its process as `MUELSYSE_NEEDS_<STEP>_<NAME>` variables, the step id and
+//! output name upper-cased with other characters than letters and digits
+//! replaced by `_`.
+//!
+//! Conditions support `==`, `!=`, `!`, `&&` and `||` (`&&` binds tighter).
+//! Operators are matched textually, so quoted literals must not contain them.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::client::StepSpec;
+use super::runner::StepStatus;
+
+/// Result of a finished step, as seen by later steps
+#[derive(Debug, Clone)]
+pub struct StepResult {
+    pub outcome: StepStatus,
+    pub outputs: HashMap<String, String>,
+}
+
+/// Results of the steps run so far in a job
+#[derive(Debug, Clone, Default)]
+pub struct StepsContext {
+    steps: HashMap<String, StepResult>,
+    failed: bool,
+    /// Steps that failed the job
+    failing: HashSet<String>,
+    /// A needed step was skipped
+    blocked: bool,
+    /// Steps the current step needs
+    needs: Vec<String>,
+    job_env: HashMap<String, String>,
+    /// Written to `MUELSYSE_ENV` by finished steps
+    exported_env: HashMap<String, String>,
+    /// Set by [`StepsContext::enter_step`] for the step about to run
+    step_env: HashMap<String, String>,
+    secrets: HashMap<String, String>,
+}
+
+impl StepsContext {
+    /// Context for a job with environment `job_env`
+    pub fn new(job_env: HashMap<String, String>) -> Self {
+        Self { job_env, ..Default::default() }
+    }
+
+    /// Scope the context to `step`, which may see `secrets`.
+    ///
+    /// The step's `env` is resolved here, so its values can refer to the
+    /// job environment but not to each other.
+    pub fn enter_step(&mut self, step: &StepSpec, secrets: HashMap<String, String>) {
+        self.secrets = secrets;
+        self.step_env.clear();
+        self.step_env = step.env
+            .iter()
+            .map(|(k, v)| (k.clone(), self.interpolate(v)))
+            .collect();
+    }
+
+    /// A copy whose status functions only consider the steps in `needs`
+    pub fn scoped_to_needs(&self, needs: &[String]) -> Self {
+        let mut scoped = self.clone();
+        scoped.needs = needs.to_vec();
+        scoped.failed = needs.iter().any(|id| self.failing.contains(id));
+        scoped.blocked = needs
+            .iter()
+            .any(|id| self.get(id).is_some_and(|s| s.outcome == StepStatus::Skipped));
+        scoped
+    }
+
+    /// The current step's resolved `env`
+    pub fn step_env(&self) -> &HashMap<String, String> {
+        &self.step_env
+    }
+
+    /// Variables exported by finished steps, which override the job's
+    /// environment but not a step's own `env`
+    pub fn exported_env(&self) -> &HashMap<String, String> {
+        &self.exported_env
+    }
+
+    /// `MUELSYSE_NEEDS_<STEP>_<NAME>` variables with the outputs of the
+    /// steps in `needs`
+    pub fn needs_env(&self) -> HashMap<String, String> {
+        let mut env = HashMap::new();
+        for id in &self.needs {
+            let Some(result) = self.get(id) else {
+                continue;
+            };
+            for (name, value) in &result.outputs {
+                env.insert(format!("MUELSYSE_NEEDS_{}_{}", env_name_part(id), env_name_part(name)), value.clone());
+            }
+        }
+        env
+    }
+
+    /// Add variables a step wrote to `MUELSYSE_ENV`
+    pub fn export_env(&mut self, env: HashMap<String, String>) {
+        self.exported_env.extend(env);
+    }
+
+    /// Record a finished step under its reference id
+    pub fn record(&mut self, step: &StepSpec, outcome: StepStatus, outputs: HashMap<String, String>) {
+        if matches!(outcome, StepStatus::Failed | StepStatus::Timeout | StepStatus::Cancelled) && !step.continue_on_error {
+            self.failed = true;
+            self.failing.insert(step.reference_id().to_string());
+        }
+
+        self.steps.insert(
+            step.reference_id().to_string(),
+            StepResult { outcome, outputs },
+        );
+    }
+
+    /// Get the result of a step by reference id
+    pub fn get(&self, id: &str) -> Option<&StepResult> {
+        self.steps.get(id)
+    }
+
+    /// Whether a previous step failed the job
+    pub fn has_failed(&self) -> bool {
+        self.failed
+    }
+
+    /// Replace every `${{ expr }}` in `text` with its value
+    pub fn interpolate(&self, text: &str) -> String {
+        let mut result = String::with_capacity(text.len());
+        let mut rest = text;
+
+        while let Some(start) = rest.find("${{") {
+            let Some(len) = rest[start..].find("}}") else {
+                break;
+            };
+
+            result.push_str(&rest[..start]);
+            result.push_str(&self.value(rest[start + 3..start + len].trim()));
+            rest = &rest[start + len + 2..];
+        }
+
+        result.push_str(rest);
+        result
+    }
+
+    /// Evaluate an `if:` condition
+    pub fn evaluate(&self, condition: &str) -> bool {
+        let condition = condition.trim();
+        let condition = condition
+            .strip_prefix("${{")
+            .and_then(|c| c.strip_suffix("}}"))
+            .unwrap_or(condition)
+            .trim();
+
+        condition.split("||").any(|any| {
+            any.split("&&").all(|term| self.evaluate_term(term.trim()))
+        })
+    }
+
+    fn evaluate_term(&self, term: &str) -> bool {
+        if let Some((left, right)) = term.split_once("!=") {
+            return self.value(left.trim()) != self.value(right.trim());
+        }
+        if let Some((left, right)) = term.split_once("==") {
+            return self.value(left.trim()) == self.value(right.trim());
+        }
+        if let Some(inner) = term.strip_prefix('!') {
+            return !self.evaluate_term(inner.trim());
+        }
+
+        is_truthy(&self.value(term))
+    }
+
+    /// Resolve a single expression operand to a string
+    fn value(&self, expr: &str) -> String {
+        if let Some(literal) = expr.strip_prefix('\'').and_then(|e| e.strip_suffix('\'')) {
+            return literal.replace("''", "'");
+        }
+
+        match expr {
+            "success()" => return (!self.failed && !self.blocked).to_string(),
+            "failure()" => return self.failed.to_string(),
+            "always()" => return "true".to_string(),
+            "cancelled()" => return "false".to_string(),
+            _ => {}
+        }
+
+        let parts: Vec<&str> = expr.split('.').collect();
+        match parts.as_slice() {
+            ["env", name] => self.step_env
+                .get(*name)
+                .or_else(|| self.exported_env.get(*name))
+                .or_else(|| self.job_env.get(*name))
+                .cloned()
+                .unwrap_or_default(),
+            ["secrets", name] => self.secrets.get(*name).cloned().unwrap_or_default(),
+            ["steps", id, "outputs", name] => self
+                .get(id)
+                .and_then(|s| s.outputs.get(*name).cloned())
+                .unwrap_or_default(),
+            ["steps", id, "outcome"] | ["steps", id, "conclusion"] => self
+                .get(id)
+                .map(|s| s.outcome.to_string())
+                .unwrap_or_default(),
+            // Numbers and booleans are literals
+            _ if expr.parse::<f64>().is_ok() || expr == "true" || expr == "false" => expr.to_string(),
+            _ => String::new(),
+        }
+    }
+}
+
+/// Truthiness of an expression value
+fn is_truthy(value: &str) -> bool {
+    !matches!(value, "" | "false" | "0")
+}
+
+/// `text` upper-cased, with anything but letters and digits replaced by `_`
+fn env_name_part(text: &str) -> String {
+    text.chars().map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(step_id: &str, id: Option<&str>) -> StepSpec {
+        serde_json::from_value(serde_json::json!({
+            "step_id": step_id,
+            "id": id,
+            "name": step_id,
+            "run": "true",
+        })).unwrap()
+    }
+
+    #[test]
+    fn test_interpolate_step_outputs() {
+        let mut ctx = StepsContext::default();
+        ctx.record(
+            &step("uuid-1", Some("build")),
+            StepStatus::Success,
+            HashMap::from([("version".to_string(), "1.2.3".to_string())]),
+        );
+
+        assert_eq!(
+            ctx.interpolate("echo ${{ steps.build.outputs.version }} (${{steps.build.outcome}})"),
+            "echo 1.2.3 (success)"
+        );
+        assert_eq!(ctx.interpolate("echo ${{ steps.missing.outputs.x }}!"), "echo !");
+        assert_eq!(ctx.interpolate("no templates"), "no templates");
+    }
+
+    #[test]
+    fn test_interpolate_env_and_secrets() {
+        let mut ctx = StepsContext::new(HashMap::from([
+            ("REGISTRY".to_string(), "ghcr.io".to_string()),
+            ("TARGET".to_string(), "debug".to_string()),
+        ]));
+        ctx.record(
+            &step("uuid-1", Some("build")),
+            StepStatus::Success,
+            HashMap::from([("version".to_string(), "1.2.3".to_string())]),
+        );
+
+        let mut deploy = step("uuid-2", Some("deploy"));
+        deploy.env = HashMap::from([
+            ("TARGET".to_string(), "release".to_string()),
+            ("IMAGE".to_string(), "${{ env.REGISTRY }}/app:${{ steps.build.outputs.version }}".to_string()),
+        ]);
+        ctx.enter_step(&deploy, HashMap::from([("TOKEN".to_string(), "s3cr3t".to_string())]));
+
+        assert_eq!(ctx.step_env().get("IMAGE").map(String::as_str), Some("ghcr.io/app:1.2.3"));
+        assert_eq!(
+            ctx.interpolate("push ${{ env.IMAGE }} --target ${{ env.TARGET }} --token ${{ secrets.TOKEN }}"),
+            "push ghcr.io/app:1.2.3 --target release --token s3cr3t"
+        );
+        assert!(ctx.evaluate("env.TARGET == 'release' && secrets.TOKEN"));
+
+        // Nothing leaks into the next step
+        ctx.enter_step(&step("uuid-3", None), HashMap::new());
+        assert_eq!(ctx.interpolate("${{ env.TARGET }}:${{ secrets.TOKEN }}"), "debug:");
+    }
+
+    #[test]
+    fn test_exported_env() {
+        let mut ctx = StepsContext::new(HashMap::from([
+            ("TARGET".to_string(), "debug".to_string()),
+            ("REGISTRY".to_string(), "ghcr.io".to_string()),
+        ]));
+        ctx.export_env(HashMap::from([
+            ("TARGET".to_string(), "release".to_string()),
+            ("VERSION".to_string(), "1.2.3".to_string()),
+        ]));
+
+        let mut step = step("uuid-1", None);
+        step.env = HashMap::from([("VERSION".to_string(), "${{ env.VERSION }}-rc".to_string())]);
+        ctx.enter_step(&step, HashMap::new());
+        assert_eq!(ctx.interpolate("${{ env.REGISTRY }} ${{ env.TARGET }} ${{ env.VERSION }}"), "ghcr.io release 1.2.3-rc");
+        assert_eq!(ctx.exported_env()["VERSION"], "1.2.3");
+        assert_eq!(ctx.scoped_to_needs(&[]).exported_env().len(), 2);
+    }
+
+    #[test]
+    fn test_step_without_id_uses_step_id() {
+        let mut ctx = StepsContext::default();
+        ctx.record(&step("uuid-1", None), StepStatus::Success, HashMap::new());
+
+        assert!(ctx.get("uuid-1").is_some());
+    }
+
+    #[test]
+    fn test_evaluate_conditions() {
+        let mut ctx = StepsContext::default();
+        ctx.record(
+            &step("uuid-1", Some("build")),
+            StepStatus::Success,
+            HashMap::from([("deploy".to_string(), "true".to_string())]),
+        );
+
+        assert!(ctx.evaluate("success()"));
+        assert!(!ctx.evaluate("failure()"));
+        assert!(ctx.evaluate("${{ steps.build.outputs.deploy == 'true' }}"));
+        assert!(ctx.evaluate("steps.build.outputs.deploy && steps.build.outcome != 'failed'"));
+        assert!(!ctx.evaluate("!steps.build.outputs.deploy"));
+
+        ctx.record(&step("uuid-2", Some("test")), StepStatus::Failed, HashMap::new());
+        assert!(!ctx.evaluate("success()"));
+        assert!(ctx.evaluate("failure() || always()"));
+        assert!(ctx.evaluate("steps.test.outcome == 'failed'"));
+    }
+
+    #[test]
+    fn test_scoped_to_needs() {
+        let mut ctx = StepsContext::default();
+        ctx.record(&step("uuid-1", Some("build")), StepStatus::Success, HashMap::new());
+        ctx.record(&step("uuid-2", Some("lint")), StepStatus::Failed, HashMap::new());
+        ctx.record(&step("uuid-3", Some("docs")), StepStatus::Skipped, HashMap::new());
+
+        // A failure on another branch does not matter
+        let scoped = ctx.scoped_to_needs(&["build".to_string()]);
+        assert!(scoped.evaluate("success()"));
+        assert!(!scoped.evaluate("failure()"));
+
+        let scoped = ctx.scoped_to_needs(&["build".to_string(), "lint".to_string()]);
+        assert!(!scoped.evaluate("success()"));
+        assert!(scoped.evaluate("failure()"));
+
+        // A skipped need is neither success nor failure
+        let scoped = ctx.scoped_to_needs(&["docs".to_string()]);
+        assert!(!scoped.evaluate("success()"));
+        assert!(!scoped.evaluate("failure()"));
+        assert!(scoped.evaluate("always()"));
+    }
+
+    #[test]
+    fn test_needs_env() {
+        let mut ctx = StepsContext::default();
+        let outputs = HashMap::from([("image-tag".to_string(), "app:1.2".to_string())]);
+        ctx.record(&step("uuid-1", Some("build.linux")), StepStatus::Success, outputs);
+        ctx.record(&step("uuid-2", Some("lint")), StepStatus::Success, HashMap::from([("ok".to_string(), "1".to_string())]));
+
+        assert!(ctx.needs_env().is_empty());
+        let env = ctx.scoped_to_needs(&["build.linux".to_string(), "docs".to_string()]).needs_env();
+        assert_eq!(env, HashMap::from([("MUELSYSE_NEEDS_BUILD_LINUX_IMAGE_TAG".to_string(), "app:1.2".to_string())]));
+    }
+}
@@ -0,0 +1,67 @@
+//! Debug sessions for failed jobs
+//!
+//! A job with `debug_on_failure` whose step fails on a runner that allows it
+//! (`job.debug_session_secs`) is not cleaned up right away: the runner keeps
+//! its workspace, and job container if any, reports a `debug_session` to the
+//! control plane and runs each `debug_exec` command relayed from a user in
+//! the environment of the failed step: same image, workspace, variables and
+//! secrets, with the output masked. Just-in-time secrets are dropped with the
+//! step and are not available. The session ends on `debug_end`, when it
+//! expires or when the job is cancelled, and the job's cleanup follows.
+
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+use crate::executor::ExecutionContext;
+
+/// Most output returned for one debug command
+pub const DEBUG_OUTPUT_MAX_BYTES: usize = 64 * 1024;
+
+/// A request relayed to an open debug session
+#[derive(Debug)]
+pub enum DebugCommand {
+    Exec {
+        request_id: Option<String>,
+        command: String,
+    },
+    End,
+}
+
+/// An open debug session, for routing requests to it
+#[derive(Debug, Clone)]
+pub struct DebugHandle {
+    pub session_id: String,
+    pub commands: mpsc::UnboundedSender<DebugCommand>,
+}
+
+/// Context running the `index`th debug command in the environment of the
+/// failed step, within `limit`
+pub fn debug_exec_context(step: &ExecutionContext, command: &str, index: usize, limit: Duration) -> ExecutionContext {
+    let mut exec = step.companion(&format!("debug-{}", index), command, limit);
+    exec.environment.insert("MUELSYSE_DEBUG".to_string(), "true".to_string());
+    exec
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::step_context;
+    use std::path::Path;
+
+    #[test]
+    fn test_debug_exec_context() {
+        let mut step = step_context(Path::new("/work"), "make test", Duration::from_secs(3600));
+        step.container_image = Some("rust:1".into());
+        step.warning_signal_after = Some(Duration::from_secs(3000));
+        step.environment.insert("CI".to_string(), "true".to_string());
+
+        let exec = debug_exec_context(&step, "cat target/test.log", 2, Duration::from_secs(60));
+        assert_eq!(exec.step_id, format!("{}-debug-2", step.step_id));
+        assert_eq!(exec.command, "cat target/test.log");
+        assert_eq!(exec.timeout, Duration::from_secs(60));
+        assert_eq!(exec.warning_signal_after, None);
+        assert_eq!(exec.container_image.as_deref(), Some("rust:1"));
+        assert_eq!(exec.environment["CI"], "true");
+        assert_eq!(exec.environment["MUELSYSE_DEBUG"], "true");
+    }
+}
@@ -0,0 +1,153 @@
+//! On-demand diagnostics for running jobs
+//!
+//! Runs the configured, read-only commands inside the job's current step
+//! container so stuck jobs can be inspected without interactive access.
+//! Host jobs share the runner's machine, so they get their own, narrower
+//! set of commands, run in the workspace without the runner's environment.
+
+use anyhow::Result;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::process::Command;
+use tokio::time::timeout;
+
+use crate::client::DiagnosticResult;
+use crate::config::{DiagnosticCommand, DiagnosticsConfig};
+use crate::executor::DockerExecutor;
+use crate::log::SecretMasker;
+
+/// Search path of host diagnostic commands
+const HOST_PATH: &str = "/usr/local/bin:/usr/bin:/bin";
+
+/// Where diagnostics for a job run
+#[derive(Debug, Clone)]
+pub struct DiagnosticTarget {
+    /// Job workspace on the host
+    pub workspace_path: PathBuf,
+
+    /// Container of the current step, for container jobs
+    pub container: Option<String>,
+
+    /// Masks the job's secrets in command output
+    pub masker: SecretMasker,
+}
+
+/// Run every configured diagnostic command against `target`
+///
+/// `docker` is required for container targets.
+pub async fn run_diagnostics(
+    config: &DiagnosticsConfig,
+    target: &DiagnosticTarget,
+    docker: Option<&DockerExecutor>,
+) -> Vec<DiagnosticResult> {
+    let limit = Duration::from_secs(config.timeout_secs);
+    let commands = match target.container {
+        Some(_) => &config.commands,
+        None => &config.host_commands,
+    };
+
+    let mut results = Vec::with_capacity(commands.len());
+    for diagnostic in commands {
+        let outcome = match (&target.container, docker) {
+            (Some(container), Some(docker)) => {
+                timeout(limit, docker.exec(container, &diagnostic.command)).await
+            }
+            (Some(_), None) => Ok(Err(anyhow::anyhow!("Docker is not available"))),
+            (None, _) => timeout(limit, run_on_host(target, diagnostic)).await,
+        };
+
+        let result = match outcome {
+            Ok(Ok((exit_code, output))) => DiagnosticResult {
+                name: diagnostic.name.clone(),
+                command: diagnostic.command.clone(),
+                exit_code,
+                output: truncate(&target.masker.mask(&output), config.max_output_bytes),
+                error: None,
+            },
+            Ok(Err(e)) => failed(diagnostic, e.to_string()),
+            Err(_) => failed(diagnostic, format!("Timed out after {}s", config.timeout_secs)),
+        };
+        results.push(result);
+    }
+
+    results
+}
+
+async fn run_on_host(target: &DiagnosticTarget, diagnostic: &DiagnosticCommand) -> Result<(Option<i64>, String)> {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(&diagnostic.command)
+        .current_dir(&target.workspace_path)
+        .env_clear()
+        .env("PATH", HOST_PATH)
+        .kill_on_drop(true)
+        .output()
+        .await?;
+
+    let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+    text.push_str(&String::from_utf8_lossy(&output.stderr));
+    Ok((output.status.code().map(i64::from), text))
+}
+
+fn failed(diagnostic: &DiagnosticCommand, error: String) -> DiagnosticResult {
+    DiagnosticResult {
+        name: diagnostic.name.clone(),
+        command: diagnostic.command.clone(),
+        exit_code: None,
+        output: String::new(),
+        error: Some(error),
+    }
+}
+
+/// Keep at most `max_bytes` of `text`, cut on a char boundary
+pub(super) fn truncate(text: &str, max_bytes: usize) -> String {
+    if text.len() <= max_bytes {
+        return text.to_string();
+    }
+    let mut end = max_bytes;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}\n... (truncated)", &text[..end])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("short", 10), "short");
+        assert_eq!(truncate("héllo", 2), "h\n... (truncated)");
+    }
+
+    #[tokio::test]
+    async fn test_run_diagnostics_on_host() {
+        let config = DiagnosticsConfig {
+            host_commands: vec![
+                DiagnosticCommand {
+                    name: "echo".into(),
+                    command: "echo token=s3cret".into(),
+                },
+                DiagnosticCommand {
+                    name: "env_keys".into(),
+                    command: "env | cut -d= -f1".into(),
+                },
+            ],
+            ..DiagnosticsConfig::default()
+        };
+        let target = DiagnosticTarget {
+            workspace_path: std::env::temp_dir(),
+            container: None,
+            masker: SecretMasker::new(["s3cret"]),
+        };
+
+        let results = run_diagnostics(&config, &target, None).await;
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].exit_code, Some(0));
+        assert_eq!(results[0].output.trim(), "token=***");
+        // The runner's environment is not passed on
+        assert!(!results[1].output.lines().any(|key| key == "HOME"));
+    }
+}
@@ -0,0 +1,182 @@
+//! Step environment size guardrails
+//!
+//! Oversized environments make `execve` fail with `E2BIG`, which surfaces as
+//! an opaque spawn error. Limits are checked before spawning, and values too
+//! large for a single variable can be moved into files exposed as `<KEY>_FILE`.
+
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::config::JobConfig;
+use crate::executor::create_private_dir;
+
+/// Limits applied to a step's environment
+#[derive(Debug, Clone, Copy)]
+pub struct EnvLimits {
+    pub max_value_bytes: usize,
+    pub max_total_bytes: usize,
+    pub max_count: usize,
+}
+
+impl From<&JobConfig> for EnvLimits {
+    fn from(config: &JobConfig) -> Self {
+        Self {
+            max_value_bytes: config.env_max_value_bytes,
+            max_total_bytes: config.env_max_total_bytes,
+            max_count: config.env_max_count,
+        }
+    }
+}
+
+impl EnvLimits {
+    /// Fail with the offending keys if `env` exceeds any limit
+    pub fn check(&self, env: &HashMap<String, String>) -> Result<()> {
+        if env.len() > self.max_count {
+            anyhow::bail!(
+                "Environment has {} variables, exceeding the limit of {}",
+                env.len(), self.max_count
+            );
+        }
+
+        let mut oversized: Vec<_> = env
+            .iter()
+            .filter(|(k, v)| entry_size(k, v) > self.max_value_bytes)
+            .map(|(k, v)| format!("{} ({} bytes)", k, entry_size(k, v)))
+            .collect();
+        if !oversized.is_empty() {
+            oversized.sort();
+            anyhow::bail!(
+                "Environment variables exceed the per-variable limit of {} bytes: {}",
+                self.max_value_bytes, oversized.join(", ")
+            );
+        }
+
+        let total: usize = env.iter().map(|(k, v)| entry_size(k, v)).sum();
+        if total > self.max_total_bytes {
+            let mut largest: Vec<_> = env.iter().collect();
+            largest.sort_by_key(|(k, v)| std::cmp::Reverse(entry_size(k, v)));
+            let keys: Vec<_> = largest
+                .iter()
+                .take(5)
+                .map(|(k, v)| format!("{} ({} bytes)", k, entry_size(k, v)))
+                .collect();
+            anyhow::bail!(
+                "Environment is {} bytes, exceeding the limit of {} bytes; largest: {}",
+                total, self.max_total_bytes, keys.join(", ")
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Size of `KEY=VALUE\0` as passed to `execve`
+fn entry_size(key: &str, value: &str) -> usize {
+    key.len() + value.len() + 2
+}
+
+/// Directory holding a job's indirected environment values, within its
+/// script directory
+pub fn env_file_dir(job_dir: &Path) -> PathBuf {
+    job_dir.join("env")
+}
+
+/// Move values over the per-variable limit into files.
+///
+/// Each moved `KEY` is replaced by `KEY_FILE`, pointing at the file under
+/// `visible_dir` (the path as seen by the step). Returns the moved keys.
+pub async fn indirect_oversized(
+    env: &mut HashMap<String, String>,
+    limits: &EnvLimits,
+    host_dir: &Path,
+    visible_dir: &Path,
+) -> Result<Vec<String>> {
+    let mut keys: Vec<String> = env
+        .iter()
+        .filter(|(k, v)| entry_size(k, v) > limits.max_value_bytes)
+        .map(|(k, _)| k.clone())
+        .collect();
+    keys.sort();
+
+    if keys.is_empty() {
+        return Ok(keys);
+    }
+
+    create_private_dir(host_dir)
+        .await
+        .context("Failed to create environment file directory")?;
+
+    for key in &keys {
+        let value = env.remove(key).unwrap_or_default();
+        write_private(&host_dir.join(key), value.as_bytes()).await?;
+        env.insert(format!("{}_FILE", key), visible_dir.join(key).display().to_string());
+    }
+
+    Ok(keys)
+}
+
+async fn write_private(path: &Path, contents: &[u8]) -> Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+
+    let mut file = options
+        .open(path)
+        .await
+        .with_context(|| format!("Failed to create environment file {:?}", path))?;
+    file.write_all(contents).await?;
+    file.flush().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits() -> EnvLimits {
+        EnvLimits { max_value_bytes: 32, max_total_bytes: 64, max_count: 3 }
+    }
+
+    #[test]
+    fn test_check_limits() {
+        let mut env = HashMap::new();
+        env.insert("A".to_string(), "small".to_string());
+        assert!(limits().check(&env).is_ok());
+
+        env.insert("BLOB".to_string(), "x".repeat(40));
+        let err = limits().check(&env).unwrap_err().to_string();
+        assert!(err.contains("BLOB"), "{}", err);
+
+        env.insert("BLOB".to_string(), "x".repeat(25));
+        env.insert("C".to_string(), "y".repeat(25));
+        let err = limits().check(&env).unwrap_err().to_string();
+        assert!(err.contains("exceeding the limit of 64"), "{}", err);
+
+        env.insert("D".to_string(), String::new());
+        let err = limits().check(&env).unwrap_err().to_string();
+        assert!(err.contains("4 variables"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_indirect_oversized() {
+        let dir = std::env::temp_dir().join(format!("muelsyse-env-{}", uuid::Uuid::new_v4()));
+        let mut env = HashMap::new();
+        env.insert("SMALL".to_string(), "ok".to_string());
+        env.insert("CERT".to_string(), "c".repeat(100));
+
+        let moved = indirect_oversized(&mut env, &limits(), &dir, Path::new("/mnt/env"))
+            .await
+            .unwrap();
+
+        assert_eq!(moved, vec!["CERT".to_string()]);
+        assert!(!env.contains_key("CERT"));
+        assert_eq!(env.get("CERT_FILE").map(String::as_str), Some("/mnt/env/CERT"));
+        assert_eq!(std::fs::read_to_string(dir.join("CERT")).unwrap(), "c".repeat(100));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,135 @@
+//! Step dependency graph
+//!
+//! Steps run in list order unless one of them declares `needs`. The job's
+//! steps then form a graph: a step starts once every step it needs has
+//! finished, and steps without `needs` start right away, so independent
+//! steps run in parallel. A step sees the outputs of the steps it needs as
+//! `MUELSYSE_NEEDS_<STEP>_<NAME>` variables.
+
+use anyhow::Result;
+use std::collections::HashMap;
+
+use crate::client::StepSpec;
+
+/// Which steps each step waits for, by index
+#[derive(Debug, Clone)]
+pub struct StepGraph {
+    needs: Vec<Vec<usize>>,
+}
+
+impl StepGraph {
+    /// The graph of `steps`, or `None` if no step declares `needs`.
+    ///
+    /// Fails on unknown or duplicate step ids and on cycles.
+    pub fn new(steps: &[StepSpec]) -> Result<Option<Self>> {
+        if steps.iter().all(|step| step.needs.is_empty()) {
+            return Ok(None);
+        }
+
+        let mut index = HashMap::new();
+        for (i, step) in steps.iter().enumerate() {
+            if index.insert(step.reference_id(), i).is_some() {
+                anyhow::bail!("Duplicate step id '{}'", step.reference_id());
+            }
+        }
+
+        let mut needs = Vec::with_capacity(steps.len());
+        for step in steps {
+            let mut indices = Vec::with_capacity(step.needs.len());
+            for need in &step.needs {
+                match index.get(need.as_str()) {
+                    Some(&i) => indices.push(i),
+                    None => anyhow::bail!("Step '{}' needs unknown step '{}'", step.reference_id(), need),
+                }
+            }
+            needs.push(indices);
+        }
+
+        let graph = Self { needs };
+        let cycle = graph.unreachable();
+        if !cycle.is_empty() {
+            let names: Vec<&str> = cycle.iter().map(|&i| steps[i].reference_id()).collect();
+            anyhow::bail!("Steps {} depend on each other through `needs`", names.join(", "));
+        }
+        Ok(Some(graph))
+    }
+
+    /// Indices of the steps `step` needs
+    pub fn needs(&self, step: usize) -> &[usize] {
+        &self.needs[step]
+    }
+
+    /// A step not yet started whose needs have all finished
+    pub fn next_ready(&self, started: &[bool], finished: &[bool]) -> Option<usize> {
+        (0..self.needs.len()).find(|&i| !started[i] && self.needs[i].iter().all(|&n| finished[n]))
+    }
+
+    /// Steps that can never start because they are on or behind a cycle
+    fn unreachable(&self) -> Vec<usize> {
+        let mut finished = vec![false; self.needs.len()];
+        while let Some(i) = self.next_ready(&finished, &finished) {
+            finished[i] = true;
+        }
+        (0..self.needs.len()).filter(|&i| !finished[i]).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn steps(needs: &[(&str, &[&str])]) -> Vec<StepSpec> {
+        needs
+            .iter()
+            .map(|(id, needs)| {
+                serde_json::from_value(serde_json::json!({
+                    "step_id": format!("uuid-{}", id),
+                    "id": id,
+                    "name": id,
+                    "run": "true",
+                    "needs": needs,
+                })).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_step_graph() {
+        assert!(StepGraph::new(&steps(&[("a", &[]), ("b", &[])])).unwrap().is_none());
+
+        let graph = StepGraph::new(&steps(&[
+            ("checkout", &[]),
+            ("lint", &["checkout"]),
+            ("test", &["checkout"]),
+            ("package", &["lint", "test"]),
+        ])).unwrap().unwrap();
+        assert_eq!(graph.needs(3), &[1, 2]);
+
+        let mut started = vec![true, false, false, false];
+        let mut finished = vec![false; 4];
+        assert_eq!(graph.next_ready(&started, &finished), None);
+
+        // Both dependents of checkout are ready together
+        finished[0] = true;
+        assert_eq!(graph.next_ready(&started, &finished), Some(1));
+        started[1] = true;
+        assert_eq!(graph.next_ready(&started, &finished), Some(2));
+        started[2] = true;
+        finished[1] = true;
+        assert_eq!(graph.next_ready(&started, &finished), None);
+        finished[2] = true;
+        assert_eq!(graph.next_ready(&started, &finished), Some(3));
+
+        let unknown = StepGraph::new(&steps(&[("a", &["missing"])])).unwrap_err();
+        assert!(unknown.to_string().contains("unknown step 'missing'"), "{}", unknown);
+
+        let cycle = StepGraph::new(&steps(&[
+            ("a", &[]),
+            ("b", &["a", "d"]),
+            ("c", &["b"]),
+            ("d", &["c"]),
+        ])).unwrap_err();
+        assert_eq!(cycle.to_string(), "Steps b, c, d depend on each other through `needs`");
+        assert!(StepGraph::new(&steps(&[("a", &["a"])])).is_err());
+    }
+}
@@ -0,0 +1,246 @@
+//! Local job history
+//!
+//! Every finished job attempt is appended as a JSON line to a history file on
+//! the runner, so jobs can be inspected on the host even when the control
+//! plane's history is unavailable. The file is compacted to the newest
+//! records once it grows past twice the configured limit.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use tokio::io::AsyncWriteExt;
+
+use crate::client::JobSpec;
+
+/// Outcome of one step in a history record
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StepRecord {
+    #[serde(default)]
+    pub step_id: String,
+    pub name: String,
+    pub status: String,
+    pub duration_ms: u64,
+    /// Exit code of the step's command, if it ran to completion
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+}
+
+/// An artifact produced by a recorded job
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArtifactRecord {
+    pub name: String,
+    pub size_bytes: u64,
+    pub checksum: String,
+    /// `uploaded`, `queued` (awaiting upload retry) or `quarantined`
+    pub disposition: String,
+}
+
+/// One executed job attempt
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryRecord {
+    pub job_id: String,
+    pub execution_id: String,
+    pub name: String,
+    /// Digest of the job spec, see [`spec_digest`]
+    pub spec_digest: String,
+    pub attempt: u32,
+    pub status: String,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub duration_ms: u64,
+    #[serde(default)]
+    pub steps: Vec<StepRecord>,
+    #[serde(default)]
+    pub artifacts: Vec<ArtifactRecord>,
+}
+
+impl HistoryRecord {
+    /// Record for an attempt of `job` that started at `started_at`
+    pub fn new(job: &JobSpec, attempt: u32, started_at: DateTime<Utc>) -> Self {
+        Self {
+            job_id: job.job_id.clone(),
+            execution_id: job.execution_id.clone(),
+            name: job.name.clone(),
+            spec_digest: spec_digest(job),
+            attempt,
+            status: "running".to_string(),
+            started_at,
+            finished_at: None,
+            duration_ms: 0,
+            steps: Vec::new(),
+            artifacts: Vec::new(),
+        }
+    }
+
+    /// Mark the attempt finished now with `status`
+    pub fn finish(&mut self, status: &str) {
+        let finished_at = Utc::now();
+        self.status = status.to_string();
+        self.duration_ms = (finished_at - self.started_at).num_milliseconds().max(0) as u64;
+        self.finished_at = Some(finished_at);
+    }
+}
+
+/// SHA-256 of the job spec without its run identity, workspace path and
+/// secret values, so reruns of the same job definition share a digest
+pub fn spec_digest(job: &JobSpec) -> String {
+    let mut spec = serde_json::to_value(job).unwrap_or_default();
+    if let Some(fields) = spec.as_object_mut() {
+        fields.remove("job_id");
+        fields.remove("execution_id");
+        if let Some(workspace) = fields.get_mut("workspace").and_then(|w| w.as_object_mut()) {
+            workspace.remove("path");
+        }
+        let mut secret_names: Vec<&String> = job.secrets.keys().collect();
+        secret_names.sort();
+        fields.insert("secrets".to_string(), serde_json::json!(secret_names));
+    }
+
+    // Objects serialize with sorted keys, so the digest is stable
+    hex::encode(Sha256::digest(spec.to_string().as_bytes()))
+}
+
+/// Filters for a history query
+#[derive(Debug, Clone, Default)]
+pub struct HistoryQuery {
+    pub job_id: Option<String>,
+    pub status: Option<String>,
+    /// Return at most this many records (0 = all)
+    pub limit: usize,
+}
+
+/// Append-only history file
+#[derive(Debug, Clone)]
+pub struct JobHistory {
+    path: PathBuf,
+    max_records: usize,
+}
+
+impl JobHistory {
+    /// Keeps about the newest `max_records` records (0 = unlimited)
+    pub fn new(path: PathBuf, max_records: usize) -> Self {
+        Self { path, max_records }
+    }
+
+    /// Append a finished attempt
+    pub async fn append(&self, record: &HistoryRecord) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .context("Failed to create job history directory")?;
+        }
+
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await
+            .context("Failed to open job history")?;
+        file.write_all(&line).await.context("Failed to write job history")?;
+        drop(file);
+
+        let records = self.read_all().await?;
+        if self.max_records > 0 && records.len() > self.max_records * 2 {
+            self.rewrite(&records[records.len() - self.max_records..]).await?;
+        }
+        Ok(())
+    }
+
+    async fn rewrite(&self, records: &[HistoryRecord]) -> Result<()> {
+        let mut contents = Vec::new();
+        for record in records {
+            contents.extend(serde_json::to_vec(record)?);
+            contents.push(b'\n');
+        }
+
+        let tmp_path = self.path.with_extension("tmp");
+        tokio::fs::write(&tmp_path, contents)
+            .await
+            .context("Failed to compact job history")?;
+        tokio::fs::rename(&tmp_path, &self.path)
+            .await
+            .context("Failed to compact job history")
+    }
+
+    /// All records, oldest first; unreadable lines are skipped
+    async fn read_all(&self) -> Result<Vec<HistoryRecord>> {
+        let contents = match tokio::fs::read_to_string(&self.path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e).context("Failed to read job history"),
+        };
+
+        Ok(contents
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect())
+    }
+
+    /// Matching records, newest first
+    pub async fn query(&self, query: &HistoryQuery) -> Result<Vec<HistoryRecord>> {
+        let records = self.read_all().await?
+            .into_iter()
+            .rev()
+            .filter(|r| query.job_id.as_ref().is_none_or(|id| &r.job_id == id))
+            .filter(|r| query.status.as_ref().is_none_or(|status| &r.status == status));
+
+        Ok(match query.limit {
+            0 => records.collect(),
+            limit => records.take(limit).collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::JobSpecBuilder;
+
+    fn job(job_id: &str, secret: &str) -> JobSpec {
+        JobSpecBuilder::new(job_id)
+            .step("s1", "make", "make")
+            .with("environment", serde_json::json!({ "CI": "true", "LANG": "C" }))
+            .with("secrets", serde_json::json!({ "TOKEN": secret }))
+            .build()
+    }
+
+    #[test]
+    fn test_spec_digest() {
+        // Run identity, workspace path and secret values don't change the spec
+        assert_eq!(spec_digest(&job("job-1", "a")), spec_digest(&job("job-2", "b")));
+
+        let mut changed = job("job-1", "a");
+        changed.steps[0].run = Some("make test".into());
+        assert_ne!(spec_digest(&job("job-1", "a")), spec_digest(&changed));
+    }
+
+    #[tokio::test]
+    async fn test_append_and_query() {
+        let dir = std::env::temp_dir().join(format!("muelsyse-history-{}", uuid::Uuid::new_v4()));
+        let history = JobHistory::new(dir.join("history.jsonl"), 2);
+
+        for (i, status) in ["success", "failed", "success", "success", "failed"].iter().enumerate() {
+            let mut record = HistoryRecord::new(&job(&format!("job-{}", i), "a"), 1, Utc::now());
+            record.finish(status);
+            history.append(&record).await.unwrap();
+        }
+
+        // Compacted to the newest two on passing four records
+        let all = history.query(&HistoryQuery::default()).await.unwrap();
+        let ids: Vec<&str> = all.iter().map(|r| r.job_id.as_str()).collect();
+        assert_eq!(ids, vec!["job-4", "job-3"]);
+        assert!(all[0].finished_at.is_some());
+
+        let failed = HistoryQuery { status: Some("failed".into()), ..Default::default() };
+        assert_eq!(history.query(&failed).await.unwrap().len(), 1);
+
+        let limited = HistoryQuery { limit: 1, ..Default::default() };
+        assert_eq!(history.query(&limited).await.unwrap()[0].job_id, "job-4");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,221 @@
+//! Step hooks
+//!
+//! Executables listed under `[hooks]` run before and after every step with a
+//! JSON payload on stdin. A before-step hook vetoes the step by exiting
+//! non-zero (its last stderr line is the reason) and may add environment
+//! variables by printing `KEY=VALUE` lines. After-step hooks are
+//! informational; their failures are only logged.
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tracing::{debug, warn};
+
+use crate::client::{JobSpec, StepSpec};
+use crate::config::HooksConfig;
+
+/// JSON document written to a hook's stdin
+#[derive(Debug, Clone, Serialize)]
+pub struct HookPayload {
+    /// `before_step` or `after_step`
+    pub event: &'static str,
+    pub runner_id: String,
+    pub job_id: String,
+    pub execution_id: String,
+    pub job_name: String,
+    pub untrusted: bool,
+    pub step_id: String,
+    pub step_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub run: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uses: Option<String>,
+    /// Outcome, for `after_step`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+}
+
+impl HookPayload {
+    pub fn before_step(runner_id: &str, job: &JobSpec, step: &StepSpec) -> Self {
+        Self {
+            event: "before_step",
+            runner_id: runner_id.to_string(),
+            job_id: job.job_id.clone(),
+            execution_id: job.execution_id.clone(),
+            job_name: job.name.clone(),
+            untrusted: job.untrusted,
+            step_id: step.step_id.clone(),
+            step_name: step.name.clone(),
+            run: step.run.clone(),
+            uses: step.uses.clone(),
+            status: None,
+            duration_ms: None,
+        }
+    }
+
+    pub fn after_step(self, status: &str, duration: Duration) -> Self {
+        Self {
+            event: "after_step",
+            status: Some(status.to_string()),
+            duration_ms: Some(duration.as_millis() as u64),
+            ..self
+        }
+    }
+}
+
+/// Configured hook executables
+#[derive(Debug, Clone)]
+pub struct StepHooks {
+    before_step: Vec<PathBuf>,
+    after_step: Vec<PathBuf>,
+    timeout: Duration,
+}
+
+impl From<&HooksConfig> for StepHooks {
+    fn from(config: &HooksConfig) -> Self {
+        Self {
+            before_step: config.before_step.clone(),
+            after_step: config.after_step.clone(),
+            timeout: Duration::from_secs(config.timeout_secs),
+        }
+    }
+}
+
+impl StepHooks {
+    /// Run the before-step hooks in order.
+    ///
+    /// Returns the variables they add, or an error vetoing the step. A hook
+    /// that cannot run or times out vetoes the step too.
+    pub async fn before_step(&self, payload: &HookPayload) -> Result<HashMap<String, String>> {
+        let mut env = HashMap::new();
+        for hook in &self.before_step {
+            let output = run_hook(hook, payload, self.timeout)
+                .await
+                .with_context(|| format!("Step hook {:?} failed", hook))?;
+
+            if !output.status.success() {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                let reason = stderr
+                    .lines()
+                    .rev()
+                    .find(|line| !line.trim().is_empty())
+                    .map(str::to_string)
+                    .unwrap_or_else(|| format!("exited with {}", output.status));
+                anyhow::bail!("Step vetoed by hook {:?}: {}", hook, reason);
+            }
+            env.extend(parse_env(hook, &String::from_utf8_lossy(&output.stdout)));
+        }
+        Ok(env)
+    }
+
+    /// Run the after-step hooks, logging failures
+    pub async fn after_step(&self, payload: &HookPayload) {
+        for hook in &self.after_step {
+            match run_hook(hook, payload, self.timeout).await {
+                Ok(output) if output.status.success() => {}
+                Ok(output) => warn!("Step hook {:?} exited with {}", hook, output.status),
+                Err(e) => warn!("Step hook {:?} failed: {}", hook, e),
+            }
+        }
+    }
+}
+
+async fn run_hook(hook: &Path, payload: &HookPayload, timeout: Duration) -> Result<std::process::Output> {
+    debug!("Running {} hook {:?} for step {}", payload.event, hook, payload.step_id);
+    let mut child = Command::new(hook)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .context("Failed to start hook")?;
+
+    let input = serde_json::to_vec(payload)?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // A hook that ignores its payload may close stdin early
+        let _ = stdin.write_all(&input).await;
+    }
+
+    tokio::time::timeout(timeout, child.wait_with_output())
+        .await
+        .map_err(|_| anyhow::anyhow!("Hook timed out after {:?}", timeout))?
+        .context("Failed to wait for hook")
+}
+
+/// `KEY=VALUE` lines printed by a hook; other lines are ignored
+fn parse_env(hook: &Path, stdout: &str) -> HashMap<String, String> {
+    stdout
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.split_once('=')?;
+            let valid = !key.is_empty()
+                && !key.starts_with(|c: char| c.is_ascii_digit())
+                && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if !valid {
+                warn!("Ignoring invalid variable name {:?} from hook {:?}", key, hook);
+                return None;
+            }
+            Some((key.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use crate::client::JobSpecBuilder;
+
+    fn script(dir: &std::path::Path, name: &str, body: &str) -> PathBuf {
+        let path = dir.join(name);
+        std::fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    fn payload() -> HookPayload {
+        let job = JobSpecBuilder::new("job-1").step("s1", "deploy", "make deploy").build();
+        HookPayload::before_step("runner-1", &job, &job.steps[0])
+    }
+
+    #[tokio::test]
+    async fn test_before_step_hooks() {
+        let dir = std::env::temp_dir().join(format!("muelsyse-hooks-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // Reads the payload and sets variables from it
+        let tag = script(&dir, "tag.sh", r#"payload=$(cat)
+case "$payload" in *'"step_name":"deploy"'*) echo "DEPLOY_TICKET=CHG-42";; esac
+echo "not a variable"
+echo "1BAD=x""#);
+        let deny = script(&dir, "deny.sh", "cat >/dev/null\necho 'checking' >&2\necho 'deploys are frozen' >&2\nexit 3");
+        let slow = script(&dir, "slow.sh", "sleep 5");
+
+        let hooks = StepHooks {
+            before_step: vec![tag.clone()],
+            after_step: vec![deny.clone()],
+            timeout: Duration::from_secs(2),
+        };
+        let env = hooks.before_step(&payload()).await.unwrap();
+        assert_eq!(env, HashMap::from([("DEPLOY_TICKET".to_string(), "CHG-42".to_string())]));
+
+        // After-step failures never fail the step
+        hooks.after_step(&payload().after_step("success", Duration::from_millis(5))).await;
+
+        let hooks = StepHooks { before_step: vec![tag, deny], ..hooks };
+        let veto = hooks.before_step(&payload()).await.unwrap_err().to_string();
+        assert!(veto.contains("deploys are frozen"), "{}", veto);
+
+        let hooks = StepHooks { before_step: vec![slow], timeout: Duration::from_millis(200), ..hooks };
+        assert!(hooks.before_step(&payload()).await.is_err());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
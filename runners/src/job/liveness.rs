@@ -0,0 +1,94 @@
+//! Liveness file for external supervisors
+//!
+//! Periodically writes a small JSON document describing the runner so that
+//! watchdogs (Nomad checks, cron scripts) can detect a hung runner process
+//! without network access to the runner.
+
+use anyhow::{Result, Context};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+/// Contents of the liveness file
+#[derive(Debug, Clone, Serialize)]
+pub struct LivenessReport {
+    pub runner_id: String,
+    pub pid: u32,
+    pub status: String,
+    pub timestamp: DateTime<Utc>,
+    pub running_jobs: Vec<String>,
+}
+
+impl LivenessReport {
+    pub fn new(runner_id: &str, status: &str, running_jobs: Vec<String>) -> Self {
+        Self {
+            runner_id: runner_id.to_string(),
+            pid: std::process::id(),
+            status: status.to_string(),
+            timestamp: Utc::now(),
+            running_jobs,
+        }
+    }
+}
+
+/// Writes liveness reports atomically to a fixed path
+pub struct LivenessWriter {
+    path: PathBuf,
+}
+
+impl LivenessWriter {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// Get the liveness file path
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Write a report, replacing the previous one atomically
+    pub async fn write(&self, report: &LivenessReport) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .context("Failed to create liveness file directory")?;
+        }
+
+        // Write to a sibling file and rename so readers never see partial JSON
+        let tmp_path = self.path.with_extension("tmp");
+        let json = serde_json::to_vec_pretty(report)?;
+
+        tokio::fs::write(&tmp_path, json)
+            .await
+            .context("Failed to write liveness file")?;
+        tokio::fs::rename(&tmp_path, &self.path)
+            .await
+            .context("Failed to replace liveness file")?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_write_liveness_file() {
+        let path = std::env::temp_dir()
+            .join(format!("muelsyse-liveness-{}", uuid::Uuid::new_v4()))
+            .join("runner.json");
+        let writer = LivenessWriter::new(path.clone());
+
+        let report = LivenessReport::new("runner-1", "running", vec!["job-1".to_string()]);
+        writer.write(&report).await.unwrap();
+
+        let content = tokio::fs::read_to_string(&path).await.unwrap();
+        let value: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(value["runner_id"], "runner-1");
+        assert_eq!(value["status"], "running");
+        assert_eq!(value["running_jobs"][0], "job-1");
+
+        let _ = tokio::fs::remove_dir_all(path.parent().unwrap()).await;
+    }
+}
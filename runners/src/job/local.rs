@@ -0,0 +1,396 @@
+//! Offline job execution
+//!
+//! `muelsyse-runner exec FILE [JOB]` runs a job spec, or one job (or matrix
+//! instance) of a pipeline file, on this host without a control plane, so
+//! pipeline authors can try a job before pushing it. Steps run one at a time
+//! with the runner's executors (in `needs` order if the job declares any)
+//! and their output is printed to stdout.
+//!
+//! Nothing is reported, uploaded or cached. `uses:` steps are skipped, as
+//! actions rely on the control plane.
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+
+use crate::client::{JobSpec, StepSpec};
+use crate::config::Settings;
+use crate::executor::{create_executor, Executor, ExecutionContext, ExecutorType, OutputLine};
+use crate::log::SecretMasker;
+use crate::pipeline::Pipeline;
+use crate::utils::{available_shells, lease_gpus, select_shell};
+use crate::workspace::WorkspaceManager;
+use super::context::StepsContext;
+use super::graph::StepGraph;
+use super::outputs::{exported_env, parse_outputs, OutputFile, EXPORT_ENV, OUTPUT_ENV};
+use super::validate::{field_report, parse_errors, validate_job};
+use super::runner::{
+    container_options, job_executor, job_timeout, step_secrets, untrusted_container_options,
+    JobStatus, StepStatus,
+};
+
+/// Read a job spec from a JSON or YAML (`.yml`/`.yaml`) file, or job `job`
+/// of a pipeline file (see [`Pipeline::select`]).
+///
+/// Ids, environment, secrets, workspace and timeout may be left out of a
+/// hand-written spec and get local defaults.
+pub fn load_spec(path: &Path, job: Option<&str>) -> Result<JobSpec> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read job spec {:?}", path))?;
+    let yaml = matches!(path.extension().and_then(|e| e.to_str()), Some("yml" | "yaml"));
+    if yaml && Pipeline::is_pipeline(&text) {
+        let execution_id = format!("local-{}", uuid::Uuid::new_v4());
+        let pipeline = Pipeline::parse(&text, &execution_id)
+            .with_context(|| format!("Invalid pipeline {:?}", path))?;
+        return Ok(pipeline.select(job)?.spec.clone());
+    }
+    if let Some(job) = job {
+        anyhow::bail!("{:?} is a single job spec, not a pipeline with a job '{}'", path, job);
+    }
+    parse_spec(&text, yaml).with_context(|| format!("Invalid job spec {:?}", path))
+}
+
+fn parse_spec(text: &str, yaml: bool) -> Result<JobSpec> {
+    let mut spec: Value = if yaml {
+        serde_yaml::from_str(text)?
+    } else {
+        serde_json::from_str(text)?
+    };
+    fill_defaults(&mut spec)?;
+    let job = JobSpec::deserialize(&spec).map_err(|_| field_report("job spec", &parse_errors(&spec)))?;
+    validate_job(&job).map_err(|errors| field_report("job spec", &errors))?;
+    Ok(job)
+}
+
+/// Fill in the fields the control plane normally provides
+fn fill_defaults(spec: &mut Value) -> Result<()> {
+    let Some(job) = spec.as_object_mut() else {
+        anyhow::bail!("Job spec must be an object");
+    };
+
+    let job_id = format!("local-{}", uuid::Uuid::new_v4());
+    job.entry("execution_id").or_insert_with(|| json!(job_id));
+    job.entry("job_id").or_insert_with(|| json!(job_id));
+    job.entry("name").or_insert_with(|| json!("local"));
+    job.entry("environment").or_insert_with(|| json!({}));
+    job.entry("secrets").or_insert_with(|| json!({}));
+    job.entry("timeout_minutes").or_insert_with(|| json!(0));
+    job.entry("workspace").or_insert_with(|| json!({ "path": "." }));
+
+    let Some(steps) = job.get_mut("steps").and_then(Value::as_array_mut) else {
+        anyhow::bail!("Job spec has no steps");
+    };
+    for (i, step) in steps.iter_mut().enumerate() {
+        let Some(step) = step.as_object_mut() else {
+            anyhow::bail!("Step {} must be an object", i + 1);
+        };
+        let step_id = step.get("id").cloned().unwrap_or_else(|| json!(format!("step-{}", i + 1)));
+        step.entry("name").or_insert_with(|| step_id.clone());
+        step.entry("step_id").or_insert(step_id);
+    }
+    Ok(())
+}
+
+/// Steps of a local run
+struct LocalRun<'a> {
+    job: &'a JobSpec,
+    settings: &'a Settings,
+    executor: &'a dyn Executor,
+    workspace_path: &'a Path,
+    temp_dir: &'a Path,
+    gpus: &'a [u32],
+    masker: SecretMasker,
+    env_file: Option<OutputFile>,
+}
+
+/// Run `job` on this host, printing its output, and return its status
+pub async fn run_local(settings: &Settings, job: &JobSpec) -> Result<JobStatus> {
+    let executor = create_executor(job_executor(job, settings)?, settings)?;
+    let workspace_manager = WorkspaceManager::new(settings.workspace.clone());
+    let workspace = workspace_manager.create(&job.job_id, &job.labels).await?;
+    let job_timeout = job_timeout(job.timeout_minutes, &settings.job);
+    let gpu_lease = lease_gpus(&job.job_id, job.gpus)?;
+
+    println!("==> Job {} ({}) in {}", job.name, job.job_id, workspace.path.display());
+    let run = LocalRun {
+        job,
+        settings,
+        executor: executor.as_ref(),
+        workspace_path: &workspace.path,
+        temp_dir: &workspace.temp_dir,
+        gpus: &gpu_lease.devices,
+        masker: SecretMasker::new(job.secrets.values().cloned()),
+        env_file: OutputFile::create_env(&job.job_id, executor.executor_type()).await?,
+    };
+
+    let status = tokio::select! {
+        result = timeout(job_timeout, run_steps(&run, job_timeout)) => match result {
+            Ok(Ok(true)) => JobStatus::Success,
+            Ok(Ok(false)) => JobStatus::Failed,
+            Ok(Err(e)) => {
+                println!("==> Error: {}", run.masker.mask(&format!("{:#}", e)));
+                JobStatus::Failed
+            }
+            Err(_) => JobStatus::Timeout,
+        },
+        _ = tokio::signal::ctrl_c() => JobStatus::Cancelled,
+    };
+
+    if let Err(e) = executor.finish_job(&job.job_id).await {
+        tracing::warn!("Failed to release executor resources for job {}: {}", job.job_id, e);
+    }
+    if let Some(ref file) = run.env_file {
+        file.remove().await;
+    }
+    if job.cleanup.should_remove(status == JobStatus::Success) {
+        workspace_manager.remove(&workspace).await;
+    } else {
+        let path = workspace_manager.retain(&workspace, &job.job_id).await?;
+        println!("==> Workspace retained at {}", path.display());
+    }
+
+    println!("==> Job {} {}", job.name, status);
+    Ok(status)
+}
+
+/// Run the steps in order, returning whether all of them passed
+async fn run_steps(run: &LocalRun<'_>, job_timeout: Duration) -> Result<bool> {
+    let steps = &run.job.steps;
+    let graph = StepGraph::new(steps)?;
+    let start = Instant::now();
+    let mut steps_ctx = StepsContext::new(run.job.environment.clone());
+    let mut started = vec![false; steps.len()];
+    let mut finished = vec![false; steps.len()];
+    let mut passed = true;
+    let mut env_read = 0;
+
+    loop {
+        let next = match graph {
+            Some(ref graph) => graph.next_ready(&started, &finished),
+            None => started.iter().position(|s| !s),
+        };
+        let Some(i) = next else { break };
+        started[i] = true;
+
+        let step = &steps[i];
+        let mut step_ctx = match graph {
+            Some(_) => steps_ctx.scoped_to_needs(&step.needs),
+            None => steps_ctx.clone(),
+        };
+        step_ctx.enter_step(step, visible_secrets(run, step));
+
+        let condition = step.condition.as_deref().filter(|c| !c.trim().is_empty()).unwrap_or("success()");
+        let (status, outputs) = if !step_ctx.evaluate(condition) {
+            println!("==> Step {} skipped: {}", step.name, condition);
+            (StepStatus::Skipped, HashMap::new())
+        } else if step.run.is_none() {
+            println!("==> Step {} skipped: actions are not available offline", step.name);
+            (StepStatus::Skipped, HashMap::new())
+        } else {
+            let remaining = job_timeout.saturating_sub(start.elapsed());
+            match run_step(run, step, &step_ctx, remaining).await {
+                Ok(executed) => executed,
+                Err(e) => {
+                    println!("==> Step {} failed: {}", step.name, run.masker.mask(&format!("{:#}", e)));
+                    (StepStatus::Failed, HashMap::new())
+                }
+            }
+        };
+
+        if matches!(status, StepStatus::Failed | StepStatus::Timeout) && !step.continue_on_error {
+            passed = false;
+        }
+        steps_ctx.record(step, status, outputs);
+        if let Some(ref file) = run.env_file {
+            let (entries, read) = file.read_from(env_read).await?;
+            env_read = read;
+            let (exported, ignored) = exported_env(entries);
+            if !ignored.is_empty() {
+                println!("==> Ignored invalid or reserved names in {}: {}", EXPORT_ENV, ignored.join(", "));
+            }
+            steps_ctx.export_env(exported);
+        }
+        finished[i] = true;
+    }
+    Ok(passed)
+}
+
+/// Secrets a step may see
+fn visible_secrets(run: &LocalRun<'_>, step: &StepSpec) -> HashMap<String, String> {
+    if run.job.untrusted {
+        HashMap::new()
+    } else {
+        step_secrets(&run.job.secrets, step.secrets.as_deref(), run.settings.job.step_secrets)
+    }
+}
+
+/// Run a `run:` step with at most `remaining` job time
+async fn run_step(
+    run: &LocalRun<'_>,
+    step: &StepSpec,
+    steps_ctx: &StepsContext,
+    remaining: Duration,
+) -> Result<(StepStatus, HashMap<String, String>)> {
+    let job = run.job;
+    let step_start = Instant::now();
+    println!("==> Step {}", step.name);
+
+    let mut env = job.environment.clone();
+    env.extend(steps_ctx.exported_env().clone());
+    env.extend(steps_ctx.needs_env());
+    env.extend(steps_ctx.step_env().clone());
+    env.extend(visible_secrets(run, step));
+
+    let working_dir = match step.working_directory {
+        Some(ref wd) => run.workspace_path.join(steps_ctx.interpolate(wd)),
+        None => run.workspace_path.to_path_buf(),
+    };
+
+    let shell = if run.executor.executor_type() == ExecutorType::Shell {
+        let fallback = &run.settings.executor.shell.fallback;
+        let shell = select_shell(&step.shell, available_shells(), fallback)?;
+        if shell != step.shell {
+            println!("==> Shell '{}' is not installed, falling back to '{}'", step.shell, shell);
+        }
+        shell
+    } else {
+        step.shell.clone()
+    };
+
+    let step_timeout = Duration::from_secs(
+        step.timeout_minutes.max(run.settings.job.default_step_timeout_minutes) as u64 * 60
+    );
+    let container_options = if job.untrusted {
+        Some(untrusted_container_options(&run.settings.untrusted))
+    } else {
+        job.container
+            .as_ref()
+            .map(|spec| container_options(spec, job.workspace.repository_url.as_deref(), &run.settings.executor.docker))
+            .transpose()?
+    };
+    let mut ctx = ExecutionContext {
+        job_id: job.job_id.clone(),
+        step_id: step.step_id.clone(),
+        command: steps_ctx.interpolate(step.run.as_deref().unwrap_or_default()),
+        shell,
+        working_directory: working_dir,
+        workspace: run.workspace_path.to_path_buf(),
+        environment: env,
+        timeout: step_timeout.min(remaining),
+        container_image: job.container.as_ref().map(|c| c.image.clone()),
+        container_options,
+        output_encoding: step.output_encoding.unwrap_or(run.settings.executor.output_encoding),
+        warning_signal_after: None,
+        cancel: Default::default(),
+        limits: step.limits,
+        temp_dir: Some(run.temp_dir.to_path_buf()),
+        gpus: run.gpus.to_vec(),
+    };
+
+    run.executor.prepare(&ctx).await?;
+
+    let output_file = OutputFile::create(&job.job_id, &step.step_id, run.executor.executor_type()).await?;
+    if let Some(ref file) = output_file {
+        ctx.environment.insert(OUTPUT_ENV.to_string(), file.visible_path().display().to_string());
+    }
+    if let Some(ref file) = run.env_file {
+        ctx.environment.insert(EXPORT_ENV.to_string(), file.visible_path().display().to_string());
+    }
+
+    let (output_tx, mut output_rx) = mpsc::unbounded_channel::<OutputLine>();
+    let masker = run.masker.clone();
+    let printer = tokio::spawn(async move {
+        while let Some(line) = output_rx.recv().await {
+            println!("{}", masker.mask(&line.text));
+        }
+    });
+    let executed = run.executor.execute(&ctx, &output_tx).await;
+    drop(output_tx);
+    let _ = printer.await;
+    let file_outputs = match output_file {
+        Some(file) => file.take().await,
+        None => HashMap::new(),
+    };
+
+    if let Err(e) = run.executor.cleanup(&ctx).await {
+        tracing::warn!("Failed to clean up step {}: {}", step.step_id, e);
+    }
+    let result = executed?;
+
+    let status = if result.timed_out {
+        StepStatus::Timeout
+    } else if result.success() {
+        StepStatus::Success
+    } else {
+        StepStatus::Failed
+    };
+    println!(
+        "==> Step {} {} (exit code {}, {:.1}s)",
+        step.name,
+        status,
+        result.exit_code,
+        step_start.elapsed().as_secs_f64()
+    );
+    let mut outputs = parse_outputs(&result.stdout);
+    outputs.extend(file_outputs);
+    Ok((status, outputs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_spec_defaults() {
+        let yaml = "name: build\nsteps:\n  - id: greet\n    run: echo hi\n  - run: echo ${{ steps.greet.outputs.x }}\n";
+        let job = parse_spec(yaml, true).unwrap();
+        assert_eq!(job.name, "build");
+        assert!(job.job_id.starts_with("local-"));
+        assert_eq!(job.execution_id, job.job_id);
+        assert!(job.secrets.is_empty());
+        assert_eq!(job.steps[0].step_id, "greet");
+        assert_eq!(job.steps[0].name, "greet");
+        assert_eq!(job.steps[1].step_id, "step-2");
+        assert_eq!(job.steps[1].shell, "bash");
+
+        let json = r#"{"job_id": "j1", "name": "test", "timeout_minutes": 5,
+            "steps": [{"step_id": "s1", "name": "Test", "run": "cargo test"}]}"#;
+        let job = parse_spec(json, false).unwrap();
+        assert_eq!(job.job_id, "j1");
+        assert_eq!(job.timeout_minutes, 5);
+        assert_eq!(job.steps[0].name, "Test");
+
+        assert!(parse_spec("name: no steps", true).is_err());
+        assert!(parse_spec("[]", false).is_err());
+
+        let duplicate = parse_spec("name: dup\nsteps:\n  - step_id: a\n    run: make\n  - step_id: a\n    run: make\n", true);
+        assert!(format!("{:#}", duplicate.unwrap_err()).contains("steps[1].step_id: duplicates steps[0]"));
+    }
+
+    #[tokio::test]
+    async fn test_env_file() {
+        let mut settings = Settings::load_offline().unwrap();
+        settings.workspace.base_path = std::env::temp_dir().join("muelsyse-local-env");
+        let yaml = concat!(
+            "name: env\nsteps:\n",
+            "  - run: echo VERSION=1.2.3 >> \"$MUELSYSE_ENV\"\n",
+            "  - run: test \"$VERSION-$IMAGE\" = 1.2.3-app:1.2.3\n",
+            "    env:\n      IMAGE: app:${{ env.VERSION }}\n",
+        );
+        let job = parse_spec(yaml, true).unwrap();
+        assert_eq!(run_local(&settings, &job).await.unwrap(), JobStatus::Success);
+    }
+
+    #[test]
+    fn test_exit_code() {
+        assert_eq!(JobStatus::Success.exit_code(), 0);
+        assert_eq!(JobStatus::Failed.exit_code(), 1);
+        assert_eq!(JobStatus::Timeout.exit_code(), 124);
+        assert_eq!(JobStatus::Cancelled.exit_code(), 130);
+    }
+}
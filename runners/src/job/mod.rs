@@ -1,6 +1,23 @@
 //! Job runner module
 
 mod runner;
+mod admin;
+mod admission;
+mod context;
+mod debug;
+mod diagnostics;
+mod env;
+mod graph;
+mod history;
+mod hooks;
+mod liveness;
+mod local;
+mod outputs;
+mod resources;
+mod scheduler;
+mod timeline;
+mod uploads;
+mod validate;
 
 pub use runner::{
     JobRunner,
@@ -8,4 +25,21 @@ pub use runner::{
     StepStatus,
     JobContext,
     RetryConfig,
+    PhaseTimeouts,
+    PhaseTimings,
 };
+pub use admission::{Admission, AdmissionPolicy, Rejection};
+pub use context::{StepsContext, StepResult};
+pub use env::EnvLimits;
+pub use graph::StepGraph;
+pub use hooks::{HookPayload, StepHooks};
+pub use history::{spec_digest, ArtifactRecord, HistoryQuery, HistoryRecord, JobHistory, StepRecord};
+pub use diagnostics::DiagnosticTarget;
+pub use liveness::{LivenessReport, LivenessWriter};
+pub use local::{load_spec, run_local};
+pub use outputs::{exported_env, parse_output_file, parse_outputs, OutputFile, EXPORT_ENV, OUTPUT_ENV};
+pub use resources::{ResourceGuard, ResourceLocks};
+pub use scheduler::Scheduler;
+pub use timeline::Timeline;
+pub use uploads::{PendingUpload, UploadQueue};
+pub use validate::{field_report, parse_errors, validate_job, FieldError, MAX_TIMEOUT_MINUTES};
@@ -0,0 +1,269 @@
+//! Step outputs
+//!
+//! A step sets outputs by appending to the file named by `MUELSYSE_OUTPUT`,
+//! one `name=value` per line, or for values spanning lines:
+//!
+//! ```text
+//! name<<EOF
+//! first line
+//! second line
+//! EOF
+//! ```
+//!
+//! The `::set-output name=NAME::VALUE` command in stdout is still read for
+//! compatibility; no other stdout line is taken as an output.
+//!
+//! Environment variables for later steps are written the same way to the
+//! job's file named by `MUELSYSE_ENV`. After each step the entries added
+//! since the last read are merged into the environment of the steps that
+//! start afterwards; names of the runner's own `MUELSYSE_*` variables are
+//! ignored. Kubernetes steps cannot see host files and get neither file.
+
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::executor::{job_output_dir, ExecutorType, CONTAINER_OUTPUT_DIR};
+
+/// Variable naming a step's output file
+pub const OUTPUT_ENV: &str = "MUELSYSE_OUTPUT";
+
+/// Variable naming the job's env file
+pub const EXPORT_ENV: &str = "MUELSYSE_ENV";
+
+/// A step's output file, or a job's env file
+#[derive(Debug)]
+pub struct OutputFile {
+    host_path: PathBuf,
+    visible_path: PathBuf,
+}
+
+impl OutputFile {
+    /// Create an empty output file for a step, or `None` if steps of
+    /// `executor` cannot see host files (Kubernetes pods)
+    pub async fn create(job_id: &str, step_id: &str, executor: ExecutorType) -> Result<Option<Self>> {
+        Self::create_named(job_id, &format!("{}-{}", job_id, step_id), executor).await
+    }
+
+    /// Create an empty env file shared by a job's steps
+    pub async fn create_env(job_id: &str, executor: ExecutorType) -> Result<Option<Self>> {
+        Self::create_named(job_id, &format!("{}.env", job_id), executor).await
+    }
+
+    /// Create `name` in the job's own output directory, the only one its
+    /// containers can see
+    async fn create_named(job_id: &str, name: &str, executor: ExecutorType) -> Result<Option<Self>> {
+        let host_dir = job_output_dir(job_id);
+        let host_path = host_dir.join(name);
+        let visible_path = match executor {
+            ExecutorType::Kubernetes => return Ok(None),
+            ExecutorType::Docker => Path::new(CONTAINER_OUTPUT_DIR).join(name),
+            ExecutorType::Shell | ExecutorType::Custom => host_path.clone(),
+        };
+
+        tokio::fs::create_dir_all(&host_dir)
+            .await
+            .context("Failed to create step output directory")?;
+        tokio::fs::write(&host_path, b"")
+            .await
+            .with_context(|| format!("Failed to create output file {:?}", host_path))?;
+        // The step may run as any user inside its container
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            tokio::fs::set_permissions(&host_path, std::fs::Permissions::from_mode(0o666)).await?;
+        }
+
+        Ok(Some(Self { host_path, visible_path }))
+    }
+
+    /// The path as seen by the step
+    pub fn visible_path(&self) -> &Path {
+        &self.visible_path
+    }
+
+    /// Entries written after the first `offset` bytes, and the file's length
+    pub async fn read_from(&self, offset: usize) -> Result<(HashMap<String, String>, usize)> {
+        let contents = tokio::fs::read_to_string(&self.host_path)
+            .await
+            .with_context(|| format!("Failed to read {:?}", self.host_path))?;
+        let added = contents.get(offset..).unwrap_or_default();
+        Ok((parse_output_file(added), contents.len().max(offset)))
+    }
+
+    pub async fn remove(&self) {
+        if let Err(e) = tokio::fs::remove_file(&self.host_path).await {
+            tracing::debug!("Failed to remove output file {:?}: {}", self.host_path, e);
+        }
+    }
+
+    /// Read the outputs the step wrote and remove the file
+    pub async fn take(self) -> HashMap<String, String> {
+        let contents = tokio::fs::read_to_string(&self.host_path).await;
+        self.remove().await;
+        match contents {
+            Ok(contents) => parse_output_file(&contents),
+            Err(e) => {
+                tracing::warn!("Failed to read output file {:?}: {}", self.host_path, e);
+                HashMap::new()
+            }
+        }
+    }
+}
+
+/// Parse `name=value` and `name<<DELIMITER` entries of an output file
+pub fn parse_output_file(contents: &str) -> HashMap<String, String> {
+    let mut outputs = HashMap::new();
+    let mut lines = contents.lines();
+
+    while let Some(line) = lines.next() {
+        let line = line.trim_end_matches('\r');
+        let heredoc = line.split_once("<<").filter(|(name, _)| !name.contains('='));
+        if let Some((name, delimiter)) = heredoc {
+            let mut value = Vec::new();
+            for line in lines.by_ref() {
+                let line = line.trim_end_matches('\r');
+                if line == delimiter {
+                    break;
+                }
+                value.push(line);
+            }
+            outputs.insert(name.trim().to_string(), value.join("\n"));
+        } else if let Some((name, value)) = line.split_once('=') {
+            if !name.trim().is_empty() {
+                outputs.insert(name.trim().to_string(), value.to_string());
+            }
+        }
+    }
+
+    outputs
+}
+
+/// Split env file entries into variables steps may set and the names of
+/// those they may not: invalid names and the runner's `MUELSYSE_*` variables
+pub fn exported_env(entries: HashMap<String, String>) -> (HashMap<String, String>, Vec<String>) {
+    let (exported, ignored): (HashMap<_, _>, HashMap<_, _>) = entries.into_iter().partition(|(name, _)| {
+        name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+            && !name.starts_with("MUELSYSE_")
+    });
+    let mut ignored: Vec<String> = ignored.into_keys().collect();
+    ignored.sort();
+    (exported, ignored)
+}
+
+/// Parse `::set-output name=NAME::VALUE` commands from stdout
+pub fn parse_outputs(stdout: &str) -> HashMap<String, String> {
+    stdout
+        .lines()
+        .filter_map(|line| line.strip_prefix("::set-output name="))
+        .filter_map(|rest| rest.split_once("::"))
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_parse_outputs() {
+        let stdout = r#"
+Hello World
+::set-output name=result::success
+::set-output name=count::42
+BUILD_ID=123
+"#;
+
+        let outputs = parse_outputs(stdout);
+        assert_eq!(outputs.get("result"), Some(&"success".to_string()));
+        assert_eq!(outputs.get("count"), Some(&"42".to_string()));
+        // Plain `key=value` output is not an output
+        assert_eq!(outputs.get("BUILD_ID"), None);
+    }
+
+    #[test]
+    fn test_parse_output_file() {
+        let contents = "version=1.2.3\nurl=https://x/?a=b\r\nnotes<<EOF\nfirst\n\nthird=3\nEOF\nempty=\nbare line\n";
+        let outputs = parse_output_file(contents);
+        assert_eq!(outputs["version"], "1.2.3");
+        assert_eq!(outputs["url"], "https://x/?a=b");
+        assert_eq!(outputs["notes"], "first\n\nthird=3");
+        assert_eq!(outputs["empty"], "");
+        assert_eq!(outputs.len(), 4);
+
+        // An unterminated value runs to the end of the file
+        assert_eq!(parse_output_file("log<<END\na\nb")["log"], "a\nb");
+    }
+
+    #[tokio::test]
+    async fn test_output_file() {
+        let file = OutputFile::create("job-outputs", "step-1", ExecutorType::Shell).await.unwrap().unwrap();
+        assert_eq!(file.visible_path(), job_output_dir("job-outputs").join("job-outputs-step-1"));
+        tokio::fs::write(file.visible_path(), "answer=42\n").await.unwrap();
+
+        let path = file.host_path.clone();
+        assert_eq!(file.take().await["answer"], "42");
+        assert!(!path.exists());
+
+        let file = OutputFile::create("job-outputs", "step-2", ExecutorType::Docker).await.unwrap().unwrap();
+        assert_eq!(file.visible_path(), Path::new("/__muelsyse/outputs/job-outputs-step-2"));
+        assert!(file.take().await.is_empty());
+        assert!(OutputFile::create("job-outputs", "step-3", ExecutorType::Kubernetes).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_env_file() {
+        let file = OutputFile::create_env("job-env", ExecutorType::Shell).await.unwrap().unwrap();
+        assert_eq!(file.visible_path(), job_output_dir("job-env").join("job-env.env"));
+
+        tokio::fs::write(file.visible_path(), "VERSION=1.2.3\nNOTES<<EOF\na\nb\nEOF\n").await.unwrap();
+        let (env, offset) = file.read_from(0).await.unwrap();
+        assert_eq!(env.len(), 2);
+        assert_eq!(env["NOTES"], "a\nb");
+
+        // Only entries appended since the last read are returned
+        let mut contents = tokio::fs::read_to_string(file.visible_path()).await.unwrap();
+        contents.push_str("VERSION=1.2.4\n");
+        tokio::fs::write(file.visible_path(), contents).await.unwrap();
+        let (env, _) = file.read_from(offset).await.unwrap();
+        assert_eq!(env, HashMap::from([("VERSION".to_string(), "1.2.4".to_string())]));
+        file.remove().await;
+        assert!(!file.visible_path().exists());
+    }
+
+    #[test]
+    fn test_exported_env() {
+        let entries = HashMap::from([
+            ("VERSION".to_string(), "1".to_string()),
+            ("_private2".to_string(), "2".to_string()),
+            ("MUELSYSE_OUTPUT".to_string(), "/etc/passwd".to_string()),
+            ("2FA".to_string(), "x".to_string()),
+            ("A-B".to_string(), "x".to_string()),
+        ]);
+        let (exported, ignored) = exported_env(entries);
+        assert_eq!(exported.len(), 2);
+        assert_eq!(ignored, vec!["2FA", "A-B", "MUELSYSE_OUTPUT"]);
+    }
+
+    proptest! {
+        #[test]
+        fn prop_parse_outputs_any_text(stdout in "\\PC*(\n\\PC*){0,8}") {
+            let _ = parse_outputs(&stdout);
+            let _ = parse_output_file(&stdout);
+        }
+
+        #[test]
+        fn prop_parse_outputs_set_output(
+            expected in prop::collection::hash_map("[A-Za-z_][A-Za-z0-9_]{0,15}", "[^\r\n]{0,40}", 0..8),
+            noise in prop::collection::vec("[^=\r\n:]{0,40}", 0..8),
+        ) {
+            let mut stdout = noise.join("\n");
+            for (name, value) in &expected {
+                stdout.push_str(&format!("\n::set-output name={}::{}", name, value));
+            }
+            prop_assert_eq!(parse_outputs(&stdout), expected);
+        }
+    }
+}
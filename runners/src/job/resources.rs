@@ -0,0 +1,103 @@
+//! Runner-local resource locks
+//!
+//! Named resources (test devices, license servers, ...) are declared in the
+//! runner config with a capacity. Jobs and steps that require them wait
+//! until every required resource has a free slot.
+
+use anyhow::Result;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::config::ResourceConfig;
+
+/// Semaphores for the runner's local resources
+#[derive(Debug, Default)]
+pub struct ResourceLocks {
+    semaphores: HashMap<String, Arc<Semaphore>>,
+}
+
+/// Held resources, released on drop
+#[derive(Debug)]
+pub struct ResourceGuard {
+    _permits: Vec<OwnedSemaphorePermit>,
+
+    /// Time spent waiting for the resources
+    pub waited: Duration,
+}
+
+impl ResourceLocks {
+    pub fn new(resources: &[ResourceConfig]) -> Self {
+        let semaphores = resources
+            .iter()
+            .map(|r| (r.name.clone(), Arc::new(Semaphore::new(r.capacity.max(1)))))
+            .collect();
+        Self { semaphores }
+    }
+
+    /// Wait for a slot on each of `names`.
+    ///
+    /// Resources are taken in sorted order so concurrent jobs cannot deadlock.
+    /// Unknown names fail immediately.
+    pub async fn acquire(&self, names: &[String]) -> Result<ResourceGuard> {
+        let mut names: Vec<&String> = names.iter().collect();
+        names.sort();
+        names.dedup();
+
+        let unknown: Vec<&str> = names
+            .iter()
+            .filter(|n| !self.semaphores.contains_key(n.as_str()))
+            .map(|n| n.as_str())
+            .collect();
+        if !unknown.is_empty() {
+            anyhow::bail!("Runner does not provide resources: {}", unknown.join(", "));
+        }
+
+        let start = Instant::now();
+        let mut permits = Vec::with_capacity(names.len());
+        for name in names {
+            let permit = self.semaphores[name.as_str()].clone().acquire_owned().await?;
+            permits.push(permit);
+        }
+
+        Ok(ResourceGuard {
+            _permits: permits,
+            waited: start.elapsed(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn locks() -> ResourceLocks {
+        ResourceLocks::new(&[
+            ResourceConfig { name: "device".into(), capacity: 1 },
+            ResourceConfig { name: "license".into(), capacity: 2 },
+        ])
+    }
+
+    #[tokio::test]
+    async fn test_acquire_serializes_access() {
+        let locks = locks();
+        let held = locks.acquire(&["device".into()]).await.unwrap();
+
+        let waiting = tokio::time::timeout(
+            Duration::from_millis(50),
+            locks.acquire(&["device".into(), "license".into()]),
+        ).await;
+        assert!(waiting.is_err(), "device should still be held");
+
+        drop(held);
+        let guard = locks.acquire(&["license".into(), "device".into()]).await.unwrap();
+        assert!(guard.waited < Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_resource() {
+        let err = locks().acquire(&["gpu".into()]).await.unwrap_err();
+        assert!(err.to_string().contains("gpu"));
+    }
+}
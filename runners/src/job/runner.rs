@@ -8,18 +8,55 @@
 //! - Connection state awareness
 
 use anyhow::Result;
-use std::collections::HashMap;
-use std::path::PathBuf;
+use chrono::{DateTime, Utc};
+use futures_util::stream::{FuturesUnordered, StreamExt};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
-use tokio::sync::{Mutex, RwLock, broadcast};
+use tokio::sync::{mpsc, oneshot, watch, Mutex, RwLock, broadcast};
 use tokio::time::timeout;
-use tracing::{info, warn, error, debug};
-
-use crate::config::{Settings, JobConfig};
-use crate::client::{ControlPlaneClient, WebSocketClient, ConnectionState, IncomingMessage, JobSpec, StepSpec};
-use crate::executor::{Executor, ExecutorType, ExecutionContext, create_executor};
-use crate::log::{LogStreamer, LogStreamerManager};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn, error, debug, Instrument, Span};
+
+use crate::actions::{ActionContext, ActionRegistry, PostAction};
+use crate::artifact::{inspect_file, ArtifactManager, ArtifactStream, ArtifactUploader, ChunkedUpload, PackagedArtifact};
+use crate::cache::{resolve_path, within_workspace, CacheStore, CacheVolume, VolumeIndex};
+use crate::config::{
+    ConfigOverrides, Settings, JobConfig, DockerConfig, LogLevelControl, StepSecrets, UntrustedConfig,
+};
+use crate::client::{
+    ControlPlaneClient, WebSocketClient, ConnectionState, IncomingMessage, OutgoingMessage, JobSpec,
+    StepSpec, StatusMeta, ContainerSpec, HttpClient, JobSnapshot, OutputFileRef, PendingJobSnapshot, Capacity,
+    FleetInfo, Cancellation,
+};
+use crate::executor::{
+    Executor, ExecutorType, ExecutionContext, ExecutionPhase, ContainerMode, ContainerOptions, DockerExecutor,
+    OutputLine, RepeatCollapser, ResourceUsage, apply_profile, create_executor, create_private_dir,
+    job_script_dir, remove_job_dirs, CONTAINER_SCRIPT_DIR,
+};
+use crate::error::{RetryClass, RunnerError};
+use crate::events::{spawn_audit_log, spawn_webhook, EventBus, EventCounters, RunnerEvent};
+use crate::log::{annotation::MAX_ANNOTATIONS, parse_annotations, Annotation, AnnotationLevel, LogStreamer, LogStreamerManager, SecretMasker};
+use crate::telemetry::{job_span, phase_span, record_status, step_span};
+use crate::utils::{available_shells, capabilities, kvm_available, lease_gpus, nvidia_gpus, select_shell, StatsCache};
+use crate::workspace::{dir_size, WorkspaceManager};
+use super::admin::{self, AdminState};
+use super::admission::{Admission, AdmissionPolicy, Rejection};
+use super::scheduler::Scheduler;
+use super::context::StepsContext;
+use super::debug::{debug_exec_context, DebugCommand, DebugHandle, DEBUG_OUTPUT_MAX_BYTES};
+use super::diagnostics::{run_diagnostics, truncate, DiagnosticTarget};
+use super::env::{env_file_dir, indirect_oversized, EnvLimits};
+use super::outputs::{exported_env, parse_outputs, OutputFile, EXPORT_ENV, OUTPUT_ENV};
+use super::graph::StepGraph;
+use super::history::{ArtifactRecord, HistoryQuery, HistoryRecord, JobHistory, StepRecord};
+use super::hooks::{HookPayload, StepHooks};
+use super::liveness::{LivenessReport, LivenessWriter};
+use super::resources::ResourceLocks;
+use super::timeline::Timeline;
+use super::uploads::{PendingUpload, UploadQueue};
+use super::validate::{parse_errors, validate_job};
 
 // ============================================================================
 // Job Status Types
@@ -49,6 +86,18 @@ impl std::fmt::Display for JobStatus {
     }
 }
 
+impl JobStatus {
+    /// Process exit code for a job run from the command line
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::Success => 0,
+            Self::Timeout => 124,
+            Self::Cancelled => 130,
+            Self::Pending | Self::Running | Self::Failed => 1,
+        }
+    }
+}
+
 /// Step execution status
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum StepStatus {
@@ -58,6 +107,8 @@ pub enum StepStatus {
     Failed,
     Timeout,
     Skipped,
+    /// Stopped because the job was cancelled
+    Cancelled,
 }
 
 impl std::fmt::Display for StepStatus {
@@ -69,6 +120,7 @@ impl std::fmt::Display for StepStatus {
             Self::Failed => write!(f, "failed"),
             Self::Timeout => write!(f, "timeout"),
             Self::Skipped => write!(f, "skipped"),
+            Self::Cancelled => write!(f, "cancelled"),
         }
     }
 }
@@ -81,31 +133,109 @@ impl std::fmt::Display for StepStatus {
 pub struct JobContext {
     pub job_id: String,
     pub cancel_tx: broadcast::Sender<()>,
-    pub cancelled: Arc<RwLock<bool>>,
+    /// When cancellation was first requested
+    pub cancelled_at: Arc<RwLock<Option<DateTime<Utc>>>>,
+    pub diagnostic_target: Arc<RwLock<Option<DiagnosticTarget>>>,
+    pub progress: Arc<RwLock<JobSnapshot>>,
+    /// Debug session kept open after the job failed
+    pub debug_session: Arc<RwLock<Option<DebugHandle>>>,
+    /// Newer job of the concurrency group this one was cancelled for
+    pub superseded_by: Arc<RwLock<Option<String>>>,
 }
 
 impl JobContext {
     pub fn new(job_id: String) -> Self {
         let (cancel_tx, _) = broadcast::channel(1);
+        let progress = JobSnapshot { job_id: job_id.clone(), ..Default::default() };
         Self {
             job_id,
             cancel_tx,
-            cancelled: Arc::new(RwLock::new(false)),
+            cancelled_at: Arc::new(RwLock::new(None)),
+            diagnostic_target: Arc::new(RwLock::new(None)),
+            progress: Arc::new(RwLock::new(progress)),
+            debug_session: Arc::new(RwLock::new(None)),
+            superseded_by: Arc::new(RwLock::new(None)),
         }
     }
 
     pub async fn cancel(&self) {
-        *self.cancelled.write().await = true;
+        self.cancelled_at.write().await.get_or_insert_with(Utc::now);
         let _ = self.cancel_tx.send(());
     }
 
     pub async fn is_cancelled(&self) -> bool {
-        *self.cancelled.read().await
+        self.cancelled_at.read().await.is_some()
+    }
+
+    pub async fn cancelled_at(&self) -> Option<DateTime<Utc>> {
+        *self.cancelled_at.read().await
+    }
+
+    /// Cancel the job for `job_id`, a newer job of its concurrency group
+    pub async fn supersede(&self, job_id: &str) {
+        *self.superseded_by.write().await = Some(job_id.to_string());
+        self.cancel().await;
+    }
+
+    /// Error and status outputs the cancelled job ends with
+    pub async fn cancellation(&self) -> (RunnerError, HashMap<String, String>) {
+        match self.superseded_by.read().await.as_deref() {
+            Some(job_id) => superseded(job_id),
+            None => {
+                let error = RunnerError::Cancelled("Job cancelled".into());
+                let outputs = error.to_outputs();
+                (error, outputs)
+            }
+        }
     }
 
     pub fn subscribe(&self) -> broadcast::Receiver<()> {
         self.cancel_tx.subscribe()
     }
+
+    pub async fn set_diagnostic_target(&self, target: DiagnosticTarget) {
+        *self.diagnostic_target.write().await = Some(target);
+    }
+
+    /// Replace the masker of the current step's diagnostics, once the
+    /// step's just-in-time secrets are known
+    pub async fn mask_in_diagnostics(&self, masker: SecretMasker) {
+        if let Some(target) = self.diagnostic_target.write().await.as_mut() {
+            target.masker = masker;
+        }
+    }
+
+    async fn set_debug_session(&self, session: Option<DebugHandle>) {
+        *self.debug_session.write().await = session;
+    }
+
+    /// Reset progress for a new attempt of `job`
+    pub async fn start_attempt(&self, job: &JobSpec, attempt: u32, started_at: DateTime<Utc>) {
+        *self.progress.write().await = JobSnapshot {
+            job_id: job.job_id.clone(),
+            name: job.name.clone(),
+            attempt,
+            started_at: Some(started_at),
+            current_step: None,
+            steps_completed: 0,
+            steps_total: job.steps.len(),
+        };
+    }
+
+    pub async fn step_started(&self, step: &StepSpec) {
+        self.progress.write().await.current_step = Some(step.name.clone());
+    }
+
+    pub async fn step_finished(&self) {
+        let mut progress = self.progress.write().await;
+        progress.current_step = None;
+        progress.steps_completed += 1;
+    }
+
+    /// Current progress for status reports
+    pub async fn snapshot(&self) -> JobSnapshot {
+        self.progress.read().await.clone()
+    }
 }
 
 // ============================================================================
@@ -118,6 +248,8 @@ pub struct RetryConfig {
     pub max_attempts: u32,
     pub delay_secs: u64,
     pub backoff_multiplier: f64,
+    /// Errors worth another attempt
+    pub retry_on: Vec<RetryClass>,
 }
 
 impl Default for RetryConfig {
@@ -126,6 +258,7 @@ impl Default for RetryConfig {
             max_attempts: 3,
             delay_secs: 5,
             backoff_multiplier: 2.0,
+            retry_on: vec![RetryClass::InfraError],
         }
     }
 }
@@ -136,10 +269,109 @@ impl From<&JobConfig> for RetryConfig {
             max_attempts: config.max_retries,
             delay_secs: config.retry_delay_secs,
             backoff_multiplier: 2.0,
+            retry_on: config.retry_on.clone(),
         }
     }
 }
 
+impl RetryConfig {
+    /// The runner's retry settings, with the job's own `retry_on`
+    pub fn for_job(config: &JobConfig, job: &JobSpec) -> Self {
+        let mut retry = Self::from(config);
+        if let Some(ref retry_on) = job.retry_on {
+            retry.retry_on = retry_on.clone();
+        }
+        retry
+    }
+
+    /// Whether a job failing with `error` is run again
+    pub fn retries(&self, error: &RunnerError) -> bool {
+        error.retry_class().is_some_and(|class| self.retry_on.contains(&class))
+    }
+}
+
+// ============================================================================
+// Phase Budgets
+// ============================================================================
+
+/// Time budgets for the phases of a single step
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PhaseTimeouts {
+    pub prepare: Duration,
+    pub execute: Duration,
+    pub collect: Duration,
+    /// When the job's time runs out, if the budgets are capped by it
+    deadline: Option<Instant>,
+}
+
+impl PhaseTimeouts {
+    /// Build phase budgets from job config with the given execute budget
+    pub fn new(config: &JobConfig, execute: Duration) -> Self {
+        Self {
+            prepare: Duration::from_secs(config.prepare_timeout_secs),
+            execute,
+            collect: Duration::from_secs(config.collect_timeout_secs),
+            deadline: None,
+        }
+    }
+
+    /// Get the budget for a phase starting now
+    pub fn get(&self, phase: ExecutionPhase) -> Duration {
+        self.get_at(phase, Instant::now())
+    }
+
+    /// The budget for a phase starting at `now`: its own, or what earlier
+    /// phases left of the job's time if that is less
+    fn get_at(&self, phase: ExecutionPhase, now: Instant) -> Duration {
+        let budget = match phase {
+            ExecutionPhase::Prepare => self.prepare,
+            ExecutionPhase::Execute => self.execute,
+            ExecutionPhase::Collect => self.collect,
+        };
+        match self.deadline {
+            Some(deadline) => budget.min(deadline.saturating_duration_since(now)),
+            None => budget,
+        }
+    }
+
+    /// Cap the phases together to the `remaining` job time
+    pub fn capped(self, remaining: Duration) -> Self {
+        Self {
+            deadline: Some(Instant::now() + remaining),
+            ..self
+        }
+    }
+}
+
+/// Measured duration of each phase of a step
+#[derive(Debug, Clone, Default)]
+pub struct PhaseTimings {
+    durations: Vec<(ExecutionPhase, Duration)>,
+}
+
+impl PhaseTimings {
+    /// Record how long a phase took
+    pub fn record(&mut self, phase: ExecutionPhase, duration: Duration) {
+        self.durations.push((phase, duration));
+    }
+
+    /// Get the recorded duration of a phase
+    pub fn get(&self, phase: ExecutionPhase) -> Option<Duration> {
+        self.durations
+            .iter()
+            .find(|(p, _)| *p == phase)
+            .map(|(_, d)| *d)
+    }
+
+    /// Render timings as status update outputs (`phase_<name>_ms`)
+    pub fn to_outputs(&self) -> HashMap<String, String> {
+        self.durations
+            .iter()
+            .map(|(phase, d)| (format!("phase_{}_ms", phase), d.as_millis().to_string()))
+            .collect()
+    }
+}
+
 // ============================================================================
 // Main Job Runner
 // ============================================================================
@@ -148,24 +380,119 @@ impl From<&JobConfig> for RetryConfig {
 pub struct JobRunner {
     settings: Settings,
     client: ControlPlaneClient,
-    current_jobs: Arc<Mutex<u32>>,
     job_contexts: Arc<RwLock<HashMap<String, Arc<JobContext>>>>,
+    /// Running and pending jobs
+    scheduler: Arc<Mutex<Scheduler>>,
+    admission: Arc<RwLock<AdmissionPolicy>>,
+    /// State of the control plane WebSocket; jobs are claimed over HTTP
+    /// while it is reconnecting or failed
+    connection: Arc<watch::Sender<ConnectionState>>,
+    /// Overrides pushed by the control plane
+    overrides: Arc<Mutex<ConfigOverrides>>,
+    log_level: Option<LogLevelControl>,
+    /// Shuts the runner down once a drain completes, if requested
+    exit_when_drained: Mutex<Option<tokio::task::JoinHandle<()>>>,
     log_manager: Arc<LogStreamerManager>,
     shutdown_tx: broadcast::Sender<()>,
+    events: EventBus,
+    event_counters: EventCounters,
+    resources: Arc<ResourceLocks>,
 }
 
 impl JobRunner {
     pub fn new(settings: Settings, client: ControlPlaneClient) -> Self {
-        let log_manager = Arc::new(LogStreamerManager::new(settings.logging.clone()));
+        let log_manager = Arc::new(
+            LogStreamerManager::new(settings.logging.clone())
+                .with_persist_dir(settings.workspace.cache_path.join("logs"))
+                .with_spool_base(settings.workspace.base_path.clone()),
+        );
         let (shutdown_tx, _) = broadcast::channel(1);
+        let events = EventBus::new(&settings.runner.id, settings.events.capacity);
+        let resources = Arc::new(ResourceLocks::new(&settings.runner.resources));
+        let overrides = ConfigOverrides::load(&settings.runner.overrides_path).unwrap_or_else(|e| {
+            warn!("Ignoring saved config overrides: {:#}", e);
+            ConfigOverrides::default()
+        });
+        let workspace = &settings.workspace;
+        if workspace.overlay && workspace.overlay_lower_dirs.is_empty() && workspace.snapshots.is_empty() {
+            warn!("Overlay workspaces are enabled but no lower directories or snapshots are configured; using plain workspaces");
+        }
+        let admission = AdmissionPolicy::from(&settings).with_overrides(&overrides);
+        let scheduler = Scheduler::new(settings.runner.concurrency.clone())
+            .with_gpus(nvidia_gpus().len() as u32);
 
         Self {
             settings,
             client,
-            current_jobs: Arc::new(Mutex::new(0)),
             job_contexts: Arc::new(RwLock::new(HashMap::new())),
+            scheduler: Arc::new(Mutex::new(scheduler)),
+            admission: Arc::new(RwLock::new(admission)),
+            connection: Arc::new(watch::channel(ConnectionState::Connecting).0),
+            overrides: Arc::new(Mutex::new(overrides)),
+            log_level: None,
+            exit_when_drained: Mutex::new(None),
             log_manager,
             shutdown_tx,
+            events,
+            event_counters: EventCounters::default(),
+            resources,
+        }
+    }
+
+    /// Let `config_update` messages change the log verbosity, applying any
+    /// saved override now
+    pub fn with_log_level_control(mut self, control: LogLevelControl) -> Self {
+        let saved = self.overrides.try_lock().ok().and_then(|overrides| overrides.log_level.clone());
+        if let Some(level) = saved {
+            if let Err(e) = control(Some(&level)) {
+                warn!("Failed to apply saved log level {}: {:#}", level, e);
+            }
+        }
+        self.log_level = Some(control);
+        self
+    }
+
+    /// Event bus for subscribing to runner events
+    pub fn events(&self) -> &EventBus {
+        &self.events
+    }
+
+    /// Per-kind counts of events emitted since start
+    pub fn event_counters(&self) -> &EventCounters {
+        &self.event_counters
+    }
+
+    /// Start the configured event subscribers
+    fn spawn_event_subscribers(&self) -> Vec<tokio::task::JoinHandle<()>> {
+        let config = &self.settings.events;
+        let mut handles = vec![self.event_counters.spawn(&self.events)];
+
+        if let Some(ref path) = config.audit_log {
+            handles.push(spawn_audit_log(&self.events, path.clone()));
+        }
+        if let Some(ref url) = config.webhook_url {
+            handles.push(spawn_webhook(&self.events, url.clone(), config.webhook_events.clone()));
+        }
+        handles
+    }
+
+    /// Start the local admin endpoint, if enabled
+    fn spawn_admin_endpoint(&self) -> Option<tokio::task::JoinHandle<()>> {
+        if !self.settings.admin.enabled {
+            return None;
+        }
+        let state = AdminState {
+            settings: self.settings.clone(),
+            job_contexts: self.job_contexts.clone(),
+            scheduler: self.scheduler.clone(),
+            admission: self.admission.clone(),
+        };
+        match admin::spawn(state) {
+            Ok(handle) => Some(handle),
+            Err(e) => {
+                warn!("Admin endpoint disabled: {:#}", e);
+                None
+            }
         }
     }
 
@@ -186,6 +513,23 @@ impl JobRunner {
         // Create a separate receiver for the main loop
         let mut shutdown_rx = self.shutdown_tx.subscribe();
 
+        // Keep the liveness file fresh independently of the connection
+        let liveness_handle = self.spawn_liveness_task();
+        let stats_handle = StatsCache::spawn_refresh(
+            Duration::from_secs(self.settings.runner.stats_refresh_secs.max(1))
+        );
+
+        info!("Available shells: {:?}", available_shells());
+        info!("Host capabilities: {:?}", capabilities());
+
+        let subscriber_handles = self.spawn_event_subscribers();
+        let admin_handle = self.spawn_admin_endpoint();
+        let docker_gc_handle = self.spawn_docker_gc_task();
+
+        // Runs alongside the connection attempts and the waits between them
+        let claims = self.claim_while_disconnected();
+        tokio::pin!(claims);
+
         loop {
             info!("Connecting to control plane...");
 
@@ -197,18 +541,23 @@ impl JobRunner {
                     break;
                 }
 
+                _ = &mut claims => {}
+
                 result = self.run_connection() => {
                     match result {
                         Ok(_) => {
                             info!("Connection closed normally");
+                            self.events.emit(RunnerEvent::Disconnected { reason: "closed".to_string() });
                             reconnect_delay = Duration::from_millis(
                                 self.settings.websocket.reconnect_initial_delay_ms
                             );
                         }
                         Err(e) => {
                             error!("Connection error: {}", e);
+                            self.events.emit(RunnerEvent::Disconnected { reason: e.to_string() });
                         }
                     }
+                    self.connection.send_replace(ConnectionState::Reconnecting);
                 }
             }
 
@@ -219,7 +568,11 @@ impl JobRunner {
 
             // Wait before reconnecting
             info!("Reconnecting in {:?}...", reconnect_delay);
-            tokio::time::sleep(reconnect_delay).await;
+            self.events.emit(RunnerEvent::Reconnecting { delay_ms: reconnect_delay.as_millis() as u64 });
+            tokio::select! {
+                _ = tokio::time::sleep(reconnect_delay) => {}
+                _ = &mut claims => {}
+            }
 
             // Exponential backoff
             reconnect_delay = std::cmp::min(
@@ -231,19 +584,128 @@ impl JobRunner {
             );
         }
 
-        // Wait for running jobs to complete
+        // Jobs that never started are handed back, then running ones finish
+        self.drain_pending_jobs().await;
         self.wait_for_jobs_completion().await;
 
+        if let Some(handle) = liveness_handle {
+            handle.abort();
+            self.write_liveness("stopped").await;
+        }
+
+        for handle in subscriber_handles {
+            handle.abort();
+        }
+        if let Some(handle) = admin_handle {
+            handle.abort();
+        }
+        if let Some(handle) = docker_gc_handle {
+            handle.abort();
+        }
+        stats_handle.abort();
+
         Ok(())
     }
 
+    /// Write the liveness file once with the given runner status
+    async fn write_liveness(&self, status: &str) {
+        let Some(ref path) = self.settings.runner.liveness_file else {
+            return;
+        };
+
+        let running_jobs = self.job_contexts.read().await.keys().cloned().collect();
+        let report = LivenessReport::new(&self.settings.runner.id, status, running_jobs);
+
+        if let Err(e) = LivenessWriter::new(path.clone()).write(&report).await {
+            warn!("Failed to write liveness file: {}", e);
+        }
+    }
+
+    fn spawn_liveness_task(&self) -> Option<tokio::task::JoinHandle<()>> {
+        let path = self.settings.runner.liveness_file.clone()?;
+        let runner_id = self.settings.runner.id.clone();
+        let job_contexts = self.job_contexts.clone();
+        let interval = Duration::from_secs(self.settings.runner.liveness_interval_secs.max(1));
+
+        info!("Writing liveness file to {:?} every {:?}", path, interval);
+
+        Some(tokio::spawn(async move {
+            let writer = LivenessWriter::new(path);
+            let mut ticker = tokio::time::interval(interval);
+
+            loop {
+                ticker.tick().await;
+
+                let running_jobs = job_contexts.read().await.keys().cloned().collect();
+                let report = LivenessReport::new(&runner_id, "running", running_jobs);
+
+                if let Err(e) = writer.write(&report).await {
+                    warn!("Failed to write liveness file {:?}: {}", writer.path(), e);
+                }
+            }
+        }))
+    }
+
+    /// Remove Docker resources of jobs that are no longer running, and the
+    /// least recently used cache volumes, at startup and then every
+    /// `gc_interval_secs`
+    fn spawn_docker_gc_task(&self) -> Option<tokio::task::JoinHandle<()>> {
+        if !self.settings.executor.enabled.iter().any(|name| name == "docker") {
+            return None;
+        }
+        let docker = match DockerExecutor::new(
+            self.settings.executor.docker.clone(),
+            self.settings.executor.shell.clone(),
+        ) {
+            Ok(docker) => docker
+                .with_runner_id(&self.settings.runner.id)
+                .with_cache_index(VolumeIndex::new(&self.settings.workspace.cache_path)),
+            Err(e) => {
+                warn!("Docker garbage collection disabled: {}", e);
+                return None;
+            }
+        };
+        let job_contexts = self.job_contexts.clone();
+        let interval = self.settings.executor.docker.gc_interval_secs;
+        let cache_max_bytes = self.settings.executor.docker.cache_volume_max_bytes;
+
+        Some(tokio::spawn(async move {
+            loop {
+                let contexts = &job_contexts;
+                let collected = docker
+                    .collect_garbage(|| async move { contexts.read().await.keys().cloned().collect() })
+                    .await;
+                match collected {
+                    Ok(report) if !report.is_empty() => info!(
+                        "Removed orphaned Docker resources: containers {:?}, networks {:?}, volumes {:?}",
+                        report.containers, report.networks, report.volumes
+                    ),
+                    Ok(_) => {}
+                    Err(e) => warn!("Docker garbage collection failed: {:#}", e),
+                }
+                if cache_max_bytes > 0 {
+                    match docker.evict_cache_volumes(cache_max_bytes).await {
+                        Ok(removed) if !removed.is_empty() => info!("Removed cache volumes {:?}", removed),
+                        Ok(_) => {}
+                        Err(e) => warn!("Cache volume eviction failed: {:#}", e),
+                    }
+                }
+
+                if interval == 0 {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_secs(interval)).await;
+            }
+        }))
+    }
+
     /// Wait for all running jobs to complete
     async fn wait_for_jobs_completion(&self) {
         let timeout_secs = self.settings.job.shutdown_timeout_secs;
         let start = Instant::now();
 
         loop {
-            let job_count = *self.current_jobs.lock().await;
+            let job_count = self.scheduler.lock().await.running();
             if job_count == 0 {
                 info!("All jobs completed");
                 break;
@@ -264,6 +726,19 @@ impl JobRunner {
         }
     }
 
+    /// Report queued jobs as cancelled so the control plane can reschedule them
+    async fn drain_pending_jobs(&self) {
+        let pending = self.scheduler.lock().await.drain_pending();
+        for job in pending {
+            info!("Dropping pending job {} on shutdown", job.job_id);
+            let reason = "Runner shut down before the job started";
+            let error = RunnerError::Cancelled(reason.to_string());
+            if let Err(e) = report_job_error(&self.settings, &job.job_id, &error, error.to_outputs(), 0).await {
+                warn!("Failed to report pending job {}: {}", job.job_id, e);
+            }
+        }
+    }
+
     /// Cancel all running jobs
     async fn cancel_all_jobs(&self) {
         let contexts = self.job_contexts.read().await;
@@ -279,10 +754,24 @@ impl JobRunner {
         // Wait for connection to be established
         ws.wait_connected(Duration::from_secs(30)).await?;
         info!("Connected to control plane");
+        self.events.emit(RunnerEvent::Connected);
+        self.connection.send_replace(ConnectionState::Connected);
 
         // Register connection state callback
         let log_manager = self.log_manager.clone();
+        let connection = self.connection.clone();
         ws.on_state_change(Arc::new(move |state| {
+            // A closed client reports disconnected; a new attempt after a
+            // drop still leaves the socket down
+            match state {
+                ConnectionState::Disconnected => {}
+                ConnectionState::Connecting => {
+                    connection.send_replace(ConnectionState::Reconnecting);
+                }
+                state => {
+                    connection.send_replace(state);
+                }
+            }
             if state == ConnectionState::Connected {
                 // Trigger resend of pending logs on reconnection
                 let log_mgr = log_manager.clone();
@@ -294,9 +783,19 @@ impl JobRunner {
             }
         })).await;
 
+        // Deliver logs of jobs interrupted by a runner restart
+        match self.log_manager.replay_persisted(&ws).await {
+            Ok(0) => {}
+            Ok(sent) => info!("Replayed {} persisted log entries", sent),
+            Err(e) => warn!("Failed to replay persisted logs: {}", e),
+        }
+
         // Start heartbeat task
         let heartbeat_handle = self.spawn_heartbeat_task(ws.clone());
 
+        // Retry uploads that failed after earlier jobs completed
+        let upload_retry_handle = self.spawn_upload_retry_task(ws.clone());
+
         // Create shutdown receiver for this connection
         let mut shutdown_rx = self.shutdown_tx.subscribe();
 
@@ -330,13 +829,18 @@ impl JobRunner {
         }
 
         heartbeat_handle.abort();
+        if let Some(handle) = upload_retry_handle {
+            handle.abort();
+        }
         ws.close().await?;
         Ok(())
     }
 
     fn spawn_heartbeat_task(&self, ws: Arc<WebSocketClient>) -> tokio::task::JoinHandle<()> {
         let settings = self.settings.clone();
-        let current_jobs = self.current_jobs.clone();
+        let scheduler = self.scheduler.clone();
+        let admission = self.admission.clone();
+        let overrides = self.overrides.clone();
 
         tokio::spawn(async move {
             let interval = Duration::from_secs(settings.runner.heartbeat_interval_secs);
@@ -345,8 +849,15 @@ impl JobRunner {
                 tokio::time::sleep(interval).await;
 
                 if ws.is_connected().await {
-                    let jobs = *current_jobs.lock().await;
-                    if let Err(e) = ws.send_heartbeat(&settings.runner.id, jobs).await {
+                    let (jobs, capacity, draining, labels) = {
+                        let admission = admission.read().await;
+                        let scheduler = scheduler.lock().await;
+                        let capacity = free_capacity(&scheduler, &admission);
+                        (scheduler.running(), capacity, admission.is_draining(), admission.labels().to_vec())
+                    };
+                    let revision = overrides.lock().await.revision;
+                    let sent = ws.send_heartbeat(&settings.runner.id, jobs, capacity, draining, &labels, revision).await;
+                    if let Err(e) = sent {
                         warn!("Failed to send heartbeat: {}", e);
                     }
                 }
@@ -354,6 +865,27 @@ impl JobRunner {
         })
     }
 
+    fn spawn_upload_retry_task(&self, ws: Arc<WebSocketClient>) -> Option<tokio::task::JoinHandle<()>> {
+        let queue = upload_queue(&self.settings)?;
+        let http = HttpClient::new(self.settings.clone());
+        let interval = Duration::from_secs(self.settings.job.upload_retry_interval_secs.max(1));
+        let chunk_bytes = artifact_chunk_bytes(&self.settings);
+
+        Some(tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+
+                if !ws.is_connected().await || queue.is_empty().await {
+                    continue;
+                }
+                let remaining = queue.retry_all(|upload| deliver_upload(&ws, &http, upload, chunk_bytes)).await;
+                if remaining > 0 {
+                    debug!("{} uploads still queued for retry", remaining);
+                }
+            }
+        }))
+    }
+
     async fn handle_message(
         &self,
         ws: Arc<WebSocketClient>,
@@ -362,6 +894,12 @@ impl JobRunner {
         match message {
             IncomingMessage::Connected { runner_id } => {
                 info!("Confirmed connection as runner: {}", runner_id);
+
+                // Jobs may have finished, or been reassigned, while disconnected
+                let mut running: Vec<String> = self.job_contexts.read().await.keys().cloned().collect();
+                running.sort();
+                let pending = self.scheduler.lock().await.pending().map(|job| job.job_id.clone()).collect();
+                ws.send(&OutgoingMessage::ActiveJobs { runner_id, running, pending }).await?;
             }
 
             IncomingMessage::HeartbeatAck { timestamp } => {
@@ -371,61 +909,184 @@ impl JobRunner {
             IncomingMessage::JobAssignment { job } => {
                 info!("Received job assignment: {} ({})", job.name, job.job_id);
 
-                // Check capacity
-                let jobs = *self.current_jobs.lock().await;
-                if jobs >= self.settings.runner.max_concurrent_jobs as u32 {
-                    warn!("At capacity, cannot accept job");
-                    // Notify control plane we're at capacity
-                    ws.send_status_update(
-                        "job",
-                        &job.job_id,
-                        "rejected",
-                        None,
-                        HashMap::from([("reason".to_string(), "runner_at_capacity".to_string())]),
-                    ).await?;
-                    return Ok(());
+                let job_id = job.job_id.clone();
+                match self.admit_job(job).await {
+                    Ok(None) => {}
+                    Ok(Some(position)) => {
+                        ws.send_status_update(
+                            "job",
+                            &job_id,
+                            "queued",
+                            None,
+                            HashMap::from([("queue_position".to_string(), position.to_string())]),
+                            StatusMeta::default(),
+                        ).await?;
+                    }
+                    Err(rejection) => self.reject_job(&ws, &job_id, rejection).await?,
                 }
+            }
 
-                // Increment job count
-                *self.current_jobs.lock().await += 1;
+            IncomingMessage::MalformedJobAssignment { job, error } => {
+                // Without an id there is nothing to report the rejection against
+                let Some(job_id) = job.get("job_id").and_then(|id| id.as_str()).filter(|id| !id.is_empty()) else {
+                    warn!("Dropping job assignment without a job_id: {}", error);
+                    return Ok(());
+                };
+                let errors = parse_errors(&job);
+                self.reject_job(&ws, job_id, Rejection::InvalidSpec { errors }).await?;
+            }
 
-                // Create job context
-                let job_ctx = Arc::new(JobContext::new(job.job_id.clone()));
-                self.job_contexts.write().await.insert(job.job_id.clone(), job_ctx.clone());
+            IncomingMessage::JobCancel { job_id } => {
+                warn!("Received cancel request for job: {}", job_id);
+                self.cancel_job(&ws, &job_id).await?;
+            }
 
-                // Spawn job execution task
-                let settings = self.settings.clone();
-                let current_jobs = self.current_jobs.clone();
-                let job_contexts = self.job_contexts.clone();
-                let log_manager = self.log_manager.clone();
-                let job_id = job.job_id.clone();
+            IncomingMessage::JobDiagnostics { job_id } => {
+                info!("Received diagnostics request for job: {}", job_id);
 
-                tokio::spawn(async move {
-                    let result = execute_job_with_retry(
-                        settings.clone(),
-                        job,
-                        job_ctx,
-                        log_manager,
-                    ).await;
-
-                    if let Err(e) = result {
-                        error!("Job execution failed: {}", e);
+                let target = match self.job_contexts.read().await.get(&job_id) {
+                    Some(ctx) if self.settings.diagnostics.enabled => {
+                        ctx.diagnostic_target.read().await.clone()
+                    }
+                    _ => None,
+                };
+
+                match target {
+                    Some(target) => {
+                        let settings = self.settings.clone();
+                        tokio::spawn(async move {
+                            let docker = match target.container {
+                                Some(_) => DockerExecutor::new(
+                                    settings.executor.docker.clone(),
+                                    settings.executor.shell.clone(),
+                                ).map_err(|e| warn!("Docker unavailable for diagnostics: {}", e)).ok(),
+                                None => None,
+                            };
+                            let results = run_diagnostics(&settings.diagnostics, &target, docker.as_ref()).await;
+
+                            let message = OutgoingMessage::JobDiagnostics { job_id, results, error: None };
+                            if let Err(e) = ws.send(&message).await {
+                                warn!("Failed to send diagnostics: {}", e);
+                            }
+                        });
+                    }
+                    None => {
+                        let error = if self.settings.diagnostics.enabled {
+                            "Job is not running on this runner"
+                        } else {
+                            "Diagnostics are disabled on this runner"
+                        };
+                        warn!("Cannot run diagnostics for job {}: {}", job_id, error);
+                        ws.send(&OutgoingMessage::JobDiagnostics {
+                            job_id,
+                            results: Vec::new(),
+                            error: Some(error.to_string()),
+                        }).await?;
                     }
+                }
+            }
 
-                    // Cleanup
-                    job_contexts.write().await.remove(&job_id);
-                    *current_jobs.lock().await -= 1;
-                });
+            IncomingMessage::QueryStatus { request_id } => {
+                debug!("Received status query");
+
+                let mut running_jobs = Vec::new();
+                for ctx in self.job_contexts.read().await.values() {
+                    running_jobs.push(ctx.snapshot().await);
+                }
+                running_jobs.sort_by(|a, b| a.started_at.cmp(&b.started_at).then_with(|| a.job_id.cmp(&b.job_id)));
+
+                let pending_jobs = self.scheduler.lock().await
+                    .pending()
+                    .enumerate()
+                    .map(|(index, job)| PendingJobSnapshot {
+                        job_id: job.job_id.clone(),
+                        name: job.name.clone(),
+                        queue_position: index + 1,
+                    })
+                    .collect();
+
+                ws.send_status_report(
+                    &self.settings.runner.id,
+                    request_id,
+                    self.admission.read().await.max_running(),
+                    running_jobs,
+                    pending_jobs,
+                ).await?;
             }
 
-            IncomingMessage::JobCancel { job_id } => {
-                warn!("Received cancel request for job: {}", job_id);
+            IncomingMessage::CapacityQuery { request_id } => {
+                debug!("Received capacity query");
+                let message = {
+                    let admission = self.admission.read().await;
+                    let scheduler = self.scheduler.lock().await;
+                    OutgoingMessage::CapacityReport {
+                        runner_id: self.settings.runner.id.clone(),
+                        request_id,
+                        fleet: FleetInfo::new(&self.settings),
+                        running_jobs: scheduler.running(),
+                        pending_jobs: scheduler.pending_len(),
+                        pending_capacity: admission.pending_capacity(scheduler.pending_len()),
+                        capacity: free_capacity(&scheduler, &admission),
+                        free_gpus: scheduler.free_gpus(),
+                        draining: admission.is_draining(),
+                        timestamp: Utc::now(),
+                    }
+                };
+                ws.send(&message).await?;
+            }
+
+            IncomingMessage::ConfigUpdate { reset, overrides } => {
+                info!("Received config update (reset: {})", reset);
+                let message = self.apply_overrides(reset, overrides).await;
+                ws.send(&message).await?;
+            }
 
-                if let Some(ctx) = self.job_contexts.read().await.get(&job_id) {
-                    ctx.cancel().await;
-                    info!("Job {} cancellation requested", job_id);
+            IncomingMessage::Drain { resume, exit } => {
+                self.admission.write().await.request_drain(!resume);
+                if resume {
+                    info!("Drain cancelled, accepting jobs again");
                 } else {
-                    warn!("Job {} not found for cancellation", job_id);
+                    info!("Draining: finishing current jobs without accepting new ones");
+                }
+
+                let exit_task = (!resume && exit).then(|| self.spawn_exit_when_drained());
+                if let Some(previous) = std::mem::replace(&mut *self.exit_when_drained.lock().await, exit_task) {
+                    previous.abort();
+                }
+            }
+
+            IncomingMessage::DebugExec { job_id, session_id, command, request_id } => {
+                info!("Received debug command for job: {}", job_id);
+                let request = DebugCommand::Exec { request_id: request_id.clone(), command };
+                if let Err(error) = self.send_debug_command(&job_id, &session_id, request).await {
+                    warn!("Cannot run debug command for job {}: {}", job_id, error);
+                    ws.send(&OutgoingMessage::DebugOutput {
+                        job_id,
+                        session_id,
+                        request_id,
+                        exit_code: None,
+                        output: String::new(),
+                        error: Some(error.to_string()),
+                    }).await?;
+                }
+            }
+
+            IncomingMessage::DebugEnd { job_id, session_id } => {
+                info!("Received end of debug session for job: {}", job_id);
+                if let Err(error) = self.send_debug_command(&job_id, &session_id, DebugCommand::End).await {
+                    warn!("Cannot end debug session of job {}: {}", job_id, error);
+                }
+            }
+
+            IncomingMessage::Reconcile { cancel, resend } => {
+                if !cancel.is_empty() {
+                    warn!("Control plane does not expect jobs {} on this runner, cancelling them", cancel.join(", "));
+                }
+                for job_id in cancel {
+                    self.cancel_job(&ws, &job_id).await?;
+                }
+                for job_id in resend {
+                    self.resend_job_status(&ws, &job_id).await?;
                 }
             }
 
@@ -447,427 +1108,2737 @@ impl JobRunner {
         Ok(())
     }
 
-    /// Get current job count
-    pub async fn current_job_count(&self) -> u32 {
-        *self.current_jobs.lock().await
+    /// Route `command` to the open debug session `session_id` of `job_id`
+    async fn send_debug_command(&self, job_id: &str, session_id: &str, command: DebugCommand) -> Result<(), &'static str> {
+        let session = match self.job_contexts.read().await.get(job_id) {
+            Some(ctx) => ctx.debug_session.read().await.clone(),
+            None => None,
+        };
+        match session {
+            Some(session) if session.session_id == session_id => {
+                session.commands.send(command).map_err(|_| "Debug session has ended")
+            }
+            _ => Err("No such debug session on this runner"),
+        }
     }
 
-    /// Check if runner is at capacity
-    pub async fn is_at_capacity(&self) -> bool {
-        *self.current_jobs.lock().await >= self.settings.runner.max_concurrent_jobs as u32
+    /// Drop `job_id` from the queue, or cancel it if it is running
+    async fn cancel_job(&self, ws: &WebSocketClient, job_id: &str) -> Result<()> {
+        let requested_at = Utc::now();
+        let mut scheduler = self.scheduler.lock().await;
+        if scheduler.remove_pending(job_id).is_some() {
+            drop(scheduler);
+            info!("Removed pending job {} from the queue", job_id);
+            ws.send_cancel_acknowledged(job_id, "dequeued").await?;
+            let cancellation = Cancellation {
+                requested_at: Some(requested_at),
+                reason: "Job cancelled before it started".to_string(),
+                steps: Vec::new(),
+            };
+            ws.send_status_update(
+                "job",
+                job_id,
+                &JobStatus::Cancelled.to_string(),
+                None,
+                HashMap::new(),
+                StatusMeta::default().with_cancellation(cancellation),
+            ).await?;
+        } else if let Some(ctx) = self.job_contexts.read().await.get(job_id) {
+            drop(scheduler);
+            ws.send_cancel_acknowledged(job_id, "stopping").await?;
+            ctx.cancel().await;
+            info!("Job {} cancellation requested", job_id);
+        } else {
+            drop(scheduler);
+            warn!("Job {} not found for cancellation", job_id);
+            ws.send_cancel_acknowledged(job_id, "unknown").await?;
+        }
+        Ok(())
     }
-}
-
-// ============================================================================
-// Job Execution with Retry
-// ============================================================================
 
-/// Execute a job with retry logic
-async fn execute_job_with_retry(
-    settings: Settings,
-    job: JobSpec,
-    ctx: Arc<JobContext>,
-    log_manager: Arc<LogStreamerManager>,
-) -> Result<()> {
-    let retry_config = RetryConfig::from(&settings.job);
-    let mut attempts = 0;
-    let mut last_error: Option<anyhow::Error> = None;
+    /// Report the status of `job_id` again, for a control plane that lost
+    /// track of it: running, queued, its outcome from the local history, or
+    /// failed if the runner does not know it
+    async fn resend_job_status(&self, ws: &WebSocketClient, job_id: &str) -> Result<()> {
+        if let Some(ctx) = self.job_contexts.read().await.get(job_id) {
+            let snapshot = ctx.snapshot().await;
+            let meta = match snapshot.started_at {
+                Some(started_at) => StatusMeta::started(snapshot.attempt, started_at),
+                None => StatusMeta::default(),
+            };
+            return ws.send_status_update("job", job_id, "running", None, HashMap::new(), meta).await;
+        }
 
-    while attempts < retry_config.max_attempts {
-        attempts += 1;
+        let position = self.scheduler.lock().await.pending().position(|job| job.job_id == job_id);
+        if let Some(index) = position {
+            let outputs = HashMap::from([("queue_position".to_string(), (index + 1).to_string())]);
+            return ws.send_status_update("job", job_id, "queued", None, outputs, StatusMeta::default()).await;
+        }
 
-        if ctx.is_cancelled().await {
-            info!("Job {} was cancelled before attempt {}", job.job_id, attempts);
-            return report_job_status(&settings, &job.job_id, JobStatus::Cancelled, None).await;
+        let max_records = self.settings.job.history_max_records;
+        if max_records > 0 {
+            let history = JobHistory::new(self.settings.workspace.history_path.clone(), max_records);
+            let query = HistoryQuery { job_id: Some(job_id.to_string()), status: None, limit: 1 };
+            match history.query(&query).await {
+                Ok(records) => {
+                    if let Some(record) = records.into_iter().next() {
+                        info!("Resending outcome {} of job {} from the local history", record.status, job_id);
+                        let meta = StatusMeta {
+                            attempt: record.attempt,
+                            started_at: Some(record.started_at),
+                            finished_at: record.finished_at,
+                            ..StatusMeta::default()
+                        };
+                        return ws.send_status_update("job", job_id, &record.status, None, HashMap::new(), meta).await;
+                    }
+                }
+                Err(e) => warn!("Failed to look up job {} in the local history: {}", job_id, e),
+            }
         }
 
-        info!(
-            "Executing job {} (attempt {}/{})",
-            job.job_id, attempts, retry_config.max_attempts
-        );
+        warn!("Control plane expects job {} on this runner, which does not know it", job_id);
+        let error = RunnerError::InfraError("Job is not known to the runner it was assigned to".to_string());
+        ws.send_status_update(
+            "job",
+            job_id,
+            &error.job_status().to_string(),
+            None,
+            error.to_outputs(),
+            StatusMeta::default(),
+        ).await
+    }
 
-        match execute_job(settings.clone(), job.clone(), ctx.clone(), log_manager.clone()).await {
-            Ok(_) => return Ok(()),
-            Err(e) => {
-                last_error = Some(e);
+    /// While the WebSocket is reconnecting or failed, claim jobs over HTTP
+    /// so they keep flowing when only the API is reachable. Never returns.
+    async fn claim_while_disconnected(&self) {
+        if !self.settings.control_plane.claim_fallback {
+            return std::future::pending().await;
+        }
+        let retry = Duration::from_secs(self.settings.control_plane.reconnect_delay_secs.max(1));
+        let mut connection = self.connection.subscribe();
 
-                if attempts < retry_config.max_attempts {
-                    let delay = Duration::from_secs(
-                        (retry_config.delay_secs as f64 *
-                         retry_config.backoff_multiplier.powi(attempts as i32 - 1)) as u64
-                    );
-                    warn!(
-                        "Job {} failed, retrying in {:?}...",
-                        job.job_id, delay
-                    );
-                    tokio::time::sleep(delay).await;
+        loop {
+            let _ = connection
+                .wait_for(|state| matches!(state, ConnectionState::Reconnecting | ConnectionState::Failed))
+                .await;
+
+            let (capacity, labels) = {
+                let admission = self.admission.read().await;
+                let running = self.scheduler.lock().await.running();
+                (admission.capacity(running), admission.labels().to_vec())
+            };
+            if capacity == 0 {
+                tokio::time::sleep(retry).await;
+                continue;
+            }
+
+            match self.client.claim_job(capacity, &labels).await {
+                Ok(Some(job)) => self.accept_claimed(job).await,
+                // Nothing came up while the claim waited
+                Ok(None) => {}
+                Err(e) => {
+                    debug!("Job claim failed: {:#}", e);
+                    tokio::time::sleep(retry).await;
                 }
             }
         }
     }
 
-    // All retries exhausted
-    error!(
-        "Job {} failed after {} attempts",
-        job.job_id, retry_config.max_attempts
-    );
+    /// Start or queue a job claimed over HTTP, handing it back if rejected
+    async fn accept_claimed(&self, job: serde_json::Value) {
+        let Some(job_id) = job.get("job_id").and_then(|id| id.as_str()).filter(|id| !id.is_empty()) else {
+            warn!("Dropping claimed job without a job_id");
+            return;
+        };
+        let job_id = job_id.to_string();
+
+        let admitted = match serde_json::from_value::<JobSpec>(job.clone()) {
+            Ok(spec) => {
+                info!("Claimed job over HTTP: {} ({})", spec.name, job_id);
+                self.admit_job(spec).await
+            }
+            Err(_) => Err(Rejection::InvalidSpec { errors: parse_errors(&job) }),
+        };
+        if let Err(rejection) = admitted {
+            self.record_rejection(&job_id, &rejection);
+            if let Err(e) = self.client.release_job(&job_id, &rejection.to_outputs()).await {
+                warn!("Failed to release claimed job {}: {:#}", job_id, e);
+            }
+        }
+    }
+
+    /// Start an assigned job, or queue it and return its queue position
+    async fn admit_job(&self, job: JobSpec) -> std::result::Result<Option<usize>, Rejection> {
+        validate_job(&job).map_err(|errors| Rejection::InvalidSpec { errors })?;
+
+        // Lock order (admission, then scheduler) matches slot release
+        let admission = self.admission.read().await;
+        let mut scheduler = self.scheduler.lock().await;
+
+        let admitted = admission.admit(&job, &scheduler);
+        let (superseded_pending, superseded_running) = match admitted {
+            Admission::Reject(_) => (Vec::new(), Vec::new()),
+            _ => scheduler.supersede(&job),
+        };
+        if !superseded_pending.is_empty() || !superseded_running.is_empty() {
+            self.supersede_jobs(&job.job_id, superseded_pending, superseded_running).await;
+        }
+
+        match admitted {
+            Admission::Start => {
+                scheduler.start(&job);
+                let launcher = self.launcher();
+                let job_ctx = launcher.register(&job).await;
+                drop((scheduler, admission));
+                self.events.emit(RunnerEvent::JobAccepted {
+                    job_id: job.job_id.clone(),
+                    name: job.name.clone(),
+                });
+                launcher.spawn(job, job_ctx);
+                Ok(None)
+            }
+            Admission::Queue => {
+                let (job_id, name) = (job.job_id.clone(), job.name.clone());
+                let position = scheduler.enqueue(job);
+                drop((scheduler, admission));
+
+                info!("At capacity, queueing job {} (position {})", job_id, position);
+                self.events.emit(RunnerEvent::JobAccepted { job_id, name });
+                Ok(Some(position))
+            }
+            Admission::Reject(rejection) => Err(rejection),
+        }
+    }
+
+    /// Drop the pending jobs and cancel the running ones `job_id` supersedes
+    /// in its concurrency group
+    async fn supersede_jobs(&self, job_id: &str, pending: Vec<JobSpec>, running: Vec<String>) {
+        for superseded_job in pending {
+            info!("Removed pending job {} from the queue, superseded by job {}", superseded_job.job_id, job_id);
+            let settings = self.settings.clone();
+            let (error, outputs) = superseded(job_id);
+            tokio::spawn(async move {
+                if let Err(e) = report_job_error(&settings, &superseded_job.job_id, &error, outputs, 0).await {
+                    warn!("Failed to report superseded job {}: {}", superseded_job.job_id, e);
+                }
+            });
+        }
+        for running_id in running {
+            if let Some(ctx) = self.job_contexts.read().await.get(&running_id) {
+                info!("Cancelling job {}, superseded by job {}", running_id, job_id);
+                ctx.supersede(job_id).await;
+            }
+        }
+    }
+
+    /// Log and announce that `job_id` is rejected
+    fn record_rejection(&self, job_id: &str, rejection: &Rejection) {
+        match rejection {
+            Rejection::InvalidSpec { errors } => {
+                let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                warn!("Rejecting job {}: invalid spec: {}", job_id, errors.join("; "));
+            }
+            rejection => warn!("Rejecting job {}: {:?}", job_id, rejection),
+        }
+        self.events.emit(RunnerEvent::JobRejected {
+            job_id: job_id.to_string(),
+            reason: rejection.reason().to_string(),
+        });
+    }
+
+    /// Report `job_id` as rejected to the control plane
+    async fn reject_job(&self, ws: &WebSocketClient, job_id: &str, rejection: Rejection) -> Result<()> {
+        self.record_rejection(job_id, &rejection);
+        ws.send_status_update(
+            "job",
+            job_id,
+            "rejected",
+            None,
+            rejection.to_outputs(),
+            StatusMeta::default(),
+        ).await
+    }
+
+    /// Apply a `config_update`, persisting the result.
+    ///
+    /// An update that fails validation changes nothing.
+    async fn apply_overrides(&self, reset: bool, update: ConfigOverrides) -> OutgoingMessage {
+        let mut current = self.overrides.lock().await;
+        let revision = update.revision;
+        let mut next = if reset { ConfigOverrides::default() } else { current.clone() };
+        next.merge(update);
+
+        if let Err(e) = next.validate() {
+            warn!("Rejecting config update: {:#}", e);
+            return OutgoingMessage::ConfigApplied {
+                runner_id: self.settings.runner.id.clone(),
+                revision,
+                applied: false,
+                error: Some(format!("{:#}", e)),
+                overrides: current.clone(),
+            };
+        }
+
+        if next.log_level != current.log_level {
+            if let Some(ref control) = self.log_level {
+                if let Err(e) = control(next.log_level.as_deref()) {
+                    warn!("Failed to change log level: {:#}", e);
+                }
+            }
+        }
+        {
+            let mut admission = self.admission.write().await;
+            let drain_requested = admission.drain_requested();
+            *admission = AdmissionPolicy::from(&self.settings).with_overrides(&next);
+            admission.request_drain(drain_requested);
+        }
+        if let Err(e) = next.save(&self.settings.runner.overrides_path).await {
+            warn!("Failed to save config overrides: {:#}", e);
+        }
+        info!("Applied config overrides: {:?}", next);
+        *current = next.clone();
+        drop(current);
+
+        // A raised limit can start queued jobs right away
+        self.start_pending().await;
+
+        OutgoingMessage::ConfigApplied {
+            runner_id: self.settings.runner.id.clone(),
+            revision: next.revision,
+            applied: true,
+            error: None,
+            overrides: next,
+        }
+    }
+
+    /// Shut down once no job is running or queued
+    fn spawn_exit_when_drained(&self) -> tokio::task::JoinHandle<()> {
+        let scheduler = self.scheduler.clone();
+        let shutdown_tx = self.shutdown_tx.clone();
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(Duration::from_secs(1));
+            loop {
+                ticker.tick().await;
+                if scheduler.lock().await.is_idle() {
+                    info!("Drain complete, shutting down");
+                    let _ = shutdown_tx.send(());
+                    return;
+                }
+            }
+        })
+    }
+
+    /// Start pending jobs while slots are free
+    async fn start_pending(&self) {
+        let max_running = self.admission.read().await.max_running();
+        let launcher = self.launcher();
+        let jobs = launcher.start_ready(&mut *self.scheduler.lock().await, max_running).await;
+
+        for (job, job_ctx) in jobs {
+            info!("Starting pending job {}", job.job_id);
+            launcher.spawn(job, job_ctx);
+        }
+    }
+
+    /// A launcher sharing the runner's job state
+    fn launcher(&self) -> JobLauncher {
+        JobLauncher {
+            settings: self.settings.clone(),
+            admission: self.admission.clone(),
+            job_contexts: self.job_contexts.clone(),
+            scheduler: self.scheduler.clone(),
+            log_manager: self.log_manager.clone(),
+            events: self.events.clone(),
+            resources: self.resources.clone(),
+        }
+    }
+
+    /// Get current job count
+    pub async fn current_job_count(&self) -> u32 {
+        self.scheduler.lock().await.running()
+    }
+
+    /// Check if runner is at capacity
+    pub async fn is_at_capacity(&self) -> bool {
+        let max_running = self.admission.read().await.max_running();
+        self.scheduler.lock().await.running() >= max_running
+    }
+}
+
+/// Starts accepted jobs, handing each finished job's slots to the pending
+/// jobs that fit
+#[derive(Clone)]
+struct JobLauncher {
+    settings: Settings,
+    admission: Arc<RwLock<AdmissionPolicy>>,
+    job_contexts: Arc<RwLock<HashMap<String, Arc<JobContext>>>>,
+    scheduler: Arc<Mutex<Scheduler>>,
+    log_manager: Arc<LogStreamerManager>,
+    events: EventBus,
+    resources: Arc<ResourceLocks>,
+}
+
+impl JobLauncher {
+    /// Run `job` in the background in slots already taken in the scheduler.
+    ///
+    /// When it finishes, its task runs pending jobs until none fits.
+    fn spawn(&self, job: JobSpec, job_ctx: Arc<JobContext>) {
+        tokio::spawn(self.clone().run(job, job_ctx));
+    }
+
+    async fn run(self, job: JobSpec, job_ctx: Arc<JobContext>) {
+        let mut next = Some((job, job_ctx));
+
+        while let Some((job, job_ctx)) = next {
+            let job_id = job.job_id.clone();
+            let result = execute_job_with_retry(
+                self.settings.clone(),
+                job,
+                job_ctx,
+                self.log_manager.clone(),
+                self.events.clone(),
+                self.resources.clone(),
+            ).await;
+
+            if let Err(e) = result {
+                error!("Job execution failed: {}", e);
+            }
+
+            // Cleanup
+            self.job_contexts.write().await.remove(&job_id);
+            next = None;
+            for (job, job_ctx) in self.finish(&job_id).await {
+                info!("Starting pending job {}", job.job_id);
+                match next {
+                    None => next = Some((job, job_ctx)),
+                    Some(_) => self.spawn(job, job_ctx),
+                }
+            }
+        }
+    }
+
+    /// Register the context of a job just started in the scheduler.
+    ///
+    /// Callers still hold the scheduler lock, so a cancel request finds the
+    /// job either pending or running.
+    async fn register(&self, job: &JobSpec) -> Arc<JobContext> {
+        let job_ctx = Arc::new(JobContext::new(job.job_id.clone()));
+        self.job_contexts.write().await.insert(job.job_id.clone(), job_ctx.clone());
+        job_ctx
+    }
+
+    /// Take the pending jobs that fit from `scheduler` and register them
+    async fn start_ready(&self, scheduler: &mut Scheduler, max_running: u32) -> Vec<(JobSpec, Arc<JobContext>)> {
+        let mut started = Vec::new();
+        for job in scheduler.start_ready(max_running) {
+            let job_ctx = self.register(&job).await;
+            started.push((job, job_ctx));
+        }
+        started
+    }
+
+    /// Release a finished job's slots and take the pending jobs that now
+    /// fit; none if the job limit was lowered below the running count
+    async fn finish(&self, job_id: &str) -> Vec<(JobSpec, Arc<JobContext>)> {
+        let max_running = self.admission.read().await.max_running();
+        let mut scheduler = self.scheduler.lock().await;
+        scheduler.finish(job_id);
+        self.start_ready(&mut scheduler, max_running).await
+    }
+}
+
+// ============================================================================
+// Job Execution with Retry
+// ============================================================================
+
+/// Execute a job with retry logic
+async fn execute_job_with_retry(
+    settings: Settings,
+    job: JobSpec,
+    ctx: Arc<JobContext>,
+    log_manager: Arc<LogStreamerManager>,
+    events: EventBus,
+    resources: Arc<ResourceLocks>,
+) -> Result<()> {
+    let retry_config = RetryConfig::for_job(&settings.job, &job);
+    let mut attempts = 0;
+    let mut last_error: Option<anyhow::Error> = None;
+
+    while attempts < retry_config.max_attempts {
+        attempts += 1;
+
+        if ctx.is_cancelled().await {
+            info!("Job {} was cancelled before attempt {}", job.job_id, attempts);
+            let (error, outputs) = ctx.cancellation().await;
+            return report_job_error(&settings, &job.job_id, &error, outputs, attempts).await;
+        }
+
+        info!(
+            "Executing job {} (attempt {}/{})",
+            job.job_id, attempts, retry_config.max_attempts
+        );
+
+        let attempt = execute_job(settings.clone(), job.clone(), ctx.clone(), log_manager.clone(), &events, &resources, attempts);
+        match attempt.instrument(job_span(&job, attempts)).await {
+            Ok(_) => return Ok(()),
+            Err(e) => {
+                let error = RunnerError::classify(&e);
+                last_error = Some(e);
+                if !retry_config.retries(&error) {
+                    info!("Job {} failed with {}, not retrying", job.job_id, error.kind());
+                    break;
+                }
+
+                if attempts < retry_config.max_attempts {
+                    let delay = Duration::from_secs(
+                        (retry_config.delay_secs as f64 *
+                         retry_config.backoff_multiplier.powi(attempts as i32 - 1)) as u64
+                    );
+                    warn!(
+                        "Job {} failed, retrying in {:?}...",
+                        job.job_id, delay
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+            }
+        }
+    }
+
+    // All retries exhausted, or the failure is not worth retrying
+    error!("Job {} failed after {} attempts", job.job_id, attempts);
+    log_manager.remove(&job.job_id).await;
 
     if let Some(e) = last_error {
-        report_job_status(
-            &settings,
-            &job.job_id,
-            JobStatus::Failed,
-            Some(&format!("Failed after {} attempts: {}", attempts, e)),
-        ).await?;
+        let error = RunnerError::classify(&e);
+        let mut outputs = error.to_outputs();
+        outputs.insert("error".to_string(), format!("Failed after {} attempts: {}", attempts, error));
+        report_job_error(&settings, &job.job_id, &error, outputs, attempts).await?;
     }
 
     Ok(())
 }
 
-/// Report job status to control plane
-async fn report_job_status(
+/// Report a job's final error to the control plane
+async fn report_job_error(
     settings: &Settings,
     job_id: &str,
-    status: JobStatus,
-    error_message: Option<&str>,
+    error: &RunnerError,
+    outputs: HashMap<String, String>,
+    attempt: u32,
 ) -> Result<()> {
     let client = ControlPlaneClient::new(settings.clone());
     let ws = client.connect_websocket().await?;
 
-    let mut outputs = HashMap::new();
-    if let Some(msg) = error_message {
-        outputs.insert("error".to_string(), msg.to_string());
-    }
-
     ws.send_status_update(
         "job",
         job_id,
-        &status.to_string(),
+        &error.job_status().to_string(),
         None,
         outputs,
+        StatusMeta::finished(attempt, None),
     ).await?;
 
     Ok(())
 }
 
+/// Free job slots, overall and per limited label
+fn free_capacity(scheduler: &Scheduler, admission: &AdmissionPolicy) -> Capacity {
+    let capacity = admission.capacity(scheduler.running());
+    Capacity { capacity, label_slots: scheduler.free_slots(capacity) }
+}
+
+/// Error and status outputs of a job superseded by `job_id`
+fn superseded(job_id: &str) -> (RunnerError, HashMap<String, String>) {
+    let error = RunnerError::Cancelled(format!("Superseded by job {}", job_id));
+    let mut outputs = error.to_outputs();
+    outputs.insert("cancel_reason".to_string(), "superseded".to_string());
+    outputs.insert("superseded_by".to_string(), job_id.to_string());
+    (error, outputs)
+}
+
+/// Per-attempt state shared by the steps of a job
+struct JobRun<'a> {
+    ws: Arc<WebSocketClient>,
+    executor: &'a dyn Executor,
+    job: &'a JobSpec,
+    settings: &'a Settings,
+    workspace_path: &'a Path,
+    temp_dir: &'a Path,
+    /// GPUs leased to the job
+    gpus: &'a [u32],
+    log_streamer: Arc<LogStreamer>,
+    events: &'a EventBus,
+    resources: &'a ResourceLocks,
+    timeline: &'a Timeline,
+    uploads: Option<&'a UploadQueue>,
+    /// The job's `MUELSYSE_ENV` file
+    env_file: Option<&'a OutputFile>,
+    /// Registered by `uses:` steps, run once all steps succeed
+    post_actions: std::sync::Mutex<Vec<PostAction>>,
+    /// Entry for the local job history
+    history: std::sync::Mutex<HistoryRecord>,
+    /// `on_cancel` scripts of the steps executing now, by step id
+    cancel_handlers: std::sync::Mutex<HashMap<String, ExecutionContext>>,
+    /// Stops the executing steps once their cancel handlers are done
+    cancel: CancellationToken,
+    attempt: u32,
+    /// Resources used by the job's steps so far
+    usage: std::sync::Mutex<Option<ResourceUsage>>,
+    /// Context of the first failed step, kept for a debug session
+    debug_step: std::sync::Mutex<Option<ExecutionContext>>,
+    /// Exit codes of the finished steps, by step id
+    exit_codes: std::sync::Mutex<HashMap<String, i32>>,
+    /// Start times of the steps executing now, by step id
+    running_steps: std::sync::Mutex<HashMap<String, DateTime<Utc>>>,
+}
+
+impl JobRun<'_> {
+    fn start_step(&self, step: &StepSpec, started_at: DateTime<Utc>) {
+        self.running_steps.lock().unwrap_or_else(|e| e.into_inner()).insert(step.step_id.clone(), started_at);
+        self.events.emit(RunnerEvent::StepStarted {
+            job_id: self.job.job_id.clone(),
+            step_id: step.step_id.clone(),
+        });
+    }
+
+    fn record_step(&self, step: &StepSpec, status: StepStatus, started: Instant) {
+        let exit_code = self.exit_codes.lock().unwrap_or_else(|e| e.into_inner()).get(&step.step_id).copied();
+        self.history.lock().unwrap_or_else(|e| e.into_inner()).steps.push(StepRecord {
+            step_id: step.step_id.clone(),
+            name: step.name.clone(),
+            status: status.to_string(),
+            duration_ms: started.elapsed().as_millis() as u64,
+            exit_code,
+        });
+    }
+
+    fn record_usage(&self, usage: &ResourceUsage) {
+        self.usage.lock().unwrap_or_else(|e| e.into_inner()).get_or_insert_with(ResourceUsage::default).merge(usage);
+    }
+
+    fn record_artifact(&self, artifact: &PackagedArtifact, disposition: &str) {
+        self.history.lock().unwrap_or_else(|e| e.into_inner()).artifacts.push(ArtifactRecord {
+            name: artifact.name.clone(),
+            size_bytes: artifact.size_bytes,
+            checksum: artifact.checksum.clone(),
+            disposition: disposition.to_string(),
+        });
+    }
+
+    fn set_cancel_handler(&self, step_id: &str, handler: Option<ExecutionContext>) {
+        let mut handlers = self.cancel_handlers.lock().unwrap_or_else(|e| e.into_inner());
+        match handler {
+            Some(handler) => handlers.insert(step_id.to_string(), handler),
+            None => handlers.remove(step_id),
+        };
+    }
+}
+
+/// Name of the executor for a job's steps; untrusted jobs only run in
+/// Docker, where the runner controls networking and limits
+pub(super) fn job_executor<'a>(job: &'a JobSpec, settings: &Settings) -> Result<&'a str> {
+    if job.untrusted {
+        if job.container.is_none() || job.executor.as_deref().is_some_and(|e| e != "docker") {
+            anyhow::bail!(RunnerError::ConfigError("Untrusted jobs must run in a container".into()));
+        }
+        Ok("docker")
+    } else if let Some(ref name) = job.executor {
+        if !settings.executor.enabled.contains(name) {
+            anyhow::bail!(RunnerError::ConfigError(format!("Executor '{}' is not enabled on this runner", name)));
+        }
+        Ok(name)
+    } else if job.container.is_some() {
+        if settings.executor.enabled.iter().any(|e| e == "kubernetes") {
+            Ok("kubernetes")
+        } else {
+            Ok("docker")
+        }
+    } else {
+        Ok("shell")
+    }
+}
+
 /// Execute a job
 async fn execute_job(
     settings: Settings,
     job: JobSpec,
     ctx: Arc<JobContext>,
     log_manager: Arc<LogStreamerManager>,
+    events: &EventBus,
+    resources: &ResourceLocks,
+    attempt: u32,
 ) -> Result<()> {
     info!("Executing job: {} ({})", job.name, job.job_id);
+    let started_at = Utc::now();
+    let job_start = Instant::now();
+    let timeline = Timeline::new();
+    ctx.start_attempt(&job, attempt, started_at).await;
+
+    // Connect to control plane for status updates
+    let client = ControlPlaneClient::new(settings.clone());
+    let ws = Arc::new(client.connect_websocket().await?);
+    ws.wait_connected(Duration::from_secs(10)).await?;
+
+    // Get log streamer for this job
+    let log_streamer = log_manager.get_or_create(&job.job_id).await;
+    log_streamer.set_ws_client(ws.clone()).await;
+
+    // Update job status to running
+    ws.send_status_update(
+        "job",
+        &job.job_id,
+        "running",
+        None,
+        HashMap::new(),
+        StatusMeta::started(attempt, started_at),
+    ).await?;
+    events.emit(RunnerEvent::JobStarted { job_id: job.job_id.clone(), attempt });
+
+    // Wait for job-wide local resources
+    let mut cancel_rx = ctx.subscribe();
+    let phase_start = Instant::now();
+    let job_resources = tokio::select! {
+        guard = resources.acquire(&job.resources) => guard?,
+        _ = cancel_rx.recv() => anyhow::bail!(RunnerError::Cancelled("Job cancelled while waiting for resources".into())),
+    };
+    if !job.resources.is_empty() {
+        info!("Job {} acquired resources {:?} after {:?}", job.job_id, job.resources, job_resources.waited);
+        timeline.record("acquire resources", "job", phase_start);
+    }
+    let gpu_lease = lease_gpus(&job.job_id, job.gpus)?;
+
+    // Prepare workspace
+    let phase_start = Instant::now();
+    let workspace_manager = WorkspaceManager::new(settings.workspace.clone());
+    let workspace = workspace_manager.create(&job.job_id, &job.labels).await?;
+    timeline.record("create workspace", "job", phase_start);
+
+    let executor = create_executor(job_executor(&job, &settings)?, &settings)?;
+
+    // Calculate job timeout, bounded by the runner's policy
+    let mut job_timeout = job_timeout(job.timeout_minutes, &settings.job);
+    if job.untrusted {
+        let cap = Duration::from_secs(settings.untrusted.max_job_duration_minutes as u64 * 60);
+        job_timeout = job_timeout.min(cap);
+    }
+    if job_timeout.as_secs() < job.timeout_minutes as u64 * 60 {
+        warn!(
+            "Job {} requested {} minutes, capped to {} by runner policy",
+            job.job_id, job.timeout_minutes, settings.job.max_job_duration_minutes
+        );
+    }
+
+    let uploads = upload_queue(&settings);
+    let env_file = OutputFile::create_env(&job.job_id, executor.executor_type()).await?;
+    let run = JobRun {
+        ws: ws.clone(),
+        executor: executor.as_ref(),
+        job: &job,
+        settings: &settings,
+        workspace_path: &workspace.path,
+        temp_dir: &workspace.temp_dir,
+        gpus: &gpu_lease.devices,
+        log_streamer: log_streamer.clone(),
+        events,
+        resources,
+        timeline: &timeline,
+        uploads: uploads.as_ref(),
+        env_file: env_file.as_ref(),
+        post_actions: std::sync::Mutex::new(Vec::new()),
+        history: std::sync::Mutex::new(HistoryRecord::new(&job, attempt, started_at)),
+        cancel_handlers: std::sync::Mutex::new(HashMap::new()),
+        cancel: CancellationToken::new(),
+        attempt,
+        usage: std::sync::Mutex::new(None),
+        debug_step: std::sync::Mutex::new(None),
+        exit_codes: std::sync::Mutex::new(HashMap::new()),
+        running_steps: std::sync::Mutex::new(HashMap::new()),
+    };
+
+    // Streamed artifacts upload while the steps run
+    let streaming = start_artifact_streams(&settings, &job, &workspace.path);
+
+    // Execute steps with job-level timeout
+    let timeout_warning = TimeoutWarning::job(&run, &ctx, job_timeout);
+    let mut steps = Box::pin(execute_steps_with_timeout(&run, ctx.clone(), job_timeout));
+    let execution_result = tokio::select! {
+        result = &mut steps => Some(result),
+        _ = cancel_rx.recv() => {
+            warn!("Job {} cancelled during execution", job.job_id);
+            // Steps are only terminated once their cancel handlers are done
+            run_cancel_handlers(&run).await;
+            run.cancel.cancel();
+            None
+        }
+        // Backstop in case a step overruns its capped budget
+        _ = tokio::time::sleep(job_timeout + JOB_TIMEOUT_GRACE) => {
+            warn!("Job {} exceeded its {:?} budget", job.job_id, job_timeout);
+            Some(Err(RunnerError::Timeout(format!("Job timeout after {:?}", job_timeout)).into()))
+        }
+    };
+    let mut cancelled_steps = Vec::new();
+    let execution_result = match execution_result {
+        Some(result) => result,
+        None => {
+            cancelled_steps = run.running_steps.lock().unwrap_or_else(|e| e.into_inner()).keys().cloned().collect();
+            cancelled_steps.sort();
+            // Let the executors stop the steps' processes and containers
+            if timeout(CANCEL_STOP_TIMEOUT, &mut steps).await.is_err() {
+                warn!("Steps of job {} did not stop within {:?}", job.job_id, CANCEL_STOP_TIMEOUT);
+            }
+            report_cancelled_steps(&run).await;
+            Err(RunnerError::Cancelled("Job cancelled".into()).into())
+        }
+    };
+    drop(steps);
+    drop(timeout_warning);
+
+    // Determine final status
+    let (failure, mut job_outputs) = match execution_result {
+        Ok(outputs) => (None, outputs),
+        Err(_) if ctx.is_cancelled().await => {
+            let (error, outputs) = ctx.cancellation().await;
+            (Some(error), outputs)
+        }
+        Err(e) => {
+            let error = RunnerError::classify(&e);
+            let outputs = error.to_outputs();
+            (Some(error), outputs)
+        }
+    };
+    let job_status = failure.as_ref().map_or(JobStatus::Success, RunnerError::job_status);
+    record_status(&Span::current(), &job_status.to_string(), failure.is_some());
+
+    if !job.resources.is_empty() {
+        job_outputs.insert("resource_wait_ms".to_string(), job_resources.waited.as_millis().to_string());
+    }
+    if let Some(usage) = *run.usage.lock().unwrap_or_else(|e| e.into_inner()) {
+        job_outputs.extend(usage.to_outputs());
+    }
+    drop(job_resources);
+
+    // Keep the failed job around for debugging before anything is removed
+    let debug_step = run.debug_step.lock().unwrap_or_else(|e| e.into_inner()).take();
+    if let Some(step) = debug_step {
+        if job_status == JobStatus::Cancelled {
+            debug!("Not keeping cancelled job {} for debugging", job.job_id);
+        } else if settings.job.debug_session_secs == 0 {
+            info!("Not keeping job {} for debugging: debug sessions are disabled on this runner", job.job_id);
+        } else if job.untrusted {
+            info!("Not keeping untrusted job {} for debugging", job.job_id);
+        } else {
+            run_debug_session(&run, &ctx, &step).await;
+        }
+        // The step put off its cleanup for the session
+        if let Err(e) = executor.cleanup(&step).await {
+            warn!("Failed to clean up step {} of job {}: {}", step.step_id, job.job_id, e);
+        }
+    }
+
+    if let Err(e) = executor.finish_job(&job.job_id).await {
+        warn!("Failed to release executor resources for job {}: {}", job.job_id, e);
+    }
+    remove_job_dirs(&job.job_id).await;
+    if let Some(ref file) = env_file {
+        file.remove().await;
+    }
+
+    // Collect and upload declared artifacts before the workspace goes away
+    let artifacts = ArtifactManager::new(settings.workspace.artifact_path.clone())
+        .with_metadata(settings.job.artifact_metadata)
+        .with_compression(settings.job.artifact_compression);
+    let succeeded = job_status == JobStatus::Success;
+    let streamed = finish_artifact_streams(&run, streaming, client.http(), succeeded).await;
+    upload_artifacts(&run, &artifacts, client.http(), succeeded, &streamed).await;
+    if settings.job.export_timeline {
+        timeline.record(&job.name, "job", job_start);
+        export_timeline(&run, &artifacts, client.http()).await;
+    }
+    artifacts.remove_staging(&job.job_id).await;
+
+    // Flush remaining logs
+    if let Err(e) = log_streamer.flush().await {
+        warn!("Failed to flush final logs: {}", e);
+        let logs = log_streamer.take_buffered().await;
+        if let (Some(queue), false) = (run.uploads, logs.is_empty()) {
+            let pending = PendingUpload::Logs { job_id: job.job_id.clone(), logs };
+            if let Err(e) = queue.enqueue(pending).await {
+                warn!("Failed to queue logs of job {} for retry: {}", job.job_id, e);
+            }
+        }
+    }
+
+    // Cleanup or retain workspace according to the job's policy
+    if job.cleanup.should_remove(job_status == JobStatus::Success) {
+        workspace_manager.remove(&workspace).await;
+    } else {
+        match workspace_manager.retain(&workspace, &job.job_id).await {
+            Ok(path) => {
+                job_outputs.insert("retained_workspace".to_string(), path.display().to_string());
+            }
+            Err(e) => {
+                warn!("Failed to retain workspace, removing it: {}", e);
+                workspace_manager.remove(&workspace).await;
+            }
+        }
+    }
+
+    // Update job status; a cancelled job says when and what it stopped
+    let mut meta = StatusMeta::finished(attempt, Some(started_at));
+    if job_status == JobStatus::Cancelled {
+        meta = meta.with_cancellation(Cancellation {
+            requested_at: ctx.cancelled_at().await,
+            reason: failure.as_ref().map(RunnerError::to_string).unwrap_or_default(),
+            steps: cancelled_steps,
+        });
+    }
+    ws.send_status_update(
+        "job",
+        &job.job_id,
+        &job_status.to_string(),
+        None,
+        job_outputs.clone(),
+        meta,
+    ).await?;
+
+    info!("Job {} completed with status: {}", job.job_id, job_status);
+    events.emit(RunnerEvent::JobFinished {
+        job_id: job.job_id.clone(),
+        status: job_status.to_string(),
+        attempt,
+        duration_ms: elapsed_ms(started_at),
+    });
+
+    // Cleanup log streamer
+    log_manager.finish(&job.job_id, &job.name).await;
+
+    if settings.job.history_max_records > 0 {
+        let mut record = run.history.into_inner().unwrap_or_else(|e| e.into_inner());
+        record.finish(&job_status.to_string());
+        let history = JobHistory::new(settings.workspace.history_path.clone(), settings.job.history_max_records);
+        if let Err(e) = history.append(&record).await {
+            warn!("Failed to record job {} in the local history: {}", job.job_id, e);
+        }
+    }
+
+    match failure {
+        Some(error) => Err(error.into()),
+        None => Ok(()),
+    }
+}
+
+/// Uploads a job's streamed artifacts in the background while its steps run
+struct ArtifactStreaming {
+    stop: oneshot::Sender<()>,
+    handle: tokio::task::JoinHandle<Vec<ArtifactStream>>,
+}
+
+/// Start streaming the job's `stream: true` artifacts, if it has any.
+///
+/// Untrusted jobs' artifacts are quarantined after the job instead.
+fn start_artifact_streams(settings: &Settings, job: &JobSpec, workspace: &Path) -> Option<ArtifactStreaming> {
+    if job.untrusted {
+        return None;
+    }
+    let streams: Vec<ArtifactStream> = job
+        .artifacts
+        .iter()
+        .filter(|spec| spec.stream)
+        .filter_map(|spec| match ArtifactStream::new(workspace, spec) {
+            Ok(stream) => Some(stream),
+            Err(e) => {
+                warn!("Not streaming artifact '{}' of job {}: {}", spec.name, job.job_id, e);
+                None
+            }
+        })
+        .collect();
+    if streams.is_empty() {
+        return None;
+    }
+
+    let http = HttpClient::new(settings.clone());
+    let interval = Duration::from_secs(settings.job.artifact_stream_interval_secs.max(1));
+    let (stop, mut stop_rx) = oneshot::channel();
+    let handle = tokio::spawn(async move {
+        let mut streams = streams;
+        loop {
+            tokio::select! {
+                _ = &mut stop_rx => break,
+                _ = tokio::time::sleep(interval) => {}
+            }
+
+            let mut active = Vec::with_capacity(streams.len());
+            for mut stream in streams {
+                match stream.poll(&http).await {
+                    Ok(0) => active.push(stream),
+                    Ok(bytes) => {
+                        debug!("Streamed {} bytes of artifact '{}'", bytes, stream.name());
+                        active.push(stream);
+                    }
+                    // Uploaded as a regular artifact after the job instead
+                    Err(e) => warn!("{:#}; falling back to upload after the job", e),
+                }
+            }
+            streams = active;
+        }
+        streams
+    });
+
+    Some(ArtifactStreaming { stop, handle })
+}
+
+/// Stop streaming and finalize the streamed artifacts for the job's outcome.
+///
+/// Returns the names of the artifacts published; the others are packaged
+/// and uploaded as usual.
+async fn finish_artifact_streams(
+    run: &JobRun<'_>,
+    streaming: Option<ArtifactStreaming>,
+    http: &HttpClient,
+    succeeded: bool,
+) -> HashSet<String> {
+    let mut published = HashSet::new();
+    let Some(streaming) = streaming else {
+        return published;
+    };
+    let _ = streaming.stop.send(());
+    let streams = match streaming.handle.await {
+        Ok(streams) => streams,
+        Err(e) => {
+            warn!("Artifact streaming task of job {} failed: {}", run.job.job_id, e);
+            return published;
+        }
+    };
+
+    let job = run.job;
+    for stream in streams {
+        let wanted = job.artifacts.iter().any(|a| a.name == stream.name() && a.when.matches(succeeded));
+        if !wanted {
+            continue;
+        }
+
+        let start = Instant::now();
+        let name = stream.name().to_string();
+        let streamed = match stream.finish(http).await {
+            Ok(streamed) => streamed,
+            Err(e) => {
+                warn!("{:#}; falling back to upload after the job", e);
+                continue;
+            }
+        };
+        let mut artifact = streamed.artifact;
+        if run.settings.job.artifact_metadata {
+            artifact.metadata = inspect_file(&artifact.path).await;
+        }
+        info!("Streamed artifact '{}' ({} bytes) for job {}", artifact.name, artifact.size_bytes, job.job_id);
+        run.record_artifact(&artifact, "uploaded");
+
+        let ready = OutgoingMessage::ArtifactReady {
+            job_id: job.job_id.clone(),
+            artifact_name: artifact.name.clone(),
+            artifact_path: streamed.storage_path,
+            size_bytes: artifact.size_bytes,
+            checksum: artifact.checksum.clone(),
+            upload_id: streamed.upload_id,
+            metadata: artifact.metadata.clone(),
+        };
+        if let Err(e) = run.ws.send(&ready).await {
+            warn!("Failed to report artifact '{}': {}", artifact.name, e);
+        }
+        run.events.emit(RunnerEvent::ArtifactUploaded {
+            job_id: job.job_id.clone(),
+            name: artifact.name,
+            size_bytes: artifact.size_bytes,
+        });
+        run.timeline.record(format!("upload {}", name), "artifacts", start);
+        published.insert(name);
+    }
+    published
+}
+
+/// Package and upload the job's artifacts for its outcome, except those
+/// already streamed.
+///
+/// Failures are logged and do not change the job status.
+async fn upload_artifacts(
+    run: &JobRun<'_>,
+    manager: &ArtifactManager,
+    http: &HttpClient,
+    succeeded: bool,
+    streamed: &HashSet<String>,
+) {
+    let job = run.job;
+    let specs = job.artifacts.iter().filter(|a| a.when.matches(succeeded) && !streamed.contains(&a.name));
+    for spec in specs {
+        let start = Instant::now();
+        let artifact = match manager.package(&job.job_id, run.workspace_path, spec).await {
+            Ok(Some(artifact)) => artifact,
+            Ok(None) => {
+                warn!("No files matched artifact '{}' of job {}", spec.name, job.job_id);
+                continue;
+            }
+            Err(e) => {
+                warn!("Failed to package artifact '{}' of job {}: {}", spec.name, job.job_id, e);
+                continue;
+            }
+        };
+
+        publish_artifact(run, manager, http, artifact).await;
+        run.timeline.record(format!("upload {}", spec.name), "artifacts", start);
+    }
+}
+
+/// Upload a packaged artifact and report it to the control plane.
+///
+/// Returns whether it was uploaded or queued for a retry.
+async fn publish_artifact(run: &JobRun<'_>, manager: &ArtifactManager, http: &HttpClient, artifact: PackagedArtifact) -> bool {
+    let job = run.job;
+    if job.untrusted {
+        let dir = run.settings.untrusted.quarantine_path.join(&job.job_id);
+        match manager.quarantine(&artifact, &dir).await {
+            Ok(path) => {
+                info!("Quarantined artifact '{}' of untrusted job {} at {:?}", artifact.name, job.job_id, path);
+                run.record_artifact(&artifact, "quarantined");
+            }
+            Err(e) => warn!("Failed to quarantine artifact '{}' of job {}: {}", artifact.name, job.job_id, e),
+        }
+        return false;
+    }
+
+    let chunk_bytes = artifact_chunk_bytes(run.settings);
+    let mut upload = ChunkedUpload::new(&artifact.path, &artifact.file_name(), artifact.size_bytes, chunk_bytes);
+    let storage_path = match upload_chunked(&run.ws, http, &job.job_id, &artifact.name, &mut upload).await {
+        Ok(path) => path,
+        Err(e) => {
+            warn!("Failed to upload artifact '{}' of job {}: {:#}", artifact.name, job.job_id, e);
+            if let Some(queue) = run.uploads {
+                match queue.enqueue_artifact(&job.job_id, &artifact, upload.upload_id()).await {
+                    Ok(()) => {
+                        run.record_artifact(&artifact, "queued");
+                        return true;
+                    }
+                    Err(e) => warn!("Failed to queue artifact '{}' for retry: {}", artifact.name, e),
+                }
+            }
+            return false;
+        }
+    };
+    info!(
+        "Uploaded artifact '{}' ({} entries, {} bytes) for job {}",
+        artifact.name, artifact.entries, artifact.size_bytes, job.job_id
+    );
+    run.record_artifact(&artifact, "uploaded");
+
+    let ready = OutgoingMessage::ArtifactReady {
+        job_id: job.job_id.clone(),
+        artifact_name: artifact.name.clone(),
+        artifact_path: storage_path,
+        size_bytes: artifact.size_bytes,
+        checksum: artifact.checksum.clone(),
+        upload_id: upload.upload_id().to_string(),
+        metadata: artifact.metadata.clone(),
+    };
+    if let Err(e) = run.ws.send(&ready).await {
+        warn!("Failed to report artifact '{}': {}", artifact.name, e);
+    }
+    run.events.emit(RunnerEvent::ArtifactUploaded {
+        job_id: job.job_id.clone(),
+        name: artifact.name,
+        size_bytes: artifact.size_bytes,
+    });
+    true
+}
+
+/// Upload the files matching a step's `outputs.files`, each as an artifact
+/// named `<step id>/<path>`, and return references for its status update.
+///
+/// Files over `job.output_file_max_bytes` and failed uploads are noted in
+/// the step log and left out.
+async fn upload_output_files(run: &JobRun<'_>, step: &StepSpec, http: &HttpClient) -> Result<Vec<OutputFileRef>> {
+    let job = run.job;
+    let mut files = Vec::new();
+    if step.outputs.files.is_empty() {
+        return Ok(files);
+    }
+    if job.untrusted {
+        run.log_streamer.add(&step.step_id, "Output files are not uploaded for untrusted jobs", "system").await?;
+        return Ok(files);
+    }
+    let matches = match ArtifactManager::collect(run.workspace_path, &step.outputs.files) {
+        Ok(matches) => matches,
+        Err(e) => {
+            run.log_streamer.add(&step.step_id, &format!("{:#}", e), "warn").await?;
+            return Ok(files);
+        }
+    };
+
+    let manager = ArtifactManager::new(run.settings.workspace.artifact_path.clone());
+    let max_bytes = run.settings.job.output_file_max_bytes;
+    for relative in matches {
+        let path = run.workspace_path.join(&relative);
+        let display = relative.display().to_string();
+        // Links are not followed, as when packaging artifacts
+        let size_bytes = match tokio::fs::symlink_metadata(&path).await {
+            Ok(metadata) if metadata.is_symlink() => {
+                let notice = format!("Output file {} is a symbolic link and is not uploaded", display);
+                run.log_streamer.add(&step.step_id, &notice, "warn").await?;
+                continue;
+            }
+            Ok(metadata) if metadata.is_file() => metadata.len(),
+            _ => continue,
+        };
+        if size_bytes > max_bytes {
+            let notice = format!("Output file {} is {} bytes, over the {} byte limit", display, size_bytes, max_bytes);
+            run.log_streamer.add(&step.step_id, &notice, "warn").await?;
+            continue;
+        }
+        let checksum = match ArtifactUploader::calculate_checksum(&path).await {
+            Ok(checksum) => checksum,
+            Err(e) => {
+                run.log_streamer.add(&step.step_id, &format!("Output file {}: {:#}", display, e), "warn").await?;
+                continue;
+            }
+        };
+
+        let name = format!("{}/{}", step.reference_id(), display);
+        let artifact = PackagedArtifact {
+            name: name.clone(),
+            path,
+            entries: 1,
+            size_bytes,
+            checksum: checksum.clone(),
+            metadata: Vec::new(),
+        };
+        if !publish_artifact(run, &manager, http, artifact).await {
+            run.log_streamer.add(&step.step_id, &format!("Failed to upload output file {}", display), "warn").await?;
+            continue;
+        }
+        files.push(OutputFileRef {
+            path: display,
+            url: http.artifact_download_url(&job.execution_id, &name),
+            artifact_name: name,
+            size_bytes,
+            checksum,
+        });
+    }
+    Ok(files)
+}
+
+/// Send the rest of `upload`, reporting progress after each chunk, and
+/// return its storage path
+async fn upload_chunked(
+    ws: &WebSocketClient,
+    http: &HttpClient,
+    job_id: &str,
+    name: &str,
+    upload: &mut ChunkedUpload,
+) -> Result<String> {
+    while !upload.is_complete() {
+        let uploaded = upload.send_next(http).await?;
+        let progress = OutgoingMessage::ArtifactProgress {
+            job_id: job_id.to_string(),
+            artifact_name: name.to_string(),
+            upload_id: upload.upload_id().to_string(),
+            uploaded_bytes: uploaded,
+            size_bytes: upload.size_bytes(),
+        };
+        if let Err(e) = ws.send(&progress).await {
+            debug!("Failed to report progress of artifact '{}': {}", name, e);
+        }
+    }
+    Ok(upload.storage_path().unwrap_or_default().to_string())
+}
+
+/// Request size of chunked artifact uploads
+fn artifact_chunk_bytes(settings: &Settings) -> usize {
+    (settings.job.artifact_chunk_size_mb.max(1) * 1024 * 1024) as usize
+}
+
+/// Upload the job's timeline as a Chrome trace artifact
+async fn export_timeline(run: &JobRun<'_>, manager: &ArtifactManager, http: &HttpClient) {
+    let trace = run.timeline.to_trace(&run.job.name);
+    let staged = match serde_json::to_vec(&trace) {
+        Ok(contents) => manager.stage(&run.job.job_id, TIMELINE_ARTIFACT, "timeline.json", &contents).await,
+        Err(e) => Err(e.into()),
+    };
+
+    match staged {
+        Ok(artifact) => {
+            publish_artifact(run, manager, http, artifact).await;
+        }
+        Err(e) => warn!("Failed to export timeline of job {}: {}", run.job.job_id, e),
+    }
+}
+
+/// Queue for failed post-completion uploads, if retries are enabled
+fn upload_queue(settings: &Settings) -> Option<UploadQueue> {
+    let window = settings.job.upload_retry_window_secs;
+    (window > 0).then(|| UploadQueue::new(settings.workspace.upload_queue_path.clone(), Duration::from_secs(window)))
+}
+
+/// Deliver a queued upload over the runner's connection
+async fn deliver_upload(ws: &WebSocketClient, http: &HttpClient, upload: PendingUpload, chunk_bytes: usize) -> Result<()> {
+    match upload {
+        PendingUpload::Logs { job_id, logs } => ws.send_log_batch(&job_id, logs).await,
+        PendingUpload::Artifact { job_id, name, file_name, file, upload_id, size_bytes, checksum, metadata } => {
+            let mut upload = match upload_id {
+                Some(ref id) => ChunkedUpload::resume(id, &file, &file_name, size_bytes, chunk_bytes),
+                None => ChunkedUpload::new(&file, &file_name, size_bytes, chunk_bytes),
+            };
+            let artifact_path = upload_chunked(ws, http, &job_id, &name, &mut upload).await?;
+            ws.send(&OutgoingMessage::ArtifactReady {
+                job_id,
+                artifact_name: name,
+                artifact_path,
+                size_bytes,
+                checksum,
+                upload_id: upload.upload_id().to_string(),
+                metadata,
+            }).await
+        }
+    }
+}
+
+/// Artifact name of the exported job timeline
+const TIMELINE_ARTIFACT: &str = "job-timeline";
+
+/// When to warn a step with `budget` to run, `warning_secs` before its deadline
+fn warning_delay(budget: Duration, warning_secs: u64) -> Option<Duration> {
+    let warning = Duration::from_secs(warning_secs);
+    if warning_secs == 0 || budget <= warning {
+        return None;
+    }
+    Some(budget - warning)
+}
+
+/// When `percent` of `budget` has passed, if it is a warning threshold
+fn timeout_warning_delay(budget: Duration, percent: u8) -> Option<Duration> {
+    if percent == 0 || percent >= 100 || budget.is_zero() {
+        return None;
+    }
+    Some(budget * percent as u32 / 100)
+}
+
+/// Warns the log and the control plane that a job or step is about to time
+/// out; dropping it cancels the warning
+struct TimeoutWarning(tokio::task::JoinHandle<()>);
+
+impl TimeoutWarning {
+    /// Warning for the job's own timeout, logged to the step running then
+    fn job(run: &JobRun<'_>, ctx: &JobContext, budget: Duration) -> Option<Self> {
+        Self::spawn(run, None, Some(ctx.progress.clone()), budget)
+    }
+
+    /// Warning for the execute phase of `step`
+    fn step(run: &JobRun<'_>, step: &StepSpec, budget: Duration) -> Option<Self> {
+        Self::spawn(run, Some(step), None, budget)
+    }
+
+    fn spawn(
+        run: &JobRun<'_>,
+        step: Option<&StepSpec>,
+        progress: Option<Arc<RwLock<JobSnapshot>>>,
+        budget: Duration,
+    ) -> Option<Self> {
+        let percent = run.settings.job.timeout_warning_percent;
+        let after = timeout_warning_delay(budget, percent)?;
+        let (ws, log_streamer) = (run.ws.clone(), run.log_streamer.clone());
+        let job_id = run.job.job_id.clone();
+        let runner_id = run.settings.runner.id.clone();
+        let step_id = step.map(|step| step.step_id.clone());
+        // Progress only knows the running step by name; the first one wins
+        let step_ids: HashMap<String, String> = run.job.steps
+            .iter()
+            .rev()
+            .map(|step| (step.name.clone(), step.step_id.clone()))
+            .collect();
+
+        Some(Self(tokio::spawn(async move {
+            tokio::time::sleep(after).await;
+            let remaining = budget - after;
+            let deadline = Utc::now() + chrono::Duration::from_std(remaining).unwrap_or_else(|_| chrono::Duration::zero());
+            let notice = format!(
+                "{} will time out in {:?} ({}% of its {:?} timeout used)",
+                if step_id.is_some() { "Step" } else { "Job" }, remaining, percent, budget
+            );
+            warn!("{} {}: {}", job_id, step_id.as_deref().unwrap_or_default(), notice);
+
+            let log_step = match (&step_id, progress) {
+                (Some(id), _) => Some(id.clone()),
+                (None, Some(progress)) => progress.read().await.current_step.as_ref().and_then(|name| step_ids.get(name).cloned()),
+                (None, None) => None,
+            };
+            if let Some(id) = log_step {
+                if let Err(e) = log_streamer.add(&id, &notice, "warn").await {
+                    warn!("Failed to log timeout warning: {}", e);
+                }
+            }
+            let message = OutgoingMessage::TimeoutWarning {
+                job_id,
+                step_id,
+                runner_id,
+                timeout_secs: budget.as_secs(),
+                deadline,
+            };
+            if let Err(e) = ws.send(&message).await {
+                warn!("Failed to send timeout warning: {}", e);
+            }
+        })))
+    }
+}
+
+impl Drop for TimeoutWarning {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Time allowed past the job budget for steps to report their own timeout
+const JOB_TIMEOUT_GRACE: Duration = Duration::from_secs(30);
+
+/// Time allowed for executors to stop the steps of a cancelled job
+const CANCEL_STOP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Wall-clock budget for a job: the requested timeout (at least the default),
+/// capped by `max_job_duration_minutes` when set
+pub(super) fn job_timeout(requested_minutes: u32, config: &JobConfig) -> Duration {
+    let mut minutes = requested_minutes.max(config.default_timeout_minutes);
+    if config.max_job_duration_minutes > 0 {
+        minutes = minutes.min(config.max_job_duration_minutes);
+    }
+    Duration::from_secs(minutes as u64 * 60)
+}
+
+/// Execute all steps with timeout
+async fn execute_steps_with_timeout(
+    run: &JobRun<'_>,
+    ctx: Arc<JobContext>,
+    job_timeout: Duration,
+) -> Result<HashMap<String, String>> {
+    let start = Instant::now();
+    let mut results = StepResults::new(StepsContext::new(run.job.environment.clone()));
+    let hooks = StepHooks::from(&run.settings.hooks);
+    let steps = &run.job.steps;
+
+    let Some(graph) = StepGraph::new(steps)? else {
+        for step in steps {
+            check_job_running(&ctx, start, job_timeout).await?;
+
+            let mut steps_ctx = results.steps_ctx.clone();
+            steps_ctx.enter_step(step, visible_secrets(run, step));
+            if let Some(condition) = unmet_condition(step, &steps_ctx) {
+                skip_step(run, &ctx, step, condition).await?;
+                results.skip(step);
+                continue;
+            }
+
+            let remaining = job_timeout.saturating_sub(start.elapsed());
+            let executed = run_step(run, &ctx, &hooks, step, &steps_ctx, remaining).await;
+            results.record(step, executed);
+            results.export_env(run, step).await;
+        }
+        return results.finish(run).await;
+    };
+
+    // Start every step whose needs have finished, then wait for the next one
+    // to finish
+    let mut started = vec![false; steps.len()];
+    let mut finished = vec![false; steps.len()];
+    let mut running = FuturesUnordered::new();
+    loop {
+        while let Some(i) = graph.next_ready(&started, &finished) {
+            check_job_running(&ctx, start, job_timeout).await?;
+            started[i] = true;
+
+            let step = &steps[i];
+            let mut steps_ctx = results.steps_ctx.scoped_to_needs(&step.needs);
+            steps_ctx.enter_step(step, visible_secrets(run, step));
+            if let Some(condition) = unmet_condition(step, &steps_ctx) {
+                skip_step(run, &ctx, step, condition).await?;
+                results.skip(step);
+                finished[i] = true;
+                continue;
+            }
+
+            let remaining = job_timeout.saturating_sub(start.elapsed());
+            let (ctx, hooks) = (&ctx, &hooks);
+            running.push(async move {
+                (i, run_step(run, ctx, hooks, step, &steps_ctx, remaining).await)
+            });
+        }
+
+        let Some((i, executed)) = running.next().await else {
+            break;
+        };
+        results.record(&steps[i], executed);
+        results.export_env(run, &steps[i]).await;
+        finished[i] = true;
+    }
+    results.finish(run).await
+}
+
+/// Outcomes of a job's finished steps
+struct StepResults {
+    steps_ctx: StepsContext,
+    job_outputs: HashMap<String, String>,
+    first_error: Option<anyhow::Error>,
+    /// Bytes of the env file merged so far
+    env_read: usize,
+}
+
+impl StepResults {
+    fn new(steps_ctx: StepsContext) -> Self {
+        Self { steps_ctx, job_outputs: HashMap::new(), first_error: None, env_read: 0 }
+    }
+
+    /// Pass what `step` wrote to `MUELSYSE_ENV` on to the steps started after it
+    async fn export_env(&mut self, run: &JobRun<'_>, step: &StepSpec) {
+        let Some(file) = run.env_file else { return };
+        let entries = match file.read_from(self.env_read).await {
+            Ok((entries, read)) => {
+                self.env_read = read;
+                entries
+            }
+            Err(e) => {
+                warn!("Failed to read the env file of job {}: {:#}", run.job.job_id, e);
+                return;
+            }
+        };
+        let (exported, ignored) = exported_env(entries);
+        if !ignored.is_empty() {
+            let notice = format!("Ignored invalid or reserved names in {}: {}", EXPORT_ENV, ignored.join(", "));
+            if let Err(e) = run.log_streamer.add(&step.step_id, &notice, "warn").await {
+                warn!("Failed to log ignored env names of step {}: {}", step.step_id, e);
+            }
+        }
+        self.steps_ctx.export_env(exported);
+    }
+
+    fn skip(&mut self, step: &StepSpec) {
+        self.steps_ctx.record(step, StepStatus::Skipped, HashMap::new());
+    }
+
+    fn record(&mut self, step: &StepSpec, executed: Result<(StepStatus, HashMap<String, String>)>) {
+        match executed {
+            Ok((status, outputs)) => {
+                self.job_outputs.extend(outputs.clone());
+                self.steps_ctx.record(step, status, outputs);
+            }
+            Err(e) => {
+                error!("Step {} failed: {}", step.name, e);
+                self.steps_ctx.record(step, RunnerError::classify(&e).step_status(), HashMap::new());
+                if !step.continue_on_error && self.first_error.is_none() {
+                    self.first_error = Some(e);
+                }
+            }
+        }
+    }
+
+    /// The job's outputs, or its first step error; post actions run only if
+    /// every step passed
+    async fn finish(self, run: &JobRun<'_>) -> Result<HashMap<String, String>> {
+        match self.first_error {
+            Some(e) => Err(e),
+            None => {
+                run_post_actions(run).await;
+                Ok(self.job_outputs)
+            }
+        }
+    }
+}
+
+/// Fail if the job was cancelled or ran out of time
+async fn check_job_running(ctx: &JobContext, start: Instant, job_timeout: Duration) -> Result<()> {
+    if start.elapsed() > job_timeout {
+        error!("Job timeout exceeded");
+        anyhow::bail!(RunnerError::Timeout("Job timeout exceeded".into()));
+    }
+    if ctx.is_cancelled().await {
+        anyhow::bail!(RunnerError::Cancelled("Job cancelled".into()));
+    }
+    Ok(())
+}
+
+/// Secrets a step's expressions may see
+fn visible_secrets(run: &JobRun<'_>, step: &StepSpec) -> HashMap<String, String> {
+    if run.job.untrusted {
+        HashMap::new()
+    } else {
+        step_secrets(&run.job.secrets, step.secrets.as_deref(), run.settings.job.step_secrets)
+    }
+}
+
+/// The step's condition, if it is not met.
+///
+/// Steps run only while the job is succeeding unless their condition says
+/// otherwise.
+fn unmet_condition<'a>(step: &'a StepSpec, steps_ctx: &StepsContext) -> Option<&'a str> {
+    let condition = step.condition
+        .as_deref()
+        .filter(|c| !c.trim().is_empty())
+        .unwrap_or("success()");
+    (!steps_ctx.evaluate(condition)).then_some(condition)
+}
+
+/// Report a step whose condition was not met
+async fn skip_step(run: &JobRun<'_>, ctx: &JobContext, step: &StepSpec, condition: &str) -> Result<()> {
+    info!("Skipping step {} (condition not met: {})", step.name, condition);
+    run.ws.send_status_update(
+        "step",
+        &step.step_id,
+        &StepStatus::Skipped.to_string(),
+        None,
+        HashMap::new(),
+        StatusMeta::finished(run.attempt, None),
+    ).await?;
+    run.record_step(step, StepStatus::Skipped, Instant::now());
+    ctx.step_finished().await;
+    Ok(())
+}
+
+/// Run a step whose condition is met, with `remaining` job time
+async fn run_step(
+    run: &JobRun<'_>,
+    ctx: &JobContext,
+    hooks: &StepHooks,
+    step: &StepSpec,
+    steps_ctx: &StepsContext,
+    remaining: Duration,
+) -> Result<(StepStatus, HashMap<String, String>)> {
+    // Calculate phase budgets for step, capped by remaining job time
+    let step_timeout = Duration::from_secs(
+        step.timeout_minutes.max(run.settings.job.default_step_timeout_minutes) as u64 * 60
+    );
+    let phases = PhaseTimeouts::new(&run.settings.job, step_timeout).capped(remaining);
+
+    // Point diagnostics requests at this step
+    ctx.set_diagnostic_target(DiagnosticTarget {
+        workspace_path: run.workspace_path.to_path_buf(),
+        container: step_container_name(run.job, step, &run.settings.executor.docker),
+        masker: SecretMasker::new(run.job.secrets.values().cloned()),
+    }).await;
+
+    ctx.step_started(step).await;
+    let step_start = Instant::now();
+
+    // Hooks may veto the step or add to its environment
+    let payload = HookPayload::before_step(&run.settings.runner.id, run.job, step);
+    let span = step_span(step);
+    let executed = async {
+        match hooks.before_step(&payload).await {
+            Err(veto) => {
+                run.log_streamer.add(&step.step_id, &veto.to_string(), "error").await?;
+                report_step_error(run, step, &veto, &PhaseTimings::default(), Utc::now()).await?;
+                Err(veto)
+            }
+            Ok(hook_env) => match step.uses {
+                Some(ref uses) if step.run.is_none() => execute_action_step(run, step, uses, phases, steps_ctx).await,
+                _ => execute_step_with_timeout(run, ctx, step, phases, steps_ctx, hook_env).await,
+            },
+        }
+    }.instrument(span.clone()).await;
+    run.timeline.record(&step.name, "steps", step_start);
+
+    let status = match executed {
+        Ok((status, _)) => status,
+        Err(_) if run.cancel.is_cancelled() => StepStatus::Cancelled,
+        Err(ref e) => RunnerError::classify(e).step_status(),
+    };
+    record_status(&span, &status.to_string(), matches!(status, StepStatus::Failed | StepStatus::Timeout));
+    hooks.after_step(&payload.after_step(&status.to_string(), step_start.elapsed())).await;
+    ctx.step_finished().await;
+    run.record_step(step, status, step_start);
+    executed
+}
+
+/// Run a `uses:` step with a built-in action on the runner host
+async fn execute_action_step(
+    run: &JobRun<'_>,
+    step: &StepSpec,
+    uses: &str,
+    phases: PhaseTimeouts,
+    steps_ctx: &StepsContext,
+) -> Result<(StepStatus, HashMap<String, String>)> {
+    info!("Executing step: {} ({}, uses {})", step.name, step.step_id, uses);
+    let job = run.job;
+    let started_at = Utc::now();
+
+    run.ws.send_status_update(
+        "step",
+        &step.step_id,
+        "running",
+        None,
+        HashMap::new(),
+        StatusMeta::started(run.attempt, started_at),
+    ).await?;
+    run.start_step(step, started_at);
+
+    let mut timings = PhaseTimings::default();
+    let registry = ActionRegistry::builtin();
+    let Some(action) = registry.get(uses) else {
+        let e = anyhow::anyhow!("Unknown action '{}'", uses);
+        report_step_error(run, step, &e, &timings, started_at).await?;
+        return Err(e);
+    };
+
+    let inputs = step.with_inputs
+        .iter()
+        .map(|(name, value)| {
+            let value = match value {
+                serde_json::Value::String(s) => steps_ctx.interpolate(s),
+                other => other.to_string(),
+            };
+            (name.clone(), value)
+        })
+        .collect();
+
+    let http = HttpClient::new(run.settings.clone());
+    let masker = SecretMasker::new(job.secrets.values().cloned());
+    let (output_tx, output_rx) = mpsc::unbounded_channel();
+    run.log_streamer.limit_output(&step.step_id, step.log);
+    let forwarder = spawn_output_forwarder(run, step, masker, output_rx);
+    let ctx = ActionContext {
+        job,
+        workspace: run.workspace_path,
+        inputs,
+        settings: run.settings,
+        http: &http,
+        output: &output_tx,
+    };
+
+    let phase_start = Instant::now();
+    let execute = phases.get(ExecutionPhase::Execute);
+    let timeout_warning = TimeoutWarning::step(run, step, execute);
+    let executed = timeout(execute, action.run(&ctx)).instrument(phase_span(ExecutionPhase::Execute)).await;
+    drop(timeout_warning);
+    timings.record(ExecutionPhase::Execute, phase_start.elapsed());
+    run.timeline.record(format!("{}: {}", step.name, ExecutionPhase::Execute), "phases", phase_start);
+
+    drop(ctx);
+    drop(output_tx);
+    if let Err(e) = forwarder.await {
+        warn!("Output forwarder of step {} failed: {}", step.step_id, e);
+    }
+    if let Err(e) = run.log_streamer.finish_output(&step.step_id).await {
+        warn!("Failed to log output summary of step {}: {}", step.step_id, e);
+    }
+
+    let outcome = match executed {
+        Ok(Ok(outcome)) => outcome,
+        Ok(Err(e)) => {
+            run.log_streamer.add(&step.step_id, &format!("{:#}", e), "error").await?;
+            report_step_error(run, step, &e, &timings, started_at).await?;
+            return Err(e);
+        }
+        Err(_) => {
+            return report_phase_timeout(run, step, ExecutionPhase::Execute, execute, &timings, started_at).await;
+        }
+    };
+
+    let manager = ArtifactManager::new(run.settings.workspace.artifact_path.clone());
+    for artifact in outcome.artifacts {
+        publish_artifact(run, &manager, &http, artifact).await;
+    }
+    if let Some(post) = outcome.post {
+        run.post_actions.lock().unwrap_or_else(|e| e.into_inner()).push(post);
+    }
+    let output_files = upload_output_files(run, step, &http).await?;
+    if let Err(e) = run.log_streamer.flush().await {
+        warn!("Failed to flush logs of step {}: {}", step.step_id, e);
+    }
+
+    let mut status_outputs = outcome.outputs.clone();
+    status_outputs.extend(timings.to_outputs());
+    run.ws.send_status_update(
+        "step",
+        &step.step_id,
+        &StepStatus::Success.to_string(),
+        Some(0),
+        status_outputs,
+        StatusMeta::finished(run.attempt, Some(started_at)).with_output_files(output_files),
+    ).await?;
+    emit_step_finished(run, step, StepStatus::Success, Some(0), started_at);
+
+    Ok((StepStatus::Success, outcome.outputs))
+}
+
+/// Run deferred action work, such as saving caches that missed.
+///
+/// Failures are logged and do not fail the job.
+async fn run_post_actions(run: &JobRun<'_>) {
+    let post_actions = std::mem::take(&mut *run.post_actions.lock().unwrap_or_else(|e| e.into_inner()));
+    for post in post_actions {
+        let start = Instant::now();
+        if let Err(e) = post.run(run.settings, run.workspace_path).await {
+            warn!("Post action {:?} of job {} failed: {}", post, run.job.job_id, e);
+        }
+        run.timeline.record("post actions", "actions", start);
+    }
+}
+
+/// Execute a single step with timeout
+async fn execute_step_with_timeout(
+    run: &JobRun<'_>,
+    ctx: &JobContext,
+    step: &StepSpec,
+    phases: PhaseTimeouts,
+    steps_ctx: &StepsContext,
+    hook_env: HashMap<String, String>,
+) -> Result<(StepStatus, HashMap<String, String>)> {
+    info!("Executing step: {} ({})", step.name, step.step_id);
+    let job = run.job;
+    let started_at = Utc::now();
+
+    // Update step status to running
+    run.ws.send_status_update(
+        "step",
+        &step.step_id,
+        "running",
+        None,
+        HashMap::new(),
+        StatusMeta::started(run.attempt, started_at),
+    ).await?;
+    run.start_step(step, started_at);
+
+    // Just-in-time secrets are fetched now and dropped with the step
+    let mut fetched = HashMap::new();
+    let names = step_jit_secrets(&job.jit_secrets, step.secrets.as_deref(), run.settings.job.step_secrets);
+    if !job.untrusted && !names.is_empty() {
+        fetched = match HttpClient::new(run.settings.clone())
+            .fetch_step_secrets(&job.job_id, &step.step_id, &names)
+            .await
+        {
+            Ok(fetched) => fetched,
+            Err(e) => {
+                let e = anyhow::Error::from(RunnerError::InfraError(format!("Secrets of step {}: {:#}", step.name, e)));
+                report_step_error(run, step, &e, &PhaseTimings::default(), started_at).await?;
+                return Err(e);
+            }
+        };
+        fetched.retain(|name, _| names.contains(&name.as_str()));
+        ctx.mask_in_diagnostics(SecretMasker::new(job.secrets.values().chain(fetched.values()).cloned())).await;
+    }
+
+    // Re-enter the step so its expressions see the fetched secrets
+    let entered;
+    let steps_ctx = if fetched.is_empty() {
+        steps_ctx
+    } else {
+        let mut secrets = visible_secrets(run, step);
+        secrets.extend(fetched.clone());
+        let mut scoped = steps_ctx.clone();
+        scoped.enter_step(step, secrets);
+        entered = scoped;
+        &entered
+    };
+
+    // Build environment; the step's own env was resolved on entering the step
+    let mut env = job.environment.clone();
+    env.extend(steps_ctx.exported_env().clone());
+    env.extend(steps_ctx.needs_env());
+    env.extend(steps_ctx.step_env().clone());
+    if !hook_env.is_empty() {
+        let mut names: Vec<&str> = hook_env.keys().map(String::as_str).collect();
+        names.sort();
+        let notice = format!("Step hooks set {}", names.join(", "));
+        run.log_streamer.add(&step.step_id, &notice, "system").await?;
+        env.extend(hook_env);
+    }
+
+    // Add the secrets this step may see (all are masked in logs)
+    if job.untrusted {
+        if !job.secrets.is_empty() || !job.jit_secrets.is_empty() {
+            run.log_streamer.add(&step.step_id, "Secrets are withheld from untrusted jobs", "warn").await?;
+        }
+    } else {
+        env.extend(step_secrets(&job.secrets, step.secrets.as_deref(), run.settings.job.step_secrets));
+        env.extend(fetched.clone());
+    }
+    let undefined: Vec<&str> = step.secrets
+        .iter()
+        .flatten()
+        .filter(|name| !job.secrets.contains_key(*name) && !job.jit_secrets.contains(name))
+        .map(String::as_str)
+        .collect();
+    if !undefined.is_empty() {
+        let notice = format!("Secrets not defined for this job: {}", undefined.join(", "));
+        run.log_streamer.add(&step.step_id, &notice, "warn").await?;
+    }
+
+    // Keep the environment within what execve accepts
+    let limits = EnvLimits::from(&run.settings.job);
+    let limited = async {
+        if run.settings.job.env_file_indirection {
+            // Within the job's private script directory, which only its own
+            // containers can see
+            let job_dir = job_script_dir(&job.job_id);
+            create_private_dir(&job_dir).await?;
+            let host_dir = env_file_dir(&job_dir);
+            let visible_dir = if run.executor.executor_type() == ExecutorType::Shell {
+                host_dir.clone()
+            } else {
+                env_file_dir(Path::new(CONTAINER_SCRIPT_DIR))
+            };
+            let moved = indirect_oversized(&mut env, &limits, &host_dir, &visible_dir).await?;
+            if !moved.is_empty() {
+                let notice = format!("Oversized variables passed as files: {}", moved.join(", "));
+                run.log_streamer.add(&step.step_id, &notice, "system").await?;
+            }
+        }
+        limits.check(&env)
+    }.await;
+    if let Err(e) = limited {
+        report_step_error(run, step, &e, &PhaseTimings::default(), started_at).await?;
+        return Err(e);
+    }
+
+    // Build execution context
+    let working_dir = if let Some(ref wd) = step.working_directory {
+        run.workspace_path.join(steps_ctx.interpolate(wd))
+    } else {
+        run.workspace_path.to_path_buf()
+    };
+
+    let command = steps_ctx.interpolate(step.run.as_deref().unwrap_or_default());
+    let masker = SecretMasker::new(job.secrets.values().chain(fetched.values()).cloned());
+
+    // Show exactly what is about to run
+    if run.settings.job.echo_commands && !command.is_empty() {
+        run.log_streamer.add(&step.step_id, &format_command_echo(&masker.mask(&command)), "system").await?;
+    }
+
+    // Host steps fall back to an installed shell; containers bring their own
+    let shell = if run.executor.executor_type() == ExecutorType::Shell {
+        let shell_config = &run.settings.executor.shell;
+        let shell = match select_shell(&step.shell, available_shells(), &shell_config.fallback) {
+            Ok(shell) => shell,
+            Err(e) => {
+                report_step_error(run, step, &e, &PhaseTimings::default(), started_at).await?;
+                return Err(e);
+            }
+        };
+        if shell != step.shell {
+            warn!("Shell '{}' not available for step {}, falling back to '{}'", step.shell, step.step_id, shell);
+            let notice = format!("Shell '{}' is not installed, falling back to '{}'", step.shell, shell);
+            run.log_streamer.add(&step.step_id, &notice, "warn").await?;
+        }
+        shell
+    } else {
+        step.shell.clone()
+    };
+
+    let command = if run.settings.job.trace_scripts {
+        inject_trace(&command, &shell)
+    } else {
+        command
+    };
+
+    let mut ctx = ExecutionContext {
+        job_id: job.job_id.clone(),
+        step_id: step.step_id.clone(),
+        command,
+        shell,
+        working_directory: working_dir,
+        workspace: run.workspace_path.to_path_buf(),
+        environment: env,
+        timeout: phases.execute,
+        container_image: job.container.as_ref().map(|c| c.image.clone()),
+        container_options: None,
+        output_encoding: step.output_encoding.unwrap_or(run.settings.executor.output_encoding),
+        warning_signal_after: None,
+        cancel: run.cancel.clone(),
+        limits: step.limits,
+        temp_dir: Some(run.temp_dir.to_path_buf()),
+        gpus: run.gpus.to_vec(),
+    };
+
+    let mut timings = PhaseTimings::default();
+
+    if job.untrusted {
+        ctx.container_options = Some(untrusted_container_options(&run.settings.untrusted));
+    } else if let Some(ref spec) = job.container {
+        match container_options(spec, job.workspace.repository_url.as_deref(), &run.settings.executor.docker) {
+            Ok(options) => ctx.container_options = Some(options),
+            Err(e) => {
+                report_step_error(run, step, &e, &timings, started_at).await?;
+                return Err(e);
+            }
+        }
+    }
+
+    // Wait for step resources the job does not already hold
+    let step_resources: Vec<String> = step.resources
+        .iter()
+        .filter(|r| !job.resources.contains(r))
+        .cloned()
+        .collect();
+    let phase_start = Instant::now();
+    let resource_guard = match run.resources.acquire(&step_resources).await {
+        Ok(guard) => guard,
+        Err(e) => {
+            report_step_error(run, step, &e, &timings, started_at).await?;
+            return Err(e);
+        }
+    };
+    if !step_resources.is_empty() {
+        run.timeline.record(format!("{}: acquire resources", step.name), "phases", phase_start);
+        let notice = format!("Acquired {} after {:?}", step_resources.join(", "), resource_guard.waited);
+        run.log_streamer.add(&step.step_id, &notice, "system").await?;
+    }
+
+    // Prepare phase: workspace setup and image pull
+    let phase_start = Instant::now();
+    let prepare = phases.get(ExecutionPhase::Prepare);
+    let prepared = timeout(prepare, run.executor.prepare(&ctx)).instrument(phase_span(ExecutionPhase::Prepare)).await;
+    timings.record(ExecutionPhase::Prepare, phase_start.elapsed());
+    run.timeline.record(format!("{}: {}", step.name, ExecutionPhase::Prepare), "phases", phase_start);
+
+    match prepared {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => {
+            report_step_error(run, step, &e, &timings, started_at).await?;
+            return Err(e);
+        }
+        Err(_) => {
+            return report_phase_timeout(run, step, ExecutionPhase::Prepare, prepare, &timings, started_at).await;
+        }
+    }
+
+    // Restore the step cache
+    let cache = step.cache.as_ref().map(|spec| {
+        let key = steps_ctx.interpolate(&spec.key);
+        let targets: Vec<PathBuf> = spec.paths.iter().map(|p| resolve_path(p, run.workspace_path)).collect();
+        (spec, key, targets)
+    });
+    let cache_hit = match cache {
+        // Trusted cache contents may only land in an untrusted job's workspace
+        Some((spec, ..)) if job.untrusted && !spec.paths.iter().all(|p| within_workspace(p)) => {
+            let notice = "Cache not restored: untrusted jobs may only cache paths in the workspace";
+            run.log_streamer.add(&step.step_id, notice, "warn").await?;
+            false
+        }
+        Some((_, ref key, ref targets)) => restore_step_cache(run, step, key, targets).await?,
+        None => false,
+    };
+
+    // Tell the step how much time it has left, after preparing and
+    // restoring its cache
+    let execute = phases.get(ExecutionPhase::Execute);
+    ctx.timeout = execute;
+    let deadline = Utc::now() + chrono::Duration::from_std(execute).unwrap_or_else(|_| chrono::Duration::zero());
+    ctx.environment.insert("MUELSYSE_DEADLINE".to_string(), deadline.to_rfc3339());
+    ctx.environment.insert("MUELSYSE_TIMEOUT_SECS".to_string(), execute.as_secs().to_string());
+
+    let output_file = OutputFile::create(&job.job_id, &step.step_id, run.executor.executor_type()).await?;
+    if let Some(ref file) = output_file {
+        ctx.environment.insert(OUTPUT_ENV.to_string(), file.visible_path().display().to_string());
+    }
+    if let Some(file) = run.env_file {
+        ctx.environment.insert(EXPORT_ENV.to_string(), file.visible_path().display().to_string());
+    }
+
+    let job_config = &run.settings.job;
+    // The host warning file is not visible inside Kubernetes pods
+    let warning_after = warning_delay(execute, job_config.deadline_warning_secs)
+        .filter(|_| run.executor.executor_type() != ExecutorType::Kubernetes);
+    let warning_file = job_script_dir(&job.job_id).join(format!("{}.deadline", step.step_id));
+    let warning_task = warning_after.map(|after| {
+        let visible = if run.executor.executor_type() == ExecutorType::Shell {
+            warning_file.clone()
+        } else {
+            Path::new(CONTAINER_SCRIPT_DIR).join(warning_file.file_name().unwrap_or_default())
+        };
+        ctx.environment.insert("MUELSYSE_DEADLINE_WARNING_FILE".to_string(), visible.display().to_string());
+        if job_config.deadline_warning_signal {
+            ctx.warning_signal_after = Some(after);
+        }
+
+        let path = warning_file.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(after).await;
+            if let Err(e) = tokio::fs::write(&path, deadline.to_rfc3339()).await {
+                warn!("Failed to write deadline warning file {:?}: {}", path, e);
+            }
+        })
+    });
+
+    // Execute phase, streaming output to the log as it is produced
+    let (output_tx, output_rx) = mpsc::unbounded_channel();
+    run.log_streamer.limit_output(&step.step_id, step.log);
+    let forwarder = spawn_output_forwarder(run, step, masker.clone(), output_rx);
+    if let Some(ref script) = step.on_cancel {
+        let window = Duration::from_secs(run.settings.job.cancel_timeout_secs);
+        let handler = cancel_handler_context(&ctx, &steps_ctx.interpolate(script), window);
+        run.set_cancel_handler(&step.step_id, Some(handler));
+    }
+    let phase_start = Instant::now();
+    let timeout_warning = TimeoutWarning::step(run, step, execute);
+    // The executor enforces the step timeout itself and stops the step
+    // gracefully; this only catches an executor that never returns
+    let backstop = execute + run.executor.stop_allowance();
+    let executed = timeout(backstop, run.executor.execute(&ctx, &output_tx))
+        .instrument(phase_span(ExecutionPhase::Execute))
+        .await;
+    drop(timeout_warning);
+    timings.record(ExecutionPhase::Execute, phase_start.elapsed());
+    run.set_cancel_handler(&step.step_id, None);
+
+    // Every line is logged before the step's status is reported
+    drop(output_tx);
+    if let Err(e) = forwarder.await {
+        warn!("Output forwarder of step {} failed: {}", step.step_id, e);
+    }
+    if let Err(e) = run.log_streamer.finish_output(&step.step_id).await {
+        warn!("Failed to log output summary of step {}: {}", step.step_id, e);
+    }
+    run.timeline.record(format!("{}: {}", step.name, ExecutionPhase::Execute), "phases", phase_start);
+
+    if let Some(task) = warning_task {
+        task.abort();
+        let _ = tokio::fs::remove_file(&warning_file).await;
+    }
+    let file_outputs = match output_file {
+        Some(file) => file.take().await,
+        None => HashMap::new(),
+    };
+
+    let result = match executed {
+        Ok(Ok(result)) => result,
+        Ok(Err(e)) => {
+            report_step_error(run, step, &e, &timings, started_at).await?;
+            return Err(e);
+        }
+        Err(_) => {
+            return report_phase_timeout(run, step, ExecutionPhase::Execute, execute, &timings, started_at).await;
+        }
+    };
+
+    // Collect phase: send remaining logs and parse outputs
+    let log_streamer = &run.log_streamer;
+    let phase_start = Instant::now();
+    let collect = phases.get(ExecutionPhase::Collect);
+    let collected = timeout(collect, async {
+        if result.timed_out {
+            log_streamer.add(&step.step_id, &result.stderr, "error").await?;
+        }
+        match result.write_audit {
+            Some(ref violations) => {
+                for violation in violations {
+                    let notice = format!("Write outside the workspace: {}", violation);
+                    log_streamer.add(&step.step_id, &masker.mask(&notice), "warn").await?;
+                }
+            }
+            None if run.settings.executor.shell.audit_writes && run.executor.executor_type() == ExecutorType::Shell => {
+                log_streamer.add(&step.step_id, "Write auditing is unavailable: strace is not installed", "system").await?;
+            }
+            None => {}
+        }
+        // Write audit violations first, so the step's own annotations cannot
+        // crowd them out
+        let violations = result.write_audit.iter().flatten().map(|violation| Annotation {
+            level: AnnotationLevel::Warning,
+            message: violation.to_string(),
+            title: Some("Write outside the workspace".to_string()),
+            file: None,
+            line: None,
+            end_line: None,
+            col: None,
+            end_column: None,
+        });
+        let annotations: Vec<Annotation> = violations
+            .chain(parse_annotations(&result.output))
+            .take(MAX_ANNOTATIONS)
+            .map(|annotation| annotation.masked(&masker))
+            .collect();
+        if !annotations.is_empty() {
+            run.ws.send_annotations(&job.job_id, &step.step_id, annotations).await?;
+        }
+
+        // Flush logs for this step
+        log_streamer.flush().await?;
+
+        // The output file wins over `::set-output` commands
+        let mut outputs = parse_outputs(&result.stdout);
+        outputs.extend(file_outputs);
+        Ok::<_, anyhow::Error>(outputs)
+    }).instrument(phase_span(ExecutionPhase::Collect)).await;
+    timings.record(ExecutionPhase::Collect, phase_start.elapsed());
+    run.timeline.record(format!("{}: {}", step.name, ExecutionPhase::Collect), "phases", phase_start);
+
+    let outputs = match collected {
+        Ok(outputs) => outputs?,
+        Err(_) => {
+            return report_phase_timeout(run, step, ExecutionPhase::Collect, collect, &timings, started_at).await;
+        }
+    };
+
+    // Determine status
+    let status = if result.timed_out {
+        StepStatus::Timeout
+    } else if result.success() {
+        StepStatus::Success
+    } else if run.cancel.is_cancelled() {
+        StepStatus::Cancelled
+    } else {
+        StepStatus::Failed
+    };
+
+    // Update step status with phase timings
+    let mut status_outputs = outputs.clone();
+    status_outputs.extend(timings.to_outputs());
+    if let Some(usage) = step_usage(run, result.usage).await {
+        status_outputs.extend(usage.to_outputs());
+        run.record_usage(&usage);
+    }
+    if !step_resources.is_empty() {
+        status_outputs.insert("resource_wait_ms".to_string(), resource_guard.waited.as_millis().to_string());
+    }
+    if result.timed_out {
+        status_outputs.insert("timed_out_phase".to_string(), ExecutionPhase::Execute.to_string());
+    }
+    if let Some(termination) = result.termination {
+        status_outputs.insert("termination".to_string(), termination.as_str().to_string());
+    }
+    if let Some(limit) = result.limit_exceeded {
+        run.log_streamer.add(&step.step_id, &format!("Step failed after reaching its {}", limit), "error").await?;
+        status_outputs.insert("failure_reason".to_string(), limit.reason().to_string());
+    }
+    if let Some(violations) = result.write_audit.as_ref().filter(|v| !v.is_empty()) {
+        let paths: Vec<String> = violations.iter().map(|v| v.path.display().to_string()).collect();
+        status_outputs.insert("write_violations".to_string(), masker.mask(&paths.join("\n")));
+    }
+    if let Some((spec, ref key, ref targets)) = cache {
+        status_outputs.insert("cache_hit".to_string(), cache_hit.to_string());
+        if status == StepStatus::Success && !cache_hit {
+            if job.untrusted {
+                // Untrusted jobs must not poison caches shared with trusted ones
+                run.log_streamer.add(&step.step_id, "Cache is read-only for untrusted jobs", "system").await?;
+            } else {
+                save_step_cache(run, step, key, &spec.paths, targets).await?;
+            }
+        }
+    }
+
+    let output_files = if status == StepStatus::Success {
+        upload_output_files(run, step, &HttpClient::new(run.settings.clone())).await?
+    } else {
+        Vec::new()
+    };
 
-    // Connect to control plane for status updates
-    let client = ControlPlaneClient::new(settings.clone());
-    let ws = Arc::new(client.connect_websocket().await?);
-    ws.wait_connected(Duration::from_secs(10)).await?;
+    // The first failed step is kept as it is for a debug session
+    let retained = status != StepStatus::Success && job.debug_on_failure && {
+        let mut debug_step = run.debug_step.lock().unwrap_or_else(|e| e.into_inner());
+        debug_step.is_none() && {
+            // Just-in-time secrets go with the step, not into the debug session
+            let mut kept = ctx.clone();
+            kept.environment.retain(|name, _| !job.jit_secrets.contains(name));
+            *debug_step = Some(kept);
+            true
+        }
+    };
 
-    // Get log streamer for this job
-    let log_streamer = log_manager.get_or_create(&job.job_id).await;
+    // Attach the end of the output so the UI can show why the step failed
+    if status != StepStatus::Success {
+        let logging = &run.settings.logging;
+        let tail = log_tail(&masker.mask(&result.output), logging.failure_tail_lines, logging.failure_tail_max_bytes);
 
-    // Update job status to running
-    ws.send_status_update(
-        "job",
-        &job.job_id,
-        "running",
-        None,
-        HashMap::new(),
+        if !tail.is_empty() {
+            status_outputs.insert("failure_log_tail".to_string(), tail);
+        }
+    }
+
+    run.ws.send_status_update(
+        "step",
+        &step.step_id,
+        &status.to_string(),
+        Some(result.exit_code),
+        status_outputs,
+        StatusMeta::finished(run.attempt, Some(started_at)).with_output_files(output_files),
     ).await?;
+    emit_step_finished(run, step, status, Some(result.exit_code), started_at);
 
-    // Prepare workspace
-    let workspace_path = PathBuf::from(&settings.workspace.base_path)
-        .join(&job.job_id);
+    // Cleanup, unless the step is kept for debugging
+    if !retained {
+        run.executor.cleanup(&ctx).await?;
+    }
 
-    tokio::fs::create_dir_all(&workspace_path).await?;
+    if status == StepStatus::Cancelled {
+        anyhow::bail!(RunnerError::Cancelled(format!("Step {} cancelled", step.name)));
+    }
+    if !result.success() && !step.continue_on_error {
+        if result.timed_out {
+            anyhow::bail!(RunnerError::Timeout(format!("Step {} timed out", step.name)));
+        }
+        anyhow::bail!(RunnerError::ExecutorFailure { exit_code: result.exit_code });
+    }
 
-    // Determine executor type
-    let executor_type = if job.container.is_some() {
-        ExecutorType::Docker
-    } else {
-        ExecutorType::Shell
-    };
+    Ok((status, outputs))
+}
 
-    let executor = create_executor(executor_type, &settings)?;
+/// A step's measured usage and the size of the workspace and temporary
+/// directory it left behind, if usage is reported
+async fn step_usage(run: &JobRun<'_>, measured: Option<ResourceUsage>) -> Option<ResourceUsage> {
+    let mut usage = measured.filter(|_| run.settings.job.report_resource_usage)?;
+    let (workspace, temp_dir) = (run.workspace_path.to_path_buf(), run.temp_dir.to_path_buf());
+    let sizes = tokio::task::spawn_blocking(move || (dir_size(&workspace), dir_size(&temp_dir))).await.ok();
+    usage.disk_bytes = sizes.map(|(disk, _)| disk);
+    usage.temp_bytes = sizes.map(|(_, temp)| temp);
+    Some(usage)
+}
 
-    // Calculate job timeout
-    let job_timeout = Duration::from_secs(
-        job.timeout_minutes.max(settings.job.default_timeout_minutes) as u64 * 60
-    );
+/// Context running a step's `on_cancel` script alongside the step itself
+fn cancel_handler_context(step: &ExecutionContext, script: &str, window: Duration) -> ExecutionContext {
+    let mut handler = step.companion("on-cancel", script, window);
+    // Not stopped with the step it cleans up after
+    handler.cancel = CancellationToken::new();
+    handler.environment.insert("MUELSYSE_CANCELLED".to_string(), "true".to_string());
+    handler
+}
 
-    // Execute steps with job-level timeout
+/// Keep a failed job for up to `job.debug_session_secs`, running the
+/// commands the control plane relays in the environment of `step`
+async fn run_debug_session(run: &JobRun<'_>, ctx: &JobContext, step: &ExecutionContext) {
+    let job = run.job;
+    let limit = Duration::from_secs(run.settings.job.debug_session_secs);
+    let session_id = uuid::Uuid::new_v4().to_string();
+    let expires_at = Utc::now() + chrono::Duration::from_std(limit).unwrap_or_else(|_| chrono::Duration::zero());
+    let (commands, mut requests) = mpsc::unbounded_channel();
+    ctx.set_debug_session(Some(DebugHandle { session_id: session_id.clone(), commands })).await;
+
+    let ready = OutgoingMessage::DebugSession {
+        job_id: job.job_id.clone(),
+        session_id: session_id.clone(),
+        step_id: step.step_id.clone(),
+        runner_id: run.settings.runner.id.clone(),
+        expires_at,
+    };
+    if let Err(e) = run.ws.send(&ready).await {
+        warn!("Failed to open debug session for job {}: {}", job.job_id, e);
+        ctx.set_debug_session(None).await;
+        return;
+    }
+    info!("Keeping job {} for debugging until {}", job.job_id, expires_at);
+    let notice = format!("Job kept for debugging until {}", expires_at.to_rfc3339());
+    let _ = run.log_streamer.add(&step.step_id, &notice, "system").await;
+
+    let masker = SecretMasker::new(job.secrets.values().cloned());
+    let deadline = tokio::time::Instant::now() + limit;
     let mut cancel_rx = ctx.subscribe();
+    let mut executed = 0;
+    let reason = loop {
+        let request = tokio::select! {
+            request = requests.recv() => request,
+            _ = tokio::time::sleep_until(deadline) => break "expired",
+            _ = cancel_rx.recv() => break "cancelled",
+        };
+        let Some(DebugCommand::Exec { request_id, command }) = request else {
+            break "ended";
+        };
 
-    let execution_result = tokio::select! {
-        result = execute_steps_with_timeout(
-            ws.clone(),
-            &executor,
-            &job,
-            &workspace_path,
-            &settings,
-            ctx.clone(),
-            log_streamer.clone(),
-            job_timeout,
-        ) => result,
-        _ = cancel_rx.recv() => {
-            warn!("Job {} cancelled during execution", job.job_id);
-            Err(anyhow::anyhow!("Job cancelled"))
+        // Commands are logged with the step, so the session leaves a trail
+        executed += 1;
+        let notice = format!("Debug command: {}", masker.mask(&command));
+        let _ = run.log_streamer.add(&step.step_id, &notice, "system").await;
+
+        let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+        let exec = debug_exec_context(step, &command, executed, remaining);
+        let (output_tx, _output_rx) = mpsc::unbounded_channel();
+        let result = timeout(remaining, async {
+            run.executor.prepare(&exec).await?;
+            run.executor.execute(&exec, &output_tx).await
+        }).await;
+
+        let (exit_code, output, error) = match result {
+            Ok(Ok(result)) if result.timed_out => (None, result.output, Some("Timed out".to_string())),
+            Ok(Ok(result)) => (Some(result.exit_code), result.output, None),
+            Ok(Err(e)) => (None, String::new(), Some(format!("{:#}", e))),
+            Err(_) => (None, String::new(), Some("Timed out".to_string())),
+        };
+        let message = OutgoingMessage::DebugOutput {
+            job_id: job.job_id.clone(),
+            session_id: session_id.clone(),
+            request_id,
+            exit_code,
+            output: truncate(&masker.mask(&output), DEBUG_OUTPUT_MAX_BYTES),
+            error: error.map(|e| masker.mask(&e)),
+        };
+        if let Err(e) = run.ws.send(&message).await {
+            warn!("Failed to send debug output of job {}: {}", job.job_id, e);
         }
     };
 
-    // Determine final status
-    let (job_status, job_outputs) = match execution_result {
-        Ok(outputs) => (JobStatus::Success, outputs),
-        Err(e) => {
-            if ctx.is_cancelled().await {
-                (JobStatus::Cancelled, HashMap::new())
-            } else if e.to_string().contains("timeout") {
-                (JobStatus::Timeout, HashMap::new())
-            } else {
-                let mut outputs = HashMap::new();
-                outputs.insert("error".to_string(), e.to_string());
-                (JobStatus::Failed, outputs)
-            }
-        }
+    ctx.set_debug_session(None).await;
+    info!("Debug session of job {} {} after {} commands", job.job_id, reason, executed);
+    let closed = OutgoingMessage::DebugSessionClosed {
+        job_id: job.job_id.clone(),
+        session_id,
+        reason: reason.to_string(),
     };
-
-    // Flush remaining logs
-    if let Err(e) = log_streamer.flush().await {
-        warn!("Failed to flush final logs: {}", e);
+    if let Err(e) = run.ws.send(&closed).await {
+        warn!("Failed to report the end of the debug session of job {}: {}", job.job_id, e);
     }
+}
 
-    // Update job status
-    ws.send_status_update(
-        "job",
-        &job.job_id,
-        &job_status.to_string(),
-        None,
-        job_outputs.clone(),
-    ).await?;
+/// Run the `on_cancel` scripts of the steps executing when the job was
+/// cancelled, each within its time window
+async fn run_cancel_handlers(run: &JobRun<'_>) {
+    let handlers = std::mem::take(&mut *run.cancel_handlers.lock().unwrap_or_else(|e| e.into_inner()));
+    futures_util::future::join_all(
+        handlers.into_iter().map(|(step_id, handler)| run_cancel_handler(run, step_id, handler)),
+    ).await;
+}
 
-    info!("Job {} completed with status: {}", job.job_id, job_status);
+async fn run_cancel_handler(run: &JobRun<'_>, step_id: String, handler: ExecutionContext) {
+    let Some(step) = run.job.steps.iter().find(|s| s.step_id == step_id) else {
+        return;
+    };
 
-    // Cleanup workspace
-    if let Err(e) = tokio::fs::remove_dir_all(&workspace_path).await {
-        warn!("Failed to cleanup workspace: {}", e);
+    info!("Running cancel handler of step {} for up to {:?}", step.name, handler.timeout);
+    let notice = format!("Job cancelled, running on_cancel for up to {:?}", handler.timeout);
+    let _ = run.log_streamer.add(&step_id, &notice, "system").await;
+
+    let (output_tx, output_rx) = mpsc::unbounded_channel();
+    let fetched = run.job.jit_secrets.iter().filter_map(|name| handler.environment.get(name));
+    let masker = SecretMasker::new(run.job.secrets.values().chain(fetched).cloned());
+    let forwarder = spawn_output_forwarder(run, step, masker, output_rx);
+    // Executors enforce the window themselves; this covers a stuck prepare
+    let executed = timeout(handler.timeout, async {
+        run.executor.prepare(&handler).await?;
+        run.executor.execute(&handler, &output_tx).await
+    }).await;
+    drop(output_tx);
+    let _ = forwarder.await;
+
+    let (message, level) = match executed {
+        Ok(Ok(result)) if result.timed_out => ("on_cancel timed out".to_string(), "warn"),
+        Ok(Ok(result)) if result.success() => ("on_cancel finished".to_string(), "system"),
+        Ok(Ok(result)) => (format!("on_cancel failed with exit code {}", result.exit_code), "warn"),
+        Ok(Err(e)) => (format!("on_cancel failed: {}", e), "warn"),
+        Err(_) => ("on_cancel timed out".to_string(), "warn"),
+    };
+    info!("Cancel handler of step {}: {}", step.name, message);
+    let _ = run.log_streamer.add(&step_id, &message, level).await;
+    if let Err(e) = run.executor.cleanup(&handler).await {
+        warn!("Failed to clean up cancel handler of step {}: {}", step.name, e);
     }
+}
 
-    // Cleanup log streamer
-    log_manager.remove(&job.job_id).await;
+/// Forward a step's output lines to its log as they arrive.
+///
+/// Lines are masked one at a time, so a secret spanning lines is only
+/// masked in the step's outputs and failure tail.
+fn spawn_output_forwarder(
+    run: &JobRun<'_>,
+    step: &StepSpec,
+    masker: SecretMasker,
+    mut lines: mpsc::UnboundedReceiver<OutputLine>,
+) -> tokio::task::JoinHandle<()> {
+    let log_streamer = run.log_streamer.clone();
+    let step_id = step.step_id.clone();
+    let interval = Duration::from_millis(run.settings.logging.flush_interval_ms.max(1));
+    let mut collapser = run.settings.logging.collapse_repeated_lines.then(RepeatCollapser::default);
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            tokio::select! {
+                line = lines.recv() => {
+                    let Some(line) = line else {
+                        break;
+                    };
+                    match collapser.as_mut() {
+                        Some(collapser) => {
+                            for line in collapser.push(line) {
+                                log_output_line(&log_streamer, &step_id, &masker, line).await;
+                            }
+                        }
+                        None => log_output_line(&log_streamer, &step_id, &masker, line).await,
+                    }
+                }
+                _ = ticker.tick() => {
+                    if let Some(summary) = collapser.as_mut().and_then(RepeatCollapser::flush) {
+                        log_output_line(&log_streamer, &step_id, &masker, summary).await;
+                    }
+                }
+            }
+        }
 
-    if job_status == JobStatus::Success {
-        Ok(())
-    } else {
-        Err(anyhow::anyhow!("Job failed with status: {}", job_status))
+        if let Some(summary) = collapser.as_mut().and_then(RepeatCollapser::flush) {
+            log_output_line(&log_streamer, &step_id, &masker, summary).await;
+        }
+    })
+}
+
+async fn log_output_line(log_streamer: &LogStreamer, step_id: &str, masker: &SecretMasker, mut line: OutputLine) {
+    // Mask secrets, which shell tracing in particular would expose
+    line.text = masker.mask(&line.text);
+    if let Err(e) = log_streamer.add_output(step_id, line).await {
+        warn!("Failed to log output of step {}: {}", step_id, e);
     }
 }
 
-/// Execute all steps with timeout
-async fn execute_steps_with_timeout(
-    ws: Arc<WebSocketClient>,
-    executor: &Box<dyn Executor>,
-    job: &JobSpec,
-    workspace_path: &PathBuf,
-    settings: &Settings,
-    ctx: Arc<JobContext>,
-    log_streamer: Arc<LogStreamer>,
-    job_timeout: Duration,
-) -> Result<HashMap<String, String>> {
-    let start = Instant::now();
-    let mut job_outputs = HashMap::new();
+/// Report a step that failed with an error before producing a result
+async fn report_step_error(
+    run: &JobRun<'_>,
+    step: &StepSpec,
+    error: &anyhow::Error,
+    timings: &PhaseTimings,
+    started_at: DateTime<Utc>,
+) -> Result<()> {
+    let mut outputs = timings.to_outputs();
+    outputs.insert("error".to_string(), error.to_string());
 
-    for step in &job.steps {
-        // Check job timeout
-        if start.elapsed() > job_timeout {
-            error!("Job timeout exceeded");
-            return Err(anyhow::anyhow!("Job timeout exceeded"));
-        }
+    let status = if run.cancel.is_cancelled() { StepStatus::Cancelled } else { StepStatus::Failed };
+    emit_step_finished(run, step, status, None, started_at);
 
-        // Check cancellation
-        if ctx.is_cancelled().await {
-            return Err(anyhow::anyhow!("Job cancelled"));
-        }
-
-        // Calculate remaining time for step
-        let remaining = job_timeout.saturating_sub(start.elapsed());
-        let step_timeout = Duration::from_secs(
-            step.timeout_minutes.max(settings.job.default_step_timeout_minutes) as u64 * 60
-        ).min(remaining);
-
-        match execute_step_with_timeout(
-            ws.clone(),
-            executor,
-            job,
-            step,
-            workspace_path,
-            step_timeout,
-            log_streamer.clone(),
-        ).await {
-            Ok(outputs) => {
-                job_outputs.extend(outputs);
-            }
-            Err(e) => {
-                error!("Step {} failed: {}", step.name, e);
-                if !step.continue_on_error {
-                    return Err(e);
-                }
-            }
+    run.ws.send_status_update(
+        "step",
+        &step.step_id,
+        &status.to_string(),
+        None,
+        outputs,
+        StatusMeta::finished(run.attempt, Some(started_at)),
+    ).await
+}
+
+/// Report the steps that did not stop in time for the cancelled job as
+/// cancelled
+async fn report_cancelled_steps(run: &JobRun<'_>) {
+    let running = std::mem::take(&mut *run.running_steps.lock().unwrap_or_else(|e| e.into_inner()));
+    for (step_id, started_at) in running {
+        let Some(step) = run.job.steps.iter().find(|s| s.step_id == step_id) else {
+            continue;
+        };
+        let status = run.ws.send_status_update(
+            "step",
+            &step_id,
+            &StepStatus::Cancelled.to_string(),
+            None,
+            HashMap::new(),
+            StatusMeta::finished(run.attempt, Some(started_at)),
+        ).await;
+        if let Err(e) = status {
+            warn!("Failed to report step {} as cancelled: {}", step_id, e);
         }
+        emit_step_finished(run, step, StepStatus::Cancelled, None, started_at);
     }
-
-    Ok(job_outputs)
 }
 
-/// Execute a single step with timeout
-async fn execute_step_with_timeout(
-    ws: Arc<WebSocketClient>,
-    executor: &Box<dyn Executor>,
-    job: &JobSpec,
+/// Report a step whose phase exceeded its `budget`
+async fn report_phase_timeout(
+    run: &JobRun<'_>,
     step: &StepSpec,
-    workspace_path: &PathBuf,
-    step_timeout: Duration,
-    log_streamer: Arc<LogStreamer>,
-) -> Result<HashMap<String, String>> {
-    info!("Executing step: {} ({})", step.name, step.step_id);
+    phase: ExecutionPhase,
+    budget: Duration,
+    timings: &PhaseTimings,
+    started_at: DateTime<Utc>,
+) -> Result<(StepStatus, HashMap<String, String>)> {
+    warn!("Step {} timed out in {} phase after {:?}", step.name, phase, budget);
 
-    // Update step status to running
-    ws.send_status_update(
+    let mut outputs = timings.to_outputs();
+    outputs.insert("timed_out_phase".to_string(), phase.to_string());
+
+    run.ws.send_status_update(
         "step",
         &step.step_id,
-        "running",
+        &StepStatus::Timeout.to_string(),
         None,
-        HashMap::new(),
+        outputs,
+        StatusMeta::finished(run.attempt, Some(started_at)),
     ).await?;
+    emit_step_finished(run, step, StepStatus::Timeout, None, started_at);
 
-    // Build environment
-    let mut env = job.environment.clone();
-    env.extend(step.env.clone());
+    Err(RunnerError::Timeout(format!("Step timeout in {} phase after {:?}", phase, budget)).into())
+}
 
-    // Add secrets (masked in logs)
-    for (key, value) in &job.secrets {
-        env.insert(key.clone(), value.clone());
+/// Publish the end of a step on the event bus
+fn emit_step_finished(
+    run: &JobRun<'_>,
+    step: &StepSpec,
+    status: StepStatus,
+    exit_code: Option<i32>,
+    started_at: DateTime<Utc>,
+) {
+    if let Some(code) = exit_code {
+        run.exit_codes.lock().unwrap_or_else(|e| e.into_inner()).insert(step.step_id.clone(), code);
     }
+    run.running_steps.lock().unwrap_or_else(|e| e.into_inner()).remove(&step.step_id);
+    run.events.emit(RunnerEvent::StepFinished {
+        job_id: run.job.job_id.clone(),
+        step_id: step.step_id.clone(),
+        status: status.to_string(),
+        exit_code,
+        duration_ms: elapsed_ms(started_at),
+    });
+}
 
-    // Build execution context
-    let working_dir = if let Some(ref wd) = step.working_directory {
-        workspace_path.join(wd)
-    } else {
-        workspace_path.clone()
-    };
+fn elapsed_ms(since: DateTime<Utc>) -> u64 {
+    (Utc::now() - since).num_milliseconds().max(0) as u64
+}
 
-    let command = step.run.clone().unwrap_or_default();
+/// Format a command as a log line, one `$ ` prefixed line per script line
+fn format_command_echo(command: &str) -> String {
+    command
+        .lines()
+        .map(|line| format!("$ {}", line))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
 
-    let ctx = ExecutionContext {
-        job_id: job.job_id.clone(),
-        step_id: step.step_id.clone(),
-        command,
-        shell: step.shell.clone(),
-        working_directory: working_dir,
-        environment: env,
-        timeout: step_timeout,
-        container_image: job.container.as_ref().map(|c| c.image.clone()),
-        container_options: None,
-    };
+/// Secrets visible to a step: its allowlist, or the runner default without one
+pub(super) fn step_secrets(
+    secrets: &HashMap<String, String>,
+    allowlist: Option<&[String]>,
+    default: StepSecrets,
+) -> HashMap<String, String> {
+    match (allowlist, default) {
+        (Some(names), _) => secrets
+            .iter()
+            .filter(|(name, _)| names.contains(name))
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect(),
+        (None, StepSecrets::All) => secrets.clone(),
+        (None, StepSecrets::None) => HashMap::new(),
+    }
+}
 
-    // Prepare and execute with timeout
-    executor.prepare(&ctx).await?;
+/// Names of the job's just-in-time secrets a step may see, chosen like
+/// [`step_secrets`]
+pub(super) fn step_jit_secrets<'a>(
+    names: &'a [String],
+    allowlist: Option<&[String]>,
+    default: StepSecrets,
+) -> Vec<&'a str> {
+    names
+        .iter()
+        .filter(|name| allowlist.map_or(default == StepSecrets::All, |allowed| allowed.contains(name)))
+        .map(String::as_str)
+        .collect()
+}
 
-    let result = match timeout(step_timeout, executor.execute(&ctx)).await {
-        Ok(Ok(result)) => result,
-        Ok(Err(e)) => {
-            // Execution error
-            ws.send_status_update(
-                "step",
-                &step.step_id,
-                "failed",
-                None,
-                HashMap::from([("error".to_string(), e.to_string())]),
-            ).await?;
-            return Err(e);
-        }
-        Err(_) => {
-            // Timeout
-            ws.send_status_update(
-                "step",
-                &step.step_id,
-                "timeout",
-                None,
-                HashMap::new(),
-            ).await?;
-            return Err(anyhow::anyhow!("Step timeout after {:?}", step_timeout));
+fn cache_store(settings: &Settings) -> CacheStore {
+    CacheStore::new(settings.workspace.cache_path.clone(), settings.workspace.cache_max_bytes)
+}
+
+/// Restore a step's cache, returning whether it was a hit.
+///
+/// Cache failures are logged and treated as misses.
+async fn restore_step_cache(run: &JobRun<'_>, step: &StepSpec, key: &str, targets: &[PathBuf]) -> Result<bool> {
+    let start = Instant::now();
+    let restored = cache_store(run.settings).restore(key, run.workspace_path, targets).await;
+    run.timeline.record(format!("restore {}", key), "cache", start);
+
+    let (hit, notice, level) = match restored {
+        Ok(true) => (true, format!("Restored cache '{}'", key), "system"),
+        Ok(false) => (false, format!("No cache found for '{}'", key), "system"),
+        Err(e) => {
+            warn!("Failed to restore cache '{}' for step {}: {}", key, step.step_id, e);
+            (false, format!("Failed to restore cache '{}': {}", key, e), "warn")
         }
     };
+    run.log_streamer.add(&step.step_id, &notice, level).await?;
 
-    // Send logs using streamer
-    if !result.stdout.is_empty() {
-        log_streamer.add(&step.step_id, &result.stdout, "info").await?;
-    }
-    if !result.stderr.is_empty() {
-        log_streamer.add(&step.step_id, &result.stderr, "error").await?;
+    if hit {
+        run.events.emit(RunnerEvent::CacheHit {
+            job_id: run.job.job_id.clone(),
+            key: key.to_string(),
+        });
     }
+    Ok(hit)
+}
 
-    // Flush logs for this step
-    log_streamer.flush().await?;
-
-    // Parse outputs (GitHub Actions style)
-    let outputs = parse_outputs(&result.stdout);
+/// Save a step's cache after a miss; failures are logged only
+async fn save_step_cache(
+    run: &JobRun<'_>,
+    step: &StepSpec,
+    key: &str,
+    paths: &[String],
+    sources: &[PathBuf],
+) -> Result<()> {
+    let start = Instant::now();
+    let saved = cache_store(run.settings).save(key, paths, run.workspace_path, sources).await;
+    run.timeline.record(format!("save {}", key), "cache", start);
 
-    // Determine status
-    let status = if result.timed_out {
-        StepStatus::Timeout
-    } else if result.success() {
-        StepStatus::Success
-    } else {
-        StepStatus::Failed
+    let (notice, level) = match saved {
+        Ok(Some(entry)) => (format!("Saved cache '{}' ({} bytes)", key, entry.size_bytes), "system"),
+        Ok(None) => return Ok(()),
+        Err(e) => {
+            warn!("Failed to save cache '{}' for step {}: {}", key, step.step_id, e);
+            (format!("Failed to save cache '{}': {}", key, e), "warn")
+        }
     };
+    run.log_streamer.add(&step.step_id, &notice, level).await?;
+    Ok(())
+}
 
-    // Update step status
-    ws.send_status_update(
-        "step",
-        &step.step_id,
-        &status.to_string(),
-        Some(result.exit_code),
-        outputs.clone(),
-    ).await?;
-
-    // Cleanup
-    executor.cleanup(&ctx).await?;
+/// Container options for untrusted jobs; nothing comes from the job spec
+pub(super) fn untrusted_container_options(config: &UntrustedConfig) -> ContainerOptions {
+    ContainerOptions {
+        network_mode: Some(config.network_mode.clone()),
+        memory_limit: Some(config.memory_limit),
+        cpu_limit: Some(config.cpu_limit),
+        ..Default::default()
+    }
+}
 
-    if !result.success() && !step.continue_on_error {
-        anyhow::bail!("Step failed with exit code {}", result.exit_code);
+/// Container options for a job's container spec, applying its profile;
+/// cache volumes are scoped to the job's `repository`
+pub(super) fn container_options(
+    spec: &ContainerSpec,
+    repository: Option<&str>,
+    docker: &DockerConfig,
+) -> Result<ContainerOptions> {
+    let mut options = ContainerOptions {
+        mode: spec.mode.unwrap_or(docker.container_mode),
+        devices: spec.devices.clone(),
+        cache_volumes: spec.caches.iter().map(|cache| CacheVolume::new(cache, repository)).collect(),
+        ..Default::default()
+    };
+    if let Some(ref profile) = spec.profile {
+        apply_profile(profile, &docker.kvm, kvm_available(), &mut options)?;
     }
+    Ok(options)
+}
 
-    Ok(outputs)
+/// Docker container a step runs in, if any; untrusted jobs always get a
+/// container per step
+fn step_container_name(job: &JobSpec, step: &StepSpec, docker: &DockerConfig) -> Option<String> {
+    let spec = job.container.as_ref()?;
+    let mode = if job.untrusted { ContainerMode::PerStep } else { spec.mode.unwrap_or(docker.container_mode) };
+    Some(match mode {
+        ContainerMode::PerStep => DockerExecutor::container_name(&job.job_id, &step.step_id),
+        ContainerMode::PerJob => DockerExecutor::job_container_name(&job.job_id),
+    })
 }
 
-/// Parse GitHub Actions style outputs from stdout
-fn parse_outputs(stdout: &str) -> HashMap<String, String> {
-    let mut outputs = HashMap::new();
+/// Prepend `set -x` to multi-line scripts run by POSIX-style shells
+fn inject_trace(command: &str, shell: &str) -> String {
+    let program = shell.split_whitespace().next().unwrap_or_default();
+    let is_posix = matches!(program, "bash" | "sh" | "zsh");
 
-    for line in stdout.lines() {
-        // Parse ::set-output name=key::value format
-        if line.starts_with("::set-output name=") {
-            if let Some(rest) = line.strip_prefix("::set-output name=") {
-                if let Some((name, value)) = rest.split_once("::") {
-                    outputs.insert(name.to_string(), value.to_string());
-                }
-            }
-        }
+    if is_posix && command.trim().contains('\n') {
+        format!("set -x\n{}", command)
+    } else {
+        command.to_string()
+    }
+}
 
-        // Parse GITHUB_OUTPUT style: key=value (for multi-line, use delimiter)
-        if line.contains('=') && !line.starts_with("::") {
-            if let Some((key, value)) = line.split_once('=') {
-                let key = key.trim();
-                let value = value.trim();
-                if !key.is_empty() && !key.contains(' ') {
-                    outputs.insert(key.to_string(), value.to_string());
-                }
-            }
-        }
+/// Last `max_lines` non-empty lines of `text`, capped to `max_bytes` from the end
+fn log_tail(text: &str, max_lines: usize, max_bytes: usize) -> String {
+    let lines: Vec<&str> = text.lines().filter(|l| !l.trim().is_empty()).collect();
+    let tail = lines[lines.len().saturating_sub(max_lines)..].join("\n");
+
+    if tail.len() <= max_bytes {
+        return tail;
     }
 
-    outputs
+    // Cut at a char boundary so the snippet stays valid UTF-8
+    let mut cut = tail.len() - max_bytes;
+    while !tail.is_char_boundary(cut) {
+        cut += 1;
+    }
+    tail[cut..].to_string()
 }
 
 // ============================================================================
@@ -877,20 +3848,101 @@ fn parse_outputs(stdout: &str) -> HashMap<String, String> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::client::JobSpecBuilder;
+    use crate::executor::step_context;
+
+    #[test]
+    fn test_job_timeout_cap() {
+        let mut config = JobConfig {
+            default_timeout_minutes: 60,
+            ..JobConfig::default()
+        };
+        assert_eq!(job_timeout(30, &config), Duration::from_secs(3600));
+        assert_eq!(job_timeout(600, &config), Duration::from_secs(36000));
+
+        config.max_job_duration_minutes = 120;
+        assert_eq!(job_timeout(600, &config), Duration::from_secs(7200));
+        assert_eq!(job_timeout(90, &config), Duration::from_secs(5400));
+    }
+
+    #[test]
+    fn test_warning_delay() {
+        assert_eq!(warning_delay(Duration::from_secs(600), 60), Some(Duration::from_secs(540)));
+        assert_eq!(warning_delay(Duration::from_secs(30), 60), None);
+        assert_eq!(warning_delay(Duration::from_secs(600), 0), None);
+    }
+
+    #[test]
+    fn test_timeout_warning_delay() {
+        assert_eq!(timeout_warning_delay(Duration::from_secs(600), 90), Some(Duration::from_secs(540)));
+        assert_eq!(timeout_warning_delay(Duration::from_secs(600), 0), None);
+        assert_eq!(timeout_warning_delay(Duration::from_secs(600), 100), None);
+        assert_eq!(timeout_warning_delay(Duration::ZERO, 50), None);
+    }
+
+    #[test]
+    fn test_format_command_echo() {
+        assert_eq!(format_command_echo("make build"), "$ make build");
+        assert_eq!(format_command_echo("cd app\nnpm test"), "$ cd app\n$ npm test");
+    }
+
+    #[test]
+    fn test_inject_trace() {
+        assert_eq!(inject_trace("cd app\nmake", "bash"), "set -x\ncd app\nmake");
+        assert_eq!(inject_trace("make\n", "sh"), "make\n");
+        assert_eq!(inject_trace("cd app\nmake", "pwsh"), "cd app\nmake");
+    }
+
+    #[test]
+    fn test_cancel_handler_context() {
+        let mut step = step_context(Path::new("/tmp/job-1"), "terraform apply", Duration::from_secs(3600));
+        step.container_image = Some("hashicorp/terraform".into());
+        step.warning_signal_after = Some(Duration::from_secs(3540));
+        step.environment.insert("TF_WORKSPACE".to_string(), "ci".to_string());
+
+        let handler = cancel_handler_context(&step, "terraform force-unlock", Duration::from_secs(30));
+        assert_eq!(handler.step_id, format!("{}-on-cancel", step.step_id));
+        assert_eq!(handler.command, "terraform force-unlock");
+        assert_eq!(handler.timeout, Duration::from_secs(30));
+        assert_eq!(handler.warning_signal_after, None);
+        assert_eq!(handler.working_directory, step.working_directory);
+        assert_eq!(handler.container_image, step.container_image);
+        assert_eq!(handler.environment.get("TF_WORKSPACE").map(String::as_str), Some("ci"));
+        assert_eq!(handler.environment.get("MUELSYSE_CANCELLED").map(String::as_str), Some("true"));
+
+        step.cancel.cancel();
+        assert!(!handler.cancel.is_cancelled());
+    }
 
     #[test]
-    fn test_parse_outputs() {
-        let stdout = r#"
-Hello World
-::set-output name=result::success
-::set-output name=count::42
-BUILD_ID=123
-"#;
+    fn test_step_secrets() {
+        let secrets = HashMap::from([
+            ("DEPLOY_KEY".to_string(), "k".to_string()),
+            ("NPM_TOKEN".to_string(), "t".to_string()),
+        ]);
+        let allowlist = vec!["NPM_TOKEN".to_string(), "UNDEFINED".to_string()];
+
+        let scoped = step_secrets(&secrets, Some(&allowlist), StepSecrets::All);
+        assert_eq!(scoped, HashMap::from([("NPM_TOKEN".to_string(), "t".to_string())]));
+        assert!(step_secrets(&secrets, Some(&[]), StepSecrets::All).is_empty());
+        assert_eq!(step_secrets(&secrets, None, StepSecrets::All), secrets);
+        assert!(step_secrets(&secrets, None, StepSecrets::None).is_empty());
+
+        let jit = vec!["DEPLOY_KEY".to_string(), "NPM_TOKEN".to_string()];
+        assert_eq!(step_jit_secrets(&jit, Some(&allowlist), StepSecrets::None), vec!["NPM_TOKEN"]);
+        assert_eq!(step_jit_secrets(&jit, None, StepSecrets::All), vec!["DEPLOY_KEY", "NPM_TOKEN"]);
+        assert!(step_jit_secrets(&jit, None, StepSecrets::None).is_empty());
+    }
 
-        let outputs = parse_outputs(stdout);
-        assert_eq!(outputs.get("result"), Some(&"success".to_string()));
-        assert_eq!(outputs.get("count"), Some(&"42".to_string()));
-        assert_eq!(outputs.get("BUILD_ID"), Some(&"123".to_string()));
+    #[test]
+    fn test_log_tail() {
+        let text = "line 1\nline 2\n\nline 3\nline 4\n";
+
+        assert_eq!(log_tail(text, 2, 1024), "line 3\nline 4");
+        assert_eq!(log_tail(text, 10, 1024), "line 1\nline 2\nline 3\nline 4");
+        assert_eq!(log_tail(text, 2, 6), "line 4");
+        assert_eq!(log_tail("ééé", 1, 3), "é");
+        assert_eq!(log_tail("", 5, 1024), "");
     }
 
     #[test]
@@ -902,19 +3954,87 @@ BUILD_ID=123
         assert_eq!(JobStatus::Cancelled.to_string(), "cancelled");
     }
 
+    #[tokio::test]
+    async fn test_job_context_cancel() {
+        let ctx = JobContext::new("job-1".to_string());
+        assert!(!ctx.is_cancelled().await && ctx.cancelled_at().await.is_none());
+
+        ctx.cancel().await;
+        let requested_at = ctx.cancelled_at().await.unwrap();
+        ctx.cancel().await;
+        assert!(ctx.is_cancelled().await);
+        assert_eq!(ctx.cancelled_at().await, Some(requested_at));
+        assert_eq!(StepStatus::Cancelled.to_string(), "cancelled");
+    }
+
     #[test]
     fn test_retry_config_from_job_config() {
         let job_config = JobConfig {
             default_timeout_minutes: 60,
             default_step_timeout_minutes: 10,
+            prepare_timeout_secs: 900,
+            collect_timeout_secs: 300,
             max_retries: 5,
             retry_delay_secs: 10,
             shutdown_timeout_secs: 300,
+            ..JobConfig::default()
         };
 
         let retry_config = RetryConfig::from(&job_config);
         assert_eq!(retry_config.max_attempts, 5);
         assert_eq!(retry_config.delay_secs, 10);
+        assert!(retry_config.retries(&RunnerError::InfraError("Failed to pull image".into())));
+        assert!(!retry_config.retries(&RunnerError::ExecutorFailure { exit_code: 1 }));
+        assert!(!retry_config.retries(&RunnerError::Timeout("Step timed out".into())));
+
+        // A job's own `retry_on` replaces the runner's
+        let mut job = JobSpecBuilder::new("job-1")
+            .with("retry-on", serde_json::json!(["executor_failure", "timeout"]))
+            .build();
+        let retry_config = RetryConfig::for_job(&job_config, &job);
+        assert!(retry_config.retries(&RunnerError::ExecutorFailure { exit_code: 1 }));
+        assert!(retry_config.retries(&RunnerError::Timeout("Step timed out".into())));
+        assert!(!retry_config.retries(&RunnerError::InfraError("Failed to pull image".into())));
+        assert!(!retry_config.retries(&RunnerError::Cancelled("Job cancelled".into())));
+
+        job.retry_on = Some(Vec::new());
+        assert!(!RetryConfig::for_job(&job_config, &job).retries(&RunnerError::InfraError("disk full".into())));
+    }
+
+    #[test]
+    fn test_phase_timeouts_capped() {
+        let job_config = JobConfig {
+            prepare_timeout_secs: 600,
+            collect_timeout_secs: 60,
+            ..JobConfig::default()
+        };
+
+        let phases = PhaseTimeouts::new(&job_config, Duration::from_secs(3600))
+            .capped(Duration::from_secs(120));
+        let deadline = phases.deadline.unwrap();
+        let after = |secs| deadline - Duration::from_secs(120) + Duration::from_secs(secs);
+
+        // Each phase gets what the ones before it left
+        assert_eq!(phases.get_at(ExecutionPhase::Prepare, after(0)), Duration::from_secs(120));
+        assert_eq!(phases.get_at(ExecutionPhase::Execute, after(30)), Duration::from_secs(90));
+        assert_eq!(phases.get_at(ExecutionPhase::Collect, after(40)), Duration::from_secs(60));
+        assert_eq!(phases.get_at(ExecutionPhase::Collect, after(100)), Duration::from_secs(20));
+        assert_eq!(phases.get_at(ExecutionPhase::Collect, after(150)), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_phase_timings_outputs() {
+        let mut timings = PhaseTimings::default();
+        timings.record(ExecutionPhase::Prepare, Duration::from_millis(1500));
+        timings.record(ExecutionPhase::Execute, Duration::from_millis(20));
+
+        assert_eq!(timings.get(ExecutionPhase::Prepare), Some(Duration::from_millis(1500)));
+        assert_eq!(timings.get(ExecutionPhase::Collect), None);
+
+        let outputs = timings.to_outputs();
+        assert_eq!(outputs.get("phase_prepare_ms"), Some(&"1500".to_string()));
+        assert_eq!(outputs.get("phase_execute_ms"), Some(&"20".to_string()));
+        assert!(!outputs.contains_key("phase_collect_ms"));
     }
 
     #[tokio::test]
@@ -927,4 +4047,50 @@ BUILD_ID=123
 
         assert!(ctx.is_cancelled().await);
     }
+
+    #[test]
+    fn test_step_container_name() {
+        let mut job = JobSpecBuilder::new("job-1")
+            .step("s1", "test", "true")
+            .with("container", serde_json::json!({ "image": "rust:1", "mode": "per-job" }))
+            .build();
+        let docker = DockerConfig::default();
+        let step = job.steps[0].clone();
+
+        assert_eq!(step_container_name(&job, &step, &docker).as_deref(), Some("muelsyse-job-1-job"));
+
+        job.untrusted = true;
+        assert_eq!(step_container_name(&job, &step, &docker).as_deref(), Some("muelsyse-job-1-s1"));
+
+        job.untrusted = false;
+        job.container.as_mut().unwrap().mode = None;
+        assert_eq!(step_container_name(&job, &step, &docker).as_deref(), Some("muelsyse-job-1-s1"));
+
+        job.container = None;
+        assert_eq!(step_container_name(&job, &step, &docker), None);
+    }
+
+    #[tokio::test]
+    async fn test_job_context_progress() {
+        let job = JobSpecBuilder::new("job-1")
+            .step("s1", "checkout", "true")
+            .step("s2", "test", "true")
+            .build();
+        let ctx = JobContext::new(job.job_id.clone());
+        assert_eq!(ctx.snapshot().await.steps_total, 0);
+
+        ctx.start_attempt(&job, 2, Utc::now()).await;
+        ctx.step_finished().await;
+        ctx.step_started(&job.steps[1]).await;
+
+        let snapshot = ctx.snapshot().await;
+        assert_eq!(snapshot.name, "build");
+        assert_eq!(snapshot.attempt, 2);
+        assert_eq!(snapshot.current_step.as_deref(), Some("test"));
+        assert_eq!((snapshot.steps_completed, snapshot.steps_total), (1, 2));
+
+        // A retry starts over
+        ctx.start_attempt(&job, 3, Utc::now()).await;
+        assert_eq!(ctx.snapshot().await.steps_completed, 0);
+    }
 }
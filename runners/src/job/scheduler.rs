@@ -0,0 +1,323 @@
+//! Job slots and the pending queue
+//!
+//! Besides `max_concurrent_jobs`, `runner.concurrency` limits jobs per
+//! label: with `gpu = 1` only one job requiring `gpu` runs at a time, and
+//! a `default` entry limits the jobs requiring none of the listed labels.
+//! Pending jobs start in priority order as slots free up; one waiting for
+//! a busy label does not hold back the jobs behind it.
+//!
+//! Jobs sharing a `concurrency` group run one at a time. A newly assigned
+//! job supersedes the group's pending jobs, and with `cancel_in_progress`
+//! its running one, so only the latest run of a group is left to start.
+//!
+//! Jobs asking for `gpus` wait until that many of the host's GPUs are not
+//! held by running jobs.
+
+use std::collections::HashMap;
+
+use crate::client::JobSpec;
+
+/// Slot key of jobs requiring none of the limited labels
+const DEFAULT_SLOT: &str = "default";
+
+/// Running and pending jobs of this runner
+#[derive(Debug, Default)]
+pub struct Scheduler {
+    /// Jobs that may run at once per label
+    limits: HashMap<String, usize>,
+    /// Slot keys held by each running job
+    running: HashMap<String, Vec<String>>,
+    /// Concurrency groups of the running jobs, by job id
+    groups: HashMap<String, String>,
+    /// GPUs of the host
+    gpus: u32,
+    /// GPUs held by the running jobs that asked for any, by job id
+    gpu_jobs: HashMap<String, u32>,
+    /// Highest priority first, then by arrival
+    pending: Vec<JobSpec>,
+}
+
+impl Scheduler {
+    pub fn new(limits: HashMap<String, usize>) -> Self {
+        Self { limits, ..Default::default() }
+    }
+
+    /// Share `gpus` GPUs among the jobs asking for them
+    pub fn with_gpus(mut self, gpus: u32) -> Self {
+        self.gpus = gpus;
+        self
+    }
+
+    /// GPUs of the host
+    pub fn gpus(&self) -> u32 {
+        self.gpus
+    }
+
+    pub fn running(&self) -> u32 {
+        self.running.len() as u32
+    }
+
+    pub fn pending_len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Pending jobs in the order they will be considered
+    pub fn pending(&self) -> impl Iterator<Item = &JobSpec> {
+        self.pending.iter()
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.running.is_empty() && self.pending.is_empty()
+    }
+
+    /// Limited labels `job` counts against
+    fn slots(&self, job: &JobSpec) -> Vec<String> {
+        let mut slots: Vec<String> = job.labels
+            .iter()
+            .filter(|label| label.as_str() != DEFAULT_SLOT && self.limits.contains_key(*label))
+            .cloned()
+            .collect();
+        if slots.is_empty() && self.limits.contains_key(DEFAULT_SLOT) {
+            slots.push(DEFAULT_SLOT.to_string());
+        }
+        slots
+    }
+
+    /// Free slots per limited label, at most `capacity`; jobs requiring
+    /// only other labels are limited by `capacity` alone
+    pub fn free_slots(&self, capacity: u32) -> HashMap<String, u32> {
+        self.limits
+            .iter()
+            .map(|(label, limit)| {
+                let used = self.running.values().filter(|held| held.contains(label)).count();
+                (label.clone(), (limit.saturating_sub(used) as u32).min(capacity))
+            })
+            .collect()
+    }
+
+    /// GPUs not held by running jobs
+    pub fn free_gpus(&self) -> u32 {
+        self.gpus.saturating_sub(self.gpu_jobs.values().sum())
+    }
+
+    /// Whether `job` fits the free slots, with at most `max_running` jobs
+    pub fn can_start(&self, job: &JobSpec, max_running: u32) -> bool {
+        let group_busy = job.concurrency
+            .as_ref()
+            .is_some_and(|concurrency| self.groups.values().any(|group| *group == concurrency.group));
+        self.running() < max_running
+            && !group_busy
+            && (job.gpus == 0 || job.gpus <= self.free_gpus())
+            && self.slots(job).iter().all(|slot| {
+                let used = self.running.values().filter(|held| held.contains(slot)).count();
+                used < self.limits[slot]
+            })
+    }
+
+    /// Count `job` as running
+    pub fn start(&mut self, job: &JobSpec) {
+        let slots = self.slots(job);
+        self.running.insert(job.job_id.clone(), slots);
+        if let Some(ref concurrency) = job.concurrency {
+            self.groups.insert(job.job_id.clone(), concurrency.group.clone());
+        }
+        if job.gpus > 0 {
+            self.gpu_jobs.insert(job.job_id.clone(), job.gpus);
+        }
+    }
+
+    /// Release the slots of a finished job
+    pub fn finish(&mut self, job_id: &str) {
+        self.running.remove(job_id);
+        self.groups.remove(job_id);
+        self.gpu_jobs.remove(job_id);
+    }
+
+    /// Jobs of `job`'s concurrency group it supersedes: the pending ones,
+    /// taken out of the queue, and the ids of the running ones if it
+    /// cancels jobs in progress
+    pub fn supersede(&mut self, job: &JobSpec) -> (Vec<JobSpec>, Vec<String>) {
+        let Some(ref concurrency) = job.concurrency else {
+            return (Vec::new(), Vec::new());
+        };
+        let in_group = |other: &JobSpec| other.concurrency.as_ref().is_some_and(|c| c.group == concurrency.group);
+
+        let (pending, kept) = self.pending.drain(..).partition(|pending| in_group(pending));
+        self.pending = kept;
+        let running = match concurrency.cancel_in_progress {
+            true => self.groups
+                .iter()
+                .filter(|(_, group)| **group == concurrency.group)
+                .map(|(job_id, _)| job_id.clone())
+                .collect(),
+            false => Vec::new(),
+        };
+        (pending, running)
+    }
+
+    /// Queue `job`, returning its 1-based position
+    pub fn enqueue(&mut self, job: JobSpec) -> usize {
+        let position = self.pending
+            .iter()
+            .position(|pending| pending.priority < job.priority)
+            .unwrap_or(self.pending.len());
+        self.pending.insert(position, job);
+        position + 1
+    }
+
+    /// Take a job out of the queue
+    pub fn remove_pending(&mut self, job_id: &str) -> Option<JobSpec> {
+        let index = self.pending.iter().position(|job| job.job_id == job_id)?;
+        Some(self.pending.remove(index))
+    }
+
+    /// Take every queued job
+    pub fn drain_pending(&mut self) -> Vec<JobSpec> {
+        self.pending.drain(..).collect()
+    }
+
+    /// Start the pending jobs that fit, highest priority first
+    pub fn start_ready(&mut self, max_running: u32) -> Vec<JobSpec> {
+        let mut started = Vec::new();
+        let mut i = 0;
+        while i < self.pending.len() && self.running() < max_running {
+            if self.can_start(&self.pending[i], max_running) {
+                let job = self.pending.remove(i);
+                self.start(&job);
+                started.push(job);
+            } else {
+                i += 1;
+            }
+        }
+        started
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{ConcurrencySpec, JobSpecBuilder};
+
+    fn job(id: &str, labels: &[&str], priority: i32) -> JobSpec {
+        JobSpecBuilder::new(id)
+            .with("labels", serde_json::json!(labels))
+            .with("priority", serde_json::json!(priority))
+            .build()
+    }
+
+    fn grouped(id: &str, group: &str, cancel_in_progress: bool) -> JobSpec {
+        let mut job = job(id, &[], 0);
+        job.concurrency = Some(ConcurrencySpec { group: group.into(), cancel_in_progress });
+        job
+    }
+
+    fn ids(jobs: &[JobSpec]) -> Vec<&str> {
+        jobs.iter().map(|j| j.job_id.as_str()).collect()
+    }
+
+    #[test]
+    fn test_label_slots() {
+        let mut scheduler = Scheduler::new(HashMap::from([("gpu".to_string(), 1), ("default".to_string(), 2)]));
+        let train = job("train", &["linux", "gpu"], 0);
+        scheduler.start(&train);
+
+        // A second GPU job waits; lint jobs use the default slots
+        assert!(!scheduler.can_start(&job("eval", &["gpu"], 0), 8));
+        assert!(scheduler.can_start(&job("lint-1", &["linux"], 0), 8));
+        scheduler.start(&job("lint-1", &["linux"], 0));
+        scheduler.start(&job("lint-2", &[], 0));
+        assert!(!scheduler.can_start(&job("lint-3", &[], 0), 8));
+
+        // The overall limit still applies
+        assert!(!scheduler.can_start(&job("eval", &["gpu"], 0), 3));
+
+        assert_eq!(scheduler.free_slots(5), HashMap::from([("gpu".to_string(), 0), ("default".to_string(), 0)]));
+
+        scheduler.finish("train");
+        scheduler.finish("lint-2");
+        assert!(scheduler.can_start(&job("eval", &["gpu"], 0), 8));
+        assert_eq!(scheduler.running(), 1);
+        assert_eq!(scheduler.free_slots(5), HashMap::from([("gpu".to_string(), 1), ("default".to_string(), 1)]));
+        // Never more than the runner's free slots
+        assert_eq!(scheduler.free_slots(0)["gpu"], 0);
+    }
+
+    #[test]
+    fn test_gpus() {
+        let mut scheduler = Scheduler::default().with_gpus(4);
+        let mut train = job("train", &[], 0);
+        train.gpus = 3;
+        scheduler.start(&train);
+
+        let mut eval = job("eval", &[], 0);
+        eval.gpus = 2;
+        assert_eq!(scheduler.free_gpus(), 1);
+        assert!(!scheduler.can_start(&eval, 8));
+        eval.gpus = 1;
+        assert!(scheduler.can_start(&eval, 8));
+        assert!(scheduler.can_start(&job("lint", &[], 0), 8));
+
+        scheduler.finish("train");
+        eval.gpus = 4;
+        assert!(scheduler.can_start(&eval, 8));
+    }
+
+    #[test]
+    fn test_priority_order() {
+        let mut scheduler = Scheduler::new(HashMap::from([("gpu".to_string(), 1)]));
+        scheduler.start(&job("train", &["gpu"], 0));
+
+        assert_eq!(scheduler.enqueue(job("low", &[], -1)), 1);
+        assert_eq!(scheduler.enqueue(job("eval", &["gpu"], 5)), 1);
+        assert_eq!(scheduler.enqueue(job("lint", &[], 0)), 2);
+        assert_eq!(scheduler.enqueue(job("test", &[], 0)), 3);
+        let pending: Vec<&str> = scheduler.pending().map(|j| j.job_id.as_str()).collect();
+        assert_eq!(pending, ["eval", "lint", "test", "low"]);
+
+        // The blocked GPU job does not hold back the others
+        let started = scheduler.start_ready(3);
+        assert_eq!(ids(&started), ["lint", "test"]);
+
+        scheduler.finish("train");
+        scheduler.finish("lint");
+        assert_eq!(ids(&scheduler.start_ready(2)), ["eval"]);
+        assert_eq!(ids(&scheduler.start_ready(4)), ["low"]);
+        assert!(scheduler.start_ready(4).is_empty());
+
+        assert_eq!(scheduler.enqueue(job("docs", &[], 0)), 1);
+        assert_eq!(scheduler.remove_pending("docs").map(|j| j.job_id), Some("docs".to_string()));
+        for id in ["test", "eval", "low"] {
+            scheduler.finish(id);
+        }
+        assert!(scheduler.is_idle());
+    }
+
+    #[test]
+    fn test_concurrency_groups() {
+        let mut scheduler = Scheduler::new(HashMap::new());
+        scheduler.start(&grouped("deploy-1", "deploy-main", false));
+
+        // One job of a group runs at a time
+        assert!(!scheduler.can_start(&grouped("deploy-2", "deploy-main", false), 8));
+        assert!(scheduler.can_start(&grouped("deploy-x", "deploy-dev", false), 8));
+        scheduler.enqueue(grouped("deploy-2", "deploy-main", false));
+        scheduler.enqueue(job("lint", &[], 0));
+
+        // A newer job replaces the pending one and waits for the running one
+        let (pending, running) = scheduler.supersede(&grouped("deploy-3", "deploy-main", false));
+        assert_eq!(ids(&pending), ["deploy-2"]);
+        assert!(running.is_empty());
+        assert_eq!(scheduler.pending_len(), 1);
+
+        let (pending, running) = scheduler.supersede(&grouped("deploy-4", "deploy-main", true));
+        assert!(pending.is_empty());
+        assert_eq!(running, ["deploy-1"]);
+        let (pending, running) = scheduler.supersede(&job("test", &[], 0));
+        assert!(pending.is_empty() && running.is_empty());
+
+        scheduler.enqueue(grouped("deploy-4", "deploy-main", true));
+        assert_eq!(ids(&scheduler.start_ready(8)), ["lint"]);
+        scheduler.finish("deploy-1");
+        assert_eq!(ids(&scheduler.start_ready(8)), ["deploy-4"]);
+    }
+}
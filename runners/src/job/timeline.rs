@@ -0,0 +1,110 @@
+//! Job execution timeline
+//!
+//! Spans (steps, phases, cache operations, uploads) are recorded while a job
+//! runs and exported in the Chrome trace event format, which Perfetto and
+//! `chrome://tracing` can open.
+
+use serde_json::{json, Value};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone)]
+struct Span {
+    name: String,
+    category: &'static str,
+    start: Duration,
+    duration: Duration,
+}
+
+/// Spans recorded relative to the start of a job
+#[derive(Debug)]
+pub struct Timeline {
+    origin: Instant,
+    spans: Mutex<Vec<Span>>,
+}
+
+impl Default for Timeline {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Timeline {
+    pub fn new() -> Self {
+        Self {
+            origin: Instant::now(),
+            spans: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Record a span that started at `start` and ends now
+    pub fn record(&self, name: impl Into<String>, category: &'static str, start: Instant) {
+        let span = Span {
+            name: name.into(),
+            category,
+            start: start.saturating_duration_since(self.origin),
+            duration: start.elapsed(),
+        };
+        self.spans.lock().unwrap_or_else(|e| e.into_inner()).push(span);
+    }
+
+    /// Export as a Chrome trace; each category gets its own track
+    pub fn to_trace(&self, job_name: &str) -> Value {
+        let spans = self.spans.lock().unwrap_or_else(|e| e.into_inner()).clone();
+
+        let mut categories: Vec<&'static str> = Vec::new();
+        for span in &spans {
+            if !categories.contains(&span.category) {
+                categories.push(span.category);
+            }
+        }
+
+        let mut events = vec![json!({
+            "name": "process_name", "ph": "M", "pid": 1,
+            "args": { "name": job_name },
+        })];
+        events.extend(categories.iter().enumerate().map(|(tid, category)| json!({
+            "name": "thread_name", "ph": "M", "pid": 1, "tid": tid + 1,
+            "args": { "name": category },
+        })));
+        events.extend(spans.iter().map(|span| {
+            let tid = categories.iter().position(|c| *c == span.category).unwrap_or_default() + 1;
+            json!({
+                "name": span.name,
+                "cat": span.category,
+                "ph": "X",
+                "ts": span.start.as_micros() as u64,
+                "dur": span.duration.as_micros() as u64,
+                "pid": 1,
+                "tid": tid,
+            })
+        }));
+
+        json!({ "traceEvents": events, "displayTimeUnit": "ms" })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trace_export() {
+        let timeline = Timeline::new();
+        let start = Instant::now();
+        timeline.record("build", "step", start);
+        timeline.record("execute", "phase", start);
+        timeline.record("test", "step", Instant::now());
+
+        let trace = timeline.to_trace("ci");
+        let events = trace["traceEvents"].as_array().unwrap();
+
+        // process name, two tracks, three spans
+        assert_eq!(events.len(), 6);
+        let spans: Vec<&Value> = events.iter().filter(|e| e["ph"] == "X").collect();
+        assert_eq!(spans.len(), 3);
+        assert_eq!(spans[0]["tid"], spans[2]["tid"]);
+        assert_ne!(spans[0]["tid"], spans[1]["tid"]);
+        assert!(spans[2]["ts"].as_u64().unwrap() >= spans[0]["ts"].as_u64().unwrap());
+    }
+}
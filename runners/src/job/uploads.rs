@@ -0,0 +1,277 @@
+//! Post-completion upload retries
+//!
+//! Log batches and artifacts that could not be delivered when a job finished
+//! are persisted to a queue directory and retried in the background until a
+//! retry window expires. Queued items survive runner restarts.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+use crate::artifact::{BinaryMetadata, PackagedArtifact};
+use crate::client::LogEntry;
+
+/// An upload that failed at job completion
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum PendingUpload {
+    Logs {
+        job_id: String,
+        logs: Vec<LogEntry>,
+    },
+    Artifact {
+        job_id: String,
+        name: String,
+        /// Name to upload the archive as
+        file_name: String,
+        /// Archive moved into the queue directory
+        file: PathBuf,
+        /// Chunked upload to resume; queued by older runners without one
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        upload_id: Option<String>,
+        size_bytes: u64,
+        checksum: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        metadata: Vec<BinaryMetadata>,
+    },
+}
+
+impl PendingUpload {
+    pub fn job_id(&self) -> &str {
+        match self {
+            Self::Logs { job_id, .. } | Self::Artifact { job_id, .. } => job_id,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct QueuedUpload {
+    queued_at: DateTime<Utc>,
+    attempts: u32,
+    upload: PendingUpload,
+}
+
+/// Directory-backed queue of failed uploads
+#[derive(Debug, Clone)]
+pub struct UploadQueue {
+    dir: PathBuf,
+    window: Duration,
+}
+
+impl UploadQueue {
+    /// Items older than `window` are dropped instead of retried
+    pub fn new(dir: PathBuf, window: Duration) -> Self {
+        Self { dir, window }
+    }
+
+    /// Persist an upload for retry
+    pub async fn enqueue(&self, upload: PendingUpload) -> Result<()> {
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .context("Failed to create upload queue directory")?;
+
+        let item = QueuedUpload {
+            queued_at: Utc::now(),
+            attempts: 0,
+            upload,
+        };
+        let path = self.dir.join(format!("{}.json", uuid::Uuid::new_v4()));
+        write_item(&path, &item).await?;
+        info!("Queued {} upload of job {} for retry", kind(&item.upload), item.upload.job_id());
+        Ok(())
+    }
+
+    /// Move a staged artifact into the queue so it outlives the job's
+    /// staging; its retries resume chunked upload `upload_id`
+    pub async fn enqueue_artifact(&self, job_id: &str, artifact: &PackagedArtifact, upload_id: &str) -> Result<()> {
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .context("Failed to create upload queue directory")?;
+
+        let file_name = artifact.file_name();
+        let file = self.dir.join(format!("{}-{}", uuid::Uuid::new_v4(), file_name));
+        tokio::fs::rename(&artifact.path, &file)
+            .await
+            .context("Failed to move artifact into the upload queue")?;
+
+        self.enqueue(PendingUpload::Artifact {
+            job_id: job_id.to_string(),
+            name: artifact.name.clone(),
+            file_name,
+            file,
+            upload_id: Some(upload_id.to_string()),
+            size_bytes: artifact.size_bytes,
+            checksum: artifact.checksum.clone(),
+            metadata: artifact.metadata.clone(),
+        }).await
+    }
+
+    /// Number of queued uploads
+    pub async fn len(&self) -> usize {
+        self.items().await.len()
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        self.len().await == 0
+    }
+
+    async fn items(&self) -> Vec<PathBuf> {
+        let Ok(mut entries) = tokio::fs::read_dir(&self.dir).await else {
+            return Vec::new();
+        };
+
+        let mut items = Vec::new();
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                items.push(path);
+            }
+        }
+        items.sort();
+        items
+    }
+
+    /// Try to deliver every queued upload once, dropping expired ones.
+    ///
+    /// Returns how many uploads remain queued.
+    pub async fn retry_all<F, Fut>(&self, deliver: F) -> usize
+    where
+        F: Fn(PendingUpload) -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        let mut remaining = 0;
+
+        for path in self.items().await {
+            let item: QueuedUpload = match tokio::fs::read(&path).await.map(|c| serde_json::from_slice(&c)) {
+                Ok(Ok(item)) => item,
+                Ok(Err(e)) => {
+                    warn!("Dropping unreadable queued upload {:?}: {}", path, e);
+                    let _ = tokio::fs::remove_file(&path).await;
+                    continue;
+                }
+                Err(e) => {
+                    warn!("Failed to read queued upload {:?}: {}", path, e);
+                    continue;
+                }
+            };
+
+            let age = (Utc::now() - item.queued_at).to_std().unwrap_or_default();
+            if age > self.window {
+                warn!(
+                    "Giving up on {} upload of job {} after {} attempts",
+                    kind(&item.upload), item.upload.job_id(), item.attempts
+                );
+                remove_item(&path, &item.upload).await;
+                continue;
+            }
+
+            match deliver(item.upload.clone()).await {
+                Ok(()) => {
+                    info!("Delivered queued {} upload of job {}", kind(&item.upload), item.upload.job_id());
+                    remove_item(&path, &item.upload).await;
+                }
+                Err(e) => {
+                    debug!("Retry of {:?} failed: {}", path, e);
+                    let item = QueuedUpload { attempts: item.attempts + 1, ..item };
+                    if let Err(e) = write_item(&path, &item).await {
+                        warn!("Failed to update queued upload {:?}: {}", path, e);
+                    }
+                    remaining += 1;
+                }
+            }
+        }
+
+        remaining
+    }
+}
+
+fn kind(upload: &PendingUpload) -> &'static str {
+    match upload {
+        PendingUpload::Logs { .. } => "log",
+        PendingUpload::Artifact { .. } => "artifact",
+    }
+}
+
+async fn write_item(path: &Path, item: &QueuedUpload) -> Result<()> {
+    // Write to a sibling file and rename so the worker never reads partial JSON
+    let tmp_path = path.with_extension("tmp");
+    tokio::fs::write(&tmp_path, serde_json::to_vec(item)?)
+        .await
+        .context("Failed to write queued upload")?;
+    tokio::fs::rename(&tmp_path, path)
+        .await
+        .context("Failed to write queued upload")
+}
+
+async fn remove_item(path: &Path, upload: &PendingUpload) {
+    if let PendingUpload::Artifact { ref file, .. } = upload {
+        let _ = tokio::fs::remove_file(file).await;
+    }
+    if let Err(e) = tokio::fs::remove_file(path).await {
+        warn!("Failed to remove queued upload {:?}: {}", path, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn logs(job_id: &str) -> PendingUpload {
+        PendingUpload::Logs {
+            job_id: job_id.to_string(),
+            logs: vec![LogEntry {
+                step_id: "step-1".into(),
+                timestamp: Utc::now(),
+                content: "done".into(),
+                level: "info".into(),
+                sequence: 0,
+                stream: None,
+            chunk: None,
+            }],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry_until_delivered() {
+        let dir = std::env::temp_dir().join(format!("muelsyse-uploads-{}", uuid::Uuid::new_v4()));
+        let queue = UploadQueue::new(dir.clone(), Duration::from_secs(3600));
+
+        queue.enqueue(logs("job-1")).await.unwrap();
+        queue.enqueue(logs("job-2")).await.unwrap();
+        assert_eq!(queue.len().await, 2);
+
+        // Only job-1 gets through on the first pass
+        let remaining = queue.retry_all(|upload| async move {
+            match upload.job_id() {
+                "job-1" => Ok(()),
+                _ => Err(anyhow::anyhow!("control plane unavailable")),
+            }
+        }).await;
+        assert_eq!(remaining, 1);
+
+        let remaining = queue.retry_all(|_| async { Ok(()) }).await;
+        assert_eq!(remaining, 0);
+        assert!(queue.is_empty().await);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_expired_uploads_dropped() {
+        let dir = std::env::temp_dir().join(format!("muelsyse-uploads-{}", uuid::Uuid::new_v4()));
+        let queue = UploadQueue::new(dir.clone(), Duration::ZERO);
+
+        queue.enqueue(logs("job-1")).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(5)).await;
+
+        let remaining = queue.retry_all(|_| async { panic!("expired upload retried") }).await;
+        assert_eq!(remaining, 0);
+        assert!(queue.is_empty().await);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,347 @@
+//! Job spec validation
+//!
+//! Assignments are checked before admission, so a malformed job is rejected
+//! back to the control plane with a report naming each offending field
+//! (`steps[2].step_id: duplicates steps[0]`) instead of failing mid-run or
+//! being dropped with a parse error in the log.
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Component, Path};
+
+use crate::client::{ArtifactSpec, ContainerSpec, JobSpec, StepSpec, WorkspaceSpec};
+use super::graph::StepGraph;
+
+/// Longest job or step timeout accepted, in minutes (one week)
+pub const MAX_TIMEOUT_MINUTES: u32 = 7 * 24 * 60;
+
+/// Fields a job spec cannot do without
+const REQUIRED_FIELDS: [&str; 8] = [
+    "job_id", "execution_id", "name", "steps", "environment", "secrets", "timeout_minutes", "workspace",
+];
+
+/// A problem with one field of a job spec
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldError {
+    /// Path of the field, e.g. `steps[1].timeout_minutes`
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    fn new(field: impl Into<String>, message: impl Into<String>) -> Self {
+        Self { field: field.into(), message: message.into() }
+    }
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+/// Check a parsed job for problems its types cannot express
+pub fn validate_job(job: &JobSpec) -> Result<(), Vec<FieldError>> {
+    let mut errors = Vec::new();
+    if job.job_id.trim().is_empty() {
+        errors.push(FieldError::new("job_id", "must not be empty"));
+    }
+    if job.timeout_minutes > MAX_TIMEOUT_MINUTES {
+        errors.push(FieldError::new("timeout_minutes", timeout_message(job.timeout_minutes)));
+    }
+
+    let mut step_ids = HashMap::new();
+    let mut reference_ids = HashMap::new();
+    for (i, step) in job.steps.iter().enumerate() {
+        let field = format!("steps[{}]", i);
+        if step.step_id.trim().is_empty() {
+            errors.push(FieldError::new(format!("{}.step_id", field), "must not be empty"));
+        } else if let Some(first) = step_ids.insert(step.step_id.as_str(), i) {
+            errors.push(FieldError::new(format!("{}.step_id", field), format!("duplicates steps[{}]", first)));
+        }
+        if let Some(id) = step.id.as_deref().filter(|id| !id.is_empty()) {
+            if let Some(first) = reference_ids.insert(id, i) {
+                errors.push(FieldError::new(format!("{}.id", field), format!("duplicates steps[{}]", first)));
+            }
+        }
+        if step.run.is_none() && step.uses.is_none() {
+            errors.push(FieldError::new(field.clone(), "must set `run` or `uses`"));
+        }
+        if step.timeout_minutes > MAX_TIMEOUT_MINUTES {
+            errors.push(FieldError::new(format!("{}.timeout_minutes", field), timeout_message(step.timeout_minutes)));
+        }
+        for (j, pattern) in step.outputs.files.iter().enumerate() {
+            let path = Path::new(pattern);
+            if pattern.is_empty() || path.is_absolute() || path.components().any(|c| c == Component::ParentDir) {
+                let message = "must be a path relative to the workspace";
+                errors.push(FieldError::new(format!("{}.outputs.files[{}]", field, j), message));
+            }
+        }
+        if step.limits.cpus.is_some_and(|cpus| !(cpus > 0.0 && cpus.is_finite())) {
+            errors.push(FieldError::new(format!("{}.limits.cpus", field), "must be positive"));
+        }
+        if step.limits.memory_mb == Some(0) {
+            errors.push(FieldError::new(format!("{}.limits.memory_mb", field), "must be positive"));
+        }
+        if step.limits.pids == Some(0) {
+            errors.push(FieldError::new(format!("{}.limits.pids", field), "must be positive"));
+        }
+        if step.log.max_lines == Some(0) {
+            errors.push(FieldError::new(format!("{}.log.max_lines", field), "must be positive"));
+        }
+        if step.log.sample_rate.is_some_and(|rate| !(rate > 0.0 && rate <= 1.0)) {
+            errors.push(FieldError::new(format!("{}.log.sample_rate", field), "must be in (0, 1]"));
+        }
+    }
+    // Duplicate ids are already reported above
+    if errors.iter().all(|e| !e.field.ends_with("id")) {
+        if let Err(e) = StepGraph::new(&job.steps) {
+            errors.push(FieldError::new("steps", format!("{:#}", e)));
+        }
+    }
+
+    if let Some(ref container) = job.container {
+        if let Some(message) = image_error(&container.image) {
+            errors.push(FieldError::new("container.image", message));
+        }
+        let mut cache_paths = HashMap::new();
+        for (i, cache) in container.caches.iter().enumerate() {
+            let field = format!("container.caches[{}]", i);
+            let path = cache.path.trim_end_matches('/');
+            if !cache.path.starts_with('/') || path.is_empty() {
+                errors.push(FieldError::new(format!("{}.path", field), "must be an absolute path other than /"));
+            } else if let Some(first) = cache_paths.insert(path, i) {
+                errors.push(FieldError::new(format!("{}.path", field), format!("duplicates container.caches[{}]", first)));
+            }
+            if cache.key.as_deref().is_some_and(str::is_empty) {
+                errors.push(FieldError::new(format!("{}.key", field), "must not be empty"));
+            }
+        }
+    }
+    if job.concurrency.as_ref().is_some_and(|concurrency| concurrency.group.trim().is_empty()) {
+        errors.push(FieldError::new("concurrency.group", "must not be empty"));
+    }
+
+    if errors.is_empty() { Ok(()) } else { Err(errors) }
+}
+
+/// Why `job` does not parse as a job spec, field by field
+pub fn parse_errors(job: &Value) -> Vec<FieldError> {
+    let Some(fields) = job.as_object() else {
+        return vec![FieldError::new("job", "must be an object")];
+    };
+
+    let mut errors: Vec<FieldError> = REQUIRED_FIELDS
+        .iter()
+        .filter(|field| fields.get(**field).is_none_or(Value::is_null))
+        .map(|field| FieldError::new(*field, "is required"))
+        .collect();
+
+    for list in ["steps", "artifacts"] {
+        match fields.get(list) {
+            Some(Value::Array(items)) => {
+                for (i, item) in items.iter().enumerate() {
+                    let field = format!("{}[{}]", list, i);
+                    let error = if list == "steps" { part_error::<StepSpec>(item) } else { part_error::<ArtifactSpec>(item) };
+                    errors.extend(error.map(|message| FieldError::new(field, message)));
+                }
+            }
+            None | Some(Value::Null) => {}
+            Some(_) => errors.push(FieldError::new(list, "must be an array")),
+        }
+    }
+    if let Some(container) = fields.get("container").filter(|c| !c.is_null()) {
+        errors.extend(part_error::<ContainerSpec>(container).map(|message| FieldError::new("container", message)));
+    }
+    if let Some(workspace) = fields.get("workspace").filter(|w| !w.is_null()) {
+        errors.extend(part_error::<WorkspaceSpec>(workspace).map(|message| FieldError::new("workspace", message)));
+    }
+
+    // Whatever is left, e.g. a top-level field of the wrong type
+    if errors.is_empty() {
+        errors.extend(part_error::<JobSpec>(job).map(|message| FieldError::new("job", message)));
+    }
+    errors
+}
+
+/// One error listing every field at fault in `what`
+pub fn field_report(what: &str, errors: &[FieldError]) -> anyhow::Error {
+    let lines: Vec<String> = errors.iter().map(|e| format!("  {}", e)).collect();
+    anyhow::anyhow!("{} problem(s) in {}:\n{}", errors.len(), what, lines.join("\n"))
+}
+
+fn part_error<T: DeserializeOwned>(value: &Value) -> Option<String> {
+    T::deserialize(value).err().map(|e| e.to_string())
+}
+
+fn timeout_message(minutes: u32) -> String {
+    format!("must be at most {} minutes, got {}", MAX_TIMEOUT_MINUTES, minutes)
+}
+
+/// Why `image` is not a valid image reference
+/// (`[registry[:port]/]path[:tag][@algorithm:digest]`), if it is not
+fn image_error(image: &str) -> Option<String> {
+    if image.trim().is_empty() {
+        return Some("must not be empty".into());
+    }
+
+    let (reference, digest) = match image.split_once('@') {
+        Some((reference, digest)) => (reference, Some(digest)),
+        None => (image, None),
+    };
+    if let Some(digest) = digest {
+        let valid = digest.split_once(':').is_some_and(|(algorithm, hex)| {
+            !algorithm.is_empty()
+                && algorithm.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b"+._-".contains(&b))
+                && hex.len() >= 32
+                && hex.bytes().all(|b| b.is_ascii_hexdigit())
+        });
+        if !valid {
+            return Some(format!("`{}` has an invalid digest, expected e.g. sha256:<64 hex digits>", image));
+        }
+    }
+
+    // A colon after the last slash starts the tag; before it, a registry port
+    let (name, tag) = match reference.rfind(':') {
+        Some(i) if !reference[i..].contains('/') => (&reference[..i], Some(&reference[i + 1..])),
+        _ => (reference, None),
+    };
+    if let Some(tag) = tag {
+        let valid = !tag.is_empty()
+            && tag.len() <= 128
+            && !tag.starts_with(['.', '-'])
+            && tag.bytes().all(|b| b.is_ascii_alphanumeric() || b"_.-".contains(&b));
+        if !valid {
+            return Some(format!("`{}` has an invalid tag '{}'", image, tag));
+        }
+    }
+
+    let mut components: Vec<&str> = name.split('/').collect();
+    let first = components[0];
+    if components.len() > 1 && (first.contains(['.', ':']) || first == "localhost") {
+        let (host, port) = match first.split_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (first, None),
+        };
+        let valid = !host.is_empty()
+            && host.bytes().all(|b| b.is_ascii_alphanumeric() || b".-".contains(&b))
+            && port.is_none_or(|port| !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()));
+        if !valid {
+            return Some(format!("`{}` has an invalid registry '{}'", image, first));
+        }
+        components.remove(0);
+    }
+    for component in components {
+        let valid = !component.is_empty()
+            && component.starts_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit())
+            && component.ends_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit())
+            && component.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b"._-".contains(&b));
+        if !valid {
+            return Some(format!(
+                "`{}` has an invalid repository name '{}': use lowercase letters, digits and . _ -", image, component
+            ));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{ConcurrencySpec, VolumeCacheSpec};
+    use serde_json::json;
+
+    fn spec() -> Value {
+        json!({
+            "job_id": "job-1",
+            "execution_id": "exec-1",
+            "name": "build",
+            "steps": [
+                { "step_id": "s1", "id": "compile", "name": "compile", "run": "make" },
+                { "step_id": "s2", "name": "test", "run": "make test", "needs": ["compile"] },
+            ],
+            "environment": {},
+            "secrets": {},
+            "container": { "image": "registry.example.com:5000/team/app:1.2" },
+            "timeout_minutes": 30,
+            "workspace": { "path": "/tmp/job-1" },
+        })
+    }
+
+    fn fields(errors: &[FieldError]) -> Vec<&str> {
+        errors.iter().map(|e| e.field.as_str()).collect()
+    }
+
+    #[test]
+    fn test_validate_job() {
+        let job: JobSpec = serde_json::from_value(spec()).unwrap();
+        assert_eq!(validate_job(&job), Ok(()));
+
+        let mut invalid = job.clone();
+        invalid.timeout_minutes = MAX_TIMEOUT_MINUTES + 1;
+        invalid.steps[1].step_id = "s1".into();
+        invalid.steps[1].run = None;
+        invalid.steps[1].outputs.files = vec!["dist/report.html".into(), "../secrets".into(), "/etc/passwd".into()];
+        invalid.steps[1].limits.cpus = Some(0.0);
+        invalid.steps[1].limits.memory_mb = Some(512);
+        invalid.steps[1].log.sample_rate = Some(1.5);
+        invalid.container.as_mut().unwrap().image = "Ubuntu:22.04".into();
+        invalid.container.as_mut().unwrap().caches = vec![
+            VolumeCacheSpec { path: "/root/.cargo".into(), key: None },
+            VolumeCacheSpec { path: "/root/.cargo/".into(), key: Some(String::new()) },
+            VolumeCacheSpec { path: "cache".into(), key: None },
+        ];
+        invalid.concurrency = Some(ConcurrencySpec { group: " ".into(), cancel_in_progress: true });
+        let errors = validate_job(&invalid).unwrap_err();
+        assert_eq!(fields(&errors), vec![
+            "timeout_minutes", "steps[1].step_id", "steps[1]", "steps[1].outputs.files[1]",
+            "steps[1].outputs.files[2]", "steps[1].limits.cpus", "steps[1].log.sample_rate", "container.image",
+            "container.caches[1].path", "container.caches[1].key", "container.caches[2].path",
+            "concurrency.group",
+        ]);
+        assert_eq!(errors[1].to_string(), "steps[1].step_id: duplicates steps[0]");
+
+        let mut unknown = job;
+        unknown.steps[1].needs = vec!["lint".into()];
+        let errors = validate_job(&unknown).unwrap_err();
+        assert_eq!(errors[0].field, "steps");
+        assert!(errors[0].message.contains("unknown step 'lint'"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let mut value = spec();
+        value.as_object_mut().unwrap().remove("job_id");
+        value["steps"][1]["timeout_minutes"] = json!("ten");
+        value["container"] = json!({ "env": {} });
+        let errors = parse_errors(&value);
+        assert_eq!(fields(&errors), vec!["job_id", "steps[1]", "container"]);
+        assert!(errors[1].message.contains("invalid type"));
+        assert!(errors[2].message.contains("missing field `image`"));
+
+        let mut value = spec();
+        value["timeout_minutes"] = json!(-1);
+        assert_eq!(fields(&parse_errors(&value)), vec!["job"]);
+        assert_eq!(fields(&parse_errors(&json!("job"))), vec!["job"]);
+        assert!(parse_errors(&spec()).is_empty());
+    }
+
+    #[test]
+    fn test_image_error() {
+        for image in [
+            "alpine",
+            "alpine:3.19",
+            "library/ubuntu:22.04",
+            "ghcr.io/org/tool-chain_x.y:v1.0-rc.1",
+            "localhost:5000/app",
+            "rust@sha256:0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef",
+        ] {
+            assert_eq!(image_error(image), None, "{}", image);
+        }
+        for image in ["", "Ubuntu", "app:", "app:-x", "registry:port/app", "-app", "app@md5", "a//b", "app:tag with space"] {
+            assert!(image_error(image).is_some(), "{}", image);
+        }
+    }
+}
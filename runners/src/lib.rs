@@ -4,14 +4,22 @@
 //! and executes jobs in Docker containers or directly on the host.
 
 pub mod config;
+pub mod error;
+pub mod events;
 pub mod client;
 pub mod executor;
 pub mod job;
 pub mod log;
 pub mod artifact;
+pub mod cache;
+pub mod actions;
 pub mod utils;
+pub mod workspace;
+pub mod pipeline;
+pub mod telemetry;
 
 pub use config::Settings;
+pub use error::RunnerError;
 pub use client::ControlPlaneClient;
-pub use executor::{Executor, ExecutorType};
+pub use executor::{register_executor, Executor, ExecutorType};
 pub use job::JobRunner;
@@ -0,0 +1,157 @@
+//! Step annotations
+//!
+//! Steps point at problems in the source with workflow commands in their
+//! output, as in GitHub Actions:
+//!
+//! ```text
+//! ::error file=src/main.rs,line=10,col=5::mismatched types
+//! ::warning title=Deprecated API::`set-output` is deprecated
+//! ::notice::Coverage went up by 2%
+//! ```
+//!
+//! The commands are sent to the control plane as [`Annotation`]s, so UIs can
+//! show them next to the code. `%0A`, `%0D` and `%25` escape line breaks and
+//! `%` in messages, and `%3A` and `%2C` escape `:` and `,` in properties.
+
+use serde::Serialize;
+
+use super::mask::SecretMasker;
+use super::process::strip_ansi;
+
+/// Annotations kept per step; later ones are ignored
+pub const MAX_ANNOTATIONS: usize = 50;
+
+/// Severity of an annotation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AnnotationLevel {
+    Error,
+    Warning,
+    Notice,
+}
+
+/// A message attached to a place in the source
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Annotation {
+    pub level: AnnotationLevel,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// Path relative to the workspace
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end_line: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub col: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end_column: Option<u32>,
+}
+
+impl Annotation {
+    /// The annotation with secrets masked in its text
+    pub fn masked(self, masker: &SecretMasker) -> Self {
+        Self {
+            message: masker.mask(&self.message),
+            title: self.title.map(|title| masker.mask(&title)),
+            file: self.file.map(|file| masker.mask(&file)),
+            ..self
+        }
+    }
+}
+
+/// Annotations in a step's output, at most [`MAX_ANNOTATIONS`]
+pub fn parse_annotations(output: &str) -> Vec<Annotation> {
+    output.lines().filter_map(parse_annotation).take(MAX_ANNOTATIONS).collect()
+}
+
+/// The annotation a line of output is, if it is one
+pub fn parse_annotation(line: &str) -> Option<Annotation> {
+    let line = strip_ansi(line);
+    let (command, message) = line.trim().strip_prefix("::")?.split_once("::")?;
+    let (name, properties) = command.split_once(' ').unwrap_or((command, ""));
+    let level = match name {
+        "error" => AnnotationLevel::Error,
+        "warning" => AnnotationLevel::Warning,
+        "notice" => AnnotationLevel::Notice,
+        _ => return None,
+    };
+
+    let mut annotation = Annotation {
+        level,
+        message: unescape(message, false),
+        title: None,
+        file: None,
+        line: None,
+        end_line: None,
+        col: None,
+        end_column: None,
+    };
+    for (key, value) in properties.split(',').filter_map(|property| property.split_once('=')) {
+        let value = unescape(value.trim(), true);
+        match key.trim() {
+            "title" => annotation.title = Some(value),
+            "file" => annotation.file = Some(value),
+            "line" => annotation.line = value.parse().ok(),
+            "endLine" => annotation.end_line = value.parse().ok(),
+            "col" => annotation.col = value.parse().ok(),
+            "endColumn" => annotation.end_column = value.parse().ok(),
+            _ => {}
+        }
+    }
+    Some(annotation)
+}
+
+/// Undo the escaping of a message, or of a property value
+fn unescape(text: &str, property: bool) -> String {
+    let mut text = text.replace("%0D", "\r").replace("%0A", "\n");
+    if property {
+        text = text.replace("%3A", ":").replace("%2C", ",");
+    }
+    // Last, so `%250A` stays `%0A`
+    text.replace("%25", "%")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_annotation() {
+        let error = parse_annotation("::error file=src/main.rs,line=10,col=5,endColumn=9::mismatched types").unwrap();
+        assert_eq!(error.level, AnnotationLevel::Error);
+        assert_eq!(error.message, "mismatched types");
+        assert_eq!(error.file.as_deref(), Some("src/main.rs"));
+        assert_eq!((error.line, error.col, error.end_column, error.end_line), (Some(10), Some(5), Some(9), None));
+
+        let warning = parse_annotation("\x1b[33m::warning title=Deprecated%3A set-output::use%0A$MUELSYSE_OUTPUT 100%25\x1b[0m").unwrap();
+        assert_eq!(warning.level, AnnotationLevel::Warning);
+        assert_eq!(warning.title.as_deref(), Some("Deprecated: set-output"));
+        assert_eq!(warning.message, "use\n$MUELSYSE_OUTPUT 100%");
+        assert_eq!(serde_json::to_value(&warning).unwrap()["level"], "warning");
+
+        let notice = parse_annotation("::notice::done").unwrap();
+        assert_eq!((notice.level, notice.message.as_str(), notice.file), (AnnotationLevel::Notice, "done", None));
+
+        assert_eq!(parse_annotation("::set-output name=a::b"), None);
+        assert_eq!(parse_annotation("::debug::cache key abc"), None);
+        assert_eq!(parse_annotation("error: ::error::not at the start"), None);
+    }
+
+    #[test]
+    fn test_parse_annotations() {
+        let output = "compiling\n::error::first\nwarning: unused\n::warning file=a.rs::second\n";
+        let annotations = parse_annotations(output);
+        assert_eq!(annotations.len(), 2);
+        assert_eq!(annotations[1].file.as_deref(), Some("a.rs"));
+
+        let flood = "::error::again\n".repeat(MAX_ANNOTATIONS + 10);
+        assert_eq!(parse_annotations(&flood).len(), MAX_ANNOTATIONS);
+
+        let masker = SecretMasker::new(["hunter2".to_string()]);
+        let masked = parse_annotation("::error title=hunter2::login as hunter2 failed").unwrap().masked(&masker);
+        assert!(!masked.message.contains("hunter2") && !masked.title.unwrap().contains("hunter2"));
+    }
+}
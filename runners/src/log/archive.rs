@@ -0,0 +1,382 @@
+//! Local log retention and search
+//!
+//! When enabled, every job's log lines are also appended to
+//! `<retention_path>/<job_id>/log.jsonl`. Each job gets a small `index.json`
+//! holding a fixed-size trigram filter, so a search only scans the logs of
+//! jobs that can contain the pattern.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::warn;
+
+use crate::client::LogEntry;
+
+const LOG_FILE: &str = "log.jsonl";
+const INDEX_FILE: &str = "index.json";
+
+/// Bits in a job's trigram filter (8KB per job)
+const FILTER_BITS: usize = 1 << 16;
+
+/// Fixed-size bitset of hashed lowercase trigrams.
+///
+/// May report trigrams that were never added, never misses one that was.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct TrigramFilter {
+    bits: Vec<u8>,
+}
+
+impl TrigramFilter {
+    fn new() -> Self {
+        Self { bits: vec![0; FILTER_BITS / 8] }
+    }
+
+    fn add_text(&mut self, text: &str) {
+        for hash in trigram_hashes(text) {
+            self.bits[hash / 8] |= 1 << (hash % 8);
+        }
+    }
+
+    /// Whether text containing `pattern` may have been added
+    fn may_contain(&self, pattern: &str) -> bool {
+        trigram_hashes(pattern).all(|hash| self.bits[hash / 8] & (1 << (hash % 8)) != 0)
+    }
+
+    fn merge(&mut self, other: &Self) {
+        for (bits, other) in self.bits.iter_mut().zip(&other.bits) {
+            *bits |= other;
+        }
+    }
+}
+
+impl Serialize for TrigramFilter {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(&self.bits))
+    }
+}
+
+impl<'de> Deserialize<'de> for TrigramFilter {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let bits = hex::decode(String::deserialize(deserializer)?).map_err(serde::de::Error::custom)?;
+        if bits.len() != FILTER_BITS / 8 {
+            return Err(serde::de::Error::custom("trigram filter has the wrong size"));
+        }
+        Ok(Self { bits })
+    }
+}
+
+/// FNV-1a hashes of the lowercase character trigrams of `text`
+fn trigram_hashes(text: &str) -> impl Iterator<Item = usize> {
+    let chars: Vec<char> = text.to_lowercase().chars().collect();
+    (0..chars.len().saturating_sub(2)).map(move |i| {
+        let mut hash: u32 = 0x811c_9dc5;
+        for c in &chars[i..i + 3] {
+            for byte in (*c as u32).to_le_bytes() {
+                hash = (hash ^ byte as u32).wrapping_mul(0x0100_0193);
+            }
+        }
+        hash as usize % FILTER_BITS
+    })
+}
+
+/// Per-job index written when a job's log is closed
+#[derive(Debug, Serialize, Deserialize)]
+struct JobIndex {
+    job_id: String,
+    name: String,
+    updated_at: DateTime<Utc>,
+    lines: u64,
+    trigrams: TrigramFilter,
+}
+
+/// Appends one job's log lines to the archive
+pub struct ArchiveWriter {
+    dir: PathBuf,
+    job_id: String,
+    /// Opened on the first line, so jobs without output leave nothing behind
+    file: Mutex<Option<std::io::BufWriter<std::fs::File>>>,
+    filter: Mutex<(TrigramFilter, u64)>,
+}
+
+impl ArchiveWriter {
+    /// Append a log line; failures are logged, never fatal to the job
+    pub fn append(&self, entry: &LogEntry) {
+        if let Err(e) = self.write_line(entry) {
+            warn!("Failed to archive log line of job {}: {}", self.job_id, e);
+            return;
+        }
+
+        let mut filter = self.filter.lock().unwrap_or_else(|e| e.into_inner());
+        filter.0.add_text(&entry.content);
+        filter.1 += 1;
+    }
+
+    fn write_line(&self, entry: &LogEntry) -> Result<()> {
+        let line = serde_json::to_string(entry)?;
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        if file.is_none() {
+            std::fs::create_dir_all(&self.dir).context("Failed to create log archive directory")?;
+            let opened = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.dir.join(LOG_FILE))
+                .context("Failed to open archived log")?;
+            *file = Some(std::io::BufWriter::new(opened));
+        }
+        if let Some(file) = file.as_mut() {
+            writeln!(file, "{}", line)?;
+        }
+        Ok(())
+    }
+
+    /// Flush the log and write the job's index, merging an earlier attempt's
+    pub fn finish(&self, name: &str) -> Result<()> {
+        match self.file.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+            Some(file) => file.flush().context("Failed to flush archived log")?,
+            None => return Ok(()),
+        }
+
+        let (mut trigrams, mut lines) = self.filter.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        let index_path = self.dir.join(INDEX_FILE);
+        if let Some(previous) = read_index(&index_path) {
+            trigrams.merge(&previous.trigrams);
+            lines += previous.lines;
+        }
+
+        let index = JobIndex {
+            job_id: self.job_id.clone(),
+            name: name.to_string(),
+            updated_at: Utc::now(),
+            lines,
+            trigrams,
+        };
+        let tmp_path = index_path.with_extension("tmp");
+        std::fs::write(&tmp_path, serde_json::to_vec(&index)?).context("Failed to write log index")?;
+        std::fs::rename(&tmp_path, &index_path).context("Failed to write log index")
+    }
+}
+
+/// Filters for a log search
+#[derive(Debug, Clone, Default)]
+pub struct LogQuery {
+    /// Substring to look for
+    pub pattern: String,
+    /// Only search this job
+    pub job_id: Option<String>,
+    pub ignore_case: bool,
+    /// Stop after this many matches (0 = unlimited)
+    pub limit: usize,
+}
+
+/// A log line matching a search
+#[derive(Debug, Clone)]
+pub struct LogMatch {
+    pub job_id: String,
+    /// Empty while the job is still running
+    pub job_name: String,
+    pub entry: LogEntry,
+}
+
+/// Directory of retained job logs
+#[derive(Debug, Clone)]
+pub struct LogArchive {
+    dir: PathBuf,
+    max_jobs: usize,
+}
+
+impl LogArchive {
+    /// Keeps the logs of the `max_jobs` most recently finished jobs (0 = unlimited)
+    pub fn new(dir: PathBuf, max_jobs: usize) -> Self {
+        Self { dir, max_jobs }
+    }
+
+    fn job_dir(&self, job_id: &str) -> PathBuf {
+        let name: String = job_id
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') { c } else { '_' })
+            .collect();
+        self.dir.join(name)
+    }
+
+    /// Writer appending to a job's log
+    pub fn writer(&self, job_id: &str) -> ArchiveWriter {
+        ArchiveWriter {
+            dir: self.job_dir(job_id),
+            job_id: job_id.to_string(),
+            file: Mutex::new(None),
+            filter: Mutex::new((TrigramFilter::new(), 0)),
+        }
+    }
+
+    /// Remove the oldest finished jobs beyond the limit
+    pub fn prune(&self) -> Result<usize> {
+        if self.max_jobs == 0 {
+            return Ok(0);
+        }
+
+        let mut finished: Vec<(DateTime<Utc>, PathBuf)> = self.job_dirs()
+            .into_iter()
+            .filter_map(|dir| read_index(&dir.join(INDEX_FILE)).map(|index| (index.updated_at, dir)))
+            .collect();
+        finished.sort_by_key(|(updated_at, _)| std::cmp::Reverse(*updated_at));
+
+        let mut removed = 0;
+        for (_, dir) in finished.into_iter().skip(self.max_jobs) {
+            std::fs::remove_dir_all(&dir).with_context(|| format!("Failed to remove archived log {:?}", dir))?;
+            removed += 1;
+        }
+        Ok(removed)
+    }
+
+    fn job_dirs(&self) -> Vec<PathBuf> {
+        std::fs::read_dir(&self.dir)
+            .map(|entries| entries.flatten().map(|e| e.path()).filter(|p| p.is_dir()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Search retained logs, most recently updated jobs first.
+    ///
+    /// Jobs still running have no index yet and are always scanned.
+    pub fn search(&self, query: &LogQuery) -> Result<Vec<LogMatch>> {
+        let needle = if query.ignore_case { query.pattern.to_lowercase() } else { query.pattern.clone() };
+
+        let mut jobs: Vec<(Option<JobIndex>, PathBuf)> = match query.job_id {
+            Some(ref job_id) => vec![(read_index(&self.job_dir(job_id).join(INDEX_FILE)), self.job_dir(job_id))],
+            None => self.job_dirs().into_iter().map(|dir| (read_index(&dir.join(INDEX_FILE)), dir)).collect(),
+        };
+        // Running jobs first, then newest
+        jobs.sort_by_key(|(index, _)| index.as_ref().map(|i| std::cmp::Reverse(i.updated_at)));
+
+        let mut matches = Vec::new();
+        for (index, dir) in jobs {
+            if index.as_ref().is_some_and(|i| !i.trigrams.may_contain(&query.pattern)) {
+                continue;
+            }
+
+            let log_path = dir.join(LOG_FILE);
+            let file = match std::fs::File::open(&log_path) {
+                Ok(file) => file,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e).with_context(|| format!("Failed to open archived log {:?}", log_path)),
+            };
+
+            let (job_id, job_name) = match index {
+                Some(index) => (index.job_id, index.name),
+                None => (dir.file_name().unwrap_or_default().to_string_lossy().into_owned(), String::new()),
+            };
+
+            for line in std::io::BufReader::new(file).lines() {
+                let Ok(entry) = serde_json::from_str::<LogEntry>(&line?) else {
+                    continue;
+                };
+                let found = if query.ignore_case {
+                    entry.content.to_lowercase().contains(&needle)
+                } else {
+                    entry.content.contains(&needle)
+                };
+                if found {
+                    matches.push(LogMatch { job_id: job_id.clone(), job_name: job_name.clone(), entry });
+                    if query.limit > 0 && matches.len() >= query.limit {
+                        return Ok(matches);
+                    }
+                }
+            }
+        }
+
+        Ok(matches)
+    }
+}
+
+fn read_index(path: &Path) -> Option<JobIndex> {
+    let contents = std::fs::read(path).ok()?;
+    serde_json::from_slice(&contents)
+        .map_err(|e| warn!("Ignoring unreadable log index {:?}: {}", path, e))
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(content: &str) -> LogEntry {
+        LogEntry {
+            step_id: "step-1".into(),
+            timestamp: Utc::now(),
+            content: content.into(),
+            level: "info".into(),
+            sequence: 0,
+            stream: None,
+            chunk: None,
+        }
+    }
+
+    #[test]
+    fn test_search_and_prune() {
+        let dir = std::env::temp_dir().join(format!("muelsyse-log-archive-{}", uuid::Uuid::new_v4()));
+        let archive = LogArchive::new(dir.clone(), 1);
+
+        let build = archive.writer("job-1");
+        build.append(&line("Compiling muelsyse v0.1.0"));
+        build.append(&line("error[E0425]: cannot find value `x`"));
+        build.finish("build").unwrap();
+
+        let test = archive.writer("job-2");
+        test.append(&line("test result: ok. 59 passed"));
+        test.finish("test").unwrap();
+
+        // Still running: no index yet
+        let running = archive.writer("job-3");
+        running.append(&line("ERROR: disk full"));
+        running.file.lock().unwrap().as_mut().unwrap().flush().unwrap();
+
+        let search = |pattern: &str, ignore_case: bool| -> Vec<String> {
+            let query = LogQuery { pattern: pattern.into(), ignore_case, ..Default::default() };
+            archive.search(&query).unwrap().into_iter().map(|m| m.job_id).collect()
+        };
+        assert_eq!(search("cannot find", false), vec!["job-1"]);
+        assert_eq!(search("error", false), vec!["job-1"]);
+        assert_eq!(search("error", true), vec!["job-3", "job-1"]);
+        assert!(search("segfault", true).is_empty());
+
+        let query = LogQuery { pattern: "o".into(), job_id: Some("job-2".into()), ..Default::default() };
+        let matches = archive.search(&query).unwrap();
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].job_name, "test");
+
+        // A later attempt appends and keeps earlier lines indexed
+        let retry = archive.writer("job-1");
+        retry.append(&line("Finished release profile"));
+        retry.finish("build").unwrap();
+        assert_eq!(search("cannot find", false), vec!["job-1"]);
+        assert_eq!(search("release", false), vec!["job-1"]);
+
+        // Nothing is written for a job without output
+        archive.writer("job-4").finish("empty").unwrap();
+        assert!(!dir.join("job-4").exists());
+
+        // job-2 is now the oldest finished job; the running job is kept
+        assert_eq!(archive.prune().unwrap(), 1);
+        assert!(!dir.join("job-2").exists());
+        assert!(dir.join("job-3").exists());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_trigram_filter() {
+        let mut filter = TrigramFilter::new();
+        filter.add_text("Connection Refused");
+
+        assert!(filter.may_contain("refused"));
+        assert!(filter.may_contain("ON R"));
+        // Too short to filter on
+        assert!(filter.may_contain("zz"));
+        assert!(!filter.may_contain("timeout"));
+
+        let json = serde_json::to_string(&filter).unwrap();
+        assert_eq!(serde_json::from_str::<TrigramFilter>(&json).unwrap(), filter);
+    }
+}
@@ -0,0 +1,155 @@
+//! Per-step limits on logged output
+//!
+//! A step's `log` settings keep jobs that print millions of lines from
+//! flooding the control plane: `drop_debug` drops lines detected as debug,
+//! `sample_rate` logs only that fraction of the remaining info lines
+//! (warnings and errors always pass), and `max_lines` stops logging the
+//! step's output after that many lines, with a marker where it stopped. Once
+//! the step finishes, a summary line tells how many lines were dropped.
+
+use serde::{Deserialize, Serialize};
+
+/// Output limits of a step
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct LogControls {
+    /// Lines of output logged before the rest is dropped
+    #[serde(default, alias = "max-lines")]
+    pub max_lines: Option<u64>,
+    /// Fraction of info and debug lines logged, e.g. `0.1` for every tenth
+    #[serde(default, alias = "sample-rate")]
+    pub sample_rate: Option<f64>,
+    /// Drop lines detected as debug
+    #[serde(default, alias = "drop-debug")]
+    pub drop_debug: bool,
+}
+
+impl LogControls {
+    pub fn is_empty(&self) -> bool {
+        self.max_lines.is_none() && self.sample_rate.is_none() && !self.drop_debug
+    }
+}
+
+/// What to do with a line of output
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) enum Admission {
+    Log,
+    Drop,
+    /// Drop this and every later line, logging this marker instead
+    Truncate(String),
+}
+
+/// Applies a step's [`LogControls`] to its output lines in order
+#[derive(Debug)]
+pub(super) struct OutputLimiter {
+    controls: LogControls,
+    logged: u64,
+    /// Lines owed to the log by the sample rate; a line is logged when a
+    /// whole one is due
+    due: f64,
+    dropped_debug: u64,
+    dropped_sampled: u64,
+    truncated: u64,
+}
+
+impl OutputLimiter {
+    pub fn new(controls: LogControls) -> Self {
+        Self {
+            controls,
+            logged: 0,
+            // The first line is always logged
+            due: 1.0,
+            dropped_debug: 0,
+            dropped_sampled: 0,
+            truncated: 0,
+        }
+    }
+
+    /// Whether to log a line of `level`
+    pub fn admit(&mut self, level: &str) -> Admission {
+        if self.truncated > 0 {
+            self.truncated += 1;
+            return Admission::Drop;
+        }
+        if self.controls.drop_debug && level == "debug" {
+            self.dropped_debug += 1;
+            return Admission::Drop;
+        }
+        if let Some(rate) = self.controls.sample_rate.filter(|_| matches!(level, "info" | "debug")) {
+            if self.due < 1.0 {
+                self.due += rate;
+                self.dropped_sampled += 1;
+                return Admission::Drop;
+            }
+            self.due += rate - 1.0;
+        }
+        if self.controls.max_lines.is_some_and(|max| self.logged >= max) {
+            self.truncated = 1;
+            return Admission::Truncate(format!(
+                "Output truncated after {} lines (log.max_lines); the rest of this step's output is not logged",
+                self.logged
+            ));
+        }
+        self.logged += 1;
+        Admission::Log
+    }
+
+    /// Summary of the dropped lines, if any were
+    pub fn summary(&self) -> Option<String> {
+        let dropped = self.dropped_debug + self.dropped_sampled + self.truncated;
+        if dropped == 0 {
+            return None;
+        }
+        let reasons: Vec<String> = [
+            (self.dropped_debug, "debug"),
+            (self.dropped_sampled, "sampled out"),
+            (self.truncated, "past max_lines"),
+        ]
+        .into_iter()
+        .filter(|(count, _)| *count > 0)
+        .map(|(count, reason)| format!("{} {}", count, reason))
+        .collect();
+        Some(format!(
+            "Logged {} of {} output lines; dropped {}",
+            self.logged,
+            self.logged + dropped,
+            reasons.join(", ")
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_limiter() {
+        let controls: LogControls = serde_json::from_str(r#"{"max-lines": 4, "sample-rate": 0.5, "drop-debug": true}"#).unwrap();
+        let mut limiter = OutputLimiter::new(controls);
+
+        let levels = ["info", "debug", "info", "info", "warn", "info", "info", "error", "info", "error"];
+        let admitted: Vec<Admission> = levels.iter().map(|level| limiter.admit(level)).collect();
+        assert_eq!(admitted, vec![
+            Admission::Log,
+            Admission::Drop,
+            Admission::Drop,
+            Admission::Log,
+            Admission::Log,
+            Admission::Drop,
+            Admission::Log,
+            Admission::Truncate(
+                "Output truncated after 4 lines (log.max_lines); the rest of this step's output is not logged".into()
+            ),
+            Admission::Drop,
+            Admission::Drop,
+        ]);
+        assert_eq!(
+            limiter.summary().as_deref(),
+            Some("Logged 4 of 10 output lines; dropped 1 debug, 2 sampled out, 3 past max_lines")
+        );
+
+        let mut unlimited = OutputLimiter::new(LogControls::default());
+        assert!((0..100).all(|_| unlimited.admit("debug") == Admission::Log));
+        assert_eq!(unlimited.summary(), None);
+        assert!(LogControls::default().is_empty());
+    }
+}
@@ -0,0 +1,56 @@
+//! Secret masking for text shown outside the job
+
+/// Replacement for masked secret values
+pub const MASK: &str = "***";
+
+/// Masks known secret values in text
+#[derive(Debug, Clone, Default)]
+pub struct SecretMasker {
+    /// Secret values, longest first so overlapping secrets mask fully
+    secrets: Vec<String>,
+}
+
+impl SecretMasker {
+    /// Build a masker from secret values
+    pub fn new<I, S>(secrets: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let mut secrets: Vec<String> = secrets
+            .into_iter()
+            .map(Into::into)
+            .filter(|s| !s.is_empty())
+            .collect();
+
+        secrets.sort_by_key(|s| std::cmp::Reverse(s.len()));
+        secrets.dedup();
+
+        Self { secrets }
+    }
+
+    /// Replace every secret value in `text` with the mask
+    pub fn mask(&self, text: &str) -> String {
+        let mut masked = text.to_string();
+        for secret in &self.secrets {
+            if masked.contains(secret.as_str()) {
+                masked = masked.replace(secret.as_str(), MASK);
+            }
+        }
+        masked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mask_secrets() {
+        let masker = SecretMasker::new(["hunter2", "", "hunter2-extended"]);
+
+        assert_eq!(masker.mask("password=hunter2-extended"), "password=***");
+        assert_eq!(masker.mask("a hunter2 b hunter2"), "a *** b ***");
+        assert_eq!(masker.mask("nothing here"), "nothing here");
+    }
+}
@@ -1,13 +1,26 @@
 //! Log utilities
 
 pub mod streamer;
+pub mod mask;
+pub mod archive;
+pub mod persist;
+pub mod spool;
+pub mod process;
+pub mod limit;
+pub mod annotation;
 
 pub use streamer::{
     LogEntry,
-    LogChunk,
     LogStreamer,
     LogStreamerManager,
     AsyncLogWriter,
     LogWriteRequest,
     SimpleLogBuffer,
 };
+pub use mask::SecretMasker;
+pub use archive::{ArchiveWriter, LogArchive, LogMatch, LogQuery};
+pub use persist::PersistedLog;
+pub use spool::LogSpool;
+pub use limit::LogControls;
+pub use annotation::{parse_annotations, Annotation, AnnotationLevel};
+pub use process::{detect_level, strip_ansi, OutputProcessor};
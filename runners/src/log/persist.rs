@@ -0,0 +1,178 @@
+//! Disk-backed pending logs
+//!
+//! With `logging.enable_persistence`, every log line is also appended to
+//! `<dir>/<job_id>.jsonl` as it is added, and `<job_id>.delivered` records
+//! the highest sequence sent or acknowledged. If the runner dies mid-job, the
+//! lines above that mark are replayed on the next connection.
+
+use anyhow::{Context, Result};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::warn;
+
+use crate::client::LogEntry;
+
+/// One job's persisted log lines
+pub struct PersistedLog {
+    log_path: PathBuf,
+    mark_path: PathBuf,
+    job_id: String,
+    file: Mutex<Option<std::fs::File>>,
+}
+
+impl PersistedLog {
+    pub fn new(dir: &Path, job_id: &str) -> Self {
+        let name: String = job_id
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') { c } else { '_' })
+            .collect();
+        Self {
+            log_path: dir.join(format!("{}.jsonl", name)),
+            mark_path: dir.join(format!("{}.delivered", name)),
+            job_id: job_id.to_string(),
+            file: Mutex::new(None),
+        }
+    }
+
+    pub fn job_id(&self) -> &str {
+        &self.job_id
+    }
+
+    /// Append a line; written straight to the file so it survives a crash
+    pub fn append(&self, entry: &LogEntry) {
+        if let Err(e) = self.write_line(entry) {
+            warn!("Failed to persist log line of job {}: {}", self.job_id, e);
+        }
+    }
+
+    fn write_line(&self, entry: &LogEntry) -> Result<()> {
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        if file.is_none() {
+            if let Some(dir) = self.log_path.parent() {
+                std::fs::create_dir_all(dir).context("Failed to create pending log directory")?;
+            }
+            *file = Some(
+                std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.log_path)
+                    .context("Failed to open pending log")?,
+            );
+        }
+        if let Some(file) = file.as_mut() {
+            file.write_all(line.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Highest sequence already sent or acknowledged
+    pub fn delivered(&self) -> Option<u64> {
+        std::fs::read_to_string(&self.mark_path).ok()?.trim().parse().ok()
+    }
+
+    /// Record that lines up to `sequence` need no replay
+    pub fn mark_delivered(&self, sequence: u64) {
+        if self.delivered().is_some_and(|delivered| delivered >= sequence) {
+            return;
+        }
+        if let Err(e) = std::fs::write(&self.mark_path, sequence.to_string()) {
+            warn!("Failed to record delivered logs of job {}: {}", self.job_id, e);
+        }
+    }
+
+    /// Persisted lines not yet delivered
+    pub fn undelivered(&self) -> Result<Vec<LogEntry>> {
+        let contents = match std::fs::read_to_string(&self.log_path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e).context("Failed to read pending log"),
+        };
+
+        let delivered = self.delivered();
+        Ok(contents
+            .lines()
+            // A crash can leave a partial last line
+            .filter_map(|line| serde_json::from_str::<LogEntry>(line).ok())
+            .filter(|entry| delivered.is_none_or(|delivered| entry.sequence > delivered))
+            .collect())
+    }
+
+    /// Delete the job's files
+    pub fn remove(&self) {
+        self.file.lock().unwrap_or_else(|e| e.into_inner()).take();
+        for path in [&self.log_path, &self.mark_path] {
+            match std::fs::remove_file(path) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => warn!("Failed to remove pending log {:?}: {}", path, e),
+            }
+        }
+    }
+}
+
+/// Jobs with persisted logs in `dir`
+pub fn persisted_jobs(dir: &Path) -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    let mut jobs: Vec<String> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.path();
+            match path.extension() {
+                Some(ext) if ext == "jsonl" => path.file_stem().map(|s| s.to_string_lossy().into_owned()),
+                _ => None,
+            }
+        })
+        .collect();
+    jobs.sort();
+    jobs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn line(sequence: u64) -> LogEntry {
+        LogEntry {
+            step_id: "step-1".into(),
+            timestamp: Utc::now(),
+            content: format!("line {}", sequence),
+            level: "info".into(),
+            sequence,
+            stream: None,
+            chunk: None,
+        }
+    }
+
+    #[test]
+    fn test_undelivered_after_restart() {
+        let dir = std::env::temp_dir().join(format!("muelsyse-pending-logs-{}", uuid::Uuid::new_v4()));
+
+        let log = PersistedLog::new(&dir, "job-1");
+        for sequence in 0..5 {
+            log.append(&line(sequence));
+        }
+        log.mark_delivered(2);
+        log.mark_delivered(1);
+        drop(log);
+
+        // A new process sees only what was never delivered
+        assert_eq!(persisted_jobs(&dir), vec!["job-1"]);
+        let log = PersistedLog::new(&dir, "job-1");
+        let sequences: Vec<u64> = log.undelivered().unwrap().iter().map(|e| e.sequence).collect();
+        assert_eq!(sequences, vec![3, 4]);
+
+        log.remove();
+        assert!(persisted_jobs(&dir).is_empty());
+        assert!(log.undelivered().unwrap().is_empty());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,172 @@
+//! Post-processing of step output lines
+//!
+//! Before a line of step output is logged, ANSI escape sequences are
+//! stripped or kept according to `logging.ansi`, and its level is taken from
+//! a prefix such as `WARN`, `[error]`, `error:` or `::debug::`. Lines
+//! without one keep the level of their stream: `info` for stdout, `error`
+//! for stderr.
+
+use std::borrow::Cow;
+
+use crate::config::{AnsiMode, LoggingConfig};
+use crate::executor::{OutputLine, OutputStream};
+
+/// Prepares output lines for the log
+#[derive(Debug, Clone, Copy)]
+pub struct OutputProcessor {
+    ansi: AnsiMode,
+    detect_levels: bool,
+}
+
+impl OutputProcessor {
+    pub fn new(config: &LoggingConfig) -> Self {
+        Self { ansi: config.ansi, detect_levels: config.detect_levels }
+    }
+
+    /// Apply the ANSI mode to `line`, returning its level
+    pub fn process(&self, line: &mut OutputLine) -> &'static str {
+        let plain = strip_ansi(&line.text);
+        let detected = if self.detect_levels { detect_level(&plain) } else { None };
+        let level = detected.unwrap_or(match line.stream {
+            OutputStream::Stdout => "info",
+            OutputStream::Stderr => "error",
+        });
+        if self.ansi == AnsiMode::Strip {
+            if let Cow::Owned(plain) = plain {
+                line.text = plain;
+            }
+        }
+        level
+    }
+}
+
+/// `text` without ANSI escape sequences: CSI (colors, cursor movement),
+/// OSC (titles, hyperlinks) and other `ESC` sequences
+pub fn strip_ansi(text: &str) -> Cow<'_, str> {
+    if !text.contains('\x1b') {
+        return Cow::Borrowed(text);
+    }
+
+    let mut plain = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            plain.push(c);
+            continue;
+        }
+        match chars.next() {
+            // CSI: parameters and intermediates up to a final byte
+            Some('[') => {
+                for c in chars.by_ref() {
+                    if ('\x40'..='\x7e').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            // OSC and other strings, ended by BEL or ST (`ESC \`)
+            Some(']' | 'P' | 'X' | '^' | '_') => {
+                while let Some(c) = chars.next() {
+                    if c == '\x07' || (c == '\x1b' && chars.next_if_eq(&'\\').is_some()) {
+                        break;
+                    }
+                }
+            }
+            // Intermediates followed by a final byte, e.g. `ESC ( B`
+            Some(' '..='/') => {
+                while chars.next_if(|c| (' '..='/').contains(c)).is_some() {}
+                chars.next();
+            }
+            Some(_) | None => {}
+        }
+    }
+    Cow::Owned(plain)
+}
+
+/// Level named by the prefix of a line, after any leading timestamp.
+///
+/// Bare words only count in capitals (`ERROR build failed`); marked ones in
+/// any case (`error: ...`, `[Warn]`, `::debug::`), so prose starting with
+/// "Error" or "Debugging" stays at its stream's level.
+pub fn detect_level(text: &str) -> Option<&'static str> {
+    let mut rest = text.trim_start();
+    // `2024-05-01T12:00:00Z WARN ...`, `2024-05-01 12:00:00 [error] ...`
+    for _ in 0..2 {
+        if !rest.starts_with(|c: char| c.is_ascii_digit()) {
+            break;
+        }
+        rest = rest.split_once(char::is_whitespace)?.1.trim_start();
+    }
+
+    let opened = ["::", "[", "<", "("].iter().find_map(|open| rest.strip_prefix(open));
+    let bracketed = opened.is_some();
+    let rest = opened.unwrap_or(rest);
+
+    let end = rest.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(rest.len());
+    let (word, after) = rest.split_at(end);
+    let marked = bracketed || after.starts_with([':', ']', '|']);
+    let bare = after.is_empty() || after.starts_with(char::is_whitespace);
+    if !(marked || bare && word.bytes().all(|b| b.is_ascii_uppercase())) {
+        return None;
+    }
+
+    match word.to_ascii_lowercase().as_str() {
+        "error" | "err" | "fatal" | "critical" | "crit" | "panic" => Some("error"),
+        "warn" | "warning" => Some("warn"),
+        "info" | "notice" => Some("info"),
+        "debug" | "trace" => Some("debug"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_ansi() {
+        assert!(matches!(strip_ansi("plain text"), Cow::Borrowed("plain text")));
+        assert_eq!(strip_ansi("\x1b[1;31merror\x1b[0m: failed"), "error: failed");
+        assert_eq!(strip_ansi("\x1b[2K\x1b[1Gprogress 50%"), "progress 50%");
+        assert_eq!(strip_ansi("\x1b]8;;https://x\x07link\x1b]8;;\x1b\\ done"), "link done");
+        assert_eq!(strip_ansi("\x1b(Bcharset \x1b=keypad"), "charset keypad");
+        // A truncated sequence at the end of a line is dropped
+        assert_eq!(strip_ansi("tail \x1b[38;5"), "tail ");
+    }
+
+    #[test]
+    fn test_detect_level() {
+        assert_eq!(detect_level("WARN disk almost full"), Some("warn"));
+        assert_eq!(detect_level("[error] connection refused"), Some("error"));
+        assert_eq!(detect_level("error: could not compile `app`"), Some("error"));
+        assert_eq!(detect_level("warning: unused variable"), Some("warn"));
+        assert_eq!(detect_level("::debug::cache key abc"), Some("debug"));
+        assert_eq!(detect_level("  INFO: starting"), Some("info"));
+        assert_eq!(detect_level("2024-05-01T12:00:00Z DEBUG resolved"), Some("debug"));
+        assert_eq!(detect_level("2024-05-01 12:00:00 [Fatal] out of memory"), Some("error"));
+        assert_eq!(detect_level("<warning> deprecated"), Some("warn"));
+
+        assert_eq!(detect_level("Error handling tests passed"), None);
+        assert_eq!(detect_level("errors: 0"), None);
+        assert_eq!(detect_level("Debugging symbols stripped"), None);
+        assert_eq!(detect_level("error_count=3"), None);
+        assert_eq!(detect_level("42 tests passed"), None);
+        assert_eq!(detect_level(""), None);
+    }
+
+    #[test]
+    fn test_process() {
+        let mut config = LoggingConfig::default();
+        let mut line = OutputLine::new(OutputStream::Stderr, "\x1b[33mwarning\x1b[0m: slow test");
+        assert_eq!(OutputProcessor::new(&config).process(&mut line), "warn");
+        assert_eq!(line.text, "\x1b[33mwarning\x1b[0m: slow test");
+
+        config.ansi = AnsiMode::Strip;
+        assert_eq!(OutputProcessor::new(&config).process(&mut line), "warn");
+        assert_eq!(line.text, "warning: slow test");
+
+        config.detect_levels = false;
+        assert_eq!(OutputProcessor::new(&config).process(&mut line), "error");
+        let mut line = OutputLine::new(OutputStream::Stdout, "[ERROR] ignored");
+        assert_eq!(OutputProcessor::new(&config).process(&mut line), "info");
+    }
+}
@@ -0,0 +1,170 @@
+//! Offline log spool
+//!
+//! Once the control plane has been unreachable for `logging.spool_after_secs`,
+//! flushes append log lines to `workspace.base_path/<job_id>/logs` instead of
+//! queueing them for the WebSocket, starting a new `spool-NNNNNN.jsonl` file
+//! every `logging.spool_file_bytes`. When the connection is back the files are
+//! sent oldest first and removed, so a long outage neither stalls the steps
+//! behind a full queue nor drops the oldest lines.
+
+use anyhow::{Context, Result};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::warn;
+
+use crate::client::LogEntry;
+
+/// The file being appended to
+struct SpoolFile {
+    file: std::fs::File,
+    written: u64,
+}
+
+/// One job's spooled log lines
+pub struct LogSpool {
+    dir: PathBuf,
+    file_bytes: u64,
+    current: Mutex<Option<SpoolFile>>,
+}
+
+impl LogSpool {
+    pub fn new(dir: PathBuf, file_bytes: u64) -> Self {
+        Self { dir, file_bytes, current: Mutex::new(None) }
+    }
+
+    /// Append `entries`, rotating to a new file when the current one is full
+    pub fn append(&self, entries: &[LogEntry]) -> Result<()> {
+        let mut current = self.current.lock().unwrap_or_else(|e| e.into_inner());
+        for entry in entries {
+            let mut line = serde_json::to_string(entry)?;
+            line.push('\n');
+
+            let full = current
+                .as_ref()
+                .is_none_or(|spool| spool.written > 0 && spool.written + line.len() as u64 > self.file_bytes);
+            if full {
+                *current = Some(self.open_next()?);
+            }
+            if let Some(spool) = current.as_mut() {
+                spool.file.write_all(line.as_bytes()).context("Failed to write log spool")?;
+                spool.written += line.len() as u64;
+            }
+        }
+        Ok(())
+    }
+
+    fn open_next(&self) -> Result<SpoolFile> {
+        std::fs::create_dir_all(&self.dir).context("Failed to create log spool directory")?;
+        let next = self.files().last().and_then(|path| file_index(path)).map_or(0, |index| index + 1);
+        let path = self.dir.join(format!("spool-{:06}.jsonl", next));
+        let file = std::fs::OpenOptions::new()
+            .create_new(true)
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("Failed to create log spool {:?}", path))?;
+        Ok(SpoolFile { file, written: 0 })
+    }
+
+    /// Spool files, oldest first
+    pub fn files(&self) -> Vec<PathBuf> {
+        let Ok(entries) = std::fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+        let mut files: Vec<PathBuf> = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| file_index(path).is_some())
+            .collect();
+        files.sort();
+        files
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files().is_empty()
+    }
+
+    /// Stop appending to the current file, so it can be sent
+    pub fn close(&self) {
+        self.current.lock().unwrap_or_else(|e| e.into_inner()).take();
+    }
+
+    /// Lines of a spool file
+    pub fn read(path: &Path) -> Result<Vec<LogEntry>> {
+        let contents = std::fs::read_to_string(path).with_context(|| format!("Failed to read log spool {:?}", path))?;
+        // A crash can leave a partial last line
+        Ok(contents.lines().filter_map(|line| serde_json::from_str(line).ok()).collect())
+    }
+
+    /// Delete a spool file once its lines are sent, and the directory with
+    /// the last one
+    pub fn remove(&self, path: &Path) {
+        if let Err(e) = std::fs::remove_file(path) {
+            warn!("Failed to remove log spool {:?}: {}", path, e);
+        }
+        if self.is_empty() {
+            let _ = std::fs::remove_dir(&self.dir);
+        }
+    }
+
+    /// All spooled lines, oldest first, removing the files
+    pub fn take(&self) -> Vec<LogEntry> {
+        self.close();
+        let mut entries = Vec::new();
+        for path in self.files() {
+            match Self::read(&path) {
+                Ok(lines) => entries.extend(lines),
+                Err(e) => warn!("{:#}", e),
+            }
+            self.remove(&path);
+        }
+        entries
+    }
+}
+
+/// Index of a `spool-NNNNNN.jsonl` file
+fn file_index(path: &Path) -> Option<u32> {
+    path.file_name()?.to_str()?.strip_prefix("spool-")?.strip_suffix(".jsonl")?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn line(sequence: u64) -> LogEntry {
+        LogEntry {
+            step_id: "step-1".into(),
+            timestamp: Utc::now(),
+            content: format!("line {}", sequence),
+            level: "info".into(),
+            sequence,
+            stream: None,
+            chunk: None,
+        }
+    }
+
+    #[test]
+    fn test_rotation() {
+        let dir = std::env::temp_dir().join(format!("muelsyse-log-spool-{}", uuid::Uuid::new_v4()));
+        let one_line = serde_json::to_string(&line(0)).unwrap().len() as u64 + 1;
+
+        let spool = LogSpool::new(dir.join("logs"), one_line * 2);
+        assert!(spool.is_empty());
+        spool.append(&(0..3).map(line).collect::<Vec<_>>()).unwrap();
+        spool.append(&[line(3), line(4)]).unwrap();
+        assert_eq!(spool.files().len(), 3);
+
+        let first = LogSpool::read(&spool.files()[0]).unwrap();
+        assert_eq!(first.iter().map(|e| e.sequence).collect::<Vec<_>>(), vec![0, 1]);
+
+        // Appending after a take starts a new file
+        let sequences: Vec<u64> = spool.take().iter().map(|e| e.sequence).collect();
+        assert_eq!(sequences, vec![0, 1, 2, 3, 4]);
+        assert!(!dir.join("logs").exists());
+        spool.append(&[line(5)]).unwrap();
+        assert_eq!(spool.files().len(), 1);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
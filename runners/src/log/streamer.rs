@@ -5,10 +5,14 @@
 //! - Buffered log queue with configurable size
 //! - Sequence number tracking for reliable delivery
 //! - Pending log persistence for reconnection retry
+//! - Spooling to local files during long control plane outages
 //! - Automatic flush on buffer full or timeout
+//! - Backpressure: a full buffer waits for a flush before dropping lines
+//! - Per-step output limits: line caps, sampling and debug filtering
 
 use std::collections::{VecDeque, HashMap};
-use std::sync::Arc;
+use std::path::PathBuf;
+use std::sync::{Arc, Weak};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::{Duration, Instant};
 use chrono::{DateTime, Utc};
@@ -17,7 +21,13 @@ use tracing::{debug, warn, info};
 use anyhow::Result;
 
 use crate::config::LoggingConfig;
-use crate::client::{WebSocketClient, LogEntry as WsLogEntry};
+use crate::client::{WebSocketClient, LogChunk, LogEntry as WsLogEntry};
+use crate::executor::{OutputLine, OutputStream};
+use super::archive::{ArchiveWriter, LogArchive};
+use super::persist::{persisted_jobs, PersistedLog};
+use super::spool::LogSpool;
+use super::limit::{Admission, LogControls, OutputLimiter};
+use super::process::OutputProcessor;
 
 // ============================================================================
 // Log Entry Types
@@ -36,6 +46,10 @@ pub struct LogEntry {
     pub content: String,
     /// Log level (info, warn, error, debug)
     pub level: String,
+    /// Output stream, for lines of step output
+    pub stream: Option<OutputStream>,
+    /// Position of this entry among the chunks of a long line
+    pub chunk: Option<LogChunk>,
     /// Whether this entry has been acknowledged
     pub acknowledged: bool,
 }
@@ -49,10 +63,22 @@ impl LogEntry {
             timestamp: Utc::now(),
             content,
             level,
+            stream: None,
+            chunk: None,
             acknowledged: false,
         }
     }
 
+    /// Create an entry for a line of step output, timestamped when it was
+    /// captured
+    pub fn output(sequence: u64, step_id: String, line: OutputLine, level: &str) -> Self {
+        Self {
+            timestamp: line.timestamp,
+            stream: Some(line.stream),
+            ..Self::new(sequence, step_id, line.text, level.to_string())
+        }
+    }
+
     /// Convert to WebSocket log entry format
     pub fn to_ws_entry(&self) -> WsLogEntry {
         WsLogEntry {
@@ -61,21 +87,30 @@ impl LogEntry {
             content: self.content.clone(),
             level: self.level.clone(),
             sequence: self.sequence,
+            stream: self.stream,
+            chunk: self.chunk,
         }
     }
 }
 
-/// Log chunk for large log entries
-#[derive(Debug, Clone)]
-pub struct LogChunk {
-    /// Original sequence number
-    pub sequence: u64,
-    /// Chunk index (0-based)
-    pub chunk_index: usize,
-    /// Total chunks
-    pub total_chunks: usize,
-    /// Chunk content
-    pub content: String,
+/// Split `content` into chunks of at most `chunk_size` bytes, cutting only
+/// at char boundaries; a char longer than `chunk_size` gets a chunk of its own
+fn split_chunks(content: &str, chunk_size: usize) -> Vec<&str> {
+    let mut chunks = Vec::new();
+    let mut rest = content;
+    while !rest.is_empty() {
+        let mut end = chunk_size.min(rest.len());
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        if end == 0 {
+            end = rest.chars().next().map_or(rest.len(), char::len_utf8);
+        }
+        let (chunk, tail) = rest.split_at(end);
+        chunks.push(chunk);
+        rest = tail;
+    }
+    chunks
 }
 
 // ============================================================================
@@ -88,6 +123,8 @@ pub struct LogStreamer {
     job_id: String,
     /// Configuration
     config: LoggingConfig,
+    /// Prepares step output lines
+    processor: OutputProcessor,
     /// Sequence counter
     sequence_counter: AtomicU64,
     /// Pending logs (not yet acknowledged)
@@ -98,8 +135,20 @@ pub struct LogStreamer {
     buffer: Arc<Mutex<VecDeque<LogEntry>>>,
     /// Last flush time
     last_flush: Arc<RwLock<Instant>>,
+    /// Held while a batch is sent, so batches go out in order
+    flushing: Mutex<()>,
     /// WebSocket client reference
-    ws_client: Option<Arc<WebSocketClient>>,
+    ws_client: RwLock<Option<Arc<WebSocketClient>>>,
+    /// Local copy of the job's log, if retention is enabled
+    archive: Option<ArchiveWriter>,
+    /// On-disk copy of undelivered logs, if persistence is enabled
+    persisted: Option<PersistedLog>,
+    /// Lines flushed while the control plane is unreachable, if spooling is enabled
+    spool: Option<LogSpool>,
+    /// When a flush first found the WebSocket disconnected
+    offline_since: std::sync::Mutex<Option<Instant>>,
+    /// Output limits of the steps running with `log` settings
+    limiters: std::sync::Mutex<HashMap<String, OutputLimiter>>,
 }
 
 impl LogStreamer {
@@ -107,19 +156,53 @@ impl LogStreamer {
     pub fn new(job_id: String, config: LoggingConfig) -> Self {
         Self {
             job_id,
+            processor: OutputProcessor::new(&config),
             config,
             sequence_counter: AtomicU64::new(0),
             pending: Arc::new(RwLock::new(VecDeque::new())),
             ack_sequences: Arc::new(RwLock::new(HashMap::new())),
             buffer: Arc::new(Mutex::new(VecDeque::new())),
             last_flush: Arc::new(RwLock::new(Instant::now())),
-            ws_client: None,
+            flushing: Mutex::new(()),
+            ws_client: RwLock::new(None),
+            archive: None,
+            persisted: None,
+            spool: None,
+            offline_since: std::sync::Mutex::new(None),
+            limiters: std::sync::Mutex::new(HashMap::new()),
         }
     }
 
+    /// Also append every log line to `archive`
+    pub fn with_archive(mut self, archive: ArchiveWriter) -> Self {
+        self.archive = Some(archive);
+        self
+    }
+
+    /// Persist log lines to disk until they are delivered
+    pub fn with_persistence(mut self, persisted: PersistedLog) -> Self {
+        self.persisted = Some(persisted);
+        self
+    }
+
+    /// Spool log lines to `spool` during long control plane outages
+    pub fn with_spool(mut self, spool: LogSpool) -> Self {
+        self.spool = Some(spool);
+        self
+    }
+
     /// Set WebSocket client for sending logs
-    pub fn set_ws_client(&mut self, client: Arc<WebSocketClient>) {
-        self.ws_client = Some(client);
+    pub async fn set_ws_client(&self, client: Arc<WebSocketClient>) {
+        *self.ws_client.write().await = Some(client);
+    }
+
+    /// Flush every `flush_interval_ms`, so lines of a quiet step are sent
+    /// without waiting for the buffer to fill. The task ends when the
+    /// streamer is dropped.
+    pub fn spawn_flush_task(self: &Arc<Self>) -> tokio::task::JoinHandle<()> {
+        let streamer = Arc::downgrade(self);
+        let interval = Duration::from_millis(self.config.flush_interval_ms.max(1));
+        tokio::spawn(flush_periodically(streamer, interval))
     }
 
     /// Get next sequence number
@@ -129,65 +212,111 @@ impl LogStreamer {
 
     /// Add a log entry
     pub async fn add(&self, step_id: &str, content: &str, level: &str) -> Result<u64> {
-        let sequence = self.next_sequence();
+        let entry = LogEntry::new(0, step_id.to_string(), content.to_string(), level.to_string());
+        self.add_sequenced(entry).await
+    }
+
+    /// Add a line of step output, with its ANSI sequences and level
+    /// handled as configured. Returns `None` for a line the step's output
+    /// limits drop.
+    pub async fn add_output(&self, step_id: &str, mut line: OutputLine) -> Result<Option<u64>> {
+        let level = self.processor.process(&mut line);
+        let admission = self.limiters
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get_mut(step_id)
+            .map(|limiter| limiter.admit(level));
+        match admission {
+            None | Some(Admission::Log) => {}
+            Some(Admission::Drop) => return Ok(None),
+            Some(Admission::Truncate(marker)) => {
+                self.add(step_id, &marker, "system").await?;
+                return Ok(None);
+            }
+        }
+        self.add_sequenced(LogEntry::output(0, step_id.to_string(), line, level)).await.map(Some)
+    }
+
+    /// Apply `controls` to the output of `step_id` until
+    /// [`finish_output`](Self::finish_output)
+    pub fn limit_output(&self, step_id: &str, controls: LogControls) {
+        if controls.is_empty() {
+            return;
+        }
+        self.limiters
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(step_id.to_string(), OutputLimiter::new(controls));
+    }
+
+    /// Stop limiting the output of `step_id`, logging how many of its lines
+    /// were dropped
+    pub async fn finish_output(&self, step_id: &str) -> Result<()> {
+        let limiter = self.limiters.lock().unwrap_or_else(|e| e.into_inner()).remove(step_id);
+        if let Some(summary) = limiter.as_ref().and_then(OutputLimiter::summary) {
+            self.add(step_id, &summary, "system").await?;
+        }
+        Ok(())
+    }
 
+    /// Number `entry` and add it, in chunks if it is too large
+    async fn add_sequenced(&self, mut entry: LogEntry) -> Result<u64> {
         // Check if content needs chunking
-        if content.len() > self.config.chunk_size_bytes {
-            self.add_chunked(step_id, content, level, sequence).await?;
-        } else {
-            let entry = LogEntry::new(
-                sequence,
-                step_id.to_string(),
-                content.to_string(),
-                level.to_string(),
-            );
-            self.add_entry(entry).await?;
+        if entry.content.len() > self.config.chunk_size_bytes {
+            return self.add_chunked(entry).await;
         }
 
+        let sequence = self.next_sequence();
+        entry.sequence = sequence;
+        self.add_entry(entry).await?;
         Ok(sequence)
     }
 
-    /// Add a chunked log entry (for large logs)
-    async fn add_chunked(
-        &self,
-        step_id: &str,
-        content: &str,
-        level: &str,
-        base_sequence: u64,
-    ) -> Result<()> {
-        let chunk_size = self.config.chunk_size_bytes;
-        let chunks: Vec<&str> = content
-            .as_bytes()
-            .chunks(chunk_size)
-            .map(|chunk| std::str::from_utf8(chunk).unwrap_or(""))
-            .collect();
+    /// Add a chunked log entry (for large logs).
+    ///
+    /// The chunks take consecutive sequence numbers, reserved together so
+    /// concurrent lines cannot interleave with them. Returns the first.
+    async fn add_chunked(&self, mut entry: LogEntry) -> Result<u64> {
+        let content = std::mem::take(&mut entry.content);
+        let chunks = split_chunks(&content, self.config.chunk_size_bytes);
 
         let total_chunks = chunks.len();
+        let base_sequence = self.sequence_counter.fetch_add(total_chunks as u64, Ordering::SeqCst);
 
         for (i, chunk_content) in chunks.into_iter().enumerate() {
-            let chunk_marker = format!("[{}/{}] ", i + 1, total_chunks);
-            let entry = LogEntry::new(
-                base_sequence + i as u64,
-                step_id.to_string(),
-                format!("{}{}", chunk_marker, chunk_content),
-                level.to_string(),
-            );
-            self.add_entry(entry).await?;
-        }
-
-        // Update sequence counter to account for extra chunks
-        if total_chunks > 1 {
-            self.sequence_counter.fetch_add((total_chunks - 1) as u64, Ordering::SeqCst);
+            self.add_entry(LogEntry {
+                sequence: base_sequence + i as u64,
+                content: chunk_content.to_string(),
+                chunk: Some(LogChunk { chunk_index: i as u32, total_chunks: total_chunks as u32 }),
+                ..entry.clone()
+            }).await?;
         }
 
-        Ok(())
+        Ok(base_sequence)
     }
 
     /// Add a single log entry to buffer
     async fn add_entry(&self, entry: LogEntry) -> Result<()> {
+        if self.archive.is_some() || self.persisted.is_some() {
+            let line = entry.to_ws_entry();
+            if let Some(ref archive) = self.archive {
+                archive.append(&line);
+            }
+            if let Some(ref persisted) = self.persisted {
+                persisted.append(&line);
+            }
+        }
+
         let mut buffer = self.buffer.lock().await;
 
-        // Check buffer capacity
+        // Wait for a flush to make room, dropping only if it cannot be sent
+        if buffer.len() >= self.config.max_pending_logs {
+            drop(buffer);
+            if let Err(e) = self.flush().await {
+                debug!("Failed to flush full log buffer for job {}: {}", self.job_id, e);
+            }
+            buffer = self.buffer.lock().await;
+        }
         if buffer.len() >= self.config.max_pending_logs {
             // Drop oldest unacknowledged log
             if let Some(dropped) = buffer.pop_front() {
@@ -217,8 +346,21 @@ impl LogStreamer {
         Ok(())
     }
 
-    /// Flush buffered logs to WebSocket
+    /// Flush buffered logs to WebSocket.
+    ///
+    /// Waits while the WebSocket's outgoing queue is full.
     pub async fn flush(&self) -> Result<()> {
+        let _flushing = self.flushing.lock().await;
+        let Some(ws) = self.ws_client.read().await.clone() else {
+            // Keep the lines until there is somewhere to send them
+            anyhow::bail!("No WebSocket client set, logs not sent");
+        };
+        if let Some(spool) = self.spool_to(&ws).await {
+            return self.flush_to_spool(spool).await;
+        }
+        // Spooled lines go out before newer ones
+        self.replay_spool(&ws).await?;
+
         let entries: Vec<LogEntry> = {
             let mut buffer = self.buffer.lock().await;
             buffer.drain(..).collect()
@@ -230,27 +372,100 @@ impl LogStreamer {
 
         *self.last_flush.write().await = Instant::now();
 
-        if let Some(ref ws) = self.ws_client {
-            // Convert to WS format and send as batch
-            let ws_entries: Vec<WsLogEntry> = entries
-                .iter()
-                .map(|e| e.to_ws_entry())
-                .collect();
+        // Convert to WS format and send as batch
+        let ws_entries: Vec<WsLogEntry> = entries
+            .iter()
+            .map(|e| e.to_ws_entry())
+            .collect();
 
-            debug!(
-                "Flushing {} log entries for job {}",
-                ws_entries.len(),
-                self.job_id
-            );
+        debug!(
+            "Flushing {} log entries for job {}",
+            ws_entries.len(),
+            self.job_id
+        );
 
-            ws.send_log_batch(&self.job_id, ws_entries).await?;
-        } else {
-            warn!("No WebSocket client set, logs not sent");
+        if let Err(e) = ws.send_log_batch(&self.job_id, ws_entries).await {
+            // Put the batch back so a later flush can retry it
+            let mut buffer = self.buffer.lock().await;
+            for entry in entries.into_iter().rev() {
+                buffer.push_front(entry);
+            }
+            return Err(e);
+        }
+        if let (Some(ref persisted), Some(last)) = (&self.persisted, entries.last()) {
+            persisted.mark_delivered(last.sequence);
+        }
+
+        Ok(())
+    }
+
+    /// The spool, once `ws` has been disconnected for `spool_after_secs`
+    async fn spool_to(&self, ws: &WebSocketClient) -> Option<&LogSpool> {
+        let spool = self.spool.as_ref()?;
+        let connected = ws.is_connected().await;
+        let mut offline_since = self.offline_since.lock().unwrap_or_else(|e| e.into_inner());
+        if connected {
+            *offline_since = None;
+            return None;
+        }
+        let since = *offline_since.get_or_insert_with(Instant::now);
+        (since.elapsed() >= Duration::from_secs(self.config.spool_after_secs)).then_some(spool)
+    }
+
+    /// Move the buffered lines to the spool
+    async fn flush_to_spool(&self, spool: &LogSpool) -> Result<()> {
+        let mut buffer = self.buffer.lock().await;
+        if buffer.is_empty() {
+            return Ok(());
+        }
+        if spool.is_empty() {
+            warn!("Control plane unreachable, spooling logs of job {} to disk", self.job_id);
         }
+        let entries: Vec<WsLogEntry> = buffer.iter().map(|e| e.to_ws_entry()).collect();
+        spool.append(&entries)?;
+        buffer.clear();
+        *self.last_flush.write().await = Instant::now();
+        Ok(())
+    }
 
+    /// Send the spooled lines, oldest file first, removing each once queued
+    async fn replay_spool(&self, ws: &WebSocketClient) -> Result<()> {
+        let Some(ref spool) = self.spool else {
+            return Ok(());
+        };
+        let files = spool.files();
+        if files.is_empty() {
+            return Ok(());
+        }
+
+        spool.close();
+        info!("Replaying {} spooled log files of job {}", files.len(), self.job_id);
+        for path in files {
+            let entries = LogSpool::read(&path)?;
+            for batch in entries.chunks(self.config.buffer_size.max(1)) {
+                ws.send_log_batch(&self.job_id, batch.to_vec()).await?;
+            }
+            spool.remove(&path);
+            if let (Some(ref persisted), Some(last)) = (&self.persisted, entries.last()) {
+                persisted.mark_delivered(last.sequence);
+            }
+        }
         Ok(())
     }
 
+    /// Take all spooled and buffered entries, in WebSocket format, without
+    /// sending them
+    pub async fn take_buffered(&self) -> Vec<WsLogEntry> {
+        let _flushing = self.flushing.lock().await;
+        let mut entries = match self.spool {
+            Some(ref spool) => spool.take(),
+            None => Vec::new(),
+        };
+        let mut buffer = self.buffer.lock().await;
+        entries.extend(buffer.drain(..).map(|e| e.to_ws_entry()));
+        entries
+    }
+
     /// Flush if interval has elapsed
     pub async fn flush_if_needed(&self) -> Result<bool> {
         let last = *self.last_flush.read().await;
@@ -276,11 +491,18 @@ impl LogStreamer {
             ack_seqs.insert(step_id.to_string(), last_sequence);
         }
 
-        // Remove acknowledged entries from pending
+        // Remove acknowledged entries from pending; an empty step id
+        // acknowledges the whole job
         if self.config.enable_persistence {
+            if let Some(ref persisted) = self.persisted {
+                if step_id.is_empty() {
+                    persisted.mark_delivered(last_sequence);
+                }
+            }
+
             let mut pending = self.pending.write().await;
             pending.retain(|e| {
-                e.step_id != step_id || e.sequence > last_sequence
+                (!step_id.is_empty() && e.step_id != step_id) || e.sequence > last_sequence
             });
 
             debug!(
@@ -312,7 +534,8 @@ impl LogStreamer {
             self.job_id
         );
 
-        if let Some(ref ws) = self.ws_client {
+        let ws_client = self.ws_client.read().await.clone();
+        if let Some(ref ws) = ws_client {
             let ws_entries: Vec<WsLogEntry> = pending
                 .iter()
                 .map(|e| e.to_ws_entry())
@@ -347,6 +570,20 @@ impl LogStreamer {
     }
 }
 
+async fn flush_periodically(streamer: Weak<LogStreamer>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+        let Some(streamer) = streamer.upgrade() else {
+            break;
+        };
+        if let Err(e) = streamer.flush().await {
+            debug!("Periodic log flush failed for job {}: {}", streamer.job_id, e);
+        }
+    }
+}
+
 // ============================================================================
 // Log Streamer Manager
 // ============================================================================
@@ -355,14 +592,41 @@ impl LogStreamer {
 pub struct LogStreamerManager {
     config: LoggingConfig,
     streamers: Arc<RwLock<HashMap<String, Arc<LogStreamer>>>>,
+    archive: Option<LogArchive>,
+    persist_dir: Option<PathBuf>,
+    /// Workspace base path, if log spooling is enabled
+    spool_base: Option<PathBuf>,
 }
 
 impl LogStreamerManager {
     pub fn new(config: LoggingConfig) -> Self {
+        let archive = config.retention_path
+            .clone()
+            .map(|path| LogArchive::new(path, config.retention_max_jobs));
         Self {
             config,
             streamers: Arc::new(RwLock::new(HashMap::new())),
+            archive,
+            persist_dir: None,
+            spool_base: None,
+        }
+    }
+
+    /// Persist undelivered logs under `dir` if `enable_persistence` is set
+    pub fn with_persist_dir(mut self, dir: PathBuf) -> Self {
+        if self.config.enable_persistence {
+            self.persist_dir = Some(dir);
+        }
+        self
+    }
+
+    /// Spool logs to `<base_path>/<job_id>/logs` during long outages if
+    /// `spool_after_secs` is set
+    pub fn with_spool_base(mut self, base_path: PathBuf) -> Self {
+        if self.config.spool_after_secs > 0 {
+            self.spool_base = Some(base_path);
         }
+        self
     }
 
     /// Get or create a streamer for a job
@@ -379,10 +643,19 @@ impl LogStreamerManager {
             return streamer.clone();
         }
 
-        let streamer = Arc::new(LogStreamer::new(
-            job_id.to_string(),
-            self.config.clone(),
-        ));
+        let mut streamer = LogStreamer::new(job_id.to_string(), self.config.clone());
+        if let Some(ref archive) = self.archive {
+            streamer = streamer.with_archive(archive.writer(job_id));
+        }
+        if let Some(ref dir) = self.persist_dir {
+            streamer = streamer.with_persistence(PersistedLog::new(dir, job_id));
+        }
+        if let Some(ref base) = self.spool_base {
+            let spool = LogSpool::new(base.join(job_id).join("logs"), self.config.spool_file_bytes);
+            streamer = streamer.with_spool(spool);
+        }
+        let streamer = Arc::new(streamer);
+        streamer.spawn_flush_task();
         streamers.insert(job_id.to_string(), streamer.clone());
         streamer
     }
@@ -393,6 +666,51 @@ impl LogStreamerManager {
         streamers.remove(job_id)
     }
 
+    /// Remove a finished job's streamer and index its retained log
+    pub async fn finish(&self, job_id: &str, job_name: &str) {
+        let Some(streamer) = self.remove(job_id).await else {
+            return;
+        };
+        // Anything still undelivered was handed to the upload queue
+        if let Some(ref persisted) = streamer.persisted {
+            persisted.remove();
+        }
+        let (Some(archive), Some(writer)) = (&self.archive, &streamer.archive) else {
+            return;
+        };
+
+        if let Err(e) = writer.finish(job_name) {
+            warn!("Failed to index retained log of job {}: {}", job_id, e);
+        }
+        match archive.prune() {
+            Ok(0) => {}
+            Ok(removed) => debug!("Pruned {} retained job logs", removed),
+            Err(e) => warn!("Failed to prune retained logs: {}", e),
+        }
+    }
+
+    /// Send logs persisted by jobs that are no longer running, e.g. because
+    /// the runner restarted mid-job. Returns the number of lines sent.
+    pub async fn replay_persisted(&self, ws: &WebSocketClient) -> Result<usize> {
+        let Some(ref dir) = self.persist_dir else {
+            return Ok(0);
+        };
+
+        let active = self.active_jobs().await;
+        let mut sent = 0;
+        for job_id in persisted_jobs(dir).into_iter().filter(|id| !active.contains(id)) {
+            let persisted = PersistedLog::new(dir, &job_id);
+            let entries = persisted.undelivered()?;
+            if !entries.is_empty() {
+                info!("Replaying {} persisted log entries for job {}", entries.len(), job_id);
+                sent += entries.len();
+                ws.send_log_batch(persisted.job_id(), entries).await?;
+            }
+            persisted.remove();
+        }
+        Ok(sent)
+    }
+
     /// Flush all streamers
     pub async fn flush_all(&self) -> Result<()> {
         let streamers = self.streamers.read().await;
@@ -522,6 +840,7 @@ impl SimpleLogBuffer {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
 
     fn test_config() -> LoggingConfig {
         LoggingConfig {
@@ -530,6 +849,7 @@ mod tests {
             flush_interval_ms: 1000,
             enable_persistence: true,
             max_pending_logs: 1000,
+            ..LoggingConfig::default()
         }
     }
 
@@ -588,6 +908,7 @@ mod tests {
             flush_interval_ms: 1000,
             enable_persistence: true,
             max_pending_logs: 1000,
+            ..LoggingConfig::default()
         };
 
         let streamer = LogStreamer::new("job-1".to_string(), config);
@@ -598,6 +919,75 @@ mod tests {
 
         // Should have created 3 chunks
         assert_eq!(streamer.pending_count().await, 3);
+
+        // Multi-byte chars are never cut, and the chunks stay in order
+        streamer.add("step-1", "日本語のログ出力", "info").await.unwrap();
+        let pending = streamer.get_pending().await;
+        let contents: Vec<&str> = pending[3..].iter().map(|e| e.content.as_str()).collect();
+        assert_eq!(contents, ["日本語", "のログ", "出力"]);
+        let chunks: Vec<_> = pending[3..].iter().map(|e| e.chunk.unwrap()).collect();
+        assert_eq!(chunks[0], LogChunk { chunk_index: 0, total_chunks: 3 });
+        assert_eq!(chunks[2], LogChunk { chunk_index: 2, total_chunks: 3 });
+
+        assert_eq!(pending.iter().map(|e| e.sequence).collect::<Vec<_>>(), (0..6).collect::<Vec<_>>());
+
+        // Lines after a chunked one continue past its range
+        assert_eq!(streamer.add("step-1", "short", "info").await.unwrap(), 6);
+        assert!(streamer.get_pending().await[6].chunk.is_none());
+        assert_eq!(streamer.current_sequence(), 7);
+    }
+
+    proptest! {
+        #[test]
+        fn prop_chunks_reassemble(content in "\\PC{0,300}", chunk_size in 0usize..40) {
+            let chunks = split_chunks(&content, chunk_size);
+            prop_assert_eq!(chunks.concat(), content.clone());
+            for chunk in &chunks {
+                prop_assert!(!chunk.is_empty());
+                prop_assert!(chunk.len() <= chunk_size || chunk.chars().count() == 1);
+            }
+
+            let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+            let pending = runtime.block_on(async {
+                // Neighbouring one-byte lines are never chunked
+                let config = LoggingConfig { chunk_size_bytes: chunk_size.max(1), buffer_size: 1000, ..test_config() };
+                let streamer = LogStreamer::new("job-1".to_string(), config);
+                streamer.add("step-1", "-", "info").await.unwrap();
+                streamer.add("step-1", &content, "info").await.unwrap();
+                streamer.add("step-1", "-", "info").await.unwrap();
+                streamer.get_pending().await
+            });
+
+            let sequences: Vec<u64> = pending.iter().map(|e| e.sequence).collect();
+            prop_assert_eq!(sequences, (0..pending.len() as u64).collect::<Vec<_>>());
+            let middle = &pending[1..pending.len() - 1];
+            let reassembled: String = middle.iter().map(|e| e.content.as_str()).collect();
+            prop_assert_eq!(reassembled, content.clone());
+            if content.len() <= chunk_size.max(1) {
+                prop_assert!(middle[0].chunk.is_none());
+            } else {
+                for (i, entry) in middle.iter().enumerate() {
+                    let chunk = LogChunk { chunk_index: i as u32, total_chunks: middle.len() as u32 };
+                    prop_assert_eq!(entry.chunk, Some(chunk));
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_flush_task() {
+        let config = LoggingConfig { flush_interval_ms: 10, ..test_config() };
+        let streamer = Arc::new(LogStreamer::new("job-1".to_string(), config));
+        let task = streamer.spawn_flush_task();
+        streamer.add("step-1", "Log 1", "info").await.unwrap();
+
+        // Without a client the lines wait in the buffer
+        assert!(streamer.flush().await.is_err());
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(streamer.buffer_size().await, 1);
+
+        drop(streamer);
+        tokio::time::timeout(Duration::from_secs(1), task).await.unwrap().unwrap();
     }
 
     #[tokio::test]
@@ -616,4 +1006,28 @@ mod tests {
         let jobs = manager.active_jobs().await;
         assert_eq!(jobs.len(), 2);
     }
+
+    #[tokio::test]
+    async fn test_output_limits() {
+        let streamer = LogStreamer::new("job-1".to_string(), test_config());
+        streamer.limit_output("step-1", LogControls { max_lines: Some(2), ..Default::default() });
+
+        for i in 0..5 {
+            let line = OutputLine::new(OutputStream::Stdout, format!("line {}", i));
+            streamer.add_output("step-1", line).await.unwrap();
+        }
+        // Other steps are not limited
+        let other = OutputLine::new(OutputStream::Stdout, "other".to_string());
+        assert!(streamer.add_output("step-2", other).await.unwrap().is_some());
+        streamer.finish_output("step-1").await.unwrap();
+
+        let contents: Vec<String> = streamer.get_pending().await.into_iter().map(|e| e.content).collect();
+        assert_eq!(contents, vec![
+            "line 0",
+            "line 1",
+            "Output truncated after 2 lines (log.max_lines); the rest of this step's output is not logged",
+            "other",
+            "Logged 2 of 5 output lines; dropped 3 past max_lines",
+        ]);
+    }
 }
@@ -4,24 +4,35 @@
 //! - Graceful shutdown on SIGINT/SIGTERM
 //! - Wait for running jobs before exit
 //! - Notify control plane on shutdown
+//! - `search-logs` to grep job logs retained on this host
+//! - `history` to list jobs executed on this host
+//! - `exec` to run a job spec locally, without a control plane
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use std::sync::Arc;
 use tokio::sync::broadcast;
 use tracing::{info, error, warn};
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, reload, EnvFilter};
 
 mod config;
+mod events;
 mod client;
 mod executor;
 mod job;
 mod log;
 mod artifact;
+mod cache;
+mod actions;
 mod utils;
+mod workspace;
+mod pipeline;
+mod telemetry;
 
-use config::Settings;
+use config::{LogLevelControl, Settings};
 use client::ControlPlaneClient;
-use job::JobRunner;
+use job::{HistoryQuery, JobHistory, JobRunner};
+use log::{LogArchive, LogQuery};
+use telemetry::Telemetry;
 
 /// Application state for shutdown coordination
 struct AppState {
@@ -55,21 +66,54 @@ let _guard = sentry::init(("https://83cd45dcbb25304c64ad9d726adde452@o4510655959
 }));
 
 async fn main() -> Result<()> {
-    // Initialize logging
+    // Load configuration; `exec` runs without a control plane
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let command = args.first().map(String::as_str);
+    let mut settings = match command {
+        Some("exec") => Settings::load_offline()?,
+        _ => Settings::load()?,
+    };
+
+    // Spans are exported for jobs from the control plane only
+    let telemetry = match command {
+        Some("search-logs" | "history" | "exec") => None,
+        _ => Telemetry::init(&settings.tracing, &settings.runner.id)?,
+    };
+
+    // Initialize logging; the filter can be replaced by a config update
+    let startup_filter = std::env::var("RUST_LOG").unwrap_or_else(|_| "info".into());
+    let (filter, filter_handle) = reload::Layer::new(EnvFilter::new(&startup_filter));
     tracing_subscriber::registry()
-        .with(tracing_subscriber::EnvFilter::new(
-            std::env::var("RUST_LOG").unwrap_or_else(|_| "info".into()),
-        ))
+        .with(filter)
         .with(tracing_subscriber::fmt::layer())
+        .with(telemetry.as_ref().map(Telemetry::layer))
         .init();
 
     info!("Starting Muelsyse Runner v{}...", env!("CARGO_PKG_VERSION"));
 
-    // Load configuration
-    let settings = Settings::load()?;
+    // Local commands work on this host's state and exit
+    match command {
+        Some("search-logs") => return search_logs(&settings, &args[1..]),
+        Some("history") => return show_history(&settings, &args[1..]).await,
+        Some("exec") => return exec_job(&settings, &args[1..]).await,
+        _ => {}
+    }
+
+    if settings.runner.auto_labels {
+        let detected = utils::detect_labels(&settings).await;
+        settings.runner.labels = utils::merge_labels(&settings.runner.labels, detected);
+    }
+
+    // Bad TLS files fail here instead of on the first request
+    client::check_tls(&settings.control_plane.tls, &settings.control_plane.proxy)?;
+
+    // New machines register with the control plane to get their credentials
+    client::ensure_registered(&mut settings).await?;
+
     info!("Loaded configuration for runner: {}", settings.runner.name);
     info!("Runner ID: {}", settings.runner.id);
     info!("Control plane: {}", settings.control_plane.ws_url);
+    info!("Labels: {}", settings.runner.labels.join(", "));
 
     // Create application state
     let app_state = Arc::new(AppState::new(settings.clone()));
@@ -82,7 +126,12 @@ async fn main() -> Result<()> {
     let client = ControlPlaneClient::new(settings.clone());
 
     // Create job runner with shutdown channel
-    let runner = JobRunner::new(settings.clone(), client);
+    let log_level: LogLevelControl = Arc::new(move |level: Option<&str>| {
+        filter_handle
+            .reload(EnvFilter::new(level.unwrap_or(&startup_filter)))
+            .context("Failed to change log level")
+    });
+    let runner = JobRunner::new(settings.clone(), client).with_log_level_control(log_level);
 
     // Connect runner's shutdown to our signal handler
     let runner_shutdown = runner.shutdown_sender();
@@ -100,6 +149,9 @@ async fn main() -> Result<()> {
 
     // Notify control plane that runner is going offline
     notify_offline(&settings).await;
+    if let Some(telemetry) = telemetry {
+        telemetry.shutdown().await;
+    }
 
     match result {
         Ok(_) => {
@@ -174,6 +226,108 @@ fn setup_signal_handlers(shutdown_tx: broadcast::Sender<()>) {
     }
 }
 
+/// `search-logs [-i] [--job ID] [--limit N] PATTERN`: print retained log
+/// lines containing PATTERN
+fn search_logs(settings: &Settings, args: &[String]) -> Result<()> {
+    let Some(ref path) = settings.logging.retention_path else {
+        anyhow::bail!("Log retention is disabled, set logging.retention_path");
+    };
+
+    let mut query = LogQuery { limit: 100, ..Default::default() };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-i" | "--ignore-case" => query.ignore_case = true,
+            "--job" => query.job_id = Some(args.next().context("--job requires a job id")?.clone()),
+            "--limit" => {
+                query.limit = args.next()
+                    .context("--limit requires a number")?
+                    .parse()
+                    .context("Invalid --limit")?;
+            }
+            pattern => query.pattern = pattern.to_string(),
+        }
+    }
+    if query.pattern.is_empty() {
+        anyhow::bail!("Usage: muelsyse-runner search-logs [-i] [--job ID] [--limit N] PATTERN");
+    }
+
+    let archive = LogArchive::new(path.clone(), settings.logging.retention_max_jobs);
+    for found in archive.search(&query)? {
+        println!(
+            "{} {} {} [{}] {}",
+            found.entry.timestamp.to_rfc3339(),
+            found.job_id,
+            found.entry.step_id,
+            found.entry.level,
+            found.entry.content,
+        );
+    }
+    Ok(())
+}
+
+/// `history [--job ID] [--status STATUS] [--limit N] [--steps] [--json]`:
+/// list job attempts recorded on this host, newest first
+async fn show_history(settings: &Settings, args: &[String]) -> Result<()> {
+    let mut query = HistoryQuery { limit: 20, ..Default::default() };
+    let mut json = false;
+    let mut steps = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--job" => query.job_id = Some(args.next().context("--job requires a job id")?.clone()),
+            "--status" => query.status = Some(args.next().context("--status requires a status")?.clone()),
+            "--limit" => {
+                query.limit = args.next()
+                    .context("--limit requires a number")?
+                    .parse()
+                    .context("Invalid --limit")?;
+            }
+            "--steps" => steps = true,
+            "--json" => json = true,
+            other => anyhow::bail!("Unknown history option: {}", other),
+        }
+    }
+
+    let history = JobHistory::new(settings.workspace.history_path.clone(), settings.job.history_max_records);
+    for record in history.query(&query).await? {
+        if json {
+            println!("{}", serde_json::to_string(&record)?);
+        } else {
+            println!(
+                "{} {} #{} {:<9} {:>8}ms {} ({}, {} artifacts)",
+                record.started_at.to_rfc3339(),
+                record.job_id,
+                record.attempt,
+                record.status,
+                record.duration_ms,
+                record.name,
+                record.spec_digest.get(..12).unwrap_or(&record.spec_digest),
+                record.artifacts.len(),
+            );
+            for step in record.steps.iter().filter(|_| steps) {
+                let exit_code = step.exit_code.map(|code| format!(" exit {}", code)).unwrap_or_default();
+                println!("    {:<9} {:>8}ms {}{}", step.status, step.duration_ms, step.name, exit_code);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `exec JOBSPEC [JOB]`: run a JSON or YAML job spec, or job `JOB` of a
+/// pipeline file, on this host and exit with the job's status
+async fn exec_job(settings: &Settings, args: &[String]) -> Result<()> {
+    let (path, selector) = match args {
+        [path] => (path, None),
+        [path, job] => (path, Some(job.as_str())),
+        _ => anyhow::bail!("Usage: muelsyse-runner exec JOBSPEC.json|JOBSPEC.yaml|PIPELINE.yml [JOB]"),
+    };
+
+    let job = job::load_spec(std::path::Path::new(path), selector)?;
+    let status = job::run_local(settings, &job).await?;
+    std::process::exit(status.exit_code());
+}
+
 /// Notify control plane that runner is going offline
 async fn notify_offline(settings: &Settings) {
     info!("Notifying control plane of runner shutdown...");
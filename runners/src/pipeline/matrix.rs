@@ -0,0 +1,246 @@
+//! Job matrices
+//!
+//! A `strategy.matrix` is expanded the way the control plane expands it:
+//! every combination of the axis values in file order, minus those matching
+//! an `exclude` entry, followed by each `include` entry as a combination of
+//! its own. `${{ matrix.<key> }}` anywhere in the job is then replaced by
+//! the instance's value; in `if:` conditions a bare `matrix.<key>` becomes
+//! a quoted literal so the condition can still be evaluated per step.
+
+use anyhow::{Context, Result};
+use serde_yaml::{Mapping, Value};
+
+/// Values of one matrix instance, in axis order
+pub type Combination = Vec<(String, String)>;
+
+/// The instances of `matrix`, in the order the control plane creates them
+pub fn expand(matrix: &Mapping) -> Result<Vec<Combination>> {
+    let mut axes = Vec::new();
+    let mut include = Vec::new();
+    let mut exclude = Vec::new();
+    for (key, values) in matrix {
+        let key = key.as_str().context("Matrix keys must be strings")?;
+        match key {
+            "include" => include = entries(key, values)?,
+            "exclude" => exclude = entries(key, values)?,
+            _ => {
+                let Value::Sequence(values) = values else {
+                    anyhow::bail!("Matrix axis '{}' must be a list", key);
+                };
+                let values = values
+                    .iter()
+                    .map(|value| scalar(value).with_context(|| format!("In matrix axis '{}'", key)))
+                    .collect::<Result<Vec<_>>>()?;
+                axes.push((key.to_string(), values));
+            }
+        }
+    }
+
+    let mut combinations: Vec<Combination> = Vec::new();
+    if !axes.is_empty() {
+        combinations.push(Vec::new());
+        for (key, values) in &axes {
+            combinations = combinations
+                .into_iter()
+                .flat_map(|combination| {
+                    values.iter().map(move |value| {
+                        let mut next = combination.clone();
+                        next.push((key.clone(), value.clone()));
+                        next
+                    })
+                })
+                .collect();
+        }
+    }
+    combinations.retain(|combination| !exclude.iter().any(|pattern| matches(combination, pattern)));
+    combinations.extend(include);
+
+    if combinations.is_empty() {
+        anyhow::bail!("Matrix has no combinations");
+    }
+    Ok(combinations)
+}
+
+/// Replace matrix references in every string of `value`
+pub fn substitute(value: &mut Value, combination: &Combination) -> Result<()> {
+    substitute_in(value, combination, false)
+}
+
+/// Job name of a matrix instance, e.g. `Test (18, linux)`
+pub fn display_name(name: &str, combination: &Combination) -> String {
+    if combination.is_empty() {
+        return name.to_string();
+    }
+    let values: Vec<&str> = combination.iter().map(|(_, value)| value.as_str()).collect();
+    format!("{} ({})", name, values.join(", "))
+}
+
+/// The string form of a scalar YAML value
+pub fn scalar(value: &Value) -> Result<String> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Number(n) => Ok(n.to_string()),
+        Value::Bool(b) => Ok(b.to_string()),
+        Value::Null => Ok(String::new()),
+        _ => anyhow::bail!("Expected a string, number or boolean, got {:?}", value),
+    }
+}
+
+fn entries(key: &str, values: &Value) -> Result<Vec<Combination>> {
+    let Value::Sequence(values) = values else {
+        anyhow::bail!("Matrix '{}' must be a list", key);
+    };
+    values
+        .iter()
+        .map(|entry| {
+            let Value::Mapping(entry) = entry else {
+                anyhow::bail!("Matrix '{}' entries must be mappings", key);
+            };
+            entry
+                .iter()
+                .map(|(k, v)| {
+                    let k = k.as_str().with_context(|| format!("Matrix '{}' keys must be strings", key))?;
+                    Ok((k.to_string(), scalar(v).with_context(|| format!("In matrix '{}' key '{}'", key, k))?))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Whether `combination` has every value of `pattern`
+fn matches(combination: &Combination, pattern: &Combination) -> bool {
+    pattern.iter().all(|(key, value)| combination.iter().any(|(k, v)| k == key && v == value))
+}
+
+fn substitute_in(value: &mut Value, combination: &Combination, condition: bool) -> Result<()> {
+    match value {
+        Value::String(text) => *text = substitute_text(text, combination, condition)?,
+        Value::Sequence(items) => {
+            for item in items {
+                substitute_in(item, combination, false)?;
+            }
+        }
+        Value::Mapping(mapping) => {
+            for (key, item) in mapping.iter_mut() {
+                substitute_in(item, combination, key.as_str() == Some("if"))?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn substitute_text(text: &str, combination: &Combination, condition: bool) -> Result<String> {
+    if !text.contains("matrix.") {
+        return Ok(text.to_string());
+    }
+    if condition && !text.contains("${{") {
+        return quote_operands(text, combination);
+    }
+
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("${{") {
+        let Some(len) = rest[start..].find("}}") else {
+            break;
+        };
+        let expr = rest[start + 3..start + len].trim();
+        result.push_str(&rest[..start]);
+        match expr.strip_prefix("matrix.") {
+            Some(key) if is_key(key) => result.push_str(lookup(key, combination)?),
+            _ if expr.contains("matrix.") => {
+                result.push_str("${{ ");
+                result.push_str(&quote_operands(expr, combination)?);
+                result.push_str(" }}");
+            }
+            _ => result.push_str(&rest[start..start + len + 2]),
+        }
+        rest = &rest[start + len + 2..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// Replace each `matrix.<key>` operand of an expression by a quoted literal
+fn quote_operands(expr: &str, combination: &Combination) -> Result<String> {
+    let mut result = String::with_capacity(expr.len());
+    let mut rest = expr;
+    while let Some(start) = rest.find("matrix.") {
+        let boundary = rest[..start]
+            .chars()
+            .next_back()
+            .is_none_or(|c| !(c.is_alphanumeric() || c == '_' || c == '.'));
+        let key_len = rest[start + 7..]
+            .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '-'))
+            .unwrap_or(rest.len() - start - 7);
+        let key = &rest[start + 7..start + 7 + key_len];
+        result.push_str(&rest[..start]);
+        if boundary && is_key(key) {
+            result.push('\'');
+            result.push_str(&lookup(key, combination)?.replace('\'', "''"));
+            result.push('\'');
+        } else {
+            result.push_str(&rest[start..start + 7 + key_len]);
+        }
+        rest = &rest[start + 7 + key_len..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+fn is_key(key: &str) -> bool {
+    !key.is_empty() && key.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+}
+
+fn lookup<'a>(key: &str, combination: &'a Combination) -> Result<&'a str> {
+    combination
+        .iter()
+        .find(|(k, _)| k == key)
+        .map(|(_, value)| value.as_str())
+        .with_context(|| format!("Unknown matrix value 'matrix.{}'", key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn combination(values: &[(&str, &str)]) -> Combination {
+        values.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn test_expand() {
+        let matrix: Mapping = serde_yaml::from_str(
+            "os: [linux, macos]\nnode: [18, 20]\nexclude:\n  - os: macos\n    node: 18\ninclude:\n  - os: linux\n    node: 16\n    experimental: true\n",
+        ).unwrap();
+        let combinations = expand(&matrix).unwrap();
+        assert_eq!(combinations, vec![
+            combination(&[("os", "linux"), ("node", "18")]),
+            combination(&[("os", "linux"), ("node", "20")]),
+            combination(&[("os", "macos"), ("node", "20")]),
+            combination(&[("os", "linux"), ("node", "16"), ("experimental", "true")]),
+        ]);
+        assert_eq!(display_name("Test", &combinations[1]), "Test (linux, 20)");
+
+        let empty: Mapping = serde_yaml::from_str("os: []").unwrap();
+        assert!(expand(&empty).is_err());
+        let nested: Mapping = serde_yaml::from_str("os: [[linux]]").unwrap();
+        assert!(expand(&nested).is_err());
+    }
+
+    #[test]
+    fn test_substitute() {
+        let mut job: Value = serde_yaml::from_str(
+            "container: node:${{ matrix.node }}\nsteps:\n  - run: echo ${{ github.sha }} ${{matrix.node}}\n    if: matrix.node == '20' && success()\n  - run: echo\n    if: ${{ matrix.node != '18' }}\n",
+        ).unwrap();
+        let values = combination(&[("node", "20")]);
+        substitute(&mut job, &values).unwrap();
+        assert_eq!(job["container"], "node:20");
+        assert_eq!(job["steps"][0]["run"], "echo ${{ github.sha }} 20");
+        assert_eq!(job["steps"][0]["if"], "'20' == '20' && success()");
+        assert_eq!(job["steps"][1]["if"], "${{ '20' != '18' }}");
+
+        let mut unknown: Value = serde_yaml::from_str("run: echo ${{ matrix.os }}").unwrap();
+        assert!(substitute(&mut unknown, &values).is_err());
+    }
+}
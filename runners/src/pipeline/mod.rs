@@ -0,0 +1,495 @@
+//! Pipeline files
+//!
+//! Parses the workflow users commit to their repositories (see
+//! `yaml/example-pipeline.yml`) into one [`JobSpec`] per job and matrix
+//! instance, with the defaults the control plane would fill in, so a
+//! pipeline can run locally with `exec` exactly as it is committed.
+//!
+//! Only what decides how a job runs is read: `name`, `env`,
+//! `defaults.run`, and per job `name`, `runs-on`, `needs`, `container`,
+//! `executor`, `env`, `timeout-minutes`, `strategy.matrix`, `steps`,
+//! `artifacts`, `cleanup`, `resources`, `priority`, `concurrency`, `gpus` and
+//! `retry-on`.
+//! Triggers, workflow concurrency and job-level `if:` are the control
+//! plane's business and are ignored.
+
+mod matrix;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use serde_yaml::{Mapping, Value};
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::client::{
+    ArtifactSpec, CacheSpec, CleanupPolicy, ConcurrencySpec, ContainerSpec, JobSpec, StepOutputs, StepSpec, WorkspaceSpec,
+};
+use crate::error::RetryClass;
+use crate::executor::{OutputEncoding, ResourceLimits};
+use crate::job::{field_report, validate_job, FieldError};
+use crate::log::LogControls;
+
+pub use matrix::Combination;
+
+/// Job and step timeout when the pipeline sets none, as on the control plane
+const DEFAULT_TIMEOUT_MINUTES: u32 = 60;
+
+/// A parsed pipeline
+#[derive(Debug, Clone)]
+pub struct Pipeline {
+    pub name: String,
+    /// Jobs in an order where each comes after the jobs it needs
+    pub jobs: Vec<PipelineJob>,
+}
+
+/// One job of a pipeline, or one matrix instance of it
+#[derive(Debug, Clone)]
+pub struct PipelineJob {
+    /// The job's key under `jobs`, with the instance number (`test-2`) for
+    /// matrix jobs
+    pub id: String,
+    /// The job's key under `jobs`
+    pub key: String,
+    /// Keys of the jobs that must finish first
+    pub needs: Vec<String>,
+    /// Matrix values of this instance
+    pub matrix: Combination,
+    pub spec: JobSpec,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct PipelineDef {
+    name: Option<String>,
+    #[serde(default)]
+    env: HashMap<String, Value>,
+    #[serde(default)]
+    defaults: DefaultsDef,
+    jobs: Mapping,
+}
+
+#[derive(Default, Deserialize)]
+struct DefaultsDef {
+    #[serde(default)]
+    run: RunDefaults,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct RunDefaults {
+    shell: Option<String>,
+    working_directory: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct JobDef {
+    name: Option<String>,
+    #[serde(default)]
+    runs_on: OneOrMany,
+    #[serde(default)]
+    needs: OneOrMany,
+    container: Option<ContainerDef>,
+    executor: Option<String>,
+    #[serde(default)]
+    env: HashMap<String, Value>,
+    timeout_minutes: Option<u32>,
+    #[serde(default)]
+    steps: Vec<StepDef>,
+    #[serde(default)]
+    artifacts: Vec<ArtifactSpec>,
+    #[serde(default)]
+    cleanup: CleanupPolicy,
+    #[serde(default)]
+    resources: Vec<String>,
+    #[serde(default)]
+    priority: i32,
+    concurrency: Option<ConcurrencyDef>,
+    #[serde(default)]
+    gpus: u32,
+    retry_on: Option<Vec<RetryClass>>,
+}
+
+#[derive(Deserialize)]
+struct StrategyDef {
+    matrix: Option<Mapping>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct StepDef {
+    id: Option<String>,
+    name: Option<String>,
+    run: Option<String>,
+    uses: Option<String>,
+    #[serde(default)]
+    with: HashMap<String, serde_json::Value>,
+    #[serde(default)]
+    env: HashMap<String, Value>,
+    working_directory: Option<String>,
+    shell: Option<String>,
+    #[serde(default)]
+    continue_on_error: bool,
+    timeout_minutes: Option<u32>,
+    #[serde(rename = "if")]
+    condition: Option<String>,
+    #[serde(default)]
+    needs: OneOrMany,
+    #[serde(default)]
+    resources: Vec<String>,
+    output_encoding: Option<OutputEncoding>,
+    cache: Option<CacheSpec>,
+    secrets: Option<Vec<String>>,
+    on_cancel: Option<String>,
+    #[serde(default)]
+    outputs: StepOutputs,
+    #[serde(default)]
+    limits: ResourceLimits,
+    #[serde(default)]
+    log: LogControls,
+}
+
+/// What every job of a pipeline shares
+struct Shared<'a> {
+    run: &'a RunDefaults,
+    env: &'a HashMap<String, String>,
+    execution_id: &'a str,
+}
+
+/// `runs-on: linux` or `runs-on: [linux, docker]`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Default for OneOrMany {
+    fn default() -> Self {
+        Self::Many(Vec::new())
+    }
+}
+
+impl OneOrMany {
+    fn into_vec(self) -> Vec<String> {
+        match self {
+            Self::One(value) => vec![value],
+            Self::Many(values) => values,
+        }
+    }
+}
+
+/// `container: node:20` or `container: { image: node:20, ... }`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ContainerDef {
+    Image(String),
+    Spec(ContainerSpec),
+}
+
+/// `concurrency: deploy` or `concurrency: { group: deploy, cancel-in-progress: true }`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ConcurrencyDef {
+    Group(String),
+    Spec(ConcurrencySpec),
+}
+
+impl Pipeline {
+    /// Whether `text` looks like a pipeline (a mapping with `jobs`) rather
+    /// than a single job spec
+    pub fn is_pipeline(text: &str) -> bool {
+        serde_yaml::from_str::<Value>(text).is_ok_and(|value| value.get("jobs").is_some_and(Value::is_mapping))
+    }
+
+    /// Read and parse the pipeline at `path`
+    pub fn load(path: &Path, execution_id: &str) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read pipeline {:?}", path))?;
+        Self::parse(&text, execution_id).with_context(|| format!("Invalid pipeline {:?}", path))
+    }
+
+    /// Parse a pipeline, giving its jobs ids prefixed with `execution_id`
+    pub fn parse(text: &str, execution_id: &str) -> Result<Self> {
+        let def: PipelineDef = serde_yaml::from_str(text)?;
+        if def.jobs.is_empty() {
+            anyhow::bail!("Pipeline must have at least one job");
+        }
+        let mut environment = HashMap::new();
+        for (name, value) in &def.env {
+            environment.insert(name.clone(), matrix::scalar(value).with_context(|| format!("In env '{}'", name))?);
+        }
+
+        let mut jobs = Vec::new();
+        let mut errors = Vec::new();
+        for (key, raw) in &def.jobs {
+            let key = key.as_str().context("Job keys must be strings")?;
+            if !is_job_key(key) {
+                anyhow::bail!(
+                    "Invalid job key '{}': use letters, digits, '_' and '-', starting with a letter or '_'", key
+                );
+            }
+            let shared = Shared { run: &def.defaults.run, env: &environment, execution_id };
+            let instances = expand_job(key, raw, &shared)
+                .with_context(|| format!("In job '{}'", key))?;
+            for job in instances {
+                if let Err(job_errors) = validate_job(&job.spec) {
+                    errors.extend(job_errors.into_iter().map(|e| FieldError {
+                        field: format!("jobs.{}.{}", job.id, e.field),
+                        message: e.message,
+                    }));
+                }
+                jobs.push(job);
+            }
+        }
+        if !errors.is_empty() {
+            return Err(field_report("pipeline", &errors));
+        }
+
+        Ok(Self {
+            name: def.name.unwrap_or_else(|| "Unnamed Pipeline".into()),
+            jobs: order_by_needs(jobs)?,
+        })
+    }
+
+    /// The job `selector` names, by id or by key if the key has only one
+    /// instance; without a selector, the pipeline's only job
+    pub fn select(&self, selector: Option<&str>) -> Result<&PipelineJob> {
+        let matching: Vec<&PipelineJob> = match selector {
+            Some(selector) => {
+                let by_id: Vec<_> = self.jobs.iter().filter(|job| job.id == selector).collect();
+                if by_id.is_empty() {
+                    self.jobs.iter().filter(|job| job.key == selector).collect()
+                } else {
+                    by_id
+                }
+            }
+            None => self.jobs.iter().collect(),
+        };
+        match matching.as_slice() {
+            [job] => Ok(job),
+            _ => {
+                let ids: Vec<&str> = self.jobs.iter().map(|job| job.id.as_str()).collect();
+                match selector {
+                    Some(selector) if matching.is_empty() => {
+                        anyhow::bail!("Pipeline has no job '{}'; its jobs are {}", selector, ids.join(", "))
+                    }
+                    _ => anyhow::bail!("Choose one of the pipeline's jobs: {}", ids.join(", ")),
+                }
+            }
+        }
+    }
+}
+
+/// The instances of job `key`, one per matrix combination
+fn expand_job(key: &str, raw: &Value, shared: &Shared) -> Result<Vec<PipelineJob>> {
+    let mut raw = raw.clone();
+    let Some(fields) = raw.as_mapping_mut() else {
+        anyhow::bail!("Job must be a mapping");
+    };
+    // The matrix itself must not be substituted
+    let strategy: Option<StrategyDef> = fields.remove("strategy").map(serde_yaml::from_value).transpose()?;
+    let combinations = match strategy.and_then(|s| s.matrix) {
+        Some(matrix) => matrix::expand(&matrix)?,
+        None => vec![Vec::new()],
+    };
+
+    let instances = combinations.len();
+    combinations
+        .into_iter()
+        .enumerate()
+        .map(|(i, combination)| {
+            let mut raw = raw.clone();
+            matrix::substitute(&mut raw, &combination)?;
+            let mut def: JobDef = serde_yaml::from_value(raw)?;
+            let id = if instances > 1 { format!("{}-{}", key, i + 1) } else { key.to_string() };
+            let needs = std::mem::take(&mut def.needs).into_vec();
+            let name = def.name.take().unwrap_or_else(|| key.to_string());
+            let spec = job_spec(def, &id, &name, &combination, shared)?;
+            Ok(PipelineJob { id, key: key.to_string(), needs, matrix: combination, spec })
+        })
+        .collect()
+}
+
+fn job_spec(def: JobDef, id: &str, name: &str, combination: &Combination, shared: &Shared) -> Result<JobSpec> {
+    let mut environment = shared.env.clone();
+    for (name, value) in &def.env {
+        environment.insert(name.clone(), matrix::scalar(value).with_context(|| format!("In env '{}'", name))?);
+    }
+    let steps = def.steps
+        .into_iter()
+        .enumerate()
+        .map(|(i, step)| step_spec(step, i, shared.run).with_context(|| format!("In step {}", i + 1)))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(JobSpec {
+        job_id: format!("{}-{}", shared.execution_id, id),
+        execution_id: shared.execution_id.to_string(),
+        name: matrix::display_name(name, combination),
+        steps,
+        environment,
+        secrets: HashMap::new(),
+        container: def.container.map(|container| match container {
+            ContainerDef::Image(image) => ContainerSpec {
+                image,
+                env: HashMap::new(),
+                volumes: Vec::new(),
+                devices: Vec::new(),
+                profile: None,
+                mode: None,
+                options: None,
+                caches: Vec::new(),
+            },
+            ContainerDef::Spec(spec) => spec,
+        }),
+        executor: def.executor,
+        timeout_minutes: def.timeout_minutes.unwrap_or(DEFAULT_TIMEOUT_MINUTES),
+        workspace: WorkspaceSpec { path: ".".into(), repository_url: None, commit_sha: None, branch: None },
+        labels: def.runs_on.into_vec(),
+        cleanup: def.cleanup,
+        resources: def.resources,
+        artifacts: def.artifacts,
+        untrusted: false,
+        priority: def.priority,
+        debug_on_failure: false,
+        concurrency: def.concurrency.map(|concurrency| match concurrency {
+            ConcurrencyDef::Group(group) => ConcurrencySpec { group, cancel_in_progress: false },
+            ConcurrencyDef::Spec(spec) => spec,
+        }),
+        gpus: def.gpus,
+        retry_on: def.retry_on,
+        jit_secrets: Vec::new(),
+    })
+}
+
+fn step_spec(def: StepDef, index: usize, defaults: &RunDefaults) -> Result<StepSpec> {
+    let mut env = HashMap::new();
+    for (name, value) in &def.env {
+        env.insert(name.clone(), matrix::scalar(value).with_context(|| format!("In env '{}'", name))?);
+    }
+    let step_id = def.id.clone().unwrap_or_else(|| format!("step-{}", index + 1));
+
+    Ok(StepSpec {
+        name: def.name.unwrap_or_else(|| format!("Step {}", index + 1)),
+        step_id,
+        id: def.id,
+        run: def.run,
+        uses: def.uses,
+        with_inputs: def.with,
+        env,
+        working_directory: def.working_directory.or_else(|| defaults.working_directory.clone()),
+        shell: def.shell.or_else(|| defaults.shell.clone()).unwrap_or_else(|| "bash".into()),
+        continue_on_error: def.continue_on_error,
+        timeout_minutes: def.timeout_minutes.unwrap_or(DEFAULT_TIMEOUT_MINUTES),
+        condition: def.condition,
+        resources: def.resources,
+        output_encoding: def.output_encoding,
+        cache: def.cache,
+        secrets: def.secrets,
+        on_cancel: def.on_cancel,
+        needs: def.needs.into_vec(),
+        outputs: def.outputs,
+        limits: def.limits,
+        log: def.log,
+    })
+}
+
+/// Sort `jobs` so each comes after the jobs it needs, otherwise keeping
+/// file order
+fn order_by_needs(mut jobs: Vec<PipelineJob>) -> Result<Vec<PipelineJob>> {
+    for job in &jobs {
+        if let Some(need) = job.needs.iter().find(|need| !jobs.iter().any(|j| &j.key == *need)) {
+            anyhow::bail!("Job '{}' needs unknown job '{}'", job.key, need);
+        }
+    }
+
+    let mut ordered: Vec<PipelineJob> = Vec::with_capacity(jobs.len());
+    while !jobs.is_empty() {
+        // A job is ready once no remaining job has a key it needs
+        let Some(ready) = jobs.iter().position(|job| {
+            !job.needs.iter().any(|need| jobs.iter().any(|j| &j.key == need))
+        }) else {
+            let mut keys: Vec<&str> = jobs.iter().map(|job| job.key.as_str()).collect();
+            keys.dedup();
+            anyhow::bail!("Jobs {} depend on each other through `needs`", keys.join(", "));
+        };
+        ordered.push(jobs.remove(ready));
+    }
+    Ok(ordered)
+}
+
+fn is_job_key(key: &str) -> bool {
+    key.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_example_pipeline() {
+        let text = include_str!("../../../yaml/example-pipeline.yml");
+        assert!(Pipeline::is_pipeline(text));
+        let pipeline = Pipeline::parse(text, "local-1").unwrap();
+        assert_eq!(pipeline.name, "Build and Test");
+
+        let ids: Vec<&str> = pipeline.jobs.iter().map(|job| job.id.as_str()).collect();
+        assert_eq!(ids, vec!["lint", "test-1", "test-2", "test-3", "build", "deploy"]);
+
+        let test = pipeline.select(Some("test-2")).unwrap();
+        assert_eq!(test.spec.job_id, "local-1-test-2");
+        assert_eq!(test.spec.name, "Test (20)");
+        assert_eq!(test.spec.labels, vec!["linux", "docker"]);
+        assert_eq!(test.spec.environment["CI"], "true");
+        assert_eq!(test.spec.container.as_ref().unwrap().image, "node:20");
+        assert_eq!(test.spec.steps[2].env["NODE_VERSION"], "20");
+        assert_eq!(test.spec.steps[3].condition.as_deref(), Some("'20' == '20'"));
+        assert_eq!(test.spec.steps[0].step_id, "step-1");
+        assert_eq!(test.needs, vec!["lint"]);
+
+        assert!(test.spec.concurrency.is_none());
+
+        let lint = pipeline.select(Some("lint")).unwrap();
+        assert_eq!(lint.spec.timeout_minutes, 10);
+        assert_eq!(lint.spec.steps[1].shell, "bash");
+        assert!(pipeline.select(Some("test")).is_err());
+        assert!(pipeline.select(None).is_err());
+        assert!(pipeline.select(Some("publish")).is_err());
+    }
+
+    #[test]
+    fn test_invalid_pipeline() {
+        let order = "jobs:\n  b:\n    needs: a\n    steps: [{run: make}]\n  a:\n    steps: [{run: make}]\n";
+        let pipeline = Pipeline::parse(order, "x").unwrap();
+        assert_eq!(pipeline.jobs[0].key, "a");
+        assert_eq!(pipeline.select(None).unwrap_err().to_string(), "Choose one of the pipeline's jobs: a, b");
+
+        let cycle = "jobs:\n  a:\n    needs: b\n    steps: [{run: make}]\n  b:\n    needs: [a]\n    steps: [{run: make}]\n";
+        assert!(format!("{:#}", Pipeline::parse(cycle, "x").unwrap_err()).contains("depend on each other"));
+
+        let unknown = "jobs:\n  a:\n    needs: lint\n    steps: [{run: make}]\n";
+        assert!(format!("{:#}", Pipeline::parse(unknown, "x").unwrap_err()).contains("unknown job 'lint'"));
+
+        let invalid = "jobs:\n  a:\n    container: Node\n    steps: [{name: nothing}]\n";
+        let error = format!("{:#}", Pipeline::parse(invalid, "x").unwrap_err());
+        assert!(error.contains("jobs.a.steps[0]: must set `run` or `uses`"), "{}", error);
+        assert!(error.contains("jobs.a.container.image"), "{}", error);
+
+        assert!(Pipeline::parse("jobs: {}", "x").is_err());
+        assert!(Pipeline::parse("jobs:\n  1a:\n    steps: [{run: make}]\n", "x").is_err());
+        assert!(!Pipeline::is_pipeline("name: job\nsteps: [{run: make}]\n"));
+    }
+
+    #[test]
+    fn test_job_concurrency() {
+        let text = concat!(
+            "jobs:\n  a:\n    concurrency: deploy\n    steps: [{run: make}]\n",
+            "  b:\n    concurrency: {group: release, cancel-in-progress: true}\n    steps: [{run: make}]\n",
+        );
+        let pipeline = Pipeline::parse(text, "x").unwrap();
+        let a = pipeline.select(Some("a")).unwrap().spec.concurrency.as_ref().unwrap();
+        assert_eq!((a.group.as_str(), a.cancel_in_progress), ("deploy", false));
+        let b = pipeline.select(Some("b")).unwrap().spec.concurrency.as_ref().unwrap();
+        assert_eq!((b.group.as_str(), b.cancel_in_progress), ("release", true));
+    }
+}
@@ -0,0 +1,170 @@
+//! OpenTelemetry export
+//!
+//! Jobs, steps and their phases run inside `tracing` spans (`job` > `step` >
+//! `prepare`/`execute`/`collect`). With `tracing.enabled` an OTLP exporter is
+//! added to the subscriber, so CI executions show up in the same tracing
+//! backend as the services they build and deploy.
+
+use anyhow::{Context, Result};
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig, WithHttpConfig, WithTonicConfig};
+use opentelemetry_sdk::trace::{Sampler, Tracer, TracerProvider};
+use opentelemetry_sdk::{runtime, Resource};
+use std::time::Duration;
+use tonic::metadata::{MetadataKey, MetadataMap};
+use tonic::transport::ClientTlsConfig;
+use tracing::field::Empty;
+use tracing::{info_span, Span, Subscriber};
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::client::{JobSpec, StepSpec};
+use crate::config::{TracingConfig, TracingProtocol};
+use crate::error::RunnerError;
+use crate::executor::ExecutionPhase;
+
+/// Exports spans until shut down
+pub struct Telemetry {
+    provider: TracerProvider,
+}
+
+impl Telemetry {
+    /// Set up the OTLP exporter, if tracing is enabled
+    pub fn init(config: &TracingConfig, runner_id: &str) -> Result<Option<Self>> {
+        if !config.enabled {
+            return Ok(None);
+        }
+        if !(0.0..=1.0).contains(&config.sample_ratio) {
+            anyhow::bail!(RunnerError::ConfigError(format!(
+                "tracing.sample_ratio must be between 0.0 and 1.0, got {}", config.sample_ratio
+            )));
+        }
+
+        let timeout = Duration::from_secs(config.timeout_secs);
+        let exporter = match config.protocol {
+            TracingProtocol::Grpc => {
+                let mut metadata = MetadataMap::new();
+                for (name, value) in &config.headers {
+                    let key = MetadataKey::from_bytes(name.to_lowercase().as_bytes())
+                        .with_context(|| format!("Invalid tracing header name '{}'", name))?;
+                    let value = value.parse().with_context(|| format!("Invalid value of tracing header '{}'", name))?;
+                    metadata.insert(key, value);
+                }
+                let mut builder = SpanExporter::builder()
+                    .with_tonic()
+                    .with_endpoint(&config.endpoint)
+                    .with_timeout(timeout)
+                    .with_metadata(metadata);
+                if config.endpoint.starts_with("https://") {
+                    builder = builder.with_tls_config(ClientTlsConfig::new().with_webpki_roots());
+                }
+                builder.build()
+            }
+            TracingProtocol::Http => SpanExporter::builder()
+                .with_http()
+                .with_endpoint(&config.endpoint)
+                .with_timeout(timeout)
+                .with_headers(config.headers.clone())
+                .build(),
+        }
+        .with_context(|| format!("Failed to create the OTLP exporter for {}", config.endpoint))?;
+
+        // Steps follow their job's sampling decision
+        let sampler = Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(config.sample_ratio)));
+        let provider = TracerProvider::builder()
+            .with_batch_exporter(exporter, runtime::Tokio)
+            .with_sampler(sampler)
+            .with_resource(Resource::new([
+                KeyValue::new("service.name", config.service_name.clone()),
+                KeyValue::new("service.version", env!("CARGO_PKG_VERSION")),
+                KeyValue::new("service.instance.id", runner_id.to_string()),
+            ]))
+            .build();
+        Ok(Some(Self { provider }))
+    }
+
+    /// Subscriber layer sending spans to the exporter
+    pub fn layer<S>(&self) -> OpenTelemetryLayer<S, Tracer>
+    where
+        S: Subscriber + for<'span> LookupSpan<'span>,
+    {
+        tracing_opentelemetry::layer().with_tracer(self.provider.tracer("muelsyse-runner"))
+    }
+
+    /// Export the spans still buffered
+    pub async fn shutdown(self) {
+        // Shutting down blocks until the batch processor has drained
+        let provider = self.provider;
+        let shutdown = tokio::task::spawn_blocking(move || provider.shutdown()).await;
+        if let Ok(Err(e)) = shutdown {
+            tracing::warn!("Failed to export remaining spans: {}", e);
+        }
+    }
+}
+
+/// Span of one attempt at a job
+pub fn job_span(job: &JobSpec, attempt: u32) -> Span {
+    info_span!(
+        "job",
+        otel.name = %format!("job {}", job.name),
+        job.id = %job.job_id,
+        job.name = %job.name,
+        execution.id = %job.execution_id,
+        attempt,
+        status = Empty,
+        otel.status_code = Empty,
+    )
+}
+
+/// Span of a step, inside its job's span
+pub fn step_span(step: &StepSpec) -> Span {
+    info_span!(
+        "step",
+        otel.name = %format!("step {}", step.name),
+        step.id = %step.step_id,
+        step.name = %step.name,
+        uses = step.uses.as_deref().unwrap_or_default(),
+        status = Empty,
+        otel.status_code = Empty,
+    )
+}
+
+/// Span of an executor phase, inside its step's span
+pub fn phase_span(phase: ExecutionPhase) -> Span {
+    info_span!("phase", otel.name = %phase)
+}
+
+/// Record the outcome of a job or step on its span
+pub fn record_status(span: &Span, status: &str, failed: bool) {
+    span.record("status", status);
+    span.record("otel.status_code", if failed { "ERROR" } else { "OK" });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_init() {
+        let mut config = TracingConfig::default();
+        assert!(Telemetry::init(&config, "runner-1").unwrap().is_none());
+
+        config.enabled = true;
+        config.sample_ratio = 1.5;
+        assert!(Telemetry::init(&config, "runner-1").is_err());
+
+        // Nothing is sent until spans are exported
+        config.sample_ratio = 0.5;
+        config.headers.insert("X-Api-Key".into(), "secret".into());
+        for protocol in [TracingProtocol::Grpc, TracingProtocol::Http] {
+            config.protocol = protocol;
+            let telemetry = Telemetry::init(&config, "runner-1").unwrap().unwrap();
+            telemetry.shutdown().await;
+        }
+
+        config.protocol = TracingProtocol::Grpc;
+        config.headers.insert("bad header".into(), "x".into());
+        assert!(Telemetry::init(&config, "runner-1").is_err());
+    }
+}
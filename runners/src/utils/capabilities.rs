@@ -0,0 +1,25 @@
+//! Host capability detection
+
+use std::sync::OnceLock;
+
+static KVM: OnceLock<bool> = OnceLock::new();
+
+/// Whether `/dev/kvm` exists and is usable by the runner, detected once
+pub fn kvm_available() -> bool {
+    *KVM.get_or_init(|| {
+        std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/kvm")
+            .is_ok()
+    })
+}
+
+/// Optional host capabilities advertised to the control plane
+pub fn capabilities() -> Vec<String> {
+    let mut capabilities = Vec::new();
+    if kvm_available() {
+        capabilities.push("kvm".to_string());
+    }
+    capabilities
+}
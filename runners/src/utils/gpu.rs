@@ -0,0 +1,147 @@
+//! NVIDIA GPU detection and assignment
+//!
+//! The GPUs are detected once, with `nvidia-smi` or else from the
+//! `/dev/nvidia<N>` device nodes, and advertised with the `gpu` label and
+//! `gpu_count` in heartbeats. A job asking for `gpus: N` is leased N of them
+//! for as long as it runs: Docker steps get them as device requests, host
+//! steps through `CUDA_VISIBLE_DEVICES`.
+
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::sync::{Mutex, OnceLock};
+use tracing::{debug, info};
+
+use crate::error::RunnerError;
+
+static GPUS: OnceLock<Vec<GpuDevice>> = OnceLock::new();
+
+/// GPU indices leased to running jobs, by job id
+static LEASES: Mutex<BTreeMap<String, Vec<u32>>> = Mutex::new(BTreeMap::new());
+
+/// An NVIDIA GPU of this host
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GpuDevice {
+    /// Index as used by `CUDA_VISIBLE_DEVICES` and `nvidia-smi`
+    pub index: u32,
+    /// Model name, if `nvidia-smi` reported it
+    pub name: Option<String>,
+}
+
+/// The host's NVIDIA GPUs, detected on first use
+pub fn nvidia_gpus() -> &'static [GpuDevice] {
+    GPUS.get_or_init(|| {
+        let gpus = query_nvidia_smi().unwrap_or_else(device_nodes);
+        if !gpus.is_empty() {
+            info!("Detected {} NVIDIA GPUs", gpus.len());
+        }
+        gpus
+    })
+}
+
+fn query_nvidia_smi() -> Option<Vec<GpuDevice>> {
+    let output = std::process::Command::new("nvidia-smi")
+        .args(["--query-gpu=index,name", "--format=csv,noheader"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    Some(parse_nvidia_smi(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// GPUs in `nvidia-smi --query-gpu=index,name --format=csv,noheader` output
+fn parse_nvidia_smi(output: &str) -> Vec<GpuDevice> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (index, name) = line.split_once(',')?;
+            Some(GpuDevice { index: index.trim().parse().ok()?, name: Some(name.trim().to_string()) })
+        })
+        .collect()
+}
+
+fn device_nodes() -> Vec<GpuDevice> {
+    let names = std::fs::read_dir("/dev")
+        .map(|entries| entries.filter_map(|e| e.ok()?.file_name().into_string().ok()).collect())
+        .unwrap_or_default();
+    node_indices(names)
+}
+
+/// Indices of `nvidia<N>` device nodes, ignoring `nvidiactl` and the like
+fn node_indices(names: Vec<String>) -> Vec<GpuDevice> {
+    let mut indices: Vec<u32> = names
+        .iter()
+        .filter_map(|name| name.strip_prefix("nvidia")?.parse().ok())
+        .collect();
+    indices.sort_unstable();
+    indices.into_iter().map(|index| GpuDevice { index, name: None }).collect()
+}
+
+/// GPUs leased to a job, given back when dropped
+#[derive(Debug)]
+pub struct GpuLease {
+    job_id: String,
+    /// Indices of the leased GPUs
+    pub devices: Vec<u32>,
+}
+
+impl Drop for GpuLease {
+    fn drop(&mut self) {
+        if !self.devices.is_empty() {
+            LEASES.lock().unwrap_or_else(|e| e.into_inner()).remove(&self.job_id);
+        }
+    }
+}
+
+/// Lease `count` free GPUs to `job_id`
+pub fn lease_gpus(job_id: &str, count: u32) -> Result<GpuLease> {
+    lease_from(nvidia_gpus(), job_id, count)
+}
+
+fn lease_from(gpus: &[GpuDevice], job_id: &str, count: u32) -> Result<GpuLease> {
+    if count == 0 {
+        return Ok(GpuLease { job_id: job_id.to_string(), devices: Vec::new() });
+    }
+    let mut leases = LEASES.lock().unwrap_or_else(|e| e.into_inner());
+    let devices: Vec<u32> = gpus
+        .iter()
+        .map(|gpu| gpu.index)
+        .filter(|index| !leases.values().flatten().any(|leased| leased == index))
+        .take(count as usize)
+        .collect();
+    if devices.len() < count as usize {
+        anyhow::bail!(RunnerError::InfraError(format!(
+            "Job needs {} GPUs, {} of {} are free", count, devices.len(), gpus.len()
+        )));
+    }
+    leases.insert(job_id.to_string(), devices.clone());
+    debug!("Job {} runs on GPUs {:?}", job_id, devices);
+    Ok(GpuLease { job_id: job_id.to_string(), devices })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detection() {
+        let output = "0, NVIDIA A100-SXM4-40GB\n1, NVIDIA A100-SXM4-40GB\n";
+        let gpus = parse_nvidia_smi(output);
+        assert_eq!(gpus.len(), 2);
+        assert_eq!(gpus[1], GpuDevice { index: 1, name: Some("NVIDIA A100-SXM4-40GB".into()) });
+
+        let names = ["nvidia1", "nvidiactl", "nvidia-uvm", "nvidia0", "null"].map(String::from).to_vec();
+        let indices: Vec<u32> = node_indices(names).iter().map(|gpu| gpu.index).collect();
+        assert_eq!(indices, vec![0, 1]);
+    }
+
+    #[test]
+    fn test_lease_gpus() {
+        let gpus = node_indices(vec!["nvidia0".into(), "nvidia1".into(), "nvidia2".into()]);
+        let train = lease_from(&gpus, "lease-train", 2).unwrap();
+        assert_eq!(train.devices, vec![0, 1]);
+        assert!(lease_from(&gpus, "lease-eval", 2).is_err());
+        assert!(lease_from(&gpus, "lease-lint", 0).unwrap().devices.is_empty());
+
+        drop(train);
+        assert_eq!(lease_from(&gpus, "lease-eval", 2).unwrap().devices, vec![0, 1]);
+    }
+}
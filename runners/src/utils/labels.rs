@@ -0,0 +1,190 @@
+//! Host label detection
+//!
+//! With `runner.auto_labels` on, the runner inspects its host on start and
+//! adds what it finds to the configured labels: the OS and architecture,
+//! `docker` when the daemon answers, `gpu` when a GPU device is present, a
+//! memory class such as `memory:16gb`, and `cloud:<provider>` with
+//! `zone:<zone>` when an instance metadata service answers.
+
+use bollard::Docker;
+use serde::Deserialize;
+use std::time::Duration;
+use tracing::debug;
+
+use super::system::get_system_info;
+use crate::config::Settings;
+
+const DOCKER_TIMEOUT: Duration = Duration::from_secs(2);
+const METADATA_TIMEOUT: Duration = Duration::from_secs(1);
+
+const GPU_DEVICES: [&str; 3] = ["/dev/nvidiactl", "/dev/nvidia0", "/dev/kfd"];
+
+/// Labels describing this host
+pub async fn detect_labels(settings: &Settings) -> Vec<String> {
+    let mut labels = vec![std::env::consts::OS.to_string(), std::env::consts::ARCH.to_string()];
+
+    if docker_available(&settings.executor.docker.socket).await {
+        labels.push("docker".to_string());
+    }
+    if !super::nvidia_gpus().is_empty() || GPU_DEVICES.iter().any(|device| std::path::Path::new(device).exists()) {
+        labels.push("gpu".to_string());
+    }
+    labels.push(memory_class(get_system_info().total_memory_mb));
+
+    if let Some((provider, zone)) = detect_cloud().await {
+        labels.push(format!("cloud:{}", provider));
+        if let Some(zone) = zone {
+            labels.push(format!("zone:{}", zone));
+        }
+    }
+
+    debug!("Detected host labels: {:?}", labels);
+    labels
+}
+
+/// Configured labels followed by detected ones not already present
+pub fn merge_labels(configured: &[String], detected: Vec<String>) -> Vec<String> {
+    let mut labels = configured.to_vec();
+    for label in detected {
+        if !labels.contains(&label) {
+            labels.push(label);
+        }
+    }
+    labels
+}
+
+/// Memory rounded down to a power of two gigabytes, e.g. `memory:16gb`
+fn memory_class(total_memory_mb: u64) -> String {
+    let gb = (total_memory_mb / 1024).max(1);
+    format!("memory:{}gb", 1u64 << gb.ilog2())
+}
+
+async fn docker_available(socket: &str) -> bool {
+    let docker = if socket.starts_with("unix://") || socket.starts_with('/') {
+        Docker::connect_with_socket(socket, DOCKER_TIMEOUT.as_secs(), bollard::API_DEFAULT_VERSION)
+    } else {
+        Docker::connect_with_socket_defaults()
+    };
+    let Ok(docker) = docker else {
+        return false;
+    };
+    matches!(tokio::time::timeout(DOCKER_TIMEOUT, docker.ping()).await, Ok(Ok(_)))
+}
+
+/// Cloud provider and zone from whichever metadata service answers
+async fn detect_cloud() -> Option<(&'static str, Option<String>)> {
+    let client = reqwest::Client::builder()
+        .timeout(METADATA_TIMEOUT)
+        .no_proxy()
+        .build()
+        .ok()?;
+
+    let (aws, gcp, azure) = tokio::join!(aws_zone(&client), gcp_zone(&client), azure_zone(&client));
+    aws.map(|zone| ("aws", Some(zone)))
+        .or_else(|| gcp.map(|zone| ("gcp", Some(zone))))
+        .or_else(|| azure.map(|zone| ("azure", zone)))
+}
+
+/// Availability zone from the EC2 instance metadata service (IMDSv2)
+async fn aws_zone(client: &reqwest::Client) -> Option<String> {
+    let token = client
+        .put("http://169.254.169.254/latest/api/token")
+        .header("X-aws-ec2-metadata-token-ttl-seconds", "60")
+        .send()
+        .await
+        .ok()?
+        .error_for_status()
+        .ok()?
+        .text()
+        .await
+        .ok()?;
+
+    let zone = client
+        .get("http://169.254.169.254/latest/meta-data/placement/availability-zone")
+        .header("X-aws-ec2-metadata-token", token)
+        .send()
+        .await
+        .ok()?
+        .error_for_status()
+        .ok()?
+        .text()
+        .await
+        .ok()?;
+    Some(zone.trim().to_string())
+}
+
+/// Zone from the GCE metadata server
+async fn gcp_zone(client: &reqwest::Client) -> Option<String> {
+    let zone = client
+        .get("http://metadata.google.internal/computeMetadata/v1/instance/zone")
+        .header("Metadata-Flavor", "Google")
+        .send()
+        .await
+        .ok()?
+        .error_for_status()
+        .ok()?
+        .text()
+        .await
+        .ok()?;
+    Some(last_segment(&zone))
+}
+
+#[derive(Debug, Deserialize)]
+struct AzureCompute {
+    location: String,
+    #[serde(default)]
+    zone: String,
+}
+
+/// Region, plus the zone number if any, from the Azure instance metadata
+/// service; `Some(None)` for an Azure host without a location
+async fn azure_zone(client: &reqwest::Client) -> Option<Option<String>> {
+    let compute: AzureCompute = client
+        .get("http://169.254.169.254/metadata/instance/compute?api-version=2021-02-01")
+        .header("Metadata", "true")
+        .send()
+        .await
+        .ok()?
+        .error_for_status()
+        .ok()?
+        .json()
+        .await
+        .ok()?;
+    Some(azure_zone_label(&compute))
+}
+
+fn azure_zone_label(compute: &AzureCompute) -> Option<String> {
+    match (compute.location.as_str(), compute.zone.as_str()) {
+        ("", _) => None,
+        (location, "") => Some(location.to_string()),
+        (location, zone) => Some(format!("{}-{}", location, zone)),
+    }
+}
+
+/// `projects/123/zones/europe-west1-b` -> `europe-west1-b`
+fn last_segment(path: &str) -> String {
+    path.trim().rsplit('/').next().unwrap_or_default().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_label_helpers() {
+        assert_eq!(memory_class(512), "memory:1gb");
+        assert_eq!(memory_class(15_900), "memory:8gb");
+        assert_eq!(memory_class(16_384), "memory:16gb");
+        assert_eq!(memory_class(64_000), "memory:32gb");
+
+        let configured = vec!["linux".to_string(), "shell".to_string()];
+        let detected = vec!["linux".to_string(), "x86_64".to_string(), "docker".to_string()];
+        assert_eq!(merge_labels(&configured, detected), ["linux", "shell", "x86_64", "docker"]);
+
+        assert_eq!(last_segment("projects/123/zones/europe-west1-b\n"), "europe-west1-b");
+        let compute = |location: &str, zone: &str| AzureCompute { location: location.into(), zone: zone.into() };
+        assert_eq!(azure_zone_label(&compute("westeurope", "2")), Some("westeurope-2".to_string()));
+        assert_eq!(azure_zone_label(&compute("westeurope", "")), Some("westeurope".to_string()));
+        assert_eq!(azure_zone_label(&compute("", "")), None);
+    }
+}
@@ -1,5 +1,15 @@
 //! Utility functions
 
+pub mod capabilities;
+pub mod gpu;
+pub mod labels;
+pub mod shells;
+pub mod stats;
 pub mod system;
 
+pub use capabilities::{capabilities, kvm_available};
+pub use gpu::{lease_gpus, nvidia_gpus, GpuLease};
+pub use labels::{detect_labels, merge_labels};
+pub use shells::{available_shells, select_shell};
+pub use stats::{HostStats, StatsCache};
 pub use system::get_system_info;
@@ -0,0 +1,88 @@
+//! Shell availability detection
+
+use anyhow::Result;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use crate::error::RunnerError;
+
+/// Shells the runner knows how to invoke
+pub const KNOWN_SHELLS: &[&str] = &["bash", "sh", "zsh", "fish", "pwsh", "cmd"];
+
+static AVAILABLE: OnceLock<Vec<String>> = OnceLock::new();
+
+/// Shells found on `PATH`, detected once per process
+pub fn available_shells() -> &'static [String] {
+    AVAILABLE.get_or_init(|| {
+        let path = std::env::var_os("PATH").unwrap_or_default();
+        let dirs: Vec<_> = std::env::split_paths(&path).collect();
+
+        KNOWN_SHELLS
+            .iter()
+            .filter(|shell| dirs.iter().any(|dir| is_executable_in(dir, shell)))
+            .map(|shell| shell.to_string())
+            .collect()
+    })
+}
+
+fn is_executable_in(dir: &Path, program: &str) -> bool {
+    let candidate = dir.join(program);
+    if candidate.is_file() {
+        return true;
+    }
+    cfg!(windows) && dir.join(format!("{}.exe", program)).is_file()
+}
+
+/// Pick the shell to run for `requested`.
+///
+/// Returns `requested` itself when its program is available, otherwise the
+/// first available entry of `fallback`. A full shell string such as
+/// `zsh -e {0}` does not fall back, as its options may mean something else
+/// to another shell or nothing at all.
+pub fn select_shell(requested: &str, available: &[String], fallback: &[String]) -> Result<String> {
+    let program = requested.split_whitespace().next().unwrap_or_default();
+    // "powershell" is invoked as pwsh
+    let program = if program == "powershell" { "pwsh" } else { program };
+
+    if available.iter().any(|s| s == program) || !KNOWN_SHELLS.contains(&program) {
+        return Ok(requested.to_string());
+    }
+    if program != requested.trim() {
+        anyhow::bail!(RunnerError::ConfigError(format!(
+            "Shell '{}' is not installed, and shells with options do not fall back",
+            requested
+        )));
+    }
+
+    match fallback.iter().find(|s| available.contains(s)) {
+        Some(shell) => Ok(shell.clone()),
+        None => anyhow::bail!(RunnerError::ConfigError(format!(
+            "Shell '{}' is not installed and no fallback of {:?} is available",
+            requested, fallback
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_shell() {
+        let available = vec!["bash".to_string(), "sh".to_string()];
+        let fallback = vec!["bash".to_string(), "sh".to_string()];
+
+        assert_eq!(select_shell("bash", &available, &fallback).unwrap(), "bash");
+        assert_eq!(select_shell("zsh", &available, &fallback).unwrap(), "bash");
+        assert!(select_shell("zsh -e {0}", &available, &fallback).unwrap_err().to_string().contains("options"));
+        assert_eq!(select_shell("bash -e {0}", &available, &fallback).unwrap(), "bash -e {0}");
+        assert_eq!(select_shell("fish", &["sh".to_string()], &fallback).unwrap(), "sh");
+        assert!(select_shell("fish", &[], &fallback).is_err());
+    }
+
+    #[test]
+    fn test_custom_programs_pass_through() {
+        // Interpreters outside the known list are left to the spawn
+        assert_eq!(select_shell("python3 -u", &[], &[]).unwrap(), "python3 -u");
+    }
+}
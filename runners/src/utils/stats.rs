@@ -0,0 +1,104 @@
+//! Cached host statistics
+//!
+//! Building a `sysinfo::System` enumerates every process, disk and network
+//! interface, which adds up when done per heartbeat. A single sampler is
+//! refreshed in the background instead, reading only CPU and memory, and
+//! heartbeats and status reports use its latest snapshot.
+
+use std::sync::{Mutex, OnceLock, RwLock};
+use std::time::Duration;
+use sysinfo::System;
+
+/// CPU and memory usage of the host at one point in time
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct HostStats {
+    pub cpu_count: usize,
+    /// Average over all CPUs since the previous sample
+    pub cpu_usage_percent: f32,
+    pub memory_total_mb: u64,
+    pub memory_used_mb: u64,
+}
+
+impl HostStats {
+    pub fn memory_usage_percent(&self) -> f32 {
+        if self.memory_total_mb > 0 {
+            (self.memory_used_mb as f32 / self.memory_total_mb as f32) * 100.0
+        } else {
+            0.0
+        }
+    }
+}
+
+static STATS: OnceLock<StatsCache> = OnceLock::new();
+
+/// Host statistics, sampled periodically
+pub struct StatsCache {
+    system: Mutex<System>,
+    latest: RwLock<HostStats>,
+}
+
+impl StatsCache {
+    /// The runner-wide cache, sampled once on first use
+    pub fn global() -> &'static Self {
+        STATS.get_or_init(|| {
+            let cache = Self {
+                system: Mutex::new(System::new()),
+                latest: RwLock::new(HostStats::default()),
+            };
+            cache.refresh();
+            cache
+        })
+    }
+
+    /// The most recent sample
+    pub fn latest(&self) -> HostStats {
+        *self.latest.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Take a new sample
+    pub fn refresh(&self) -> HostStats {
+        let stats = {
+            let mut system = self.system.lock().unwrap_or_else(|e| e.into_inner());
+            system.refresh_cpu();
+            system.refresh_memory();
+            HostStats {
+                cpu_count: system.cpus().len(),
+                cpu_usage_percent: system.global_cpu_info().cpu_usage(),
+                memory_total_mb: system.total_memory() / 1024 / 1024,
+                memory_used_mb: system.used_memory() / 1024 / 1024,
+            }
+        };
+        *self.latest.write().unwrap_or_else(|e| e.into_inner()) = stats;
+        stats
+    }
+
+    /// Refresh the global cache every `interval`
+    pub fn spawn_refresh(interval: Duration) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            let cache = Self::global();
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                cache.refresh();
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats_cache() {
+        let cache = StatsCache::global();
+        let stats = cache.latest();
+        assert!(stats.cpu_count > 0);
+        assert!(stats.memory_total_mb > 0);
+        assert!(stats.memory_usage_percent() <= 100.0);
+
+        let refreshed = cache.refresh();
+        assert_eq!(cache.latest(), refreshed);
+        assert_eq!(refreshed.memory_total_mb, stats.memory_total_mb);
+    }
+}
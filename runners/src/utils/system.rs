@@ -2,6 +2,8 @@
 
 use sysinfo::System;
 
+use super::stats::StatsCache;
+
 /// System information
 #[derive(Debug, Clone)]
 pub struct SystemInfo {
@@ -15,14 +17,14 @@ pub struct SystemInfo {
 
 /// Get system information
 pub fn get_system_info() -> SystemInfo {
-    let sys = System::new_all();
+    let stats = StatsCache::global().latest();
 
     SystemInfo {
         os_name: System::name().unwrap_or_else(|| "Unknown".into()),
         os_version: System::os_version().unwrap_or_else(|| "Unknown".into()),
         arch: std::env::consts::ARCH.to_string(),
         hostname: System::host_name().unwrap_or_else(|| "Unknown".into()),
-        cpu_count: sys.cpus().len(),
-        total_memory_mb: sys.total_memory() / 1024 / 1024,
+        cpu_count: stats.cpu_count,
+        total_memory_mb: stats.memory_total_mb,
     }
 }
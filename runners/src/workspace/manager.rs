@@ -0,0 +1,340 @@
+//! Workspace creation and removal
+
+use anyhow::{Result, Context};
+use chrono::{NaiveDateTime, Utc};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::{info, warn, debug};
+
+use crate::config::{WorkspaceConfig, SnapshotConfig};
+use super::overlay::{overlay_supported, mount_overlay, unmount_overlay};
+use super::snapshot::{SnapshotKind, apply_snapshot};
+
+/// A prepared job workspace
+#[derive(Debug, Clone)]
+pub struct Workspace {
+    /// Path steps run in
+    pub path: PathBuf,
+
+    /// Overlay upper/work directory, if the workspace is an overlay mount
+    pub overlay_state: Option<PathBuf>,
+
+    /// The job's temporary directory, next to the workspace so it goes with it
+    pub temp_dir: PathBuf,
+}
+
+impl Workspace {
+    /// Whether the workspace is backed by an OverlayFS mount
+    pub fn is_overlay(&self) -> bool {
+        self.overlay_state.is_some()
+    }
+}
+
+/// Creates job workspaces, layering configured base snapshots
+pub struct WorkspaceManager {
+    config: WorkspaceConfig,
+}
+
+impl WorkspaceManager {
+    pub fn new(config: WorkspaceConfig) -> Self {
+        Self { config }
+    }
+
+    /// Create the workspace for a job, applying snapshots matching its labels
+    pub async fn create(&self, job_id: &str, labels: &[String]) -> Result<Workspace> {
+        let path = self.config.base_path.join(job_id);
+
+        tokio::fs::create_dir_all(&path)
+            .await
+            .context("Failed to create job workspace")?;
+
+        let start = Instant::now();
+        let snapshots: Vec<&SnapshotConfig> = self.config.snapshots
+            .iter()
+            .filter(|s| labels.contains(&s.label))
+            .collect();
+
+        // Whole-workspace directory snapshots can be overlay lower layers
+        let (layered, copied): (Vec<&SnapshotConfig>, Vec<&SnapshotConfig>) = snapshots
+            .into_iter()
+            .partition(|s| {
+                self.config.overlay
+                    && s.target.is_none()
+                    && SnapshotKind::from_path(&s.path) == SnapshotKind::Directory
+            });
+
+        let mut overlay_state = None;
+        let mut pending = copied;
+
+        // Snapshots sit above the shared base layers
+        let lower_dirs: Vec<PathBuf> = layered
+            .iter()
+            .map(|s| s.path.clone())
+            .chain(self.config.overlay_lower_dirs.iter().cloned())
+            .collect();
+
+        if self.config.overlay && lower_dirs.is_empty() {
+            // A runner without any layers is reported once at startup
+            debug!("No overlay layers for job {}, using plain workspace", job_id);
+        } else if self.config.overlay {
+            match self.mount(job_id, &path, &lower_dirs).await {
+                Ok(state_dir) => overlay_state = Some(state_dir),
+                Err(e) => {
+                    warn!("OverlayFS unavailable, using plain workspace for job {}: {}", job_id, e);
+                    pending.extend(layered);
+                }
+            }
+        }
+
+        for snapshot in &pending {
+            apply_snapshot(snapshot, &path).await?;
+        }
+
+        if overlay_state.is_some() || !pending.is_empty() {
+            info!(
+                "Prepared workspace for job {} (overlay: {}, snapshots copied: {}) in {:?}",
+                job_id,
+                overlay_state.is_some(),
+                pending.len(),
+                start.elapsed()
+            );
+        }
+
+        let temp_dir = self.temp_dir(job_id);
+        create_temp_dir(&temp_dir).await?;
+
+        Ok(Workspace { path, overlay_state, temp_dir })
+    }
+
+    /// Temporary directory of a job, exported to its steps as `TMPDIR`
+    pub fn temp_dir(&self, job_id: &str) -> PathBuf {
+        self.config.base_path.join(".tmp").join(job_id)
+    }
+
+    /// Mount an overlay workspace, returning its state directory
+    async fn mount(&self, job_id: &str, target: &Path, lower_dirs: &[PathBuf]) -> Result<PathBuf> {
+        if !overlay_supported() {
+            anyhow::bail!("kernel does not support OverlayFS");
+        }
+
+        let state_dir = self.config.base_path.join(".overlay").join(job_id);
+        if let Err(e) = mount_overlay(lower_dirs, &state_dir, target).await {
+            let _ = tokio::fs::remove_dir_all(&state_dir).await;
+            return Err(e);
+        }
+
+        Ok(state_dir)
+    }
+
+    /// Directory holding workspaces retained by cleanup policies
+    pub fn retained_dir(&self) -> PathBuf {
+        self.config.base_path.join(".retained")
+    }
+
+    /// Retain a workspace for debugging, returning where it was moved
+    ///
+    /// Overlay workspaces keep only their upper layer (the files the job
+    /// created or changed), since the shared lower layers are still on disk.
+    pub async fn retain(&self, workspace: &Workspace, job_id: &str) -> Result<PathBuf> {
+        if let Err(e) = self.prune_retained().await {
+            warn!("Failed to prune retained workspaces: {}", e);
+        }
+
+        let retained_dir = self.retained_dir();
+        tokio::fs::create_dir_all(&retained_dir)
+            .await
+            .context("Failed to create retained workspace directory")?;
+
+        // Timestamp prefix keeps entries ordered by retention time
+        let dest = retained_dir.join(format!(
+            "{}-{}",
+            Utc::now().format(RETAINED_TIMESTAMP_FORMAT),
+            job_id
+        ));
+
+        match workspace.overlay_state {
+            Some(ref state_dir) => {
+                tokio::fs::rename(state_dir.join("upper"), &dest)
+                    .await
+                    .context("Failed to retain overlay workspace")?;
+                self.remove(workspace).await;
+            }
+            None => {
+                tokio::fs::rename(&workspace.path, &dest)
+                    .await
+                    .context("Failed to retain workspace")?;
+                remove_temp_dir(&workspace.temp_dir).await;
+            }
+        }
+
+        info!("Retained workspace for job {} at {:?}", job_id, dest);
+        Ok(dest)
+    }
+
+    /// Remove retained workspaces past their TTL or over the disk quota
+    pub async fn prune_retained(&self) -> Result<usize> {
+        let retained_dir = self.retained_dir();
+        let ttl = Duration::from_secs(self.config.retention_ttl_hours * 3600);
+        let max_bytes = self.config.retention_max_bytes;
+
+        let to_remove = tokio::task::spawn_blocking(move || {
+            select_retained_for_pruning(&retained_dir, ttl, max_bytes)
+        }).await??;
+
+        for path in &to_remove {
+            debug!("Pruning retained workspace {:?}", path);
+            if let Err(e) = tokio::fs::remove_dir_all(path).await {
+                warn!("Failed to prune retained workspace {:?}: {}", path, e);
+            }
+        }
+
+        Ok(to_remove.len())
+    }
+
+    /// Remove a job workspace
+    pub async fn remove(&self, workspace: &Workspace) {
+        if let Some(ref state_dir) = workspace.overlay_state {
+            if let Err(e) = unmount_overlay(&workspace.path).await {
+                warn!("Failed to unmount overlay workspace: {}", e);
+            }
+            if let Err(e) = tokio::fs::remove_dir_all(state_dir).await {
+                warn!("Failed to cleanup overlay state: {}", e);
+            }
+        }
+
+        if let Err(e) = tokio::fs::remove_dir_all(&workspace.path).await {
+            warn!("Failed to cleanup workspace: {}", e);
+        }
+        remove_temp_dir(&workspace.temp_dir).await;
+    }
+}
+
+/// Create an empty temporary directory, writable like `/tmp` by whichever
+/// user the job's steps or containers run as
+async fn create_temp_dir(path: &Path) -> Result<()> {
+    // Left behind by an earlier attempt or a crashed runner
+    if path.exists() {
+        tokio::fs::remove_dir_all(path)
+            .await
+            .with_context(|| format!("Failed to clear temporary directory {:?}", path))?;
+    }
+    tokio::fs::create_dir_all(path)
+        .await
+        .with_context(|| format!("Failed to create temporary directory {:?}", path))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(0o1777))
+            .await
+            .with_context(|| format!("Failed to set permissions of {:?}", path))?;
+    }
+    Ok(())
+}
+
+async fn remove_temp_dir(path: &Path) {
+    if let Err(e) = tokio::fs::remove_dir_all(path).await {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            warn!("Failed to cleanup temporary directory {:?}: {}", path, e);
+        }
+    }
+}
+
+const RETAINED_TIMESTAMP_FORMAT: &str = "%Y%m%d%H%M%S";
+
+/// Pick retained workspaces to prune: expired ones first, then oldest until
+/// the total size fits the quota
+fn select_retained_for_pruning(dir: &Path, ttl: Duration, max_bytes: u64) -> Result<Vec<PathBuf>> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let now = Utc::now().naive_utc();
+    let mut entries: Vec<(PathBuf, NaiveDateTime, u64)> = Vec::new();
+
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+
+        let Some(retained_at) = name
+            .split_once('-')
+            .and_then(|(ts, _)| NaiveDateTime::parse_from_str(ts, RETAINED_TIMESTAMP_FORMAT).ok())
+        else {
+            continue;
+        };
+
+        entries.push((path.clone(), retained_at, dir_size(&path)));
+    }
+
+    // Oldest first
+    entries.sort_by_key(|(_, retained_at, _)| *retained_at);
+
+    let mut to_remove = Vec::new();
+    let mut total: u64 = entries.iter().map(|(_, _, size)| size).sum();
+
+    for (path, retained_at, size) in entries {
+        let age = (now - retained_at).to_std().unwrap_or_default();
+        let over_quota = max_bytes > 0 && total > max_bytes;
+
+        if age > ttl || over_quota {
+            total -= size;
+            to_remove.push(path);
+        }
+    }
+
+    Ok(to_remove)
+}
+
+/// Total size of regular files under a path
+pub fn dir_size(path: &Path) -> u64 {
+    let Ok(metadata) = std::fs::symlink_metadata(path) else {
+        return 0;
+    };
+
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+
+    std::fs::read_dir(path)
+        .map(|entries| {
+            entries
+                .filter_map(|e| e.ok())
+                .map(|e| dir_size(&e.path()))
+                .sum()
+        })
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_retained_for_pruning() {
+        let dir = std::env::temp_dir().join(format!("muelsyse-retained-{}", uuid::Uuid::new_v4()));
+        let now = Utc::now();
+
+        let entries = [
+            (now - chrono::Duration::hours(48), "job-expired", 10),
+            (now - chrono::Duration::hours(2), "job-old", 100),
+            (now - chrono::Duration::hours(1), "job-new", 100),
+        ];
+
+        for (at, job_id, size) in entries {
+            let path = dir.join(format!("{}-{}", at.format(RETAINED_TIMESTAMP_FORMAT), job_id));
+            std::fs::create_dir_all(&path).unwrap();
+            std::fs::write(path.join("log.txt"), vec![0u8; size]).unwrap();
+        }
+
+        let pruned = select_retained_for_pruning(&dir, Duration::from_secs(24 * 3600), 150).unwrap();
+        let names: Vec<String> = pruned
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+
+        assert_eq!(names.len(), 2);
+        assert!(names[0].ends_with("job-expired"));
+        assert!(names[1].ends_with("job-old"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
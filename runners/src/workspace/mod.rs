@@ -0,0 +1,9 @@
+//! Job workspace management
+
+mod manager;
+mod overlay;
+mod snapshot;
+
+pub use manager::{dir_size, Workspace, WorkspaceManager};
+pub use overlay::overlay_supported;
+pub use snapshot::{SnapshotKind, apply_snapshot};
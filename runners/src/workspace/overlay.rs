@@ -0,0 +1,112 @@
+//! OverlayFS copy-on-write workspaces (Linux only)
+//!
+//! A job workspace is mounted as an overlay whose lower layers are shared,
+//! read-only base directories (repo mirrors, dependency caches, directory
+//! snapshots). Writes land in a per-job upper directory, so setup is a
+//! single mount and per-job disk usage is limited to what the job changes.
+
+use anyhow::{Result, Context};
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+
+/// Check whether the kernel supports OverlayFS
+pub fn overlay_supported() -> bool {
+    if !cfg!(target_os = "linux") {
+        return false;
+    }
+
+    std::fs::read_to_string("/proc/filesystems")
+        .map(|fs| fs.lines().any(|line| line.trim_end().ends_with("overlay")))
+        .unwrap_or(false)
+}
+
+/// Build the `-o` option string for an overlay mount
+///
+/// The first lower directory is the topmost layer.
+pub fn mount_options(lower_dirs: &[PathBuf], upper_dir: &Path, work_dir: &Path) -> String {
+    let lower = lower_dirs
+        .iter()
+        .map(|p| p.display().to_string())
+        .collect::<Vec<_>>()
+        .join(":");
+
+    format!(
+        "lowerdir={},upperdir={},workdir={}",
+        lower,
+        upper_dir.display(),
+        work_dir.display()
+    )
+}
+
+/// Mount an overlay at `target`, keeping upper/work directories in `state_dir`
+pub async fn mount_overlay(lower_dirs: &[PathBuf], state_dir: &Path, target: &Path) -> Result<()> {
+    if lower_dirs.is_empty() {
+        anyhow::bail!("OverlayFS requires at least one lower directory");
+    }
+
+    let upper_dir = state_dir.join("upper");
+    let work_dir = state_dir.join("work");
+
+    for dir in [&upper_dir, &work_dir] {
+        tokio::fs::create_dir_all(dir)
+            .await
+            .context("Failed to create overlay state directory")?;
+    }
+
+    let output = Command::new("mount")
+        .arg("-t")
+        .arg("overlay")
+        .arg("overlay")
+        .arg("-o")
+        .arg(mount_options(lower_dirs, &upper_dir, &work_dir))
+        .arg(target)
+        .output()
+        .await
+        .context("Failed to spawn mount")?;
+
+    if !output.status.success() {
+        anyhow::bail!(
+            "Overlay mount failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(())
+}
+
+/// Unmount an overlay workspace
+pub async fn unmount_overlay(target: &Path) -> Result<()> {
+    let output = Command::new("umount")
+        .arg(target)
+        .output()
+        .await
+        .context("Failed to spawn umount")?;
+
+    if !output.status.success() {
+        anyhow::bail!(
+            "Overlay unmount failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mount_options() {
+        let options = mount_options(
+            &[PathBuf::from("/snap/node"), PathBuf::from("/mirror/repo")],
+            Path::new("/ws/.overlay/job-1/upper"),
+            Path::new("/ws/.overlay/job-1/work"),
+        );
+
+        assert_eq!(
+            options,
+            "lowerdir=/snap/node:/mirror/repo,upperdir=/ws/.overlay/job-1/upper,workdir=/ws/.overlay/job-1/work"
+        );
+    }
+}
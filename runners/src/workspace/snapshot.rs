@@ -0,0 +1,99 @@
+//! Base workspace snapshots
+//!
+//! Snapshots are tarballs or directories configured per label that are
+//! layered into a fresh job workspace before the first step runs, so jobs
+//! start with preinstalled dependencies instead of fetching them.
+
+use anyhow::{Result, Context};
+use std::path::Path;
+use tokio::process::Command;
+use tracing::debug;
+
+use crate::config::SnapshotConfig;
+
+/// How a snapshot is materialized into a workspace
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotKind {
+    /// Archive extracted with `tar`
+    Tarball,
+    /// Directory copied (or used as an overlay lower layer)
+    Directory,
+}
+
+impl SnapshotKind {
+    /// Infer the snapshot kind from its path
+    pub fn from_path(path: &Path) -> Self {
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+
+        const ARCHIVE_SUFFIXES: &[&str] = &[".tar", ".tar.gz", ".tgz", ".tar.xz", ".tar.zst", ".tar.bz2"];
+
+        if ARCHIVE_SUFFIXES.iter().any(|suffix| name.ends_with(suffix)) {
+            Self::Tarball
+        } else {
+            Self::Directory
+        }
+    }
+}
+
+/// Materialize a snapshot into the given workspace directory
+pub async fn apply_snapshot(snapshot: &SnapshotConfig, workspace: &Path) -> Result<()> {
+    let target = match snapshot.target {
+        Some(ref sub) => workspace.join(sub),
+        None => workspace.to_path_buf(),
+    };
+
+    tokio::fs::create_dir_all(&target)
+        .await
+        .context("Failed to create snapshot target directory")?;
+
+    let kind = SnapshotKind::from_path(&snapshot.path);
+    debug!("Applying {:?} snapshot {:?} to {:?}", kind, snapshot.path, target);
+
+    let output = match kind {
+        SnapshotKind::Tarball => {
+            Command::new("tar")
+                .arg("-xf")
+                .arg(&snapshot.path)
+                .arg("-C")
+                .arg(&target)
+                .output()
+                .await
+                .context("Failed to spawn tar")?
+        }
+        SnapshotKind::Directory => {
+            Command::new("cp")
+                .arg("-a")
+                .arg(snapshot.path.join("."))
+                .arg(&target)
+                .output()
+                .await
+                .context("Failed to spawn cp")?
+        }
+    };
+
+    if !output.status.success() {
+        anyhow::bail!(
+            "Failed to apply snapshot {:?}: {}",
+            snapshot.path,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_kind_from_path() {
+        assert_eq!(SnapshotKind::from_path(Path::new("/srv/node.tar.gz")), SnapshotKind::Tarball);
+        assert_eq!(SnapshotKind::from_path(Path::new("/srv/crates.TGZ")), SnapshotKind::Tarball);
+        assert_eq!(SnapshotKind::from_path(Path::new("/srv/deps.tar.zst")), SnapshotKind::Tarball);
+        assert_eq!(SnapshotKind::from_path(Path::new("/srv/node_modules")), SnapshotKind::Directory);
+    }
+}